@@ -0,0 +1,554 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+//! Parsing of RFC 5092 IMAP URLs.
+//!
+//! Only URLs which refer to a single message or a part of one are supported,
+//! since those are the only ones which are useful for `CATENATE`, `URLAUTH`,
+//! and `BURL`. Such a URL looks like
+//!
+//! ```text
+//! imap://user@host/mailbox;UIDVALIDITY=n/;UID=n/;SECTION=s/;PARTIAL=o.l
+//!   ;EXPIRE=datetime;URLAUTH=access:mechanism:token
+//! ```
+//!
+//! where everything after `UID` is optional, and the leading `imap://` and
+//! authority may be left off to make the URL relative to the server.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use chrono::prelude::*;
+
+use crate::account::model::Uid;
+use crate::mime::fetch::section::{BodySection, LeafType};
+use crate::support::error::Error;
+
+/// A parsed IMAP URL referring to a message or a part of one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImapUrl {
+    /// The user in the authority, if any.
+    pub user: Option<String>,
+    /// The host in the authority, if any. The port, if any, is discarded.
+    pub host: Option<String>,
+    /// The name of the mailbox containing the message.
+    pub mailbox: String,
+    /// The expected `UIDVALIDITY` of the mailbox, if given.
+    pub uid_validity: Option<u32>,
+    /// The UID of the message.
+    pub uid: Uid,
+    /// The portion of the message the URL refers to.
+    ///
+    /// This is the whole message if the URL has no `SECTION` or `PARTIAL`.
+    pub section: BodySection,
+    /// When the URL stops being valid, if ever.
+    pub expire: Option<DateTime<FixedOffset>>,
+    /// The access identifier, if this is a URLAUTH URL.
+    pub access: Option<UrlauthAccess>,
+    /// The authorisation mechanism and token, if this is a URLAUTH URL which
+    /// has been authorised.
+    pub verifier: Option<(String, String)>,
+    /// The text of the URL, excluding the verifier if any.
+    ///
+    /// For a URLAUTH URL, this is the "rump" over which the token is
+    /// computed.
+    pub rump: String,
+}
+
+/// The RFC 4467 access identifier of a URLAUTH URL, which says who may use
+/// it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UrlauthAccess {
+    /// Only the message submission server, acting for the given user.
+    Submit(String),
+    /// Only the given user.
+    User(String),
+    /// Any authenticated user.
+    AuthUser,
+    /// Anyone at all.
+    Anonymous,
+}
+
+impl ImapUrl {
+    /// Returns whether a session authenticated as a user going by any of
+    /// `aliases` may use this URL.
+    ///
+    /// `submission` indicates that the URL is being used by message
+    /// submission (i.e. `BURL`) rather than IMAP.
+    ///
+    /// This does not check the verifier.
+    pub fn permits(&self, aliases: &HashSet<String>, submission: bool) -> bool {
+        // A user can only ever reach their own mailboxes.
+        if self.user.as_ref().is_some_and(|u| !aliases.contains(u)) {
+            return false;
+        }
+
+        match self.access {
+            None | Some(UrlauthAccess::AuthUser | UrlauthAccess::Anonymous) => {
+                true
+            },
+            Some(UrlauthAccess::User(ref user)) => aliases.contains(user),
+            Some(UrlauthAccess::Submit(ref user)) => {
+                submission && aliases.contains(user)
+            },
+        }
+    }
+
+    /// Returns whether the URL has expired as of `now`.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expire.is_some_and(|expire| expire <= now)
+    }
+}
+
+impl FromStr for ImapUrl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (user, host, path) =
+            if let Some(rest) = strip_prefix_ci(s, "imap://") {
+                let authority_end = rest.find('/').ok_or(Error::BadUrl)?;
+                let (authority, path) = rest.split_at(authority_end);
+                let (user, host) = match authority.rfind('@') {
+                    Some(at) => {
+                        let userinfo = &authority[..at];
+                        // Drop any ";AUTH=" part; it has no bearing on
+                        // anything we do.
+                        let user = match find_ci(userinfo, ";AUTH=") {
+                            Some(ix) => &userinfo[..ix],
+                            None => userinfo,
+                        };
+                        let user = Some(percent_decode(user)?)
+                            .filter(|u| !u.is_empty());
+                        (user, &authority[at + 1..])
+                    },
+                    None => (None, authority),
+                };
+                let host = host.split(':').next().unwrap_or_default();
+                if host.is_empty() {
+                    return Err(Error::BadUrl);
+                }
+
+                (user, Some(host.to_owned()), path)
+            } else if s.starts_with('/') {
+                (None, None, s)
+            } else {
+                return Err(Error::BadUrl);
+            };
+
+        let path = &path[1..];
+
+        // Split off the URLAUTH parts, which may only come at the very end.
+        let (path, expire, access, verifier, rump) =
+            match find_ci(path, ";EXPIRE=")
+                .or_else(|| find_ci(path, ";URLAUTH="))
+            {
+                None => (path, None, None, None, s.to_owned()),
+                Some(ix) => {
+                    let mut authorisation = &path[ix..];
+                    let mut expire = None::<DateTime<FixedOffset>>;
+                    if let Some(rest) =
+                        strip_prefix_ci(authorisation, ";EXPIRE=")
+                    {
+                        let end =
+                            find_ci(rest, ";URLAUTH=").ok_or(Error::BadUrl)?;
+                        expire = Some(
+                            DateTime::parse_from_rfc3339(&rest[..end])
+                                .map_err(|_| Error::BadUrl)?,
+                        );
+                        authorisation = &rest[end..];
+                    }
+
+                    let authorisation =
+                        strip_prefix_ci(authorisation, ";URLAUTH=")
+                            .ok_or(Error::BadUrl)?;
+                    let mut parts = authorisation.splitn(3, ':');
+                    let access =
+                        parse_access(parts.next().unwrap_or_default())?;
+                    let verifier = match (parts.next(), parts.next()) {
+                        (None, None) => None,
+                        (Some(mechanism), Some(token))
+                            if !mechanism.is_empty()
+                                && token.len() >= 32
+                                && token
+                                    .bytes()
+                                    .all(|b| b.is_ascii_hexdigit()) =>
+                        {
+                            Some((mechanism.to_owned(), token.to_owned()))
+                        },
+                        _ => return Err(Error::BadUrl),
+                    };
+
+                    let rump = match verifier {
+                        None => s.to_owned(),
+                        Some((ref mechanism, ref token)) => s
+                            [..s.len() - mechanism.len() - token.len() - 2]
+                            .to_owned(),
+                    };
+
+                    (&path[..ix], expire, Some(access), verifier, rump)
+                },
+            };
+
+        let uid_start = find_ci(path, "/;UID=").ok_or(Error::BadUrl)?;
+        let (mailbox, mut rest) =
+            (&path[..uid_start], &path[uid_start + "/;UID=".len()..]);
+
+        let (mailbox, uid_validity) = match find_ci(mailbox, ";UIDVALIDITY=") {
+            None => (mailbox, None),
+            Some(ix) => (
+                &mailbox[..ix],
+                Some(parse_nz_number(&mailbox[ix + ";UIDVALIDITY=".len()..])?),
+            ),
+        };
+        let mailbox = percent_decode(mailbox)?;
+        if mailbox.is_empty() {
+            return Err(Error::BadUrl);
+        }
+
+        let uid_end = rest.find('/').unwrap_or(rest.len());
+        let uid =
+            Uid::of(parse_nz_number(&rest[..uid_end])?).ok_or(Error::BadUrl)?;
+        rest = &rest[uid_end..];
+
+        let mut section = BodySection::default();
+        if let Some(after) = strip_prefix_ci(rest, "/;SECTION=") {
+            let end = after.find('/').unwrap_or(after.len());
+            parse_section(&percent_decode(&after[..end])?, &mut section)?;
+            rest = &after[end..];
+        }
+
+        if let Some(after) = strip_prefix_ci(rest, "/;PARTIAL=") {
+            let (offset, length) = match after.split_once('.') {
+                None => (after, None),
+                Some((offset, length)) => (offset, Some(length)),
+            };
+            let offset = offset.parse::<u64>().map_err(|_| Error::BadUrl)?;
+            let end = match length {
+                None => u64::MAX,
+                Some(length) => {
+                    u64::from(parse_nz_number(length)?).saturating_add(offset)
+                },
+            };
+            section.partial = Some((offset, end));
+            rest = "";
+        }
+
+        if !rest.is_empty() {
+            return Err(Error::BadUrl);
+        }
+
+        Ok(ImapUrl {
+            user,
+            host,
+            mailbox,
+            uid_validity,
+            uid,
+            section,
+            expire,
+            access,
+            verifier,
+            rump,
+        })
+    }
+}
+
+fn parse_access(s: &str) -> Result<UrlauthAccess, Error> {
+    if let Some(user) = strip_prefix_ci(s, "submit+") {
+        Ok(UrlauthAccess::Submit(percent_decode(user)?))
+    } else if let Some(user) = strip_prefix_ci(s, "user+") {
+        Ok(UrlauthAccess::User(percent_decode(user)?))
+    } else if s.eq_ignore_ascii_case("authuser") {
+        Ok(UrlauthAccess::AuthUser)
+    } else if s.eq_ignore_ascii_case("anonymous") {
+        Ok(UrlauthAccess::Anonymous)
+    } else {
+        Err(Error::BadUrl)
+    }
+}
+
+/// Parses an IMAP section spec (e.g. `1.2.MIME`) into `section`.
+fn parse_section(s: &str, section: &mut BodySection) -> Result<(), Error> {
+    let mut rest = s;
+    while let Some(first) = rest.bytes().next() {
+        if !first.is_ascii_digit() {
+            break;
+        }
+
+        let (subscript, after) = match rest.split_once('.') {
+            Some((subscript, after)) => (subscript, Some(after)),
+            None => (rest, None),
+        };
+        section.subscripts.push(parse_nz_number(subscript)?);
+        rest = after.unwrap_or_default();
+        if after.is_none() {
+            break;
+        }
+    }
+
+    let top_level = section.subscripts.is_empty();
+    section.leaf_type = if top_level {
+        LeafType::Full
+    } else {
+        LeafType::Content
+    };
+
+    if rest.is_empty() {
+        // An empty section at top level ("SECTION=") is nonsense.
+        return if top_level {
+            Err(Error::BadUrl)
+        } else {
+            Ok(())
+        };
+    }
+
+    if rest.eq_ignore_ascii_case("HEADER") {
+        section.leaf_type = LeafType::Headers;
+    } else if rest.eq_ignore_ascii_case("TEXT") {
+        section.leaf_type = LeafType::Text;
+    } else if rest.eq_ignore_ascii_case("MIME") && !top_level {
+        section.leaf_type = LeafType::Mime;
+    } else if let Some(fields) = strip_prefix_ci(rest, "HEADER.FIELDS") {
+        let (negative, fields) = match strip_prefix_ci(fields, ".NOT") {
+            Some(fields) => (true, fields),
+            None => (false, fields),
+        };
+        let fields = fields
+            .strip_prefix(" (")
+            .and_then(|f| f.strip_suffix(')'))
+            .ok_or(Error::BadUrl)?;
+
+        section.leaf_type = LeafType::Headers;
+        section.discard_matching_headers = negative;
+        section.header_filter = fields
+            .split_whitespace()
+            .map(|f| f.trim_matches('"').to_owned())
+            .collect();
+        if section.header_filter.is_empty() {
+            return Err(Error::BadUrl);
+        }
+    } else {
+        return Err(Error::BadUrl);
+    }
+
+    Ok(())
+}
+
+fn parse_nz_number(s: &str) -> Result<u32, Error> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::BadUrl);
+    }
+
+    s.parse::<u32>()
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or(Error::BadUrl)
+}
+
+fn percent_decode(s: &str) -> Result<String, Error> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b'%' == b {
+            let hex = [
+                bytes.next().ok_or(Error::BadUrl)?,
+                bytes.next().ok_or(Error::BadUrl)?,
+            ];
+            let hex = std::str::from_utf8(&hex).map_err(|_| Error::BadUrl)?;
+            out.push(u8::from_str_radix(hex, 16).map_err(|_| Error::BadUrl)?);
+        } else {
+            out.push(b);
+        }
+    }
+
+    String::from_utf8(out).map_err(|_| Error::BadUrl)
+}
+
+fn strip_prefix_ci<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    s.get(..prefix.len())
+        .filter(|p| p.eq_ignore_ascii_case(prefix))
+        .map(|_| &s[prefix.len()..])
+}
+
+fn find_ci(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|w| w.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::support::chronox::*;
+
+    fn parse(s: &str) -> ImapUrl {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_simple_urls() {
+        let url = parse("imap://fred@example.com/INBOX;UIDVALIDITY=42/;UID=20");
+        assert_eq!(Some("fred"), url.user.as_deref());
+        assert_eq!(Some("example.com"), url.host.as_deref());
+        assert_eq!("INBOX", url.mailbox);
+        assert_eq!(Some(42), url.uid_validity);
+        assert_eq!(Uid::u(20), url.uid);
+        assert_eq!(BodySection::default(), url.section);
+        assert_eq!(None, url.access);
+        assert_eq!(
+            "imap://fred@example.com/INBOX;UIDVALIDITY=42/;UID=20",
+            url.rump,
+        );
+
+        let url = parse("/Lists/R%C3%BCst;uidvalidity=1/;uid=5");
+        assert_eq!(None, url.user);
+        assert_eq!(None, url.host);
+        assert_eq!("Lists/Rüst", url.mailbox);
+        assert_eq!(Some(1), url.uid_validity);
+        assert_eq!(Uid::u(5), url.uid);
+
+        let url = parse("imap://example.com:993/Drafts/;UID=1");
+        assert_eq!(None, url.user);
+        assert_eq!(Some("example.com"), url.host.as_deref());
+        assert_eq!("Drafts", url.mailbox);
+        assert_eq!(None, url.uid_validity);
+
+        let url = parse("imap://fred;AUTH=*@example.com/Drafts/;UID=1");
+        assert_eq!(Some("fred"), url.user.as_deref());
+    }
+
+    #[test]
+    fn parse_sections() {
+        let url = parse("/INBOX/;UID=1/;SECTION=1.2");
+        assert_eq!(vec![1, 2], url.section.subscripts);
+        assert_eq!(LeafType::Content, url.section.leaf_type);
+
+        let url = parse("/INBOX/;UID=1/;SECTION=2.MIME");
+        assert_eq!(vec![2], url.section.subscripts);
+        assert_eq!(LeafType::Mime, url.section.leaf_type);
+
+        let url = parse("/INBOX/;UID=1/;SECTION=TEXT");
+        assert!(url.section.subscripts.is_empty());
+        assert_eq!(LeafType::Text, url.section.leaf_type);
+
+        let url = parse(
+            "/INBOX/;UID=1/;SECTION=HEADER.FIELDS.NOT%20(Subject%20From)",
+        );
+        assert_eq!(LeafType::Headers, url.section.leaf_type);
+        assert!(url.section.discard_matching_headers);
+        assert_eq!(vec!["Subject", "From"], url.section.header_filter);
+
+        let url = parse("/INBOX/;UID=1/;SECTION=1/;PARTIAL=10.5");
+        assert_eq!(vec![1], url.section.subscripts);
+        assert_eq!(Some((10, 15)), url.section.partial);
+
+        let url = parse("/INBOX/;UID=1/;PARTIAL=10");
+        assert_eq!(LeafType::Full, url.section.leaf_type);
+        assert_eq!(Some((10, u64::MAX)), url.section.partial);
+    }
+
+    #[test]
+    fn parse_urlauth() {
+        let url = parse(
+            "imap://fred@example.com/INBOX/;UID=1\
+             ;EXPIRE=2024-03-01T10:00:00Z;URLAUTH=submit+fred",
+        );
+        assert_eq!(Some(UrlauthAccess::Submit("fred".to_owned())), url.access);
+        assert_eq!(
+            Some(
+                FixedOffset::zero()
+                    .with_ymd_and_hms(2024, 3, 1, 10, 0, 0)
+                    .unwrap()
+            ),
+            url.expire,
+        );
+        assert_eq!(None, url.verifier);
+        assert!(url.is_expired(Utc::now()));
+
+        let token = "0123456789abcdef0123456789ABCDEF";
+        let url = parse(&format!(
+            "imap://fred@example.com/INBOX/;UID=1;URLAUTH=authuser\
+             :INTERNAL:{token}",
+        ));
+        assert_eq!(Some(UrlauthAccess::AuthUser), url.access);
+        assert_eq!(
+            Some(("INTERNAL".to_owned(), token.to_owned())),
+            url.verifier,
+        );
+        assert_eq!(
+            "imap://fred@example.com/INBOX/;UID=1;URLAUTH=authuser",
+            url.rump,
+        );
+        assert!(!url.is_expired(Utc::now()));
+    }
+
+    #[test]
+    fn reject_bad_urls() {
+        for bad in [
+            "",
+            "INBOX/;UID=1",
+            "http://example.com/INBOX/;UID=1",
+            "imap://example.com",
+            "imap:///INBOX/;UID=1",
+            "/INBOX",
+            "/INBOX/;UID=0",
+            "/INBOX/;UID=x",
+            "/;UID=1",
+            "/INBOX;UIDVALIDITY=0/;UID=1",
+            "/INBOX/;UID=1/",
+            "/INBOX/;UID=1/;SECTION=",
+            "/INBOX/;UID=1/;SECTION=MIME",
+            "/INBOX/;UID=1/;SECTION=1.BODY",
+            "/INBOX/;UID=1/;PARTIAL=1.0",
+            "/INBOX/;UID=1/;PARTIAL=1/;SECTION=1",
+            "/INB%XXOX/;UID=1",
+            "/INB%FFOX/;UID=1",
+            "/INBOX/;UID=1;URLAUTH=nobody",
+            "/INBOX/;UID=1;EXPIRE=2024-03-01T10:00:00Z",
+            "/INBOX/;UID=1;EXPIRE=tomorrow;URLAUTH=anonymous",
+            "/INBOX/;UID=1;URLAUTH=anonymous:INTERNAL:1234",
+            "/INBOX/;UID=1;URLAUTH=anonymous:INTERNAL",
+        ] {
+            assert!(bad.parse::<ImapUrl>().is_err(), "accepted {bad:?}");
+        }
+    }
+
+    #[test]
+    fn access_permissions() {
+        let aliases = ["fred".to_owned(), "fred@example.com".to_owned()]
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        let check = |url: &str, submission: bool| {
+            parse(url).permits(&aliases, submission)
+        };
+
+        assert!(check("imap://fred@host/INBOX/;UID=1", false));
+        assert!(check("/INBOX/;UID=1", false));
+        assert!(!check("imap://bob@host/INBOX/;UID=1", false));
+
+        let url = "imap://fred@host/INBOX/;UID=1;URLAUTH=";
+        assert!(check(&format!("{url}anonymous"), false));
+        assert!(check(&format!("{url}authuser"), true));
+        assert!(check(&format!("{url}user+fred"), false));
+        assert!(!check(&format!("{url}user+bob"), false));
+        assert!(!check(&format!("{url}submit+fred"), false));
+        assert!(check(&format!("{url}submit+fred"), true));
+        assert!(!check(&format!("{url}submit+bob"), true));
+    }
+}
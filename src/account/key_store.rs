@@ -21,7 +21,9 @@
 //! The key store itself is a flat set of files. Private keys are stored in
 //! files ending with `.pem`. The part of the file name before `.pem` is the
 //! key name. Private keys are stored in PEM format with a passphrase derived
-//! from the master key.
+//! from the master key. Keys are either RSA or X25519; new keys use the
+//! algorithm set in the user's configuration, but existing keys of either
+//! type can always be used.
 //!
 //! A single public key is stored in `public`. This file consists of a single
 //! line containing the key name, followed by the PEM format of the public key.
@@ -37,13 +39,14 @@
 use std::collections::HashMap;
 use std::fs;
 use std::io::{self, BufRead, Read, Write};
-use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use log::{info, warn};
 use openssl::{
-    pkey::{Private, Public},
+    pkey::{Id, PKey, Private, Public},
     rsa::Rsa,
 };
 use serde::{Deserialize, Serialize};
@@ -89,6 +92,38 @@ pub struct KeyStoreConfig {
     ///
     /// This value must be a "safe string".
     pub external_key_pattern: String,
+    /// If set, private keys are retired this many days after they were
+    /// created.
+    ///
+    /// During daily maintenance, messages encrypted with a key due for
+    /// retirement are re-encrypted with the current key, after which the old
+    /// key is destroyed. The current internal and external keys are never
+    /// retired.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retire_after_days: Option<u32>,
+    /// The algorithm used for newly-generated keys.
+    ///
+    /// Changing this does not affect existing keys, so it only takes effect
+    /// once a new key is generated by key rotation.
+    #[serde(default)]
+    pub algorithm: KeyAlgorithm,
+}
+
+/// The public-key algorithm used for a key in the key store.
+#[derive(
+    Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAlgorithm {
+    /// RSA with `RSA_BITS` bits.
+    ///
+    /// This is the default since older versions of Crymap cannot read
+    /// anything else.
+    #[default]
+    Rsa,
+    /// X25519, which is much faster to generate and results in much smaller
+    /// encrypted session keys.
+    X25519,
 }
 
 impl Default for KeyStoreConfig {
@@ -96,6 +131,8 @@ impl Default for KeyStoreConfig {
         KeyStoreConfig {
             internal_key_pattern: "internal-%Y-%m".to_owned(),
             external_key_pattern: "external-%Y-%m".to_owned(),
+            retire_after_days: None,
+            algorithm: KeyAlgorithm::Rsa,
         }
     }
 }
@@ -113,8 +150,8 @@ pub struct KeyStore {
     root: PathBuf,
     tmp: PathBuf,
     master_key: Option<Arc<MasterKey>>,
-    private_keys: HashMap<String, Arc<Rsa<Private>>>,
-    public_key: Option<(String, Rsa<Public>)>,
+    private_keys: HashMap<String, Arc<PKey<Private>>>,
+    public_key: Option<(String, PKey<Public>)>,
     preferred_private_key: Option<String>,
     rsa_bits: u32,
 }
@@ -169,10 +206,15 @@ impl KeyStore {
 
         // Create external first, so if both have the same name, we still get
         // the public key saved on disk.
-        let created_external =
-            self.create_key_if_not_exists(&preferred_external)?;
-        self.create_key_if_not_exists(&preferred_internal)?;
-
+        let created_external = self
+            .create_key_if_not_exists(&preferred_external, config.algorithm)?;
+        self.create_key_if_not_exists(&preferred_internal, config.algorithm)?;
+
+        // The cached default public key may belong to a key that is no
+        // longer preferred.
+        if self.preferred_private_key.as_ref() != Some(&preferred_internal) {
+            self.public_key = None;
+        }
         self.preferred_private_key = Some(preferred_internal);
 
         if let Some(created_external) = created_external {
@@ -206,7 +248,8 @@ impl KeyStore {
     fn create_key_if_not_exists(
         &mut self,
         name: &str,
-    ) -> Result<Option<Rsa<Private>>, Error> {
+        algorithm: KeyAlgorithm,
+    ) -> Result<Option<PKey<Private>>, Error> {
         if !is_safe_name(name) {
             return Err(Error::UnsafeName);
         }
@@ -224,16 +267,37 @@ impl KeyStore {
         }
 
         // Doesn't exist, generate the new key
-        info!(
-            "{} Generating new {}-bit RSA key '{}'",
-            self.log_prefix, self.rsa_bits, name
-        );
-        let generated_key = Rsa::generate(self.rsa_bits)?;
-        let generated_key_bytes = generated_key.private_key_to_pem_passphrase(
-            // AEAD ciphers aren't supported here
-            openssl::symm::Cipher::aes_128_cbc(),
-            master_key.pem_passphrase(name).as_bytes(),
-        )?;
+        // AEAD ciphers aren't supported here
+        let pem_cipher = openssl::symm::Cipher::aes_128_cbc();
+        let passphrase = master_key.pem_passphrase(name);
+        let (generated_key, generated_key_bytes) = match algorithm {
+            KeyAlgorithm::Rsa => {
+                info!(
+                    "{} Generating new {}-bit RSA key '{}'",
+                    self.log_prefix, self.rsa_bits, name
+                );
+                let rsa = Rsa::generate(self.rsa_bits)?;
+                // RSA keys keep using the traditional format so that older
+                // versions can still read them.
+                let bytes = rsa.private_key_to_pem_passphrase(
+                    pem_cipher,
+                    passphrase.as_bytes(),
+                )?;
+                (PKey::from_rsa(rsa)?, bytes)
+            },
+            KeyAlgorithm::X25519 => {
+                info!(
+                    "{} Generating new X25519 key '{}'",
+                    self.log_prefix, name
+                );
+                let key = PKey::generate_x25519()?;
+                let bytes = key.private_key_to_pem_pkcs8_passphrase(
+                    pem_cipher,
+                    passphrase.as_bytes(),
+                )?;
+                (key, bytes)
+            },
+        };
 
         match file_ops::spit(
             &self.tmp,
@@ -276,7 +340,7 @@ impl KeyStore {
     /// On success, returns the key name and the key itself.
     pub fn get_default_public_key(
         &mut self,
-    ) -> Result<(&str, &Rsa<Public>), Error> {
+    ) -> Result<(&str, &PKey<Public>), Error> {
         if let Some((ref name, ref key)) = self.public_key {
             return Ok((name, key));
         }
@@ -290,10 +354,7 @@ impl KeyStore {
                 &self.root,
                 &mut self.private_keys,
             )?;
-            let pub_key = Rsa::from_public_components(
-                priv_key.n().to_owned()?,
-                priv_key.e().to_owned()?,
-            )?;
+            let pub_key = public_key_of(&priv_key)?;
 
             self.public_key = Some((name.to_owned(), pub_key));
         } else {
@@ -312,7 +373,7 @@ impl KeyStore {
             let mut pem_data = Vec::new();
             reader.read_to_end(&mut pem_data)?;
 
-            let pub_key = Rsa::public_key_from_pem(&pem_data)?;
+            let pub_key = PKey::public_key_from_pem(&pem_data)?;
 
             self.public_key = Some((name, pub_key));
         }
@@ -330,7 +391,7 @@ impl KeyStore {
     pub fn get_private_key(
         &mut self,
         name: &str,
-    ) -> Result<Arc<Rsa<Private>>, Error> {
+    ) -> Result<Arc<PKey<Private>>, Error> {
         let master_key = self
             .master_key
             .as_ref()
@@ -355,14 +416,96 @@ impl KeyStore {
         self.public_key = None;
         self.private_keys.clear();
     }
+
+    /// Returns the names of all private keys created before `created_before`,
+    /// excluding the keys currently used for internal and external
+    /// operations.
+    ///
+    /// The key store must have been initialised.
+    pub fn list_retirable_keys(
+        &self,
+        created_before: SystemTime,
+    ) -> Result<Vec<String>, Error> {
+        let preferred_internal = self
+            .preferred_private_key
+            .as_deref()
+            .ok_or(Error::MasterKeyUnavailable)?;
+
+        let mut preferred_external = String::new();
+        match fs::File::open(self.root.join("public")) {
+            Ok(file) => {
+                io::BufReader::new(file.take(MAX_KEY_FILE_SIZE))
+                    .read_line(&mut preferred_external)?;
+            },
+            Err(e) if io::ErrorKind::NotFound == e.kind() => {},
+            Err(e) => return Err(e.into()),
+        }
+        let preferred_external = preferred_external.trim_end();
+
+        let mut ret = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let Some(name) = entry
+                .file_name()
+                .to_str()
+                .and_then(|n| n.strip_suffix(".pem"))
+                .filter(|n| is_safe_name(n))
+                .map(str::to_owned)
+            else {
+                continue;
+            };
+
+            if name == preferred_internal || name == preferred_external {
+                continue;
+            }
+
+            if entry.metadata()?.modified()? < created_before {
+                ret.push(name);
+            }
+        }
+
+        ret.sort();
+        Ok(ret)
+    }
+
+    /// Permanently destroys the private key of the given name.
+    ///
+    /// The key file is overwritten with zeroes and synced before being
+    /// removed, so that the key cannot be recovered from the file system
+    /// afterwards (to the extent the underlying storage allows). Anything
+    /// still encrypted with the key becomes unreadable.
+    pub fn destroy_private_key(&mut self, name: &str) -> Result<(), Error> {
+        if !is_safe_name(name) {
+            return Err(Error::UnsafeName);
+        }
+
+        let path = self.root.join(format!("{}.pem", name));
+        let len = fs::metadata(&path)?.len();
+        // Key files are created read-only.
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        {
+            let mut file = fs::OpenOptions::new().write(true).open(&path)?;
+            io::copy(&mut io::repeat(0).take(len), &mut file)?;
+            file.sync_all()?;
+        }
+        fs::remove_file(&path)?;
+
+        self.private_keys.remove(name);
+        if matches!(self.public_key, Some((ref n, _)) if n == name) {
+            self.public_key = None;
+        }
+
+        info!("{} Destroyed private key '{}'", self.log_prefix, name);
+        Ok(())
+    }
 }
 
 fn load_private_key(
     master_key: &MasterKey,
     name: &str,
     root: &Path,
-    cache: &mut HashMap<String, Arc<Rsa<Private>>>,
-) -> Result<Arc<Rsa<Private>>, Error> {
+    cache: &mut HashMap<String, Arc<PKey<Private>>>,
+) -> Result<Arc<PKey<Private>>, Error> {
     if !is_safe_name(name) {
         return Err(Error::UnsafeName);
     }
@@ -375,7 +518,7 @@ fn load_private_key(
     fs::File::open(root.join(format!("{}.pem", name)))?
         .take(MAX_KEY_FILE_SIZE)
         .read_to_end(&mut pem_data)?;
-    let priv_key = Rsa::private_key_from_pem_passphrase(
+    let priv_key = PKey::private_key_from_pem_passphrase(
         &pem_data,
         master_key.pem_passphrase(name).as_bytes(),
     )?;
@@ -387,6 +530,22 @@ fn load_private_key(
     ))
 }
 
+/// Extracts the public half of `priv_key`.
+fn public_key_of(priv_key: &PKey<Private>) -> Result<PKey<Public>, Error> {
+    if Id::X25519 == priv_key.id() {
+        Ok(PKey::public_key_from_raw_bytes(
+            &priv_key.raw_public_key()?,
+            Id::X25519,
+        )?)
+    } else {
+        let rsa = priv_key.rsa()?;
+        Ok(PKey::from_rsa(Rsa::from_public_components(
+            rsa.n().to_owned()?,
+            rsa.e().to_owned()?,
+        )?)?)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -414,6 +573,8 @@ mod test {
             .init(&KeyStoreConfig {
                 internal_key_pattern: "internal1".to_owned(),
                 external_key_pattern: "external1".to_owned(),
+                retire_after_days: None,
+                algorithm: KeyAlgorithm::Rsa,
             })
             .unwrap();
         // Init twice to ensure it tolerates the files already existing
@@ -421,6 +582,8 @@ mod test {
             .init(&KeyStoreConfig {
                 internal_key_pattern: "internal1".to_owned(),
                 external_key_pattern: "external1".to_owned(),
+                retire_after_days: None,
+                algorithm: KeyAlgorithm::Rsa,
             })
             .unwrap();
 
@@ -444,6 +607,8 @@ mod test {
             .init(&KeyStoreConfig {
                 internal_key_pattern: "internal2".to_owned(),
                 external_key_pattern: "external2".to_owned(),
+                retire_after_days: None,
+                algorithm: KeyAlgorithm::Rsa,
             })
             .unwrap();
 
@@ -470,5 +635,86 @@ mod test {
             anon_store.get_private_key("internal1"),
             Err(Error::MasterKeyUnavailable)
         ));
+
+        let future = SystemTime::now() + std::time::Duration::from_secs(60);
+        assert_eq!(
+            vec!["external1".to_owned(), "internal1".to_owned()],
+            authed_store.list_retirable_keys(future).unwrap(),
+        );
+        assert!(authed_store
+            .list_retirable_keys(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .is_empty());
+
+        authed_store.destroy_private_key("internal1").unwrap();
+        authed_store.clear_cache();
+        assert!(matches!(
+            authed_store.get_private_key("internal1"),
+            Err(Error::NamedKeyNotFound)
+        ));
+        assert_eq!(
+            vec!["external1".to_owned()],
+            authed_store.list_retirable_keys(future).unwrap(),
+        );
+    }
+
+    #[test]
+    fn x25519_keys() {
+        let root = tempfile::tempdir().unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let master_key = Arc::new(MasterKey::new());
+
+        let mut authed_store = KeyStore::new(
+            LogPrefix::new("authed".to_owned()),
+            root.path().to_owned(),
+            tmp.path().to_owned(),
+            Some(Arc::clone(&master_key)),
+        );
+        let mut anon_store = KeyStore::new(
+            LogPrefix::new("anon".to_owned()),
+            root.path().to_owned(),
+            tmp.path().to_owned(),
+            None,
+        );
+
+        authed_store.set_rsa_bits(1024);
+        authed_store
+            .init(&KeyStoreConfig {
+                internal_key_pattern: "internal1".to_owned(),
+                external_key_pattern: "external1".to_owned(),
+                retire_after_days: None,
+                algorithm: KeyAlgorithm::Rsa,
+            })
+            .unwrap();
+        authed_store
+            .init(&KeyStoreConfig {
+                internal_key_pattern: "internal2".to_owned(),
+                external_key_pattern: "external2".to_owned(),
+                retire_after_days: None,
+                algorithm: KeyAlgorithm::X25519,
+            })
+            .unwrap();
+        authed_store.clear_cache();
+
+        {
+            let (name, key) = anon_store.get_default_public_key().unwrap();
+            assert_eq!("external2", name);
+            assert_eq!(Id::X25519, key.id());
+        }
+
+        {
+            let (name, key) = authed_store.get_default_public_key().unwrap();
+            assert_eq!("internal2", name);
+            assert_eq!(Id::X25519, key.id());
+        }
+
+        assert_eq!(
+            Id::RSA,
+            authed_store.get_private_key("internal1").unwrap().id(),
+        );
+        assert_eq!(
+            Id::X25519,
+            authed_store.get_private_key("internal2").unwrap().id(),
+        );
     }
 }
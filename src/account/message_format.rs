@@ -72,6 +72,35 @@ pub fn read_message<'a, R: Read + 'a>(
     Ok((metadata, stream))
 }
 
+/// Copies the message in `src` to `dst`, re-encrypting its session key with
+/// the default public key from `key_store`.
+///
+/// `should_rewrap` is invoked with the name of the key the message currently
+/// uses. If it returns `false`, the message is left alone and this returns
+/// `Ok(false)`; the content of `dst` is then unspecified.
+///
+/// The payload is copied without being decrypted.
+pub fn rewrap_message(
+    mut src: impl Read,
+    mut dst: impl Write,
+    key_store: &mut KeyStore,
+    should_rewrap: impl FnOnce(&str) -> bool,
+) -> Result<bool, Error> {
+    let (key_name, pub_key) = key_store.get_default_public_key()?;
+    let (key_name, pub_key) = (key_name.to_owned(), pub_key.clone());
+
+    let size_xor = src.read_u32::<LittleEndian>()?;
+    dst.write_u32::<LittleEndian>(size_xor)?;
+    data_stream::rewrap(
+        src,
+        dst,
+        |name| name != key_name && should_rewrap(name),
+        |k| key_store.get_private_key(k),
+        &pub_key,
+        key_name.clone(),
+    )
+}
+
 /// Writes a message to `out`, using `key_store` to obtain the public key and
 /// the full data from `message_contents` as the payload.
 ///
@@ -21,6 +21,7 @@
 //!
 //! Unversioned submodules are not storage-implementation-specific.
 
+pub mod imap_url;
 pub mod key_store;
 mod message_format;
 pub mod model;
@@ -16,7 +16,7 @@
 // You should have received a copy of the GNU General Public License along with
 // Crymap. If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
 use std::marker::PhantomData;
@@ -29,8 +29,9 @@ use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
 use tempfile::TempPath;
 
+use crate::account::key_store::KeyAlgorithm;
 use crate::mime::fetch;
-use crate::support::error::Error;
+use crate::support::{error::Error, user_config::AppPasswordRestriction};
 
 /// Uniquely identifies a message within a single mailbox.
 ///
@@ -601,7 +602,7 @@ pub enum MailboxAttribute {
     NonExistent,
     Subscribed,
     // RFC 6154
-    // \All is not supported
+    All,
     Archive,
     Drafts,
     Flagged,
@@ -621,6 +622,7 @@ impl MailboxAttribute {
             Self::HasNoChildren => "\\HasNoChildren",
             Self::NonExistent => "\\NonExistent",
             Self::Subscribed => "\\Subscribed",
+            Self::All => "\\All",
             Self::Archive => "\\Archive",
             Self::Drafts => "\\Drafts",
             Self::Flagged => "\\Flagged",
@@ -634,7 +636,8 @@ impl MailboxAttribute {
     pub fn is_special_use(self) -> bool {
         matches!(
             self,
-            Self::Archive
+            Self::All
+                | Self::Archive
                 | Self::Drafts
                 | Self::Flagged
                 | Self::Junk
@@ -668,6 +671,8 @@ impl FromStr for MailboxAttribute {
             Ok(Self::NonExistent)
         } else if "\\Subscribed".eq_ignore_ascii_case(s) {
             Ok(Self::Subscribed)
+        } else if "\\all".eq_ignore_ascii_case(s) {
+            Ok(MailboxAttribute::All)
         } else if "\\archive".eq_ignore_ascii_case(s) {
             Ok(MailboxAttribute::Archive)
         } else if "\\drafts".eq_ignore_ascii_case(s) {
@@ -967,6 +972,52 @@ pub struct PollResponse {
     /// The new `HIGHESTMODSEQ`, or `None` if it hasn't changed since the last
     /// poll.
     pub max_modseq: Option<Modseq>,
+    /// Changes to the results of RFC 5267 search contexts.
+    ///
+    /// These are sent after the `FETCH` responses as
+    /// `* ESEARCH (TAG "tag") [UID] ADDTO (0 added) REMOVETO (0 removed)`.
+    pub search_updates: Vec<SearchContextUpdate>,
+}
+
+/// A change in the result set of one RFC 5267 search context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchContextUpdate {
+    /// The tag of the `SEARCH` command which created the context.
+    pub tag: String,
+    /// Whether the context was created by `UID SEARCH`, in which case
+    /// `added` and `removed` are UIDs rather than sequence numbers.
+    pub uid: bool,
+    /// Messages which now match the search.
+    pub added: SeqRange<u32>,
+    /// Messages which no longer match the search.
+    ///
+    /// Expunged messages are never reported here.
+    pub removed: SeqRange<u32>,
+}
+
+/// A lightweight summary of every mailbox and subscription in an account.
+///
+/// This is used to implement RFC 5465 `NOTIFY`: the command processor keeps
+/// the last snapshot it saw and compares it against a fresh one to discover
+/// what changed outside the selected mailbox.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NotifySnapshot {
+    /// All mailboxes in the account, keyed by RFC 8474 `MAILBOXID`.
+    pub mailboxes: HashMap<String, NotifyMailbox>,
+    /// The normalised paths of all subscriptions.
+    pub subscriptions: HashSet<String>,
+}
+
+/// The state of a single mailbox within a `NotifySnapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotifyMailbox {
+    /// The full path of the mailbox.
+    pub name: String,
+    /// Whether the mailbox can be selected.
+    pub selectable: bool,
+    /// The greatest modseq of the mailbox. This changes whenever a message
+    /// is added, expunged, or has its flags changed.
+    pub max_modseq: Modseq,
 }
 
 /// The `QRESYNC` part of `SELELCT` or `EXPUNGE`.
@@ -1191,6 +1242,12 @@ where
     pub email_id: bool,
     /// Return the thread id?
     pub thread_id: bool,
+    // ==================== RFC 8970 ====================
+    /// Return the preview text?
+    pub preview: bool,
+    /// If true, `preview` only returns preview text which is already cached,
+    /// and `NIL` for anything else.
+    pub preview_lazy: bool,
 }
 
 /// What the tagged response from a `FETCH` should be.
@@ -1371,6 +1428,43 @@ pub struct FetchResponse {
 pub struct SearchRequest {
     /// The top-level queries, which get ANDed together.
     pub queries: Vec<SearchQuery>,
+    /// If set, only the hits within this window are needed.
+    ///
+    /// The search stops as soon as it has found enough hits from the relevant
+    /// end of the mailbox, and the response contains only the hits within the
+    /// window, so everything other than `hits` and `hit_uids` describes only
+    /// those hits.
+    pub partial: Option<PartialRange>,
+}
+
+/// A window of 1-based positions within an ordered set of results, as used by
+/// RFC 9394 `PARTIAL`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PartialRange {
+    /// The first position in the window. Must be at least 1.
+    pub low: u32,
+    /// The last position in the window (inclusive). Must be at least `low`.
+    pub high: u32,
+    /// If true, positions count backwards from the end, so position 1 is the
+    /// last item.
+    pub from_end: bool,
+}
+
+impl PartialRange {
+    /// Returns the elements of `items`, which are in ascending order, that
+    /// fall within this window.
+    pub fn window<T>(self, items: &[T]) -> &[T] {
+        let len = items.len();
+        let low = self.low.max(1) as usize;
+        let high = self.high as usize;
+        let (start, end) = if self.from_end {
+            (len.saturating_sub(high), len.saturating_sub(low - 1))
+        } else {
+            ((low - 1).min(len), high.min(len))
+        };
+
+        &items[start..end.max(start)]
+    }
 }
 
 /// The query for the `SEARCH` command and related commands.
@@ -1380,9 +1474,14 @@ pub struct SearchRequest {
 /// some of the quirks (such as the "Un$flag" queries) are purely syntactic,
 /// and keeping all the translation logic in one place makes it easier to
 /// manage.
-#[derive(Clone, Debug)]
+///
+/// Queries are serialised as the definitions of virtual mailboxes. The
+/// variants which refer to specific messages of one mailbox cannot be
+/// serialised.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum SearchQuery {
     // ==================== RFC 3501 ====================
+    #[serde(skip)]
     SequenceSet(SeqRange<Seqnum>),
     All,
     Answered,
@@ -1412,6 +1511,7 @@ pub enum SearchQuery {
     Subject(String),
     Text(String),
     To(String),
+    #[serde(skip)]
     UidSet(SeqRange<Uid>), // RFC 3501 calls it "UID"; "Set" for disambiguation
     Unanswered,
     Undeleted,
@@ -1461,6 +1561,29 @@ pub struct SearchResponse<ID> {
     pub max_modseq: Option<Modseq>,
 }
 
+/// The RFC 7377 multi-mailbox `ESEARCH` command.
+#[derive(Clone, Debug, Default)]
+pub struct MultiSearchRequest {
+    /// Whether to search the selected mailbox, if there is one.
+    pub selected: bool,
+    /// `LIST` patterns matching the mailboxes to search.
+    pub patterns: Vec<String>,
+    /// `LIST` patterns matching the mailboxes to search, but only those which
+    /// are subscribed.
+    pub subscribed_patterns: Vec<String>,
+    /// The search to run against each mailbox.
+    pub search: SearchRequest,
+}
+
+/// The result of searching one mailbox for a `MultiSearchRequest`.
+#[derive(Clone, Debug)]
+pub struct MultiSearchResponse {
+    /// The full name of the mailbox.
+    pub mailbox: String,
+    pub uidvalidity: u32,
+    pub response: SearchResponse<Uid>,
+}
+
 /// The `APPEND` request.
 #[derive(Debug, Default)]
 pub struct AppendRequest {
@@ -1520,6 +1643,205 @@ pub struct SetUserConfigRequest {
     pub smtp_out_save: Option<Option<String>>,
     pub smtp_out_success_receipts: Option<Option<String>>,
     pub smtp_out_failure_receipts: Option<Option<String>>,
+    pub new_device_notifications: Option<bool>,
+    pub expunge_retention_days: Option<u32>,
+    pub key_retirement_days: Option<Option<u32>>,
+    pub key_algorithm: Option<KeyAlgorithm>,
+}
+
+/// Information about an application-specific password, as returned by `XCRY
+/// APP-PASSWORD LIST`.
+#[derive(Debug, Clone)]
+pub struct AppPasswordInfo {
+    pub name: String,
+    pub restriction: AppPasswordRestriction,
+    pub created: DateTime<FixedOffset>,
+    pub last_used: Option<DateTime<FixedOffset>>,
+}
+
+/// An entry in the per-user login history, as returned by `XCRY
+/// LOGIN-HISTORY`.
+///
+/// This is also the format in which failed logins are queued (encrypted) in
+/// the delivery database until the user next logs in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoginHistoryEntry {
+    /// When the login was attempted.
+    pub at: DateTime<Utc>,
+    /// The protocol through which the login was attempted, e.g. "IMAP".
+    pub protocol: String,
+    /// Whether the login succeeded.
+    pub success: bool,
+    /// The IP address of the client, if known.
+    pub peer_ip: Option<String>,
+    /// A description of the TLS session, if any.
+    pub tls: Option<String>,
+    /// The client name the client reported through the RFC 2971 `ID`
+    /// command, if any.
+    pub client_name: Option<String>,
+    /// The full RFC 2971 `ID` data the client reported, if any.
+    pub client_id: Option<String>,
+    /// The application-specific password used to log in, if any.
+    pub app_password: Option<String>,
+}
+
+/// A mailbox in a database backup which has since been deleted or lost
+/// messages, as returned by `XCRY RESTORE MAILBOXES`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestorableMailbox {
+    /// The name the mailbox had at the time of the backup.
+    pub name: String,
+    /// The UIDVALIDITY of the mailbox.
+    pub uid_validity: u32,
+    /// Whether the mailbox no longer exists.
+    pub deleted: bool,
+    /// The number of messages which are gone from the mailbox but can still
+    /// be restored.
+    pub messages: usize,
+}
+
+/// A message in a database backup which is no longer in its mailbox but can
+/// still be restored, as returned by `XCRY RESTORE MESSAGES`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestorableMessage {
+    /// The UID the message had in its mailbox.
+    pub uid: Uid,
+    /// The flags the message had at the time of the backup.
+    pub flags: Vec<Flag>,
+    /// When the message was added to its mailbox.
+    pub savedate: DateTime<Utc>,
+    /// The decoded subject of the message, if it has one.
+    pub subject: Option<String>,
+}
+
+/// A message which was expunged from every mailbox but can still be put back,
+/// as returned by `XCRY UNEXPUNGE LIST`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnexpungeableMessage {
+    /// The mailbox the message was expunged from.
+    pub mailbox: String,
+    /// The UID the message had in that mailbox.
+    pub uid: Uid,
+    /// When the message was expunged.
+    pub expunged: DateTime<Utc>,
+    /// The flags the message had when it was expunged.
+    pub flags: Vec<Flag>,
+    /// The decoded subject of the message, if it has one.
+    pub subject: Option<String>,
+}
+
+/// Which date a mailbox retention rule measures the age of messages by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum RetentionAgeBasis {
+    /// When the message was added to the mailbox (RFC 8514 `SAVEDATE`).
+    #[default]
+    SaveDate,
+    /// The `INTERNALDATE` of the message.
+    InternalDate,
+}
+
+/// Rules for automatically expunging messages from a mailbox, as set by
+/// `XCRY SET-MAILBOX-RETENTION`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct MailboxRetention {
+    /// If set, messages older than this many days are expunged.
+    pub max_age_days: Option<u32>,
+    /// What `max_age_days` is measured against.
+    pub age_basis: RetentionAgeBasis,
+    /// If set, only this many messages (those with the greatest UIDs) are
+    /// kept.
+    pub max_messages: Option<u32>,
+    /// If true, messages without the `\Seen` flag are never expunged.
+    pub only_seen: bool,
+}
+
+/// The maximum size, in bytes, of a single RFC 5464 METADATA value.
+pub const METADATA_MAX_SIZE: u32 = 16384;
+
+/// The `DEPTH` option of the RFC 5464 `GETMETADATA` command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum MetadataDepth {
+    /// Only the named entries themselves.
+    #[default]
+    Zero,
+    /// The named entries and their immediate children.
+    One,
+    /// The named entries and all their descendants.
+    Infinity,
+}
+
+impl MetadataDepth {
+    /// Returns whether a request for `requested` at this depth selects the
+    /// entry named `entry`.
+    pub fn selects(self, requested: &str, entry: &str) -> bool {
+        if requested == entry {
+            return true;
+        }
+
+        let Some(rest) = entry
+            .strip_prefix(requested)
+            .and_then(|rest| rest.strip_prefix('/'))
+        else {
+            return false;
+        };
+
+        match self {
+            Self::Zero => false,
+            Self::One => !rest.contains('/'),
+            Self::Infinity => true,
+        }
+    }
+}
+
+/// Request for the RFC 5464 `GETMETADATA` command.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct GetMetadataRequest {
+    /// The mailbox whose entries to fetch, or `""` for server entries.
+    pub mailbox: String,
+    /// The entry names requested.
+    pub entries: Vec<String>,
+    /// Which descendants of `entries` to include.
+    pub depth: MetadataDepth,
+    /// If set, omit values longer than this many bytes.
+    pub max_size: Option<u32>,
+}
+
+/// Response for the RFC 5464 `GETMETADATA` command.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct GetMetadataResponse {
+    /// The entries found, sorted by name. With `MetadataDepth::Zero`,
+    /// requested entries which do not exist are reported with a value of
+    /// `None`.
+    pub entries: Vec<(String, Option<String>)>,
+    /// If any values were omitted due to `max_size`, the size of the largest
+    /// such value.
+    pub longest_omitted: Option<u32>,
+}
+
+/// The findings of `crymap server user verify`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VerifyReport {
+    /// Problems reported by SQLite's `integrity_check` on the metadata
+    /// database.
+    pub database_problems: Vec<String>,
+    /// The number of messages whose data was checked.
+    pub messages_checked: usize,
+    /// Messages which could not be read in full or whose size is wrong.
+    pub damaged_messages: Vec<DamagedMessage>,
+    /// Files in the message store which the metadata database does not
+    /// reference.
+    pub unaccounted_files: Vec<String>,
+    /// The number of mailbox entries moved into the quarantine mailbox.
+    pub quarantined: usize,
+}
+
+/// A message found to be damaged by `crymap server user verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DamagedMessage {
+    /// The path of the message file relative to the message store.
+    pub path: String,
+    /// A description of what is wrong with the message.
+    pub problem: String,
 }
 
 /// Holder for common paths used pervasively through a process.
@@ -1863,4 +2185,27 @@ mod test {
                 SeqRange::parse(&seqrange.to_string(), Uid::MAX).unwrap());
         }
     }
+
+    #[test]
+    fn partial_range_window() {
+        let items = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let window = |low, high, from_end| {
+            PartialRange {
+                low,
+                high,
+                from_end,
+            }
+            .window(&items)
+            .to_vec()
+        };
+
+        assert_eq!(vec![1, 2, 3], window(1, 3, false));
+        assert_eq!(vec![9, 10], window(9, 20, false));
+        assert_eq!(Vec::<i32>::new(), window(11, 20, false));
+        assert_eq!(vec![8, 9, 10], window(1, 3, true));
+        assert_eq!(vec![1, 2], window(9, 20, true));
+        assert_eq!(Vec::<i32>::new(), window(11, 20, true));
+        assert_eq!(vec![5], window(5, 5, false));
+        assert_eq!(vec![6], window(5, 5, true));
+    }
 }
@@ -31,7 +31,7 @@ use crate::account::{
     key_store::{KeyStore, KeyStoreConfig},
     model::*,
 };
-use crate::crypt::master_key::MasterKey;
+use crate::crypt::master_key::{Argon2Params, MasterKey};
 use crate::support::{
     chronox::*, error::Error, file_ops::IgnoreKinds, mailbox_paths::*,
     safe_name::is_safe_name, user_config::UserConfig,
@@ -147,10 +147,14 @@ impl Account {
                 .master_key
                 .as_ref()
                 .expect("Account::provision() called without master key")
-                .make_config(password)
+                .make_config(password, &Argon2Params::default(), None)
                 .expect("Password hashing failed"),
             key_store: KeyStoreConfig::default(),
             smtp_out: Default::default(),
+            login: Default::default(),
+            expunge: Default::default(),
+            urlauth_keys: Default::default(),
+            app_passwords: Default::default(),
         };
 
         let user_config_toml = toml::to_vec(&user_config)
@@ -252,7 +256,11 @@ impl Account {
 
         if let Some(password) = request.password {
             config.master_key = master_key
-                .make_config(password.as_bytes())
+                .make_config(
+                    password.as_bytes(),
+                    &Argon2Params::default(),
+                    None,
+                )
                 .expect("argon2 hash failed");
             config.master_key.last_changed =
                 Some(FixedOffset::zero().from_utc_datetime(&now.naive_local()));
@@ -189,6 +189,8 @@ impl StatefulMailbox {
             collect_vanished: request.collect_vanished,
             email_id: request.email_id,
             thread_id: request.thread_id,
+            preview: request.preview,
+            preview_lazy: request.preview_lazy,
         };
         self.fetch(&request, receiver)
     }
@@ -441,6 +443,8 @@ mod test {
             collect_vanished: false,
             email_id: true,
             thread_id: true,
+            preview: false, // Unsupported by V1
+            preview_lazy: false,
         };
         let prefetch = setup.mb1.prefetch(&request, &request.ids);
         let response = setup.mb1.fetch(&request, &setup.receiver()).unwrap();
@@ -193,6 +193,7 @@ impl StatefulMailbox {
             } else {
                 Some(flush.max_modseq.into())
             },
+            search_updates: Vec::new(), // Unsupported by V1
         })
     }
 
@@ -491,7 +491,7 @@ mod test {
         macro_rules! search {
             ($($query:expr),*) => {
                 mb1.search(&SearchRequest {
-                    queries: vec![$($query),*],
+                    queries: vec![$($query),*], partial: None,
                 }).unwrap().hits
             }
         }
@@ -670,6 +670,7 @@ mod test {
         let result = mb1
             .search(&SearchRequest {
                 queries: vec![SearchQuery::Text("許されない".to_owned())],
+                partial: None,
             })
             .unwrap();
         assert_eq!(vec![uid1], result.hits);
@@ -677,6 +678,7 @@ mod test {
         let result = mb1
             .search(&SearchRequest {
                 queries: vec![SearchQuery::Text("ÆONS".to_owned())],
+                partial: None,
             })
             .unwrap();
         assert_eq!(vec![uid2], result.hits);
@@ -698,6 +700,7 @@ mod test {
         let result = mb1
             .seqnum_search(&SearchRequest {
                 queries: vec![SearchQuery::All],
+                partial: None,
             })
             .unwrap();
 
@@ -728,6 +731,7 @@ mod test {
                 // Use Text search to force loading of the message (and subsequent
                 // failure since most of them are gone).
                 queries: vec![SearchQuery::Text("@".to_owned())],
+                partial: None,
             })
             .unwrap();
 
@@ -759,6 +763,7 @@ mod test {
         let result = mb
             .search(&SearchRequest {
                 queries: vec![SearchQuery::All],
+                partial: None,
             })
             .unwrap();
         assert_eq!(
@@ -777,6 +782,7 @@ mod test {
         let result = mb
             .search(&SearchRequest {
                 queries: vec![SearchQuery::Text("gaap".to_owned())],
+                partial: None,
             })
             .unwrap();
         assert_eq!(None, result.first_modseq);
@@ -23,7 +23,8 @@ mod storage;
 
 pub use super::v1::account::account_config_file;
 pub use state::{
-    Account, DeliveryAccount, FetchReceiver, LogInError, Mailbox,
-    SpooledMessage, SpooledMessageId,
+    Account, DeliveryAccount, FetchReceiver, LogInClient, LogInError,
+    LogInProtocol, LoginThrottle, Mailbox, SpooledMessage, SpooledMessageId,
+    UidMigrationItem, URLAUTH_MECHANISM,
 };
 pub use storage::SmtpTransfer;
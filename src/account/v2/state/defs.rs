@@ -17,17 +17,22 @@
 // Crymap. If not, see <http://www.gnu.org/licenses/>.
 
 use std::borrow::Cow;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use chrono::prelude::*;
+use openssl::{pkey::Public, rsa::Rsa};
 
 use super::super::storage;
 use crate::{
-    account::{key_store::KeyStore, model::*},
-    crypt::master_key::MasterKey,
-    support::{error::Error, log_prefix::LogPrefix, small_bitset::SmallBitset},
+    account::{key_store::KeyStore, model::*, search_backend::Op},
+    crypt::master_key::{Argon2Params, MasterKey},
+    mime::fetch::search::OptionalSearchParts,
+    support::{
+        error::Error, log_prefix::LogPrefix, small_bitset::SmallBitset,
+        system_config::SecurityConfig,
+    },
 };
 
 pub(super) const METADB_NAME: &str = "meta.sqlite.xex";
@@ -46,6 +51,16 @@ pub struct Account {
     pub(super) common_paths: Arc<CommonPaths>,
     pub(super) backup_path: PathBuf,
     pub(super) log_prefix: LogPrefix,
+    /// If the session was authenticated with an application-specific
+    /// password, the name of that password.
+    pub(super) app_password: Option<String>,
+    /// The system recovery key under which the master key is escrowed when
+    /// the password is changed.
+    pub(super) recovery_key: Option<Rsa<Public>>,
+    /// The parameters with which new password hashes are generated.
+    pub(super) password_hash_params: Argon2Params,
+    /// The ID of the login history entry for the current session, if any.
+    pub(super) login_history_id: Option<i64>,
 }
 
 /// The state for a selected mailbox.
@@ -80,6 +95,54 @@ pub struct Mailbox {
     /// connection. The set gets cleared on a full poll cycle. See
     /// `FetchResponseKind` for more details.
     pub(super) fetch_loopbreaker: HashSet<Uid>,
+    /// The RFC 5267 search contexts whose results are kept up to date.
+    pub(super) search_contexts: Vec<SearchContext>,
+    /// UIDs of messages which need to be re-evaluated against
+    /// `search_contexts` on the next full poll. This is only populated while
+    /// there is at least one search context.
+    pub(super) search_context_pending_uids: Vec<Uid>,
+    /// If this is a virtual mailbox, the state needed to map its messages
+    /// back to the real mailboxes they live in.
+    pub(super) virtual_view: Option<Box<VirtualView>>,
+}
+
+/// The state of a selected virtual mailbox.
+///
+/// Each message in the virtual mailbox is one message of one of the real
+/// mailboxes in `sources`. The sources are kept up to date by polling them
+/// like any other selected mailbox, and the virtual mailbox follows their
+/// changes.
+#[derive(Clone, Debug)]
+pub(super) struct VirtualView {
+    /// The compiled search query which defines the virtual mailbox.
+    pub(super) ops: Arc<Vec<Op>>,
+    /// The parts of the message `ops` needs to see.
+    pub(super) want: OptionalSearchParts,
+    /// The real mailboxes messages are drawn from. These are always opened
+    /// read-only so that the virtual mailbox does not claim `\Recent`.
+    pub(super) sources: BTreeMap<storage::MailboxId, Mailbox>,
+    /// Maps virtual UIDs to the source mailbox and UID of the message.
+    pub(super) origins: HashMap<Uid, (storage::MailboxId, Uid)>,
+    /// The inverse of `origins`.
+    pub(super) virtual_uids: HashMap<(storage::MailboxId, Uid), Uid>,
+    /// Source messages whose flags changed in a mini poll, and so need to be
+    /// checked against the query in the next full poll.
+    pub(super) pending: Vec<(storage::MailboxId, Uid)>,
+}
+
+/// A search created with `SEARCH RETURN (UPDATE)`.
+#[derive(Clone, Debug)]
+pub(super) struct SearchContext {
+    /// The tag of the command which created the context.
+    pub(super) tag: String,
+    /// Whether the client addresses results by UID.
+    pub(super) uid: bool,
+    /// The compiled search program.
+    pub(super) ops: Arc<Vec<Op>>,
+    /// The parts of the message `ops` needs to see.
+    pub(super) want: OptionalSearchParts,
+    /// The UIDs of the messages the client believes match the search.
+    pub(super) hits: HashSet<Uid>,
 }
 
 /// Information about a message retained in a selected mailbox.
@@ -124,6 +187,14 @@ impl Account {
     pub fn user_name(&self) -> Option<&str> {
         self.root.file_name().and_then(|name| name.to_str())
     }
+
+    /// Applies the parts of the system security configuration that control
+    /// how new password configurations are generated.
+    pub fn apply_security_config(&mut self, security: &SecurityConfig) {
+        self.recovery_key =
+            security.recovery_public_key.as_ref().map(|k| k.0.clone());
+        self.password_hash_params = security.password_hash;
+    }
 }
 
 impl Mailbox {
@@ -147,6 +218,11 @@ impl Mailbox {
         !self.writable
     }
 
+    /// Returns whether this is a virtual mailbox.
+    pub fn is_virtual(&self) -> bool {
+        self.virtual_view.is_some()
+    }
+
     pub fn rfc8474_mailbox_id(&self) -> String {
         self.id.format_rfc8474()
     }
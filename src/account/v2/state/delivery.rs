@@ -21,7 +21,7 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 use chrono::prelude::*;
-use log::error;
+use log::{error, warn};
 
 use super::super::storage;
 use super::defs::*;
@@ -33,8 +33,8 @@ use crate::{
 /// A not-logged-in handle on an account which can be used for delivering
 /// messages.
 pub struct DeliveryAccount {
-    deliverydb: storage::DeliveryDb,
-    key_store: KeyStore,
+    pub(super) deliverydb: storage::DeliveryDb,
+    pub(super) key_store: KeyStore,
     message_store: storage::MessageStore,
     common_paths: Arc<CommonPaths>,
     log_prefix: LogPrefix,
@@ -80,15 +80,43 @@ impl DeliveryAccount {
     pub fn buffer_message(
         &mut self,
         data: impl std::io::Read,
+    ) -> Result<BufferedMessage, Error> {
+        self.buffer_message_with_date(Utc::now().into(), data)
+    }
+
+    /// Like `buffer_message()`, but uses the given internal date instead of
+    /// the current time.
+    pub fn buffer_message_with_date(
+        &mut self,
+        internal_date: DateTime<FixedOffset>,
+        data: impl std::io::Read,
     ) -> Result<BufferedMessage, Error> {
         super::messages::buffer_message(
             &mut self.key_store,
             &self.common_paths,
-            Utc::now().into(),
+            internal_date,
             data,
         )
     }
 
+    /// Queue the creation of the given mailbox (and any missing parents).
+    ///
+    /// The mailbox is created when the deliveries are next drained, before
+    /// any message queued after this call is delivered. Nothing happens if
+    /// the mailbox already exists. If `special_use` cannot be applied, the
+    /// mailbox is created without it.
+    pub fn queue_mailbox_creation(
+        &mut self,
+        mailbox: &str,
+        special_use: Option<MailboxAttribute>,
+    ) -> Result<(), Error> {
+        self.deliverydb
+            .queue_mailbox_creation(&storage::MailboxCreation {
+                mailbox: mailbox.to_owned(),
+                special_use: special_use.map(|a| a.name().to_owned()),
+            })
+    }
+
     /// Deliver the given data as a message into the given mailbox with the
     /// requested flags.
     pub fn deliver(
@@ -142,6 +170,8 @@ impl Account {
     /// This should be called after every command and before invoking `poll()`
     /// or `mini_poll()` if there is a selected mailbox.
     pub fn drain_deliveries(&mut self) {
+        self.drain_mailbox_creations();
+
         loop {
             // By successfully removing an entry, we're committing to
             // delivering it. If we can't for some reason and drop it on the
@@ -167,7 +197,15 @@ impl Account {
                 },
             };
 
-            let dst_id = match self.metadb.find_mailbox(&delivery.mailbox) {
+            // If the mailbox doesn't exist, it may have been queued for
+            // creation after we started draining.
+            let dst_id = match self
+                .metadb
+                .find_mailbox(&delivery.mailbox)
+                .or_else(|_| {
+                    self.drain_mailbox_creations();
+                    self.metadb.find_mailbox(&delivery.mailbox)
+                }) {
                 Ok(id) => id,
                 Err(e) => {
                     error!(
@@ -211,6 +249,55 @@ impl Account {
             }
         }
     }
+
+    /// Process all mailbox creations currently queued in the delivery
+    /// database.
+    fn drain_mailbox_creations(&mut self) {
+        let creations = match self.deliverydb.pop_mailbox_creations() {
+            Ok(c) => c,
+            Err(e) => {
+                error!(
+                    "{} Failed to pop mailbox creations: {e:?}",
+                    self.log_prefix,
+                );
+                return;
+            },
+        };
+
+        for creation in creations {
+            let r = self.create_if_nx(CreateRequest {
+                name: creation.mailbox.clone(),
+                special_use: creation.special_use.iter().cloned().collect(),
+            });
+            let Err(e) = r else {
+                continue;
+            };
+
+            if creation.special_use.is_none() {
+                error!(
+                    "{} Failed to create mailbox '{}': {e:?}",
+                    self.log_prefix, creation.mailbox,
+                );
+                continue;
+            }
+
+            // The special use may not be one we support; the mailbox itself
+            // is still worth creating.
+            warn!(
+                "{} Creating '{}' without special use {:?}: {e:?}",
+                self.log_prefix, creation.mailbox, creation.special_use,
+            );
+            if let Err(e) = self.create_if_nx(CreateRequest {
+                name: creation.mailbox.clone(),
+                special_use: vec![],
+            }) {
+                error!(
+                    "{} Failed to create mailbox '{}': {e:?}",
+                    self.log_prefix, creation.mailbox,
+                );
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -247,6 +334,39 @@ mod test {
         assert_eq!(1, mb.select_response().unwrap().exists);
     }
 
+    #[test]
+    fn deliver_with_mailbox_creation() {
+        let mut fixture = TestFixture::new();
+        let mut delivery = DeliveryAccount::new(
+            LogPrefix::new("delivery".to_owned()),
+            fixture.root.path().to_owned(),
+        )
+        .unwrap();
+
+        let date = FixedOffset::east_opt(3600)
+            .unwrap()
+            .with_ymd_and_hms(2001, 2, 3, 4, 5, 6)
+            .unwrap();
+        let buf1 = delivery
+            .buffer_message_with_date(date, b"foobar" as &[u8])
+            .unwrap();
+        delivery.queue_mailbox_creation("foo/bar", None).unwrap();
+        delivery
+            .queue_mailbox_creation("Sent Items", Some(MailboxAttribute::Sent))
+            .unwrap();
+        delivery
+            .queue_mailbox_creation("Rubbish", Some(MailboxAttribute::Junk))
+            .unwrap();
+        delivery.deliver_buffered("foo/bar", &[], &buf1).unwrap();
+        delivery.deliver_buffered("Rubbish", &[], &buf1).unwrap();
+
+        let (mb, _) = fixture.select("foo/bar", false, None).unwrap();
+        assert_eq!(1, mb.select_response().unwrap().exists);
+        let (mb, _) = fixture.select("Rubbish", false, None).unwrap();
+        assert_eq!(1, mb.select_response().unwrap().exists);
+        fixture.select("Sent Items", false, None).unwrap();
+    }
+
     #[test]
     fn deliver_bad_destination() {
         let mut fixture = TestFixture::new();
@@ -39,8 +39,7 @@ impl Account {
     ) -> Result<(), Error> {
         mailbox.require_writable()?;
         let uids = mailbox.filter_uid_range(uids);
-        self.metadb
-            .expunge_mailbox_messages(mailbox.id, &mut uids.items(u32::MAX))?;
+        self.expunge_uids(mailbox, &mut uids.items(u32::MAX))?;
         Ok(())
     }
 
@@ -75,8 +74,8 @@ impl Account {
         };
 
         let indices = mailbox.uid_range_to_indices(uids, true)?;
-        self.metadb.expunge_mailbox_messages(
-            mailbox.id,
+        self.expunge_uids(
+            mailbox,
             &mut indices
                 .items(u32::MAX)
                 .map(|ix| &mailbox.messages[ix as usize])
@@ -103,8 +102,8 @@ impl Account {
             return Ok(());
         };
 
-        self.metadb.expunge_mailbox_messages(
-            mailbox.id,
+        self.expunge_uids(
+            mailbox,
             &mut mailbox
                 .messages
                 .iter()
@@ -114,6 +113,26 @@ impl Account {
         Ok(())
     }
 
+    /// Expunge the given UIDs from `mailbox`, or from the mailboxes they live
+    /// in if `mailbox` is virtual.
+    fn expunge_uids(
+        &mut self,
+        mailbox: &Mailbox,
+        uids: &mut dyn Iterator<Item = Uid>,
+    ) -> Result<(), Error> {
+        let Some(ref view) = mailbox.virtual_view else {
+            return self.metadb.expunge_mailbox_messages(mailbox.id, uids);
+        };
+
+        for (source_id, targets) in view.group_by_source(uids) {
+            self.metadb.expunge_mailbox_messages(
+                source_id,
+                &mut targets.into_iter().map(|(_, uid)| uid),
+            )?;
+        }
+        Ok(())
+    }
+
     /// Immediately purge all pending soft expunges which have a
     /// `last_activity` before the given datetime.
     ///
@@ -15,7 +15,7 @@
 //
 // You should have received a copy of the GNU General Public License along with
 // Crymap. If not, see <http://www.gnu.org/licenses/>.
-
+use std::fmt;
 use std::io::{self, BufRead};
 use std::mem;
 use std::sync::Arc;
@@ -145,6 +145,8 @@ impl Account {
             collect_vanished: request.collect_vanished,
             email_id: request.email_id,
             thread_id: request.thread_id,
+            preview: request.preview,
+            preview_lazy: request.preview_lazy,
         };
         self.fetch(mailbox, request, receiver).await
     }
@@ -272,8 +274,27 @@ impl Account {
                     );
                 }
 
+                let message_id = accessor.message_status.id;
+                let cached_preview = if request.preview {
+                    accessor.account.metadb.fetch_message_preview(message_id)?
+                } else {
+                    None
+                };
+                let generate_preview = request.preview
+                    && !request.preview_lazy
+                    && cached_preview.is_none();
+                if generate_preview {
+                    fetcher.add_preview();
+                }
+
                 let mut fetched = grovel(&mut accessor, fetcher)?;
 
+                if generate_preview {
+                    accessor.account.cache_preview(message_id, &fetched);
+                } else if request.preview {
+                    fetched.push(FetchedItem::Preview(cached_preview));
+                }
+
                 // Ensure any section parts are OK
                 for part in &mut fetched {
                     if let FetchedItem::BodySection((_, ref mut section)) =
@@ -305,6 +326,27 @@ impl Account {
         }
     }
 
+    /// Saves the freshly generated preview in `fetched` so that future
+    /// fetches need not regenerate it.
+    fn cache_preview(
+        &mut self,
+        message_id: storage::MessageId,
+        fetched: &[FetchedItem],
+    ) {
+        for item in fetched {
+            if let FetchedItem::Preview(Some(ref preview)) = *item {
+                if let Err(e) =
+                    self.metadb.cache_message_preview(message_id, preview)
+                {
+                    error!(
+                        "{} Failed to cache preview for message {}: {:?}",
+                        self.log_prefix, message_id.0, e,
+                    );
+                }
+            }
+        }
+    }
+
     /// Open the message with the given UID for reading.
     #[cfg(test)]
     pub fn open_message_by_uid(
@@ -330,7 +372,7 @@ impl Account {
 
     /// Open the given raw message ID for reading, with an already-loaded
     /// `MessageAccessData`.
-    fn open_message_with_access(
+    pub(super) fn open_message_with_access(
         &mut self,
         message_id: storage::MessageId,
         access: &storage::MessageAccessData,
@@ -424,6 +466,66 @@ If you cannot restore the message, it is safe to just delete this placeholder.\r
     }
 }
 
+impl Mailbox {
+    /// Determines which of the messages selected by a `FETCH` request fall
+    /// within the RFC 9394 `PARTIAL` window.
+    ///
+    /// Only messages in the snapshot, and which were modified after
+    /// `request.changed_since` if that is set, count towards the window.
+    pub fn seqnum_partial_fetch_ids(
+        &self,
+        request: &FetchRequest<Seqnum>,
+        partial: PartialRange,
+    ) -> SeqRange<Seqnum> {
+        self.partial_fetch_ids_impl(request, partial, |ix, _| {
+            Seqnum::from_index(ix)
+        })
+    }
+
+    /// Determines which of the messages selected by a `UID FETCH` request
+    /// fall within the RFC 9394 `PARTIAL` window.
+    ///
+    /// Only messages in the snapshot, and which were modified after
+    /// `request.changed_since` if that is set, count towards the window.
+    pub fn partial_fetch_ids(
+        &self,
+        request: &FetchRequest<Uid>,
+        partial: PartialRange,
+    ) -> SeqRange<Uid> {
+        self.partial_fetch_ids_impl(request, partial, |_, m| m.uid)
+    }
+
+    fn partial_fetch_ids_impl<ID>(
+        &self,
+        request: &FetchRequest<ID>,
+        partial: PartialRange,
+        id_of: impl Fn(usize, &MessageStatus) -> ID,
+    ) -> SeqRange<ID>
+    where
+        ID: TryFrom<u32> + Into<u32> + PartialOrd + Send + Sync + Copy,
+        SeqRange<ID>: fmt::Debug,
+    {
+        let candidates = self
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|&(_, m)| {
+                request
+                    .changed_since
+                    .is_none_or(|since| m.last_modified > since)
+            })
+            .map(|(ix, m)| id_of(ix, m))
+            .filter(|&id| request.ids.contains(id))
+            .collect::<Vec<_>>();
+
+        let mut ids = SeqRange::new();
+        for &id in partial.window(&candidates) {
+            ids.append(id);
+        }
+        ids
+    }
+}
+
 pub struct MailboxMessageAccessor<'a, 'm> {
     account: &'a mut Account,
     mailbox: &'m Mailbox,
@@ -577,6 +679,8 @@ mod test {
             collect_vanished: false,
             email_id: true,
             thread_id: true,
+            preview: true,
+            preview_lazy: false,
         };
         let prefetch = fixture.prefetch(&mut mb, &request).unwrap();
         let receiver = fixture.receiver();
@@ -606,6 +710,7 @@ mod test {
             let mut has_modseq = false;
             let mut has_emailid = false;
             let mut has_threadid = false;
+            let mut has_preview = false;
 
             for part in fetched {
                 match part {
@@ -620,6 +725,7 @@ mod test {
                     FetchedItem::Modseq(_) => has_modseq = true,
                     FetchedItem::EmailId(_) => has_emailid = true,
                     FetchedItem::ThreadIdNil => has_threadid = true,
+                    FetchedItem::Preview(p) => has_preview = p.is_some(),
                     part => panic!("Unexpected part: {:?}", part),
                 }
             }
@@ -635,9 +741,43 @@ mod test {
             assert!(has_modseq);
             assert!(has_emailid);
             assert!(has_threadid);
+            assert!(has_preview);
         }
     }
 
+    #[test]
+    fn preview_cached() {
+        let mut fixture = FetchFixture::new();
+        let mut mb = fixture.select("INBOX", false, None).unwrap().0;
+        let uid = fixture.uids[0];
+
+        let mut fetch_preview = |lazy: bool| {
+            let request = FetchRequest {
+                ids: SeqRange::just(uid),
+                preview: true,
+                preview_lazy: lazy,
+                ..FetchRequest::default()
+            };
+            let receiver = fixture.receiver();
+            futures::executor::block_on(
+                fixture.fetch(&mut mb, request, receiver),
+            )
+            .unwrap();
+
+            let mut fetched = fixture.received();
+            assert_eq!(1, fetched.len());
+            let mut items = fetched.pop().unwrap().1;
+            assert_eq!(1, items.len());
+            items.pop().unwrap().into_preview()
+        };
+
+        assert_eq!(None, fetch_preview(true));
+        let preview = fetch_preview(false).unwrap();
+        assert!(!preview.is_empty());
+        assert_eq!(Some(preview.clone()), fetch_preview(true));
+        assert_eq!(Some(preview), fetch_preview(false));
+    }
+
     #[test]
     fn fetches_correct_data() {
         let mut fixture = FetchFixture::new();
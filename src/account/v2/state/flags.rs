@@ -109,14 +109,39 @@ impl Account {
             .map(|ix| (ix as usize, mailbox.messages[ix as usize].uid))
             .collect::<Vec<_>>();
 
-        let db_results = self.metadb.modify_mailbox_message_flags(
-            mailbox.id,
-            &flags,
-            request.remove_listed,
-            request.remove_unlisted,
-            request.unchanged_since.unwrap_or(Modseq::MAX),
-            &mut target_ix_uids.iter().map(|&(_, uid)| uid),
-        )?;
+        let db_results = if let Some(ref view) = mailbox.virtual_view {
+            // Each message is modified within the real mailbox it lives in.
+            // Anything without a source has been expunged from it.
+            let mut results = vec![
+                storage::StoreResult::PreconditionsFailed;
+                target_ix_uids.len()
+            ];
+            for (source_id, targets) in
+                view.group_by_source(target_ix_uids.iter().map(|&(_, uid)| uid))
+            {
+                let source_results = self.metadb.modify_mailbox_message_flags(
+                    source_id,
+                    &flags,
+                    request.remove_listed,
+                    request.remove_unlisted,
+                    request.unchanged_since.unwrap_or(Modseq::MAX),
+                    &mut targets.iter().map(|&(_, uid)| uid),
+                )?;
+                for (&(ix, _), result) in targets.iter().zip(source_results) {
+                    results[ix] = result;
+                }
+            }
+            results
+        } else {
+            self.metadb.modify_mailbox_message_flags(
+                mailbox.id,
+                &flags,
+                request.remove_listed,
+                request.remove_unlisted,
+                request.unchanged_since.unwrap_or(Modseq::MAX),
+                &mut target_ix_uids.iter().map(|&(_, uid)| uid),
+            )?
+        };
 
         let mut successful_uids = target_ix_uids
             .iter()
@@ -28,29 +28,31 @@ use std::path::Path;
 use std::time::{Duration, SystemTime};
 
 use super::defs::*;
-use crate::{account::model::*, support::error::Error};
+use crate::support::error::Error;
 
 impl Account {
-    /// Idles.
+    /// Idles until `check` returns a value.
     ///
-    /// This blocks (asynchronously) until there is a non-trivial poll response
-    /// to return or an error occurs.
+    /// This blocks (asynchronously) until `check` returns `Some` or an error
+    /// occurs. `check` is invoked after draining deliveries, once immediately
+    /// and then again each time the account may have been modified. Besides
+    /// polling the selected mailbox, this allows the caller to watch for
+    /// changes elsewhere in the account, as needed for the NOTIFY extension.
     ///
-    /// The idle is cancelled by simply dropping the future. `mailbox` is only
-    /// mutated upon a non-trivial poll.
+    /// The idle is cancelled by simply dropping the future.
     #[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
-    pub async fn idle(
+    pub async fn idle_until<T>(
         &mut self,
-        mailbox: &mut Mailbox,
-    ) -> Result<PollResponse, Error> {
-        self.idle_poll(mailbox).await
+        check: impl FnMut(&mut Self) -> Result<Option<T>, Error>,
+    ) -> Result<T, Error> {
+        self.idle_poll(check).await
     }
 
     #[cfg(any(target_os = "linux", target_os = "freebsd"))]
-    pub async fn idle(
+    pub async fn idle_until<T>(
         &mut self,
-        mailbox: &mut Mailbox,
-    ) -> Result<PollResponse, Error> {
+        mut check: impl FnMut(&mut Self) -> Result<Option<T>, Error>,
+    ) -> Result<T, Error> {
         use std::os::unix::io::RawFd;
         use tokio::io::unix::AsyncFd;
 
@@ -194,9 +196,8 @@ impl Account {
         let (handle, asyncfd) = init(&self.metadb_path, &self.deliverydb_path)?;
         loop {
             self.drain_deliveries();
-            let poll = self.poll(mailbox)?;
-            if PollResponse::default() != poll {
-                return Ok(poll);
+            if let Some(result) = check(self)? {
+                return Ok(result);
             }
 
             let mut readable = asyncfd.readable().await?;
@@ -207,10 +208,10 @@ impl Account {
 
     // Always compiled to verify it builds.
     #[allow(dead_code)]
-    async fn idle_poll(
+    async fn idle_poll<T>(
         &mut self,
-        mailbox: &mut Mailbox,
-    ) -> Result<PollResponse, Error> {
+        mut check: impl FnMut(&mut Self) -> Result<Option<T>, Error>,
+    ) -> Result<T, Error> {
         let mut last_metadb = SystemTime::UNIX_EPOCH;
         let mut last_deliverydb = SystemTime::UNIX_EPOCH;
         loop {
@@ -220,9 +221,8 @@ impl Account {
                 last_metadb = metadb;
                 last_deliverydb = deliverydb;
                 self.drain_deliveries();
-                let poll = self.poll(mailbox)?;
-                if PollResponse::default() != poll {
-                    return Ok(poll);
+                if let Some(result) = check(self)? {
+                    return Ok(result);
                 }
             }
 
@@ -19,24 +19,32 @@
 use std::collections::HashSet;
 use std::fs;
 use std::io::{Read, Write};
+use std::net::IpAddr;
 use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
+use chrono::prelude::*;
 use log::{error, info, warn};
 
 use super::super::{account_config_file, storage};
 use super::defs::*;
+use super::login_throttle::LoginThrottle;
 use crate::{
     account::{
         key_store::{KeyStore, KeyStoreConfig},
         model::*,
     },
-    crypt::master_key::MasterKey,
+    crypt::master_key::{Argon2Params, MasterKey},
     support::{
-        error::Error, file_ops::IgnoreKinds, log_prefix::LogPrefix,
-        safe_name::is_safe_name, system_config::SystemConfig, unix_privileges,
-        user_config::UserConfig,
+        error::Error,
+        file_ops::IgnoreKinds,
+        log_prefix::LogPrefix,
+        safe_name::is_safe_name,
+        system_config::SystemConfig,
+        unix_privileges,
+        user_config::{AppPasswordRestriction, UserConfig},
     },
 };
 
@@ -53,6 +61,72 @@ pub enum LogInError {
     ConfigError,
     #[error("Error setting up account; refer to server logs for details")]
     SetupError,
+    #[error("Too many failed login attempts; try again later")]
+    LockedOut,
+}
+
+/// The protocol through which a login attempt is being made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogInProtocol {
+    Imap,
+    Submission,
+}
+
+impl LogInProtocol {
+    /// The name of the protocol as recorded in the login history.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Imap => "IMAP",
+            Self::Submission => "SUBMISSION",
+        }
+    }
+
+    /// Returns whether an application-specific password with the given
+    /// restriction may be used to log in through this protocol.
+    fn permits(self, restriction: AppPasswordRestriction) -> bool {
+        match restriction {
+            AppPasswordRestriction::None => true,
+            AppPasswordRestriction::ImapOnly => LogInProtocol::Imap == self,
+            AppPasswordRestriction::SubmissionOnly => {
+                LogInProtocol::Submission == self
+            },
+        }
+    }
+}
+
+/// Information about the client attempting to log in, which is recorded in
+/// the login history.
+#[derive(Clone, Debug, Default)]
+pub struct LogInClient {
+    /// The IP address of the client, if known.
+    pub peer_ip: Option<IpAddr>,
+    /// A description of the TLS session, if any.
+    pub tls: Option<String>,
+    /// The "name" field the client sent with the RFC 2971 `ID` command, if
+    /// any.
+    pub name: Option<String>,
+    /// All fields the client sent with the RFC 2971 `ID` command, if any.
+    pub id: Option<String>,
+}
+
+impl LogInClient {
+    fn history_entry(
+        &self,
+        protocol: LogInProtocol,
+        success: bool,
+        app_password: Option<&str>,
+    ) -> LoginHistoryEntry {
+        LoginHistoryEntry {
+            at: Utc::now(),
+            protocol: protocol.name().to_owned(),
+            success,
+            peer_ip: self.peer_ip.map(|ip| ip.to_string()),
+            tls: self.tls.clone(),
+            client_name: self.name.clone(),
+            client_id: self.id.clone(),
+            app_password: app_password.map(str::to_owned),
+        }
+    }
 }
 
 impl Account {
@@ -98,6 +172,10 @@ impl Account {
             root,
             common_paths,
             log_prefix,
+            app_password: None,
+            recovery_key: None,
+            password_hash_params: Argon2Params::default(),
+            login_history_id: None,
         })
     }
 
@@ -151,10 +229,18 @@ impl Account {
         let user_config = UserConfig {
             master_key: self
                 .master_key
-                .make_config(password)
+                .make_config(
+                    password,
+                    &self.password_hash_params,
+                    self.recovery_key.as_ref(),
+                )
                 .expect("Password hashing failed"),
             key_store: KeyStoreConfig::default(),
             smtp_out: Default::default(),
+            login: Default::default(),
+            expunge: Default::default(),
+            urlauth_keys: Default::default(),
+            app_passwords: Default::default(),
         };
 
         let user_config_toml = toml::to_vec(&user_config)
@@ -204,55 +290,122 @@ impl Account {
     /// Attempts to log in to an account identified by `userid` under
     /// `data_root` with the given password.
     ///
+    /// The password may either be the user's main password or one of their
+    /// application-specific passwords whose restrictions permit `protocol`.
+    ///
     /// On success, this returns the account itself plus the set of user IDs
     /// that are aliased to the user that logged in. As side-effects, the log
     /// prefix is updated to reflect the user, and privileges are dropped to
     /// reflect the user.
     ///
-    /// On failure, it returns the error to send to the client.
+    /// Both successful and failed attempts are recorded in the user's login
+    /// history, along with the details in `client`.
+    ///
+    /// Failed attempts are recorded in the login throttle. Attempts for a
+    /// user or from a network which is locked out are refused without
+    /// checking the password at all.
+    ///
+    /// On failure, it returns the error to send to the client, along with how
+    /// long the caller should wait before sending it. The wait increases with
+    /// each failure.
     pub fn log_in(
         log_prefix: LogPrefix,
         system_config: &SystemConfig,
         data_root: &Path,
         userid: &str,
         password: &str,
-    ) -> Result<(Self, HashSet<String>), LogInError> {
-        if !is_safe_name(userid) {
-            return Err(LogInError::IllegalUserId);
+        protocol: LogInProtocol,
+        client: &LogInClient,
+    ) -> Result<(Self, HashSet<String>), (LogInError, Duration)> {
+        let peer_ip = client.peer_ip;
+        let safe_userid = is_safe_name(userid).then_some(userid);
+        let mut throttle = LoginThrottle::new(
+            log_prefix.clone(),
+            data_root,
+            &system_config.security.login_throttle,
+        );
+
+        if let Some(locked_until) = throttle.locked_until(safe_userid, peer_ip)
+        {
+            warn!(
+                "{} Refused login for user '{}' due to lockout until {}",
+                log_prefix,
+                userid,
+                locked_until.to_rfc3339(),
+            );
+            return Err((LogInError::LockedOut, Duration::ZERO));
+        }
+
+        if safe_userid.is_none() {
+            return Err((
+                LogInError::IllegalUserId,
+                throttle.record_failure(None, peer_ip),
+            ));
         }
 
         let mut user_dir = data_root.join(userid);
 
         let user_data_file = account_config_file(&user_dir);
-        let (user_config, master_key) = fs::File::open(user_data_file)
-            .ok()
-            .and_then(|f| {
-                let mut buf = Vec::<u8>::new();
-                f.take(65536).read_to_end(&mut buf).ok()?;
-                toml::from_slice::<UserConfig>(&buf).ok()
-            })
-            .and_then(|config| {
-                let master_key = MasterKey::from_config(
-                    &config.master_key,
-                    password.as_bytes(),
-                )?;
-                Some((config, master_key))
-            })
-            .ok_or_else(|| {
-                // Only log a warning if a password was actually provided.
-                // Login attempts with no password aren't generally remarkable,
-                // but importantly, they can occur if the user accidentally
-                // inputs their password in the username field. For the same
-                // reason, we're silent if the userid and password are equal.
-                if !password.is_empty() && password != userid {
-                    warn!(
-                        "{} Rejected login for user '{}'",
-                        log_prefix, userid
-                    );
-                }
+        let (user_config, master_key, app_password) =
+            fs::File::open(user_data_file)
+                .ok()
+                .and_then(|f| {
+                    let mut buf = Vec::<u8>::new();
+                    f.take(65536).read_to_end(&mut buf).ok()?;
+                    toml::from_slice::<UserConfig>(&buf).ok()
+                })
+                .and_then(|config| {
+                    if let Some(master_key) = MasterKey::from_config(
+                        &config.master_key,
+                        password.as_bytes(),
+                    ) {
+                        return Some((config, master_key, None));
+                    }
+
+                    // Application passwords are prefixed with their name, so
+                    // at most one of them needs to be hashed. The secret part
+                    // is base64 and so never contains a ':'.
+                    let (name, _) = password.rsplit_once(':')?;
+                    let ap = config
+                        .app_passwords
+                        .get(name)
+                        .filter(|ap| protocol.permits(ap.restriction))?;
+                    let master_key = MasterKey::from_config(
+                        &ap.master_key,
+                        password.as_bytes(),
+                    )?;
+                    let name = name.to_owned();
+                    Some((config, master_key, Some(name)))
+                })
+                .ok_or_else(|| {
+                    // Only log a warning if a password was actually provided.
+                    // Login attempts with no password aren't generally remarkable,
+                    // but importantly, they can occur if the user accidentally
+                    // inputs their password in the username field. For the same
+                    // reason, we're silent if the userid and password are equal.
+                    if !password.is_empty() && password != userid {
+                        warn!(
+                            "{} Rejected login for user '{}'",
+                            log_prefix, userid
+                        );
+                    }
+
+                    if user_dir.is_dir() {
+                        super::login_history::queue_failed_log_in(
+                            &log_prefix,
+                            system_config,
+                            &user_dir,
+                            &client.history_entry(protocol, false, None),
+                        );
+                    }
 
-                LogInError::InvalidCredentials
-            })?;
+                    (
+                        LogInError::InvalidCredentials,
+                        throttle.record_failure(safe_userid, peer_ip),
+                    )
+                })?;
+
+        throttle.record_success(userid);
 
         let mut aliases = HashSet::<String>::new();
         aliases.insert(userid.to_owned());
@@ -290,7 +443,14 @@ impl Account {
         // Login successful (at least barring further operational issues)
 
         log_prefix.set_user(userid.to_owned());
-        info!("{} Login successful", log_prefix);
+        if let Some(ref app_password) = app_password {
+            info!(
+                "{} Login successful with application password '{}'",
+                log_prefix, app_password,
+            );
+        } else {
+            info!("{} Login successful", log_prefix);
+        }
 
         unix_privileges::assume_user_privileges(
             &log_prefix.to_string(),
@@ -298,19 +458,77 @@ impl Account {
             &mut user_dir,
             false,
         )
-        .map_err(|_| LogInError::ConfigError)?;
+        .map_err(|_| (LogInError::ConfigError, Duration::ZERO))?;
 
         let mut account =
             Account::new(log_prefix.clone(), user_dir, Arc::new(master_key))
                 .map_err(|e| {
                     error!("{} Error setting up account: {e}", log_prefix);
-                    LogInError::SetupError
+                    (LogInError::SetupError, Duration::ZERO)
                 })?;
         account.init(&user_config.key_store).map_err(|e| {
             error!("{} Error initialising account: {e}", log_prefix);
-            LogInError::SetupError
+            (LogInError::SetupError, Duration::ZERO)
         })?;
 
+        account.apply_security_config(&system_config.security);
+
+        let master_key_config = match app_password {
+            None => Some(&user_config.master_key),
+            Some(ref name) => {
+                user_config.app_passwords.get(name).map(|ap| &ap.master_key)
+            },
+        };
+        if master_key_config.is_some_and(|c| {
+            c.is_outdated(&system_config.security.password_hash)
+        }) {
+            if let Err(e) =
+                account.rehash_password(password, app_password.as_deref())
+            {
+                warn!(
+                    "{} Failed to re-hash password with current parameters: {e}",
+                    log_prefix,
+                );
+            }
+        }
+
+        account.record_log_in(
+            client.history_entry(protocol, true, app_password.as_deref()),
+            user_config.login.new_device_notifications,
+        );
+
+        if let Some(app_password) = app_password {
+            if let Err(e) = account.touch_app_password(&app_password) {
+                warn!(
+                    "{} Failed to record use of application password: {e}",
+                    log_prefix,
+                );
+            }
+            account.app_password = Some(app_password);
+        }
+
         Ok((account, aliases))
     }
+
+    /// Ends the session without ending the process, scrubbing the user's key
+    /// material from memory.
+    ///
+    /// Cached private keys are discarded and the master key is zeroed once
+    /// everything else holding it (i.e. the metadata database) has been
+    /// dropped along with the account.
+    pub fn log_out(mut self) {
+        self.key_store.clear_cache();
+        let master_key = Arc::clone(&self.master_key);
+        let log_prefix = self.log_prefix.clone();
+        drop(self);
+
+        match Arc::try_unwrap(master_key) {
+            Ok(mut master_key) => master_key.zeroise(),
+            // It'll still be zeroed when the last reference goes away.
+            Err(_) => warn!(
+                "{} Master key still in use after logging out",
+                log_prefix,
+            ),
+        }
+    }
 }
@@ -0,0 +1,387 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::prelude::*;
+use log::{error, info, warn};
+use tempfile::NamedTempFile;
+
+use super::super::storage;
+use super::defs::*;
+use crate::{
+    account::message_format,
+    support::{error::Error, file_ops},
+};
+
+impl Account {
+    /// Retires private keys older than the user's configured
+    /// `retire_after_days`, if any.
+    ///
+    /// Every message whose session key is encrypted with a retiring key is
+    /// rewritten with the session key encrypted with the current key instead.
+    /// Since the message file changes, it also moves to a new canonical path,
+    /// and the database backups are updated to refer to the new path. Queued
+    /// deliveries are processed the same way. Once no known message depends
+    /// on the retiring keys, they are destroyed.
+    ///
+    /// If any message or backup cannot be rewritten, no keys are destroyed,
+    /// so that the whole process is retried next time.
+    pub(super) fn retire_old_keys(
+        &mut self,
+        now: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let Some(days) = self.load_config()?.key_store.retire_after_days else {
+            return Ok(());
+        };
+
+        let retiring = self.key_store.list_retirable_keys(
+            (now - chrono::Duration::days(days.into())).into(),
+        )?;
+        if retiring.is_empty() {
+            return Ok(());
+        }
+
+        info!("{} Retiring keys: {}", self.log_prefix, retiring.join(", "),);
+
+        let message_ids = self.metadb.fetch_all_message_ids()?;
+        let mut max_message_id = message_ids.last().copied();
+        let (mut rewrapped, mut failed) =
+            self.rewrap_messages(message_ids, &retiring);
+
+        if let Err(e) = self.update_backup_message_paths() {
+            error!(
+                "{} Failed to update database backups: {e:?}",
+                self.log_prefix,
+            );
+            failed += 1;
+        }
+
+        // A delivery queued since maintenance drained the queue can still be
+        // encrypted with a retiring key (e.g. if the delivering process loaded
+        // the public key before it was rotated), so take in everything queued
+        // so far and process the resulting messages until nothing new
+        // arrives. These are newer than any backup, so the backups don't need
+        // updating again.
+        loop {
+            self.drain_deliveries();
+            let new_message_ids = self
+                .metadb
+                .fetch_all_message_ids()?
+                .into_iter()
+                .filter(|&id| Some(id) > max_message_id)
+                .collect::<Vec<_>>();
+            if new_message_ids.is_empty() {
+                break;
+            }
+
+            max_message_id = new_message_ids.last().copied();
+            let (r, f) = self.rewrap_messages(new_message_ids, &retiring);
+            rewrapped += r;
+            failed += f;
+        }
+
+        if rewrapped > 0 {
+            info!(
+                "{} Re-encrypted {rewrapped} messages with the current key",
+                self.log_prefix,
+            );
+        }
+
+        if failed > 0 {
+            warn!(
+                "{} Not destroying old keys since {failed} messages or backups \
+                 could not be updated",
+                self.log_prefix,
+            );
+            return Ok(());
+        }
+
+        for name in &retiring {
+            self.key_store.destroy_private_key(name)?;
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites each of the given messages whose session key is encrypted
+    /// with one of the keys in `retiring`.
+    ///
+    /// Returns the number of messages rewritten and the number which could
+    /// not be.
+    fn rewrap_messages(
+        &mut self,
+        message_ids: Vec<storage::MessageId>,
+        retiring: &[String],
+    ) -> (usize, usize) {
+        let mut rewrapped = 0;
+        let mut failed = 0;
+        for message_id in message_ids {
+            match self.rewrap_message(message_id, retiring) {
+                Ok(true) => rewrapped += 1,
+                Ok(false) => {},
+                Err(e) => {
+                    error!(
+                        "{} Failed to re-encrypt message {}: {e:?}",
+                        self.log_prefix, message_id.0,
+                    );
+                    failed += 1;
+                },
+            }
+        }
+
+        (rewrapped, failed)
+    }
+
+    /// Points the messages in every database backup at their current files.
+    ///
+    /// Message IDs are never reused, so a message in a backup with the same
+    /// ID as one in the live database is the same message. Without this, a
+    /// message re-encrypted after a backup was made could not be restored
+    /// from that backup, since its old file is deleted.
+    fn update_backup_message_paths(&mut self) -> Result<(), Error> {
+        let paths = self
+            .metadb
+            .fetch_all_message_paths()?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let xex_vfs = storage::XexVfs::new(Arc::clone(&self.master_key))?;
+
+        for date in self.list_backups()? {
+            let backup_path = self
+                .backup_path
+                .join(format!("{METADB_NAME}.{}", date.format("%Y-%m-%d")));
+
+            // As with restoring, the backup must be opened under the original
+            // name. Working on a copy and renaming it into place means the
+            // backup is never left half-updated.
+            let tmpdir = tempfile::TempDir::new_in(&self.common_paths.tmp)?;
+            let copy_path = tmpdir.path().join(METADB_NAME);
+            fs::copy(&backup_path, &copy_path)?;
+
+            let updated = storage::MetaDb::new(
+                &self.log_prefix,
+                copy_path.clone(),
+                &xex_vfs,
+            )?
+            .update_message_paths(&paths)?;
+            if 0 == updated {
+                continue;
+            }
+
+            fs::File::open(&copy_path)?.sync_all()?;
+            fs::rename(&copy_path, &backup_path)?;
+            info!(
+                "{} Updated {updated} message paths in backup from {date}",
+                self.log_prefix,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites the given message if its session key is encrypted with one of
+    /// the keys in `retiring`.
+    ///
+    /// Returns whether the message was rewritten.
+    fn rewrap_message(
+        &mut self,
+        message_id: storage::MessageId,
+        retiring: &[String],
+    ) -> Result<bool, Error> {
+        let access = match self.metadb.access_message(message_id) {
+            Ok(access) => access,
+            // Forgotten since we listed the IDs.
+            Err(Error::ExpungedMessage) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+
+        let src = self.message_store.open(Path::new(&access.path))?;
+        let mut buffer_file = NamedTempFile::new_in(&self.common_paths.tmp)?;
+        {
+            let mut dst = io::BufWriter::new(buffer_file.as_file_mut());
+            if !message_format::rewrap_message(
+                io::BufReader::new(src),
+                &mut dst,
+                &mut self.key_store,
+                |name| retiring.iter().any(|r| r == name),
+            )? {
+                return Ok(false);
+            }
+            dst.flush()?;
+        }
+
+        file_ops::chmod(buffer_file.path(), 0o440)?;
+        buffer_file.as_file_mut().sync_all()?;
+
+        let new_path = fs::File::open(buffer_file.path())
+            .and_then(storage::MessageStore::canonical_path)?;
+        // The new file is put in place before the database is updated. If we
+        // crash in between, the new file gets recovered into the inbox as an
+        // unaccounted message, but nothing is lost.
+        self.message_store.insert(buffer_file.path(), &new_path)?;
+        self.metadb.set_message_path(
+            message_id,
+            new_path.to_str().expect("canonical paths are always UTF-8"),
+        )?;
+
+        match self.message_store.delete(Path::new(&access.path)) {
+            Ok(()) => {},
+            Err(e) if io::ErrorKind::NotFound == e.kind() => {},
+            Err(e) => {
+                error!("{} rm {}: {e:?}", self.log_prefix, access.path);
+            },
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::delivery::DeliveryAccount;
+    use super::*;
+    use crate::{
+        account::model::{SeqRange, SetUserConfigRequest},
+        support::log_prefix::LogPrefix,
+    };
+
+    #[test]
+    fn retire_old_keys() {
+        let mut fixture = TestFixture::new();
+        let uid1 = fixture.simple_append_data("INBOX", b"message one");
+        let uid2 = fixture.simple_append_data("INBOX", b"message two");
+
+        let config = fixture.load_config().unwrap();
+        let old_key = Utc::now()
+            .format(&config.key_store.internal_key_pattern)
+            .to_string();
+
+        // Nothing happens while retirement is disabled.
+        fixture
+            .retire_old_keys(Utc::now() + chrono::Duration::days(30))
+            .unwrap();
+        fixture.key_store.get_private_key(&old_key).unwrap();
+
+        // A backup made while the messages still use the old key.
+        let backup_date = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        fs::create_dir_all(&fixture.backup_path).unwrap();
+        let backup_path = fixture
+            .backup_path
+            .join(format!("{METADB_NAME}.2024-01-02"));
+        let tmp = fixture.common_paths.tmp.clone();
+        fixture.metadb.back_up(&tmp, &backup_path).unwrap();
+
+        // A message encrypted with the old key, but delivered only once
+        // retirement is about to start.
+        let mut delivery = DeliveryAccount::new(
+            LogPrefix::new("delivery".to_owned()),
+            fixture.root.path().to_owned(),
+        )
+        .unwrap();
+        let delivered =
+            delivery.buffer_message(b"message three" as &[u8]).unwrap();
+
+        fixture
+            .update_config(SetUserConfigRequest {
+                internal_key_pattern: Some("new-internal".to_owned()),
+                external_key_pattern: Some("new-external".to_owned()),
+                key_retirement_days: Some(Some(1)),
+                ..SetUserConfigRequest::default()
+            })
+            .unwrap();
+        let config = fixture.load_config().unwrap();
+        fixture.key_store.init(&config.key_store).unwrap();
+
+        // The old keys aren't old enough yet.
+        fixture.retire_old_keys(Utc::now()).unwrap();
+        fixture.key_store.get_private_key(&old_key).unwrap();
+
+        let (mut mb, _) = fixture.select("INBOX", false, None).unwrap();
+        let old_path = {
+            let id = mb.messages[0].id;
+            fixture.metadb.access_message(id).unwrap().path
+        };
+
+        delivery.deliver_buffered("INBOX", &[], &delivered).unwrap();
+        fixture
+            .retire_old_keys(Utc::now() + chrono::Duration::days(2))
+            .unwrap();
+
+        fixture.key_store.clear_cache();
+        assert!(matches!(
+            fixture.key_store.get_private_key(&old_key),
+            Err(Error::NamedKeyNotFound),
+        ));
+        fixture.key_store.get_private_key("new-internal").unwrap();
+        fixture.key_store.get_private_key("new-external").unwrap();
+
+        let new_path = {
+            let id = mb.messages[0].id;
+            fixture.metadb.access_message(id).unwrap().path
+        };
+        assert_ne!(old_path, new_path);
+        assert!(fixture.message_store.open(Path::new(&old_path)).is_err());
+
+        fixture.poll(&mut mb).unwrap();
+        assert_eq!(3, mb.messages.len());
+        let uid3 = mb.messages[2].uid;
+
+        // All messages are still readable. The first pass goes through the new
+        // key; the second uses the cached session key.
+        for _ in 0..2 {
+            for (uid, expected) in [
+                (uid1, "message one"),
+                (uid2, "message two"),
+                (uid3, "message three"),
+            ] {
+                let mut content = String::new();
+                let (_, mut reader) =
+                    fixture.open_message_by_uid(&mb, uid).unwrap();
+                io::Read::read_to_string(&mut reader, &mut content).unwrap();
+                assert_eq!(expected, content);
+            }
+        }
+
+        // The backup from before retirement still works.
+        let inbox_id = fixture.metadb.find_mailbox("INBOX").unwrap();
+        fixture
+            .metadb
+            .expunge_mailbox_messages(inbox_id, &mut std::iter::once(uid2))
+            .unwrap();
+        assert_eq!(
+            1,
+            fixture
+                .restore_messages(backup_date, "INBOX", &SeqRange::just(uid2))
+                .unwrap(),
+        );
+        let (restored, _) = fixture.select("Restored", false, None).unwrap();
+        let mut content = String::new();
+        let (_, mut reader) = fixture
+            .open_message_by_uid(&restored, restored.messages[0].uid)
+            .unwrap();
+        io::Read::read_to_string(&mut reader, &mut content).unwrap();
+        assert_eq!("message two", content);
+
+        assert!(fixture.verify(None).unwrap().damaged_messages.is_empty());
+    }
+}
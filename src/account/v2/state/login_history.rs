@@ -0,0 +1,360 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::{self, Write};
+use std::path::Path;
+
+use chrono::prelude::*;
+use log::{error, info, warn};
+
+use super::defs::*;
+use super::delivery::DeliveryAccount;
+use crate::{
+    account::model::*,
+    crypt::data_stream,
+    support::{
+        compression::{Compression, FinishWrite},
+        error::Error,
+        log_prefix::LogPrefix,
+        system_config::SystemConfig,
+        unix_privileges,
+    },
+};
+
+impl Account {
+    /// Records a successful login in the login history.
+    ///
+    /// Failed logins queued in the delivery database since the last login are
+    /// moved into the history first. If `notify_new_device` is true and the
+    /// client/IP combination of `entry` has never logged in successfully
+    /// before, a notification message is delivered into the INBOX.
+    ///
+    /// Errors are logged but otherwise ignored, since they should not prevent
+    /// the user from logging in.
+    pub(super) fn record_log_in(
+        &mut self,
+        entry: LoginHistoryEntry,
+        notify_new_device: bool,
+    ) {
+        self.import_login_failures();
+
+        let new_device = match (
+            self.metadb.has_successful_login(),
+            self.metadb.is_known_login_device(
+                entry.peer_ip.as_deref(),
+                entry.client_name.as_deref(),
+            ),
+        ) {
+            // The very first login (which includes the first login after
+            // upgrading to a version which records the history) isn't
+            // interesting, since every device would be new.
+            (Ok(has_history), Ok(known)) => has_history && !known,
+            (Err(e), _) | (_, Err(e)) => {
+                error!(
+                    "{} Failed to query login history: {e}",
+                    self.log_prefix
+                );
+                false
+            },
+        };
+
+        match self.metadb.insert_login_history(&entry) {
+            Ok(id) => self.login_history_id = Some(id),
+            Err(e) => {
+                error!("{} Failed to record login: {e}", self.log_prefix);
+            },
+        }
+
+        if new_device && notify_new_device {
+            info!(
+                "{} First login from this client and address",
+                self.log_prefix,
+            );
+            if let Err(e) = self.deliver_new_device_notification(&entry) {
+                error!(
+                    "{} Failed to deliver new device notification: {e}",
+                    self.log_prefix,
+                );
+            }
+        }
+    }
+
+    /// Updates the RFC 2971 `ID` information of the login history entry for
+    /// the current session.
+    ///
+    /// This is used when the client identifies itself only after logging in.
+    pub fn update_login_client(
+        &mut self,
+        client_name: Option<&str>,
+        client_id: Option<&str>,
+    ) -> Result<(), Error> {
+        let Some(id) = self.login_history_id else {
+            return Ok(());
+        };
+
+        self.metadb
+            .update_login_history_client(id, client_name, client_id)
+    }
+
+    /// Returns up to `limit` of the most recent login history entries, most
+    /// recent first.
+    ///
+    /// Failed logins that have not yet been moved into the history are
+    /// included.
+    pub fn login_history(
+        &mut self,
+        limit: u32,
+    ) -> Result<Vec<LoginHistoryEntry>, Error> {
+        self.import_login_failures();
+        self.metadb.fetch_login_history(limit)
+    }
+
+    fn import_login_failures(&mut self) {
+        let failures = match self.deliverydb.pop_login_failures() {
+            Ok(failures) => failures,
+            Err(e) => {
+                error!(
+                    "{} Failed to fetch queued login failures: {e}",
+                    self.log_prefix,
+                );
+                return;
+            },
+        };
+
+        for data in failures {
+            let entry = match self.decrypt_login_failure(&data) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!(
+                        "{} Discarding undecipherable login failure: {e}",
+                        self.log_prefix,
+                    );
+                    continue;
+                },
+            };
+
+            if let Err(e) = self.metadb.insert_login_history(&entry) {
+                error!(
+                    "{} Failed to record login failure: {e}",
+                    self.log_prefix,
+                );
+            }
+        }
+    }
+
+    fn decrypt_login_failure(
+        &mut self,
+        data: &[u8],
+    ) -> Result<LoginHistoryEntry, Error> {
+        let stream = data_stream::Reader::new(data, None, |k| {
+            self.key_store.get_private_key(k)
+        })?;
+        let compression = stream.metadata.compression;
+        let stream = compression.decompressor(stream)?;
+        serde_cbor::from_reader(stream).map_err(Into::into)
+    }
+
+    fn deliver_new_device_notification(
+        &mut self,
+        entry: &LoginHistoryEntry,
+    ) -> Result<(), Error> {
+        let message = format!(
+            "\
+From: \"Mailer Daemon\" <postmaster>\r
+Subject: New login to your account\r
+Date: {now}\r
+Content-Type: text/plain; charset=utf-8\r
+Content-Transfer-Encoding: 8bit\r
+MIME-Version: 1.0\r
+\r
+Your account was just accessed from a client and address combination which\r
+has not been used before.\r
+\r
+\tTime: {at}\r
+\tProtocol: {protocol}\r
+\tAddress: {peer_ip}\r
+\tClient: {client}\r
+\tTLS: {tls}\r
+\tApplication password: {app_password}\r
+\r
+If this was you, no action is necessary. Otherwise, change your password\r
+immediately and revoke any application passwords you do not recognise.\r
+",
+            now = Utc::now().to_rfc2822(),
+            at = entry.at.to_rfc3339(),
+            protocol = entry.protocol,
+            peer_ip = entry.peer_ip.as_deref().unwrap_or("unknown"),
+            client = entry.client_id.as_deref().unwrap_or("unknown"),
+            tls = entry.tls.as_deref().unwrap_or("none"),
+            app_password = entry.app_password.as_deref().unwrap_or("none"),
+        );
+
+        self.append(
+            "INBOX",
+            Utc::now().into(),
+            [Flag::Flagged],
+            message.as_bytes(),
+        )?;
+        Ok(())
+    }
+}
+
+impl DeliveryAccount {
+    /// Queues a record of a failed login to be moved into the login history
+    /// the next time the user logs in.
+    ///
+    /// The record is encrypted with the user's public key, so it can only be
+    /// read once the user has logged in.
+    pub fn queue_login_failure(
+        &mut self,
+        entry: &LoginHistoryEntry,
+    ) -> Result<(), Error> {
+        let mut data = Vec::<u8>::new();
+        {
+            let compression = Compression::DEFAULT_FOR_STATE;
+            let (key_name, pub_key) =
+                self.key_store.get_default_public_key()?;
+            let mut crypt_writer = data_stream::Writer::new(
+                &mut data,
+                pub_key,
+                key_name.to_owned(),
+                compression,
+            )?;
+            {
+                let mut compressor =
+                    compression.compressor(&mut crypt_writer)?;
+                serde_cbor::to_writer(&mut compressor, entry)?;
+                compressor.finish()?;
+            }
+            crypt_writer.flush()?;
+        }
+
+        self.deliverydb.queue_login_failure(&data)
+    }
+}
+
+/// Queues a record of a failed login against the user whose data is in
+/// `user_dir`.
+///
+/// This is called before privileges have been dropped, so it temporarily
+/// assumes the privileges of the user, restoring the original privileges
+/// before returning. Errors are logged but otherwise ignored.
+pub(super) fn queue_failed_log_in(
+    log_prefix: &LogPrefix,
+    system_config: &SystemConfig,
+    user_dir: &Path,
+    entry: &LoginHistoryEntry,
+) {
+    struct RestoreUidGid;
+    impl Drop for RestoreUidGid {
+        fn drop(&mut self) {
+            let _ = nix::unistd::seteuid(nix::unistd::getuid());
+            let _ = nix::unistd::setegid(nix::unistd::getgid());
+        }
+    }
+
+    let mut user_dir = user_dir.to_owned();
+    let _restore_uid_gid = RestoreUidGid;
+    if unix_privileges::assume_user_privileges(
+        &log_prefix.to_string(),
+        system_config.security.chroot_system,
+        &mut user_dir,
+        true,
+    )
+    .is_err()
+    {
+        return;
+    }
+
+    let result = DeliveryAccount::new(log_prefix.deep_clone(), user_dir)
+        .and_then(|mut account| account.queue_login_failure(entry));
+    if let Err(e) = result {
+        match e {
+            // The user has never logged in, so there's no public key yet.
+            Error::Io(ref e) if io::ErrorKind::NotFound == e.kind() => {},
+            e => {
+                warn!("{} Failed to record failed login: {e}", log_prefix);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(success: bool) -> LoginHistoryEntry {
+        LoginHistoryEntry {
+            at: DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap(),
+            protocol: "IMAP".to_owned(),
+            success,
+            peer_ip: Some("192.0.2.3".to_owned()),
+            tls: None,
+            client_name: None,
+            client_id: None,
+            app_password: None,
+        }
+    }
+
+    fn inbox_size(fixture: &mut TestFixture) -> usize {
+        let (mb, _) = fixture.select("INBOX", false, None).unwrap();
+        mb.select_response().unwrap().exists
+    }
+
+    #[test]
+    fn login_history_and_notifications() {
+        let mut fixture = TestFixture::new();
+        let mut delivery = DeliveryAccount::new(
+            LogPrefix::new("delivery".to_owned()),
+            fixture.root.path().to_owned(),
+        )
+        .unwrap();
+
+        let failure = entry(false);
+        delivery.queue_login_failure(&failure).unwrap();
+
+        // First login; not a new device since there is no history.
+        fixture.record_log_in(entry(true), true);
+        assert_eq!(0, inbox_size(&mut fixture));
+
+        let history = fixture.login_history(10).unwrap();
+        assert_eq!(2, history.len());
+        assert!(history[0].success);
+        assert_eq!(failure, history[1]);
+
+        // Same device again.
+        fixture.record_log_in(entry(true), true);
+        assert_eq!(0, inbox_size(&mut fixture));
+
+        // Identifying the client afterwards makes it a different device.
+        fixture
+            .update_login_client(Some("Foo"), Some("\"name\" = \"Foo\";"))
+            .unwrap();
+        let history = fixture.login_history(1).unwrap();
+        assert_eq!(Some("Foo"), history[0].client_name.as_deref());
+
+        let mut new_ip = entry(true);
+        new_ip.peer_ip = Some("192.0.2.4".to_owned());
+        fixture.record_log_in(new_ip.clone(), false);
+        assert_eq!(0, inbox_size(&mut fixture));
+
+        new_ip.peer_ip = Some("192.0.2.5".to_owned());
+        fixture.record_log_in(new_ip, true);
+        assert_eq!(1, inbox_size(&mut fixture));
+    }
+}
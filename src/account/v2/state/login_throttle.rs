@@ -0,0 +1,390 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+//! Throttling of failed login attempts.
+//!
+//! Failures are tracked in a SQLite database at the root of the users
+//! directory, keyed both by user name and by client IP prefix. The database is
+//! only ever touched by processes that have not (yet) dropped privileges to a
+//! particular user.
+//!
+//! Errors accessing the database are logged but otherwise ignored, so that a
+//! problem with the database cannot prevent legitimate users from logging in.
+
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use log::{error, warn};
+
+use super::super::storage;
+use crate::support::{
+    error::Error, log_prefix::LogPrefix, system_config::LoginThrottleConfig,
+};
+
+const DB_NAME: &str = ".login-throttle.sqlite";
+
+/// Tracks failed login attempts and enforces delays and lockouts.
+pub struct LoginThrottle {
+    log_prefix: LogPrefix,
+    config: LoginThrottleConfig,
+    db: Option<storage::LoginThrottleDb>,
+}
+
+/// The state of a single throttling key, for administrative purposes.
+#[derive(Clone, Debug)]
+pub struct LoginThrottleEntry {
+    /// The key, such as `user:azure` or `ip:192.0.2.0/24`.
+    pub key: String,
+    /// The number of failures within the current window.
+    pub failures: u32,
+    /// The time of the most recent failure.
+    pub last_failure: DateTime<Utc>,
+    /// If set, the time until which the key is locked out.
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+impl LoginThrottle {
+    /// Opens the throttle for the users under `data_root`.
+    ///
+    /// If throttling is disabled or the database cannot be opened, the
+    /// returned throttle does nothing.
+    pub fn new(
+        log_prefix: LogPrefix,
+        data_root: &Path,
+        config: &LoginThrottleConfig,
+    ) -> Self {
+        let db = if config.enabled {
+            match storage::LoginThrottleDb::new(
+                &log_prefix,
+                &data_root.join(DB_NAME),
+            ) {
+                Ok(db) => Some(db),
+                Err(e) => {
+                    error!(
+                        "{} Failed to open login throttle database: {e}",
+                        log_prefix,
+                    );
+                    None
+                },
+            }
+        } else {
+            None
+        };
+
+        Self {
+            log_prefix,
+            config: config.clone(),
+            db,
+        }
+    }
+
+    /// Opens the throttle database under `data_root` for administration.
+    ///
+    /// Returns `None` if the database does not exist, which means that no
+    /// failures have ever been recorded.
+    pub fn open_existing(
+        log_prefix: LogPrefix,
+        data_root: &Path,
+    ) -> Result<Option<Self>, Error> {
+        let path = data_root.join(DB_NAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let db = storage::LoginThrottleDb::new(&log_prefix, &path)?;
+        Ok(Some(Self {
+            log_prefix,
+            config: LoginThrottleConfig::default(),
+            db: Some(db),
+        }))
+    }
+
+    /// Returns the latest time until which any key applicable to a login
+    /// attempt for `userid` from `peer_ip` is locked out, if any.
+    pub(super) fn locked_until(
+        &mut self,
+        userid: Option<&str>,
+        peer_ip: Option<IpAddr>,
+    ) -> Option<DateTime<Utc>> {
+        let keys = self.keys(userid, peer_ip);
+        let db = self.db.as_mut()?;
+        let now = Utc::now();
+
+        let mut ret = None::<DateTime<Utc>>;
+        for (key, _) in keys {
+            match db.locked_until(&key, now) {
+                Ok(Some(until)) => {
+                    ret = Some(ret.map_or(until, |r| r.max(until)));
+                },
+                Ok(None) => {},
+                Err(e) => {
+                    error!(
+                        "{} Failed to check login throttle for {key}: {e}",
+                        self.log_prefix,
+                    );
+                },
+            }
+        }
+
+        ret
+    }
+
+    /// Records a failed login attempt for `userid` from `peer_ip`.
+    ///
+    /// Returns how long the caller should wait before responding to the
+    /// client.
+    pub(super) fn record_failure(
+        &mut self,
+        userid: Option<&str>,
+        peer_ip: Option<IpAddr>,
+    ) -> Duration {
+        let keys = self.keys(userid, peer_ip);
+        let Some(db) = self.db.as_mut() else {
+            return Duration::ZERO;
+        };
+
+        let now = Utc::now();
+        let window_start = now
+            - chrono::Duration::seconds(
+                self.config
+                    .failure_window_secs
+                    .try_into()
+                    .unwrap_or(i64::MAX),
+            );
+        let lock_until = now
+            + chrono::Duration::seconds(
+                self.config.lockout_secs.try_into().unwrap_or(i64::MAX),
+            );
+
+        let mut max_failures_seen = 0u32;
+        for (key, max_failures) in keys {
+            match db.record_failure(
+                &key,
+                now,
+                window_start,
+                max_failures,
+                lock_until,
+            ) {
+                Ok(failures) => {
+                    if failures >= max_failures {
+                        warn!(
+                            "{} Locking out {key} after {failures} failed \
+                             login attempts",
+                            self.log_prefix,
+                        );
+                    }
+                    max_failures_seen = max_failures_seen.max(failures);
+                },
+                Err(e) => {
+                    error!(
+                        "{} Failed to record login failure for {key}: {e}",
+                        self.log_prefix,
+                    );
+                },
+            }
+        }
+
+        let delay_ms = self
+            .config
+            .base_delay_ms
+            .checked_shl(max_failures_seen.saturating_sub(1))
+            .unwrap_or(u64::MAX)
+            .min(self.config.max_delay_ms);
+        Duration::from_millis(delay_ms)
+    }
+
+    /// Records a successful login for `userid`, forgetting prior failures.
+    ///
+    /// Failures from the client's IP prefix are deliberately retained, since
+    /// one successful login does not mean that the network isn't also
+    /// guessing passwords for other users.
+    pub(super) fn record_success(&mut self, userid: &str) {
+        let Some(db) = self.db.as_mut() else {
+            return;
+        };
+
+        if let Err(e) = db.clear(&user_key(userid)) {
+            error!(
+                "{} Failed to clear login failures for '{userid}': {e}",
+                self.log_prefix,
+            );
+        }
+    }
+
+    /// Lists all keys with recorded failures or lockouts.
+    pub fn list(&mut self) -> Result<Vec<LoginThrottleEntry>, Error> {
+        let Some(db) = self.db.as_mut() else {
+            return Ok(Vec::new());
+        };
+
+        Ok(db
+            .list()?
+            .into_iter()
+            .map(|f| LoginThrottleEntry {
+                key: f.key,
+                failures: f.failures,
+                last_failure: f.last_failure.0,
+                locked_until: f.locked_until.map(|t| t.0),
+            })
+            .collect())
+    }
+
+    /// Clears failures and any lockout for the given key.
+    ///
+    /// `key` may be given in the full form shown by `list()` or as a bare
+    /// user name. Returns whether there was anything to clear.
+    pub fn clear(&mut self, key: &str) -> Result<bool, Error> {
+        let Some(db) = self.db.as_mut() else {
+            return Ok(false);
+        };
+
+        if key.starts_with("user:") || key.starts_with("ip:") {
+            db.clear(key)
+        } else {
+            db.clear(&user_key(key))
+        }
+    }
+
+    /// Clears all failures and lockouts.
+    pub fn clear_all(&mut self) -> Result<(), Error> {
+        if let Some(db) = self.db.as_mut() {
+            db.clear_all()?;
+        }
+        Ok(())
+    }
+
+    fn keys(
+        &self,
+        userid: Option<&str>,
+        peer_ip: Option<IpAddr>,
+    ) -> Vec<(String, u32)> {
+        let mut keys = Vec::with_capacity(2);
+        if let Some(userid) = userid {
+            keys.push((user_key(userid), self.config.max_user_failures));
+        }
+        if let Some(peer_ip) = peer_ip {
+            keys.push((
+                ip_key(
+                    peer_ip,
+                    self.config.ipv4_prefix_len,
+                    self.config.ipv6_prefix_len,
+                ),
+                self.config.max_ip_failures,
+            ));
+        }
+        keys
+    }
+}
+
+fn user_key(userid: &str) -> String {
+    format!("user:{userid}")
+}
+
+fn ip_key(ip: IpAddr, ipv4_prefix_len: u8, ipv6_prefix_len: u8) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let len = ipv4_prefix_len.min(32);
+            let mask = u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0);
+            let net = std::net::Ipv4Addr::from(u32::from(ip) & mask);
+            format!("ip:{net}/{len}")
+        },
+        IpAddr::V6(ip) => {
+            let len = ipv6_prefix_len.min(128);
+            let mask = u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0);
+            let net = std::net::Ipv6Addr::from(u128::from(ip) & mask);
+            format!("ip:{net}/{len}")
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ip_key() {
+        assert_eq!(
+            "ip:192.0.2.3/32",
+            ip_key("192.0.2.3".parse().unwrap(), 32, 64),
+        );
+        assert_eq!(
+            "ip:192.0.2.0/24",
+            ip_key("192.0.2.3".parse().unwrap(), 24, 64),
+        );
+        assert_eq!("ip:0.0.0.0/0", ip_key("192.0.2.3".parse().unwrap(), 0, 64));
+        assert_eq!(
+            "ip:2001:db8::/64",
+            ip_key("2001:db8::1:2:3:4".parse().unwrap(), 32, 64),
+        );
+    }
+
+    #[test]
+    fn test_throttle() {
+        let tmpdir = tempfile::TempDir::new().unwrap();
+        let config = LoginThrottleConfig {
+            max_user_failures: 3,
+            max_ip_failures: 5,
+            base_delay_ms: 100,
+            max_delay_ms: 300,
+            ..LoginThrottleConfig::default()
+        };
+        let mut throttle = LoginThrottle::new(
+            LogPrefix::new("test".to_owned()),
+            tmpdir.path(),
+            &config,
+        );
+        let ip = Some("192.0.2.3".parse().unwrap());
+
+        assert_eq!(
+            Duration::from_millis(100),
+            throttle.record_failure(Some("azure"), ip),
+        );
+        assert_eq!(
+            Duration::from_millis(200),
+            throttle.record_failure(Some("azure"), ip),
+        );
+        assert!(throttle.locked_until(Some("azure"), ip).is_none());
+
+        throttle.record_success("azure");
+        // The IP failures are retained, so the delay continues to grow.
+        assert_eq!(
+            Duration::from_millis(300),
+            throttle.record_failure(Some("azure"), ip),
+        );
+        throttle.record_failure(Some("azure"), ip);
+        assert!(throttle.locked_until(Some("azure"), None).is_none());
+        throttle.record_failure(Some("azure"), ip);
+        assert!(throttle.locked_until(Some("azure"), None).is_some());
+        assert!(throttle.locked_until(None, ip).is_some());
+        assert!(throttle.locked_until(Some("zim"), None).is_none());
+
+        let mut admin = LoginThrottle::open_existing(
+            LogPrefix::new("admin".to_owned()),
+            tmpdir.path(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(2, admin.list().unwrap().len());
+        assert!(admin.clear("azure").unwrap());
+        assert!(admin.clear("ip:192.0.2.3/32").unwrap());
+        assert!(admin.list().unwrap().is_empty());
+        assert!(throttle.locked_until(Some("azure"), ip).is_none());
+    }
+}
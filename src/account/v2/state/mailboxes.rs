@@ -58,6 +58,20 @@ impl Account {
                 None
             };
 
+        // \All is the only special use that makes sense as a virtual mailbox,
+        // since all the others are places where messages get put. (\Flagged
+        // in particular is left as an ordinary mailbox for compatibility.)
+        if Some(MailboxAttribute::All) == special_use {
+            return self
+                .metadb
+                .create_virtual_mailbox_hierarchy(
+                    &request.name,
+                    special_use,
+                    &[SearchQuery::All],
+                )
+                .map(storage::MailboxId::format_rfc8474);
+        }
+
         // create_mailbox_hierarchy validates that all parts of request.name
         // are safe names.
         self.metadb
@@ -65,6 +79,33 @@ impl Account {
             .map(storage::MailboxId::format_rfc8474)
     }
 
+    /// Creates a virtual mailbox holding the messages of all other mailboxes
+    /// (except `\Trash` and `\Junk`) which match all of `queries`.
+    ///
+    /// This is the `XCRY CREATE-SEARCH-MAILBOX` command. A query of just
+    /// `FLAGGED` gives the mailbox the `\Flagged` special use.
+    ///
+    /// Returns the RFC 8474 `MAILBOXID` of the new mailbox.
+    pub fn create_search_mailbox(
+        &mut self,
+        name: &str,
+        queries: &[SearchQuery],
+    ) -> Result<String, Error> {
+        if path_is_inbox(name) {
+            return Err(Error::BadOperationOnInbox);
+        }
+
+        if !queries.iter().all(is_savable_search) {
+            return Err(Error::UnsavableSearch);
+        }
+
+        let special_use = matches!(*queries, [SearchQuery::Flagged])
+            .then_some(MailboxAttribute::Flagged);
+        self.metadb
+            .create_virtual_mailbox_hierarchy(name, special_use, queries)
+            .map(storage::MailboxId::format_rfc8474)
+    }
+
     /// Like `create()`, but returns no error if the mailbox already exists.
     pub fn create_if_nx(
         &mut self,
@@ -139,7 +180,7 @@ impl Account {
         &mut self,
         request: &StatusRequest,
     ) -> Result<StatusResponse, Error> {
-        let (mb, _) = self.select(&request.name, false, None)?;
+        let mb = self.examine_for_status(&request.name)?;
         let select = mb.select_response()?;
 
         let mut response = StatusResponse {
@@ -172,7 +213,12 @@ impl Account {
         }
 
         if request.max_modseq {
-            response.max_modseq = Some(select.max_modseq);
+            // RFC 7162 requires 0 for mailboxes without modseqs.
+            response.max_modseq = Some(if mb.is_virtual() {
+                Modseq::of(0)
+            } else {
+                select.max_modseq
+            });
         }
 
         if request.mailbox_id {
@@ -203,6 +249,37 @@ impl Account {
         Ok(response)
     }
 
+    /// Returns a summary of all mailboxes and subscriptions, used to detect
+    /// changes for RFC 5465 `NOTIFY`.
+    pub fn notify_snapshot(&mut self) -> Result<NotifySnapshot, Error> {
+        let paths = super::restore::mailbox_paths(&mut self.metadb)?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let mailboxes = self
+            .metadb
+            .fetch_all_mailboxes()?
+            .into_iter()
+            .filter_map(|mb| {
+                let name = paths.get(&mb.id)?.clone();
+                Some((
+                    mb.id.format_rfc8474(),
+                    NotifyMailbox {
+                        name,
+                        selectable: mb.selectable,
+                        max_modseq: mb.max_modseq,
+                    },
+                ))
+            })
+            .collect();
+        let subscriptions =
+            self.metadb.fetch_all_subscriptions()?.into_iter().collect();
+
+        Ok(NotifySnapshot {
+            mailboxes,
+            subscriptions,
+        })
+    }
+
     /// The RFC 3501 `LIST` and `LSUB` commands and the non-standard `XLIST`
     /// command.
     ///
@@ -312,6 +389,28 @@ fn normalise_path(path: &str) -> Result<String, Error> {
     Ok(normalised)
 }
 
+/// Returns whether `query` can define a virtual mailbox.
+///
+/// Queries which refer to particular messages or modseqs of the selected
+/// mailbox are meaningless across mailboxes, as is `\Recent` since virtual
+/// mailboxes never have any recent messages.
+fn is_savable_search(query: &SearchQuery) -> bool {
+    match *query {
+        SearchQuery::SequenceSet(_)
+        | SearchQuery::UidSet(_)
+        | SearchQuery::Modseq(_)
+        | SearchQuery::New
+        | SearchQuery::Old
+        | SearchQuery::Recent => false,
+        SearchQuery::Not(ref sub) => is_savable_search(sub),
+        SearchQuery::Or(ref a, ref b) => {
+            is_savable_search(a) && is_savable_search(b)
+        },
+        SearchQuery::And(ref queries) => queries.iter().all(is_savable_search),
+        _ => true,
+    }
+}
+
 /// An intermediate representation of a mailbox and/or subscription used by the
 /// `list` implementation.
 #[derive(Debug, Default)]
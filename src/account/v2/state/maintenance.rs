@@ -25,7 +25,7 @@ use log::{error, info, warn};
 
 use super::super::storage;
 use super::defs::*;
-use crate::support::error::Error;
+use crate::support::{error::Error, user_config::ExpungeConfig};
 
 impl Account {
     pub(super) fn run_maintenance(&mut self) {
@@ -45,6 +45,10 @@ impl Account {
         }
 
         info!("{} Running daily maintenance...", self.log_prefix);
+        // Expired messages are expunged before orphans are cleaned up, but
+        // since that goes by when messages were orphaned, they still get the
+        // full expunge retention period.
+        self.apply_mailbox_retention(now)?;
         self.clean_up_orphans(now)?;
         // Process any pending deliveries immediately before running
         // unaccounted recovery so that optimisations are not defeated by
@@ -53,6 +57,10 @@ impl Account {
         // that's rare enough to not matter: we still do the right thing, just
         // more slowly.)
         self.drain_deliveries();
+        // Key retirement runs after deliveries are drained so that their
+        // messages are re-encrypted too, and before the database backup so
+        // that the backup refers to the new message files.
+        self.retire_old_keys(now)?;
         self.recover_unaccounted_files(now)?;
         self.make_database_backup(now.date_naive())?;
         self.remove_old_database_backups();
@@ -63,9 +71,21 @@ impl Account {
     }
 
     fn clean_up_orphans(&mut self, now: DateTime<Utc>) -> Result<(), Error> {
-        let orphans = self.metadb.fetch_orphaned_messages(
-            storage::UnixTimestamp(now - chrono::Duration::hours(24)),
-        )?;
+        // Orphaned messages are kept for the user's expunge retention period
+        // so that they can still be unexpunged. This also serves as the grace
+        // period for messages which are briefly orphaned while being added to
+        // a mailbox, so it is never less than a day.
+        let retention_days = match self.load_config() {
+            Ok(config) => config.expunge.retention_days,
+            Err(e) => {
+                warn!("{} Failed to load user config: {e:?}", self.log_prefix);
+                ExpungeConfig::default().retention_days
+            },
+        };
+        let orphans =
+            self.metadb.fetch_orphaned_messages(storage::UnixTimestamp(
+                now - chrono::Duration::days(retention_days.max(1).into()),
+            ))?;
 
         for (id, path) in orphans {
             match self.message_store.delete(path.as_ref()) {
@@ -271,6 +271,10 @@ impl Account {
     ) -> Result<CopyResponse, Error> {
         let dst_id = self.metadb.find_mailbox(dst)?;
         let from_uids = mb.filter_uid_range(&request.ids);
+        if let Some(ref view) = mb.virtual_view {
+            return self.copy_out_of_virtual(view, &from_uids, dst_id, false);
+        }
+
         let ret = self.metadb.copy_mailbox_messages(
             mb.id,
             &mut from_uids.items(u32::MAX),
@@ -305,6 +309,10 @@ impl Account {
         mb.require_writable()?;
         let dst_id = self.metadb.find_mailbox(dst)?;
         let from_uids = mb.filter_uid_range(&request.ids);
+        if let Some(ref view) = mb.virtual_view {
+            return self.copy_out_of_virtual(view, &from_uids, dst_id, true);
+        }
+
         let ret = self.metadb.move_mailbox_messages(
             mb.id,
             from_uids.items(u32::MAX),
@@ -312,6 +320,50 @@ impl Account {
         );
         ret
     }
+
+    /// Copies or moves the messages in `uids` out of the virtual mailbox
+    /// described by `view`.
+    ///
+    /// When moving, messages which already live in `dst_id` are left alone.
+    fn copy_out_of_virtual(
+        &mut self,
+        view: &VirtualView,
+        uids: &SeqRange<Uid>,
+        dst_id: storage::MailboxId,
+        moove: bool,
+    ) -> Result<CopyResponse, Error> {
+        let mut from_uids = Vec::new();
+        let mut origins = Vec::new();
+        for uid in uids.items(u32::MAX) {
+            let Some(&origin) = view.origins.get(&uid) else {
+                continue;
+            };
+            if moove && origin.0 == dst_id {
+                continue;
+            }
+
+            from_uids.push(uid);
+            origins.push(origin);
+        }
+
+        let dst_uids = self
+            .metadb
+            .copy_messages_from_many(&origins, dst_id, moove)?;
+
+        let mut response = CopyResponse {
+            uid_validity: dst_id.as_uid_validity()?,
+            from_uids: SeqRange::new(),
+            to_uids: SeqRange::new(),
+        };
+        for (from_uid, to_uid) in from_uids.into_iter().zip(dst_uids) {
+            if let Some(to_uid) = to_uid {
+                response.from_uids.append(from_uid);
+                response.to_uids.append(to_uid);
+            }
+        }
+
+        Ok(response)
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,322 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+//! Storage of RFC 5464 METADATA entries.
+//!
+//! Entries live in the metadata database, so they are encrypted at rest like
+//! everything else there. Server entries are attached to the root
+//! pseudo-mailbox.
+
+use std::collections::BTreeMap;
+
+use super::super::storage;
+use super::defs::*;
+use super::retention::validate_retention;
+use crate::{account::model::*, support::error::Error};
+
+/// The maximum number of METADATA entries on one mailbox or on the server.
+const METADATA_MAX_ENTRIES: usize = 256;
+
+impl Account {
+    /// The RFC 5464 `GETMETADATA` command.
+    ///
+    /// `request.mailbox` is `""` to fetch server entries.
+    pub fn get_metadata(
+        &mut self,
+        request: &GetMetadataRequest,
+    ) -> Result<GetMetadataResponse, Error> {
+        let requested = request
+            .entries
+            .iter()
+            .map(|e| normalise_metadata_entry(e))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mailbox_id = self.metadata_mailbox(&request.mailbox)?;
+
+        // With DEPTH 0, entries explicitly requested are reported even if they
+        // don't exist. Otherwise, the request is more of a search and only
+        // existing entries are returned.
+        let mut entries = BTreeMap::<String, Option<String>>::new();
+        if MetadataDepth::Zero == request.depth {
+            entries.extend(requested.iter().map(|e| (e.clone(), None)));
+        }
+        let mut longest_omitted = None::<u32>;
+        for (name, value) in self.metadb.fetch_metadata(mailbox_id)? {
+            if !requested.iter().any(|r| request.depth.selects(r, &name)) {
+                continue;
+            }
+
+            let len = u32::try_from(value.len()).unwrap_or(u32::MAX);
+            if request.max_size.is_some_and(|max| len > max) {
+                longest_omitted = longest_omitted.max(Some(len));
+                entries.remove(&name);
+                continue;
+            }
+
+            entries.insert(name, Some(value));
+        }
+
+        Ok(GetMetadataResponse {
+            entries: entries.into_iter().collect(),
+            longest_omitted,
+        })
+    }
+
+    /// The RFC 5464 `SETMETADATA` command.
+    ///
+    /// `mailbox` is `""` to set server entries. Entries whose value is `None`
+    /// are removed. If `retention` is `Some`, the retention rules of the
+    /// mailbox are also set as with `set_mailbox_retention`. Either all
+    /// changes are made or none are.
+    pub fn set_metadata(
+        &mut self,
+        mailbox: &str,
+        entries: Vec<(String, Option<String>)>,
+        retention: Option<Option<MailboxRetention>>,
+    ) -> Result<(), Error> {
+        let entries = entries
+            .into_iter()
+            .map(|(name, value)| {
+                let name = normalise_metadata_entry(&name)?;
+                // The top-level /private and /shared are only meaningful as
+                // GETMETADATA prefixes.
+                if 1 == name.matches('/').count() {
+                    return Err(Error::BadMetadataEntry);
+                }
+
+                if value
+                    .as_ref()
+                    .is_some_and(|v| v.len() > METADATA_MAX_SIZE as usize)
+                {
+                    return Err(Error::MetadataTooLarge);
+                }
+
+                Ok((name, value))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let retention = retention.map(validate_retention).transpose()?;
+
+        let mailbox_id = self.metadata_mailbox(mailbox)?;
+        self.metadb.set_metadata(
+            mailbox_id,
+            &entries,
+            retention.as_ref().map(Option::as_ref),
+            METADATA_MAX_ENTRIES,
+        )
+    }
+
+    fn metadata_mailbox(
+        &mut self,
+        mailbox: &str,
+    ) -> Result<storage::MailboxId, Error> {
+        if mailbox.is_empty() {
+            Ok(storage::MailboxId::ROOT)
+        } else {
+            self.metadb.find_mailbox(mailbox)
+        }
+    }
+}
+
+/// Validates `name` as an RFC 5464 entry name, returning it in the lower-case
+/// form in which entries are stored.
+fn normalise_metadata_entry(name: &str) -> Result<String, Error> {
+    let name = name.to_ascii_lowercase();
+    let valid = ("/private" == name
+        || "/shared" == name
+        || name.starts_with("/private/")
+        || name.starts_with("/shared/"))
+        && !name.ends_with('/')
+        && !name.contains("//")
+        && !name.chars().any(|c| c.is_control() || '*' == c || '%' == c);
+
+    if valid {
+        Ok(name)
+    } else {
+        Err(Error::BadMetadataEntry)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn get(
+        fixture: &mut TestFixture,
+        mailbox: &str,
+        entries: &[&str],
+        depth: MetadataDepth,
+        max_size: Option<u32>,
+    ) -> GetMetadataResponse {
+        fixture
+            .get_metadata(&GetMetadataRequest {
+                mailbox: mailbox.to_owned(),
+                entries: entries.iter().map(|&e| e.to_owned()).collect(),
+                depth,
+                max_size,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn metadata_entries() {
+        let mut fixture = TestFixture::new();
+        fixture.create("Lists");
+
+        fixture
+            .set_metadata(
+                "Lists",
+                vec![
+                    ("/private/Comment".to_owned(), Some("hello".to_owned())),
+                    (
+                        "/private/vendor/x/colour".to_owned(),
+                        Some("red".to_owned()),
+                    ),
+                    ("/shared/comment".to_owned(), Some("x".repeat(100))),
+                ],
+                None,
+            )
+            .unwrap();
+        fixture
+            .set_metadata(
+                "",
+                vec![("/private/comment".to_owned(), Some("srv".to_owned()))],
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(
+            GetMetadataResponse {
+                entries: vec![
+                    ("/private/comment".to_owned(), Some("hello".to_owned())),
+                    ("/private/missing".to_owned(), None),
+                ],
+                longest_omitted: None,
+            },
+            get(
+                &mut fixture,
+                "Lists",
+                &["/PRIVATE/COMMENT", "/private/missing"],
+                MetadataDepth::Zero,
+                None,
+            ),
+        );
+        assert_eq!(
+            vec![("/private/comment".to_owned(), Some("hello".to_owned()))],
+            get(
+                &mut fixture,
+                "Lists",
+                &["/private"],
+                MetadataDepth::One,
+                None,
+            )
+            .entries,
+        );
+        assert_eq!(
+            GetMetadataResponse {
+                entries: vec![
+                    ("/private/comment".to_owned(), Some("hello".to_owned())),
+                    (
+                        "/private/vendor/x/colour".to_owned(),
+                        Some("red".to_owned()),
+                    ),
+                ],
+                longest_omitted: Some(100),
+            },
+            get(
+                &mut fixture,
+                "Lists",
+                &["/private/comment", "/private/vendor", "/shared/comment"],
+                MetadataDepth::Infinity,
+                Some(10),
+            ),
+        );
+        assert_eq!(
+            vec![("/private/comment".to_owned(), Some("srv".to_owned()))],
+            get(
+                &mut fixture,
+                "",
+                &["/private/comment"],
+                MetadataDepth::Zero,
+                None,
+            )
+            .entries,
+        );
+
+        fixture
+            .set_metadata(
+                "Lists",
+                vec![("/private/comment".to_owned(), None)],
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            vec![("/private/comment".to_owned(), None)],
+            get(
+                &mut fixture,
+                "Lists",
+                &["/private/comment"],
+                MetadataDepth::Zero,
+                None,
+            )
+            .entries,
+        );
+
+        for bad in ["/private", "/other/x", "/private/x/", "/private//x"] {
+            assert_matches!(
+                Err(Error::BadMetadataEntry),
+                fixture.set_metadata(
+                    "Lists",
+                    vec![(bad.to_owned(), None)],
+                    None,
+                ),
+            );
+        }
+
+        // Retention rules are not applied if any entry is invalid
+        assert_matches!(
+            Err(Error::BadMetadataEntry),
+            fixture.set_metadata(
+                "Lists",
+                vec![("/other/x".to_owned(), None)],
+                Some(Some(MailboxRetention {
+                    max_messages: Some(10),
+                    ..MailboxRetention::default()
+                })),
+            ),
+        );
+        assert_eq!(None, fixture.mailbox_retention("Lists").unwrap());
+        assert_matches!(
+            Err(Error::MetadataTooLarge),
+            fixture.set_metadata(
+                "Lists",
+                vec![(
+                    "/private/big".to_owned(),
+                    Some("x".repeat(METADATA_MAX_SIZE as usize + 1)),
+                )],
+                None,
+            ),
+        );
+        assert_matches!(
+            Err(Error::NxMailbox),
+            fixture.set_metadata(
+                "Nonexistent",
+                vec![("/private/x".to_owned(), None)],
+                None,
+            ),
+        );
+    }
+}
@@ -17,8 +17,10 @@
 // Crymap. If not, see <http://www.gnu.org/licenses/>.
 
 use std::fs;
+use std::io::Read;
 use std::sync::Arc;
 
+use chrono::prelude::*;
 use log::{error, info};
 
 use super::super::storage;
@@ -190,6 +192,106 @@ impl Account {
 
         result
     }
+
+    /// Migrates mail from another mail system into this account, preserving
+    /// the UIDVALIDITY and UIDs of the original mailboxes where possible.
+    ///
+    /// `source` is called once and passes each item it finds to the callback
+    /// it is given. Each `Message` belongs to the most recent `Mailbox`, and
+    /// mailboxes must be given parent-first. Mailboxes may only be migrated
+    /// into if they do not exist or have never held any messages, such as
+    /// those created by `provision()`.
+    ///
+    /// The whole migration is one metadata transaction; if it fails, no
+    /// mailboxes or messages become visible, though some message files may
+    /// be left behind to be cleaned up later.
+    pub fn migrate_with_uids(
+        &mut self,
+        source: &mut dyn FnMut(
+            &mut dyn FnMut(UidMigrationItem<'_>) -> Result<(), Error>,
+        ) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let key_store = &mut self.key_store;
+        let common_paths = &self.common_paths;
+        let message_store = &self.message_store;
+        self.metadb.migrate_with_uids(&mut |migrator| {
+            source(&mut |item| match item {
+                UidMigrationItem::ReserveMailboxIds { next } => {
+                    migrator(storage::UidMigrationEvent::ReserveMailboxIds {
+                        next,
+                    })
+                },
+
+                UidMigrationItem::Mailbox {
+                    path,
+                    special_use,
+                    uid_validity,
+                    next_uid,
+                } => migrator(storage::UidMigrationEvent::Mailbox {
+                    path,
+                    special_use,
+                    uid_validity,
+                    next_uid,
+                }),
+
+                UidMigrationItem::Message {
+                    uid,
+                    flags,
+                    internal_date,
+                    data,
+                } => {
+                    let buffered = super::messages::buffer_message(
+                        key_store,
+                        common_paths,
+                        internal_date,
+                        data,
+                    )?;
+                    let canonical_path = fs::File::open(&buffered.0)
+                        .and_then(storage::MessageStore::canonical_path)?;
+                    message_store.insert(&buffered.0, &canonical_path)?;
+
+                    migrator(storage::UidMigrationEvent::Message {
+                        path: canonical_path
+                            .to_str()
+                            .expect("canonical paths are always UTF-8"),
+                        uid,
+                        flags,
+                    })
+                },
+
+                UidMigrationItem::Subscription { path } => {
+                    migrator(storage::UidMigrationEvent::Subscription { path })
+                },
+            })
+        })
+    }
+}
+
+/// An item passed to the callback of `Account::migrate_with_uids`.
+pub enum UidMigrationItem<'a> {
+    /// Ensures that no mailbox created in the future has an ID (and thus
+    /// UIDVALIDITY) less than `next`.
+    ReserveMailboxIds { next: u32 },
+    /// Begins migrating the mailbox at `path`.
+    ///
+    /// If `uid_validity` is given and not already in use, it becomes the
+    /// UIDVALIDITY of the mailbox. The UID of the next message appended to
+    /// the mailbox will be at least `next_uid`.
+    Mailbox {
+        path: &'a str,
+        special_use: Option<MailboxAttribute>,
+        uid_validity: Option<u32>,
+        next_uid: Uid,
+    },
+    /// Adds a message with the given UID to the current mailbox.
+    Message {
+        uid: Uid,
+        flags: &'a [Flag],
+        internal_date: DateTime<FixedOffset>,
+        data: &'a mut dyn Read,
+    },
+    /// Subscribes to `path`, which need not exist.
+    Subscription { path: &'a str },
 }
 
 #[cfg(test)]
@@ -348,6 +450,70 @@ mod test {
         v2_account2.init(&user_config.key_store).unwrap();
     }
 
+    #[test]
+    fn test_migrate_with_uids() {
+        let mut fixture = TestFixture::new();
+        fixture
+            .account
+            .migrate_with_uids(&mut |emit| {
+                emit(UidMigrationItem::ReserveMailboxIds { next: 600 })?;
+                emit(UidMigrationItem::Mailbox {
+                    path: "INBOX",
+                    special_use: None,
+                    uid_validity: Some(500),
+                    next_uid: Uid::u(10),
+                })?;
+                emit(UidMigrationItem::Message {
+                    uid: Uid::u(4),
+                    flags: &[Flag::Seen],
+                    internal_date: Utc::now().into(),
+                    data: &mut b"hello".as_slice(),
+                })?;
+                emit(UidMigrationItem::Mailbox {
+                    path: "Foo/Bar",
+                    special_use: None,
+                    uid_validity: Some(501),
+                    next_uid: Uid::u(1),
+                })?;
+                emit(UidMigrationItem::Message {
+                    uid: Uid::u(2),
+                    flags: &[],
+                    internal_date: Utc::now().into(),
+                    data: &mut b"world".as_slice(),
+                })?;
+                emit(UidMigrationItem::Subscription { path: "Foo/Bar" })
+            })
+            .unwrap();
+
+        let (inbox, _) = fixture.account.select("INBOX", false, None).unwrap();
+        let select = inbox.select_response().unwrap();
+        assert_eq!(500, select.uidvalidity);
+        assert_eq!(Uid::u(10), select.uidnext);
+        assert_eq!(0, select.recent);
+        assert_eq!(
+            "uid=4 [\\Seen] hello",
+            slurp_message(&mut fixture.account, &inbox, 0),
+        );
+
+        let (bar, _) = fixture.account.select("Foo/Bar", false, None).unwrap();
+        let select = bar.select_response().unwrap();
+        assert_eq!(501, select.uidvalidity);
+        assert_eq!(Uid::u(3), select.uidnext);
+        assert_eq!(
+            "uid=2 [] world",
+            slurp_message(&mut fixture.account, &bar, 0),
+        );
+
+        // Foo was created by the migration itself, so it gets the first ID
+        // past the reservation, and new mailboxes continue from there.
+        let (foo, _) = fixture.account.select("Foo", false, None).unwrap();
+        assert_eq!(600, foo.select_response().unwrap().uidvalidity);
+
+        fixture.create("New");
+        let (new, _) = fixture.account.select("New", false, None).unwrap();
+        assert_eq!(601, new.select_response().unwrap().uidvalidity);
+    }
+
     fn slurp_message(
         account: &mut Account,
         mailbox: &Mailbox,
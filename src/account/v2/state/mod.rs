@@ -31,15 +31,24 @@ mod fetch;
 mod flags;
 mod idle;
 mod init;
+mod key_retirement;
+mod login_history;
+mod login_throttle;
 mod mailboxes;
 mod maintenance;
 mod messages;
+mod metadata;
 mod migration;
 mod poll;
+mod restore;
+mod retention;
 mod search;
 mod select;
 mod spool;
+mod unexpunge;
+mod urlauth;
 mod user_config;
+mod verify;
 
 #[cfg(feature = "dev-tools")]
 mod zstd_train;
@@ -47,5 +56,8 @@ mod zstd_train;
 pub use defs::{Account, Mailbox};
 pub use delivery::DeliveryAccount;
 pub use fetch::FetchReceiver;
-pub use init::LogInError;
+pub use init::{LogInClient, LogInError, LogInProtocol};
+pub use login_throttle::LoginThrottle;
+pub use migration::UidMigrationItem;
 pub use spool::{SpooledMessage, SpooledMessageId};
+pub use urlauth::URLAUTH_MECHANISM;
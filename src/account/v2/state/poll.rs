@@ -42,6 +42,10 @@ impl Account {
         &mut self,
         mailbox: &mut Mailbox,
     ) -> Result<MiniPollResponse, Error> {
+        if mailbox.is_virtual() {
+            return self.mini_poll_virtual(mailbox);
+        }
+
         let mut mini_poll = self.metadb.mini_poll(
             mailbox.id,
             mailbox
@@ -88,6 +92,10 @@ impl Account {
         &mut self,
         mailbox: &mut Mailbox,
     ) -> Result<PollResponse, Error> {
+        if mailbox.is_virtual() {
+            return self.poll_virtual(mailbox);
+        }
+
         let mut poll = self.metadb.full_poll(
             mailbox.id,
             mailbox.writable,
@@ -117,6 +125,11 @@ impl Account {
         mailbox.next_uid = poll.next_uid;
         let mut changed_uids = mailbox.take_changed_flags_uids();
         changed_uids.extend(poll.new_messages.iter().map(|m| m.uid));
+        if !mailbox.search_contexts.is_empty() {
+            mailbox
+                .search_context_pending_uids
+                .extend(poll.new_messages.iter().map(|m| m.uid));
+        }
         mailbox
             .messages
             .extend(poll.new_messages.into_iter().map(MessageStatus::from));
@@ -153,6 +166,9 @@ impl Account {
         // loopbreaker state.
         mailbox.fetch_loopbreaker.clear();
 
+        let search_updates =
+            self.update_search_contexts(mailbox, &poll.expunged);
+
         Ok(PollResponse {
             expunge,
             exists: new_messages.then_some(mailbox.messages.len()),
@@ -160,6 +176,7 @@ impl Account {
                 .then(|| mailbox.messages.iter().filter(|m| m.recent).count()),
             fetch: changed_uids,
             max_modseq: modseq_changed.then_some(poll.snapshot_modseq),
+            search_updates,
         })
     }
 }
@@ -180,11 +197,14 @@ impl Mailbox {
                 old.last_modified = new.last_modified;
                 old.flags = new.flags;
                 self.changed_flags_uids.push(old.uid);
+                if !self.search_contexts.is_empty() {
+                    self.search_context_pending_uids.push(old.uid);
+                }
             }
         }
     }
 
-    fn take_changed_flags_uids(&mut self) -> Vec<Uid> {
+    pub(super) fn take_changed_flags_uids(&mut self) -> Vec<Uid> {
         self.changed_flags_uids.sort_unstable();
         self.changed_flags_uids.dedup();
         mem::take(&mut self.changed_flags_uids)
@@ -313,4 +333,133 @@ mod test {
         assert_eq!(Vec::<Uid>::new(), poll.fetch);
         assert_eq!(Some(Modseq::of(4)), poll.max_modseq);
     }
+
+    #[test]
+    fn search_context_updates() {
+        let mut fixture = TestFixture::new();
+        let (mut mb, _) = fixture.select("INBOX", true, None).unwrap();
+        let (mut mb2, _) = fixture.select("INBOX", true, None).unwrap();
+
+        for _ in 0..3 {
+            fixture.simple_append("INBOX");
+        }
+        fixture.poll(&mut mb).unwrap();
+        fixture.poll(&mut mb2).unwrap();
+
+        let store = |fixture: &mut TestFixture,
+                     mb: &mut Mailbox,
+                     uid: u32,
+                     flag: Flag,
+                     remove: bool| {
+            fixture
+                .store(
+                    mb,
+                    &StoreRequest {
+                        ids: &SeqRange::just(Uid::u(uid)),
+                        flags: &[flag],
+                        remove_listed: remove,
+                        remove_unlisted: false,
+                        loud: false,
+                        unchanged_since: None,
+                    },
+                )
+                .unwrap();
+        };
+
+        store(&mut fixture, &mut mb2, 2, Flag::Flagged, false);
+        fixture.poll(&mut mb).unwrap();
+
+        let request = SearchRequest {
+            queries: vec![SearchQuery::Flagged],
+            partial: None,
+        };
+        let result = fixture.search(&mb, &request).unwrap();
+        assert_eq!(vec![Uid::u(2)], result.hit_uids);
+        mb.add_search_context(
+            "A1".to_owned(),
+            true,
+            &request,
+            &result.hit_uids,
+        );
+        mb.add_search_context(
+            "A2".to_owned(),
+            false,
+            &request,
+            &result.hit_uids,
+        );
+        assert_eq!(2, mb.search_context_count());
+
+        // Changes which don't affect the result set are not reported
+        store(&mut fixture, &mut mb2, 1, Flag::Seen, false);
+        let poll = fixture.poll(&mut mb).unwrap();
+        assert_eq!(Vec::<SearchContextUpdate>::new(), poll.search_updates);
+
+        store(&mut fixture, &mut mb2, 1, Flag::Flagged, false);
+        store(&mut fixture, &mut mb2, 2, Flag::Flagged, true);
+        let poll = fixture.poll(&mut mb).unwrap();
+        assert_eq!(
+            vec![
+                SearchContextUpdate {
+                    tag: "A1".to_owned(),
+                    uid: true,
+                    added: SeqRange::just(1),
+                    removed: SeqRange::just(2),
+                },
+                SearchContextUpdate {
+                    tag: "A2".to_owned(),
+                    uid: false,
+                    added: SeqRange::just(1),
+                    removed: SeqRange::just(2),
+                },
+            ],
+            poll.search_updates,
+        );
+
+        // Our own changes are reported too, and UIDs and sequence numbers
+        // diverge once something is expunged.
+        store(&mut fixture, &mut mb, 2, Flag::Deleted, false);
+        fixture.mini_poll(&mut mb).unwrap();
+        fixture.expunge_all_deleted(&mb).unwrap();
+        fixture.poll(&mut mb).unwrap();
+        store(&mut fixture, &mut mb, 3, Flag::Flagged, false);
+        fixture.mini_poll(&mut mb).unwrap();
+        // Expunging a hit is not reported as a removal
+        store(&mut fixture, &mut mb2, 1, Flag::Deleted, false);
+        fixture.poll(&mut mb2).unwrap();
+        fixture.expunge_all_deleted(&mb2).unwrap();
+        let poll = fixture.poll(&mut mb).unwrap();
+        assert_eq!(vec![(Seqnum::u(1), Uid::u(1))], poll.expunge);
+        assert_eq!(
+            vec![
+                SearchContextUpdate {
+                    tag: "A1".to_owned(),
+                    uid: true,
+                    added: SeqRange::just(3),
+                    removed: SeqRange::new(),
+                },
+                SearchContextUpdate {
+                    tag: "A2".to_owned(),
+                    uid: false,
+                    added: SeqRange::just(1),
+                    removed: SeqRange::new(),
+                },
+            ],
+            poll.search_updates,
+        );
+
+        assert!(mb.cancel_search_context("A1"));
+        assert!(!mb.cancel_search_context("A1"));
+        fixture.simple_append("INBOX");
+        store(&mut fixture, &mut mb2, 3, Flag::Flagged, true);
+        let poll = fixture.poll(&mut mb).unwrap();
+        assert_eq!(
+            vec![SearchContextUpdate {
+                tag: "A2".to_owned(),
+                uid: false,
+                added: SeqRange::new(),
+                removed: SeqRange::just(1),
+            }],
+            poll.search_updates,
+        );
+    }
 }
@@ -0,0 +1,422 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::prelude::*;
+
+use super::super::storage;
+use super::defs::*;
+use crate::{
+    account::{message_format, model::*},
+    mime::{encoded_word::ew_decode_unstructured, header::FULL_HEADER_LINE},
+    support::{error::Error, small_bitset::SmallBitset},
+};
+
+/// The mailbox into which restored messages are placed.
+const RESTORED_MAILBOX: &str = "Restored";
+
+/// A read-only copy of a daily database backup.
+struct Backup {
+    metadb: storage::MetaDb,
+    // Dropped after `metadb`.
+    _tmpdir: tempfile::TempDir,
+}
+
+impl Account {
+    /// Lists the dates of the available database backups, oldest first.
+    pub fn list_backups(&self) -> Result<Vec<NaiveDate>, Error> {
+        let readdir = match fs::read_dir(&self.backup_path) {
+            Ok(readdir) => readdir,
+            Err(e) if io::ErrorKind::NotFound == e.kind() => {
+                return Ok(Vec::new())
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut dates = Vec::<NaiveDate>::new();
+        for entry in readdir {
+            let entry = entry?;
+            if let Some(date) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix(METADB_NAME))
+                .and_then(|suffix| suffix.strip_prefix('.'))
+                .and_then(|date| {
+                    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+                })
+            {
+                dates.push(date);
+            }
+        }
+
+        dates.sort();
+        Ok(dates)
+    }
+
+    /// Lists the mailboxes in the backup from `date` which have since been
+    /// deleted or lost messages that can still be restored.
+    pub fn list_restorable_mailboxes(
+        &mut self,
+        date: NaiveDate,
+    ) -> Result<Vec<RestorableMailbox>, Error> {
+        let mut backup = self.open_backup(date)?;
+        let mut ret = Vec::<RestorableMailbox>::new();
+        for (id, name) in mailbox_paths(&mut backup.metadb)? {
+            let (deleted, messages) =
+                self.restorable_messages(&mut backup.metadb, id)?;
+            if deleted || !messages.is_empty() {
+                ret.push(RestorableMailbox {
+                    name,
+                    uid_validity: id.as_uid_validity()?,
+                    deleted,
+                    messages: messages.len(),
+                });
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Lists the messages in `mailbox` (as named at the time of the backup)
+    /// in the backup from `date` which are no longer in that mailbox but can
+    /// still be restored.
+    pub fn list_restorable_messages(
+        &mut self,
+        date: NaiveDate,
+        mailbox: &str,
+    ) -> Result<Vec<RestorableMessage>, Error> {
+        let mut backup = self.open_backup(date)?;
+        let mailbox_id = backup.metadb.find_mailbox(mailbox)?;
+        let (_, messages) =
+            self.restorable_messages(&mut backup.metadb, mailbox_id)?;
+
+        Ok(messages
+            .into_iter()
+            .map(|m| RestorableMessage {
+                uid: m.uid,
+                subject: self.read_subject(&m.path),
+                flags: m.flags,
+                savedate: m.savedate,
+            })
+            .collect())
+    }
+
+    /// Restores the messages with the given `uids` in `mailbox` (as named at
+    /// the time of the backup) from the backup from `date` into the
+    /// `Restored` mailbox, which is created if needed.
+    ///
+    /// Only messages which are no longer in their original mailbox and whose
+    /// files still exist are restored. The `\Deleted` flag is not restored.
+    ///
+    /// Returns the number of messages restored.
+    pub fn restore_messages(
+        &mut self,
+        date: NaiveDate,
+        mailbox: &str,
+        uids: &SeqRange<Uid>,
+    ) -> Result<usize, Error> {
+        let mut backup = self.open_backup(date)?;
+        let mailbox_id = backup.metadb.find_mailbox(mailbox)?;
+        let (_, mut messages) =
+            self.restorable_messages(&mut backup.metadb, mailbox_id)?;
+        messages.retain(|m| uids.contains(m.uid));
+        if messages.is_empty() {
+            return Ok(0);
+        }
+
+        self.create_if_nx(CreateRequest {
+            name: RESTORED_MAILBOX.to_owned(),
+            special_use: vec![],
+        })?;
+        let dst_id = self.metadb.find_mailbox(RESTORED_MAILBOX)?;
+
+        let mut flags = Vec::<SmallBitset>::with_capacity(messages.len());
+        for message in &messages {
+            let mut bitset = SmallBitset::new();
+            for flag in &message.flags {
+                if Flag::Deleted != *flag {
+                    bitset.insert(self.metadb.intern_flag(flag)?.0);
+                }
+            }
+            flags.push(bitset);
+        }
+
+        self.metadb.intern_and_append_mailbox_messages(
+            dst_id,
+            &mut messages
+                .iter()
+                .zip(&flags)
+                .map(|(m, flags)| (&*m.path, Some(flags))),
+        )?;
+
+        Ok(messages.len())
+    }
+
+    fn open_backup(&self, date: NaiveDate) -> Result<Backup, Error> {
+        let backup_path = self
+            .backup_path
+            .join(format!("{METADB_NAME}.{}", date.format("%Y-%m-%d"),));
+        if !backup_path.is_file() {
+            return Err(Error::NxBackup);
+        }
+
+        // The encryption keys depend on the file name, so the backup must be
+        // opened under the original name. Working on a copy also guarantees
+        // the backup itself is never touched.
+        let tmpdir = tempfile::TempDir::new_in(&self.common_paths.tmp)?;
+        let copy_path = tmpdir.path().join(METADB_NAME);
+        fs::copy(&backup_path, &copy_path)?;
+
+        let xex_vfs = storage::XexVfs::new(Arc::clone(&self.master_key))?;
+        let metadb = storage::MetaDb::open_backup(copy_path, &xex_vfs)?;
+        Ok(Backup {
+            metadb,
+            _tmpdir: tmpdir,
+        })
+    }
+
+    /// Finds the messages of `mailbox_id` in `backup` which are no longer in
+    /// that mailbox and whose files still exist.
+    ///
+    /// Also returns whether the mailbox itself is gone.
+    fn restorable_messages(
+        &mut self,
+        backup: &mut storage::MetaDb,
+        mailbox_id: storage::MailboxId,
+    ) -> Result<(bool, Vec<BackupMessage>), Error> {
+        let then = match backup.select(mailbox_id, false, None) {
+            Ok(snapshot) => snapshot,
+            Err(Error::MailboxUnselectable) => return Ok((false, Vec::new())),
+            Err(e) => return Err(e),
+        };
+
+        // Mailbox IDs and UIDs are never reused, so anything with the same
+        // mailbox ID and UID is the same message.
+        let (deleted, present) = match self
+            .metadb
+            .select(mailbox_id, false, None)
+        {
+            Ok(now) => (false, now.messages.iter().map(|m| m.uid).collect()),
+            Err(Error::NxMailbox | Error::MailboxUnselectable) => {
+                (true, HashSet::new())
+            },
+            Err(e) => return Err(e),
+        };
+
+        let flags = then.flags.into_iter().collect::<HashMap<_, _>>();
+        let mut messages = Vec::<BackupMessage>::new();
+        for message in then.messages {
+            if present.contains(&message.uid) {
+                continue;
+            }
+
+            let path = backup.access_message(message.id)?.path;
+            if self.message_store.open(Path::new(&path)).is_err() {
+                continue;
+            }
+
+            messages.push(BackupMessage {
+                uid: message.uid,
+                path,
+                flags: message
+                    .flags
+                    .iter()
+                    .filter_map(|id| flags.get(&storage::FlagId(id)).cloned())
+                    .collect(),
+                savedate: message.savedate.0,
+            });
+        }
+
+        Ok((deleted, messages))
+    }
+
+    /// Reads and decodes the `Subject` header of the message at `path` in
+    /// the message store.
+    pub(super) fn read_subject(&mut self, path: &str) -> Option<String> {
+        let file = self.message_store.open(Path::new(path)).ok()?;
+        let (_, reader) = message_format::read_message(
+            file,
+            None,
+            &mut self.key_store,
+            |_| (),
+        )
+        .ok()?;
+
+        let mut reader = reader.take(65536);
+        let mut header_block = Vec::<u8>::new();
+        while !header_block.ends_with(b"\n\n")
+            && !header_block.ends_with(b"\n\r\n")
+        {
+            if 0 == reader.read_until(b'\n', &mut header_block).ok()? {
+                break;
+            }
+        }
+
+        let subject = FULL_HEADER_LINE
+            .captures_iter(&header_block)
+            .find(|m| {
+                std::str::from_utf8(m.get(2).unwrap().as_bytes())
+                    .is_ok_and(|n| "Subject".eq_ignore_ascii_case(n))
+            })
+            .and_then(|m| {
+                std::str::from_utf8(m.get(3).unwrap().as_bytes()).ok()
+            })?
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        Some(ew_decode_unstructured(&subject).into_owned())
+    }
+}
+
+/// A message found in a backup.
+struct BackupMessage {
+    uid: Uid,
+    path: String,
+    flags: Vec<Flag>,
+    savedate: DateTime<Utc>,
+}
+
+/// Returns the IDs and full paths of all mailboxes in `metadb`, sorted by
+/// path.
+pub(super) fn mailbox_paths(
+    metadb: &mut storage::MetaDb,
+) -> Result<Vec<(storage::MailboxId, String)>, Error> {
+    let mailboxes = metadb
+        .fetch_all_mailboxes()?
+        .into_iter()
+        .map(|mb| (mb.id, mb))
+        .collect::<HashMap<_, _>>();
+
+    let mut paths = mailboxes
+        .values()
+        .map(|mb| {
+            let mut path = mb.name.clone();
+            let mut parent = mb.parent_id;
+            while let Some(p) = mailboxes.get(&parent) {
+                path = format!("{}/{}", p.name, path);
+                parent = p.parent_id;
+            }
+            (mb.id, path)
+        })
+        .collect::<Vec<_>>();
+    paths.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn restore_from_backup() {
+        let mut fixture = TestFixture::new();
+        // Provisioning runs maintenance, which backs up the fresh database.
+        fs::remove_dir_all(&fixture.account.backup_path).unwrap();
+        assert!(fixture.account.list_backups().unwrap().is_empty());
+
+        let uid1 =
+            fixture.simple_append_data("INBOX", b"Subject: one\r\n\r\nfoo");
+        let uid2 = fixture.simple_append_data(
+            "INBOX",
+            b"Subject: =?utf-8?q?two?=\r\n\r\nbar",
+        );
+        fixture.create("Foo/Bar");
+        fixture.simple_append_data("Foo/Bar", b"Subject: three\r\n\r\nbaz");
+
+        let date = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        fs::create_dir_all(&fixture.account.backup_path).unwrap();
+        let backup_path = fixture
+            .account
+            .backup_path
+            .join(format!("{METADB_NAME}.2024-01-02"));
+        let tmp = fixture.account.common_paths.tmp.clone();
+        fixture.account.metadb.back_up(&tmp, &backup_path).unwrap();
+        assert_eq!(vec![date], fixture.account.list_backups().unwrap());
+
+        // Nothing has been lost yet.
+        assert!(fixture
+            .account
+            .list_restorable_mailboxes(date)
+            .unwrap()
+            .is_empty());
+
+        let inbox_id = fixture.account.metadb.find_mailbox("INBOX").unwrap();
+        fixture
+            .account
+            .metadb
+            .expunge_mailbox_messages(inbox_id, &mut std::iter::once(uid2))
+            .unwrap();
+        fixture.delete("Foo/Bar").unwrap();
+
+        let mailboxes =
+            fixture.account.list_restorable_mailboxes(date).unwrap();
+        assert_eq!(2, mailboxes.len());
+        assert_eq!("Foo/Bar", mailboxes[0].name);
+        assert!(mailboxes[0].deleted);
+        assert_eq!(1, mailboxes[0].messages);
+        assert_eq!("INBOX", mailboxes[1].name);
+        assert!(!mailboxes[1].deleted);
+        assert_eq!(1, mailboxes[1].messages);
+
+        let messages = fixture
+            .account
+            .list_restorable_messages(date, "INBOX")
+            .unwrap();
+        assert_eq!(1, messages.len());
+        assert_eq!(uid2, messages[0].uid);
+        assert_eq!(Some("two"), messages[0].subject.as_deref());
+
+        assert_eq!(
+            0,
+            fixture
+                .account
+                .restore_messages(date, "INBOX", &SeqRange::just(uid1))
+                .unwrap(),
+        );
+        assert_eq!(
+            1,
+            fixture
+                .account
+                .restore_messages(date, "INBOX", &SeqRange::range(uid1, uid2))
+                .unwrap(),
+        );
+        assert_eq!(
+            1,
+            fixture
+                .account
+                .restore_messages(date, "Foo/Bar", &SeqRange::just(Uid::MIN))
+                .unwrap(),
+        );
+
+        let (restored, _) =
+            fixture.account.select("Restored", false, None).unwrap();
+        assert_eq!(2, restored.messages.len());
+
+        assert_matches!(
+            Err(Error::NxBackup),
+            fixture.account.list_restorable_mailboxes(
+                NaiveDate::from_ymd_opt(2024, 1, 3).unwrap()
+            ),
+        );
+    }
+}
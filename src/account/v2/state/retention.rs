@@ -0,0 +1,315 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::prelude::*;
+use log::info;
+
+use super::super::storage;
+use super::defs::*;
+use super::restore::mailbox_paths;
+use crate::{account::model::*, support::error::Error};
+
+impl Account {
+    /// Sets the retention rules of `mailbox`, replacing any existing rules.
+    ///
+    /// Rules with neither a maximum age nor a maximum message count do
+    /// nothing, so passing such rules (or `None`) removes the mailbox's
+    /// retention rules instead.
+    pub fn set_mailbox_retention(
+        &mut self,
+        mailbox: &str,
+        retention: Option<MailboxRetention>,
+    ) -> Result<(), Error> {
+        let retention = validate_retention(retention)?;
+        let mailbox_id = self.metadb.find_mailbox(mailbox)?;
+        self.metadb
+            .set_mailbox_retention(mailbox_id, retention.as_ref())
+    }
+
+    /// Fetches the retention rules of `mailbox`, if it has any.
+    pub fn mailbox_retention(
+        &mut self,
+        mailbox: &str,
+    ) -> Result<Option<MailboxRetention>, Error> {
+        let mailbox_id = self.metadb.find_mailbox(mailbox)?;
+        Ok(self
+            .metadb
+            .fetch_all_mailbox_retentions()?
+            .into_iter()
+            .find(|&(id, _)| id == mailbox_id)
+            .map(|(_, retention)| retention))
+    }
+
+    /// Lists every mailbox which has retention rules, sorted by name.
+    pub fn list_mailbox_retentions(
+        &mut self,
+    ) -> Result<Vec<(String, MailboxRetention)>, Error> {
+        let mut retentions = self
+            .metadb
+            .fetch_all_mailbox_retentions()?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        Ok(mailbox_paths(&mut self.metadb)?
+            .into_iter()
+            .filter_map(|(id, name)| {
+                retentions.remove(&id).map(|retention| (name, retention))
+            })
+            .collect())
+    }
+
+    /// Expunges every message which has expired under the retention rules of
+    /// its mailbox.
+    pub(super) fn apply_mailbox_retention(
+        &mut self,
+        now: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        for (mailbox_id, retention) in
+            self.metadb.fetch_all_mailbox_retentions()?
+        {
+            let snapshot = match self.metadb.select(mailbox_id, false, None) {
+                Ok(snapshot) => snapshot,
+                Err(Error::NxMailbox | Error::MailboxUnselectable) => continue,
+                Err(e) => return Err(e),
+            };
+
+            let expired = self.expired_messages(&retention, snapshot, now);
+            if expired.is_empty() {
+                continue;
+            }
+
+            info!(
+                "{} Expunging {} messages from mailbox {} per its \
+                 retention rules",
+                self.log_prefix,
+                expired.len(),
+                mailbox_id.0,
+            );
+            self.metadb.expunge_mailbox_messages(
+                mailbox_id,
+                &mut expired.into_iter(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Determines which messages in `snapshot` have expired under
+    /// `retention`.
+    ///
+    /// The returned UIDs are sorted ascending.
+    fn expired_messages(
+        &mut self,
+        retention: &MailboxRetention,
+        snapshot: storage::InitialSnapshot,
+        now: DateTime<Utc>,
+    ) -> Vec<Uid> {
+        let seen_flag = snapshot
+            .flags
+            .iter()
+            .find(|&&(_, ref flag)| Flag::Seen == *flag)
+            .map(|&(id, _)| id);
+        let eligible = |m: &storage::InitialMessageStatus| {
+            !retention.only_seen
+                || seen_flag.is_some_and(|id| m.flags.contains(id.0))
+        };
+
+        let mut expired = HashSet::<Uid>::new();
+        if let Some(max_age_days) = retention.max_age_days {
+            let cutoff = now - chrono::Duration::days(max_age_days.into());
+            for message in snapshot.messages.iter().filter(|m| eligible(m)) {
+                let date = match retention.age_basis {
+                    RetentionAgeBasis::SaveDate => message.savedate.0,
+                    // This needs to read every message, but it's only done
+                    // once a day. If the message can't be read, it's simply
+                    // left alone.
+                    RetentionAgeBasis::InternalDate => {
+                        match self.open_message(message.id) {
+                            Ok((metadata, _)) => {
+                                metadata.internal_date.with_timezone(&Utc)
+                            },
+                            Err(_) => continue,
+                        }
+                    },
+                };
+
+                if date < cutoff {
+                    expired.insert(message.uid);
+                }
+            }
+        }
+
+        if let Some(max_messages) = retention.max_messages {
+            let remaining = snapshot
+                .messages
+                .iter()
+                .filter(|m| !expired.contains(&m.uid))
+                .collect::<Vec<_>>();
+            let excess = remaining.len().saturating_sub(max_messages as usize);
+            // Messages are sorted by UID, so this takes the oldest.
+            expired.extend(
+                remaining
+                    .into_iter()
+                    .filter(|m| eligible(m))
+                    .take(excess)
+                    .map(|m| m.uid),
+            );
+        }
+
+        let mut expired = expired.into_iter().collect::<Vec<_>>();
+        expired.sort_unstable();
+        expired
+    }
+}
+
+/// Checks that `retention` is in range, and normalises rules which do nothing
+/// to `None`.
+pub(super) fn validate_retention(
+    retention: Option<MailboxRetention>,
+) -> Result<Option<MailboxRetention>, Error> {
+    let retention = retention
+        .filter(|r| r.max_age_days.is_some() || r.max_messages.is_some());
+    if let Some(ref retention) = retention {
+        if Some(0) == retention.max_age_days
+            || Some(0) == retention.max_messages
+        {
+            return Err(Error::RetentionOutOfRange);
+        }
+    }
+
+    Ok(retention)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn retention_rules() {
+        let mut fixture = TestFixture::new();
+        fixture.create("Lists");
+
+        let trash_uids = (0..4)
+            .map(|_| fixture.simple_append("Trash"))
+            .collect::<Vec<_>>();
+        let lists_uids = (0..3)
+            .map(|_| fixture.simple_append("Lists"))
+            .collect::<Vec<_>>();
+
+        let (mut trash, _) = fixture.select("Trash", true, None).unwrap();
+        fixture
+            .store(
+                &mut trash,
+                &StoreRequest {
+                    ids: &SeqRange::range(trash_uids[0], trash_uids[1]),
+                    flags: &[Flag::Seen],
+                    remove_listed: false,
+                    remove_unlisted: false,
+                    loud: false,
+                    unchanged_since: None,
+                },
+            )
+            .unwrap();
+
+        fixture
+            .set_mailbox_retention(
+                "Trash",
+                Some(MailboxRetention {
+                    max_messages: Some(1),
+                    only_seen: true,
+                    ..MailboxRetention::default()
+                }),
+            )
+            .unwrap();
+        fixture
+            .set_mailbox_retention(
+                "Lists",
+                Some(MailboxRetention {
+                    max_age_days: Some(30),
+                    ..MailboxRetention::default()
+                }),
+            )
+            .unwrap();
+        assert_eq!(
+            vec!["Lists".to_owned(), "Trash".to_owned()],
+            fixture
+                .list_mailbox_retentions()
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>(),
+        );
+
+        // Nothing in Lists is old enough yet.
+        fixture.apply_mailbox_retention(Utc::now()).unwrap();
+        fixture.poll(&mut trash).unwrap();
+        // Only one message may be kept, but the two newest aren't \Seen.
+        assert_eq!(
+            vec![trash_uids[2], trash_uids[3]],
+            trash.messages.iter().map(|m| m.uid).collect::<Vec<_>>(),
+        );
+        let (lists, _) = fixture.select("Lists", false, None).unwrap();
+        assert_eq!(lists_uids.len(), lists.messages.len());
+
+        fixture
+            .apply_mailbox_retention(Utc::now() + chrono::Duration::days(31))
+            .unwrap();
+        let (lists, _) = fixture.select("Lists", false, None).unwrap();
+        assert!(lists.messages.is_empty());
+
+        // The expunged messages can still be put back.
+        assert_eq!(
+            5,
+            fixture
+                .list_unexpungeable()
+                .unwrap()
+                .into_iter()
+                .filter(|m| "Lists" == m.mailbox || "Trash" == m.mailbox)
+                .count(),
+        );
+
+        fixture.set_mailbox_retention("Trash", None).unwrap();
+        fixture
+            .set_mailbox_retention(
+                "Lists",
+                Some(MailboxRetention {
+                    only_seen: true,
+                    ..MailboxRetention::default()
+                }),
+            )
+            .unwrap();
+        assert!(fixture.list_mailbox_retentions().unwrap().is_empty());
+
+        assert_matches!(
+            Err(Error::RetentionOutOfRange),
+            fixture.set_mailbox_retention(
+                "Lists",
+                Some(MailboxRetention {
+                    max_messages: Some(0),
+                    ..MailboxRetention::default()
+                }),
+            ),
+        );
+        assert_matches!(
+            Err(Error::NxMailbox),
+            fixture.set_mailbox_retention("Nx", None),
+        );
+    }
+}
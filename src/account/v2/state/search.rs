@@ -16,6 +16,8 @@
 // You should have received a copy of the GNU General Public License along with
 // Crymap. If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::BTreeSet;
+use std::mem;
 use std::sync::Arc;
 
 use log::warn;
@@ -74,13 +76,42 @@ impl Account {
         let want = search_backend::want(&ops);
 
         let ops = Arc::new(ops);
-        let mut hits = mailbox
-            .messages
-            .iter()
-            .filter(|message| {
+        let mut hits = if let Some(partial) = request.partial {
+            // Only the first (or last) `high` hits can be in the window, so
+            // evaluate messages from the relevant end and stop once we have
+            // that many. The window of those hits is then the same as the
+            // window of the full result set.
+            let limit = partial.high as usize;
+            let mut matches = |message: &&MessageStatus| {
                 self.search_one(mailbox, message, Arc::clone(&ops), want)
-            })
-            .collect::<Vec<_>>();
+            };
+            let mut hits = if partial.from_end {
+                mailbox
+                    .messages
+                    .iter()
+                    .rev()
+                    .filter(&mut matches)
+                    .take(limit)
+                    .collect::<Vec<_>>()
+            } else {
+                mailbox
+                    .messages
+                    .iter()
+                    .filter(&mut matches)
+                    .take(limit)
+                    .collect::<Vec<_>>()
+            };
+            hits.sort_unstable_by_key(|m| m.uid);
+            partial.window(&hits).to_vec()
+        } else {
+            mailbox
+                .messages
+                .iter()
+                .filter(|message| {
+                    self.search_one(mailbox, message, Arc::clone(&ops), want)
+                })
+                .collect::<Vec<_>>()
+        };
         // RFC 3501 doesn't require the results to be in any particular order,
         // but this step is very cheap and there could be clients depending on
         // it. We also need the output sorted for ESEARCH.
@@ -97,7 +128,148 @@ impl Account {
         })
     }
 
-    fn search_one(
+    /// The RFC 7377 multi-mailbox `ESEARCH` command.
+    ///
+    /// Each mailbox is searched as with `UID SEARCH`. If the selected mailbox
+    /// is one of those searched, `selected` is used as-is so that the results
+    /// agree with what the client already knows. Mailboxes which stop
+    /// existing before they can be searched are silently skipped.
+    ///
+    /// Results are sorted by mailbox name.
+    pub fn multi_search(
+        &mut self,
+        selected: Option<&Mailbox>,
+        request: &MultiSearchRequest,
+    ) -> Result<Vec<MultiSearchResponse>, Error> {
+        let mut names = BTreeSet::<String>::new();
+        for (patterns, subscribed) in [
+            (&request.patterns, false),
+            (&request.subscribed_patterns, true),
+        ] {
+            let list = self.list(&ListRequest {
+                patterns: patterns.clone(),
+                select_subscribed: subscribed,
+                return_subscribed: subscribed,
+                ..ListRequest::default()
+            })?;
+            names.extend(
+                list.into_iter()
+                    .filter(|r| {
+                        !r.attributes.iter().any(|&a| {
+                            MailboxAttribute::Noselect == a
+                                || MailboxAttribute::NonExistent == a
+                        })
+                    })
+                    .map(|r| r.name),
+            );
+        }
+
+        if let Some(selected) = selected.filter(|_| request.selected) {
+            if let Some((_, name)) =
+                super::restore::mailbox_paths(&mut self.metadb)?
+                    .into_iter()
+                    .find(|&(id, _)| id == selected.id)
+            {
+                names.insert(name);
+            }
+        }
+
+        let mut responses = Vec::with_capacity(names.len());
+        for name in names {
+            let id = match self.metadb.find_mailbox(&name) {
+                Ok(id) => id,
+                Err(Error::NxMailbox) => continue,
+                Err(e) => return Err(e),
+            };
+
+            let examined;
+            let mailbox = match selected {
+                Some(selected) if selected.id == id => selected,
+                _ => match self.select(&name, false, None) {
+                    Ok((mb, _)) => {
+                        examined = mb;
+                        &examined
+                    },
+                    Err(Error::NxMailbox | Error::MailboxUnselectable) => {
+                        continue
+                    },
+                    Err(e) => return Err(e),
+                },
+            };
+
+            responses.push(MultiSearchResponse {
+                uidvalidity: mailbox.id.as_uid_validity()?,
+                response: self.search(mailbox, &request.search)?,
+                mailbox: name,
+            });
+        }
+
+        Ok(responses)
+    }
+
+    /// Re-evaluates the RFC 5267 search contexts on `mailbox` against all
+    /// messages which arrived or changed since the last call.
+    ///
+    /// `expunged` lists the UIDs expunged by the current poll; these are
+    /// silently dropped from the contexts since the client learns about them
+    /// through `EXPUNGE` or `VANISHED`.
+    pub(super) fn update_search_contexts(
+        &mut self,
+        mailbox: &mut Mailbox,
+        expunged: &[Uid],
+    ) -> Vec<SearchContextUpdate> {
+        let mut pending = mem::take(&mut mailbox.search_context_pending_uids);
+        pending.sort_unstable();
+        pending.dedup();
+
+        let mut contexts = mem::take(&mut mailbox.search_contexts);
+        let mut updates = Vec::new();
+        for context in &mut contexts {
+            for uid in expunged {
+                context.hits.remove(uid);
+            }
+
+            let mut added = SeqRange::new();
+            let mut removed = SeqRange::new();
+            for &uid in &pending {
+                let Some(ix) = mailbox.uid_index(uid) else {
+                    continue;
+                };
+
+                let id = if context.uid {
+                    uid.0.get()
+                } else {
+                    Seqnum::from_index(ix).0.get()
+                };
+                let matches = self.search_one(
+                    mailbox,
+                    &mailbox.messages[ix],
+                    Arc::clone(&context.ops),
+                    context.want,
+                );
+
+                if matches && context.hits.insert(uid) {
+                    added.append(id);
+                } else if !matches && context.hits.remove(&uid) {
+                    removed.append(id);
+                }
+            }
+
+            if !added.is_empty() || !removed.is_empty() {
+                updates.push(SearchContextUpdate {
+                    tag: context.tag.clone(),
+                    uid: context.uid,
+                    added,
+                    removed,
+                });
+            }
+        }
+
+        mailbox.search_contexts = contexts;
+        updates
+    }
+
+    pub(super) fn search_one(
         &mut self,
         mailbox: &Mailbox,
         message: &MessageStatus,
@@ -133,7 +305,54 @@ impl Account {
 }
 
 impl Mailbox {
-    fn compile_and(&self, dst: &mut Vec<Op>, queries: &[SearchQuery]) {
+    /// Starts an RFC 5267 search context for `request`, whose current results
+    /// are `hit_uids`.
+    ///
+    /// Subsequent full polls report changes to the result set. Any existing
+    /// context with the same tag is replaced.
+    pub fn add_search_context(
+        &mut self,
+        tag: String,
+        uid: bool,
+        request: &SearchRequest,
+        hit_uids: &[Uid],
+    ) {
+        let mut ops = Vec::new();
+        self.compile_and(&mut ops, &request.queries);
+        let want = search_backend::want(&ops);
+
+        self.cancel_search_context(&tag);
+        self.search_contexts.push(SearchContext {
+            tag,
+            uid,
+            ops: Arc::new(ops),
+            want,
+            hits: hit_uids.iter().copied().collect(),
+        });
+    }
+
+    /// Stops updating the search context with the given tag.
+    ///
+    /// Returns whether there was such a context.
+    pub fn cancel_search_context(&mut self, tag: &str) -> bool {
+        let len_before = self.search_contexts.len();
+        self.search_contexts.retain(|c| c.tag != tag);
+        if self.search_contexts.is_empty() {
+            self.search_context_pending_uids.clear();
+        }
+        len_before != self.search_contexts.len()
+    }
+
+    /// Returns the number of active search contexts.
+    pub fn search_context_count(&self) -> usize {
+        self.search_contexts.len()
+    }
+
+    pub(super) fn compile_and(
+        &self,
+        dst: &mut Vec<Op>,
+        queries: &[SearchQuery],
+    ) {
         if queries.is_empty() {
             dst.push(Op::True);
             return;
@@ -510,7 +729,7 @@ mod test {
         macro_rules! search {
             ($($query:expr),*) => {
                 fixture.search(&mb1, &SearchRequest {
-                    queries: vec![$($query),*],
+                    queries: vec![$($query),*], partial: None,
                 }).unwrap().hits
             }
         }
@@ -690,6 +909,7 @@ mod test {
                 &mb,
                 &SearchRequest {
                     queries: vec![SearchQuery::Text("許されない".to_owned())],
+                    partial: None,
                 },
             )
             .unwrap();
@@ -700,6 +920,7 @@ mod test {
                 &mb,
                 &SearchRequest {
                     queries: vec![SearchQuery::Text("ÆONS".to_owned())],
+                    partial: None,
                 },
             )
             .unwrap();
@@ -723,6 +944,7 @@ mod test {
                 &mb,
                 &SearchRequest {
                     queries: vec![SearchQuery::All],
+                    partial: None,
                 },
             )
             .unwrap();
@@ -730,6 +952,116 @@ mod test {
         assert_eq!(vec![Seqnum::u(1), Seqnum::u(2)], result.hits);
     }
 
+    #[test]
+    fn partial_search() {
+        let mut fixture = TestFixture::new();
+
+        for _ in 0..10 {
+            fixture.simple_append("INBOX");
+        }
+
+        let mut mb = fixture.select("INBOX", true, None).unwrap().0;
+        fixture
+            .store(
+                &mut mb,
+                &StoreRequest {
+                    ids: &SeqRange::parse("2:4,8", Uid::MAX).unwrap(),
+                    flags: &[Flag::Flagged],
+                    remove_listed: false,
+                    remove_unlisted: false,
+                    loud: false,
+                    unchanged_since: None,
+                },
+            )
+            .unwrap();
+        fixture.poll(&mut mb).unwrap();
+
+        let mut search = |low, high, from_end| {
+            fixture
+                .search(
+                    &mb,
+                    &SearchRequest {
+                        queries: vec![SearchQuery::Unflagged],
+                        partial: Some(PartialRange {
+                            low,
+                            high,
+                            from_end,
+                        }),
+                    },
+                )
+                .unwrap()
+                .hits
+        };
+
+        assert_eq!(vec![Uid::u(1), Uid::u(5)], search(1, 2, false));
+        assert_eq!(vec![Uid::u(6), Uid::u(7)], search(3, 4, false));
+        assert_eq!(vec![Uid::u(9), Uid::u(10)], search(1, 2, true));
+        assert_eq!(vec![Uid::u(1), Uid::u(5)], search(5, 10, true));
+        assert_eq!(Vec::<Uid>::new(), search(7, 10, false));
+    }
+
+    #[test]
+    fn multi_search() {
+        let mut fixture = TestFixture::new();
+        fixture.create("foo");
+        fixture.create("foo/bar");
+        fixture.create("food");
+        fixture.create("empty");
+
+        fixture.simple_append("INBOX");
+        fixture.simple_append("foo");
+        fixture.simple_append("foo");
+        fixture.simple_append("foo/bar");
+        fixture.simple_append("food");
+
+        let inbox = fixture.select("INBOX", false, None).unwrap().0;
+        // Arrives after the selected snapshot was taken, so must not be found
+        // when searching the selected mailbox.
+        fixture.simple_append("INBOX");
+
+        let mut search = |selected: bool, patterns: &[&str]| {
+            fixture
+                .multi_search(
+                    Some(&inbox),
+                    &MultiSearchRequest {
+                        selected,
+                        patterns: patterns
+                            .iter()
+                            .map(|&p| p.to_owned())
+                            .collect(),
+                        search: SearchRequest {
+                            queries: vec![SearchQuery::All],
+                            ..SearchRequest::default()
+                        },
+                        ..MultiSearchRequest::default()
+                    },
+                )
+                .unwrap()
+                .into_iter()
+                .map(|r| (r.mailbox, r.response.hits.len()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec![
+                ("INBOX".to_owned(), 1),
+                ("empty".to_owned(), 0),
+                ("foo".to_owned(), 2),
+                ("foo/bar".to_owned(), 1),
+                ("food".to_owned(), 1),
+            ],
+            search(false, &["INBOX", "empty", "foo*"]),
+        );
+        assert_eq!(
+            vec![("foo".to_owned(), 2), ("foo/bar".to_owned(), 1)],
+            search(false, &["foo", "foo/*"]),
+        );
+        assert_eq!(
+            vec![("INBOX".to_owned(), 1), ("food".to_owned(), 1)],
+            search(true, &["food", "nonexistent"]),
+        );
+    }
+
     #[test]
     fn purged_messages_ignored() {
         let mut fixture = TestFixture::new();
@@ -753,6 +1085,7 @@ mod test {
                     // Use Text search to force loading of the message (and subsequent
                     // failure since most of them are gone).
                     queries: vec![SearchQuery::Text("@".to_owned())],
+                    partial: None,
                 },
             )
             .unwrap();
@@ -791,6 +1124,7 @@ mod test {
                 &mb,
                 &SearchRequest {
                     queries: vec![SearchQuery::All],
+                    partial: None,
                 },
             )
             .unwrap();
@@ -803,6 +1137,7 @@ mod test {
                 &mb,
                 &SearchRequest {
                     queries: vec![SearchQuery::Text("gaap".to_owned())],
+                    partial: None,
                 },
             )
             .unwrap();
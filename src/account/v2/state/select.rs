@@ -1,5 +1,5 @@
 //-
-// Copyright (c) 2023, Jason Lingle
+// Copyright (c) 2023, 2024, Jason Lingle
 //
 // This file is part of Crymap.
 //
@@ -16,8 +16,16 @@
 // You should have received a copy of the GNU General Public License along with
 // Crymap. If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+use std::sync::Arc;
+
+use super::super::storage;
 use super::defs::*;
-use crate::{account::model::*, support::error::Error};
+use crate::{
+    account::{model::*, search_backend},
+    support::error::Error,
+};
 
 impl Account {
     /// Probe for the given mailbox, returning the error `select` would if
@@ -47,10 +55,509 @@ impl Account {
         self.drain_deliveries();
 
         let mailbox_id = self.metadb.find_mailbox(mailbox)?;
-        let snapshot = self.metadb.select(mailbox_id, writable, qresync)?;
-        let mailbox = Mailbox {
+        if let Some(query) =
+            self.metadb.fetch_virtual_mailbox_query(mailbox_id)?
+        {
+            // Virtual mailboxes have no modseqs, so there is nothing to
+            // resynchronise.
+            let mailbox =
+                self.select_virtual(mailbox_id, writable, &query, true)?;
+            return Ok((mailbox, None));
+        }
+
+        let mut snapshot = self.metadb.select(mailbox_id, writable, qresync)?;
+        let qresync = snapshot.qresync.take();
+        Ok((
+            Mailbox::from_snapshot(mailbox_id, writable, snapshot),
+            qresync,
+        ))
+    }
+
+    /// Opens the given mailbox read-only in order to report on it, as for
+    /// `STATUS`.
+    ///
+    /// This is like an `EXAMINE`, except that the membership of a virtual
+    /// mailbox is worked out without being stored, so the result is what a
+    /// `SELECT` would see but nothing is written.
+    pub(super) fn examine_for_status(
+        &mut self,
+        mailbox: &str,
+    ) -> Result<Mailbox, Error> {
+        self.drain_deliveries();
+
+        let mailbox_id = self.metadb.find_mailbox(mailbox)?;
+        if let Some(query) =
+            self.metadb.fetch_virtual_mailbox_query(mailbox_id)?
+        {
+            return self.select_virtual(mailbox_id, false, &query, false);
+        }
+
+        let snapshot = self.metadb.select(mailbox_id, false, None)?;
+        Ok(Mailbox::from_snapshot(mailbox_id, false, snapshot))
+    }
+
+    /// Selects the virtual mailbox `mailbox_id`, defined by `query`.
+    ///
+    /// The virtual mailbox draws from every selectable real mailbox except
+    /// those marked `\Trash` or `\Junk`. Mailboxes created after this point
+    /// are not seen until the virtual mailbox is selected again.
+    ///
+    /// Only messages which have changed since the stored membership was last
+    /// brought up to date are checked against the query. If `store` is true,
+    /// the updated membership is stored; otherwise, the UIDs that new members
+    /// would get are used without being assigned.
+    fn select_virtual(
+        &mut self,
+        mailbox_id: storage::MailboxId,
+        writable: bool,
+        query: &[SearchQuery],
+        store: bool,
+    ) -> Result<Mailbox, Error> {
+        let virtual_ids = self
+            .metadb
+            .fetch_virtual_mailbox_ids()?
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        let mut sources = BTreeMap::new();
+        for source in self.metadb.fetch_all_mailboxes()? {
+            if !source.selectable
+                || virtual_ids.contains(&source.id)
+                || matches!(
+                    source.special_use,
+                    Some(MailboxAttribute::Trash | MailboxAttribute::Junk),
+                )
+            {
+                continue;
+            }
+
+            match self.metadb.select(source.id, false, None) {
+                Ok(snapshot) => {
+                    sources.insert(
+                        source.id,
+                        Mailbox::from_snapshot(source.id, false, snapshot),
+                    );
+                },
+                Err(Error::NxMailbox | Error::MailboxUnselectable) => {},
+                Err(e) => return Err(e),
+            }
+        }
+
+        let flags = self.metadb.fetch_all_flags()?;
+        let mut mailbox = Mailbox {
             id: mailbox_id,
             writable,
+            messages: Vec::new(),
+            max_client_known_flag_id: flags
+                .last()
+                .expect("there is always at least one flag")
+                .0,
+            flags,
+            snapshot_modseq: Modseq::MIN,
+            polled_snapshot_modseq: Modseq::MIN,
+            has_pending_expunge: false,
+            next_uid: Uid::MIN,
+            changed_flags_uids: Vec::new(),
+            fetch_loopbreaker: Default::default(),
+            search_contexts: Vec::new(),
+            search_context_pending_uids: Vec::new(),
+            virtual_view: None,
+        };
+
+        let mut ops = Vec::new();
+        mailbox.compile_and(&mut ops, query);
+        let want = search_backend::want(&ops);
+        let mut view = VirtualView {
+            ops: Arc::new(ops),
+            want,
+            sources,
+            origins: HashMap::new(),
+            virtual_uids: HashMap::new(),
+            pending: Vec::new(),
+        };
+
+        let membership =
+            self.metadb.fetch_virtual_mailbox_membership(mailbox_id)?;
+        let mut members = membership
+            .messages
+            .into_iter()
+            .map(|(uid, source_id, source_uid)| ((source_id, source_uid), uid))
+            .collect::<HashMap<_, _>>();
+
+        let mut kept = Vec::new();
+        let mut kept_uids = Vec::new();
+        let mut hits = Vec::new();
+        let mut removed = Vec::new();
+        let mut checked = BTreeMap::new();
+        for (&source_id, source) in &view.sources {
+            let checked_modseq = membership.sources.get(&source_id).copied();
+            for message in &source.messages {
+                let origin = (source_id, message.uid);
+                let member = members.remove(&origin);
+                let matches = if checked_modseq
+                    .is_some_and(|modseq| message.last_modified <= modseq)
+                {
+                    member.is_some()
+                } else {
+                    self.search_one(
+                        source,
+                        message,
+                        Arc::clone(&view.ops),
+                        view.want,
+                    )
+                };
+
+                match member {
+                    Some(uid) if matches => {
+                        kept.push(origin);
+                        kept_uids.push(uid);
+                    },
+                    Some(uid) => removed.push(uid),
+                    None if matches => hits.push(origin),
+                    None => {},
+                }
+            }
+
+            checked.insert(source_id, source.snapshot_modseq);
+        }
+
+        // Anything left over was expunged or is in a mailbox which is no
+        // longer a source.
+        removed.extend(members.into_values());
+
+        let (uids, next_uid) = if !store {
+            let mut next_uid = membership.next_uid;
+            let mut uids = Vec::with_capacity(hits.len());
+            for _ in &hits {
+                uids.push(next_uid);
+                next_uid = next_uid.next().ok_or(Error::MailboxFull)?;
+            }
+            (uids, next_uid)
+        } else if hits.is_empty()
+            && removed.is_empty()
+            && checked == membership.sources
+        {
+            (Vec::new(), membership.next_uid)
+        } else {
+            self.metadb
+                .sync_virtual_mailbox(mailbox_id, &hits, &removed, &checked)?
+        };
+
+        mailbox.next_uid = next_uid;
+        kept.extend_from_slice(&hits);
+        kept_uids.extend_from_slice(&uids);
+        view.add_messages(&mut mailbox, &kept, &kept_uids);
+        mailbox.virtual_view = Some(Box::new(view));
+
+        Ok(mailbox)
+    }
+
+    /// Does a full poll on the virtual mailbox `mailbox`, as with `poll()`.
+    ///
+    /// All source mailboxes are polled. Messages which stopped matching the
+    /// query or were expunged from their source are expunged, and messages
+    /// which started matching are added.
+    pub(super) fn poll_virtual(
+        &mut self,
+        mailbox: &mut Mailbox,
+    ) -> Result<PollResponse, Error> {
+        let mut view = mailbox
+            .virtual_view
+            .take()
+            .expect("poll_virtual() on real mailbox");
+        let result = self.poll_virtual_view(mailbox, &mut view);
+        mailbox.virtual_view = Some(view);
+        result
+    }
+
+    fn poll_virtual_view(
+        &mut self,
+        mailbox: &mut Mailbox,
+        view: &mut VirtualView,
+    ) -> Result<PollResponse, Error> {
+        let mut gone = Vec::<(storage::MailboxId, Uid)>::new();
+        let mut changed = mem::take(&mut view.pending);
+        let mut lost_sources = Vec::new();
+        for (&source_id, source) in &mut view.sources {
+            match self.poll(source) {
+                Ok(poll) => {
+                    gone.extend(
+                        poll.expunge
+                            .into_iter()
+                            .map(|(_, uid)| (source_id, uid)),
+                    );
+                    changed.extend(
+                        poll.fetch.into_iter().map(|uid| (source_id, uid)),
+                    );
+                },
+                Err(Error::NxMailbox | Error::MailboxUnselectable) => {
+                    gone.extend(
+                        source.messages.iter().map(|m| (source_id, m.uid)),
+                    );
+                    lost_sources.push(source_id);
+                },
+                Err(e) => return Err(e),
+            }
+        }
+
+        for source_id in lost_sources {
+            view.sources.remove(&source_id);
+        }
+
+        let mut expunged = Vec::<Uid>::new();
+        for origin in gone {
+            if let Some(uid) = view.remove(origin) {
+                expunged.push(uid);
+            }
+        }
+
+        changed.sort_unstable();
+        changed.dedup();
+        let mut hits = Vec::new();
+        for origin in changed {
+            let (source_id, source_uid) = origin;
+            let Some(source) = view.sources.get(&source_id) else {
+                continue;
+            };
+            let Some(source_ix) = source.uid_index(source_uid) else {
+                continue;
+            };
+
+            let source_message = &source.messages[source_ix];
+            let matches = self.search_one(
+                source,
+                source_message,
+                Arc::clone(&view.ops),
+                view.want,
+            );
+            match view.virtual_uids.get(&origin).copied() {
+                Some(uid) if matches => {
+                    if let Some(ix) = mailbox.uid_index(uid) {
+                        mailbox.messages[ix].flags =
+                            source_message.flags.clone();
+                    }
+                    mailbox.changed_flags_uids.push(uid);
+                    if !mailbox.search_contexts.is_empty() {
+                        mailbox.search_context_pending_uids.push(uid);
+                    }
+                },
+
+                Some(_) => {
+                    expunged.extend(view.remove(origin));
+                },
+
+                None if matches => hits.push(origin),
+                None => {},
+            }
+        }
+
+        expunged.sort_unstable();
+        expunged.dedup();
+        if !expunged.is_empty() {
+            self.metadb.forget_virtual_mailbox_uids(
+                mailbox.id,
+                &mut expunged.iter().copied(),
+            )?;
+        }
+
+        let expunge = expunged
+            .iter()
+            .filter_map(|&uid| {
+                mailbox
+                    .uid_index(uid)
+                    .map(|ix| (Seqnum::from_index(ix), uid))
+            })
+            .collect::<Vec<_>>();
+        let expunged_set = expunged.iter().copied().collect::<HashSet<_>>();
+        mailbox.messages.retain(|m| !expunged_set.contains(&m.uid));
+
+        if !hits.is_empty() {
+            let (uids, next_uid) = self.metadb.assign_virtual_mailbox_uids(
+                mailbox.id,
+                &hits,
+                mailbox.next_uid,
+            )?;
+            mailbox.next_uid = next_uid;
+            view.add_messages(mailbox, &hits, &uids);
+            mailbox.changed_flags_uids.extend_from_slice(&uids);
+            if !mailbox.search_contexts.is_empty() {
+                mailbox.search_context_pending_uids.extend_from_slice(&uids);
+            }
+        }
+
+        view.sync_flags(mailbox);
+        mailbox.has_pending_expunge = false;
+        let mut changed_uids = mailbox.take_changed_flags_uids();
+        changed_uids.retain(|uid| !expunged_set.contains(uid));
+        mailbox.fetch_loopbreaker.clear();
+
+        let search_updates = self.update_search_contexts(mailbox, &expunged);
+
+        Ok(PollResponse {
+            expunge,
+            exists: (!hits.is_empty()).then_some(mailbox.messages.len()),
+            recent: (!hits.is_empty()).then_some(0),
+            fetch: changed_uids,
+            max_modseq: None,
+            search_updates,
+        })
+    }
+
+    /// Does a mini poll on the virtual mailbox `mailbox`, as with
+    /// `mini_poll()`.
+    ///
+    /// Only flag changes are reported. Whether the changed messages still
+    /// match the query is determined on the next full poll.
+    pub(super) fn mini_poll_virtual(
+        &mut self,
+        mailbox: &mut Mailbox,
+    ) -> Result<MiniPollResponse, Error> {
+        let mut view = mailbox
+            .virtual_view
+            .take()
+            .expect("mini_poll_virtual() on real mailbox");
+        let result = self.mini_poll_virtual_view(mailbox, &mut view);
+        mailbox.virtual_view = Some(view);
+        result
+    }
+
+    fn mini_poll_virtual_view(
+        &mut self,
+        mailbox: &mut Mailbox,
+        view: &mut VirtualView,
+    ) -> Result<MiniPollResponse, Error> {
+        for (&source_id, source) in &mut view.sources {
+            let poll = match self.mini_poll(source) {
+                Ok(poll) => poll,
+                // The full poll will deal with this
+                Err(Error::NxMailbox | Error::MailboxUnselectable) => continue,
+                Err(e) => return Err(e),
+            };
+
+            for source_uid in poll.fetch {
+                let origin = (source_id, source_uid);
+                view.pending.push(origin);
+
+                let Some(&uid) = view.virtual_uids.get(&origin) else {
+                    continue;
+                };
+                let (Some(source_ix), Some(ix)) =
+                    (source.uid_index(source_uid), mailbox.uid_index(uid))
+                else {
+                    continue;
+                };
+
+                mailbox.messages[ix].flags =
+                    source.messages[source_ix].flags.clone();
+                mailbox.changed_flags_uids.push(uid);
+            }
+        }
+
+        view.sync_flags(mailbox);
+
+        Ok(MiniPollResponse {
+            fetch: mailbox.take_changed_flags_uids(),
+            divergent_modseq: None,
+        })
+    }
+}
+
+impl VirtualView {
+    /// Groups `uids` by the source mailbox of each message.
+    ///
+    /// Each element of the returned lists is the position of the UID within
+    /// `uids` and the UID of the message within the source mailbox. UIDs
+    /// which are not in the virtual mailbox are omitted.
+    pub(super) fn group_by_source(
+        &self,
+        uids: impl Iterator<Item = Uid>,
+    ) -> BTreeMap<storage::MailboxId, Vec<(usize, Uid)>> {
+        let mut groups = BTreeMap::<_, Vec<_>>::new();
+        for (ix, uid) in uids.enumerate() {
+            if let Some(&(source_id, source_uid)) = self.origins.get(&uid) {
+                groups.entry(source_id).or_default().push((ix, source_uid));
+            }
+        }
+
+        groups
+    }
+
+    /// Adds the messages identified by `origins` to `mailbox`, with the
+    /// virtual UIDs in `uids`. Every UID must be greater than those already in
+    /// `mailbox`.
+    fn add_messages(
+        &mut self,
+        mailbox: &mut Mailbox,
+        origins: &[(storage::MailboxId, Uid)],
+        uids: &[Uid],
+    ) {
+        let start = mailbox.messages.len();
+        for (&origin, &uid) in origins.iter().zip(uids) {
+            let (source_id, source_uid) = origin;
+            let Some(source_message) =
+                self.sources.get(&source_id).and_then(|s| {
+                    s.uid_index(source_uid).map(|ix| &s.messages[ix])
+                })
+            else {
+                continue;
+            };
+
+            mailbox.messages.push(MessageStatus {
+                uid,
+                id: source_message.id,
+                flags: source_message.flags.clone(),
+                // Virtual mailboxes have no modseqs of their own.
+                last_modified: Modseq::MIN,
+                recent: false,
+                savedate: source_message.savedate,
+            });
+            self.origins.insert(uid, origin);
+            self.virtual_uids.insert(origin, uid);
+        }
+
+        mailbox.messages[start..].sort_unstable_by_key(|m| m.uid);
+    }
+
+    /// Removes the message identified by `origin` from the mapping, returning
+    /// its virtual UID if it was present.
+    fn remove(&mut self, origin: (storage::MailboxId, Uid)) -> Option<Uid> {
+        let uid = self.virtual_uids.remove(&origin)?;
+        self.origins.remove(&uid);
+        Some(uid)
+    }
+
+    /// Brings the flag table of `mailbox` up to date with the sources.
+    ///
+    /// Flag IDs are global, so the longest flag table of any source is a
+    /// superset of all the others.
+    fn sync_flags(&self, mailbox: &mut Mailbox) {
+        let Some(longest) = self
+            .sources
+            .values()
+            .map(|s| &s.flags)
+            .max_by_key(|f| f.len())
+        else {
+            return;
+        };
+
+        if longest.len() > mailbox.flags.len() {
+            mailbox
+                .flags
+                .extend_from_slice(&longest[mailbox.flags.len()..]);
+        }
+    }
+}
+
+impl Mailbox {
+    /// Builds the state of a real mailbox from its initial snapshot.
+    fn from_snapshot(
+        id: storage::MailboxId,
+        writable: bool,
+        snapshot: storage::InitialSnapshot,
+    ) -> Self {
+        Self {
+            id,
+            writable,
             messages: snapshot
                 .messages
                 .into_iter()
@@ -68,13 +575,12 @@ impl Account {
             next_uid: snapshot.next_uid,
             changed_flags_uids: Vec::new(),
             fetch_loopbreaker: Default::default(),
-        };
-
-        Ok((mailbox, snapshot.qresync))
+            search_contexts: Vec::new(),
+            search_context_pending_uids: Vec::new(),
+            virtual_view: None,
+        }
     }
-}
 
-impl Mailbox {
     /// Generates the `SelectResponse` to be produced in response to selecting
     /// the mailbox in this state.
     pub fn select_response(&self) -> Result<SelectResponse, Error> {
@@ -101,3 +607,266 @@ impl Mailbox {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use chrono::prelude::*;
+
+    use super::*;
+    use crate::support::chronox::*;
+
+    fn store(
+        fixture: &mut TestFixture,
+        mb: &mut Mailbox,
+        uid: Uid,
+        flag: Flag,
+        remove: bool,
+    ) {
+        fixture
+            .store(
+                mb,
+                &StoreRequest {
+                    ids: &SeqRange::just(uid),
+                    flags: &[flag],
+                    remove_listed: remove,
+                    remove_unlisted: false,
+                    loud: false,
+                    unchanged_since: None,
+                },
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn all_mail_mailbox() {
+        let mut fixture = TestFixture::new();
+        fixture
+            .account
+            .create(CreateRequest {
+                name: "All".to_owned(),
+                special_use: vec!["\\All".to_owned()],
+            })
+            .unwrap();
+
+        fixture.simple_append("INBOX");
+        fixture.simple_append("Archive");
+        fixture.simple_append("Trash");
+        fixture.simple_append("Spam");
+
+        let (mut mb, qresync) = fixture.select("All", true, None).unwrap();
+        assert!(mb.is_virtual());
+        assert!(qresync.is_none());
+        let select_res = mb.select_response().unwrap();
+        assert_eq!(2, select_res.exists);
+        assert_eq!(0, select_res.recent);
+        assert_eq!(Uid::u(3), select_res.uidnext);
+
+        assert_matches!(
+            Err(Error::VirtualMailbox),
+            fixture.account.append(
+                "All",
+                FixedOffset::zero().timestamp0(),
+                std::iter::empty(),
+                &b"foobar"[..],
+            ),
+        );
+
+        let inbox_uid = fixture.simple_append("INBOX");
+        let poll = fixture.poll(&mut mb).unwrap();
+        assert_eq!(Some(3), poll.exists);
+        assert_eq!(Some(0), poll.recent);
+        assert_eq!(vec![Uid::u(3)], poll.fetch);
+        assert_eq!(None, poll.max_modseq);
+
+        // UIDs are stable across selections
+        let (mb2, _) = fixture.select("All", false, None).unwrap();
+        assert_eq!(
+            vec![Uid::u(1), Uid::u(2), Uid::u(3)],
+            mb2.messages.iter().map(|m| m.uid).collect::<Vec<_>>(),
+        );
+
+        // Flags are changed on the real message
+        store(&mut fixture, &mut mb, Uid::u(3), Flag::Flagged, false);
+        let poll = fixture.mini_poll(&mut mb).unwrap();
+        assert_eq!(vec![Uid::u(3)], poll.fetch);
+        assert!(mb.test_flag_o(&Flag::Flagged, Uid::u(3)));
+        let (inbox, _) = fixture.select("INBOX", false, None).unwrap();
+        assert!(inbox.test_flag_o(&Flag::Flagged, inbox_uid));
+
+        // Moving to an excluded mailbox takes the message out of the view
+        let moved = fixture
+            .moove(
+                &mb,
+                &CopyRequest {
+                    ids: SeqRange::just(Uid::u(3)),
+                },
+                "Trash",
+            )
+            .unwrap();
+        assert_eq!(SeqRange::just(Uid::u(3)), moved.from_uids);
+        let poll = fixture.poll(&mut mb).unwrap();
+        assert_eq!(vec![(Seqnum::u(3), Uid::u(3))], poll.expunge);
+        assert_eq!(None, poll.exists);
+
+        // Copying creates a new message, which shows up in the view
+        let copied = fixture
+            .copy(
+                &mb,
+                &CopyRequest {
+                    ids: SeqRange::just(Uid::u(1)),
+                },
+                "Archive",
+            )
+            .unwrap();
+        assert_eq!(SeqRange::just(Uid::u(1)), copied.from_uids);
+        let poll = fixture.poll(&mut mb).unwrap();
+        assert_eq!(Some(3), poll.exists);
+        assert_eq!(vec![Uid::u(4)], poll.fetch);
+
+        // Expunging removes the real message
+        store(&mut fixture, &mut mb, Uid::u(4), Flag::Deleted, false);
+        fixture.mini_poll(&mut mb).unwrap();
+        fixture.expunge_all_deleted(&mb).unwrap();
+        let poll = fixture.poll(&mut mb).unwrap();
+        assert_eq!(vec![(Seqnum::u(3), Uid::u(4))], poll.expunge);
+        let (archive, _) = fixture.select("Archive", false, None).unwrap();
+        assert_eq!(1, archive.messages.len());
+    }
+
+    #[test]
+    fn search_mailbox_membership() {
+        let mut fixture = TestFixture::new();
+        fixture
+            .create_search_mailbox("Starred", &[SearchQuery::Flagged])
+            .unwrap();
+        assert_matches!(
+            Err(Error::UnsavableSearch),
+            fixture.create_search_mailbox("Bad", &[SearchQuery::Recent]),
+        );
+        assert_matches!(
+            Err(Error::UnsavableSearch),
+            fixture.create_search_mailbox(
+                "Bad",
+                &[SearchQuery::Not(Box::new(SearchQuery::UidSet(
+                    SeqRange::just(Uid::u(1))
+                )))],
+            ),
+        );
+
+        fixture.simple_append("INBOX");
+        fixture.simple_append("INBOX");
+
+        let (mut mb, _) = fixture.select("Starred", true, None).unwrap();
+        let (mut inbox, _) = fixture.select("INBOX", true, None).unwrap();
+        assert_eq!(0, mb.messages.len());
+
+        store(&mut fixture, &mut inbox, Uid::u(2), Flag::Flagged, false);
+        let poll = fixture.poll(&mut mb).unwrap();
+        assert_eq!(Some(1), poll.exists);
+        assert_eq!(vec![Uid::u(1)], poll.fetch);
+
+        // Changes made through the virtual mailbox are only reflected in its
+        // membership on the next full poll.
+        store(&mut fixture, &mut mb, Uid::u(1), Flag::Flagged, true);
+        let poll = fixture.mini_poll(&mut mb).unwrap();
+        assert_eq!(vec![Uid::u(1)], poll.fetch);
+        assert_eq!(1, mb.messages.len());
+        let poll = fixture.poll(&mut mb).unwrap();
+        assert_eq!(vec![(Seqnum::u(1), Uid::u(1))], poll.expunge);
+        assert_eq!(0, mb.messages.len());
+
+        // A message which comes back gets a new UID
+        store(&mut fixture, &mut inbox, Uid::u(2), Flag::Flagged, false);
+        let poll = fixture.poll(&mut mb).unwrap();
+        assert_eq!(vec![Uid::u(2)], poll.fetch);
+
+        let (mb2, _) = fixture.select("Starred", false, None).unwrap();
+        assert_eq!(
+            vec![Uid::u(2)],
+            mb2.messages.iter().map(|m| m.uid).collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn search_mailbox_incremental_membership() {
+        let mut fixture = TestFixture::new();
+        fixture
+            .create_search_mailbox("Starred", &[SearchQuery::Flagged])
+            .unwrap();
+
+        fixture.simple_append("INBOX");
+        fixture.simple_append("INBOX");
+        fixture.simple_append("INBOX");
+        let (mut inbox, _) = fixture.select("INBOX", true, None).unwrap();
+        store(&mut fixture, &mut inbox, Uid::u(1), Flag::Flagged, false);
+        store(&mut fixture, &mut inbox, Uid::u(3), Flag::Flagged, false);
+
+        let (mb, _) = fixture.select("Starred", false, None).unwrap();
+        assert_eq!(
+            vec![Uid::u(1), Uid::u(2)],
+            mb.messages.iter().map(|m| m.uid).collect::<Vec<_>>(),
+        );
+
+        // Messages which haven't changed since are not looked at again, so
+        // this doesn't make the first message drop out.
+        let path = fixture
+            .metadb
+            .access_message(inbox.messages[0].id)
+            .unwrap()
+            .path;
+        std::fs::remove_file(fixture.root.path().join("messages").join(path))
+            .unwrap();
+
+        store(&mut fixture, &mut inbox, Uid::u(2), Flag::Flagged, false);
+        store(&mut fixture, &mut inbox, Uid::u(3), Flag::Flagged, true);
+
+        // STATUS sees the changes but doesn't store anything.
+        let membership = fixture
+            .metadb
+            .fetch_virtual_mailbox_membership(mb.id)
+            .unwrap();
+        let status = fixture
+            .status(&StatusRequest {
+                name: "Starred".to_owned(),
+                messages: true,
+                uidnext: true,
+                ..StatusRequest::default()
+            })
+            .unwrap();
+        assert_eq!(Some(2), status.messages);
+        assert_eq!(Some(Uid::u(4)), status.uidnext);
+        assert_eq!(
+            membership,
+            fixture
+                .metadb
+                .fetch_virtual_mailbox_membership(mb.id)
+                .unwrap(),
+        );
+
+        let (mb, _) = fixture.select("Starred", false, None).unwrap();
+        assert_eq!(
+            vec![Uid::u(1), Uid::u(3)],
+            mb.messages.iter().map(|m| m.uid).collect::<Vec<_>>(),
+        );
+        assert_eq!(Uid::u(4), mb.next_uid);
+
+        // Expunged messages are dropped.
+        store(&mut fixture, &mut inbox, Uid::u(2), Flag::Deleted, false);
+        fixture.poll(&mut inbox).unwrap();
+        fixture.expunge_all_deleted(&inbox).unwrap();
+        let (mb, _) = fixture.select("Starred", false, None).unwrap();
+        assert_eq!(
+            vec![Uid::u(1)],
+            mb.messages.iter().map(|m| m.uid).collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            1,
+            fixture
+                .metadb
+                .fetch_virtual_mailbox_membership(mb.id)
+                .unwrap()
+                .messages
+                .len(),
+        );
+    }
+}
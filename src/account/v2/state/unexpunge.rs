@@ -0,0 +1,154 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::super::storage;
+use super::defs::*;
+use super::restore::mailbox_paths;
+use crate::{
+    account::model::*,
+    support::{error::Error, small_bitset::SmallBitset},
+};
+
+impl Account {
+    /// Lists the messages which were expunged from every mailbox they were
+    /// in but are still within the user's expunge retention period, most
+    /// recently expunged first.
+    ///
+    /// Messages whose original mailbox has since been deleted are not
+    /// included.
+    pub fn list_unexpungeable(
+        &mut self,
+    ) -> Result<Vec<UnexpungeableMessage>, Error> {
+        let mailboxes = mailbox_paths(&mut self.metadb)?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let flags = self
+            .metadb
+            .fetch_all_flags()?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        let mut ret = Vec::<UnexpungeableMessage>::new();
+        for message in self.metadb.fetch_unexpungeable_messages()? {
+            let Some(mailbox) = mailboxes.get(&message.mailbox_id) else {
+                continue;
+            };
+
+            // The file may already have been removed by another process
+            // which is about to forget the message.
+            if self.message_store.open(Path::new(&message.path)).is_err() {
+                continue;
+            }
+
+            ret.push(UnexpungeableMessage {
+                mailbox: mailbox.clone(),
+                uid: message.uid,
+                expunged: message.expunged_at.0,
+                flags: SmallBitset::new_with_near(message.near_flags as u64)
+                    .iter()
+                    .filter_map(|id| flags.get(&storage::FlagId(id)).cloned())
+                    .collect(),
+                subject: self.read_subject(&message.path),
+            });
+        }
+
+        Ok(ret)
+    }
+
+    /// Puts the expunged messages with the given `uids` back into `mailbox`.
+    ///
+    /// The messages get new UIDs and keep the flags they had when they were
+    /// expunged, except for `\Deleted`. UIDs which do not identify a message
+    /// listed by `list_unexpungeable` are ignored.
+    ///
+    /// Returns the number of messages put back.
+    pub fn unexpunge(
+        &mut self,
+        mailbox: &str,
+        uids: &SeqRange<Uid>,
+    ) -> Result<usize, Error> {
+        let mailbox_id = self.metadb.find_mailbox(mailbox)?;
+        self.metadb
+            .unexpunge_mailbox_messages(mailbox_id, &mut uids.items(u32::MAX))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unexpunge_messages() {
+        let mut fixture = TestFixture::new();
+        let uid1 =
+            fixture.simple_append_data("INBOX", b"Subject: one\r\n\r\nfoo");
+        let uid2 =
+            fixture.simple_append_data("INBOX", b"Subject: two\r\n\r\nbar");
+        assert!(fixture.account.list_unexpungeable().unwrap().is_empty());
+
+        let inbox_id = fixture.account.metadb.find_mailbox("INBOX").unwrap();
+        fixture
+            .account
+            .metadb
+            .expunge_mailbox_messages(inbox_id, &mut [uid1, uid2].into_iter())
+            .unwrap();
+
+        let messages = fixture.account.list_unexpungeable().unwrap();
+        assert!(messages.iter().all(|m| "INBOX" == m.mailbox));
+        let mut summary = messages
+            .iter()
+            .map(|m| (m.uid, m.subject.as_deref()))
+            .collect::<Vec<_>>();
+        summary.sort();
+        assert_eq!(vec![(uid1, Some("one")), (uid2, Some("two"))], summary);
+
+        assert_eq!(
+            1,
+            fixture
+                .account
+                .unexpunge("INBOX", &SeqRange::just(uid2))
+                .unwrap(),
+        );
+        let messages = fixture.account.list_unexpungeable().unwrap();
+        assert_eq!(1, messages.len());
+        assert_eq!(uid1, messages[0].uid);
+
+        let (mb, _) = fixture.account.select("INBOX", false, None).unwrap();
+        assert_eq!(1, mb.messages.len());
+        assert!(mb.messages[0].uid > uid2);
+
+        // Once the message is purged, it can no longer be unexpunged.
+        fixture.account.purge_all().unwrap();
+        assert!(fixture.account.list_unexpungeable().unwrap().is_empty());
+        assert_eq!(
+            0,
+            fixture
+                .account
+                .unexpunge("INBOX", &SeqRange::just(uid1))
+                .unwrap(),
+        );
+
+        assert_matches!(
+            Err(Error::NxMailbox),
+            fixture.account.unexpunge("Nx", &SeqRange::just(uid1)),
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+//! Support for RFC 4467 URLAUTH and fetching messages by IMAP URL.
+//!
+//! Each mailbox has its own randomly-generated access key, created the first
+//! time a URL into that mailbox is authorised and kept in the user
+//! configuration so that other processes (i.e. SMTP submission) can verify
+//! URLs too. The only supported mechanism is `INTERNAL`, whose token is a
+//! truncated KMAC256 of the URL rump under the mailbox's key.
+
+use std::sync::Arc;
+
+use chrono::prelude::*;
+use rand::{rngs::OsRng, Rng};
+use tiny_keccak::{Hasher, Kmac};
+
+use super::super::storage;
+use super::defs::*;
+use crate::{
+    account::imap_url::ImapUrl,
+    mime::{fetch::section::FetchedBodySection, grovel::grovel},
+    support::error::Error,
+};
+
+/// The only URLAUTH mechanism we support.
+pub const URLAUTH_MECHANISM: &str = "INTERNAL";
+
+impl Account {
+    /// Authorises `url`, which must be a URLAUTH URL without a verifier,
+    /// returning the full URL including the verifier.
+    ///
+    /// A new access key for the mailbox is generated if it does not already
+    /// have one.
+    pub fn generate_urlauth(&mut self, url: &ImapUrl) -> Result<String, Error> {
+        if url.access.is_none() || url.verifier.is_some() {
+            return Err(Error::BadUrl);
+        }
+
+        let mailbox_id = self.find_url_mailbox(url)?;
+        let key_name = mailbox_id.format_rfc8474();
+
+        let key = self.modify_config(None, |config| {
+            if let Some(key) = config
+                .urlauth_keys
+                .get(&key_name)
+                .and_then(|k| base64::decode(k).ok())
+            {
+                return Ok(key);
+            }
+
+            let key: [u8; 32] = OsRng.gen();
+            config.urlauth_keys.insert(key_name, base64::encode(key));
+            Ok(key.to_vec())
+        })?;
+
+        Ok(format!(
+            "{}:{}:{}",
+            url.rump,
+            URLAUTH_MECHANISM,
+            urlauth_token(&key, &url.rump),
+        ))
+    }
+
+    /// Discards the URLAUTH access key of the given mailbox, or of every
+    /// mailbox if `None`, so that all URLs authorised so far stop working.
+    pub fn reset_urlauth_keys(
+        &mut self,
+        mailbox: Option<&str>,
+    ) -> Result<(), Error> {
+        let key_name = match mailbox {
+            None => None,
+            Some(mailbox) => {
+                Some(self.metadb.find_mailbox(mailbox)?.format_rfc8474())
+            },
+        };
+
+        self.modify_config(None, |config| {
+            match key_name {
+                None => config.urlauth_keys.clear(),
+                Some(ref key_name) => {
+                    config.urlauth_keys.remove(key_name);
+                },
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Fetches the content `url` refers to.
+    ///
+    /// If `url` is a URLAUTH URL, it must carry a valid verifier and not be
+    /// expired. Checking whether the current user may use the URL at all is
+    /// up to the caller.
+    pub fn fetch_url(
+        &mut self,
+        url: &ImapUrl,
+    ) -> Result<FetchedBodySection, Error> {
+        let mailbox_id = self.find_url_mailbox(url)?;
+
+        if url.access.is_some() {
+            let &(ref mechanism, ref token) =
+                url.verifier.as_ref().ok_or(Error::BadUrl)?;
+            if !mechanism.eq_ignore_ascii_case(URLAUTH_MECHANISM) {
+                return Err(Error::UnsupportedUrlauthMechanism);
+            }
+
+            if url.is_expired(Utc::now()) {
+                return Err(Error::BadUrl);
+            }
+
+            let config = self.load_config()?;
+            let key = config
+                .urlauth_keys
+                .get(&mailbox_id.format_rfc8474())
+                .and_then(|k| base64::decode(k).ok())
+                .ok_or(Error::BadUrl)?;
+            let expected = urlauth_token(&key, &url.rump);
+            if !openssl::memcmp::eq(
+                expected.as_bytes(),
+                token.to_ascii_lowercase().as_bytes(),
+            ) {
+                return Err(Error::BadUrl);
+            }
+        }
+
+        let (mailbox, _) = self.select(&url.mailbox, false, None)?;
+        let fetcher =
+            url.section.clone().fetcher(Arc::clone(&self.common_paths));
+        let mut accessor = self
+            .access_message(&mailbox, url.uid)
+            .map_err(|_| Error::BadUrl)?;
+        let (_, result) = grovel(&mut accessor, fetcher)?;
+        result.map_err(|_| Error::BadUrl)
+    }
+
+    /// Finds the mailbox `url` points into, checking its `UIDVALIDITY` if
+    /// given.
+    fn find_url_mailbox(
+        &mut self,
+        url: &ImapUrl,
+    ) -> Result<storage::MailboxId, Error> {
+        let mailbox_id =
+            self.metadb
+                .find_mailbox(&url.mailbox)
+                .map_err(|e| match e {
+                    Error::NxMailbox => Error::BadUrl,
+                    e => e,
+                })?;
+        if url
+            .uid_validity
+            .is_some_and(|uv| Some(uv) != mailbox_id.as_uid_validity().ok())
+        {
+            return Err(Error::BadUrl);
+        }
+
+        Ok(mailbox_id)
+    }
+}
+
+/// Computes the `INTERNAL` URLAUTH token for `rump` under `key`.
+fn urlauth_token(key: &[u8], rump: &str) -> String {
+    let mut out = [0u8; 16];
+    let mut k = Kmac::v256(key, b"urlauth");
+    k.update(rump.as_bytes());
+    k.finalize(&mut out);
+    out.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use super::*;
+
+    fn fetch(fixture: &mut TestFixture, url: &str) -> Result<String, Error> {
+        let url = url.parse::<ImapUrl>().unwrap();
+        let mut data = String::new();
+        fixture
+            .fetch_url(&url)?
+            .buffer
+            .read_to_string(&mut data)
+            .unwrap();
+        Ok(data)
+    }
+
+    fn generate(fixture: &mut TestFixture, url: &str) -> String {
+        fixture
+            .generate_urlauth(&url.parse::<ImapUrl>().unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn fetch_plain_urls() {
+        let mut fixture = TestFixture::new();
+        fixture.simple_append_data("INBOX", b"Subject: hello\r\n\r\nworld\r\n");
+        let uid_validity = fixture
+            .metadb
+            .find_mailbox("INBOX")
+            .unwrap()
+            .as_uid_validity()
+            .unwrap();
+
+        assert_eq!(
+            "Subject: hello\r\n\r\nworld\r\n",
+            fetch(&mut fixture, "/INBOX/;UID=1").unwrap(),
+        );
+        assert_eq!(
+            "world\r\n",
+            fetch(
+                &mut fixture,
+                &format!(
+                    "/INBOX;UIDVALIDITY={uid_validity}/;UID=1/;SECTION=TEXT"
+                ),
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "orl",
+            fetch(&mut fixture, "/INBOX/;UID=1/;SECTION=TEXT/;PARTIAL=1.3")
+                .unwrap(),
+        );
+
+        assert_matches!(
+            Err(Error::BadUrl),
+            fetch(
+                &mut fixture,
+                &format!("/INBOX;UIDVALIDITY={}/;UID=1", uid_validity + 1),
+            ),
+        );
+        assert_matches!(
+            Err(Error::BadUrl),
+            fetch(&mut fixture, "/INBOX/;UID=2"),
+        );
+        assert_matches!(
+            Err(Error::BadUrl),
+            fetch(&mut fixture, "/Archive/;UID=1"),
+        );
+    }
+
+    #[test]
+    fn urlauth_round_trip() {
+        let mut fixture = TestFixture::new();
+        fixture.simple_append("INBOX");
+        fixture.simple_append("Drafts");
+
+        let inbox_url = "imap://user@host/INBOX/;UID=1;URLAUTH=anonymous";
+        let drafts_url = "imap://user@host/Drafts/;UID=1;URLAUTH=user+user";
+
+        // Unauthorised URLAUTH URLs can't be fetched.
+        assert_matches!(Err(Error::BadUrl), fetch(&mut fixture, inbox_url));
+
+        let authed_inbox = generate(&mut fixture, inbox_url);
+        assert!(authed_inbox.starts_with(&format!("{inbox_url}:INTERNAL:")));
+        assert_eq!("foobar", fetch(&mut fixture, &authed_inbox).unwrap());
+        // Generation is deterministic while the key stays the same.
+        assert_eq!(authed_inbox, generate(&mut fixture, inbox_url));
+
+        let authed_drafts = generate(&mut fixture, drafts_url);
+        assert_eq!("foobar", fetch(&mut fixture, &authed_drafts).unwrap());
+
+        // Tampering with the rump or the token is detected.
+        let tampered = authed_inbox.replace("anonymous", "authuser");
+        assert_matches!(Err(Error::BadUrl), fetch(&mut fixture, &tampered));
+        let tampered = format!("{}0", &authed_inbox[..authed_inbox.len() - 1]);
+        let tampered = if tampered == authed_inbox {
+            format!("{}1", &authed_inbox[..authed_inbox.len() - 1])
+        } else {
+            tampered
+        };
+        assert_matches!(Err(Error::BadUrl), fetch(&mut fixture, &tampered));
+
+        assert_matches!(
+            Err(Error::UnsupportedUrlauthMechanism),
+            fetch(&mut fixture, &authed_inbox.replace("INTERNAL", "OTHER")),
+        );
+
+        let expired = generate(
+            &mut fixture,
+            "imap://user@host/INBOX/;UID=1\
+             ;EXPIRE=2000-01-01T00:00:00Z;URLAUTH=anonymous",
+        );
+        assert_matches!(Err(Error::BadUrl), fetch(&mut fixture, &expired));
+
+        // Resetting one mailbox's key leaves the others alone.
+        fixture.reset_urlauth_keys(Some("Drafts")).unwrap();
+        assert_matches!(
+            Err(Error::BadUrl),
+            fetch(&mut fixture, &authed_drafts),
+        );
+        assert_eq!("foobar", fetch(&mut fixture, &authed_inbox).unwrap());
+
+        fixture.reset_urlauth_keys(None).unwrap();
+        assert_matches!(Err(Error::BadUrl), fetch(&mut fixture, &authed_inbox));
+        assert_ne!(authed_inbox, generate(&mut fixture, inbox_url));
+    }
+}
@@ -18,15 +18,34 @@
 
 use std::fs;
 use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
 
 use chrono::prelude::*;
+use log::info;
+use rand::{rngs::OsRng, Rng};
 
 use super::{super::account_config_file, defs::*};
 use crate::{
     account::model::*,
-    support::{error::Error, safe_name::is_safe_name, user_config::UserConfig},
+    support::{
+        error::Error,
+        safe_name::is_safe_name,
+        user_config::{AppPasswordConfig, AppPasswordRestriction, UserConfig},
+    },
 };
 
+/// The maximum number of application-specific passwords a user may have.
+///
+/// Each one is kept in `user.toml` along with its own wrapped master key, and
+/// the whole list is shown to the user, so this is kept small.
+const MAX_APP_PASSWORDS: usize = 16;
+
+/// The longest a user may have expunged messages retained.
+///
+/// Retained messages still take up disk space, so this shouldn't be
+/// unbounded.
+const MAX_EXPUNGE_RETENTION_DAYS: u32 = 90;
+
 // Like format!, but returns None if the formatter fails instead of panicking.
 macro_rules! try_format {
     ($($stuff:tt)*) => {{
@@ -59,10 +78,20 @@ impl Account {
         &self,
         request: SetUserConfigRequest,
     ) -> Result<String, Error> {
-        let config_file = account_config_file(&self.root);
-        let mut config = self.load_config()?;
         let now = Utc::now();
+        let backup_name = format!("config-backup-{}.toml", now.to_rfc3339());
+        self.modify_config(Some(&backup_name), |config| {
+            self.apply_config_request(config, request, now)
+        })?;
+        Ok(backup_name)
+    }
 
+    fn apply_config_request(
+        &self,
+        config: &mut UserConfig,
+        request: SetUserConfigRequest,
+        now: DateTime<Utc>,
+    ) -> Result<(), Error> {
         if let Some(internal_key_pattern) = request.internal_key_pattern {
             // We need to format the keys with some date to check the patterns
             // for validity. This is both because they contain % in raw form,
@@ -91,9 +120,17 @@ impl Account {
         }
 
         if let Some(password) = request.password {
+            if self.app_password.is_some() {
+                return Err(Error::NotPermittedForAppPassword);
+            }
+
             config.master_key = self
                 .master_key
-                .make_config(password.as_bytes())
+                .make_config(
+                    password.as_bytes(),
+                    &self.password_hash_params,
+                    self.recovery_key.as_ref(),
+                )
                 .expect("argon2 hash failed");
             config.master_key.last_changed = Some(now.into());
         }
@@ -110,24 +147,316 @@ impl Account {
             config.smtp_out.failure_receipts = failure_receipts;
         }
 
-        let config_toml =
+        if let Some(notify) = request.new_device_notifications {
+            config.login.new_device_notifications = notify;
+        }
+
+        if let Some(days) = request.expunge_retention_days {
+            // Less than a day isn't allowed since messages are briefly
+            // orphaned while being delivered.
+            if !(1..=MAX_EXPUNGE_RETENTION_DAYS).contains(&days) {
+                return Err(Error::RetentionOutOfRange);
+            }
+
+            config.expunge.retention_days = days;
+        }
+
+        if let Some(days) = request.key_retirement_days {
+            config.key_store.retire_after_days = days.filter(|&d| d > 0);
+        }
+
+        if let Some(algorithm) = request.key_algorithm {
+            config.key_store.algorithm = algorithm;
+        }
+
+        Ok(())
+    }
+
+    /// Creates a new application-specific password with the given name and
+    /// restriction.
+    ///
+    /// The password itself is randomly generated and returned. It cannot be
+    /// recovered later. It is prefixed with `name` and a `:` so that logging
+    /// in only needs to check that one application password.
+    pub fn create_app_password(
+        &self,
+        name: &str,
+        restriction: AppPasswordRestriction,
+    ) -> Result<String, Error> {
+        if self.app_password.is_some() {
+            return Err(Error::NotPermittedForAppPassword);
+        }
+
+        if !is_safe_name(name) {
+            return Err(Error::UnsafeName);
+        }
+
+        let data: [u8; 12] = OsRng.gen();
+        let password = format!("{name}:{}", base64::encode(data));
+        let master_key = self
+            .master_key
+            .make_config(password.as_bytes(), &self.password_hash_params, None)
+            .expect("argon2 hash failed");
+
+        self.modify_config(None, |config| {
+            if config.app_passwords.contains_key(name) {
+                return Err(Error::AppPasswordExists);
+            }
+
+            if config.app_passwords.len() >= MAX_APP_PASSWORDS {
+                return Err(Error::TooManyAppPasswords);
+            }
+
+            config.app_passwords.insert(
+                name.to_owned(),
+                AppPasswordConfig {
+                    created: Utc::now().into(),
+                    last_used: None,
+                    restriction,
+                    master_key,
+                },
+            );
+            Ok(())
+        })?;
+
+        Ok(password)
+    }
+
+    /// Lists the user's application-specific passwords, sorted by name.
+    pub fn list_app_passwords(&self) -> Result<Vec<AppPasswordInfo>, Error> {
+        let config = self.load_config()?;
+        Ok(config
+            .app_passwords
+            .into_iter()
+            .map(|(name, ap)| AppPasswordInfo {
+                name,
+                restriction: ap.restriction,
+                created: ap.created,
+                last_used: ap.last_used,
+            })
+            .collect())
+    }
+
+    /// Revokes the application-specific password with the given name.
+    ///
+    /// Sessions already logged in with that password are not affected.
+    pub fn revoke_app_password(&self, name: &str) -> Result<(), Error> {
+        if self.app_password.is_some() {
+            return Err(Error::NotPermittedForAppPassword);
+        }
+
+        self.modify_config(None, |config| {
+            if config.app_passwords.remove(name).is_none() {
+                return Err(Error::NxAppPassword);
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Records that the application-specific password with the given name
+    /// was just used to log in.
+    pub(super) fn touch_app_password(&self, name: &str) -> Result<(), Error> {
+        self.modify_config(None, |config| {
+            if let Some(ap) = config.app_passwords.get_mut(name) {
+                ap.last_used = Some(Utc::now().into());
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Re-wraps the master key for `password` using the current password
+    /// hashing parameters, without changing the master key itself.
+    ///
+    /// `app_password` gives the name of the application-specific password
+    /// `password` belongs to, or `None` if it is the main password. Nothing
+    /// happens if the stored configuration is already up to date, for
+    /// example because the password was changed concurrently.
+    pub(super) fn rehash_password(
+        &self,
+        password: &str,
+        app_password: Option<&str>,
+    ) -> Result<(), Error> {
+        self.modify_config(None, |config| {
+            let master_key_config = match app_password {
+                None => &mut config.master_key,
+                Some(name) => match config.app_passwords.get_mut(name) {
+                    Some(ap) => &mut ap.master_key,
+                    None => return Ok(()),
+                },
+            };
+
+            if !master_key_config.is_outdated(&self.password_hash_params) {
+                return Ok(());
+            }
+
+            let last_changed = master_key_config.last_changed;
+            *master_key_config = self
+                .master_key
+                .make_config(
+                    password.as_bytes(),
+                    &self.password_hash_params,
+                    if app_password.is_none() {
+                        self.recovery_key.as_ref()
+                    } else {
+                        None
+                    },
+                )
+                .expect("argon2 hash failed");
+            master_key_config.last_changed = last_changed;
+
+            info!(
+                "{} Re-hashed password with current parameters",
+                self.log_prefix,
+            );
+            Ok(())
+        })
+    }
+
+    /// Applies `modify` to the user configuration and saves the result.
+    ///
+    /// An exclusive lock on `user.toml.lock` is held from before the
+    /// configuration is loaded until the new one is in place, so that
+    /// concurrent updates (such as a login recording the use of an
+    /// application password while that password is being revoked) can't
+    /// write back a configuration which predates the other. Nothing is
+    /// written if `modify` fails, or if it leaves the configuration unchanged
+    /// and `backup_name` is `None`.
+    ///
+    /// If `backup_name` is given, the current configuration is first linked
+    /// into the temporary directory under that name.
+    pub(super) fn modify_config<R>(
+        &self,
+        backup_name: Option<&str>,
+        modify: impl FnOnce(&mut UserConfig) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        // The lock is released when this is closed on return.
+        let lock_file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.root.join("user.toml.lock"))?;
+        nix::fcntl::flock(
+            lock_file.as_raw_fd(),
+            nix::fcntl::FlockArg::LockExclusive,
+        )?;
+
+        let mut config = self.load_config()?;
+        let original =
             toml::to_vec(&config).expect("TOML serialisation failed");
+        let ret = modify(&mut config)?;
+        if backup_name.is_some()
+            || original
+                != toml::to_vec(&config).expect("TOML serialisation failed")
+        {
+            self.save_config(&config, backup_name)?;
+        }
+
+        Ok(ret)
+    }
+
+    /// Atomically replaces the user configuration with `config`.
+    ///
+    /// If `backup_name` is given, the current configuration is first linked
+    /// into the temporary directory under that name.
+    fn save_config(
+        &self,
+        config: &UserConfig,
+        backup_name: Option<&str>,
+    ) -> Result<(), Error> {
+        let config_file = account_config_file(&self.root);
+        let config_toml =
+            toml::to_vec(config).expect("TOML serialisation failed");
 
         let mut tmpfile =
             tempfile::NamedTempFile::new_in(&self.common_paths.tmp)?;
         tmpfile.write_all(&config_toml)?;
 
-        let backup_name = format!("config-backup-{}.toml", now.to_rfc3339());
-        let backup_file = self.common_paths.tmp.join(&backup_name);
-        nix::unistd::linkat(
-            None,
-            &config_file,
-            None,
-            &backup_file,
-            nix::unistd::LinkatFlags::NoSymlinkFollow,
-        )?;
+        if let Some(backup_name) = backup_name {
+            let backup_file = self.common_paths.tmp.join(backup_name);
+            nix::unistd::linkat(
+                None,
+                &config_file,
+                None,
+                &backup_file,
+                nix::unistd::LinkatFlags::NoSymlinkFollow,
+            )?;
+        }
         tmpfile.persist(&config_file).map_err(|e| e.error)?;
 
-        Ok(backup_name)
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        crypt::master_key::{Argon2Params, MasterKey},
+        support::{log_prefix::LogPrefix, system_config::SecurityConfig},
+    };
+
+    #[test]
+    fn rehash_password() {
+        let mut fixture = TestFixture::new();
+
+        let new_params = Argon2Params {
+            memory_cost_kib: 1024,
+            time_cost: 1,
+            lanes: 1,
+        };
+        let orig = fixture.load_config().unwrap().master_key;
+        assert!(orig.is_outdated(&new_params));
+
+        fixture.apply_security_config(&SecurityConfig {
+            password_hash: new_params,
+            ..SecurityConfig::default()
+        });
+        fixture.rehash_password("hunter2", None).unwrap();
+
+        let rehashed = fixture.load_config().unwrap().master_key;
+        assert!(!rehashed.is_outdated(&new_params));
+        assert!(MasterKey::from_config(&rehashed, b"hunter2").is_some());
+        assert!(MasterKey::from_config(&rehashed, b"hunter3").is_none());
+    }
+
+    #[test]
+    fn revoke_during_touch() {
+        let fixture = TestFixture::new();
+        fixture
+            .create_app_password("phone", AppPasswordRestriction::None)
+            .unwrap();
+
+        let root = fixture.root.path().to_owned();
+        let master_key = Arc::clone(&fixture.master_key);
+        // Revoke the password from another process while a login is in the
+        // middle of recording that it was used.
+        let revoker = fixture
+            .modify_config(None, |config| {
+                let revoker = std::thread::spawn(move || {
+                    Account::new(
+                        LogPrefix::new("revoker".to_owned()),
+                        root,
+                        master_key,
+                    )
+                    .unwrap()
+                    .revoke_app_password("phone")
+                });
+
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                assert!(!revoker.is_finished());
+
+                config.app_passwords.get_mut("phone").unwrap().last_used =
+                    Some(Utc::now().into());
+                Ok(revoker)
+            })
+            .unwrap();
+        revoker.join().unwrap().unwrap();
+
+        assert!(fixture.list_app_passwords().unwrap().is_empty());
     }
 }
@@ -0,0 +1,272 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::path::Path;
+
+use chrono::prelude::*;
+use log::warn;
+
+use super::super::storage;
+use super::defs::*;
+use crate::{account::model::*, support::error::Error};
+
+impl Account {
+    /// Checks the whole account for damage.
+    ///
+    /// This runs SQLite's integrity check on the metadata database, reads
+    /// every message in full (which checks the authentication tag of every
+    /// slab) and compares its size against what is recorded, and looks for
+    /// files in the message store which the database does not know about.
+    ///
+    /// If `quarantine` is given, every damaged message which is in a mailbox
+    /// is moved into that mailbox, which is created if needed. Nothing else
+    /// is modified.
+    pub fn verify(
+        &mut self,
+        quarantine: Option<&str>,
+    ) -> Result<VerifyReport, Error> {
+        self.verify_at(quarantine, Utc::now())
+    }
+
+    fn verify_at(
+        &mut self,
+        quarantine: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<VerifyReport, Error> {
+        let database_problems = match self.metadb.integrity_check() {
+            Ok(problems) => problems,
+            Err(Error::DatabaseTampered) => {
+                vec!["some pages failed authentication".to_owned()]
+            },
+            Err(e) => return Err(e),
+        };
+        let mut report = VerifyReport {
+            database_problems,
+            ..VerifyReport::default()
+        };
+
+        let mut damaged_ids = Vec::<storage::MessageId>::new();
+        for message_id in self.metadb.fetch_all_message_ids()? {
+            let access = match self.metadb.access_message(message_id) {
+                Ok(access) => access,
+                // Forgotten since we listed the IDs.
+                Err(Error::ExpungedMessage) => continue,
+                Err(e) => return Err(e),
+            };
+
+            report.messages_checked += 1;
+            if let Err(problem) = self.verify_message(message_id, &access) {
+                warn!(
+                    "{} Message {} ({}) is damaged: {problem}",
+                    self.log_prefix, message_id.0, access.path,
+                );
+                report.damaged_messages.push(DamagedMessage {
+                    path: access.path,
+                    problem,
+                });
+                damaged_ids.push(message_id);
+            }
+        }
+
+        // As with recovery during maintenance, recently-modified files are
+        // ignored since they could be deliveries in progress.
+        for path in self
+            .message_store
+            .list(Some(now - chrono::Duration::hours(1)))
+        {
+            let Ok(path) = path.into_os_string().into_string() else {
+                continue;
+            };
+
+            if !self.metadb.is_known_message(&path)?
+                && !self.deliverydb.is_delivery(&path)?
+            {
+                report.unaccounted_files.push(path);
+            }
+        }
+        report.unaccounted_files.sort();
+
+        if let Some(quarantine) = quarantine {
+            if !damaged_ids.is_empty() {
+                report.quarantined =
+                    self.quarantine_messages(quarantine, &damaged_ids)?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Reads the message with the given ID to the end, returning a
+    /// description of the problem if that fails or the amount of data is
+    /// wrong.
+    fn verify_message(
+        &mut self,
+        message_id: storage::MessageId,
+        access: &storage::MessageAccessData,
+    ) -> Result<(), String> {
+        // `open_message_with_access` substitutes a placeholder for files it
+        // can't open, so check that separately.
+        if let Err(e) = self.message_store.open(Path::new(&access.path)) {
+            return Err(format!("cannot open file: {e}"));
+        }
+
+        let (metadata, mut reader) = self
+            .open_message_with_access(message_id, access)
+            .map_err(|e| format!("cannot decrypt: {e}"))?;
+        let size = io::copy(&mut reader, &mut io::sink())
+            .map_err(|e| format!("cannot read content: {e}"))?;
+
+        if size != u64::from(metadata.size) {
+            return Err(format!(
+                "content is {size} bytes but should be {}",
+                metadata.size,
+            ));
+        }
+        if let Some(rfc822_size) = access.rfc822_size {
+            if size != u64::from(rfc822_size) {
+                return Err(format!(
+                    "content is {size} bytes but the database says \
+                     {rfc822_size}",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves every instance of the given messages into the mailbox named
+    /// `quarantine`, returning the number of instances moved.
+    fn quarantine_messages(
+        &mut self,
+        quarantine: &str,
+        message_ids: &[storage::MessageId],
+    ) -> Result<usize, Error> {
+        self.create_if_nx(CreateRequest {
+            name: quarantine.to_owned(),
+            special_use: vec![],
+        })?;
+        let quarantine_id = self.metadb.find_mailbox(quarantine)?;
+
+        let mut moved = 0;
+        for &message_id in message_ids {
+            for (mailbox_id, uid) in
+                self.metadb.fetch_message_instances(message_id)?
+            {
+                if quarantine_id == mailbox_id {
+                    continue;
+                }
+
+                self.metadb.move_mailbox_messages(
+                    mailbox_id,
+                    std::iter::once(uid),
+                    quarantine_id,
+                )?;
+                moved += 1;
+            }
+        }
+
+        Ok(moved)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io::Seek;
+
+    use super::*;
+
+    #[test]
+    fn verify_account() {
+        let mut fixture = TestFixture::new();
+        let now = Utc::now();
+
+        fixture.simple_append("INBOX");
+        let damaged_uid = fixture.simple_append("INBOX");
+        fixture.simple_append("Archive");
+
+        let report = fixture.verify_at(Some("Quarantine"), now).unwrap();
+        assert_eq!(
+            VerifyReport {
+                messages_checked: 3,
+                ..VerifyReport::default()
+            },
+            report,
+        );
+        // Nothing was damaged, so the quarantine isn't needed.
+        assert_matches!(
+            Err(Error::NxMailbox),
+            fixture.select("Quarantine", false, None),
+        );
+
+        let (inbox, _) = fixture.select("INBOX", false, None).unwrap();
+        let damaged_id =
+            inbox.messages[inbox.uid_index(damaged_uid).unwrap()].id;
+        let damaged_path =
+            fixture.metadb.access_message(damaged_id).unwrap().path;
+        // Flip a bit in the last authentication tag. The file is read-only,
+        // so it needs to be replaced rather than modified in place.
+        let damaged_file =
+            fixture.root.path().join("messages").join(&damaged_path);
+        let mut data = fs::read(&damaged_file).unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        fs::remove_file(&damaged_file).unwrap();
+        fs::write(&damaged_file, data).unwrap();
+
+        // A message file the database doesn't know about.
+        let mut tmp =
+            tempfile::NamedTempFile::new_in(&fixture.account.common_paths.tmp)
+                .unwrap();
+        crate::account::message_format::write_message(
+            &mut tmp,
+            &mut fixture.account.key_store,
+            now.into(),
+            &b"unaccounted"[..],
+        )
+        .unwrap();
+        tmp.seek(io::SeekFrom::Start(0)).unwrap();
+        let unaccounted_path =
+            storage::MessageStore::canonical_path(&mut tmp).unwrap();
+        fixture
+            .account
+            .message_store
+            .insert(tmp.path(), &unaccounted_path)
+            .unwrap();
+
+        let report = fixture
+            .verify_at(Some("Quarantine"), now + chrono::Duration::hours(2))
+            .unwrap();
+        assert!(report.database_problems.is_empty());
+        assert_eq!(3, report.messages_checked);
+        assert_eq!(1, report.damaged_messages.len());
+        assert_eq!(damaged_path, report.damaged_messages[0].path);
+        assert_eq!(
+            vec![unaccounted_path.to_str().unwrap().to_owned()],
+            report.unaccounted_files,
+        );
+        assert_eq!(1, report.quarantined);
+
+        let (inbox, _) = fixture.select("INBOX", false, None).unwrap();
+        assert_eq!(1, inbox.messages.len());
+        let (quarantine, _) =
+            fixture.select("Quarantine", false, None).unwrap();
+        assert_eq!(1, quarantine.messages.len());
+        assert_eq!(damaged_id, quarantine.messages[0].id);
+    }
+}
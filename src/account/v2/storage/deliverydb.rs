@@ -33,7 +33,16 @@ pub struct Connection {
     cxn: rusqlite::Connection,
 }
 
-static MIGRATIONS: &[&str] = &[include_str!("deliverydb.v1.sql")];
+static MIGRATIONS: &[&str] = &[
+    include_str!("deliverydb.v1.sql"),
+    include_str!("deliverydb.v2.sql"),
+    include_str!("deliverydb.v3.sql"),
+];
+
+/// The maximum number of failed logins kept in the queue. Beyond this, the
+/// oldest are dropped so that a flood of failed logins cannot grow the
+/// database without bound.
+const LOGIN_FAILURE_LIMIT: i64 = 1000;
 
 impl Connection {
     pub fn new(log_prefix: &LogPrefix, path: &Path) -> Result<Self, Error> {
@@ -106,6 +115,63 @@ impl Connection {
             .map_err(Into::into)
     }
 
+    /// Queues the creation of the given mailbox, to be processed before any
+    /// deliveries queued after it.
+    pub fn queue_mailbox_creation(
+        &mut self,
+        creation: &MailboxCreation,
+    ) -> Result<(), Error> {
+        self.cxn.execute(
+            "INSERT INTO `mailbox_creation` (`mailbox`, `special_use`) \
+             VALUES (?, ?)",
+            (&creation.mailbox, &creation.special_use),
+        )?;
+        Ok(())
+    }
+
+    /// Removes and returns all queued mailbox creations, oldest first.
+    pub fn pop_mailbox_creations(
+        &mut self,
+    ) -> Result<Vec<MailboxCreation>, Error> {
+        let txn = self.cxn.transaction()?;
+        let creations = txn
+            .prepare("SELECT * FROM `mailbox_creation` ORDER BY `id`")?
+            .query_map((), from_row)?
+            .collect::<Result<Vec<MailboxCreation>, _>>()?;
+        txn.execute("DELETE FROM `mailbox_creation`", ())?;
+        txn.commit()?;
+        Ok(creations)
+    }
+
+    /// Queues an encrypted login failure record to be moved into the login
+    /// history the next time the user logs in.
+    pub fn queue_login_failure(&mut self, data: &[u8]) -> Result<(), Error> {
+        let txn = self.cxn.transaction()?;
+        txn.execute(
+            "INSERT INTO `login_failure` (`data`) VALUES (?)",
+            (data,),
+        )?;
+        let id = txn.last_insert_rowid();
+        txn.execute(
+            "DELETE FROM `login_failure` WHERE `id` <= ?",
+            (id - LOGIN_FAILURE_LIMIT,),
+        )?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// Removes and returns all queued login failure records, oldest first.
+    pub fn pop_login_failures(&mut self) -> Result<Vec<Vec<u8>>, Error> {
+        let txn = self.cxn.transaction()?;
+        let failures = txn
+            .prepare("SELECT `data` FROM `login_failure` ORDER BY `id`")?
+            .query_map((), from_single)?
+            .collect::<Result<Vec<Vec<u8>>, _>>()?;
+        txn.execute("DELETE FROM `login_failure`", ())?;
+        txn.commit()?;
+        Ok(failures)
+    }
+
     /// Clear old entries from the delivery database.
     pub fn clear_old_deliveries(&mut self) -> Result<(), Error> {
         self.cxn.execute(
@@ -168,4 +234,51 @@ mod test {
         assert!(cxn.is_delivery("foo/bar").unwrap());
         assert!(cxn.is_delivery("baz/quux").unwrap());
     }
+
+    #[test]
+    fn test_mailbox_creations() {
+        let tmpdir = TempDir::new().unwrap();
+        let mut cxn = Connection::new(
+            &LogPrefix::new("test".to_owned()),
+            &tmpdir.path().join("delivery.sqlite"),
+        )
+        .unwrap();
+
+        let creation1 = MailboxCreation {
+            mailbox: "foo/bar".to_owned(),
+            special_use: None,
+        };
+        let creation2 = MailboxCreation {
+            mailbox: "Sent Items".to_owned(),
+            special_use: Some("\\Sent".to_owned()),
+        };
+
+        assert!(cxn.pop_mailbox_creations().unwrap().is_empty());
+        cxn.queue_mailbox_creation(&creation1).unwrap();
+        cxn.queue_mailbox_creation(&creation2).unwrap();
+        assert_eq!(
+            vec![creation1, creation2],
+            cxn.pop_mailbox_creations().unwrap(),
+        );
+        assert!(cxn.pop_mailbox_creations().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_login_failures() {
+        let tmpdir = TempDir::new().unwrap();
+        let mut cxn = Connection::new(
+            &LogPrefix::new("test".to_owned()),
+            &tmpdir.path().join("delivery.sqlite"),
+        )
+        .unwrap();
+
+        assert!(cxn.pop_login_failures().unwrap().is_empty());
+        cxn.queue_login_failure(b"foo").unwrap();
+        cxn.queue_login_failure(b"bar").unwrap();
+        assert_eq!(
+            vec![b"foo".to_vec(), b"bar".to_vec()],
+            cxn.pop_login_failures().unwrap(),
+        );
+        assert!(cxn.pop_login_failures().unwrap().is_empty());
+    }
 }
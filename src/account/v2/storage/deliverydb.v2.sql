@@ -0,0 +1,26 @@
+---
+-- Copyright (c) 2024, Jason Lingle
+--
+-- This file is part of Crymap.
+--
+-- Crymap is free software: you can  redistribute it and/or modify it under the
+-- terms of  the GNU General Public  License as published by  the Free Software
+-- Foundation, either version  3 of the License, or (at  your option) any later
+-- version.
+--
+-- Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+-- WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+-- FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+-- details.
+--
+-- You should have received a copy of the GNU General Public License along with
+-- Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+-- Failed login attempts waiting to be moved into the login history in the
+-- main database on the next successful login.
+CREATE TABLE `login_failure` (
+  `id` INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  -- The `LoginHistoryEntry`, in CBOR, encrypted as a data stream with the
+  -- user's external public key.
+  `data` BLOB NOT NULL
+) STRICT;
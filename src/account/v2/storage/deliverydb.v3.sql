@@ -0,0 +1,28 @@
+---
+-- Copyright (c) 2024, Jason Lingle
+--
+-- This file is part of Crymap.
+--
+-- Crymap is free software: you can  redistribute it and/or modify it under the
+-- terms of  the GNU General Public  License as published by  the Free Software
+-- Foundation, either version  3 of the License, or (at  your option) any later
+-- version.
+--
+-- Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+-- WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+-- FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+-- details.
+--
+-- You should have received a copy of the GNU General Public License along with
+-- Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+-- Mailboxes to be created before further deliveries are processed, used by
+-- bulk imports which need to recreate a folder hierarchy without access to
+-- the main database.
+CREATE TABLE `mailbox_creation` (
+  `id` INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  -- The path to the mailbox to create.
+  `mailbox` TEXT NOT NULL,
+  -- The special-use attribute to give the mailbox, if any.
+  `special_use` TEXT
+) STRICT;
@@ -0,0 +1,226 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use rusqlite::OptionalExtension as _;
+
+use super::types::*;
+use crate::support::{error::Error, log_prefix::LogPrefix};
+
+/// A connection to the system-wide `login-throttle.sqlite` database.
+pub struct Connection {
+    cxn: rusqlite::Connection,
+}
+
+static MIGRATIONS: &[&str] = &[include_str!("loginthrottledb.v1.sql")];
+
+impl Connection {
+    pub fn new(log_prefix: &LogPrefix, path: &Path) -> Result<Self, Error> {
+        let mut cxn = rusqlite::Connection::open_with_flags(
+            path,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE
+                | rusqlite::OpenFlags::SQLITE_OPEN_CREATE
+                | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+
+        // This is only ever accessed by processes that have not yet dropped
+        // privileges to a particular user, so it need not be accessible to
+        // anyone else.
+        let _ = fs::set_permissions(path, fs::Permissions::from_mode(0o600));
+
+        cxn.pragma_update(None, "journal_mode", "PERSIST")?;
+        cxn.pragma_update(None, "journal_size_limit", 64 * 1024)?;
+        cxn.busy_timeout(Duration::from_secs(10))?;
+
+        super::db_migrations::apply_migrations(
+            log_prefix,
+            &mut cxn,
+            "login-throttle",
+            MIGRATIONS,
+        )?;
+
+        Ok(Self { cxn })
+    }
+
+    /// Returns the time until which `key` is locked out, if it currently is.
+    pub fn locked_until(
+        &mut self,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        self.cxn
+            .prepare_cached(
+                "SELECT `locked_until` FROM `login_failure` \
+                 WHERE `key` = ? AND `locked_until` > ?",
+            )?
+            .query_row((key, UnixTimestamp(now)), from_single::<UnixTimestamp>)
+            .optional()
+            .map(|r| r.map(|t| t.0))
+            .map_err(Into::into)
+    }
+
+    /// Records a login failure against `key`.
+    ///
+    /// Failures from before `window_start` are forgotten first. If the
+    /// failure count then reaches `max_failures`, `key` is locked out until
+    /// `lock_until` and the count is reset.
+    ///
+    /// Returns the number of failures, including this one, since the count
+    /// was last reset.
+    pub fn record_failure(
+        &mut self,
+        key: &str,
+        now: DateTime<Utc>,
+        window_start: DateTime<Utc>,
+        max_failures: u32,
+        lock_until: DateTime<Utc>,
+    ) -> Result<u32, Error> {
+        let txn = self.cxn.transaction_with_behavior(
+            rusqlite::TransactionBehavior::Immediate,
+        )?;
+
+        // Expire entries that no longer carry any information.
+        txn.execute(
+            "DELETE FROM `login_failure` \
+             WHERE `last_failure` < ?1 \
+             AND (`locked_until` IS NULL OR `locked_until` < ?2)",
+            (UnixTimestamp(window_start), UnixTimestamp(now)),
+        )?;
+
+        let failures = txn.query_row(
+            "INSERT INTO `login_failure` \
+                 (`key`, `failures`, `last_failure`) VALUES (?1, 1, ?2) \
+                 ON CONFLICT (`key`) DO UPDATE SET \
+                 `failures` = CASE WHEN `last_failure` < ?3 THEN 1 \
+                   ELSE `failures` + 1 END, \
+                 `last_failure` = ?2 \
+                 RETURNING `failures`",
+            (key, UnixTimestamp(now), UnixTimestamp(window_start)),
+            from_single::<u32>,
+        )?;
+
+        if failures >= max_failures {
+            txn.execute(
+                "UPDATE `login_failure` \
+                 SET `failures` = 0, `locked_until` = ? WHERE `key` = ?",
+                (UnixTimestamp(lock_until), key),
+            )?;
+        }
+
+        txn.commit()?;
+        Ok(failures)
+    }
+
+    /// Forgets all failures and any lockout for `key`.
+    ///
+    /// Returns whether there was anything to forget.
+    pub fn clear(&mut self, key: &str) -> Result<bool, Error> {
+        let n = self
+            .cxn
+            .execute("DELETE FROM `login_failure` WHERE `key` = ?", (key,))?;
+        Ok(n > 0)
+    }
+
+    /// Forgets all failures and lockouts.
+    pub fn clear_all(&mut self) -> Result<(), Error> {
+        self.cxn.execute("DELETE FROM `login_failure`", ())?;
+        Ok(())
+    }
+
+    /// Returns all keys with recorded failures or lockouts, ordered by key.
+    pub fn list(&mut self) -> Result<Vec<LoginFailures>, Error> {
+        self.cxn
+            .prepare("SELECT * FROM `login_failure` ORDER BY `key`")?
+            .query_map((), from_row)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_login_throttle() {
+        let tmpdir = TempDir::new().unwrap();
+        let mut cxn = Connection::new(
+            &LogPrefix::new("test".to_owned()),
+            &tmpdir.path().join("login-throttle.sqlite"),
+        )
+        .unwrap();
+
+        let t = |s: i64| DateTime::from_timestamp(s, 0).unwrap();
+
+        assert_eq!(None, cxn.locked_until("user:azure", t(100)).unwrap());
+        assert_eq!(
+            1,
+            cxn.record_failure("user:azure", t(100), t(0), 3, t(200))
+                .unwrap(),
+        );
+        assert_eq!(
+            2,
+            cxn.record_failure("user:azure", t(110), t(10), 3, t(210))
+                .unwrap(),
+        );
+        assert_eq!(None, cxn.locked_until("user:azure", t(110)).unwrap());
+        // Outside the window, so the count starts over.
+        assert_eq!(
+            1,
+            cxn.record_failure("user:azure", t(500), t(400), 3, t(600))
+                .unwrap(),
+        );
+        assert_eq!(
+            2,
+            cxn.record_failure("user:azure", t(510), t(410), 3, t(610))
+                .unwrap(),
+        );
+        assert_eq!(
+            3,
+            cxn.record_failure("user:azure", t(520), t(420), 3, t(620))
+                .unwrap(),
+        );
+        assert_eq!(
+            Some(t(620)),
+            cxn.locked_until("user:azure", t(520)).unwrap()
+        );
+        assert_eq!(None, cxn.locked_until("user:azure", t(620)).unwrap());
+        assert_eq!(None, cxn.locked_until("ip:192.0.2.3/32", t(520)).unwrap());
+
+        cxn.record_failure("ip:192.0.2.3/32", t(520), t(420), 3, t(620))
+            .unwrap();
+        let list = cxn.list().unwrap();
+        assert_eq!(2, list.len());
+        assert_eq!("ip:192.0.2.3/32", list[0].key);
+        assert_eq!("user:azure", list[1].key);
+        assert_eq!(Some(UnixTimestamp(t(620))), list[1].locked_until);
+
+        assert!(cxn.clear("user:azure").unwrap());
+        assert!(!cxn.clear("user:azure").unwrap());
+        assert_eq!(None, cxn.locked_until("user:azure", t(520)).unwrap());
+
+        cxn.clear_all().unwrap();
+        assert!(cxn.list().unwrap().is_empty());
+    }
+}
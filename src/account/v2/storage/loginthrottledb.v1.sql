@@ -0,0 +1,36 @@
+---
+-- Copyright (c) 2024, Jason Lingle
+--
+-- This file is part of Crymap.
+--
+-- Crymap is free software: you can  redistribute it and/or modify it under the
+-- terms of  the GNU General Public  License as published by  the Free Software
+-- Foundation, either version  3 of the License, or (at  your option) any later
+-- version.
+--
+-- Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+-- WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+-- FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+-- details.
+--
+-- You should have received a copy of the GNU General Public License along with
+-- Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+-- Tracks failed login attempts across all users of the system.
+--
+-- This database lives at the root of the users directory rather than with any
+-- particular user since it must be consulted before the user is known to be
+-- legitimate.
+CREATE TABLE `login_failure` (
+  -- What is being throttled. This is either `user:` followed by the user name
+  -- or `ip:` followed by a network prefix in CIDR notation.
+  `key` TEXT NOT NULL PRIMARY KEY,
+  -- The number of failures since the failure count was last reset.
+  `failures` INTEGER NOT NULL,
+  -- The time of the most recent failure.
+  `last_failure` INTEGER NOT NULL,
+  -- If not NULL, login attempts matching `key` are refused until this time.
+  `locked_until` INTEGER
+) STRICT;
+
+CREATE INDEX `login_failure_last_failure` ON `login_failure` (`last_failure`);
@@ -16,20 +16,23 @@
 // You should have received a copy of the GNU General Public License along with
 // Crymap. If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
 use std::fmt::Write as _;
 use std::fs;
 use std::io;
+use std::os::raw::c_int;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use log::error;
+use log::{error, info};
 use rusqlite::OptionalExtension as _;
 
 use super::{sqlite_xex_vfs::XexVfs, types::*};
 use crate::{
     account::model::*,
+    crypt::page_mac,
     support::{
         error::Error, log_prefix::LogPrefix, mailbox_paths::parse_mailbox_path,
         safe_name::is_safe_name, small_bitset::SmallBitset,
@@ -45,7 +48,21 @@ pub struct Connection {
     override_savedate: Option<UnixTimestamp>,
 }
 
-static MIGRATIONS: &[&str] = &[include_str!("metadb.v1.sql")];
+static MIGRATIONS: &[&str] = &[
+    include_str!("metadb.v1.sql"),
+    include_str!("metadb.v2.sql"),
+    include_str!("metadb.v3.sql"),
+    include_str!("metadb.v4.sql"),
+    include_str!("metadb.v5.sql"),
+    include_str!("metadb.v6.sql"),
+    include_str!("metadb.v7.sql"),
+];
+
+/// The number of entries retained in the login history.
+const LOGIN_HISTORY_LIMIT: i64 = 1000;
+
+/// The modseq given to messages migrated by `migrate_with_uids`.
+const MIGRATED_MODSEQ: u64 = 1 << 32;
 
 impl Connection {
     pub fn new(
@@ -75,6 +92,7 @@ impl Connection {
         cxn.pragma_update(None, "journal_size_limit", 1024 * 1024)?;
         cxn.busy_timeout(Duration::from_secs(10))?;
 
+        enable_page_authentication(log_prefix, &cxn)?;
         super::db_migrations::apply_migrations(
             log_prefix, &mut cxn, "meta", MIGRATIONS,
         )?;
@@ -87,6 +105,29 @@ impl Connection {
         })
     }
 
+    /// Opens a copy of a backup made by `back_up` for reading.
+    ///
+    /// Since the XEX keys depend on the file name, `path` must have the same
+    /// file name as the original database. The database is opened read-only
+    /// and no migrations are applied, so only queries which work against all
+    /// schema versions may be used.
+    pub fn open_backup(path: PathBuf, xex: &XexVfs) -> Result<Self, Error> {
+        let cxn = rusqlite::Connection::open_with_flags_and_vfs(
+            &path,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY
+                | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            xex.name(),
+        )?;
+        cxn.busy_timeout(Duration::from_secs(10))?;
+
+        Ok(Self {
+            cxn,
+            path,
+            #[cfg(test)]
+            override_savedate: None,
+        })
+    }
+
     /// Creates a mailbox with the given name, parent, and special use.
     ///
     /// On success, returns the ID of the created mailbox.
@@ -131,6 +172,29 @@ impl Connection {
         Ok(id)
     }
 
+    /// Like `create_mailbox_hierarchy`, but the final child is a virtual
+    /// mailbox whose contents are the messages matching `query`.
+    pub fn create_virtual_mailbox_hierarchy(
+        &mut self,
+        path: &str,
+        special_use: Option<MailboxAttribute>,
+        query: &[SearchQuery],
+    ) -> Result<MailboxId, Error> {
+        let query = serde_cbor::to_vec(&query)?;
+        let txn = self.cxn.write_tx()?;
+
+        let (parent, child_name) = create_parent_hierarchy(&txn, path)?;
+        let id = create_mailbox(&txn, parent, child_name, special_use)?;
+        txn.execute(
+            "INSERT INTO `virtual_mailbox` (`mailbox_id`, `query`) \
+             VALUES (?, ?)",
+            (id, query),
+        )?;
+        txn.commit()?;
+
+        Ok(id)
+    }
+
     /// Finds the ID of the mailbox with the given path, or returns
     /// `Error::NxMailbox` if it does not exist.
     ///
@@ -289,6 +353,23 @@ impl Connection {
              WHERE `mailbox_id` = ?",
             (id,),
         )?;
+        txn.execute(
+            "DELETE FROM `mailbox_retention` WHERE `mailbox_id` = ?",
+            (id,),
+        )?;
+        txn.execute("DELETE FROM `metadata` WHERE `mailbox_id` = ?", (id,))?;
+        txn.execute(
+            "DELETE FROM `virtual_mailbox_message` WHERE `mailbox_id` = ?",
+            (id,),
+        )?;
+        txn.execute(
+            "DELETE FROM `virtual_mailbox_source` WHERE `mailbox_id` = ?",
+            (id,),
+        )?;
+        txn.execute(
+            "DELETE FROM `virtual_mailbox` WHERE `mailbox_id` = ?",
+            (id,),
+        )?;
 
         // Remove the mailbox entirely if it has no inferiors; otherwise, just
         // make it \Noselect.
@@ -307,6 +388,316 @@ impl Connection {
         Ok(())
     }
 
+    /// Sets the retention rules of the given mailbox, or removes them if
+    /// `retention` is `None`.
+    pub fn set_mailbox_retention(
+        &mut self,
+        mailbox_id: MailboxId,
+        retention: Option<&MailboxRetention>,
+    ) -> Result<(), Error> {
+        let txn = self.cxn.write_tx()?;
+        write_mailbox_retention(&txn, mailbox_id, retention)?;
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    /// Fetches the retention rules of every mailbox that has any, sorted by
+    /// mailbox ID.
+    pub fn fetch_all_mailbox_retentions(
+        &mut self,
+    ) -> Result<Vec<(MailboxId, MailboxRetention)>, Error> {
+        self.cxn.enable_write(false)?;
+        self.cxn
+            .prepare("SELECT * FROM `mailbox_retention` ORDER BY `mailbox_id`")?
+            .query_map((), |row| {
+                Ok((row.get("mailbox_id")?, MailboxRetention::from_row(row)?))
+            })?
+            .collect::<Result<_, _>>()
+            .map_err(Into::into)
+    }
+
+    /// Fetches the search query of the given mailbox, or `None` if it is not
+    /// a virtual mailbox.
+    pub fn fetch_virtual_mailbox_query(
+        &mut self,
+        mailbox_id: MailboxId,
+    ) -> Result<Option<Vec<SearchQuery>>, Error> {
+        self.cxn.enable_write(false)?;
+        let query = self
+            .cxn
+            .prepare_cached(
+                "SELECT `query` FROM `virtual_mailbox` WHERE `mailbox_id` = ?",
+            )?
+            .query_row((mailbox_id,), from_single::<Vec<u8>>)
+            .optional()?;
+
+        query
+            .map(|query| serde_cbor::from_slice(&query).map_err(Into::into))
+            .transpose()
+    }
+
+    /// Fetches the IDs of all virtual mailboxes.
+    pub fn fetch_virtual_mailbox_ids(
+        &mut self,
+    ) -> Result<Vec<MailboxId>, Error> {
+        self.cxn.enable_write(false)?;
+        self.cxn
+            .prepare("SELECT `mailbox_id` FROM `virtual_mailbox`")?
+            .query_map((), from_single)?
+            .collect::<Result<_, _>>()
+            .map_err(Into::into)
+    }
+
+    /// Fetches the stored membership of the virtual mailbox `mailbox_id`.
+    pub fn fetch_virtual_mailbox_membership(
+        &mut self,
+        mailbox_id: MailboxId,
+    ) -> Result<VirtualMailboxMembership, Error> {
+        let txn = self.cxn.read_tx()?;
+        let next_uid = selectable_mailbox_status(&txn, mailbox_id)?.next_uid;
+        let messages = txn
+            .prepare_cached(
+                "SELECT `uid`, `source_mailbox_id`, `source_uid` \
+                 FROM `virtual_mailbox_message` WHERE `mailbox_id` = ?",
+            )?
+            .query_map((mailbox_id,), from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        let sources = txn
+            .prepare_cached(
+                "SELECT `source_mailbox_id`, `modseq` \
+                 FROM `virtual_mailbox_source` WHERE `mailbox_id` = ?",
+            )?
+            .query_map((mailbox_id,), from_row)?
+            .collect::<Result<_, _>>()?;
+
+        Ok(VirtualMailboxMembership {
+            next_uid,
+            messages,
+            sources,
+        })
+    }
+
+    /// Assigns UIDs within the virtual mailbox `mailbox_id` to the messages
+    /// identified by the `(mailbox, uid)` pairs in `sources`, which must not
+    /// contain duplicates.
+    ///
+    /// A message which already has a UID of at least `min_uid` keeps it.
+    /// Other messages are given new UIDs in the order they are listed.
+    ///
+    /// Returns the UIDs parallel to `sources`, and the new `next_uid` of the
+    /// mailbox.
+    pub fn assign_virtual_mailbox_uids(
+        &mut self,
+        mailbox_id: MailboxId,
+        sources: &[(MailboxId, Uid)],
+        min_uid: Uid,
+    ) -> Result<(Vec<Uid>, Uid), Error> {
+        let txn = self.cxn.write_tx()?;
+        let ret =
+            assign_virtual_mailbox_uids(&txn, mailbox_id, sources, min_uid)?;
+        txn.commit()?;
+        Ok(ret)
+    }
+
+    /// Brings the stored membership of the virtual mailbox `mailbox_id` up
+    /// to date.
+    ///
+    /// The messages in `added` are given new UIDs in the order they are
+    /// listed, the UIDs in `removed` are forgotten, and the checked modseqs
+    /// of the source mailboxes are replaced with `sources`.
+    ///
+    /// Returns the UIDs parallel to `added`, and the new `next_uid` of the
+    /// mailbox.
+    pub fn sync_virtual_mailbox(
+        &mut self,
+        mailbox_id: MailboxId,
+        added: &[(MailboxId, Uid)],
+        removed: &[Uid],
+        sources: &BTreeMap<MailboxId, Modseq>,
+    ) -> Result<(Vec<Uid>, Uid), Error> {
+        let txn = self.cxn.write_tx()?;
+        {
+            let mut delete = txn.prepare_cached(
+                "DELETE FROM `virtual_mailbox_message` \
+                 WHERE `mailbox_id` = ? AND `uid` = ?",
+            )?;
+            for &uid in removed {
+                delete.execute((mailbox_id, uid))?;
+            }
+        }
+
+        let ret =
+            assign_virtual_mailbox_uids(&txn, mailbox_id, added, Uid::MIN)?;
+
+        txn.execute(
+            "DELETE FROM `virtual_mailbox_source` WHERE `mailbox_id` = ?",
+            (mailbox_id,),
+        )?;
+        {
+            let mut insert = txn.prepare_cached(
+                "INSERT INTO `virtual_mailbox_source` ( \
+                   `mailbox_id`, `source_mailbox_id`, `modseq` \
+                 ) VALUES (?, ?, ?)",
+            )?;
+            for (&source_mailbox_id, &modseq) in sources {
+                insert.execute((mailbox_id, source_mailbox_id, modseq))?;
+            }
+        }
+        txn.commit()?;
+
+        Ok(ret)
+    }
+
+    /// Removes the given UIDs from the virtual mailbox `mailbox_id`, so that
+    /// the messages they referred to get new UIDs if they are added back.
+    pub fn forget_virtual_mailbox_uids(
+        &mut self,
+        mailbox_id: MailboxId,
+        uids: &mut dyn Iterator<Item = Uid>,
+    ) -> Result<(), Error> {
+        let txn = self.cxn.write_tx()?;
+        {
+            let mut delete = txn.prepare_cached(
+                "DELETE FROM `virtual_mailbox_message` \
+                 WHERE `mailbox_id` = ? AND `uid` = ?",
+            )?;
+            for uid in uids {
+                delete.execute((mailbox_id, uid))?;
+            }
+        }
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    /// Copies the messages identified by the `(mailbox, uid)` pairs in `src`
+    /// into `dst_mailbox_id`, in the order given. If `moove` is true, each
+    /// message that was copied is also expunged from its source mailbox.
+    ///
+    /// This is how messages are copied or moved out of a virtual mailbox.
+    ///
+    /// Returns the UID of each new message, parallel to `src`, or `None`
+    /// where the source message no longer exists.
+    pub fn copy_messages_from_many(
+        &mut self,
+        src: &[(MailboxId, Uid)],
+        dst_mailbox_id: MailboxId,
+        moove: bool,
+    ) -> Result<Vec<Option<Uid>>, Error> {
+        if moove && src.iter().any(|&(id, _)| id == dst_mailbox_id) {
+            return Err(Error::MoveIntoSelf);
+        }
+
+        let savedate = self.savedate();
+        let txn = self.cxn.write_tx()?;
+
+        require_selectable_mailbox(&txn, dst_mailbox_id)?;
+        let dst_uids = copy_messages_into(
+            &txn,
+            &mut src.iter().copied(),
+            dst_mailbox_id,
+            savedate,
+        )?;
+
+        if moove {
+            let mut by_mailbox = BTreeMap::<MailboxId, Vec<Uid>>::new();
+            for (&(src_mailbox_id, src_uid), dst_uid) in
+                src.iter().zip(&dst_uids)
+            {
+                if dst_uid.is_some() {
+                    by_mailbox.entry(src_mailbox_id).or_default().push(src_uid);
+                }
+            }
+
+            for (src_mailbox_id, src_uids) in by_mailbox {
+                expunge_mailbox_messages(
+                    &txn,
+                    src_mailbox_id,
+                    &mut src_uids.into_iter(),
+                )?;
+            }
+        }
+
+        txn.commit()?;
+
+        Ok(dst_uids)
+    }
+
+    /// Fetches all METADATA entries attached to the given mailbox, sorted by
+    /// name.
+    pub fn fetch_metadata(
+        &mut self,
+        mailbox_id: MailboxId,
+    ) -> Result<Vec<(String, String)>, Error> {
+        self.cxn.enable_write(false)?;
+        self.cxn
+            .prepare(
+                "SELECT `name`, `value` FROM `metadata` \
+                 WHERE `mailbox_id` = ? ORDER BY `name`",
+            )?
+            .query_map((mailbox_id,), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()
+            .map_err(Into::into)
+    }
+
+    /// Atomically sets (or, where the value is `None`, removes) the given
+    /// METADATA entries on the given mailbox.
+    ///
+    /// If `retention` is `Some`, the retention rules of the mailbox are set
+    /// as with `set_mailbox_retention` in the same transaction.
+    ///
+    /// If this would leave the mailbox with more than `max_entries` entries,
+    /// nothing is changed and `Error::TooManyMetadataEntries` is returned.
+    pub fn set_metadata(
+        &mut self,
+        mailbox_id: MailboxId,
+        entries: &[(String, Option<String>)],
+        retention: Option<Option<&MailboxRetention>>,
+        max_entries: usize,
+    ) -> Result<(), Error> {
+        let txn = self.cxn.write_tx()?;
+        if 0 == txn.query_row(
+            "SELECT COUNT(*) FROM `mailbox` WHERE `id` = ?",
+            (mailbox_id,),
+            from_single::<i64>,
+        )? {
+            return Err(Error::NxMailbox);
+        }
+
+        if let Some(retention) = retention {
+            write_mailbox_retention(&txn, mailbox_id, retention)?;
+        }
+
+        for &(ref name, ref value) in entries {
+            if let Some(ref value) = *value {
+                txn.execute(
+                    "INSERT OR REPLACE INTO `metadata` \
+                     (`mailbox_id`, `name`, `value`) VALUES (?, ?, ?)",
+                    (mailbox_id, name, value),
+                )?;
+            } else {
+                txn.execute(
+                    "DELETE FROM `metadata` \
+                     WHERE `mailbox_id` = ? AND `name` = ?",
+                    (mailbox_id, name),
+                )?;
+            }
+        }
+
+        let count = txn.query_row(
+            "SELECT COUNT(*) FROM `metadata` WHERE `mailbox_id` = ?",
+            (mailbox_id,),
+            from_single::<i64>,
+        )?;
+        if count > max_entries as i64 {
+            return Err(Error::TooManyMetadataEntries);
+        }
+
+        txn.commit()?;
+        Ok(())
+    }
+
     /// Adds `path` as a new subscription.
     ///
     /// No normalisation is applied to `path`; this is the responsibility of
@@ -374,7 +765,6 @@ impl Connection {
     }
 
     /// Retrieves all flags that currently exist in the account.
-    #[cfg(test)]
     pub fn fetch_all_flags(&mut self) -> Result<Vec<(FlagId, Flag)>, Error> {
         self.cxn.enable_write(false)?;
         self.cxn
@@ -408,6 +798,33 @@ impl Connection {
         get_message_by_path(&self.cxn, path).map(|e| e.is_some())
     }
 
+    /// Fetches the ID of every known message, including orphaned ones.
+    pub fn fetch_all_message_ids(&mut self) -> Result<Vec<MessageId>, Error> {
+        self.cxn.enable_write(false)?;
+        self.cxn
+            .prepare("SELECT `id` FROM `message` ORDER BY `id`")?
+            .query_map((), from_single)?
+            .collect::<Result<_, _>>()
+            .map_err(Into::into)
+    }
+
+    /// Fetches the mailbox and UID of every instance of the given message,
+    /// ordered by mailbox ID and then UID.
+    pub fn fetch_message_instances(
+        &mut self,
+        message_id: MessageId,
+    ) -> Result<Vec<(MailboxId, Uid)>, Error> {
+        self.cxn.enable_write(false)?;
+        self.cxn
+            .prepare_cached(
+                "SELECT `mailbox_id`, `uid` FROM `mailbox_message` \
+                 WHERE `message_id` = ? ORDER BY `mailbox_id`, `uid`",
+            )?
+            .query_map((message_id,), from_row)?
+            .collect::<Result<_, _>>()
+            .map_err(Into::into)
+    }
+
     /// Returns a summary of the messages known to the database.
     ///
     /// The table returned holds the sum of the `summary_increment` values for
@@ -502,6 +919,115 @@ impl Connection {
         Ok(())
     }
 
+    /// Fetches the cached `PREVIEW` text of the given message, if any.
+    pub fn fetch_message_preview(
+        &mut self,
+        message_id: MessageId,
+    ) -> Result<Option<String>, Error> {
+        self.cxn.enable_write(false)?;
+        self.cxn
+            .prepare_cached("SELECT `preview` FROM `message` WHERE `id` = ?")?
+            .query_row((message_id,), |row| row.get(0))
+            .optional()
+            .map(Option::flatten)
+            .map_err(Into::into)
+    }
+
+    /// Caches the `PREVIEW` text of the given message.
+    ///
+    /// If the message no longer exists, the call silently does nothing.
+    pub fn cache_message_preview(
+        &mut self,
+        message_id: MessageId,
+        preview: &str,
+    ) -> Result<(), Error> {
+        self.cxn.enable_write(true)?;
+        self.cxn
+            .prepare_cached(
+                "UPDATE `message` SET `preview` = ?2 WHERE `id` = ?1",
+            )?
+            .execute((message_id, preview))?;
+        Ok(())
+    }
+
+    /// Changes the path of the given message, e.g. because its file has been
+    /// rewritten.
+    pub fn set_message_path(
+        &mut self,
+        message_id: MessageId,
+        path: &str,
+    ) -> Result<(), Error> {
+        let (summary_bucket, summary_increment) = message_summary_values(path);
+
+        self.cxn.enable_write(true)?;
+        self.cxn
+            .prepare_cached(
+                "UPDATE `message` \
+                 SET `path` = ?2, `summary_bucket` = ?3, \
+                 `summary_increment` = ?4 \
+                 WHERE `id` = ?1",
+            )?
+            .execute((message_id, path, summary_bucket, summary_increment))?;
+        Ok(())
+    }
+
+    /// Fetches the ID and path of every message, ordered by ID.
+    pub fn fetch_all_message_paths(
+        &mut self,
+    ) -> Result<Vec<(MessageId, String)>, Error> {
+        self.cxn.enable_write(false)?;
+        self.cxn
+            .prepare("SELECT `id`, `path` FROM `message` ORDER BY `id`")?
+            .query_map((), from_row)?
+            .collect::<Result<_, _>>()
+            .map_err(Into::into)
+    }
+
+    /// Sets the path of every message whose ID is in `paths` to the path
+    /// given there, if different.
+    ///
+    /// This is used to point database backups at message files which have
+    /// since been rewritten. Returns the number of messages updated.
+    pub fn update_message_paths(
+        &mut self,
+        paths: &HashMap<MessageId, String>,
+    ) -> Result<usize, Error> {
+        let txn = self.cxn.write_tx()?;
+        let mut updated = 0;
+        {
+            let existing = txn
+                .prepare("SELECT `id`, `path` FROM `message`")?
+                .query_map((), from_row::<(MessageId, String)>)?
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut update = txn.prepare(
+                "UPDATE `message` \
+                 SET `path` = ?2, `summary_bucket` = ?3, \
+                 `summary_increment` = ?4 \
+                 WHERE `id` = ?1",
+            )?;
+
+            for (id, path) in existing {
+                let Some(new_path) =
+                    paths.get(&id).filter(|&new_path| *new_path != path)
+                else {
+                    continue;
+                };
+
+                let (summary_bucket, summary_increment) =
+                    message_summary_values(new_path);
+                update.execute((
+                    id,
+                    new_path,
+                    summary_bucket,
+                    summary_increment,
+                ))?;
+                updated += 1;
+            }
+        }
+        txn.commit()?;
+        Ok(updated)
+    }
+
     /// Append already-interned messages into the given mailbox, with the given
     /// initial flags if requested.
     ///
@@ -709,6 +1235,107 @@ impl Connection {
         Ok(())
     }
 
+    /// Fetches every expunged mailbox message which can still be put back,
+    /// most recently expunged first.
+    ///
+    /// A message qualifies if it is not currently in any mailbox, has not yet
+    /// been forgotten, and its original mailbox is still selectable.
+    pub fn fetch_unexpungeable_messages(
+        &mut self,
+    ) -> Result<Vec<ExpungedMessage>, Error> {
+        self.cxn.enable_write(false)?;
+        self.cxn
+            .prepare(
+                "SELECT `e`.`mailbox_id`, `e`.`uid`, `e`.`near_flags`, \
+                        `e`.`expunged_at`, `m`.`path` \
+                 FROM `message` `m` \
+                 JOIN `mailbox_message_expungement` `e` \
+                 ON `e`.`message_id` = `m`.`id` \
+                 JOIN `mailbox` `mb` ON `mb`.`id` = `e`.`mailbox_id` \
+                 WHERE `m`.`refcount` = 0 AND `mb`.`selectable` \
+                 ORDER BY `e`.`expunged_at` DESC, `e`.`mailbox_id`, `e`.`uid`",
+            )?
+            .query_map((), from_row)?
+            .collect::<Result<_, _>>()
+            .map_err(Into::into)
+    }
+
+    /// Puts the expunged messages identified by `uids` (which must be sorted
+    /// ascending) back into `mailbox_id`, with the flags they had when they
+    /// were expunged, except for `\Deleted`.
+    ///
+    /// The messages are given new UIDs, since their old UIDs have already
+    /// been reported as expunged. Only messages which are not currently in
+    /// any mailbox are restored; anything else in `uids` is silently ignored.
+    ///
+    /// Returns the number of messages restored.
+    pub fn unexpunge_mailbox_messages(
+        &mut self,
+        mailbox_id: MailboxId,
+        uids: &mut dyn Iterator<Item = Uid>,
+    ) -> Result<usize, Error> {
+        let savedate = self.savedate();
+        let txn = self.cxn.write_tx()?;
+
+        require_selectable_mailbox(&txn, mailbox_id)?;
+        let deleted_flag = txn
+            .prepare_cached("SELECT `id` FROM `flag` WHERE `flag` = ?")?
+            .query_row((&Flag::Deleted,), from_single::<FlagId>)
+            .optional()?;
+
+        let mut messages = Vec::<(Uid, MessageId, SmallBitset)>::new();
+        {
+            let mut fetch = txn.prepare(
+                "SELECT `e`.`message_id`, `e`.`near_flags` \
+                 FROM `mailbox_message_expungement` `e` \
+                 JOIN `message` `m` ON `m`.`id` = `e`.`message_id` \
+                 WHERE `e`.`mailbox_id` = ? AND `e`.`uid` = ? \
+                 AND `m`.`refcount` = 0",
+            )?;
+            for uid in uids {
+                let Some((message_id, near_flags)) = fetch
+                    .query_row((mailbox_id, uid), from_row::<(MessageId, i64)>)
+                    .optional()?
+                else {
+                    continue;
+                };
+
+                let mut flags = SmallBitset::new_with_near(near_flags as u64);
+                if let Some(deleted_flag) = deleted_flag {
+                    flags.remove(deleted_flag.0);
+                }
+                messages.push((uid, message_id, flags));
+            }
+        }
+
+        if messages.is_empty() {
+            return Ok(0);
+        }
+
+        append_mailbox_messages(
+            &txn,
+            mailbox_id,
+            savedate,
+            &mut messages.iter().map(|&(_, message_id, ref flags)| {
+                Ok((message_id, Some(flags)))
+            }),
+        )?;
+
+        // Ensure the same expungement can't be undone twice, even if the
+        // message is orphaned again later.
+        let mut forget = txn.prepare(
+            "UPDATE `mailbox_message_expungement` SET `message_id` = NULL \
+             WHERE `mailbox_id` = ? AND `uid` = ?",
+        )?;
+        for &(uid, _, _) in &messages {
+            forget.execute((mailbox_id, uid))?;
+        }
+        drop(forget);
+
+        txn.commit()?;
+        Ok(messages.len())
+    }
+
     /// Fetch the messages in the given mailbox which were expunged after the
     /// given modseq and pass the given filter.
     ///
@@ -1480,6 +2107,169 @@ impl Connection {
         Ok(())
     }
 
+    /// Migrates mailboxes, messages, and subscriptions from another mail
+    /// system, preserving UIDVALIDITY and UIDs.
+    ///
+    /// This works like `migrate_v1_to_v2`: the callback passes events to its
+    /// own callback in the order they are discovered, and everything happens
+    /// in one transaction.
+    ///
+    /// The modseq of every migrated message is `MIGRATED_MODSEQ`, which is
+    /// likely to be greater than any modseq the client saw on the other
+    /// system, so that clients using CONDSTORE or QRESYNC resynchronise flags
+    /// (but not message content). Migrated messages are not `\Recent`.
+    pub fn migrate_with_uids(
+        &mut self,
+        callback: &mut dyn FnMut(
+            &mut dyn FnMut(UidMigrationEvent<'_>) -> Result<(), Error>,
+        ) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let savedate = self.savedate();
+        self.cxn.enable_write(true)?;
+        let txn = self.cxn.transaction_with_behavior(
+            rusqlite::TransactionBehavior::Exclusive,
+        )?;
+
+        let mut reserved_id = 0u32;
+        let mut migrated_mailboxes = Vec::<MailboxId>::new();
+        callback(&mut |evt| {
+            match evt {
+                UidMigrationEvent::ReserveMailboxIds { next } => {
+                    reserved_id = reserved_id.max(next);
+                    // Apply the reservation immediately so that mailboxes
+                    // created by the migration itself don't take IDs that
+                    // later mailboxes want to use as their UIDVALIDITY.
+                    reserve_mailbox_ids(&txn, reserved_id)?;
+                },
+
+                UidMigrationEvent::Mailbox {
+                    path,
+                    special_use,
+                    uid_validity,
+                    next_uid,
+                } => {
+                    let (parent_id, child_name) =
+                        create_parent_hierarchy(&txn, path)?;
+                    let uid_validity = match uid_validity {
+                        Some(v) if 0 != v => {
+                            let in_use = 0
+                                != txn.query_row(
+                                    "SELECT COUNT(*) FROM `mailbox` \
+                                     WHERE `id` = ?",
+                                    (v,),
+                                    from_single::<i64>,
+                                )?;
+                            (!in_use).then_some(MailboxId(v))
+                        },
+                        _ => None,
+                    };
+                    let mailbox_id =
+                        match look_up_mailbox(&txn, parent_id, child_name)? {
+                            Some(id) => {
+                                // We can only take over mailboxes that have
+                                // never been used, such as the ones created
+                                // when the account is provisioned.
+                                let status =
+                                    selectable_mailbox_status(&txn, id)?;
+                                let has_children = 0
+                                    != txn.query_row(
+                                        "SELECT COUNT(*) FROM `mailbox` \
+                                         WHERE `parent_id` = ?",
+                                        (id,),
+                                        from_single::<i64>,
+                                    )?;
+                                if Uid::MIN != status.next_uid || has_children {
+                                    return Err(Error::MailboxExists);
+                                }
+
+                                if let Some(uid_validity) = uid_validity {
+                                    txn.execute(
+                                        "UPDATE `mailbox` SET `id` = ? \
+                                         WHERE `id` = ?",
+                                        (uid_validity, id),
+                                    )?;
+                                    uid_validity
+                                } else {
+                                    id
+                                }
+                            },
+                            // Insert the mailbox with its UIDVALIDITY as the
+                            // ID directly instead of renumbering it after the
+                            // fact so that it doesn't use up an ID past the
+                            // reservation.
+                            None => create_mailbox_with_id(
+                                &txn,
+                                uid_validity,
+                                parent_id,
+                                child_name,
+                                special_use,
+                            )?,
+                        };
+
+                    txn.execute(
+                        "UPDATE `mailbox` \
+                         SET `next_uid` = MAX(`next_uid`, ?), \
+                             `max_modseq` = ? \
+                         WHERE `id` = ?",
+                        (next_uid, Modseq::of(MIGRATED_MODSEQ), mailbox_id),
+                    )?;
+                    migrated_mailboxes.push(mailbox_id);
+                },
+
+                UidMigrationEvent::Message { path, uid, flags } => {
+                    let &mailbox_id = migrated_mailboxes
+                        .last()
+                        .expect("Message event before Mailbox");
+                    let message_id = intern_message_as_orphan(&txn, path)?;
+                    let flags = flags
+                        .iter()
+                        .map(|flag| intern_flag(&txn, flag).map(|id| id.0))
+                        .collect::<Result<SmallBitset, _>>()?;
+
+                    insert_mailbox_message(
+                        &txn,
+                        mailbox_id,
+                        uid,
+                        message_id,
+                        Some(&flags),
+                        savedate,
+                        Modseq::of(MIGRATED_MODSEQ),
+                    )?;
+                    txn.execute(
+                        "UPDATE `mailbox` SET `next_uid` = MAX(`next_uid`, ?) \
+                         WHERE `id` = ?",
+                        (uid.next().ok_or(Error::MailboxFull)?, mailbox_id),
+                    )?;
+                },
+
+                UidMigrationEvent::Subscription { path } => {
+                    txn.execute(
+                        "INSERT OR IGNORE INTO `subscription` (`path`) \
+                         VALUES (?)",
+                        (path,),
+                    )?;
+                },
+            }
+
+            Ok(())
+        })?;
+
+        for &mailbox_id in &migrated_mailboxes {
+            txn.execute(
+                "UPDATE `mailbox` SET `recent_uid` = `next_uid` WHERE `id` = ?",
+                (mailbox_id,),
+            )?;
+        }
+
+        // SQLite only advances the AUTOINCREMENT counter on INSERT, so we
+        // need to account for the IDs we changed ourselves to ensure they
+        // don't get handed out again.
+        reserve_mailbox_ids(&txn, reserved_id)?;
+
+        txn.commit()?;
+        Ok(())
+    }
+
     /// See if the given maintenance should be started.
     pub fn start_maintenance(
         &mut self,
@@ -1554,6 +2344,25 @@ impl Connection {
         Ok(())
     }
 
+    /// Runs SQLite's `integrity_check` over the database.
+    ///
+    /// Since this reads every page through the XEX layer, it also catches
+    /// pages which no longer decrypt to anything sensible. Returns the
+    /// problems found, which is empty if the database is intact.
+    pub fn integrity_check(&mut self) -> Result<Vec<String>, Error> {
+        self.cxn.enable_write(false)?;
+        let mut problems = self
+            .cxn
+            .prepare("PRAGMA integrity_check")?
+            .query_map((), from_single::<String>)?
+            .collect::<Result<Vec<_>, _>>()?;
+        if problems.len() == 1 && "ok" == problems[0] {
+            problems.clear();
+        }
+
+        Ok(problems)
+    }
+
     /// Fetches the message spool information for the given message, if any.
     pub fn fetch_message_spool(
         &mut self,
@@ -1732,13 +2541,110 @@ impl Connection {
     ) -> Result<(), Error> {
         self.cxn.enable_write(true)?;
         self.cxn.execute(
-            "DELETE FROM `foreign_smtp_tls_status` \
-             WHERE `domain` = ?",
-            (domain,),
+            "DELETE FROM `foreign_smtp_tls_status` \
+             WHERE `domain` = ?",
+            (domain,),
+        )?;
+        Ok(())
+    }
+
+    /// Adds `entry` to the login history, discarding the oldest entries if
+    /// the history has grown too large.
+    ///
+    /// Returns the ID of the new entry.
+    pub fn insert_login_history(
+        &mut self,
+        entry: &LoginHistoryEntry,
+    ) -> Result<i64, Error> {
+        let txn = self.cxn.write_tx()?;
+        txn.execute(
+            "INSERT INTO `login_history` (\
+             `at`, `protocol`, `success`, `peer_ip`, `tls`, \
+             `client_name`, `client_id`, `app_password`) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            (
+                UnixTimestamp(entry.at),
+                &entry.protocol,
+                entry.success,
+                &entry.peer_ip,
+                &entry.tls,
+                &entry.client_name,
+                &entry.client_id,
+                &entry.app_password,
+            ),
+        )?;
+        let id = txn.last_insert_rowid();
+        txn.execute(
+            "DELETE FROM `login_history` WHERE `id` <= ?",
+            (id - LOGIN_HISTORY_LIMIT,),
+        )?;
+        txn.commit()?;
+        Ok(id)
+    }
+
+    /// Updates the RFC 2971 `ID` information on the given login history
+    /// entry.
+    ///
+    /// This is used when the client only identifies itself after logging in.
+    pub fn update_login_history_client(
+        &mut self,
+        id: i64,
+        client_name: Option<&str>,
+        client_id: Option<&str>,
+    ) -> Result<(), Error> {
+        self.cxn.enable_write(true)?;
+        self.cxn.execute(
+            "UPDATE `login_history` \
+             SET `client_name` = ?, `client_id` = ? \
+             WHERE `id` = ?",
+            (client_name, client_id, id),
         )?;
         Ok(())
     }
 
+    /// Returns whether there is any successful login in the history.
+    pub fn has_successful_login(&mut self) -> Result<bool, Error> {
+        self.cxn.enable_write(false)?;
+        self.cxn
+            .prepare("SELECT 1 FROM `login_history` WHERE `success`")?
+            .exists(())
+            .map_err(Into::into)
+    }
+
+    /// Returns whether there is a successful login in the history from the
+    /// given IP address and client name.
+    pub fn is_known_login_device(
+        &mut self,
+        peer_ip: Option<&str>,
+        client_name: Option<&str>,
+    ) -> Result<bool, Error> {
+        self.cxn.enable_write(false)?;
+        self.cxn
+            .prepare(
+                "SELECT 1 FROM `login_history` \
+                 WHERE `success` AND `peer_ip` IS ? AND `client_name` IS ?",
+            )?
+            .exists((peer_ip, client_name))
+            .map_err(Into::into)
+    }
+
+    /// Fetches up to `limit` of the most recent login history entries, most
+    /// recent first.
+    pub fn fetch_login_history(
+        &mut self,
+        limit: u32,
+    ) -> Result<Vec<LoginHistoryEntry>, Error> {
+        self.cxn.enable_write(false)?;
+        self.cxn
+            .prepare(
+                "SELECT * FROM `login_history` \
+                 ORDER BY `id` DESC LIMIT ?",
+            )?
+            .query_map((limit,), from_row)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Into::into)
+    }
+
     #[cfg(not(test))]
     fn savedate(&self) -> UnixTimestamp {
         UnixTimestamp::now()
@@ -1812,6 +2718,18 @@ fn create_mailbox(
     parent: MailboxId,
     name: &str,
     special_use: Option<MailboxAttribute>,
+) -> Result<MailboxId, Error> {
+    create_mailbox_with_id(txn, None, parent, name, special_use)
+}
+
+/// Like `create_mailbox`, but uses `id` as the ID of the new mailbox if given
+/// instead of allocating a fresh one.
+fn create_mailbox_with_id(
+    txn: &rusqlite::Connection,
+    id: Option<MailboxId>,
+    parent: MailboxId,
+    name: &str,
+    special_use: Option<MailboxAttribute>,
 ) -> Result<MailboxId, Error> {
     if 0 == txn.query_row(
         "SELECT COUNT(*) FROM `mailbox` WHERE `id` = ?",
@@ -1831,9 +2749,9 @@ fn create_mailbox(
     }
 
     txn.execute(
-        "INSERT INTO `mailbox` (`parent_id`, `name`, `special_use`)\
-         VALUES (?, ?, ?)",
-        (parent, name, special_use),
+        "INSERT INTO `mailbox` (`id`, `parent_id`, `name`, `special_use`)\
+         VALUES (?, ?, ?, ?)",
+        (id, parent, name, special_use),
     )?;
 
     let Ok(mailbox_id) = u32::try_from(txn.last_insert_rowid()) else {
@@ -1874,6 +2792,21 @@ fn create_parent_hierarchy<'a>(
     Err(Error::UnsafeName)
 }
 
+/// Ensures that mailboxes created in the future are given IDs of at least
+/// `next` and greater than any existing mailbox ID.
+fn reserve_mailbox_ids(
+    txn: &rusqlite::Connection,
+    next: u32,
+) -> Result<(), Error> {
+    txn.execute(
+        "UPDATE `sqlite_sequence` \
+         SET `seq` = MAX(`seq`, ?, (SELECT MAX(`id`) FROM `mailbox`)) \
+         WHERE `name` = 'mailbox'",
+        (i64::from(next) - 1,),
+    )?;
+    Ok(())
+}
+
 fn look_up_mailbox(
     txn: &rusqlite::Connection,
     parent_id: MailboxId,
@@ -1998,51 +2931,22 @@ fn append_mailbox_messages(
         Item = Result<(MessageId, Option<&SmallBitset>), Error>,
     >,
 ) -> Result<Uid, Error> {
+    require_real_mailbox(cxn, mailbox_id)?;
     let modseq = new_modseq(cxn, mailbox_id)?;
 
     // Read the UID for the first new message out of the database.
     let first_uid = selectable_mailbox_status(cxn, mailbox_id)?.next_uid;
     let mut next_uid = first_uid;
 
-    let mut mailbox_message_insert = cxn.prepare_cached(
-        "INSERT INTO `mailbox_message` ( \
-           `mailbox_id`, `uid`, `message_id`, `near_flags`, \
-           `savedate`, `append_modseq`, `flags_modseq` \
-         ) VALUES (?, ?, ?, ?, ?, ?, ?)",
-    )?;
-    let mut mailbox_message_far_flag_insert = cxn.prepare_cached(
-        "INSERT INTO `mailbox_message_far_flag` (\
-           `mailbox_id`, `uid`, `flag_id` \
-         ) VALUES (?, ?, ?)",
-    )?;
-
     for message in messages {
         let (message_id, flags) = message?;
 
         let uid = next_uid;
         next_uid = next_uid.next().ok_or(Error::MailboxFull)?;
 
-        mailbox_message_insert.execute((
-            mailbox_id,
-            uid,
-            message_id,
-            flags.map_or(0, |f| f.near_bits() as i64),
-            savedate,
-            modseq,
-            modseq,
-        ))?;
-
-        if let Some(flags) = flags {
-            if flags.has_far() {
-                for far_flag in flags.iter_far() {
-                    mailbox_message_far_flag_insert.execute((
-                        mailbox_id,
-                        uid,
-                        FlagId(far_flag),
-                    ))?;
-                }
-            }
-        }
+        insert_mailbox_message(
+            cxn, mailbox_id, uid, message_id, flags, savedate, modseq,
+        )?;
     }
 
     if next_uid > first_uid {
@@ -2055,6 +2959,53 @@ fn append_mailbox_messages(
     Ok(first_uid)
 }
 
+/// Inserts a single message into `mailbox_id` with the given UID, without
+/// updating the mailbox's `next_uid`.
+fn insert_mailbox_message(
+    cxn: &rusqlite::Connection,
+    mailbox_id: MailboxId,
+    uid: Uid,
+    message_id: MessageId,
+    flags: Option<&SmallBitset>,
+    savedate: UnixTimestamp,
+    modseq: Modseq,
+) -> Result<(), Error> {
+    cxn.prepare_cached(
+        "INSERT INTO `mailbox_message` ( \
+           `mailbox_id`, `uid`, `message_id`, `near_flags`, \
+           `savedate`, `append_modseq`, `flags_modseq` \
+         ) VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )?
+    .execute((
+        mailbox_id,
+        uid,
+        message_id,
+        flags.map_or(0, |f| f.near_bits() as i64),
+        savedate,
+        modseq,
+        modseq,
+    ))?;
+
+    if let Some(flags) = flags {
+        if flags.has_far() {
+            let mut mailbox_message_far_flag_insert = cxn.prepare_cached(
+                "INSERT INTO `mailbox_message_far_flag` (\
+                   `mailbox_id`, `uid`, `flag_id` \
+                 ) VALUES (?, ?, ?)",
+            )?;
+            for far_flag in flags.iter_far() {
+                mailbox_message_far_flag_insert.execute((
+                    mailbox_id,
+                    uid,
+                    FlagId(far_flag),
+                ))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 /// Copy the messages represented by `src_uids` (which must be sorted
 /// ascending) from `src_mailbox_id` into `dst_mailbox_id`.
 ///
@@ -2072,8 +3023,39 @@ fn copy_mailbox_messages(
         to_uids: SeqRange::new(),
     };
 
+    let src_uids = src_uids.collect::<Vec<_>>();
+    let dst_uids = copy_messages_into(
+        txn,
+        &mut src_uids.iter().map(|&uid| (src_mailbox_id, uid)),
+        dst_mailbox_id,
+        savedate,
+    )?;
+
+    for (src_uid, dst_uid) in src_uids.into_iter().zip(dst_uids) {
+        if let Some(dst_uid) = dst_uid {
+            response.from_uids.append(src_uid);
+            response.to_uids.append(dst_uid);
+        }
+    }
+
+    Ok(response)
+}
+
+/// Copy the messages represented by the `(mailbox, uid)` pairs in `src` into
+/// `dst_mailbox_id`, in the order given.
+///
+/// Returns the UID of each new message, parallel to `src`, or `None` where the
+/// source message does not exist.
+fn copy_messages_into(
+    txn: &rusqlite::Connection,
+    src: &mut dyn Iterator<Item = (MailboxId, Uid)>,
+    dst_mailbox_id: MailboxId,
+    savedate: UnixTimestamp,
+) -> Result<Vec<Option<Uid>>, Error> {
+    require_real_mailbox(txn, dst_mailbox_id)?;
     let dst_modseq = new_modseq(txn, dst_mailbox_id)?;
-    let mut next_uid = selectable_mailbox_status(txn, dst_mailbox_id)?.next_uid;
+    let first_uid = selectable_mailbox_status(txn, dst_mailbox_id)?.next_uid;
+    let mut next_uid = first_uid;
 
     let mut copy_message = txn.prepare(
         "INSERT INTO `mailbox_message` ( \
@@ -2093,7 +3075,8 @@ fn copy_mailbox_messages(
          WHERE `mailbox_id` = ?1 AND `uid` = ?2",
     )?;
 
-    for src_uid in src_uids {
+    let mut dst_uids = Vec::new();
+    for (src_mailbox_id, src_uid) in src {
         let dst_uid = next_uid;
         if 0 == copy_message.execute((
             src_mailbox_id,
@@ -2103,6 +3086,7 @@ fn copy_mailbox_messages(
             savedate,
             dst_modseq,
         ))? {
+            dst_uids.push(None);
             continue;
         }
 
@@ -2112,20 +3096,19 @@ fn copy_mailbox_messages(
             dst_mailbox_id,
             dst_uid,
         ))?;
-        response.from_uids.append(src_uid);
-        response.to_uids.append(dst_uid);
+        dst_uids.push(Some(dst_uid));
 
         next_uid = next_uid.next().ok_or(Error::MailboxFull)?;
     }
 
-    if !response.from_uids.is_empty() {
+    if next_uid != first_uid {
         txn.execute(
             "UPDATE `mailbox` SET `next_uid` = ? WHERE `id` = ?",
             (next_uid, dst_mailbox_id),
         )?;
     }
 
-    Ok(response)
+    Ok(dst_uids)
 }
 
 /// Expunges the given messages from the given mailbox.
@@ -2146,18 +3129,27 @@ fn expunge_mailbox_messages(
         "DELETE FROM `mailbox_message` \
          WHERE `mailbox_id` = ? AND `uid` = ?",
     )?;
+    // The expungement is recorded first since it copies the message ID and
+    // flags out of `mailbox_message`. If the message does not exist, nothing
+    // is inserted.
     let mut insert_mailbox_message_expungement = txn.prepare(
         "INSERT INTO `mailbox_message_expungement` \
-         (`mailbox_id`, `uid`, `expunged_modseq`) \
-         VALUES (?, ?, ?)",
+         (`mailbox_id`, `uid`, `expunged_modseq`, \
+          `message_id`, `near_flags`, `expunged_at`) \
+         SELECT `mailbox_id`, `uid`, ?, `message_id`, `near_flags`, ? \
+         FROM `mailbox_message` \
+         WHERE `mailbox_id` = ? AND `uid` = ?",
     )?;
 
+    let now = UnixTimestamp::now();
     let mut count = 0;
     for uid in messages {
-        delete_from_mailbox_message_far_flag.execute((mailbox_id, uid))?;
-        if delete_from_mailbox_message.execute((mailbox_id, uid))? > 0 {
-            insert_mailbox_message_expungement
-                .execute((mailbox_id, uid, modseq))?;
+        if insert_mailbox_message_expungement
+            .execute((modseq, now, mailbox_id, uid))?
+            > 0
+        {
+            delete_from_mailbox_message_far_flag.execute((mailbox_id, uid))?;
+            delete_from_mailbox_message.execute((mailbox_id, uid))?;
             count += 1;
         }
     }
@@ -2323,6 +3315,56 @@ fn require_selectable_mailbox(
     }
 }
 
+/// Sets the retention rules of `mailbox_id`, or removes them if `retention` is
+/// `None`.
+fn write_mailbox_retention(
+    cxn: &rusqlite::Connection,
+    mailbox_id: MailboxId,
+    retention: Option<&MailboxRetention>,
+) -> Result<(), Error> {
+    require_selectable_mailbox(cxn, mailbox_id)?;
+    if let Some(retention) = retention {
+        cxn.execute(
+            "INSERT OR REPLACE INTO `mailbox_retention` ( \
+               `mailbox_id`, `max_age_days`, `age_basis`, \
+               `max_messages`, `only_seen` \
+             ) VALUES (?, ?, ?, ?, ?)",
+            (
+                mailbox_id,
+                retention.max_age_days,
+                retention.age_basis,
+                retention.max_messages,
+                retention.only_seen,
+            ),
+        )?;
+    } else {
+        cxn.execute(
+            "DELETE FROM `mailbox_retention` WHERE `mailbox_id` = ?",
+            (mailbox_id,),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Fails with `Error::VirtualMailbox` if `id` is a virtual mailbox, which
+/// cannot hold messages of its own.
+fn require_real_mailbox(
+    cxn: &rusqlite::Connection,
+    id: MailboxId,
+) -> Result<(), Error> {
+    if cxn
+        .prepare_cached(
+            "SELECT 1 FROM `virtual_mailbox` WHERE `mailbox_id` = ?",
+        )?
+        .exists((id,))?
+    {
+        return Err(Error::VirtualMailbox);
+    }
+
+    Ok(())
+}
+
 fn selectable_mailbox_status(
     cxn: &rusqlite::Connection,
     mailbox_id: MailboxId,
@@ -2343,6 +3385,68 @@ fn selectable_mailbox_status(
     Ok(status)
 }
 
+/// Implements `Connection::assign_virtual_mailbox_uids` within a transaction.
+fn assign_virtual_mailbox_uids(
+    cxn: &rusqlite::Connection,
+    mailbox_id: MailboxId,
+    sources: &[(MailboxId, Uid)],
+    min_uid: Uid,
+) -> Result<(Vec<Uid>, Uid), Error> {
+    let first_uid = selectable_mailbox_status(cxn, mailbox_id)?.next_uid;
+    let mut next_uid = first_uid;
+
+    let mut uids = Vec::with_capacity(sources.len());
+    {
+        let mut find = cxn.prepare_cached(
+            "SELECT `uid` FROM `virtual_mailbox_message` \
+             WHERE `mailbox_id` = ? \
+             AND `source_mailbox_id` = ? AND `source_uid` = ?",
+        )?;
+        let mut delete = cxn.prepare_cached(
+            "DELETE FROM `virtual_mailbox_message` \
+             WHERE `mailbox_id` = ? AND `uid` = ?",
+        )?;
+        let mut insert = cxn.prepare_cached(
+            "INSERT INTO `virtual_mailbox_message` ( \
+               `mailbox_id`, `uid`, `source_mailbox_id`, `source_uid` \
+             ) VALUES (?, ?, ?, ?)",
+        )?;
+
+        for &(source_mailbox_id, source_uid) in sources {
+            match find
+                .query_row(
+                    (mailbox_id, source_mailbox_id, source_uid),
+                    from_single::<Uid>,
+                )
+                .optional()?
+            {
+                Some(uid) if uid >= min_uid => {
+                    uids.push(uid);
+                    continue;
+                },
+                Some(uid) => {
+                    delete.execute((mailbox_id, uid))?;
+                },
+                None => {},
+            }
+
+            let uid = next_uid;
+            next_uid = next_uid.next().ok_or(Error::MailboxFull)?;
+            insert.execute((mailbox_id, uid, source_mailbox_id, source_uid))?;
+            uids.push(uid);
+        }
+    }
+
+    if next_uid != first_uid {
+        cxn.execute(
+            "UPDATE `mailbox` SET `next_uid` = ? WHERE `id` = ?",
+            (next_uid, mailbox_id),
+        )?;
+    }
+
+    Ok((uids, next_uid))
+}
+
 /// Allocates a new `Modseq` for a change within the given mailbox.
 fn new_modseq(
     cxn: &rusqlite::Connection,
@@ -2363,6 +3467,55 @@ fn new_modseq(
     Ok(this_modseq)
 }
 
+/// Ensures that the database behind `cxn` reserves the space needed for page
+/// authentication, converting it if it was created without.
+///
+/// For a new database, this just needs to happen before anything is written.
+/// Existing databases are rebuilt with `VACUUM`, which rewrites every page.
+fn enable_page_authentication(
+    log_prefix: &LogPrefix,
+    cxn: &rusqlite::Connection,
+) -> Result<(), Error> {
+    // Reading anything loads the header, which includes the amount of
+    // reserved space.
+    let page_count =
+        cxn.query_row("PRAGMA page_count", (), from_single::<i64>)?;
+    if page_mac::RESERVED_BYTES as c_int == reserve_bytes(cxn, -1)? {
+        return Ok(());
+    }
+
+    reserve_bytes(cxn, page_mac::RESERVED_BYTES as c_int)?;
+    if page_count > 0 {
+        info!("{log_prefix} Adding page authentication to meta DB");
+        cxn.execute_batch("VACUUM")?;
+    }
+
+    Ok(())
+}
+
+/// Wraps `SQLITE_FCNTL_RESERVE_BYTES` on the main database of `cxn`.
+///
+/// If `n` is non-negative, the requested amount of reserved space is changed
+/// to `n`. Either way, the amount from before the call is returned.
+fn reserve_bytes(cxn: &rusqlite::Connection, n: c_int) -> Result<c_int, Error> {
+    let mut arg = n;
+    // Safety: The handle is valid for the lifetime of `cxn`, and this file
+    // control only accesses `arg` during the call.
+    let rc = unsafe {
+        libsqlite3_sys::sqlite3_file_control(
+            cxn.handle(),
+            c"main".as_ptr(),
+            libsqlite3_sys::SQLITE_FCNTL_RESERVE_BYTES,
+            (&mut arg as *mut c_int).cast(),
+        )
+    };
+    if 0 != rc {
+        return Err(Error::Sqlite(rc));
+    }
+
+    Ok(arg)
+}
+
 #[cfg(test)]
 mod test {
     use std::sync::Arc;
@@ -2643,35 +3796,189 @@ mod test {
             fixture.cxn.create_mailbox_hierarchy("foo/b%r", None),
         );
         assert_matches!(
-            Err(Error::MailboxExists),
-            fixture.cxn.create_mailbox_hierarchy("foo/bar", None),
+            Err(Error::MailboxExists),
+            fixture.cxn.create_mailbox_hierarchy("foo/bar", None),
+        );
+    }
+
+    #[test]
+    fn test_subscription_crud() {
+        let mut fixture = Fixture::new();
+
+        fixture.cxn.add_subscription("foo").unwrap();
+        fixture.cxn.add_subscription("bar").unwrap();
+        fixture.cxn.add_subscription("foo").unwrap();
+
+        fn sorted(mut v: Vec<String>) -> Vec<String> {
+            v.sort();
+            v
+        }
+
+        assert_eq!(
+            vec!["bar".to_owned(), "foo".to_owned()],
+            sorted(fixture.cxn.fetch_all_subscriptions().unwrap(),),
+        );
+
+        fixture.cxn.rm_subscription("foo").unwrap();
+        fixture.cxn.rm_subscription("quux").unwrap();
+
+        assert_eq!(
+            vec!["bar".to_owned()],
+            fixture.cxn.fetch_all_subscriptions().unwrap(),
+        );
+    }
+
+    #[test]
+    fn mailbox_retention_crud() {
+        let mut fixture = Fixture::new();
+
+        let foo = fixture
+            .cxn
+            .create_mailbox(MailboxId::ROOT, "foo", None)
+            .unwrap();
+        let bar = fixture
+            .cxn
+            .create_mailbox(MailboxId::ROOT, "bar", None)
+            .unwrap();
+        assert!(fixture
+            .cxn
+            .fetch_all_mailbox_retentions()
+            .unwrap()
+            .is_empty());
+
+        let by_age = MailboxRetention {
+            max_age_days: Some(30),
+            age_basis: RetentionAgeBasis::InternalDate,
+            max_messages: None,
+            only_seen: true,
+        };
+        let by_count = MailboxRetention {
+            max_messages: Some(100),
+            ..MailboxRetention::default()
+        };
+        fixture
+            .cxn
+            .set_mailbox_retention(foo, Some(&by_age))
+            .unwrap();
+        fixture
+            .cxn
+            .set_mailbox_retention(bar, Some(&by_age))
+            .unwrap();
+        fixture
+            .cxn
+            .set_mailbox_retention(bar, Some(&by_count))
+            .unwrap();
+        assert_eq!(
+            vec![(foo, by_age), (bar, by_count)],
+            fixture.cxn.fetch_all_mailbox_retentions().unwrap(),
+        );
+
+        fixture.cxn.set_mailbox_retention(foo, None).unwrap();
+        fixture.cxn.delete_mailbox(bar).unwrap();
+        assert!(fixture
+            .cxn
+            .fetch_all_mailbox_retentions()
+            .unwrap()
+            .is_empty());
+
+        assert_matches!(
+            Err(Error::NxMailbox),
+            fixture.cxn.set_mailbox_retention(bar, Some(&by_count)),
+        );
+    }
+
+    #[test]
+    fn metadata_crud() {
+        let mut fixture = Fixture::new();
+
+        let foo = fixture
+            .cxn
+            .create_mailbox(MailboxId::ROOT, "foo", None)
+            .unwrap();
+        assert!(fixture.cxn.fetch_metadata(foo).unwrap().is_empty());
+
+        fixture
+            .cxn
+            .set_metadata(
+                foo,
+                &[
+                    ("/private/comment".to_owned(), Some("hello".to_owned())),
+                    ("/shared/comment".to_owned(), Some("world".to_owned())),
+                ],
+                None,
+                2,
+            )
+            .unwrap();
+        fixture
+            .cxn
+            .set_metadata(
+                MailboxId::ROOT,
+                &[("/private/comment".to_owned(), Some("server".to_owned()))],
+                None,
+                2,
+            )
+            .unwrap();
+        assert_eq!(
+            vec![
+                ("/private/comment".to_owned(), "hello".to_owned()),
+                ("/shared/comment".to_owned(), "world".to_owned()),
+            ],
+            fixture.cxn.fetch_metadata(foo).unwrap(),
+        );
+
+        // Exceeding the limit changes nothing, even for the valid parts of
+        // the request.
+        let by_count = MailboxRetention {
+            max_messages: Some(100),
+            ..MailboxRetention::default()
+        };
+        assert_matches!(
+            Err(Error::TooManyMetadataEntries),
+            fixture.cxn.set_metadata(
+                foo,
+                &[
+                    ("/private/comment".to_owned(), None),
+                    ("/private/a".to_owned(), Some("a".to_owned())),
+                    ("/private/b".to_owned(), Some("b".to_owned())),
+                ],
+                Some(Some(&by_count)),
+                2,
+            ),
         );
-    }
-
-    #[test]
-    fn test_subscription_crud() {
-        let mut fixture = Fixture::new();
-
-        fixture.cxn.add_subscription("foo").unwrap();
-        fixture.cxn.add_subscription("bar").unwrap();
-        fixture.cxn.add_subscription("foo").unwrap();
-
-        fn sorted(mut v: Vec<String>) -> Vec<String> {
-            v.sort();
-            v
-        }
+        assert_eq!(2, fixture.cxn.fetch_metadata(foo).unwrap().len());
+        assert!(fixture
+            .cxn
+            .fetch_all_mailbox_retentions()
+            .unwrap()
+            .is_empty());
 
+        fixture
+            .cxn
+            .set_metadata(
+                foo,
+                &[("/private/comment".to_owned(), None)],
+                Some(Some(&by_count)),
+                2,
+            )
+            .unwrap();
         assert_eq!(
-            vec!["bar".to_owned(), "foo".to_owned()],
-            sorted(fixture.cxn.fetch_all_subscriptions().unwrap(),),
+            vec![("/shared/comment".to_owned(), "world".to_owned())],
+            fixture.cxn.fetch_metadata(foo).unwrap(),
+        );
+        assert_eq!(
+            vec![(foo, by_count)],
+            fixture.cxn.fetch_all_mailbox_retentions().unwrap(),
         );
 
-        fixture.cxn.rm_subscription("foo").unwrap();
-        fixture.cxn.rm_subscription("quux").unwrap();
-
+        fixture.cxn.delete_mailbox(foo).unwrap();
+        assert!(fixture.cxn.fetch_metadata(foo).unwrap().is_empty());
+        assert_matches!(
+            Err(Error::NxMailbox),
+            fixture.cxn.set_metadata(foo, &[], None, 2),
+        );
         assert_eq!(
-            vec!["bar".to_owned()],
-            fixture.cxn.fetch_all_subscriptions().unwrap(),
+            vec![("/private/comment".to_owned(), "server".to_owned())],
+            fixture.cxn.fetch_metadata(MailboxId::ROOT).unwrap(),
         );
     }
 
@@ -3761,6 +5068,27 @@ mod test {
                 1234,
             )
             .unwrap();
+
+        assert_eq!(
+            None,
+            fixture.cxn.fetch_message_preview(message_id).unwrap(),
+        );
+        fixture
+            .cxn
+            .cache_message_preview(message_id, "Hello world")
+            .unwrap();
+        assert_eq!(
+            Some("Hello world".to_owned()),
+            fixture.cxn.fetch_message_preview(message_id).unwrap(),
+        );
+        assert_eq!(
+            None,
+            fixture.cxn.fetch_message_preview(MessageId(-1)).unwrap(),
+        );
+        fixture
+            .cxn
+            .cache_message_preview(MessageId(-1), "Hello world")
+            .unwrap();
     }
 
     #[test]
@@ -3815,6 +5143,96 @@ mod test {
         }
     }
 
+    #[test]
+    fn unexpunge() {
+        let mut fixture = Fixture::new();
+
+        let inbox = fixture
+            .cxn
+            .create_mailbox(MailboxId::ROOT, "INBOX", None)
+            .unwrap();
+        let other = fixture
+            .cxn
+            .create_mailbox(MailboxId::ROOT, "other", None)
+            .unwrap();
+
+        let seen_deleted = SmallBitset::new_with_near(0b101);
+        fixture
+            .cxn
+            .intern_and_append_mailbox_messages(
+                inbox,
+                &mut [("a", Some(&seen_deleted)), ("b", None), ("c", None)]
+                    .iter()
+                    .copied(),
+            )
+            .unwrap();
+        // c is also in another mailbox, so it isn't restorable when expunged
+        // from INBOX.
+        fixture
+            .cxn
+            .copy_mailbox_messages(inbox, &mut [Uid::u(3)].into_iter(), other)
+            .unwrap();
+        fixture
+            .cxn
+            .expunge_mailbox_messages(
+                inbox,
+                &mut [Uid::u(1), Uid::u(2), Uid::u(3)].into_iter(),
+            )
+            .unwrap();
+
+        let restorable = fixture.cxn.fetch_unexpungeable_messages().unwrap();
+        assert_eq!(
+            vec![(inbox, Uid::u(1), 0b101), (inbox, Uid::u(2), 0)],
+            restorable
+                .iter()
+                .map(|m| (m.mailbox_id, m.uid, m.near_flags))
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!("a", restorable[0].path);
+
+        assert_eq!(
+            1,
+            fixture
+                .cxn
+                .unexpunge_mailbox_messages(
+                    inbox,
+                    &mut [Uid::u(1), Uid::u(3), Uid::u(99)].into_iter(),
+                )
+                .unwrap(),
+        );
+
+        let snapshot = fixture.cxn.select(inbox, false, None).unwrap();
+        assert_eq!(1, snapshot.messages.len());
+        assert_eq!(Uid::u(4), snapshot.messages[0].uid);
+        assert_eq!(
+            vec![0],
+            snapshot.messages[0].flags.iter().collect::<Vec<_>>(),
+        );
+
+        // The same expungement can't be undone again, even once the message
+        // is orphaned again.
+        fixture
+            .cxn
+            .expunge_mailbox_messages(inbox, &mut [Uid::u(4)].into_iter())
+            .unwrap();
+        assert_eq!(
+            0,
+            fixture
+                .cxn
+                .unexpunge_mailbox_messages(inbox, &mut [Uid::u(1)].into_iter())
+                .unwrap(),
+        );
+        let mut restorable = fixture
+            .cxn
+            .fetch_unexpungeable_messages()
+            .unwrap()
+            .into_iter()
+            .map(|m| m.uid)
+            .collect::<Vec<_>>();
+        restorable.sort();
+        assert_eq!(vec![Uid::u(2), Uid::u(4)], restorable);
+    }
+
     #[test]
     fn test_select() {
         let mut fixture = Fixture::new();
@@ -4759,4 +6177,426 @@ mod test {
                 .unwrap(),
         );
     }
+
+    #[test]
+    fn login_history() {
+        let mut fixture = Fixture::new();
+
+        assert!(!fixture.cxn.has_successful_login().unwrap());
+
+        let mut entry = LoginHistoryEntry {
+            at: DateTime::from_timestamp(1000, 0).unwrap(),
+            protocol: "IMAP".to_owned(),
+            success: false,
+            peer_ip: Some("192.0.2.3".to_owned()),
+            tls: None,
+            client_name: None,
+            client_id: None,
+            app_password: None,
+        };
+        fixture.cxn.insert_login_history(&entry).unwrap();
+        assert!(!fixture.cxn.has_successful_login().unwrap());
+        assert!(!fixture
+            .cxn
+            .is_known_login_device(Some("192.0.2.3"), None)
+            .unwrap());
+
+        entry.success = true;
+        entry.at = DateTime::from_timestamp(2000, 0).unwrap();
+        let id = fixture.cxn.insert_login_history(&entry).unwrap();
+        assert!(fixture.cxn.has_successful_login().unwrap());
+        assert!(fixture
+            .cxn
+            .is_known_login_device(Some("192.0.2.3"), None)
+            .unwrap());
+        assert!(!fixture
+            .cxn
+            .is_known_login_device(Some("192.0.2.4"), None)
+            .unwrap());
+
+        fixture
+            .cxn
+            .update_login_history_client(id, Some("Foo"), Some("name=Foo"))
+            .unwrap();
+        assert!(!fixture
+            .cxn
+            .is_known_login_device(Some("192.0.2.3"), None)
+            .unwrap());
+        assert!(fixture
+            .cxn
+            .is_known_login_device(Some("192.0.2.3"), Some("Foo"))
+            .unwrap());
+
+        let history = fixture.cxn.fetch_login_history(10).unwrap();
+        assert_eq!(2, history.len());
+        assert!(history[0].success);
+        assert_eq!(Some("Foo"), history[0].client_name.as_deref());
+        assert_eq!(Some("name=Foo"), history[0].client_id.as_deref());
+        assert!(!history[1].success);
+
+        for _ in 0..LOGIN_HISTORY_LIMIT {
+            fixture.cxn.insert_login_history(&entry).unwrap();
+        }
+        assert_eq!(
+            LOGIN_HISTORY_LIMIT as usize,
+            fixture.cxn.fetch_login_history(u32::MAX).unwrap().len(),
+        );
+    }
+
+    #[test]
+    fn migrate_with_uids() {
+        let mut fixture = Fixture::new();
+
+        let inbox = fixture
+            .cxn
+            .create_mailbox(MailboxId::ROOT, "INBOX", None)
+            .unwrap();
+        let sent = fixture
+            .cxn
+            .create_mailbox(
+                MailboxId::ROOT,
+                "Sent",
+                Some(MailboxAttribute::Sent),
+            )
+            .unwrap();
+
+        fixture
+            .cxn
+            .migrate_with_uids(&mut |emit| {
+                emit(UidMigrationEvent::ReserveMailboxIds { next: 2000 })?;
+                emit(UidMigrationEvent::Mailbox {
+                    path: "INBOX",
+                    special_use: None,
+                    uid_validity: Some(1000),
+                    next_uid: Uid::u(10),
+                })?;
+                emit(UidMigrationEvent::Message {
+                    path: "a",
+                    uid: Uid::u(3),
+                    flags: &[Flag::Seen],
+                })?;
+                emit(UidMigrationEvent::Message {
+                    path: "b",
+                    uid: Uid::u(7),
+                    flags: &[Flag::Keyword("foo".to_owned())],
+                })?;
+                emit(UidMigrationEvent::Mailbox {
+                    path: "Archive/2020",
+                    special_use: None,
+                    uid_validity: Some(1001),
+                    next_uid: Uid::u(1),
+                })?;
+                emit(UidMigrationEvent::Message {
+                    path: "c",
+                    uid: Uid::u(5),
+                    flags: &[],
+                })?;
+                // The UIDVALIDITY is already in use, so the mailbox keeps its
+                // ID.
+                emit(UidMigrationEvent::Mailbox {
+                    path: "Sent",
+                    special_use: Some(MailboxAttribute::Sent),
+                    uid_validity: Some(1000),
+                    next_uid: Uid::u(1),
+                })?;
+                emit(UidMigrationEvent::Subscription {
+                    path: "Archive/2020",
+                })?;
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(MailboxId(1000), fixture.cxn.find_mailbox("INBOX").unwrap());
+        assert!(fixture.cxn.fetch_mailbox(inbox).is_err());
+        assert_eq!(sent, fixture.cxn.find_mailbox("Sent").unwrap());
+        assert_eq!(
+            MailboxId(1001),
+            fixture.cxn.find_mailbox("Archive/2020").unwrap(),
+        );
+        assert_eq!(
+            vec!["Archive/2020".to_owned()],
+            fixture.cxn.fetch_all_subscriptions().unwrap(),
+        );
+
+        let snapshot = fixture.cxn.select(MailboxId(1000), true, None).unwrap();
+        assert_eq!(Uid::u(10), snapshot.next_uid);
+        assert_eq!(Modseq::of(MIGRATED_MODSEQ), snapshot.max_modseq);
+        assert_eq!(
+            vec![Uid::u(3), Uid::u(7)],
+            snapshot.messages.iter().map(|m| m.uid).collect::<Vec<_>>(),
+        );
+        assert!(snapshot.messages.iter().all(|m| !m.recent));
+        assert_eq!(1, snapshot.messages[0].flags.iter().count());
+
+        let snapshot = fixture.cxn.select(MailboxId(1001), true, None).unwrap();
+        assert_eq!(Uid::u(6), snapshot.next_uid);
+
+        // Archive was created implicitly during the migration and so was the
+        // first mailbox to get an ID past the reservation. New mailboxes never
+        // reuse the reserved IDs.
+        assert_eq!(
+            MailboxId(2000),
+            fixture.cxn.find_mailbox("Archive").unwrap(),
+        );
+        let new_mailbox = fixture
+            .cxn
+            .create_mailbox(MailboxId::ROOT, "new", None)
+            .unwrap();
+        assert_eq!(MailboxId(2001), new_mailbox);
+
+        // INBOX is no longer empty, so it can't be migrated into again.
+        assert_matches!(
+            Err(Error::MailboxExists),
+            fixture.cxn.migrate_with_uids(&mut |emit| {
+                emit(UidMigrationEvent::Mailbox {
+                    path: "INBOX",
+                    special_use: None,
+                    uid_validity: None,
+                    next_uid: Uid::u(1),
+                })
+            }),
+        );
+    }
+
+    #[test]
+    fn page_authentication() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("meta.sqlite.xex");
+        let log_prefix = LogPrefix::new("test".to_owned());
+        let xex = XexVfs::new(Arc::new(MasterKey::new())).unwrap();
+
+        // Create a database the way it was done before page authentication.
+        {
+            let mut cxn = rusqlite::Connection::open_with_flags_and_vfs(
+                &path,
+                rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE
+                    | rusqlite::OpenFlags::SQLITE_OPEN_CREATE,
+                xex.name(),
+            )
+            .unwrap();
+            cxn.pragma_update(None, "journal_mode", "PERSIST").unwrap();
+            super::super::db_migrations::apply_migrations(
+                &log_prefix,
+                &mut cxn,
+                "meta",
+                MIGRATIONS,
+            )
+            .unwrap();
+            assert_eq!(0, reserve_bytes(&cxn, -1).unwrap());
+        }
+
+        // Opening it converts it.
+        {
+            let mut cxn =
+                Connection::new(&log_prefix, path.clone(), &xex).unwrap();
+            assert_eq!(
+                page_mac::RESERVED_BYTES as c_int,
+                reserve_bytes(&cxn.cxn, -1).unwrap(),
+            );
+            assert!(cxn.integrity_check().unwrap().is_empty());
+        }
+
+        let page_size = 4096;
+        let before = fs::read(&path).unwrap();
+        {
+            let mut cxn =
+                Connection::new(&log_prefix, path.clone(), &xex).unwrap();
+            for i in 0..100 {
+                cxn.create_mailbox_hierarchy(&format!("mailbox{i}"), None)
+                    .unwrap();
+            }
+        }
+        let after = fs::read(&path).unwrap();
+
+        // A page from a later version of the database can't be spliced into
+        // an earlier one.
+        let changed_page = (1..before.len() / page_size)
+            .find(|&page| {
+                let range = page * page_size..(page + 1) * page_size;
+                before[range.clone()] != after[range]
+            })
+            .unwrap();
+        let mut spliced = before.clone();
+        spliced[changed_page * page_size..(changed_page + 1) * page_size]
+            .copy_from_slice(
+                &after
+                    [changed_page * page_size..(changed_page + 1) * page_size],
+            );
+        fs::write(&path, &spliced).unwrap();
+        assert_matches!(
+            Err(Error::DatabaseTampered),
+            Connection::new(&log_prefix, path.clone(), &xex)
+                .and_then(|mut cxn| cxn.integrity_check()),
+        );
+
+        // Nor can an earlier version of a page be spliced into a later
+        // database.
+        let mut spliced = after.clone();
+        spliced[changed_page * page_size..(changed_page + 1) * page_size]
+            .copy_from_slice(
+                &before
+                    [changed_page * page_size..(changed_page + 1) * page_size],
+            );
+        fs::write(&path, &spliced).unwrap();
+        assert_matches!(
+            Err(Error::DatabaseTampered),
+            Connection::new(&log_prefix, path.clone(), &xex)
+                .and_then(|mut cxn| cxn.integrity_check()),
+        );
+
+        // Flipping a bit is detected.
+        let mut flipped = after;
+        flipped[page_size + 200] ^= 1;
+        fs::write(&path, &flipped).unwrap();
+        assert_matches!(
+            Err(Error::DatabaseTampered),
+            Connection::new(&log_prefix, path.clone(), &xex)
+                .and_then(|mut cxn| cxn.integrity_check()),
+        );
+    }
+
+    #[test]
+    fn stale_journal_detected() {
+        let tmpdir = TempDir::new().unwrap();
+        let copydir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("meta.sqlite.xex");
+        let journal_path = tmpdir.path().join("meta.sqlite.xex-journal");
+        let copy_path = copydir.path().join("meta.sqlite.xex");
+        let copy_journal_path = copydir.path().join("meta.sqlite.xex-journal");
+        let log_prefix = LogPrefix::new("test".to_owned());
+        let xex = XexVfs::new(Arc::new(MasterKey::new())).unwrap();
+
+        Connection::new(&log_prefix, path.clone(), &xex).unwrap();
+
+        // Capture the state of the database in the middle of a transaction
+        // which is large enough to spill pages into the file before commit.
+        let journal = {
+            let mut cxn = rusqlite::Connection::open_with_flags_and_vfs(
+                &path,
+                rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE,
+                xex.name(),
+            )
+            .unwrap();
+            cxn.pragma_update(None, "journal_mode", "DELETE").unwrap();
+            cxn.pragma_update(None, "cache_size", 10).unwrap();
+            cxn.execute("CREATE TABLE t (x)", ()).unwrap();
+
+            let txn = cxn.transaction().unwrap();
+            for _ in 0..100 {
+                txn.execute("INSERT INTO t (x) VALUES (randomblob(2000))", ())
+                    .unwrap();
+            }
+            fs::copy(&path, &copy_path).unwrap();
+            fs::copy(&journal_path, &copy_journal_path).unwrap();
+            let journal = fs::read(&journal_path).unwrap();
+            txn.commit().unwrap();
+            journal
+        };
+
+        // A genuine crash at that point is recovered from.
+        {
+            let cxn = rusqlite::Connection::open_with_flags_and_vfs(
+                &copy_path,
+                rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE,
+                xex.name(),
+            )
+            .unwrap();
+            assert_eq!(
+                "ok",
+                cxn.query_row("PRAGMA integrity_check", (), |row| {
+                    row.get::<_, String>(0)
+                })
+                .unwrap(),
+            );
+            assert_eq!(
+                0,
+                cxn.query_row("SELECT COUNT(*) FROM t", (), |row| {
+                    row.get::<_, i64>(0)
+                })
+                .unwrap(),
+            );
+        }
+
+        {
+            let mut cxn =
+                Connection::new(&log_prefix, path.clone(), &xex).unwrap();
+            for i in 0..100 {
+                cxn.create_mailbox_hierarchy(&format!("mailbox{i}"), None)
+                    .unwrap();
+            }
+        }
+
+        // But replaying the old journal over the later database is not.
+        fs::write(&journal_path, &journal).unwrap();
+        assert_matches!(
+            Err(Error::DatabaseTampered),
+            Connection::new(&log_prefix, path.clone(), &xex)
+                .and_then(|mut cxn| cxn.integrity_check()),
+        );
+    }
+
+    #[test]
+    fn page_versions_shared_between_connections() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("meta.sqlite.xex");
+        let versions_path = tmpdir.path().join("meta.sqlite.xex-versions");
+        let log_prefix = LogPrefix::new("test".to_owned());
+        let xex = XexVfs::new(Arc::new(MasterKey::new())).unwrap();
+
+        Connection::new(&log_prefix, path.clone(), &xex).unwrap();
+
+        let open = || {
+            rusqlite::Connection::open_with_flags_and_vfs(
+                &path,
+                rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE,
+                xex.name(),
+            )
+            .unwrap()
+        };
+        // Unlike COUNT(*), this only needs to read a few pages.
+        let count = |cxn: &rusqlite::Connection| {
+            cxn.query_row("SELECT MAX(rowid) FROM t", (), |row| {
+                row.get::<_, i64>(0)
+            })
+            .unwrap()
+        };
+        let full_scans =
+            || super::super::sqlite_xex_vfs::FULL_SCANS.with(|n| n.get());
+
+        let writer = open();
+        writer.execute("CREATE TABLE t (x)", ()).unwrap();
+        writer
+            .execute(
+                "WITH RECURSIVE n (i) AS \
+                 (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000) \
+                 INSERT INTO t (x) SELECT randomblob(3000) FROM n",
+                (),
+            )
+            .unwrap();
+        assert!(fs::metadata(&path).unwrap().len() > 2000 * 4096);
+
+        let reader = open();
+        assert_eq!(2000, count(&reader));
+
+        // Each commit by the writer gives the reader a new page 1, whose
+        // versions it gets from the `-versions` file instead of reading every
+        // page.
+        let scans = full_scans();
+        for i in 1..=20 {
+            writer
+                .execute("INSERT INTO t (x) VALUES (randomblob(3000))", ())
+                .unwrap();
+            assert_eq!(2000 + i, count(&reader));
+        }
+        assert_eq!(scans, full_scans());
+
+        // If the file doesn't match page 1, the versions are read from the
+        // pages.
+        writer
+            .execute("INSERT INTO t (x) VALUES (randomblob(3000))", ())
+            .unwrap();
+        fs::write(&versions_path, [0u8; 64]).unwrap();
+        assert_eq!(2021, count(&reader));
+        assert_eq!(scans + 1, full_scans());
+    }
 }
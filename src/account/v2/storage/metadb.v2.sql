@@ -0,0 +1,46 @@
+---
+-- Copyright (c) 2024, Jason Lingle
+--
+-- This file is part of Crymap.
+--
+-- Crymap is free software: you can  redistribute it and/or modify it under the
+-- terms of  the GNU General Public  License as published by  the Free Software
+-- Foundation, either version  3 of the License, or (at  your option) any later
+-- version.
+--
+-- Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+-- WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+-- FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+-- details.
+--
+-- You should have received a copy of the GNU General Public License along with
+-- Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+-- Records login attempts against this account.
+--
+-- Successful logins are recorded directly by the process that logged in.
+-- Failed logins cannot access this database, so they are queued (encrypted)
+-- in the delivery database and moved here on the next successful login.
+CREATE TABLE `login_history` (
+  `id` INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  -- The UNIX time at which the login was attempted.
+  `at` INTEGER NOT NULL,
+  -- The protocol through which the login was attempted, e.g. "IMAP".
+  `protocol` TEXT NOT NULL,
+  -- Whether the login succeeded.
+  `success` INTEGER NOT NULL,
+  -- The IP address of the client, if known.
+  `peer_ip` TEXT,
+  -- A description of the TLS session (version, cipher, strength), if any.
+  `tls` TEXT,
+  -- The "name" field from the RFC 2971 `ID` command, if any.
+  `client_name` TEXT,
+  -- All fields from the RFC 2971 `ID` command, if any.
+  `client_id` TEXT,
+  -- The name of the application-specific password used, if any.
+  `app_password` TEXT
+) STRICT;
+
+-- Used to determine whether a client/IP combination has logged in before.
+CREATE INDEX `login_history_device`
+ON `login_history` (`peer_ip`, `client_name`) WHERE `success`;
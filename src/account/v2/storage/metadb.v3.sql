@@ -0,0 +1,36 @@
+---
+-- Copyright (c) 2024, Jason Lingle
+--
+-- This file is part of Crymap.
+--
+-- Crymap is free software: you can  redistribute it and/or modify it under the
+-- terms of  the GNU General Public  License as published by  the Free Software
+-- Foundation, either version  3 of the License, or (at  your option) any later
+-- version.
+--
+-- Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+-- WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+-- FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+-- details.
+--
+-- You should have received a copy of the GNU General Public License along with
+-- Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+-- Remember what each expunged mailbox message was so that it can be put back
+-- (`XCRY UNEXPUNGE`) for as long as the message itself still exists.
+--
+-- These are all NULL for expungements recorded before this migration.
+-- `message_id` is set back to NULL once the message has been restored.
+--
+-- There is deliberately no foreign key on `message_id`: orphaned messages are
+-- forgotten without regard to expungement records. Message IDs are never
+-- reused, so a stale ID simply never matches anything.
+ALTER TABLE `mailbox_message_expungement` ADD COLUMN `message_id` INTEGER;
+-- The `near_flags` the message had when it was expunged.
+ALTER TABLE `mailbox_message_expungement` ADD COLUMN `near_flags` INTEGER;
+-- The UNIX time at which the message was expunged.
+ALTER TABLE `mailbox_message_expungement` ADD COLUMN `expunged_at` INTEGER;
+
+CREATE INDEX `mailbox_message_expungement_message_id`
+ON `mailbox_message_expungement` (`message_id`)
+WHERE `message_id` IS NOT NULL;
@@ -0,0 +1,32 @@
+---
+-- Copyright (c) 2024, Jason Lingle
+--
+-- This file is part of Crymap.
+--
+-- Crymap is free software: you can  redistribute it and/or modify it under the
+-- terms of  the GNU General Public  License as published by  the Free Software
+-- Foundation, either version  3 of the License, or (at  your option) any later
+-- version.
+--
+-- Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+-- WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+-- FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+-- details.
+--
+-- You should have received a copy of the GNU General Public License along with
+-- Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+-- Per-mailbox rules for automatically expunging old messages. These are
+-- enforced by the daily maintenance.
+CREATE TABLE `mailbox_retention` (
+  `mailbox_id` INTEGER NOT NULL PRIMARY KEY,
+  -- If set, messages older than this many days are expunged.
+  `max_age_days` INTEGER,
+  -- What `max_age_days` is measured against: 'savedate' or 'internaldate'.
+  `age_basis` TEXT NOT NULL DEFAULT 'savedate',
+  -- If set, only this many messages (those with the greatest UIDs) are kept.
+  `max_messages` INTEGER,
+  -- If true, messages without the \Seen flag are never expunged.
+  `only_seen` INTEGER NOT NULL DEFAULT FALSE,
+  FOREIGN KEY (`mailbox_id`) REFERENCES `mailbox` (`id`) ON DELETE RESTRICT
+) STRICT;
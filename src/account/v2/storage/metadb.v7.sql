@@ -0,0 +1,64 @@
+---
+-- Copyright (c) 2024, Jason Lingle
+--
+-- This file is part of Crymap.
+--
+-- Crymap is free software: you can  redistribute it and/or modify it under the
+-- terms of  the GNU General Public  License as published by  the Free Software
+-- Foundation, either version  3 of the License, or (at  your option) any later
+-- version.
+--
+-- Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+-- WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+-- FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+-- details.
+--
+-- You should have received a copy of the GNU General Public License along with
+-- Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+-- Virtual mailboxes. A virtual mailbox has no `mailbox_message` rows of its
+-- own; its contents are the messages in the other mailboxes which match the
+-- stored search query.
+CREATE TABLE `virtual_mailbox` (
+  `mailbox_id` INTEGER NOT NULL PRIMARY KEY,
+  -- The search query, as a CBOR-encoded list of `SearchQuery` values which
+  -- are ANDed together.
+  `query` BLOB NOT NULL,
+  FOREIGN KEY (`mailbox_id`) REFERENCES `mailbox` (`id`) ON DELETE RESTRICT
+) STRICT;
+
+-- The UIDs assigned within virtual mailboxes. Each UID refers to one message
+-- (i.e. a `mailbox_message` row) in one of the real mailboxes the virtual
+-- mailbox draws from.
+--
+-- A row is removed when the message stops being part of the virtual mailbox,
+-- so that if it comes back, it gets a new UID greater than any seen before.
+-- UIDs come from `next_uid` of the virtual mailbox itself.
+--
+-- As with `mailbox_message_expungement`, there is deliberately no foreign key
+-- on the source message; rows for expunged messages are pruned the next time
+-- the virtual mailbox is selected.
+CREATE TABLE `virtual_mailbox_message` (
+  `mailbox_id` INTEGER NOT NULL,
+  `uid` INTEGER NOT NULL,
+  `source_mailbox_id` INTEGER NOT NULL,
+  `source_uid` INTEGER NOT NULL,
+  PRIMARY KEY (`mailbox_id`, `uid`),
+  UNIQUE (`mailbox_id`, `source_mailbox_id`, `source_uid`),
+  FOREIGN KEY (`mailbox_id`) REFERENCES `virtual_mailbox` (`mailbox_id`)
+    ON DELETE RESTRICT
+) STRICT;
+
+-- How far each real mailbox has been checked against the query of a virtual
+-- mailbox. A message in `source_mailbox_id` whose modseq is not greater than
+-- `modseq` is in the virtual mailbox if and only if it has a row in
+-- `virtual_mailbox_message`, so only messages changed since need to be
+-- checked again.
+CREATE TABLE `virtual_mailbox_source` (
+  `mailbox_id` INTEGER NOT NULL,
+  `source_mailbox_id` INTEGER NOT NULL,
+  `modseq` INTEGER NOT NULL,
+  PRIMARY KEY (`mailbox_id`, `source_mailbox_id`),
+  FOREIGN KEY (`mailbox_id`) REFERENCES `virtual_mailbox` (`mailbox_id`)
+    ON DELETE RESTRICT
+) STRICT;
@@ -27,12 +27,14 @@
 
 mod db_migrations;
 mod deliverydb;
+mod loginthrottledb;
 mod messages;
 mod metadb;
 mod sqlite_xex_vfs;
 mod types;
 
 pub use deliverydb::Connection as DeliveryDb;
+pub use loginthrottledb::Connection as LoginThrottleDb;
 pub use messages::MessageStore;
 pub use metadb::{message_summary_values, Connection as MetaDb};
 pub use sqlite_xex_vfs::XexVfs;
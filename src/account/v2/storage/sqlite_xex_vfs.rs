@@ -17,25 +17,67 @@
 // Crymap. If not, see <http://www.gnu.org/licenses/>.
 
 //! The SQLite XEX encryption shim VFS layer.
+//!
+//! Besides encrypting every file with XEX, the main database file is
+//! authenticated page by page (see `crypt::page_mac`) once its header declares
+//! `page_mac::RESERVED_BYTES` of reserved space per page. A page which fails
+//! authentication produces `SQLITE_IOERR_DATA`, which surfaces as
+//! `Error::DatabaseTampered`. Databases without the reserved space (i.e. those
+//! created before this existed, and backups thereof) are read and written
+//! with plain XEX; `MetaDb` converts them when it opens them.
+//!
+//! Page 1 carries a digest of the versions of all other pages. The VFS keeps
+//! those versions in memory, checking them against page 1 whenever a new
+//! page 1 is seen, and only accepts a page if it has exactly the version page
+//! 1 expects. When SQLite writes page 1 in a transaction, its trailer is
+//! finalised when the database file is synced (or unlocked), once all the
+//! other pages of the transaction have been written.
+//!
+//! Finding the versions when another connection has committed would mean
+//! reading the trailer of every page, so the connection which commits also
+//! writes the versions to a `-versions` file next to the database. The file
+//! is only a cache: it is used if page 1's digest matches it, and otherwise
+//! the versions are read from the pages themselves.
+//!
+//! SQLite rolls transactions back by writing the pages saved in the rollback
+//! journal back into the file. Page images written to the journal are sealed
+//! with the trailer the page had as of the last commit, and pages whose
+//! trailer is already valid are written back unchanged rather than being
+//! sealed anew. Restoring an old page therefore can't launder it: it is only
+//! accepted if it is what the last commit had, or if page 1 turns out to
+//! vouch for it once the rollback is complete. This is what lets a stale
+//! journal replayed over a later database be detected.
 
 use std::cell::UnsafeCell;
 use std::convert::TryFrom;
 use std::ffi::{CStr, CString, OsStr};
+use std::fs;
+use std::io;
 use std::mem::{self, ManuallyDrop};
 use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
 use std::path::Path;
 use std::ptr;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use libsqlite3_sys::*;
 use log::error;
 
 use crate::{
-    crypt::{master_key::MasterKey, xex},
+    crypt::{master_key::MasterKey, page_mac, xex, AES_BLOCK},
     support::error::Error,
 };
 
+#[cfg(test)]
+thread_local! {
+    /// The number of times `PageAuth::adopt_header` had to read the versions
+    /// from the pages themselves on this thread.
+    pub(super) static FULL_SCANS: std::cell::Cell<u64> =
+        const { std::cell::Cell::new(0) };
+}
+
 /// A SQLite VFS layer for XEX encryption.
 ///
 /// Each `XexVfs` instance holds on to a single `MasterKey` which is used for
@@ -172,10 +214,491 @@ struct File {
     delegate_file: *mut sqlite3_file,
     delegate_vfs: *mut sqlite3_vfs,
     xex: xex::Xex,
+    /// Page authentication state. Only present for the main database file.
+    auth: Option<PageAuth>,
+    /// For the rollback journal of a main database, that database's file.
+    /// Null for other files.
+    main_db: *mut File,
     /// Ensure the VFS does not get dropped while the file is open.
     vfs_wrapper: Arc<VfsWrapper>,
 }
 
+struct PageAuth {
+    mac: page_mac::PageMac,
+    /// The page size, once a header declaring our reserved bytes has been
+    /// seen. Pages are only authenticated once this is set, and it is never
+    /// unset, so that a file can't be downgraded while it is open.
+    page_size: Option<usize>,
+    /// The version of page 1 as last read or written.
+    db_version: u64,
+    /// The version given to every page written in the current write
+    /// transaction. Chosen on the first write and cleared when the file drops
+    /// below a `RESERVED` lock.
+    txn_version: Option<u64>,
+    /// The trailer of the page 1 that `committed` was checked against or
+    /// made for.
+    verified: Option<page_mac::Trailer>,
+    /// The versions of the pages after page 1 (index 0 being page 2) which
+    /// `verified` vouches for. `None` until page 1 has been verified.
+    committed: Option<Vec<u64>>,
+    /// `committed` updated with the pages written since. `None` if a page was
+    /// rolled back to a version neither accounts for, in which case page 1
+    /// must vouch for the versions in the file before any more are sealed.
+    versions: Option<Vec<u64>>,
+    /// The state of page 1 if it has been written in the current transaction
+    /// and its trailer is not yet final.
+    pending_header: Option<PendingHeader>,
+    /// The `-versions` file, if it could be opened.
+    version_file: Option<VersionFile>,
+    /// Scratch space for sealing pages and re-reading page 1.
+    buf: Vec<u8>,
+}
+
+enum PendingHeader {
+    /// SQLite wrote new content, which still needs to be sealed with the
+    /// digest of the final versions of the other pages.
+    Modified,
+    /// SQLite restored an earlier page 1 with this valid trailer, which must
+    /// agree with the other pages once the rollback is complete.
+    Restored(page_mac::Trailer),
+}
+
+impl PageAuth {
+    /// If `header` is the start of a SQLite database whose pages have our
+    /// reserved bytes, starts authenticating pages of the size it declares.
+    ///
+    /// Returns an error if authentication is already in effect but the header
+    /// no longer declares the reserved bytes.
+    fn observe_header(&mut self, header: &[u8]) -> Result<(), c_int> {
+        if header.len() <= 20 || !header.starts_with(b"SQLite format 3\0") {
+            return Ok(());
+        }
+
+        let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+            1 => 65536,
+            n => usize::from(n),
+        };
+        if usize::from(header[20]) == page_mac::RESERVED_BYTES
+            && page_size > page_mac::RESERVED_BYTES
+        {
+            self.page_size = Some(page_size);
+            Ok(())
+        } else if self.page_size.is_some() {
+            error!("SQLite header no longer declares page authentication");
+            Err(SQLITE_IOERR_DATA)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// If `len` bytes at `offset` are exactly one page and authentication is
+    /// in effect, returns the 1-based page number.
+    fn page_number(&self, offset: u64, len: usize) -> Option<u64> {
+        let page_size = self.page_size?;
+        (len == page_size && 0 == offset % page_size as u64)
+            .then(|| offset / page_size as u64 + 1)
+    }
+
+    /// Returns the version to give pages written in the current transaction.
+    fn txn_version(&mut self) -> u64 {
+        let db_version = self.db_version;
+        *self.txn_version.get_or_insert_with(|| {
+            // Using the clock keeps versions increasing even if page 1 was
+            // torn by a crash and had to be restored from the journal.
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_micros() as u64)
+                .unwrap_or(0);
+            (db_version + 1).max(now)
+        })
+    }
+
+    /// Returns the version page 1 expects page `page_number` (which is not 1)
+    /// to have, if known.
+    fn expected_version(&self, page_number: u64) -> Option<u64> {
+        self.versions
+            .as_ref()?
+            .get(version_index(page_number))
+            .copied()
+    }
+
+    /// Returns the trailer page `page_number` had as of the last commit, if
+    /// known.
+    fn committed_trailer(&self, page_number: u64) -> Option<page_mac::Trailer> {
+        if 1 == page_number {
+            self.verified
+        } else {
+            self.committed
+                .as_ref()?
+                .get(version_index(page_number))
+                .copied()
+                .map(page_mac::Trailer::page)
+        }
+    }
+
+    /// Handles `trailer`, the valid trailer of page 1 as just read.
+    fn check_header(
+        &mut self,
+        trailer: &page_mac::Trailer,
+        xex: &mut xex::Xex,
+        backing: &mut DelegateBacking,
+    ) -> Result<(), c_int> {
+        if self.pending_header.is_some() {
+            // Page 1 was written by the current transaction and its trailer
+            // isn't final yet.
+            return Ok(());
+        }
+
+        if trailer.version < self.db_version {
+            error!(
+                "SQLite page 1 went back from version {} to {}",
+                self.db_version, trailer.version,
+            );
+            return Err(SQLITE_IOERR_DATA);
+        }
+
+        self.db_version = trailer.version;
+        if Some(trailer) == self.verified.as_ref() {
+            return Ok(());
+        }
+
+        self.adopt_header(trailer, xex, backing)
+    }
+
+    /// Makes `trailer`, that of the page 1 currently in the file, the basis
+    /// for authenticating the other pages, after checking that it vouches for
+    /// the versions they have in the file.
+    fn adopt_header(
+        &mut self,
+        trailer: &page_mac::Trailer,
+        xex: &mut xex::Xex,
+        backing: &mut DelegateBacking,
+    ) -> Result<(), c_int> {
+        let Some(page_size) = self.page_size.map(|p| p as u64) else {
+            return Ok(());
+        };
+
+        // The versions aren't authenticated here, but if any are wrong, the
+        // digest won't match, and reading that page will fail anyway unless
+        // it actually has that version.
+        let page_count = xex::Backing::len(backing)? / page_size;
+        let cached = self
+            .version_file
+            .as_mut()
+            .and_then(VersionFile::load)
+            .filter(|versions| {
+                versions.len() as u64 == page_count.saturating_sub(1)
+                    && self.mac.digest(versions) == trailer.digest
+            });
+        let versions = match cached {
+            Some(versions) => versions,
+            None => {
+                #[cfg(test)]
+                FULL_SCANS.with(|n| n.set(n.get() + 1));
+
+                let mut page_trailer = [0u8; page_mac::RESERVED_BYTES];
+                let mut versions = Vec::new();
+                for page_number in 2..=page_count {
+                    xex.read(
+                        backing,
+                        &mut page_trailer,
+                        page_number * page_size
+                            - page_mac::RESERVED_BYTES as u64,
+                    )?;
+                    versions
+                        .push(page_mac::unauthenticated_version(&page_trailer));
+                }
+                versions
+            },
+        };
+
+        if self.mac.digest(&versions) != trailer.digest {
+            error!(
+                "SQLite page 1 (version {}) does not match the versions of \
+                 the other pages",
+                trailer.version,
+            );
+            return Err(SQLITE_IOERR_DATA);
+        }
+
+        self.db_version = trailer.version;
+        self.verified = Some(*trailer);
+        self.committed = Some(versions.clone());
+        self.versions = Some(versions);
+        Ok(())
+    }
+
+    /// Reads and checks page 1 from the file.
+    fn reload_header(
+        &mut self,
+        xex: &mut xex::Xex,
+        backing: &mut DelegateBacking,
+    ) -> Result<(), c_int> {
+        let Some(page_size) = self.page_size else {
+            return Ok(());
+        };
+
+        self.buf.resize(page_size, 0);
+        xex.read(backing, &mut self.buf, 0)?;
+        let Some(trailer) = self.mac.open(&self.buf, 1) else {
+            error!("SQLite page 1 failed authentication");
+            return Err(SQLITE_IOERR_DATA);
+        };
+
+        self.check_header(&trailer, xex, backing)
+    }
+
+    /// Fills in the trailer of the page in `buf`, which is about to be
+    /// written as page `page_number`, and updates `versions` to match.
+    fn prepare_write(&mut self, page_number: u64) {
+        let existing = self.mac.open(&self.buf, page_number);
+
+        if 1 == page_number {
+            if let Some(trailer) = existing {
+                self.pending_header = Some(PendingHeader::Restored(trailer));
+            } else {
+                // This is only a placeholder; `flush_header` seals it again
+                // once the digest is known.
+                let version = self.txn_version();
+                self.mac.seal(
+                    &mut self.buf,
+                    1,
+                    &page_mac::Trailer::page(version),
+                );
+                self.pending_header = Some(PendingHeader::Modified);
+            }
+            return;
+        }
+
+        let Some(existing) = existing else {
+            let version = self.txn_version();
+            self.mac.seal(
+                &mut self.buf,
+                page_number,
+                &page_mac::Trailer::page(version),
+            );
+            if let Some(ref mut versions) = self.versions {
+                set_version(versions, page_number, version);
+            }
+            return;
+        };
+
+        // A page which is already valid is either one SQLite didn't actually
+        // change or one it is restoring from the journal. Either way, it's
+        // written as-is rather than given a new version, which would make
+        // whatever version it came from current.
+        let ix = version_index(page_number);
+        if self.versions.as_ref().and_then(|v| v.get(ix))
+            == Some(&existing.version)
+        {
+            return;
+        }
+
+        if self.committed.as_ref().and_then(|v| v.get(ix))
+            == Some(&existing.version)
+        {
+            // Back to what the last commit had
+            if let Some(ref mut versions) = self.versions {
+                set_version(versions, page_number, existing.version);
+            }
+            return;
+        }
+
+        // Some other version. This is legitimate if page 1 is being restored
+        // to a state which includes it (e.g. when rolling back a hot journal
+        // left by another process), which `flush_header` will check.
+        self.versions = None;
+    }
+
+    /// Finalises page 1 if it was written in the current transaction.
+    fn flush_header(
+        &mut self,
+        xex: &mut xex::Xex,
+        backing: &mut DelegateBacking,
+    ) -> Result<(), c_int> {
+        let (Some(pending), Some(page_size)) =
+            (self.pending_header.take(), self.page_size)
+        else {
+            return Ok(());
+        };
+
+        // The file still holds an exclusive lock here, so nothing else can be
+        // reading the `-versions` file while it is rewritten.
+        match pending {
+            PendingHeader::Restored(trailer) => {
+                self.adopt_header(&trailer, xex, backing)?;
+                self.store_versions();
+                Ok(())
+            },
+
+            PendingHeader::Modified => {
+                let Some(ref mut versions) = self.versions else {
+                    error!(
+                        "SQLite pages were rolled back to versions the \
+                         database does not account for"
+                    );
+                    return Err(SQLITE_IOERR_DATA);
+                };
+
+                let page_count = xex::Backing::len(backing)? / page_size as u64;
+                versions.resize(page_count.saturating_sub(1) as usize, 0);
+                let trailer = page_mac::Trailer {
+                    version: self.txn_version.unwrap_or(self.db_version),
+                    digest: self.mac.digest(versions),
+                };
+
+                self.buf.resize(page_size, 0);
+                xex.read(backing, &mut self.buf, 0)?;
+                self.mac.seal(&mut self.buf, 1, &trailer);
+                xex.write(backing, &self.buf, 0)?;
+
+                self.db_version = trailer.version;
+                self.verified = Some(trailer);
+                self.committed = self.versions.clone();
+                self.store_versions();
+                Ok(())
+            },
+        }
+    }
+
+    /// Writes `committed` to the `-versions` file.
+    fn store_versions(&mut self) {
+        if let (Some(version_file), Some(committed)) =
+            (self.version_file.as_mut(), self.committed.as_ref())
+        {
+            // Failure only means other connections have to do a full scan.
+            let _ = version_file.store(committed);
+        }
+    }
+
+    /// Called when the file is truncated to `size` bytes.
+    fn truncate(&mut self, size: u64) {
+        if let (Some(page_size), Some(ref mut versions)) =
+            (self.page_size, self.versions.as_mut())
+        {
+            let page_count = size / page_size as u64;
+            versions.truncate(page_count.saturating_sub(1) as usize);
+        }
+    }
+}
+
+/// The `-versions` file kept next to an authenticated database.
+///
+/// It is encrypted with XEX like everything else and holds the number of
+/// versions followed by the versions themselves, zero-padded to a whole
+/// block. Its content isn't authenticated, since `PageAuth::adopt_header`
+/// checks it against page 1 anyway.
+struct VersionFile {
+    file: fs::File,
+    xex: xex::Xex,
+}
+
+impl VersionFile {
+    /// Opens the `-versions` file for the main database at `db_path`, whose
+    /// file name is `db_name`, creating it unless `read_only`.
+    fn open(
+        master_key: &MasterKey,
+        db_path: &Path,
+        db_name: &str,
+        read_only: bool,
+    ) -> Option<Self> {
+        let name = format!("{db_name}-versions");
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(!read_only)
+            .create(!read_only)
+            .mode(0o600)
+            .open(db_path.with_file_name(&name))
+            .ok()?;
+        let xex = xex::Xex::new(master_key, &name).ok()?;
+        Some(Self { file, xex })
+    }
+
+    /// Reads the versions from the file, returning `None` if it is missing or
+    /// malformed.
+    fn load(&mut self) -> Option<Vec<u64>> {
+        let len = usize::try_from(self.file.metadata().ok()?.len()).ok()?;
+        if 0 == len || 0 != len % AES_BLOCK {
+            return None;
+        }
+
+        let mut data = vec![0u8; len];
+        self.xex
+            .read(&mut FileBacking(&self.file), &mut data, 0)
+            .ok()?;
+
+        let (count, data) = data.split_at(8);
+        let count =
+            usize::try_from(u64::from_le_bytes(count.try_into().unwrap()))
+                .ok()?;
+        if count > len / 8 || versions_file_len(count) != len {
+            return None;
+        }
+
+        Some(
+            data.chunks_exact(8)
+                .take(count)
+                .map(|v| u64::from_le_bytes(v.try_into().unwrap()))
+                .collect(),
+        )
+    }
+
+    /// Replaces the content of the file with `versions`.
+    fn store(&mut self, versions: &[u64]) -> io::Result<()> {
+        let len = versions_file_len(versions.len());
+        let mut data = Vec::with_capacity(len);
+        data.extend_from_slice(&(versions.len() as u64).to_le_bytes());
+        for version in versions {
+            data.extend_from_slice(&version.to_le_bytes());
+        }
+        data.resize(len, 0);
+
+        self.xex.write(&mut FileBacking(&self.file), &data, 0)?;
+        self.file.set_len(len as u64)
+    }
+}
+
+/// Returns the length of a `-versions` file holding `count` versions.
+fn versions_file_len(count: usize) -> usize {
+    (8 + 8 * count).next_multiple_of(AES_BLOCK)
+}
+
+struct FileBacking<'a>(&'a fs::File);
+
+impl xex::Backing for FileBacking<'_> {
+    type Error = io::Error;
+
+    fn read(&mut self, dst: &mut [u8], offset: u64) -> io::Result<()> {
+        self.0.read_exact_at(dst, offset)
+    }
+
+    fn write(&mut self, src: &[u8], offset: u64) -> io::Result<()> {
+        self.0.write_all_at(src, offset)
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        self.0.metadata().map(|md| md.len())
+    }
+
+    fn encryption_error() -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, "XEX failed")
+    }
+}
+
+/// Returns the index of page `page_number` (which must not be 1) into the
+/// version tables of `PageAuth`.
+fn version_index(page_number: u64) -> usize {
+    (page_number - 2) as usize
+}
+
+fn set_version(versions: &mut Vec<u64>, page_number: u64, version: u64) {
+    let ix = version_index(page_number);
+    if versions.len() <= ix {
+        versions.resize(ix + 1, 0);
+    }
+    versions[ix] = version;
+}
+
 impl Drop for VfsWrapper {
     fn drop(&mut self) {
         unsafe {
@@ -285,13 +808,11 @@ unsafe extern "C" fn vfs_open(
         return SQLITE_IOERR_CONVPATH;
     };
 
-    let Some(mut name_str) = CStr::from_ptr(name.cast())
-        .to_str()
-        .ok()
-        .map(Path::new)
-        .and_then(Path::file_name)
-        .and_then(OsStr::to_str)
-    else {
+    let Ok(path_str) = CStr::from_ptr(name.cast()).to_str() else {
+        return SQLITE_IOERR_CONVPATH;
+    };
+    let path = Path::new(path_str);
+    let Some(mut name_str) = path.file_name().and_then(OsStr::to_str) else {
         return SQLITE_IOERR_CONVPATH;
     };
 
@@ -305,6 +826,31 @@ unsafe extern "C" fn vfs_open(
             return SQLITE_IOERR_AUTH;
         },
     };
+    let auth = (0 != flags & SQLITE_OPEN_MAIN_DB).then(|| PageAuth {
+        mac: page_mac::PageMac::new(&app_data.master_key, name_str),
+        page_size: None,
+        db_version: 0,
+        txn_version: None,
+        verified: None,
+        committed: None,
+        versions: None,
+        pending_header: None,
+        version_file: VersionFile::open(
+            &app_data.master_key,
+            path,
+            name_str,
+            0 != flags & SQLITE_OPEN_READONLY,
+        ),
+        buf: Vec::new(),
+    });
+
+    let main_db = if 0 != flags & SQLITE_OPEN_MAIN_JOURNAL {
+        // The main database is always opened through this VFS as well, so
+        // this is one of our files.
+        sqlite3_database_file_object(name).cast::<File>()
+    } else {
+        ptr::null_mut()
+    };
 
     let child_file: *mut sqlite3_file =
         sqlite3_malloc((*app_data.delegate_vfs).szOsFile).cast();
@@ -329,6 +875,8 @@ unsafe extern "C" fn vfs_open(
             delegate_file: child_file,
             delegate_vfs: app_data.delegate_vfs,
             xex,
+            auth,
+            main_db,
             vfs_wrapper,
         },
     );
@@ -441,7 +989,16 @@ unsafe extern "C" fn file_read(
     };
 
     match f.xex.read(&mut backing, dst, offset) {
-        Ok(()) => 0,
+        Ok(()) => match f.auth {
+            Some(ref mut auth) => {
+                authenticate_read(auth, &mut f.xex, &mut backing, dst, offset)
+                    .err()
+                    .unwrap_or(0)
+            },
+            None => 0,
+        },
+        // Pages are only authenticated when read in full, so there's nothing
+        // to do for short reads.
         Err(SQLITE_IOERR_SHORT_READ) => {
             // This is an awkward case. SQLite expects to be able to use the
             // prefix of the data that was read, but there's no way for us to
@@ -501,9 +1058,142 @@ unsafe extern "C" fn file_write(
         delegate_file: f.delegate_file,
     };
 
+    if let Some(ref mut auth) = f.auth {
+        if 0 == offset {
+            let was_authenticating = auth.page_size.is_some();
+            if let Err(err) = auth.observe_header(src) {
+                return err;
+            }
+
+            if !was_authenticating && auth.page_size.is_some() {
+                // The database is being created or converted, so every page
+                // is about to be written.
+                auth.committed = Some(Vec::new());
+                auth.versions = Some(Vec::new());
+            }
+        }
+
+        if let Some(page_number) = auth.page_number(offset, src.len()) {
+            auth.buf.clear();
+            auth.buf.extend_from_slice(src);
+            auth.prepare_write(page_number);
+
+            return f
+                .xex
+                .write(&mut backing, &auth.buf, offset)
+                .err()
+                .unwrap_or(0);
+        }
+    }
+
+    if let Some(main_db) = f.main_db.as_mut() {
+        match seal_journal_page(main_db, &mut f.xex, &mut backing, src, offset)
+        {
+            Ok(Some(sealed)) => {
+                return f
+                    .xex
+                    .write(&mut backing, sealed, offset)
+                    .err()
+                    .unwrap_or(0);
+            },
+            Ok(None) => {},
+            Err(err) => return err,
+        }
+    }
+
     f.xex.write(&mut backing, src, offset).err().unwrap_or(0)
 }
 
+/// If `src`, about to be written to the rollback journal of `main_db` at
+/// `offset`, is the image of one of its pages, returns that image sealed with
+/// the trailer the page had as of the last commit.
+///
+/// SQLite journals pages from its cache, where the trailer is whatever the
+/// page had before the VFS last sealed it, so the image would otherwise not
+/// be authentic and rolling back to it would look like new content. Since the
+/// image is the page as of the last commit, sealing it this way reproduces
+/// exactly what was in the file.
+fn seal_journal_page<'a>(
+    main_db: &'a mut File,
+    xex: &mut xex::Xex,
+    backing: &mut DelegateBacking,
+    src: &[u8],
+    offset: u64,
+) -> Result<Option<&'a [u8]>, c_int> {
+    let Some(ref mut auth) = main_db.auth else {
+        return Ok(None);
+    };
+    if auth.page_size != Some(src.len()) || offset < 4 {
+        return Ok(None);
+    }
+
+    // Each page image is immediately preceded by its page number.
+    let mut page_number = [0u8; 4];
+    xex.read(backing, &mut page_number, offset - 4)?;
+    let page_number = u64::from(u32::from_be_bytes(page_number));
+    if 0 == page_number || auth.mac.open(src, page_number).is_some() {
+        return Ok(None);
+    }
+
+    let Some(trailer) = auth.committed_trailer(page_number) else {
+        return Ok(None);
+    };
+
+    auth.buf.clear();
+    auth.buf.extend_from_slice(src);
+    auth.mac.seal(&mut auth.buf, page_number, &trailer);
+    Ok(Some(&auth.buf))
+}
+
+/// Authenticates the data just read into `dst` from `offset` if it is a
+/// whole page.
+fn authenticate_read(
+    auth: &mut PageAuth,
+    xex: &mut xex::Xex,
+    backing: &mut DelegateBacking,
+    dst: &[u8],
+    offset: u64,
+) -> Result<(), c_int> {
+    if 0 == offset {
+        auth.observe_header(dst)?;
+    }
+
+    let Some(page_number) = auth.page_number(offset, dst.len()) else {
+        return Ok(());
+    };
+
+    let Some(trailer) = auth.mac.open(dst, page_number) else {
+        error!("SQLite page {page_number} failed authentication");
+        return Err(SQLITE_IOERR_DATA);
+    };
+
+    if 1 == page_number {
+        return auth.check_header(&trailer, xex, backing);
+    }
+
+    if auth.expected_version(page_number) == Some(trailer.version) {
+        return Ok(());
+    }
+
+    // Another process may have committed since we last saw page 1, so
+    // re-read it before deciding this page doesn't belong.
+    if auth.pending_header.is_none() {
+        auth.reload_header(xex, backing)?;
+    }
+
+    let expected = auth.expected_version(page_number);
+    if expected != Some(trailer.version) {
+        error!(
+            "SQLite page {page_number} has version {}, \
+             but page 1 expects {expected:?}",
+            trailer.version,
+        );
+        return Err(SQLITE_IOERR_DATA);
+    }
+
+    Ok(())
+}
+
 unsafe extern "C" fn file_truncate(
     f: *mut sqlite3_file,
     mut size: i64,
@@ -513,14 +1203,33 @@ unsafe extern "C" fn file_truncate(
     size = (size + block_size - 1) / block_size * block_size;
 
     let f: &mut File = &mut *f.cast();
+    if let Some(ref mut auth) = f.auth {
+        auth.truncate(size as u64);
+    }
     invoke_file_delegate!(f->xTruncate(size))
 }
 
 unsafe extern "C" fn file_sync(f: *mut sqlite3_file, flags: c_int) -> c_int {
     let f: &mut File = &mut *f.cast();
+    if let Err(err) = flush_header(f) {
+        return err;
+    }
     invoke_file_delegate!(f->xSync(flags))
 }
 
+/// Finalises page 1 of the main database if it was written in the current
+/// transaction.
+fn flush_header(f: &mut File) -> Result<(), c_int> {
+    let Some(ref mut auth) = f.auth else {
+        return Ok(());
+    };
+
+    let mut backing = DelegateBacking {
+        delegate_file: f.delegate_file,
+    };
+    auth.flush_header(&mut f.xex, &mut backing)
+}
+
 unsafe extern "C" fn file_file_size(
     f: *mut sqlite3_file,
     dst: *mut i64,
@@ -536,6 +1245,16 @@ unsafe extern "C" fn file_lock(f: *mut sqlite3_file, i: c_int) -> c_int {
 
 unsafe extern "C" fn file_unlock(f: *mut sqlite3_file, i: c_int) -> c_int {
     let f: &mut File = &mut *f.cast();
+    if i < SQLITE_LOCK_RESERVED {
+        // Normally done when the file is synced, but syncs can be turned off.
+        if let Err(err) = flush_header(f) {
+            return err;
+        }
+
+        if let Some(ref mut auth) = f.auth {
+            auth.txn_version = None;
+        }
+    }
     invoke_file_delegate!(f->xUnlock(i))
 }
 
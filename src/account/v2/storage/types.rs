@@ -19,6 +19,7 @@
 //! Bindings for our model types to `rusqlite`, plus model types specific to
 //! the database itself.
 
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
 use chrono::prelude::*;
@@ -325,6 +326,28 @@ impl FromRow for RawMailboxMessage {
     }
 }
 
+/// An expunged mailbox message which can still be put back.
+#[derive(Debug, Clone)]
+pub struct ExpungedMessage {
+    pub mailbox_id: MailboxId,
+    pub uid: Uid,
+    pub near_flags: i64,
+    pub expunged_at: UnixTimestamp,
+    pub path: String,
+}
+
+impl FromRow for ExpungedMessage {
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            mailbox_id: row.get("mailbox_id")?,
+            uid: row.get("uid")?,
+            near_flags: row.get("near_flags")?,
+            expunged_at: row.get("expunged_at")?,
+            path: row.get("path")?,
+        })
+    }
+}
+
 /// The result of a `STORE` against a single message.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum StoreResult {
@@ -354,6 +377,19 @@ pub struct InitialSnapshot {
     pub qresync: Option<QresyncResponse>,
 }
 
+/// The stored membership of a virtual mailbox.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualMailboxMembership {
+    /// The `next_uid` field of the virtual mailbox.
+    pub next_uid: Uid,
+    /// The messages in the virtual mailbox, as `(uid, source_mailbox_id,
+    /// source_uid)`.
+    pub messages: Vec<(Uid, MailboxId, Uid)>,
+    /// The modseq up to which each source mailbox has been checked against
+    /// the query.
+    pub sources: BTreeMap<MailboxId, Modseq>,
+}
+
 /// Information about a message in the mailbox which is being newly introduced
 /// to the snapshot.
 #[derive(Debug, Clone, PartialEq)]
@@ -471,6 +507,24 @@ impl FromRow for Delivery {
     }
 }
 
+/// An entry in the delivery database describing a mailbox to be created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailboxCreation {
+    /// The path to the mailbox to create.
+    pub mailbox: String,
+    /// The special-use attribute to give the mailbox, if any.
+    pub special_use: Option<String>,
+}
+
+impl FromRow for MailboxCreation {
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            mailbox: row.get("mailbox")?,
+            special_use: row.get("special_use")?,
+        })
+    }
+}
+
 /// An event used to stream state in the V1-to-V2 migration process.
 pub enum V1MigrationEvent<'a> {
     /// Begins migration of a mailbox.
@@ -499,6 +553,43 @@ pub enum V1MigrationEvent<'a> {
     Subscription { path: &'a str },
 }
 
+/// An event used to stream state when migrating from another mail system
+/// while preserving UIDVALIDITY and UIDs.
+pub enum UidMigrationEvent<'a> {
+    /// Ensures that no mailbox created in the future is given an ID (and thus
+    /// UIDVALIDITY) less than `next`.
+    ReserveMailboxIds { next: u32 },
+    /// Begins migration of a mailbox.
+    ///
+    /// Mailboxes must be migrated in pre-order; i.e., parents before children.
+    /// If the mailbox already exists, it must be empty.
+    Mailbox {
+        /// The mailbox path; e.g. "INBOX", "Archive/2023".
+        path: &'a str,
+        /// The special-use attribute, if any.
+        special_use: Option<MailboxAttribute>,
+        /// The UIDVALIDITY the mailbox had on the other system, if known.
+        ///
+        /// This is only honoured if no other mailbox already has it as its ID.
+        uid_validity: Option<u32>,
+        /// The next UID the other system would have assigned.
+        next_uid: Uid,
+    },
+    /// Migrates a message in the current mailbox.
+    Message {
+        /// The path of the message relative to the message store (i.e. its new
+        /// location).
+        path: &'a str,
+        /// The UID of the message, which must not already be in use within the
+        /// mailbox.
+        uid: Uid,
+        /// The flags on the message.
+        flags: &'a [Flag],
+    },
+    /// Migrates a subscription.
+    Subscription { path: &'a str },
+}
+
 impl ToSql for TlsVersion {
     fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
         let s = match *self {
@@ -565,6 +656,45 @@ impl FromRow for MessageSpool {
     }
 }
 
+impl FromRow for LoginHistoryEntry {
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            at: row.get::<_, UnixTimestamp>("at")?.0,
+            protocol: row.get("protocol")?,
+            success: row.get("success")?,
+            peer_ip: row.get("peer_ip")?,
+            tls: row.get("tls")?,
+            client_name: row.get("client_name")?,
+            client_id: row.get("client_id")?,
+            app_password: row.get("app_password")?,
+        })
+    }
+}
+
+/// The state of login failures for a single throttling key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoginFailures {
+    /// The key, such as `user:azure` or `ip:192.0.2.0/24`.
+    pub key: String,
+    /// The number of failures within the current window.
+    pub failures: u32,
+    /// The time of the most recent failure.
+    pub last_failure: UnixTimestamp,
+    /// If set, the key is locked out until this time.
+    pub locked_until: Option<UnixTimestamp>,
+}
+
+impl FromRow for LoginFailures {
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            key: row.get("key")?,
+            failures: row.get("failures")?,
+            last_failure: row.get("last_failure")?,
+            locked_until: row.get("locked_until")?,
+        })
+    }
+}
+
 /// A hint of the SMTP transfer to use to send a message.
 ///
 /// Ordering of the enum indicates preference, where greater values require
@@ -625,6 +755,44 @@ impl FromSql for SmtpTransfer {
     }
 }
 
+impl ToSql for RetentionAgeBasis {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let s = match *self {
+            Self::SaveDate => "savedate",
+            Self::InternalDate => "internaldate",
+        };
+        Ok(ToSqlOutput::Borrowed(ValueRef::Text(s.as_bytes())))
+    }
+}
+
+impl FromSql for RetentionAgeBasis {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let ValueRef::Text(value) = value else {
+            return Err(FromSqlError::InvalidType);
+        };
+
+        match value {
+            b"savedate" => Ok(Self::SaveDate),
+            b"internaldate" => Ok(Self::InternalDate),
+            _ => Err(FromSqlError::Other(Box::from(format!(
+                "invalid RetentionAgeBasis: {}",
+                String::from_utf8_lossy(value),
+            )))),
+        }
+    }
+}
+
+impl FromRow for MailboxRetention {
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            max_age_days: row.get("max_age_days")?,
+            age_basis: row.get("age_basis")?,
+            max_messages: row.get("max_messages")?,
+            only_seen: row.get("only_seen")?,
+        })
+    }
+}
+
 pub fn from_row<T: FromRow>(row: &rusqlite::Row<'_>) -> rusqlite::Result<T> {
     T::from_row(row)
 }
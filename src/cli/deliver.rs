@@ -186,7 +186,7 @@ enum LineEndingDisposition {
 }
 
 #[derive(Debug, Clone)]
-struct NormaliseLineEnding<R> {
+pub(super) struct NormaliseLineEnding<R> {
     inner: R,
     disposition: LineEndingDisposition,
     has_trailing_cr: bool,
@@ -194,7 +194,7 @@ struct NormaliseLineEnding<R> {
 }
 
 impl<R> NormaliseLineEnding<R> {
-    fn new(inner: R) -> Self {
+    pub(super) fn new(inner: R) -> Self {
         NormaliseLineEnding {
             inner,
             disposition: LineEndingDisposition::Unknown,
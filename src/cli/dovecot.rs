@@ -0,0 +1,354 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+//! Migration of accounts from Dovecot's Maildir++ layout.
+//!
+//! Unlike `import`, this preserves the UIDVALIDITY and UIDs that Dovecot
+//! assigned to each mailbox (from `dovecot-uidlist`), so clients do not need
+//! to throw away their caches and download everything again. It can only be
+//! done into a freshly provisioned account.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::prelude::*;
+
+use super::deliver::NormaliseLineEnding;
+use super::import::{
+    self, maildir_files, maildir_flags, read_dovecot_keywords,
+};
+use crate::account::{
+    model::*,
+    v2::{Account, UidMigrationItem},
+};
+use crate::mime::utf7;
+use crate::support::{error::Error, safe_name::is_safe_name};
+
+/// The content of a `dovecot-uidlist` file.
+#[derive(Debug, Default, PartialEq)]
+struct UidList {
+    uid_validity: Option<u32>,
+    next_uid: u32,
+    /// Maps base file names (the part before the `:`) to UIDs.
+    uids: HashMap<String, u32>,
+}
+
+/// Migrate the Dovecot Maildir++ at `root` into `account`, which must not
+/// have any messages yet.
+pub(super) fn migrate(account: &mut Account, root: &Path) -> Result<(), Error> {
+    let mailboxes = import::scan(root)?;
+    let next_uid_validity = read_uidvalidity_file(root)?;
+    let subscriptions = read_subscriptions(root)?;
+
+    let mut summary = Vec::<(String, Option<u32>, usize)>::new();
+    account.migrate_with_uids(&mut |emit| {
+        if let Some(next) = next_uid_validity {
+            emit(UidMigrationItem::ReserveMailboxIds { next })?;
+        }
+
+        for mailbox in &mailboxes {
+            let Some(dir) = mailbox.maildir_path() else {
+                continue;
+            };
+
+            let keywords = read_dovecot_keywords(dir)?;
+            let uidlist = match fs::read_to_string(dir.join("dovecot-uidlist"))
+            {
+                Ok(content) => parse_uidlist(&content).unwrap_or_else(|| {
+                    eprintln!(
+                        "{}: unrecognised dovecot-uidlist; \
+                         UIDs will not be preserved",
+                        mailbox.name,
+                    );
+                    UidList::default()
+                }),
+                Err(e) if io::ErrorKind::NotFound == e.kind() => {
+                    UidList::default()
+                },
+                Err(e) => return Err(e.into()),
+            };
+
+            let (messages, next_uid) =
+                assign_uids(maildir_files(dir)?, &uidlist);
+            emit(UidMigrationItem::Mailbox {
+                path: &mailbox.name,
+                special_use: mailbox.special_use,
+                uid_validity: uidlist.uid_validity,
+                next_uid,
+            })?;
+
+            for &(uid, ref file_name, ref path) in &messages {
+                let file = fs::File::open(path)?;
+                // Dovecot uses the modification time as the INTERNALDATE.
+                let internal_date = file
+                    .metadata()?
+                    .modified()
+                    .map(|mtime| DateTime::<Utc>::from(mtime).into())
+                    .unwrap_or_else(|_| Utc::now().into());
+                emit(UidMigrationItem::Message {
+                    uid,
+                    flags: &maildir_flags(file_name, &keywords),
+                    internal_date,
+                    data: &mut NormaliseLineEnding::new(io::BufReader::new(
+                        file,
+                    )),
+                })?;
+            }
+
+            summary.push((
+                mailbox.name.clone(),
+                uidlist.uid_validity,
+                messages.len(),
+            ));
+        }
+
+        for subscription in &subscriptions {
+            emit(UidMigrationItem::Subscription { path: subscription })?;
+        }
+
+        Ok(())
+    })?;
+
+    for (name, uid_validity, count) in summary {
+        let actual = account
+            .select(&name, false, None)
+            .and_then(|(mailbox, _)| mailbox.select_response())
+            .map(|select| select.uidvalidity)?;
+        if uid_validity.is_some_and(|v| v != actual) {
+            println!(
+                "{name}: {count} messages \
+                 (UIDVALIDITY {actual} instead of {}; \
+                 clients will need to resynchronise)",
+                uid_validity.unwrap_or_default(),
+            );
+        } else {
+            println!("{name}: {count} messages (UIDVALIDITY {actual})");
+        }
+    }
+
+    Ok(())
+}
+
+/// Pair each file in `files` with its UID, sorted by UID.
+///
+/// Files not in `uidlist` (for example, ones delivered after Dovecot last
+/// looked at the mailbox) are given new UIDs after all existing ones, in the
+/// order of their names. Returns the messages and the next UID of the
+/// mailbox.
+fn assign_uids(
+    files: Vec<(String, PathBuf)>,
+    uidlist: &UidList,
+) -> (Vec<(Uid, String, PathBuf)>, Uid) {
+    let mut known = Vec::<(Uid, String, PathBuf)>::new();
+    let mut unknown = Vec::<(String, PathBuf)>::new();
+    for (file_name, path) in files {
+        let base_name = file_name.split(':').next().unwrap_or_default();
+        match uidlist.uids.get(base_name).copied().and_then(Uid::of) {
+            Some(uid) => known.push((uid, file_name, path)),
+            None => unknown.push((file_name, path)),
+        }
+    }
+
+    known.sort_by_key(|&(uid, _, _)| uid);
+    let mut next_uid = known
+        .last()
+        .and_then(|&(uid, _, _)| uid.next())
+        .unwrap_or(Uid::MIN)
+        .max(Uid::of(uidlist.next_uid).unwrap_or(Uid::MIN));
+
+    for (file_name, path) in unknown {
+        known.push((next_uid, file_name, path));
+        next_uid = next_uid.next().unwrap_or(Uid::MAX);
+    }
+
+    (known, next_uid)
+}
+
+/// Parse the content of a `dovecot-uidlist` file.
+///
+/// Versions 1 and 3 of the format are understood. Returns `None` if the
+/// header is not understood.
+fn parse_uidlist(content: &str) -> Option<UidList> {
+    let mut lines = content.lines();
+    let mut header = lines.next()?.split_ascii_whitespace();
+    let version = header.next()?;
+
+    let mut uidlist = UidList::default();
+    match version {
+        // 1 <uidvalidity> <next uid>
+        "1" => {
+            uidlist.uid_validity = header.next()?.parse().ok();
+            uidlist.next_uid = header.next()?.parse().ok()?;
+        },
+
+        // 3 V<uidvalidity> N<next uid> G<guid> ...
+        "3" => {
+            for field in header {
+                if let Some(v) = field.strip_prefix('V') {
+                    uidlist.uid_validity = v.parse().ok();
+                } else if let Some(n) = field.strip_prefix('N') {
+                    uidlist.next_uid = n.parse().ok()?;
+                }
+            }
+        },
+
+        _ => return None,
+    }
+
+    for line in lines {
+        let Some((uid, rest)) = line.split_once(' ') else {
+            continue;
+        };
+        let Ok(uid) = uid.parse::<u32>() else {
+            continue;
+        };
+
+        // Version 3 puts extension fields before the file name and marks the
+        // file name with a `:`; version 1 just has the file name.
+        let file_name = match rest.split_once(':') {
+            Some((_, file_name)) if "3" == version => file_name,
+            _ => rest,
+        };
+        let base_name = file_name.split(':').next().unwrap_or_default();
+        uidlist.uids.insert(base_name.to_owned(), uid);
+    }
+
+    Some(uidlist)
+}
+
+/// Read the next UIDVALIDITY Dovecot would have used from the
+/// `dovecot-uidvalidity` file under `root`.
+fn read_uidvalidity_file(root: &Path) -> io::Result<Option<u32>> {
+    match fs::read_to_string(root.join("dovecot-uidvalidity")) {
+        Ok(content) => Ok(u32::from_str_radix(content.trim(), 16).ok()),
+        Err(e) if io::ErrorKind::NotFound == e.kind() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Read the IMAP names of the mailboxes in the `subscriptions` file under
+/// `root`.
+fn read_subscriptions(root: &Path) -> io::Result<Vec<String>> {
+    match fs::read_to_string(root.join("subscriptions")) {
+        Ok(content) => Ok(parse_subscriptions(&content)),
+        Err(e) if io::ErrorKind::NotFound == e.kind() => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Parse the content of a Dovecot `subscriptions` file.
+///
+/// Version 2 of the format begins with a `V 2` header and separates
+/// hierarchy levels with tabs. The older format is just the Maildir++ names,
+/// separated with `.`.
+fn parse_subscriptions(content: &str) -> Vec<String> {
+    let mut lines = content.lines().peekable();
+    let separator = if lines.peek().is_some_and(|l| l.starts_with("V\t")) {
+        lines.next();
+        '\t'
+    } else {
+        '.'
+    };
+
+    lines
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.split(separator)
+                .map(|part| utf7::IMAP.decode(part).into_owned())
+                .collect::<Vec<_>>()
+        })
+        .filter(|parts| parts.iter().all(|part| is_safe_name(part)))
+        .map(|parts| parts.join("/"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_uidlist() {
+        let v3 = parse_uidlist(
+            "3 V1600000000 N12 G0123456789abcdef\n\
+             3 :1600000001.M1P2.host\n\
+             7 W1234 S5678 :1600000002.M3P4.host\n",
+        )
+        .unwrap();
+        assert_eq!(Some(1600000000), v3.uid_validity);
+        assert_eq!(12, v3.next_uid);
+        assert_eq!(Some(&3), v3.uids.get("1600000001.M1P2.host"));
+        assert_eq!(Some(&7), v3.uids.get("1600000002.M3P4.host"));
+
+        let v1 = parse_uidlist(
+            "1 1234 5\n\
+             2 1600000001.M1P2.host:2,S\n",
+        )
+        .unwrap();
+        assert_eq!(Some(1234), v1.uid_validity);
+        assert_eq!(5, v1.next_uid);
+        assert_eq!(Some(&2), v1.uids.get("1600000001.M1P2.host"));
+
+        assert_eq!(None, parse_uidlist("2 foo\n"));
+        assert_eq!(None, parse_uidlist(""));
+    }
+
+    #[test]
+    fn test_assign_uids() {
+        let uidlist = parse_uidlist("3 V1 N10\n3 :b\n5 :a\n").unwrap();
+        let files = ["a:2,S", "b", "c", "d:2,"]
+            .iter()
+            .map(|&name| (name.to_owned(), PathBuf::from(name)))
+            .collect::<Vec<_>>();
+
+        let (messages, next_uid) = assign_uids(files, &uidlist);
+        assert_eq!(
+            vec![
+                (Uid::u(3), "b"),
+                (Uid::u(5), "a:2,S"),
+                (Uid::u(10), "c"),
+                (Uid::u(11), "d:2,"),
+            ],
+            messages
+                .iter()
+                .map(|&(uid, ref name, _)| (uid, name.as_str()))
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(Uid::u(12), next_uid);
+
+        // A stale next_uid doesn't cause UIDs to be reused.
+        let uidlist = parse_uidlist("3 V1 N2\n3 :b\n").unwrap();
+        let (messages, next_uid) =
+            assign_uids(vec![("b".to_owned(), PathBuf::new())], &uidlist);
+        assert_eq!(Uid::u(3), messages[0].0);
+        assert_eq!(Uid::u(4), next_uid);
+    }
+
+    #[test]
+    fn test_parse_subscriptions() {
+        assert_eq!(
+            vec!["INBOX", "Archive/2020", "Caf\u{e9}"],
+            parse_subscriptions("V\t2\n\nINBOX\nArchive\t2020\nCaf&AOk-\n"),
+        );
+        assert_eq!(
+            vec!["Archive/2020", "Sent"],
+            parse_subscriptions("Archive.2020\nSent\n\n..\n"),
+        );
+    }
+}
@@ -0,0 +1,1094 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+//! Support for exporting a whole account to local files.
+//!
+//! Three output formats are supported:
+//!
+//! - Maildir++, laid out the same way `import` expects.
+//!
+//! - mboxrd, one file per mailbox, with Thunderbird-style `.sbd` directories
+//!   for children.
+//!
+//! - A tar archive of `.eml` files, with flags stored as PAX attributes.
+//!
+//! Progress is recorded in a small TOML file next to the output so that an
+//! export can be resumed or incrementally updated. For each mailbox, it holds
+//! the UIDVALIDITY, the greatest UID exported, and the HIGHESTMODSEQ as of the
+//! last time the mailbox was exported completely. For formats that only ever
+//! append to a file, the length of that file at the checkpoint is also kept so
+//! that anything written after the last checkpoint can be discarded.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::import::{read_dovecot_keywords, MBOX_METADATA_HEADERS};
+use crate::account::model::*;
+use crate::mime::utf7;
+use crate::support::file_ops;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum ExportFormat {
+    Maildir,
+    Mbox,
+    Eml,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "maildir" => Ok(Self::Maildir),
+            "mbox" => Ok(Self::Mbox),
+            "eml" => Ok(Self::Eml),
+            _ => Err(format!("Unknown export format: {s}")),
+        }
+    }
+}
+
+/// How many messages are written between checkpoints.
+const CHECKPOINT_INTERVAL: usize = 100;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportState {
+    format: ExportFormat,
+    /// For the eml format, the length of the archive, not including the
+    /// end-of-archive marker.
+    #[serde(default)]
+    length: u64,
+    #[serde(default)]
+    mailboxes: BTreeMap<String, MailboxCheckpoint>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+struct MailboxCheckpoint {
+    uid_validity: u32,
+    uid: u32,
+    modseq: u64,
+    /// For the mbox format, the length of the mailbox file.
+    #[serde(default)]
+    length: u64,
+}
+
+/// Writes messages received from the server to the export destination and
+/// tracks the export's progress.
+pub(super) struct Exporter {
+    state_path: PathBuf,
+    state: ExportState,
+    writer: Writer,
+    current: Option<(String, MailboxCheckpoint)>,
+    since_checkpoint: usize,
+}
+
+enum Writer {
+    Maildir(MaildirWriter),
+    Mbox(MboxWriter),
+    Eml(TarWriter),
+}
+
+impl Exporter {
+    /// Open `output` for exporting in the given format, picking up from any
+    /// checkpoint already there.
+    pub(super) fn open(
+        format: ExportFormat,
+        output: &Path,
+    ) -> io::Result<Self> {
+        let state_path = match format {
+            ExportFormat::Maildir | ExportFormat::Mbox => {
+                fs::create_dir_all(output)?;
+                output.join(".crymap-export")
+            },
+            ExportFormat::Eml => {
+                let mut path = OsString::from(output);
+                path.push(".crymap-export");
+                PathBuf::from(path)
+            },
+        };
+
+        let state = match fs::read(&state_path) {
+            Ok(data) => {
+                let state =
+                    toml::from_slice::<ExportState>(&data).map_err(|e| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{}: {}", state_path.display(), e),
+                        )
+                    })?;
+                if state.format != format {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "{} was previously exported in {:?} format",
+                            output.display(),
+                            state.format,
+                        ),
+                    ));
+                }
+                state
+            },
+            Err(e) if io::ErrorKind::NotFound == e.kind() => ExportState {
+                format,
+                length: 0,
+                mailboxes: BTreeMap::new(),
+            },
+            Err(e) => return Err(e),
+        };
+
+        let writer = match format {
+            ExportFormat::Maildir => {
+                Writer::Maildir(MaildirWriter::new(output))
+            },
+            ExportFormat::Mbox => Writer::Mbox(MboxWriter::new(output)),
+            ExportFormat::Eml => {
+                Writer::Eml(TarWriter::open(output, state.length)?)
+            },
+        };
+
+        Ok(Self {
+            state_path,
+            state,
+            writer,
+            current: None,
+            since_checkpoint: 0,
+        })
+    }
+
+    /// Returns the UID and modseq from which the export of `mailbox` should
+    /// continue.
+    pub(super) fn resume_point(&self, mailbox: &str) -> (u32, u64) {
+        self.state
+            .mailboxes
+            .get(mailbox)
+            .map_or((0, 0), |cp| (cp.uid, cp.modseq))
+    }
+
+    /// Start receiving messages for `mailbox`.
+    ///
+    /// If the checkpoint for `mailbox` is for a different UIDVALIDITY, it is
+    /// discarded and `false` is returned, in which case the caller must
+    /// restart the export of this mailbox from the new `resume_point()`.
+    pub(super) fn begin_mailbox(
+        &mut self,
+        mailbox: &str,
+        uid_validity: u32,
+    ) -> io::Result<bool> {
+        let existing = self.state.mailboxes.get(mailbox).copied();
+        let checkpoint = match existing {
+            Some(cp) if cp.uid_validity != uid_validity => {
+                eprintln!(
+                    "UIDVALIDITY of {mailbox} changed; exporting it again \
+                     from the beginning",
+                );
+                self.state.mailboxes.insert(
+                    mailbox.to_owned(),
+                    MailboxCheckpoint {
+                        uid_validity,
+                        ..MailboxCheckpoint::default()
+                    },
+                );
+                self.save()?;
+                return Ok(false);
+            },
+            Some(cp) => cp,
+            None => MailboxCheckpoint {
+                uid_validity,
+                ..MailboxCheckpoint::default()
+            },
+        };
+
+        match self.writer {
+            Writer::Maildir(ref mut w) => {
+                w.begin_mailbox(mailbox, uid_validity)?
+            },
+            Writer::Mbox(ref mut w) => {
+                w.begin_mailbox(mailbox, checkpoint.length)?
+            },
+            Writer::Eml(ref mut w) => w.begin_mailbox(mailbox),
+        }
+
+        self.current = Some((mailbox.to_owned(), checkpoint));
+        self.since_checkpoint = 0;
+        Ok(true)
+    }
+
+    /// Apply new flags to a message exported previously.
+    ///
+    /// This only has an effect on the Maildir format.
+    pub(super) fn update_flags(
+        &mut self,
+        uid: u32,
+        flags: &[Flag],
+    ) -> io::Result<()> {
+        match self.writer {
+            Writer::Maildir(ref mut w) => w.update_flags(uid, flags),
+            Writer::Mbox(_) | Writer::Eml(_) => Ok(()),
+        }
+    }
+
+    /// Write a new message to the current mailbox.
+    pub(super) fn add_message(
+        &mut self,
+        uid: u32,
+        flags: &[Flag],
+        internal_date: Option<DateTime<FixedOffset>>,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let internal_date = internal_date.unwrap_or_else(|| Utc::now().into());
+        match self.writer {
+            Writer::Maildir(ref mut w) => {
+                w.add_message(uid, flags, internal_date, data)?
+            },
+            Writer::Mbox(ref mut w) => {
+                w.add_message(flags, internal_date, data)?
+            },
+            Writer::Eml(ref mut w) => {
+                w.add_message(uid, flags, internal_date, data)?
+            },
+        }
+
+        if let Some((_, ref mut checkpoint)) = self.current {
+            checkpoint.uid = checkpoint.uid.max(uid);
+        }
+
+        self.since_checkpoint += 1;
+        if self.since_checkpoint >= CHECKPOINT_INTERVAL {
+            self.checkpoint()?;
+        }
+
+        Ok(())
+    }
+
+    /// Record that the current mailbox has been completely exported as of
+    /// `highest_modseq`.
+    pub(super) fn end_mailbox(
+        &mut self,
+        highest_modseq: u64,
+    ) -> io::Result<()> {
+        if let Some((_, ref mut checkpoint)) = self.current {
+            checkpoint.modseq = highest_modseq;
+        }
+        self.checkpoint()?;
+        self.current = None;
+        Ok(())
+    }
+
+    /// Complete the export.
+    pub(super) fn finish(self) -> io::Result<()> {
+        match self.writer {
+            Writer::Maildir(_) | Writer::Mbox(_) => Ok(()),
+            Writer::Eml(w) => w.finish(),
+        }
+    }
+
+    fn checkpoint(&mut self) -> io::Result<()> {
+        self.since_checkpoint = 0;
+        let Some((ref name, ref mut checkpoint)) = self.current else {
+            return Ok(());
+        };
+
+        // The data must be durable before the checkpoint claims it exists.
+        match self.writer {
+            Writer::Maildir(_) => (),
+            Writer::Mbox(ref mut w) => checkpoint.length = w.sync()?,
+            Writer::Eml(ref mut w) => self.state.length = w.sync()?,
+        }
+
+        self.state.mailboxes.insert(name.clone(), *checkpoint);
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let data = toml::to_vec(&self.state).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+        })?;
+        let dir = self
+            .state_path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        file_ops::spit(dir, &self.state_path, true, 0o600, &data)
+    }
+}
+
+/// Returns the letters used in Maildir file names for the non-keyword flags
+/// in `flags`.
+fn maildir_system_flags(flags: &[Flag]) -> impl Iterator<Item = char> + '_ {
+    flags.iter().filter_map(|flag| match *flag {
+        Flag::Draft => Some('D'),
+        Flag::Flagged => Some('F'),
+        Flag::Answered => Some('R'),
+        Flag::Seen => Some('S'),
+        Flag::Deleted => Some('T'),
+        Flag::Keyword(_) => None,
+    })
+}
+
+struct MaildirWriter {
+    root: PathBuf,
+    dir: PathBuf,
+    uid_validity: u32,
+    keywords: Vec<Option<Flag>>,
+    /// The current file name in `cur` of each message with the current
+    /// UIDVALIDITY, keyed by UID.
+    existing: HashMap<u32, String>,
+}
+
+impl MaildirWriter {
+    fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_owned(),
+            dir: root.to_owned(),
+            uid_validity: 0,
+            keywords: Vec::new(),
+            existing: HashMap::new(),
+        }
+    }
+
+    fn begin_mailbox(
+        &mut self,
+        mailbox: &str,
+        uid_validity: u32,
+    ) -> io::Result<()> {
+        self.dir = if "INBOX" == mailbox {
+            self.root.clone()
+        } else {
+            self.root.join(maildir_folder_name(mailbox))
+        };
+        self.uid_validity = uid_validity;
+
+        for sub in ["cur", "new", "tmp"] {
+            fs::create_dir_all(self.dir.join(sub))?;
+        }
+        if self.dir != self.root {
+            fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(self.dir.join("maildirfolder"))?;
+        }
+
+        self.keywords = read_dovecot_keywords(&self.dir)?;
+        self.existing.clear();
+        for entry in fs::read_dir(self.dir.join("cur"))? {
+            let Ok(name) = entry?.file_name().into_string() else {
+                continue;
+            };
+            if let Some(uid) = parse_maildir_name(&name, uid_validity) {
+                self.existing.insert(uid, name);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_message(
+        &mut self,
+        uid: u32,
+        flags: &[Flag],
+        internal_date: DateTime<FixedOffset>,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let name = self.file_name(uid, flags)?;
+        let path = self.dir.join("cur").join(&name);
+        file_ops::spit(self.dir.join("tmp"), &path, true, 0o600, data)?;
+
+        let timeval = nix::sys::time::TimeVal::new(
+            internal_date.timestamp() as nix::sys::time::time_t,
+            0,
+        );
+        nix::sys::stat::utimes(&path, &timeval, &timeval)?;
+
+        // If a previous attempt was interrupted after writing this message,
+        // it may already be present under a different name.
+        if let Some(old) = self.existing.insert(uid, name.clone()) {
+            if old != name {
+                fs::remove_file(self.dir.join("cur").join(old))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn update_flags(&mut self, uid: u32, flags: &[Flag]) -> io::Result<()> {
+        let Some(old) = self.existing.get(&uid).cloned() else {
+            return Ok(());
+        };
+
+        let name = self.file_name(uid, flags)?;
+        if name != old {
+            let cur = self.dir.join("cur");
+            fs::rename(cur.join(&old), cur.join(&name))?;
+            self.existing.insert(uid, name);
+        }
+
+        Ok(())
+    }
+
+    fn file_name(&mut self, uid: u32, flags: &[Flag]) -> io::Result<String> {
+        let mut info = maildir_system_flags(flags).collect::<Vec<_>>();
+        for flag in flags {
+            if matches!(*flag, Flag::Keyword(_)) {
+                if let Some(letter) = self.keyword_letter(flag)? {
+                    info.push(letter);
+                }
+            }
+        }
+        info.sort_unstable();
+        info.dedup();
+
+        Ok(format!(
+            "{uid}.{}.crymap:2,{}",
+            self.uid_validity,
+            info.into_iter().collect::<String>(),
+        ))
+    }
+
+    /// Returns the letter for `keyword`, allocating one if needed.
+    ///
+    /// Maildir can only represent 26 keywords per folder; any beyond that are
+    /// dropped.
+    fn keyword_letter(&mut self, keyword: &Flag) -> io::Result<Option<char>> {
+        let index = match self
+            .keywords
+            .iter()
+            .position(|k| Some(keyword) == k.as_ref())
+        {
+            Some(index) => index,
+            None => {
+                let Some(index) =
+                    self.keywords.iter().position(Option::is_none)
+                else {
+                    return Ok(None);
+                };
+
+                self.keywords[index] = Some(keyword.clone());
+                let content = self
+                    .keywords
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(ix, k)| {
+                        Some(format!("{ix} {}\n", k.as_ref()?))
+                    })
+                    .collect::<String>();
+                file_ops::spit(
+                    self.dir.join("tmp"),
+                    self.dir.join("dovecot-keywords"),
+                    true,
+                    0o600,
+                    content.as_bytes(),
+                )?;
+                index
+            },
+        };
+
+        Ok(Some((b'a' + index as u8) as char))
+    }
+}
+
+/// Returns the Maildir++ directory name for the given (non-INBOX) mailbox.
+///
+/// Maildir++ uses `.` as the hierarchy delimiter, so any `.` within a name is
+/// replaced with `_`.
+fn maildir_folder_name(mailbox: &str) -> String {
+    let mut name = String::new();
+    for part in mailbox.split('/') {
+        name.push('.');
+        name.push_str(&utf7::IMAP.encode(part).replace('.', "_"));
+    }
+    name
+}
+
+/// If `name` is a file name written by `MaildirWriter` for `uid_validity`,
+/// returns the UID it is for.
+fn parse_maildir_name(name: &str, uid_validity: u32) -> Option<u32> {
+    let mut parts = name.splitn(3, '.');
+    let uid = parts.next()?.parse::<u32>().ok()?;
+    let file_uid_validity = parts.next()?.parse::<u32>().ok()?;
+    parts
+        .next()?
+        .starts_with("crymap:")
+        .then_some(uid)
+        .filter(|_| file_uid_validity == uid_validity)
+}
+
+struct MboxWriter {
+    root: PathBuf,
+    file: Option<io::BufWriter<fs::File>>,
+    length: u64,
+}
+
+impl MboxWriter {
+    fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_owned(),
+            file: None,
+            length: 0,
+        }
+    }
+
+    fn begin_mailbox(&mut self, mailbox: &str, length: u64) -> io::Result<()> {
+        let path = mbox_path(&self.root, mailbox);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        truncate_to_checkpoint(&mut file, &path, length)?;
+
+        self.file = Some(io::BufWriter::new(file));
+        self.length = length;
+        Ok(())
+    }
+
+    fn add_message(
+        &mut self,
+        flags: &[Flag],
+        internal_date: DateTime<FixedOffset>,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let mut out = Vec::<u8>::with_capacity(data.len() + 256);
+        writeln!(
+            out,
+            "From MAILER-DAEMON {}",
+            internal_date
+                .with_timezone(&Utc)
+                .format("%a %b %e %H:%M:%S %Y"),
+        )?;
+
+        if flags.contains(&Flag::Seen) {
+            out.extend_from_slice(b"Status: RO\n");
+        } else {
+            out.extend_from_slice(b"Status: O\n");
+        }
+
+        let x_status = flags
+            .iter()
+            .filter_map(|flag| match *flag {
+                Flag::Answered => Some('A'),
+                Flag::Deleted => Some('D'),
+                Flag::Flagged => Some('F'),
+                Flag::Draft => Some('T'),
+                _ => None,
+            })
+            .collect::<String>();
+        if !x_status.is_empty() {
+            writeln!(out, "X-Status: {x_status}")?;
+        }
+
+        let keywords = flags
+            .iter()
+            .filter(|f| matches!(**f, Flag::Keyword(_)))
+            .map(Flag::as_str)
+            .collect::<Vec<_>>();
+        if !keywords.is_empty() {
+            writeln!(out, "X-Keywords: {}", keywords.join(" "))?;
+        }
+
+        let mut in_header = true;
+        let mut keep = true;
+        for line in data.split_inclusive(|&b| b'\n' == b) {
+            let line = line
+                .strip_suffix(b"\r\n")
+                .or_else(|| line.strip_suffix(b"\n"))
+                .unwrap_or(line);
+
+            if in_header {
+                if line.is_empty() {
+                    in_header = false;
+                } else if !line.starts_with(b" ") && !line.starts_with(b"\t") {
+                    // Any metadata headers already in the message would be
+                    // mistaken for ours when the mbox is read back.
+                    let name = line
+                        .split(|&b| b':' == b)
+                        .next()
+                        .map(String::from_utf8_lossy)
+                        .unwrap_or_default();
+                    keep = !MBOX_METADATA_HEADERS
+                        .iter()
+                        .any(|h| h.eq_ignore_ascii_case(name.trim()));
+                }
+
+                if in_header && !keep {
+                    continue;
+                }
+            }
+
+            // mboxrd quoting: any line matching /^>*From / gets another `>`.
+            if line
+                .iter()
+                .position(|&b| b'>' != b)
+                .is_some_and(|ix| line[ix..].starts_with(b"From "))
+            {
+                out.push(b'>');
+            }
+            out.extend_from_slice(line);
+            out.push(b'\n');
+        }
+        out.push(b'\n');
+
+        let file = self.file.as_mut().expect("add_message without mailbox");
+        file.write_all(&out)?;
+        self.length += out.len() as u64;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<u64> {
+        if let Some(ref mut file) = self.file {
+            file.flush()?;
+            file.get_ref().sync_data()?;
+        }
+        Ok(self.length)
+    }
+}
+
+/// Returns the path of the mbox file for `mailbox`.
+///
+/// Each parent mailbox `Name` has its children in `Name.sbd`.
+fn mbox_path(root: &Path, mailbox: &str) -> PathBuf {
+    let mut path = root.to_owned();
+    let mut parts = mailbox.split('/').peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_some() {
+            path.push(format!("{part}.sbd"));
+        } else {
+            path.push(part);
+        }
+    }
+    path
+}
+
+/// Discard anything in `file` after `length`, which is the length recorded in
+/// the last checkpoint, then seek to the end.
+fn truncate_to_checkpoint(
+    file: &mut fs::File,
+    path: &Path,
+    length: u64,
+) -> io::Result<()> {
+    if file.metadata()?.len() < length {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} is shorter than the last checkpoint; \
+                 remove the export and start over",
+                path.display(),
+            ),
+        ));
+    }
+
+    file.set_len(length)?;
+    file.seek(io::SeekFrom::End(0))?;
+    Ok(())
+}
+
+const TAR_BLOCK: usize = 512;
+
+struct TarWriter {
+    file: io::BufWriter<fs::File>,
+    length: u64,
+    mailbox: String,
+}
+
+impl TarWriter {
+    fn open(path: &Path, length: u64) -> io::Result<Self> {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        truncate_to_checkpoint(&mut file, path, length)?;
+
+        Ok(Self {
+            file: io::BufWriter::new(file),
+            length,
+            mailbox: String::new(),
+        })
+    }
+
+    fn begin_mailbox(&mut self, mailbox: &str) {
+        self.mailbox = mailbox.to_owned();
+    }
+
+    fn add_message(
+        &mut self,
+        uid: u32,
+        flags: &[Flag],
+        internal_date: DateTime<FixedOffset>,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let path = format!("{}/{uid}.eml", self.mailbox);
+        let flags =
+            flags.iter().map(Flag::as_str).collect::<Vec<_>>().join(" ");
+
+        // The real path and the flags may not fit in (or be representable
+        // by) the ustar header, so they go in a PAX extended header.
+        let mut pax = Vec::<u8>::new();
+        pax_record(&mut pax, "path", &path);
+        pax_record(&mut pax, "mtime", &internal_date.timestamp().to_string());
+        pax_record(&mut pax, "CRYMAP.flags", &flags);
+
+        let short_name = format!("{uid}.eml");
+        self.write_entry(&format!("PaxHeaders/{short_name}"), b'x', 0, &pax)?;
+        self.write_entry(
+            &short_name,
+            b'0',
+            internal_date.timestamp().max(0) as u64,
+            data,
+        )
+    }
+
+    fn write_entry(
+        &mut self,
+        name: &str,
+        kind: u8,
+        mtime: u64,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let header = tar_header(name, kind, mtime, data.len() as u64);
+        self.file.write_all(&header)?;
+        self.file.write_all(data)?;
+
+        let padding = (TAR_BLOCK - data.len() % TAR_BLOCK) % TAR_BLOCK;
+        self.file.write_all(&[0u8; TAR_BLOCK][..padding])?;
+
+        self.length += (header.len() + data.len() + padding) as u64;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<u64> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        Ok(self.length)
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        // The end-of-archive marker is not counted in `length` so that it is
+        // overwritten if the export is continued later.
+        self.file.write_all(&[0u8; 2 * TAR_BLOCK])?;
+        self.file.flush()?;
+        self.file.get_ref().sync_data()
+    }
+}
+
+fn tar_header(name: &str, kind: u8, mtime: u64, size: u64) -> [u8; TAR_BLOCK] {
+    fn octal(dst: &mut [u8], value: u64) {
+        let width = dst.len() - 1;
+        let s = format!("{:0width$o}", value, width = width);
+        dst[..width].copy_from_slice(&s.as_bytes()[..width]);
+    }
+
+    let mut header = [0u8; TAR_BLOCK];
+    let name = name.as_bytes();
+    let name_len = name.len().min(100);
+    header[..name_len].copy_from_slice(&name[..name_len]);
+    octal(&mut header[100..108], 0o600);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(&mut header[124..136], size);
+    octal(&mut header[136..148], mtime);
+    header[148..156].fill(b' ');
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    let checksum = header.iter().map(|&b| u64::from(b)).sum::<u64>();
+    octal(&mut header[148..155], checksum);
+    header
+}
+
+/// Append a PAX extended header record to `dst`.
+///
+/// The record begins with its own length in decimal, including the length
+/// field itself.
+fn pax_record(dst: &mut Vec<u8>, key: &str, value: &str) {
+    let rest = format!(" {key}={value}\n");
+    let mut len = rest.len() + 1;
+    while len != rest.len() + len.to_string().len() {
+        len = rest.len() + len.to_string().len();
+    }
+    dst.extend_from_slice(len.to_string().as_bytes());
+    dst.extend_from_slice(rest.as_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::import::scan;
+    use super::*;
+
+    fn date(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    #[test]
+    fn test_pax_record() {
+        let mut pax = Vec::new();
+        pax_record(&mut pax, "path", "INBOX/1.eml");
+        assert_eq!(b"20 path=INBOX/1.eml\n", &pax[..]);
+
+        // The length crossing a power of 10 makes the field one digit longer.
+        let mut pax = Vec::new();
+        pax_record(&mut pax, "k", &"x".repeat(94));
+        assert_eq!(101, pax.len());
+        assert!(pax.starts_with(b"101 k="));
+    }
+
+    #[test]
+    fn test_maildir_folder_name() {
+        assert_eq!(".Foo", maildir_folder_name("Foo"));
+        assert_eq!(".Foo.Bar_baz", maildir_folder_name("Foo/Bar.baz"));
+        assert_eq!(".Entw&APw-rfe", maildir_folder_name("Entwürfe"));
+        assert_eq!(Some(42), parse_maildir_name("42.7.crymap:2,S", 7));
+        assert_eq!(None, parse_maildir_name("42.8.crymap:2,S", 7));
+        assert_eq!(None, parse_maildir_name("1234.M1P2.host:2,", 1));
+    }
+
+    #[test]
+    fn maildir_round_trip() {
+        let root = tempfile::TempDir::new().unwrap();
+        let out = root.path().join("out");
+
+        let mut exporter = Exporter::open(ExportFormat::Maildir, &out).unwrap();
+        assert_eq!((0, 0), exporter.resume_point("Archive/2020"));
+        assert!(exporter.begin_mailbox("INBOX", 7).unwrap());
+        exporter
+            .add_message(
+                1,
+                &[Flag::Seen, Flag::Keyword("$Important".to_owned())],
+                Some(date("2020-01-02T03:04:05Z")),
+                b"Subject: one\r\n\r\nbody\r\n",
+            )
+            .unwrap();
+        exporter.end_mailbox(10).unwrap();
+        assert!(exporter.begin_mailbox("Archive/2020", 8).unwrap());
+        exporter
+            .add_message(
+                3,
+                &[],
+                Some(date("2020-01-02T03:04:05Z")),
+                b"Subject: two\r\n\r\nbody\r\n",
+            )
+            .unwrap();
+        exporter.end_mailbox(20).unwrap();
+        exporter.finish().unwrap();
+
+        // Flag changes are applied when continuing.
+        let mut exporter = Exporter::open(ExportFormat::Maildir, &out).unwrap();
+        assert_eq!((1, 10), exporter.resume_point("INBOX"));
+        assert_eq!((3, 20), exporter.resume_point("Archive/2020"));
+        assert!(exporter.begin_mailbox("INBOX", 7).unwrap());
+        exporter.update_flags(1, &[Flag::Flagged]).unwrap();
+        exporter.end_mailbox(11).unwrap();
+        exporter.finish().unwrap();
+
+        let mailboxes = scan(&out).unwrap();
+        assert_eq!(
+            vec!["INBOX", "Archive/2020"],
+            mailboxes.iter().map(|m| &m.name[..]).collect::<Vec<_>>(),
+        );
+
+        let mut messages = Vec::new();
+        for mailbox in &mailboxes {
+            mailbox
+                .for_each_message(|m| -> io::Result<()> {
+                    messages.push(m);
+                    Ok(())
+                })
+                .unwrap();
+        }
+        assert_eq!(2, messages.len());
+        assert_eq!(vec![Flag::Flagged], messages[0].flags);
+        assert_eq!(
+            Some(date("2020-01-02T03:04:05Z")),
+            messages[1].internal_date,
+        );
+        assert_eq!(b"Subject: two\r\n\r\nbody\r\n", &messages[1].data[..]);
+    }
+
+    #[test]
+    fn mbox_round_trip() {
+        let root = tempfile::TempDir::new().unwrap();
+        let out = root.path().join("out");
+
+        let mut exporter = Exporter::open(ExportFormat::Mbox, &out).unwrap();
+        assert!(exporter.begin_mailbox("Archive/2020", 1).unwrap());
+        exporter
+            .add_message(
+                1,
+                &[
+                    Flag::Seen,
+                    Flag::Flagged,
+                    Flag::Keyword("$Important".to_owned()),
+                ],
+                Some(date("2020-01-02T03:04:05Z")),
+                b"Status: U\r\nSubject: one\r\n\r\nFrom here\r\n>From there\r\n",
+            )
+            .unwrap();
+        exporter.end_mailbox(5).unwrap();
+        exporter.finish().unwrap();
+
+        // Simulate an interrupted write after the checkpoint.
+        let path = out.join("Archive.sbd").join("2020");
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"From MAILER-DAEMON garbage\n").unwrap();
+        drop(file);
+
+        let mut exporter = Exporter::open(ExportFormat::Mbox, &out).unwrap();
+        assert_eq!((1, 5), exporter.resume_point("Archive/2020"));
+        assert!(exporter.begin_mailbox("Archive/2020", 1).unwrap());
+        exporter
+            .add_message(
+                2,
+                &[],
+                Some(date("2021-01-02T03:04:05Z")),
+                b"Subject: two\r\n\r\nbody\r\n",
+            )
+            .unwrap();
+        exporter.end_mailbox(6).unwrap();
+        exporter.finish().unwrap();
+
+        let mailboxes = scan(&out).unwrap();
+        assert_eq!(1, mailboxes.len());
+        assert_eq!("Archive/2020", mailboxes[0].name);
+
+        let mut messages = Vec::new();
+        mailboxes[0]
+            .for_each_message(|m| -> io::Result<()> {
+                messages.push(m);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(2, messages.len());
+        assert_eq!(
+            vec![
+                Flag::Seen,
+                Flag::Flagged,
+                Flag::Keyword("$Important".to_owned()),
+            ],
+            messages[0].flags,
+        );
+        assert_eq!(
+            Some(date("2020-01-02T03:04:05Z")),
+            messages[0].internal_date,
+        );
+        assert_eq!(
+            b"Subject: one\r\n\r\nFrom here\r\n>From there\r\n",
+            &messages[0].data[..],
+        );
+        assert_eq!(b"Subject: two\r\n\r\nbody\r\n", &messages[1].data[..]);
+    }
+
+    #[test]
+    fn uid_validity_change_restarts() {
+        let root = tempfile::TempDir::new().unwrap();
+        let out = root.path().join("out");
+
+        let mut exporter = Exporter::open(ExportFormat::Mbox, &out).unwrap();
+        assert!(exporter.begin_mailbox("INBOX", 1).unwrap());
+        exporter.add_message(5, &[], None, b"\r\n").unwrap();
+        exporter.end_mailbox(5).unwrap();
+
+        assert!(!exporter.begin_mailbox("INBOX", 2).unwrap());
+        assert_eq!((0, 0), exporter.resume_point("INBOX"));
+        assert!(exporter.begin_mailbox("INBOX", 2).unwrap());
+
+        assert!(Exporter::open(ExportFormat::Maildir, &out).is_err());
+    }
+
+    #[test]
+    fn eml_archive() {
+        let root = tempfile::TempDir::new().unwrap();
+        let out = root.path().join("out.tar");
+
+        let mut exporter = Exporter::open(ExportFormat::Eml, &out).unwrap();
+        assert!(exporter.begin_mailbox("INBOX", 1).unwrap());
+        exporter
+            .add_message(
+                1,
+                &[Flag::Seen],
+                Some(date("2020-01-02T03:04:05Z")),
+                b"Subject: one\r\n\r\nbody\r\n",
+            )
+            .unwrap();
+        exporter.end_mailbox(1).unwrap();
+        exporter.finish().unwrap();
+        let first_len = fs::metadata(&out).unwrap().len();
+        // PAX header + data, file header + data, end-of-archive marker
+        assert_eq!(6 * TAR_BLOCK as u64, first_len);
+
+        let mut exporter = Exporter::open(ExportFormat::Eml, &out).unwrap();
+        assert!(exporter.begin_mailbox("Sent", 1).unwrap());
+        exporter
+            .add_message(
+                1,
+                &[],
+                Some(date("2020-01-02T03:04:05Z")),
+                b"Subject: two\r\n\r\nbody\r\n",
+            )
+            .unwrap();
+        exporter.end_mailbox(1).unwrap();
+        exporter.finish().unwrap();
+
+        let data = fs::read(&out).unwrap();
+        assert_eq!(10 * TAR_BLOCK, data.len());
+
+        let headers = [0, 2, 4, 6]
+            .into_iter()
+            .map(|block| &data[block * TAR_BLOCK..][..TAR_BLOCK])
+            .collect::<Vec<_>>();
+        for header in &headers {
+            assert_eq!(b"ustar\0", &header[257..263]);
+
+            let mut unsummed = header.to_vec();
+            unsummed[148..156].fill(b' ');
+            let expected = unsummed.iter().map(|&b| u64::from(b)).sum::<u64>();
+            let actual = u64::from_str_radix(
+                std::str::from_utf8(&header[148..154]).unwrap(),
+                8,
+            )
+            .unwrap();
+            assert_eq!(expected, actual);
+        }
+
+        assert_eq!(
+            [b'x', b'0', b'x', b'0'],
+            [
+                headers[0][156],
+                headers[1][156],
+                headers[2][156],
+                headers[3][156]
+            ],
+        );
+
+        let pax = String::from_utf8_lossy(&data[5 * TAR_BLOCK..][..TAR_BLOCK]);
+        assert!(pax.contains(" path=Sent/1.eml\n"));
+        assert!(pax.contains(" mtime=1577934245\n"));
+        assert!(data[8 * TAR_BLOCK..].iter().all(|&b| 0 == b));
+    }
+}
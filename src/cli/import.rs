@@ -0,0 +1,801 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+//! Support for importing whole mail trees from other systems.
+//!
+//! Two source formats are understood:
+//!
+//! - Maildir++ (as used by Dovecot, Courier, etc). The directory given is the
+//!   INBOX, and each `.Name.Child` directory beneath it is the mailbox
+//!   `Name/Child`. Keywords are taken from `dovecot-keywords` if present.
+//!
+//! - mbox. A single file is imported into the INBOX. A directory is walked
+//!   recursively, with each file becoming a mailbox named after its path
+//!   relative to the directory. Thunderbird-style `.sbd` directories are
+//!   understood.
+
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+
+use chrono::prelude::*;
+
+use super::deliver::NormaliseLineEnding;
+use super::main::ServerUserImportSubcommand;
+use crate::account::model::*;
+use crate::account::v2::DeliveryAccount;
+use crate::mime::{header::parse_datetime, utf7};
+use crate::support::{
+    error::Error, log_prefix::LogPrefix, safe_name::is_safe_name,
+    unix_privileges,
+};
+
+/// A mailbox found in the import source.
+#[derive(Debug)]
+pub(super) struct SourceMailbox {
+    /// The IMAP name of the mailbox, with `/` as the hierarchy delimiter.
+    pub(super) name: String,
+    /// The special use to give the mailbox, inferred from its name.
+    pub(super) special_use: Option<MailboxAttribute>,
+    location: Location,
+}
+
+#[derive(Debug)]
+enum Location {
+    Maildir(PathBuf),
+    Mbox(PathBuf),
+}
+
+/// A message read from the import source.
+#[derive(Debug)]
+pub(super) struct SourceMessage {
+    pub(super) flags: Vec<Flag>,
+    pub(super) internal_date: Option<DateTime<FixedOffset>>,
+    /// The message content with canonical line endings.
+    pub(super) data: Vec<u8>,
+}
+
+/// Headers used by mbox implementations to store metadata, which are removed
+/// from imported messages.
+pub(super) static MBOX_METADATA_HEADERS: &[&str] = &[
+    "Content-Length",
+    "Status",
+    "X-IMAP",
+    "X-IMAPbase",
+    "X-Keywords",
+    "X-Status",
+    "X-UID",
+];
+
+pub(super) fn server_import(
+    cmd: ServerUserImportSubcommand,
+    users_root: PathBuf,
+) {
+    if !is_safe_name(&cmd.name) {
+        die!(EX_USAGE, "Invalid user name: {}", cmd.name);
+    }
+
+    // Resolve the source before switching users, since that also changes the
+    // working directory.
+    let source = match fs::canonicalize(&cmd.source) {
+        Ok(source) => source,
+        Err(e) => die!(EX_NOINPUT, "{}: {}", cmd.source.display(), e),
+    };
+
+    let log_prefix = LogPrefix::new("import".to_owned());
+    log_prefix.set_user(cmd.name.clone());
+
+    let mut user_dir = users_root.join(&cmd.name);
+    if !user_dir.is_dir() {
+        die!(EX_NOUSER, "User '{}' does not exist", cmd.name);
+    }
+
+    // As with `deliver`, everything from here on is done as the user, but we
+    // don't chroot so that the source remains accessible.
+    if let Err(exit) = unix_privileges::assume_user_privileges(
+        &log_prefix.to_string(),
+        false,
+        &mut user_dir,
+        false,
+    ) {
+        exit.exit();
+    }
+
+    let mailboxes = match scan(&source) {
+        Ok(mailboxes) => mailboxes,
+        Err(e) => {
+            die!(EX_NOINPUT, "Error scanning {}: {}", source.display(), e)
+        },
+    };
+
+    let mut account = match DeliveryAccount::new(log_prefix, user_dir) {
+        Ok(a) => a,
+        Err(e) => die!(EX_CANTCREAT, "Failed to open account: {e:?}"),
+    };
+
+    for mailbox in mailboxes {
+        if "INBOX" != mailbox.name {
+            if let Err(e) = account
+                .queue_mailbox_creation(&mailbox.name, mailbox.special_use)
+            {
+                die!(EX_SOFTWARE, "Failed to create {}: {}", mailbox.name, e);
+            }
+        }
+
+        let mut count = 0usize;
+        let result = mailbox.for_each_message(|message| -> Result<(), Error> {
+            let buffered = account.buffer_message_with_date(
+                message.internal_date.unwrap_or_else(|| Utc::now().into()),
+                &message.data[..],
+            )?;
+            account.deliver_buffered(
+                &mailbox.name,
+                &message.flags,
+                &buffered,
+            )?;
+            count += 1;
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            die!(
+                EX_SOFTWARE,
+                "Failed to import {} after {} messages: {}",
+                mailbox.name,
+                count,
+                e
+            );
+        }
+
+        println!("{}: {} messages", mailbox.name, count);
+    }
+
+    println!(
+        "Import queued. The messages will appear in the account the next \
+         time {} logs in.",
+        cmd.name,
+    );
+}
+
+/// Discover the mailboxes to import from `root`.
+///
+/// The INBOX, if present, is always returned first. Mailboxes with names that
+/// could not be created are skipped with a warning.
+pub(super) fn scan(root: &Path) -> io::Result<Vec<SourceMailbox>> {
+    let mut mailboxes = Vec::new();
+    if root.is_file() {
+        mailboxes.push(SourceMailbox {
+            name: "INBOX".to_owned(),
+            special_use: None,
+            location: Location::Mbox(root.to_owned()),
+        });
+    } else if root.join("cur").is_dir() {
+        scan_maildir(root, &mut mailboxes)?;
+    } else {
+        scan_mbox_dir(root, "", &mut mailboxes)?;
+    }
+
+    mailboxes.retain(|mailbox| {
+        let ok = mailbox.name.split('/').all(is_safe_name);
+        if !ok {
+            eprintln!("Skipping mailbox with unusable name: {}", mailbox.name);
+        }
+        ok
+    });
+    mailboxes.sort_by(|a, b| {
+        ("INBOX" != a.name, &a.name).cmp(&("INBOX" != b.name, &b.name))
+    });
+
+    Ok(mailboxes)
+}
+
+fn scan_maildir(root: &Path, dst: &mut Vec<SourceMailbox>) -> io::Result<()> {
+    dst.push(SourceMailbox {
+        name: "INBOX".to_owned(),
+        special_use: None,
+        location: Location::Maildir(root.to_owned()),
+    });
+
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let path = entry.path();
+        let Some(file_name) = entry.file_name().to_str().map(str::to_owned)
+        else {
+            continue;
+        };
+
+        if !file_name.starts_with('.')
+            || ".." == file_name
+            || !path.join("cur").is_dir()
+        {
+            continue;
+        }
+
+        // Maildir++ uses `.` as the hierarchy delimiter and encodes the names
+        // with modified UTF-7, like IMAP itself.
+        let name = file_name[1..]
+            .split('.')
+            .map(|part| utf7::IMAP.decode(part).into_owned())
+            .collect::<Vec<_>>()
+            .join("/");
+        dst.push(SourceMailbox {
+            special_use: guess_special_use(&name),
+            name,
+            location: Location::Maildir(path),
+        });
+    }
+
+    Ok(())
+}
+
+fn scan_mbox_dir(
+    dir: &Path,
+    prefix: &str,
+    dst: &mut Vec<SourceMailbox>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let Some(file_name) = entry.file_name().to_str().map(str::to_owned)
+        else {
+            eprintln!("Skipping non-UTF-8 file name: {}", path.display());
+            continue;
+        };
+
+        // Skip hidden files and the index/lock files other clients keep
+        // alongside mbox files.
+        if file_name.starts_with('.')
+            || file_name.ends_with(".msf")
+            || file_name.ends_with(".lock")
+        {
+            continue;
+        }
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            let child = file_name.strip_suffix(".sbd").unwrap_or(&file_name);
+            scan_mbox_dir(&path, &format!("{prefix}{child}/"), dst)?;
+        } else if file_type.is_file() {
+            let name = if prefix.is_empty()
+                && file_name.eq_ignore_ascii_case("inbox")
+            {
+                "INBOX".to_owned()
+            } else {
+                format!("{prefix}{file_name}")
+            };
+
+            dst.push(SourceMailbox {
+                special_use: guess_special_use(&name),
+                name,
+                location: Location::Mbox(path),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Infer the special use of a top-level mailbox from the names common
+/// clients give them.
+fn guess_special_use(name: &str) -> Option<MailboxAttribute> {
+    match &*name.to_lowercase() {
+        "archive" | "archives" => Some(MailboxAttribute::Archive),
+        "drafts" => Some(MailboxAttribute::Drafts),
+        "junk" | "junk e-mail" | "junk email" | "spam" => {
+            Some(MailboxAttribute::Junk)
+        },
+        "sent" | "sent items" | "sent mail" | "sent messages" => {
+            Some(MailboxAttribute::Sent)
+        },
+        "deleted items" | "deleted messages" | "trash" => {
+            Some(MailboxAttribute::Trash)
+        },
+        _ => None,
+    }
+}
+
+impl SourceMailbox {
+    /// Invoke `f` on each message in this mailbox, in their original order.
+    pub(super) fn for_each_message<E: From<io::Error>>(
+        &self,
+        f: impl FnMut(SourceMessage) -> Result<(), E>,
+    ) -> Result<(), E> {
+        match self.location {
+            Location::Maildir(ref path) => maildir_messages(path, f),
+            Location::Mbox(ref path) => mbox_messages(path, f),
+        }
+    }
+
+    /// The directory of this mailbox, if it is a Maildir.
+    pub(super) fn maildir_path(&self) -> Option<&Path> {
+        match self.location {
+            Location::Maildir(ref path) => Some(path),
+            Location::Mbox(_) => None,
+        }
+    }
+}
+
+fn maildir_messages<E: From<io::Error>>(
+    dir: &Path,
+    mut f: impl FnMut(SourceMessage) -> Result<(), E>,
+) -> Result<(), E> {
+    let keywords = read_dovecot_keywords(dir)?;
+
+    for (file_name, path) in maildir_files(dir)? {
+        let file = fs::File::open(&path)?;
+        // Dovecot uses the modification time as the INTERNALDATE.
+        let internal_date: Option<DateTime<FixedOffset>> = file
+            .metadata()?
+            .modified()
+            .ok()
+            .map(|mtime| DateTime::<Utc>::from(mtime).into());
+        let mut data = Vec::new();
+        NormaliseLineEnding::new(io::BufReader::new(file))
+            .read_to_end(&mut data)?;
+
+        f(SourceMessage {
+            flags: maildir_flags(&file_name, &keywords),
+            internal_date,
+            data,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// List the message files in the `new` and `cur` directories of the Maildir
+/// `dir`, sorted by file name.
+pub(super) fn maildir_files(dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::<(String, PathBuf)>::new();
+    for sub in ["new", "cur"] {
+        let sub = dir.join(sub);
+        if !sub.is_dir() {
+            continue;
+        }
+
+        for entry in fs::read_dir(sub)? {
+            let entry = entry?;
+            let Some(file_name) = entry.file_name().to_str().map(str::to_owned)
+            else {
+                continue;
+            };
+
+            if !file_name.starts_with('.') && entry.file_type()?.is_file() {
+                files.push((file_name, entry.path()));
+            }
+        }
+    }
+
+    // Maildir file names begin with the delivery time, so this puts them in
+    // approximately the order they arrived.
+    files.sort();
+    Ok(files)
+}
+
+/// Read the `dovecot-keywords` file in `dir`, which maps the lowercase
+/// letters in file names to keywords.
+pub(super) fn read_dovecot_keywords(
+    dir: &Path,
+) -> io::Result<Vec<Option<Flag>>> {
+    let mut keywords = vec![None; 26];
+    let content = match fs::read_to_string(dir.join("dovecot-keywords")) {
+        Ok(content) => content,
+        Err(e) if io::ErrorKind::NotFound == e.kind() => return Ok(keywords),
+        Err(e) => return Err(e),
+    };
+
+    for line in content.lines() {
+        let Some((index, keyword)) = line.split_once(' ') else {
+            continue;
+        };
+
+        if let (Ok(index), Ok(keyword)) =
+            (index.parse::<usize>(), keyword.trim().parse::<Flag>())
+        {
+            if let Some(slot) = keywords.get_mut(index) {
+                *slot = Some(keyword);
+            }
+        }
+    }
+
+    Ok(keywords)
+}
+
+pub(super) fn maildir_flags(
+    file_name: &str,
+    keywords: &[Option<Flag>],
+) -> Vec<Flag> {
+    let Some((_, info)) = file_name.rsplit_once(":2,") else {
+        return Vec::new();
+    };
+
+    info.chars()
+        .filter_map(|ch| match ch {
+            'D' => Some(Flag::Draft),
+            'F' => Some(Flag::Flagged),
+            'R' => Some(Flag::Answered),
+            'S' => Some(Flag::Seen),
+            'T' => Some(Flag::Deleted),
+            'a'..='z' => keywords
+                .get((ch as usize) - ('a' as usize))
+                .cloned()
+                .flatten(),
+            _ => None,
+        })
+        .collect()
+}
+
+fn mbox_messages<E: From<io::Error>>(
+    path: &Path,
+    mut f: impl FnMut(SourceMessage) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut reader = io::BufReader::new(fs::File::open(path)?);
+    let mut line = Vec::<u8>::new();
+    let mut current = None::<MboxMessage>;
+    let mut prev_blank = true;
+
+    loop {
+        line.clear();
+        if 0 == reader.read_until(b'\n', &mut line)? {
+            break;
+        }
+
+        // A "From " line only starts a new message at the start of the file
+        // or after a blank line; elsewhere, it is just an unquoted body line
+        // from a sloppy writer.
+        if prev_blank && line.starts_with(b"From ") {
+            if let Some(message) = current.take() {
+                f(message.finish())?;
+            }
+
+            current = Some(MboxMessage::new(&line));
+            prev_blank = false;
+            continue;
+        }
+
+        let Some(ref mut message) = current else {
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not an mbox file", path.display()),
+            )
+            .into());
+        };
+
+        prev_blank = b"\n" == &line[..] || b"\r\n" == &line[..];
+        message.push_line(&line);
+    }
+
+    if let Some(message) = current {
+        f(message.finish())?;
+    }
+
+    Ok(())
+}
+
+struct MboxMessage {
+    from_line_date: Option<DateTime<FixedOffset>>,
+    data: Vec<u8>,
+}
+
+impl MboxMessage {
+    fn new(from_line: &[u8]) -> Self {
+        Self {
+            from_line_date: parse_from_line_date(from_line),
+            data: Vec::new(),
+        }
+    }
+
+    fn push_line(&mut self, line: &[u8]) {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        // Undo mboxrd quoting: `>From `, `>>From `, etc, lose one `>`.
+        let unquoted = line
+            .iter()
+            .position(|&b| b'>' != b)
+            .filter(|&n| n > 0 && line[n..].starts_with(b"From "))
+            .map_or(line, |_| &line[1..]);
+
+        self.data.extend_from_slice(unquoted);
+        self.data.extend_from_slice(b"\r\n");
+    }
+
+    fn finish(mut self) -> SourceMessage {
+        // The blank line before the next "From " line belongs to the mbox
+        // format, not the message.
+        if self.data.ends_with(b"\r\n\r\n") {
+            self.data.truncate(self.data.len() - 2);
+        }
+
+        let header_end = memchr::memmem::find(&self.data, b"\r\n\r\n")
+            .map_or(self.data.len(), |ix| ix + 2);
+
+        let mut flags = Vec::<Flag>::new();
+        let mut date_header = None::<DateTime<FixedOffset>>;
+        let mut data = Vec::with_capacity(self.data.len());
+        let mut keep = true;
+
+        for line in self.data[..header_end].split_inclusive(|&b| b'\n' == b) {
+            // Continuation lines go with whatever header they continue.
+            if !line.starts_with(b" ") && !line.starts_with(b"\t") {
+                let text = String::from_utf8_lossy(line);
+                let (name, value) = text.split_once(':').unwrap_or((&text, ""));
+                let (name, value) = (name.trim(), value.trim());
+
+                keep = !MBOX_METADATA_HEADERS
+                    .iter()
+                    .any(|h| h.eq_ignore_ascii_case(name));
+
+                if name.eq_ignore_ascii_case("Status") {
+                    if value.contains('R') {
+                        flags.push(Flag::Seen);
+                    }
+                } else if name.eq_ignore_ascii_case("X-Status") {
+                    flags.extend(value.chars().filter_map(|ch| match ch {
+                        'A' => Some(Flag::Answered),
+                        'D' => Some(Flag::Deleted),
+                        'F' => Some(Flag::Flagged),
+                        'T' => Some(Flag::Draft),
+                        _ => None,
+                    }));
+                } else if name.eq_ignore_ascii_case("X-Keywords") {
+                    flags.extend(
+                        value
+                            .split(|c: char| ',' == c || c.is_whitespace())
+                            .filter_map(|kw| kw.parse::<Flag>().ok()),
+                    );
+                } else if name.eq_ignore_ascii_case("Date") {
+                    date_header = parse_datetime(value);
+                }
+            }
+
+            if keep {
+                data.extend_from_slice(line);
+            }
+        }
+        data.extend_from_slice(&self.data[header_end..]);
+
+        SourceMessage {
+            flags,
+            internal_date: self.from_line_date.or(date_header),
+            data,
+        }
+    }
+}
+
+/// Parse the date out of an mbox "From " line, such as
+/// `From user@example.com Wed Jan  3 01:05:34 1996`.
+fn parse_from_line_date(line: &[u8]) -> Option<DateTime<FixedOffset>> {
+    let line = std::str::from_utf8(line).ok()?;
+    let (_, date) = line.strip_prefix("From ")?.trim().split_once(' ')?;
+    let date = date.trim();
+
+    DateTime::parse_from_str(date, "%a %b %e %H:%M:%S %Y %z")
+        .or_else(|_| DateTime::parse_from_str(date, "%a %b %e %H:%M:%S %z %Y"))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(date, "%a %b %e %H:%M:%S %Y")
+                .ok()
+                .map(|dt| Utc.from_utc_datetime(&dt).into())
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn read_all(mailbox: &SourceMailbox) -> Vec<SourceMessage> {
+        let mut messages = Vec::new();
+        mailbox
+            .for_each_message(|m| -> io::Result<()> {
+                messages.push(m);
+                Ok(())
+            })
+            .unwrap();
+        messages
+    }
+
+    #[test]
+    fn test_maildir_flags() {
+        let keywords = vec![
+            Some(Flag::Keyword("$Label1".to_owned())),
+            None,
+            Some(Flag::Keyword("Work".to_owned())),
+        ];
+
+        assert_eq!(Vec::<Flag>::new(), maildir_flags("1234.foo", &keywords));
+        assert_eq!(
+            vec![
+                Flag::Answered,
+                Flag::Seen,
+                Flag::Keyword("$Label1".to_owned()),
+                Flag::Keyword("Work".to_owned()),
+            ],
+            maildir_flags("1234.M1P2.host,S=42:2,RSabcz", &keywords),
+        );
+    }
+
+    #[test]
+    fn test_parse_from_line_date() {
+        assert_eq!(
+            Some(
+                FixedOffset::east_opt(0)
+                    .unwrap()
+                    .with_ymd_and_hms(1996, 1, 3, 1, 5, 34)
+                    .unwrap()
+            ),
+            parse_from_line_date(
+                b"From foo@bar.com Wed Jan  3 01:05:34 1996\n"
+            ),
+        );
+        assert_eq!(
+            Some(
+                FixedOffset::east_opt(3600)
+                    .unwrap()
+                    .with_ymd_and_hms(2020, 12, 25, 13, 0, 0)
+                    .unwrap()
+            ),
+            parse_from_line_date(
+                b"From MAILER-DAEMON Fri Dec 25 13:00:00 2020 +0100\n"
+            ),
+        );
+        assert_eq!(None, parse_from_line_date(b"From foo@bar.com\n"));
+    }
+
+    #[test]
+    fn scan_maildir_tree() {
+        let root = tempfile::TempDir::new().unwrap();
+        let root = root.path();
+        write(&root.join("cur/1000.a.host:2,S"), "Subject: one\n\nfoo\n");
+        write(&root.join("new/1001.a.host"), "Subject: two\n\nbar\n");
+        write(
+            &root.join(".Sent/cur/1002.a.host:2,Sa"),
+            "Subject: three\n\n",
+        );
+        write(&root.join(".Sent/dovecot-keywords"), "0 $Forwarded\n");
+        write(&root.join(".Work.Caf&AOk-/cur/1003.a.host:2,"), "x\n");
+        fs::create_dir_all(root.join(".Work.Caf&AOk-/new")).unwrap();
+        fs::create_dir_all(root.join("tmp")).unwrap();
+
+        let mailboxes = scan(root).unwrap();
+        assert_eq!(
+            vec!["INBOX", "Sent", "Work/Café"],
+            mailboxes.iter().map(|m| &*m.name).collect::<Vec<_>>(),
+        );
+        assert_eq!(None, mailboxes[0].special_use);
+        assert_eq!(Some(MailboxAttribute::Sent), mailboxes[1].special_use);
+        assert_eq!(None, mailboxes[2].special_use);
+
+        let inbox = read_all(&mailboxes[0]);
+        assert_eq!(2, inbox.len());
+        assert_eq!(vec![Flag::Seen], inbox[0].flags);
+        assert_eq!(b"Subject: one\r\n\r\nfoo\r\n" as &[u8], inbox[0].data);
+        assert!(inbox[0].internal_date.is_some());
+        assert_eq!(Vec::<Flag>::new(), inbox[1].flags);
+
+        let sent = read_all(&mailboxes[1]);
+        assert_eq!(
+            vec![Flag::Seen, Flag::Keyword("$Forwarded".to_owned())],
+            sent[0].flags,
+        );
+    }
+
+    #[test]
+    fn scan_mbox_tree() {
+        let root = tempfile::TempDir::new().unwrap();
+        let root = root.path();
+        write(
+            &root.join("inbox"),
+            "From foo@bar.com Wed Jan  3 01:05:34 1996\n\
+             Subject: one\n\
+             Status: RO\n\
+             X-Status: AF\n\
+             X-Keywords: $Label1, Work\n\
+             \n\
+             >From the top\n\
+             >>From here\n\
+             From the middle\n\
+             \n\
+             From MAILER-DAEMON Fri Dec 25 13:00:00 2020\n\
+             Date: Thu, 24 Dec 2020 12:00:00 +0000\n\
+             Subject: two\n\
+             \n\
+             body\n\
+             \n",
+        );
+        write(&root.join("Trash"), "");
+        write(&root.join("Lists.sbd/rust"), "");
+        write(&root.join("Trash.msf"), "garbage");
+
+        let mailboxes = scan(root).unwrap();
+        assert_eq!(
+            vec!["INBOX", "Lists/rust", "Trash"],
+            mailboxes.iter().map(|m| &*m.name).collect::<Vec<_>>(),
+        );
+        assert_eq!(Some(MailboxAttribute::Trash), mailboxes[2].special_use);
+        assert!(read_all(&mailboxes[1]).is_empty());
+
+        let inbox = read_all(&mailboxes[0]);
+        assert_eq!(2, inbox.len());
+        assert_eq!(
+            "Subject: one\r\n\
+             \r\n\
+             From the top\r\n\
+             >From here\r\n\
+             From the middle\r\n",
+            String::from_utf8_lossy(&inbox[0].data),
+        );
+        assert_eq!(
+            vec![
+                Flag::Seen,
+                Flag::Answered,
+                Flag::Flagged,
+                Flag::Keyword("$Label1".to_owned()),
+                Flag::Keyword("Work".to_owned()),
+            ],
+            inbox[0].flags,
+        );
+        assert_eq!(
+            Some(
+                FixedOffset::east_opt(0)
+                    .unwrap()
+                    .with_ymd_and_hms(1996, 1, 3, 1, 5, 34)
+                    .unwrap()
+            ),
+            inbox[0].internal_date,
+        );
+
+        assert_eq!(
+            "Date: Thu, 24 Dec 2020 12:00:00 +0000\r\n\
+             Subject: two\r\n\
+             \r\n\
+             body\r\n",
+            String::from_utf8_lossy(&inbox[1].data),
+        );
+        assert_eq!(
+            Some(
+                FixedOffset::east_opt(0)
+                    .unwrap()
+                    .with_ymd_and_hms(2020, 12, 25, 13, 0, 0)
+                    .unwrap()
+            ),
+            inbox[1].internal_date,
+        );
+    }
+
+    #[test]
+    fn reject_non_mbox() {
+        let root = tempfile::TempDir::new().unwrap();
+        let path = root.path().join("foo");
+        write(&path, "Subject: not an mbox\n\nfoo\n");
+
+        let mailboxes = scan(&path).unwrap();
+        assert!(mailboxes[0]
+            .for_each_message(|_| -> io::Result<()> { Ok(()) })
+            .is_err());
+    }
+}
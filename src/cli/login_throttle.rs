@@ -0,0 +1,115 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use std::path::PathBuf;
+
+use chrono::prelude::*;
+
+use super::main::{
+    ServerLoginThrottleClearSubcommand, ServerLoginThrottleSubcommand,
+};
+use crate::account::v2::LoginThrottle;
+use crate::support::{
+    log_prefix::LogPrefix, system_config::SystemConfig, unix_privileges,
+};
+
+pub(super) fn main(
+    system_config: SystemConfig,
+    cmd: ServerLoginThrottleSubcommand,
+    mut users_root: PathBuf,
+) {
+    // Run as the same user the servers do so that any files SQLite creates
+    // remain accessible to them.
+    if let Err(exit) =
+        unix_privileges::assume_system(&system_config.security, &mut users_root)
+    {
+        exit.exit();
+    }
+
+    let log_prefix = LogPrefix::new("login-throttle".to_owned());
+    let throttle = match LoginThrottle::open_existing(log_prefix, &users_root) {
+        Ok(throttle) => throttle,
+        Err(e) => die!(EX_SOFTWARE, "Failed to open login throttle: {}", e),
+    };
+
+    let Some(mut throttle) = throttle else {
+        if matches!(cmd, ServerLoginThrottleSubcommand::List(_)) {
+            println!("No login failures have been recorded.");
+        }
+        return;
+    };
+
+    match cmd {
+        ServerLoginThrottleSubcommand::List(_) => list(&mut throttle),
+        ServerLoginThrottleSubcommand::Clear(cmd) => clear(&mut throttle, cmd),
+    }
+}
+
+fn list(throttle: &mut LoginThrottle) {
+    let entries = match throttle.list() {
+        Ok(entries) => entries,
+        Err(e) => die!(EX_SOFTWARE, "Failed to list login failures: {}", e),
+    };
+
+    if entries.is_empty() {
+        println!("No login failures have been recorded.");
+        return;
+    }
+
+    let now = Utc::now();
+    for entry in entries {
+        match entry.locked_until {
+            Some(until) if until > now => println!(
+                "{}: LOCKED until {}; last failure {}",
+                entry.key,
+                until.to_rfc3339(),
+                entry.last_failure.to_rfc3339(),
+            ),
+            _ => println!(
+                "{}: {} failure(s); last failure {}",
+                entry.key,
+                entry.failures,
+                entry.last_failure.to_rfc3339(),
+            ),
+        }
+    }
+}
+
+fn clear(
+    throttle: &mut LoginThrottle,
+    cmd: ServerLoginThrottleClearSubcommand,
+) {
+    if cmd.all {
+        if let Err(e) = throttle.clear_all() {
+            die!(EX_SOFTWARE, "Failed to clear login failures: {}", e);
+        }
+        return;
+    }
+
+    if cmd.keys.is_empty() {
+        die!(EX_USAGE, "Nothing to clear; pass keys or --all");
+    }
+
+    for key in cmd.keys {
+        match throttle.clear(&key) {
+            Ok(true) => {},
+            Ok(false) => eprintln!("{key}: nothing recorded"),
+            Err(e) => die!(EX_SOFTWARE, "Failed to clear {}: {}", key, e),
+        }
+    }
+}
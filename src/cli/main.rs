@@ -81,6 +81,8 @@ enum ServerSubcommand {
     SmtpOutSanityCheck(SmtpOutSanityCheckSubcommand),
     /// Manage user accounts.
     User(ServerUserSubcommand),
+    /// Inspect and clear login failure records and lockouts.
+    LoginThrottle(ServerLoginThrottleSubcommand),
     /// Serve a single IMAPS session over standard IO.
     ///
     /// This is intended to be used with inetd, xinetd, etc. It is the main way
@@ -115,6 +117,21 @@ impl ServerSubcommand {
             ServerSubcommand::User(ServerUserSubcommand::Add(ref mut c)) => {
                 mem::take(&mut c.common)
             },
+            ServerSubcommand::User(ServerUserSubcommand::Recover(
+                ref mut c,
+            )) => mem::take(&mut c.common),
+            ServerSubcommand::User(ServerUserSubcommand::Import(ref mut c)) => {
+                mem::take(&mut c.common)
+            },
+            ServerSubcommand::User(ServerUserSubcommand::Verify(ref mut c)) => {
+                mem::take(&mut c.common)
+            },
+            ServerSubcommand::LoginThrottle(
+                ServerLoginThrottleSubcommand::List(ref mut c),
+            ) => mem::take(c),
+            ServerSubcommand::LoginThrottle(
+                ServerLoginThrottleSubcommand::Clear(ref mut c),
+            ) => mem::take(&mut c.common),
             ServerSubcommand::ServeImaps(ref mut c) => mem::take(c),
             ServerSubcommand::ServeLmtp(ref mut c) => mem::take(c),
             ServerSubcommand::ServeSmtpin(ref mut c) => mem::take(c),
@@ -128,6 +145,9 @@ impl ServerSubcommand {
 enum ServerUserSubcommand {
     /// Create a new user account.
     Add(ServerUserAddSubcommand),
+    Recover(ServerUserRecoverSubcommand),
+    Import(ServerUserImportSubcommand),
+    Verify(ServerUserVerifySubcommand),
 }
 
 #[derive(StructOpt)]
@@ -144,6 +164,12 @@ pub(super) struct ServerUserAddSubcommand {
     #[structopt(short, long)]
     pub(super) uid: Option<nix::libc::uid_t>,
 
+    /// Migrate the mail in this Dovecot Maildir++ directory into the new
+    /// account, keeping the UIDVALIDITY and UIDs Dovecot assigned so that
+    /// clients don't need to download everything again.
+    #[structopt(long, parse(from_os_str))]
+    pub(super) dovecot_maildir: Option<PathBuf>,
+
     /// Name of the user to create.
     pub(super) name: String,
 
@@ -153,6 +179,110 @@ pub(super) struct ServerUserAddSubcommand {
     pub(super) data_path: Option<PathBuf>,
 }
 
+#[derive(StructOpt)]
+pub(super) enum ServerLoginThrottleSubcommand {
+    /// List user names and networks with recent login failures or lockouts.
+    List(ServerCommonOptions),
+    Clear(ServerLoginThrottleClearSubcommand),
+}
+
+/// Forget login failures and lift lockouts.
+#[derive(StructOpt)]
+pub(super) struct ServerLoginThrottleClearSubcommand {
+    #[structopt(flatten)]
+    pub(super) common: ServerCommonOptions,
+
+    /// Clear everything.
+    #[structopt(long, conflicts_with = "keys")]
+    pub(super) all: bool,
+
+    /// The keys to clear, as shown by `list`. A bare user name is also
+    /// accepted.
+    pub(super) keys: Vec<String>,
+}
+
+/// Reset the password of a user who has forgotten it.
+///
+/// This requires that `recovery_public_key` was configured in `crymap.toml`
+/// when the user's password was last set, and that the corresponding private
+/// key is available. The master key is recovered from the escrowed copy and
+/// the user is given a new password; no message data is modified. The old
+/// configuration is kept as a backup in the user's `tmp` directory.
+///
+/// Application-specific passwords are not affected.
+#[derive(StructOpt)]
+pub(super) struct ServerUserRecoverSubcommand {
+    #[structopt(flatten)]
+    pub(super) common: ServerCommonOptions,
+
+    /// Prompt for the new password instead of generating one.
+    #[structopt(long)]
+    pub(super) prompt_password: bool,
+
+    /// Path to the recovery private key, in PEM format.
+    #[structopt(long, parse(from_os_str))]
+    pub(super) recovery_key: PathBuf,
+
+    /// Name of the user to recover.
+    pub(super) name: String,
+}
+
+/// Import a Maildir++ or mbox tree into a user's account.
+///
+/// If SOURCE is a directory containing `cur`, it is treated as a Maildir++
+/// tree: SOURCE itself becomes the INBOX and each `.Name.Child` folder
+/// becomes `Name/Child`. Flags are taken from the file names, keywords from
+/// `dovecot-keywords`, and the INTERNALDATE from the file modification time.
+///
+/// If SOURCE is a single file, it is imported as an mbox into the INBOX.
+/// Otherwise, every file under SOURCE is imported as an mbox into the mailbox
+/// named by its relative path (with Thunderbird's `.sbd` suffix removed). A
+/// top-level file named `inbox` is imported into the INBOX. Flags are taken
+/// from the `Status`, `X-Status` and `X-Keywords` headers, and the
+/// INTERNALDATE from the "From " line.
+///
+/// Mailboxes with common names like "Sent Items" or "Junk" are given the
+/// corresponding special use. Existing mailboxes are reused.
+///
+/// This does not need the user's password. As with `crymap server deliver`,
+/// it runs as the user, who must be able to read SOURCE, and the messages
+/// appear in the account the next time the user logs in.
+#[derive(StructOpt)]
+pub(super) struct ServerUserImportSubcommand {
+    #[structopt(flatten)]
+    pub(super) common: ServerCommonOptions,
+
+    /// Name of the user to import into.
+    pub(super) name: String,
+
+    /// The Maildir++ directory, mbox directory, or mbox file to import.
+    #[structopt(parse(from_os_str))]
+    pub(super) source: PathBuf,
+}
+
+/// Check a user's account for damaged or tampered data.
+///
+/// Every message is read in full, which checks the authentication tags of
+/// the encrypted data, and its size is compared with what was recorded when
+/// it was stored. The metadata database is checked with SQLite's integrity
+/// check, and files in the message store that the database does not
+/// reference are reported.
+///
+/// This needs the user's password, for which it prompts. The account is not
+/// modified unless `--quarantine` is given.
+#[derive(StructOpt)]
+pub(super) struct ServerUserVerifySubcommand {
+    #[structopt(flatten)]
+    pub(super) common: ServerCommonOptions,
+
+    /// Move damaged messages into this mailbox, creating it if needed.
+    #[structopt(long)]
+    pub(super) quarantine: Option<String>,
+
+    /// Name of the user to verify.
+    pub(super) name: String,
+}
+
 /// Deliver or import mail.
 ///
 /// By default, this will read from standard input and deliver it to the INBOX
@@ -177,7 +307,8 @@ pub(super) struct ServerUserAddSubcommand {
 ///
 /// ls Maildir/cur/* | xargs -d'\n' crymap server deliver --maildir-flags
 ///
-/// This command cannot be used to import mbox files.
+/// To import a whole Maildir++ or mbox tree, including subfolders, use
+/// `crymap server user import` instead.
 #[derive(StructOpt)]
 pub(super) struct ServerDeliverSubcommand {
     #[structopt(flatten)]
@@ -276,6 +407,13 @@ pub(super) enum RemoteSubcommand {
     Config(RemoteConfigSubcommand),
     ForeignSmtpTls(ForeignSmtpTlsCommand),
     RetryEmail(RetryEmailCommand),
+    AppPassword(AppPasswordCommand),
+    LoginHistory(LoginHistoryCommand),
+    Import(ImportCommand),
+    Export(ExportCommand),
+    Restore(RestoreCommand),
+    Unexpunge(UnexpungeCommand),
+    MailboxRetention(MailboxRetentionCommand),
 }
 
 impl RemoteSubcommand {
@@ -285,6 +423,9 @@ impl RemoteSubcommand {
             | RemoteSubcommand::Chpw(ref mut c)
             | RemoteSubcommand::ForeignSmtpTls(ForeignSmtpTlsCommand::List(
                 ref mut c,
+            ))
+            | RemoteSubcommand::AppPassword(AppPasswordCommand::List(
+                ref mut c,
             )) => mem::take(c),
 
             RemoteSubcommand::Config(ref mut c) => mem::take(&mut c.common),
@@ -292,6 +433,22 @@ impl RemoteSubcommand {
                 ForeignSmtpTlsCommand::Delete(ref mut c),
             ) => mem::take(&mut c.common),
             RemoteSubcommand::RetryEmail(ref mut c) => mem::take(&mut c.common),
+            RemoteSubcommand::AppPassword(AppPasswordCommand::Create(
+                ref mut c,
+            )) => mem::take(&mut c.common),
+            RemoteSubcommand::AppPassword(AppPasswordCommand::Revoke(
+                ref mut c,
+            )) => mem::take(&mut c.common),
+            RemoteSubcommand::LoginHistory(ref mut c) => {
+                mem::take(&mut c.common)
+            },
+            RemoteSubcommand::Import(ref mut c) => mem::take(&mut c.common),
+            RemoteSubcommand::Export(ref mut c) => mem::take(&mut c.common),
+            RemoteSubcommand::Restore(ref mut c) => mem::take(&mut c.common),
+            RemoteSubcommand::Unexpunge(ref mut c) => mem::take(&mut c.common),
+            RemoteSubcommand::MailboxRetention(ref mut c) => {
+                mem::take(&mut c.common)
+            },
         }
     }
 }
@@ -356,6 +513,41 @@ pub(super) struct RemoteConfigSubcommand {
     /// mail submission.
     #[structopt(long)]
     pub(super) smtp_out_failure_receipts: Option<String>,
+
+    /// Turn notifications about logins from new devices on or off.
+    ///
+    /// When on, a message is delivered to your INBOX whenever a client logs
+    /// in from a combination of client software and IP address which has not
+    /// been used to log in before.
+    #[structopt(long, possible_values(&["on", "off"]))]
+    pub(super) new_device_notifications: Option<String>,
+
+    /// Change how many days expunged messages are kept before being deleted
+    /// for good (1 to 90).
+    ///
+    /// Until then, messages which are no longer in any mailbox can be put
+    /// back with the `unexpunge` command.
+    #[structopt(long)]
+    pub(super) expunge_retention_days: Option<u32>,
+
+    /// Retire private keys this many days after they are created (0 to
+    /// disable, the default).
+    ///
+    /// Messages encrypted with a retired key are re-encrypted with the
+    /// current key during daily maintenance, after which the old key is
+    /// destroyed. This means that a key which leaks after it was retired
+    /// cannot be used to read mail.
+    #[structopt(long)]
+    pub(super) key_retirement_days: Option<u32>,
+
+    /// Change the algorithm used for new keys.
+    ///
+    /// `x25519` keys are much faster to generate and make messages slightly
+    /// smaller, but messages encrypted with them cannot be read by older
+    /// versions of Crymap. The change takes effect the next time keys are
+    /// rotated.
+    #[structopt(long, possible_values(&["rsa", "x25519"]))]
+    pub(super) key_algorithm: Option<String>,
 }
 
 /// Inspect or modify the TLS status recorded for foreign SMTP domains.
@@ -388,6 +580,199 @@ pub(super) struct RetryEmailCommand {
     pub(super) message_id: String,
 }
 
+/// Manage application-specific passwords.
+///
+/// Application-specific passwords can be used instead of your main password to
+/// log in from a particular device or mail client. Each one can be revoked
+/// independently without changing your main password or affecting other
+/// devices.
+#[derive(StructOpt)]
+pub(super) enum AppPasswordCommand {
+    /// List all application-specific passwords and when they were last used.
+    List(RemoteCommonOptions),
+    Create(CreateAppPasswordCommand),
+    Revoke(RevokeAppPasswordCommand),
+}
+
+/// Creates a new application-specific password.
+///
+/// The password is randomly generated and printed once. There is no way to
+/// retrieve it again later.
+#[derive(StructOpt)]
+pub(super) struct CreateAppPasswordCommand {
+    #[structopt(flatten)]
+    pub(super) common: RemoteCommonOptions,
+    /// Only allow the password to be used to log in to IMAP.
+    #[structopt(long, conflicts_with = "submission-only")]
+    pub(super) imap_only: bool,
+    /// Only allow the password to be used for SMTP submission.
+    #[structopt(long)]
+    pub(super) submission_only: bool,
+    /// A name to identify the password, such as the device it is for.
+    pub(super) name: String,
+}
+
+/// Revokes an application-specific password.
+///
+/// The password can no longer be used to log in. Existing sessions are not
+/// terminated.
+#[derive(StructOpt)]
+pub(super) struct RevokeAppPasswordCommand {
+    #[structopt(flatten)]
+    pub(super) common: RemoteCommonOptions,
+    /// The name of the password to revoke.
+    pub(super) name: String,
+}
+
+/// Show recent logins to your account, both successful and failed.
+#[derive(StructOpt)]
+pub(super) struct LoginHistoryCommand {
+    #[structopt(flatten)]
+    pub(super) common: RemoteCommonOptions,
+    /// The maximum number of entries to show.
+    #[structopt(long, default_value = "50")]
+    pub(super) limit: u32,
+}
+
+/// Import a Maildir++ or mbox tree into your account.
+///
+/// SOURCE is interpreted the same way as by `crymap server user import`.
+/// Mailboxes are created as needed and the messages are uploaded with their
+/// original flags and dates using MULTIAPPEND.
+///
+/// Unlike the server-side import, this only requires the ability to log in,
+/// and the messages are available as soon as the command completes.
+#[derive(StructOpt)]
+pub(super) struct ImportCommand {
+    #[structopt(flatten)]
+    pub(super) common: RemoteCommonOptions,
+    /// The Maildir++ directory, mbox directory, or mbox file to import.
+    #[structopt(parse(from_os_str))]
+    pub(super) source: PathBuf,
+}
+
+/// Export every mailbox in your account to local files.
+///
+/// The available formats are:
+///
+/// - maildir: OUTPUT is a Maildir++ directory. INBOX is the top level and
+///   other mailboxes are `.Name.Child` directories beneath it. Flags are kept
+///   in the file names, keywords in `dovecot-keywords`, and the modification
+///   time of each file is its INTERNALDATE.
+///
+/// - mbox: OUTPUT is a directory of mboxrd files, with child mailboxes in
+///   Thunderbird-style `.sbd` directories. Flags are written into the Status,
+///   X-Status, and X-Keywords headers.
+///
+/// - eml: OUTPUT is a tar archive containing one `.eml` file per message,
+///   under a directory named after the mailbox. Flags are recorded in the
+///   `CRYMAP.flags` PAX attribute.
+///
+/// Progress is recorded as the export runs, so running the same command again
+/// continues an interrupted export, or brings a completed one up to date by
+/// adding new messages. In the maildir format, flag changes to messages that
+/// were already exported are also applied. Messages deleted from the account
+/// are not removed from the export.
+///
+/// The maildir and mbox formats can be imported back with `crymap remote
+/// import` or `crymap server user import`.
+#[derive(StructOpt)]
+pub(super) struct ExportCommand {
+    #[structopt(flatten)]
+    pub(super) common: RemoteCommonOptions,
+    /// The format to write.
+    #[structopt(
+        long,
+        parse(try_from_str),
+        possible_values(&["maildir", "mbox", "eml"]),
+        default_value = "maildir"
+    )]
+    pub(super) format: super::export::ExportFormat,
+    /// The directory (maildir, mbox) or file (eml) to write to.
+    #[structopt(parse(from_os_str))]
+    pub(super) output: PathBuf,
+}
+
+/// Recover deleted mail from the server's daily backups.
+///
+/// The server keeps a copy of your account's index from each of the last few
+/// days. Messages deleted since then can be recovered until the server
+/// removes their files for good, usually a day after deletion.
+///
+/// With no arguments, lists the dates of the available backups. Given a DATE,
+/// lists the mailboxes which have since been deleted or lost messages. Given
+/// a MAILBOX as well, lists the messages that are gone from it. Given UIDS
+/// (e.g. `12,15:20`, or `1:*` for everything), those messages are copied into
+/// a mailbox named `Restored`.
+#[derive(StructOpt)]
+pub(super) struct RestoreCommand {
+    #[structopt(flatten)]
+    pub(super) common: RemoteCommonOptions,
+    /// The date of the backup, as YYYY-MM-DD.
+    pub(super) date: Option<String>,
+    /// The name of the mailbox at the time of the backup.
+    pub(super) mailbox: Option<String>,
+    /// The UIDs of the messages to restore.
+    pub(super) uids: Option<String>,
+}
+
+/// Put back messages that were recently expunged.
+///
+/// Messages which are no longer in any mailbox are kept for a while (see
+/// `--expunge-retention-days` of the `config` command) before being deleted
+/// for good.
+///
+/// With no arguments, lists the messages that can be put back. Given a
+/// MAILBOX and UIDS (e.g. `12,15:20`, or `1:*` for everything), puts those
+/// messages back into the mailbox they were expunged from.
+#[derive(StructOpt)]
+pub(super) struct UnexpungeCommand {
+    #[structopt(flatten)]
+    pub(super) common: RemoteCommonOptions,
+    /// The mailbox the messages were expunged from.
+    pub(super) mailbox: Option<String>,
+    /// The UIDs the messages had in that mailbox.
+    pub(super) uids: Option<String>,
+}
+
+/// Get or set the retention rules of a mailbox.
+///
+/// Messages which break the rules of their mailbox are expunged automatically
+/// once a day.
+///
+/// With no arguments, lists every mailbox which has retention rules. Given a
+/// MAILBOX, replaces its rules with those given by the options. A MAILBOX
+/// with neither `--max-age-days` nor `--max-messages` has its rules removed.
+#[derive(StructOpt)]
+pub(super) struct MailboxRetentionCommand {
+    #[structopt(flatten)]
+    pub(super) common: RemoteCommonOptions,
+
+    /// Expunge messages once they are older than this many days.
+    ///
+    /// By default, age is measured from when the message was added to the
+    /// mailbox.
+    #[structopt(long)]
+    pub(super) max_age_days: Option<u32>,
+
+    /// Measure the age of messages from their internal date (usually when
+    /// they were received) instead of when they were added to the mailbox.
+    #[structopt(long)]
+    pub(super) by_internal_date: bool,
+
+    /// Expunge the oldest messages once the mailbox holds more than this
+    /// many.
+    #[structopt(long)]
+    pub(super) max_messages: Option<u32>,
+
+    /// Only ever expunge messages which have been read.
+    #[structopt(long)]
+    pub(super) only_seen: bool,
+
+    /// The mailbox whose rules are to be set.
+    pub(super) mailbox: Option<String>,
+}
+
 pub fn main() {
     // Clap exits with status 1 instead of EX_USAGE if we use the more concise
     // API
@@ -544,7 +929,19 @@ fn server(mut cmd: ServerSubcommand) {
             super::sanity::sanity_check(system_config, cmd);
         },
         ServerSubcommand::User(ServerUserSubcommand::Add(cmd)) => {
-            super::user::add(cmd, users_root);
+            super::user::add(system_config, cmd, users_root);
+        },
+        ServerSubcommand::User(ServerUserSubcommand::Recover(cmd)) => {
+            super::user::recover(system_config, cmd, users_root);
+        },
+        ServerSubcommand::User(ServerUserSubcommand::Import(cmd)) => {
+            super::import::server_import(cmd, users_root);
+        },
+        ServerSubcommand::User(ServerUserSubcommand::Verify(cmd)) => {
+            super::user::verify(system_config, cmd, users_root);
+        },
+        ServerSubcommand::LoginThrottle(cmd) => {
+            super::login_throttle::main(system_config, cmd, users_root);
         },
         ServerSubcommand::ServeImaps(_) => {
             super::serve::imaps(system_config, root, users_root);
@@ -29,6 +29,10 @@ pub mod main;
 mod imap_test;
 
 mod deliver;
+mod dovecot;
+mod export;
+mod import;
+mod login_throttle;
 mod remote;
 mod sanity;
 mod serve;
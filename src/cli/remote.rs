@@ -17,16 +17,21 @@
 // Crymap. If not, see <http://www.gnu.org/licenses/>.
 
 use std::borrow::Cow;
-use std::io::{self, BufRead, Write};
+use std::io::{self, BufRead, Read, Write};
 use std::net::{self, ToSocketAddrs};
+use std::path::Path;
 
 use openssl::ssl::{HandshakeError, SslConnector, SslMethod, SslVerifyMode};
 use thiserror::Error;
 
+use super::export::{ExportFormat, Exporter};
+use super::import::SourceMailbox;
 use super::main::*;
 use crate::{
-    imap::{client::Client, syntax as s},
+    imap::{client::Client, syntax as s, MailboxName},
+    mime::utf7,
     support::rcio::*,
+    support::safe_name::is_safe_name,
 };
 
 type RemoteClient = Client<Box<dyn BufRead>, Box<dyn Write>>;
@@ -73,6 +78,33 @@ fn main_impl(mut cmd: RemoteSubcommand) -> Result<(), Error> {
         RemoteSubcommand::RetryEmail(cmd) => {
             retry_email(&mut client, cmd.message_id)?;
         },
+        RemoteSubcommand::AppPassword(AppPasswordCommand::List(_)) => {
+            list_app_passwords(&mut client)?;
+        },
+        RemoteSubcommand::AppPassword(AppPasswordCommand::Create(cmd)) => {
+            create_app_password(&mut client, cmd)?;
+        },
+        RemoteSubcommand::AppPassword(AppPasswordCommand::Revoke(cmd)) => {
+            revoke_app_password(&mut client, cmd.name)?;
+        },
+        RemoteSubcommand::LoginHistory(cmd) => {
+            login_history(&mut client, cmd.limit)?;
+        },
+        RemoteSubcommand::Import(cmd) => {
+            import(&mut client, &cmd.source)?;
+        },
+        RemoteSubcommand::Export(cmd) => {
+            export(&mut client, cmd.format, &cmd.output)?;
+        },
+        RemoteSubcommand::Restore(cmd) => {
+            restore(&mut client, cmd)?;
+        },
+        RemoteSubcommand::Unexpunge(cmd) => {
+            unexpunge(&mut client, cmd)?;
+        },
+        RemoteSubcommand::MailboxRetention(cmd) => {
+            mailbox_retention(&mut client, cmd)?;
+        },
     }
 
     let mut buffer = Vec::new();
@@ -324,6 +356,38 @@ fn config(
         ));
     }
 
+    if let Some(s) = cmd.new_device_notifications {
+        require_configurable(&current_config, "NEW-DEVICE-NOTIFICATIONS");
+        configs.push(s::XCryUserConfigOption::NewDeviceNotifications(
+            if "on" == s {
+                s::XCryToggle::On
+            } else {
+                s::XCryToggle::Off
+            },
+        ));
+    }
+
+    if let Some(days) = cmd.expunge_retention_days {
+        require_configurable(&current_config, "EXPUNGE-RETENTION");
+        configs.push(s::XCryUserConfigOption::ExpungeRetention(days));
+    }
+
+    if let Some(days) = cmd.key_retirement_days {
+        require_configurable(&current_config, "KEY-RETIREMENT");
+        configs.push(s::XCryUserConfigOption::KeyRetirement(days));
+    }
+
+    if let Some(algorithm) = cmd.key_algorithm {
+        require_configurable(&current_config, "KEY-ALGORITHM");
+        configs.push(s::XCryUserConfigOption::KeyAlgorithm(
+            if "x25519" == algorithm {
+                s::XCryKeyAlgorithm::X25519
+            } else {
+                s::XCryKeyAlgorithm::Rsa
+            },
+        ));
+    }
+
     if configs.is_empty() {
         println!(
             "Current configuration:\n\
@@ -364,6 +428,35 @@ fn config(
                 )) => {
                     println!("\tsmtp-out-failure-receipts: delivered to {mb}");
                 },
+                s::XCry2UserConfigData::NewDeviceNotifications(
+                    s::XCryToggle::On,
+                ) => {
+                    println!("\tnew-device-notifications: on");
+                },
+                s::XCry2UserConfigData::NewDeviceNotifications(
+                    s::XCryToggle::Off,
+                ) => {
+                    println!("\tnew-device-notifications: off");
+                },
+                s::XCry2UserConfigData::ExpungeRetention(days) => {
+                    println!("\texpunge-retention-days: {days}");
+                },
+                s::XCry2UserConfigData::KeyRetirement(0) => {
+                    println!("\tkey-retirement-days: off");
+                },
+                s::XCry2UserConfigData::KeyRetirement(days) => {
+                    println!("\tkey-retirement-days: {days}");
+                },
+                s::XCry2UserConfigData::KeyAlgorithm(
+                    s::XCryKeyAlgorithm::Rsa,
+                ) => {
+                    println!("\tkey-algorithm: rsa");
+                },
+                s::XCry2UserConfigData::KeyAlgorithm(
+                    s::XCryKeyAlgorithm::X25519,
+                ) => {
+                    println!("\tkey-algorithm: x25519");
+                },
                 s::XCry2UserConfigData::Unknown(..) => {},
             }
         }
@@ -497,3 +590,769 @@ fn require_smtp_out_support(client: &mut RemoteClient) -> Result<(), Error> {
         "Crymap server does not support SMTP-OUT extensions",
     )
 }
+
+fn list_app_passwords(client: &mut RemoteClient) -> Result<(), Error> {
+    require_app_password_support(client)?;
+
+    let mut buffer = Vec::new();
+    let mut responses = client.command(
+        s::Command::XCryAppPassword(s::XCryAppPasswordCommand::List(())),
+        &mut buffer,
+    )?;
+    die_if_not_success("APP-PASSWORD LIST", responses.pop().unwrap());
+
+    if responses.is_empty() {
+        println!("no application passwords");
+    }
+
+    for line in responses {
+        if let s::Response::XCryAppPassword(data) = line.response {
+            println!(
+                "{name}: {restriction}; created {created}; last used {used}",
+                name = data.name,
+                restriction = match data.restriction {
+                    s::XCryAppPasswordRestriction::Any => "any protocol",
+                    s::XCryAppPasswordRestriction::ImapOnly => "IMAP only",
+                    s::XCryAppPasswordRestriction::SubmissionOnly => {
+                        "SMTP submission only"
+                    },
+                },
+                created = data.created.to_rfc3339(),
+                used = data
+                    .last_used
+                    .map(|dt| dt.to_rfc3339())
+                    .unwrap_or_else(|| "never".to_owned()),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn create_app_password(
+    client: &mut RemoteClient,
+    cmd: CreateAppPasswordCommand,
+) -> Result<(), Error> {
+    require_app_password_support(client)?;
+
+    let restriction = if cmd.imap_only {
+        s::XCryAppPasswordRestriction::ImapOnly
+    } else if cmd.submission_only {
+        s::XCryAppPasswordRestriction::SubmissionOnly
+    } else {
+        s::XCryAppPasswordRestriction::Any
+    };
+
+    let mut buffer = Vec::new();
+    let mut responses = client.command(
+        s::Command::XCryAppPassword(s::XCryAppPasswordCommand::Create(
+            s::XCryAppPasswordCreateCommand {
+                name: Cow::Owned(cmd.name),
+                restriction,
+            },
+        )),
+        &mut buffer,
+    )?;
+    die_if_not_success("APP-PASSWORD CREATE", responses.pop().unwrap());
+
+    for line in responses {
+        if let s::Response::XCryAppPasswordCreated(data) = line.response {
+            println!(
+                "Application password '{}' created.\n\
+                 Password: {}\n\
+                 This password will not be shown again.",
+                data.name, data.password,
+            );
+            return Ok(());
+        }
+    }
+
+    die!(EX_PROTOCOL, "Server did not return the new password")
+}
+
+fn revoke_app_password(
+    client: &mut RemoteClient,
+    name: String,
+) -> Result<(), Error> {
+    require_app_password_support(client)?;
+
+    let mut buffer = Vec::new();
+    let mut responses = client.command(
+        s::Command::XCryAppPassword(s::XCryAppPasswordCommand::Revoke(
+            Cow::Owned(name),
+        )),
+        &mut buffer,
+    )?;
+    die_if_not_success("APP-PASSWORD REVOKE", responses.pop().unwrap());
+    Ok(())
+}
+
+fn require_app_password_support(
+    client: &mut RemoteClient,
+) -> Result<(), Error> {
+    let mut buffer = Vec::new();
+    let mut responses = client.command(
+        s::Command::Simple(s::SimpleCommand::XCryGetUserConfig),
+        &mut buffer,
+    )?;
+    die_if_not_success("GET-USER-CONFIG", responses.pop().unwrap());
+
+    let current_config = responses
+        .into_iter()
+        .filter_map(|r| match r.response {
+            s::Response::XCryUserConfig(c) => Some(c),
+            _ => None,
+        })
+        .next()
+        .unwrap_or_else(|| die!(EX_PROTOCOL, "No user config returned"));
+
+    require_configurable(&current_config, "APP-PASSWORD");
+    Ok(())
+}
+
+fn login_history(client: &mut RemoteClient, limit: u32) -> Result<(), Error> {
+    require_login_history_support(client)?;
+
+    let mut buffer = Vec::new();
+    let mut responses =
+        client.command(s::Command::XCryLoginHistory(limit), &mut buffer)?;
+    die_if_not_success("LOGIN-HISTORY", responses.pop().unwrap());
+
+    if responses.is_empty() {
+        println!("no logins recorded");
+    }
+
+    for line in responses {
+        if let s::Response::XCryLoginHistory(data) = line.response {
+            println!(
+                "{at} {outcome} {protocol} from {peer_ip}\n\
+                 \tclient: {client}\n\
+                 \ttls: {tls}{app_password}",
+                at = data.at.to_rfc3339(),
+                outcome = match data.outcome {
+                    s::XCryLoginOutcome::Success => "SUCCESS",
+                    s::XCryLoginOutcome::Failure => "FAILURE",
+                },
+                protocol = data.protocol,
+                peer_ip = data.peer_ip.as_deref().unwrap_or("unknown address"),
+                client = data.client_id.as_deref().unwrap_or("unknown"),
+                tls = data.tls.as_deref().unwrap_or("none"),
+                app_password = data
+                    .app_password
+                    .map(|ap| format!("\n\tapplication password: {ap}"))
+                    .unwrap_or_default(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn require_login_history_support(
+    client: &mut RemoteClient,
+) -> Result<(), Error> {
+    let mut buffer = Vec::new();
+    let mut responses = client.command(
+        s::Command::Simple(s::SimpleCommand::XCryGetUserConfig),
+        &mut buffer,
+    )?;
+    die_if_not_success("GET-USER-CONFIG", responses.pop().unwrap());
+
+    let current_config = responses
+        .into_iter()
+        .filter_map(|r| match r.response {
+            s::Response::XCryUserConfig(c) => Some(c),
+            _ => None,
+        })
+        .next()
+        .unwrap_or_else(|| die!(EX_PROTOCOL, "No user config returned"));
+
+    require_configurable(&current_config, "LOGIN-HISTORY");
+    Ok(())
+}
+
+/// The maximum number of messages sent in one MULTIAPPEND.
+const IMPORT_BATCH_MESSAGES: usize = 100;
+/// The number of bytes after which a MULTIAPPEND is ended.
+const IMPORT_BATCH_BYTES: usize = 16 * 1024 * 1024;
+
+fn import(client: &mut RemoteClient, source: &Path) -> Result<(), Error> {
+    check_capabilities(client, &["MULTIAPPEND", "CREATE-SPECIAL-USE"])?;
+
+    let mailboxes = super::import::scan(source)?;
+    for mailbox in mailboxes {
+        if "INBOX" != mailbox.name {
+            create_for_import(client, &mailbox)?;
+        }
+
+        let wire_name = utf7::IMAP.encode(&mailbox.name).into_owned();
+        let mut count = 0usize;
+        let mut batch_messages = 0usize;
+        let mut batch_bytes = 0usize;
+        mailbox.for_each_message(|message| -> Result<(), Error> {
+            let fragment = s::AppendFragment {
+                flags: Some(message.flags).filter(|f| !f.is_empty()),
+                internal_date: message.internal_date,
+                utf8: false,
+                catenate: false,
+            };
+
+            if 0 == batch_messages {
+                client.start_append(&wire_name, fragment, &message.data)?;
+            } else {
+                client.append_item(fragment, &message.data)?;
+            }
+
+            count += 1;
+            batch_messages += 1;
+            batch_bytes += message.data.len();
+            if batch_messages >= IMPORT_BATCH_MESSAGES
+                || batch_bytes >= IMPORT_BATCH_BYTES
+            {
+                finish_import_batch(client)?;
+                batch_messages = 0;
+                batch_bytes = 0;
+            }
+
+            Ok(())
+        })?;
+
+        if batch_messages > 0 {
+            finish_import_batch(client)?;
+        }
+
+        println!("{}: {} messages", mailbox.name, count);
+    }
+
+    Ok(())
+}
+
+fn create_for_import(
+    client: &mut RemoteClient,
+    mailbox: &SourceMailbox,
+) -> Result<(), Error> {
+    let mut special_use = mailbox.special_use;
+    loop {
+        let mut buffer = Vec::new();
+        let mut responses = client.command(
+            s::Command::Create(s::CreateCommand {
+                mailbox: MailboxName::of_wire(utf7::IMAP.encode(&mailbox.name)),
+                special_use: special_use
+                    .map(|attr| vec![Cow::Borrowed(attr.name())]),
+            }),
+            &mut buffer,
+        )?;
+
+        let response = responses.pop().unwrap();
+        match response.response {
+            s::Response::Cond(s::CondResponse {
+                cond: s::RespCondType::No,
+                code: Some(s::RespTextCode::AlreadyExists(())),
+                ..
+            }) => return Ok(()),
+
+            s::Response::Cond(s::CondResponse {
+                cond: s::RespCondType::No,
+                code: Some(s::RespTextCode::UseAttr(())),
+                ..
+            }) if special_use.is_some() => {
+                eprintln!(
+                    "Creating {} without special use {}",
+                    mailbox.name,
+                    special_use.unwrap().name(),
+                );
+                special_use = None;
+            },
+
+            _ => {
+                die_if_not_success("CREATE", response);
+                return Ok(());
+            },
+        }
+    }
+}
+
+fn finish_import_batch(client: &mut RemoteClient) -> Result<(), Error> {
+    let mut buffer = Vec::new();
+    let mut responses = client.finish_append(&mut buffer)?;
+    die_if_not_success("APPEND", responses.pop().unwrap());
+    Ok(())
+}
+
+fn export(
+    client: &mut RemoteClient,
+    format: ExportFormat,
+    output: &Path,
+) -> Result<(), Error> {
+    require_export_support(client)?;
+
+    let mut exporter = Exporter::open(format, output)?;
+    for (wire_name, name) in list_exportable_mailboxes(client)? {
+        let count = loop {
+            if let Some(count) =
+                export_mailbox(client, &mut exporter, &wire_name, &name)?
+            {
+                break count;
+            }
+        };
+
+        println!("{}: {} new messages", name, count);
+    }
+
+    exporter.finish()?;
+    Ok(())
+}
+
+/// Lists all selectable mailboxes, returning their wire and UTF-8 names.
+fn list_exportable_mailboxes(
+    client: &mut RemoteClient,
+) -> Result<Vec<(String, String)>, Error> {
+    let mut buffer = Vec::new();
+    let mut responses = client.command(
+        s::Command::List(s::ListCommand {
+            select_opts: None,
+            reference: MailboxName::of_wire(Cow::Borrowed("")),
+            pattern: s::MboxOrPat::Single(MailboxName::of_wire(Cow::Borrowed(
+                "*",
+            ))),
+            return_opts: None,
+        }),
+        &mut buffer,
+    )?;
+    die_if_not_success("LIST", responses.pop().unwrap());
+
+    let mut mailboxes = Vec::new();
+    for response in responses {
+        let s::Response::List(list) = response.response else {
+            continue;
+        };
+
+        if list.flags.iter().any(|f| {
+            f.eq_ignore_ascii_case("\\Noselect")
+                || f.eq_ignore_ascii_case("\\NonExistent")
+        }) {
+            continue;
+        }
+
+        let name = list.name.get_utf8(false).into_owned();
+        // The names become file names, so don't trust the server to have
+        // sanitised them.
+        if !name.split('/').all(is_safe_name) {
+            eprintln!("Skipping mailbox with unsafe name: {:?}", name);
+            continue;
+        }
+
+        mailboxes.push((list.name.raw.into_owned(), name));
+    }
+
+    Ok(mailboxes)
+}
+
+/// Exports new messages and flag changes from one mailbox.
+///
+/// Returns the number of new messages, or `None` if the export of this
+/// mailbox needs to be restarted due to a UIDVALIDITY change.
+fn export_mailbox(
+    client: &mut RemoteClient,
+    exporter: &mut Exporter,
+    wire_name: &str,
+    name: &str,
+) -> Result<Option<usize>, Error> {
+    let (uid, modseq) = exporter.resume_point(name);
+    let mut proceed = false;
+    let mut highest_modseq = 0u64;
+    let mut count = 0usize;
+
+    let mut buffer = Vec::new();
+    let response = client.command_streaming(
+        s::Command::XCryExport(s::XCryExportCommand {
+            mailbox: MailboxName::of_wire(Cow::Borrowed(wire_name)),
+            uid,
+            modseq,
+        }),
+        &mut buffer,
+        |response| -> Result<(), Error> {
+            match response.response {
+                s::Response::XCryExportMailbox(data) => {
+                    proceed =
+                        exporter.begin_mailbox(name, data.uid_validity)?;
+                    highest_modseq = data.highest_modseq;
+                },
+
+                s::Response::XCryExport(atts) if proceed => {
+                    let mut uid = 0u32;
+                    let mut flags = Vec::new();
+                    let mut internal_date = None;
+                    let mut data = None::<Vec<u8>>;
+                    for att in atts.atts {
+                        match att {
+                            s::MsgAtt::Uid(u) => uid = u,
+                            s::MsgAtt::Flags(
+                                s::FlagsFetch::Recent(f)
+                                | s::FlagsFetch::NotRecent(f),
+                            ) => flags = f,
+                            s::MsgAtt::InternalDate(d) => {
+                                internal_date = Some(d)
+                            },
+                            s::MsgAtt::Body(mut body) => {
+                                let mut buf =
+                                    Vec::with_capacity(body.data.len as usize);
+                                body.data.data.read_to_end(&mut buf)?;
+                                data = Some(buf);
+                            },
+                            _ => (),
+                        }
+                    }
+
+                    if let Some(data) = data {
+                        exporter.add_message(
+                            uid,
+                            &flags,
+                            internal_date,
+                            &data,
+                        )?;
+                        count += 1;
+                    } else {
+                        exporter.update_flags(uid, &flags)?;
+                    }
+                },
+
+                _ => (),
+            }
+
+            Ok(())
+        },
+    )?;
+    die_if_not_success("EXPORT", response);
+
+    if !proceed {
+        return Ok(None);
+    }
+
+    exporter.end_mailbox(highest_modseq)?;
+    Ok(Some(count))
+}
+
+fn require_export_support(client: &mut RemoteClient) -> Result<(), Error> {
+    let mut buffer = Vec::new();
+    let mut responses = client.command(
+        s::Command::Simple(s::SimpleCommand::XCryGetUserConfig),
+        &mut buffer,
+    )?;
+    die_if_not_success("GET-USER-CONFIG", responses.pop().unwrap());
+
+    let current_config = responses
+        .into_iter()
+        .filter_map(|r| match r.response {
+            s::Response::XCryUserConfig(c) => Some(c),
+            _ => None,
+        })
+        .next()
+        .unwrap_or_else(|| die!(EX_PROTOCOL, "No user config returned"));
+
+    require_configurable(&current_config, "EXPORT");
+    Ok(())
+}
+
+fn restore(
+    client: &mut RemoteClient,
+    cmd: RestoreCommand,
+) -> Result<(), Error> {
+    require_restore_support(client)?;
+
+    let mut buffer = Vec::new();
+    let (Some(date), mailbox, uids) = (cmd.date, cmd.mailbox, cmd.uids) else {
+        let mut responses = client.command(
+            s::Command::XCryRestore(s::XCryRestoreCommand::List(())),
+            &mut buffer,
+        )?;
+        die_if_not_success("RESTORE LIST", responses.pop().unwrap());
+
+        if responses.is_empty() {
+            println!("no backups available");
+        }
+        for line in responses {
+            if let s::Response::XCryRestoreBackup(date) = line.response {
+                println!("{date}");
+            }
+        }
+
+        return Ok(());
+    };
+
+    let Some(mailbox) = mailbox else {
+        let mut responses = client.command(
+            s::Command::XCryRestore(s::XCryRestoreCommand::Mailboxes(
+                Cow::Borrowed(&date),
+            )),
+            &mut buffer,
+        )?;
+        die_if_not_success("RESTORE MAILBOXES", responses.pop().unwrap());
+
+        if responses.is_empty() {
+            println!("nothing to restore from {date}");
+        }
+        for line in responses {
+            if let s::Response::XCryRestoreMailbox(data) = line.response {
+                println!(
+                    "{name}: {messages} messages{deleted}",
+                    name = data.name.get_utf8(false),
+                    messages = data.messages,
+                    deleted = match data.status {
+                        s::XCryRestoreMailboxStatus::Deleted => " (deleted)",
+                        s::XCryRestoreMailboxStatus::Exists => "",
+                    },
+                );
+            }
+        }
+
+        return Ok(());
+    };
+
+    let wire_name = MailboxName::of_wire(utf7::IMAP.encode(&mailbox));
+    let Some(uids) = uids else {
+        let mut responses = client.command(
+            s::Command::XCryRestore(s::XCryRestoreCommand::Messages(
+                s::XCryRestoreMessagesCommand {
+                    date: Cow::Borrowed(&date),
+                    mailbox: wire_name,
+                },
+            )),
+            &mut buffer,
+        )?;
+        die_if_not_success("RESTORE MESSAGES", responses.pop().unwrap());
+
+        if responses.is_empty() {
+            println!("nothing to restore in {mailbox} from {date}");
+        }
+        for line in responses {
+            if let s::Response::XCryRestoreMessage(data) = line.response {
+                println!(
+                    "{uid}\t{savedate}\t{subject}",
+                    uid = data.uid,
+                    savedate = data.savedate.to_rfc3339(),
+                    subject = data.subject.as_deref().unwrap_or("(no subject)"),
+                );
+            }
+        }
+
+        return Ok(());
+    };
+
+    let mut responses = client.command(
+        s::Command::XCryRestore(s::XCryRestoreCommand::Copy(
+            s::XCryRestoreCopyCommand {
+                date: Cow::Borrowed(&date),
+                mailbox: wire_name,
+                uids: Cow::Borrowed(&uids),
+            },
+        )),
+        &mut buffer,
+    )?;
+    die_if_not_success("RESTORE COPY", responses.pop().unwrap());
+
+    for line in responses {
+        if let s::Response::XCryRestored(count) = line.response {
+            println!("{count} messages restored into Restored");
+        }
+    }
+
+    Ok(())
+}
+
+fn require_restore_support(client: &mut RemoteClient) -> Result<(), Error> {
+    let mut buffer = Vec::new();
+    let mut responses = client.command(
+        s::Command::Simple(s::SimpleCommand::XCryGetUserConfig),
+        &mut buffer,
+    )?;
+    die_if_not_success("GET-USER-CONFIG", responses.pop().unwrap());
+
+    let current_config = responses
+        .into_iter()
+        .filter_map(|r| match r.response {
+            s::Response::XCryUserConfig(c) => Some(c),
+            _ => None,
+        })
+        .next()
+        .unwrap_or_else(|| die!(EX_PROTOCOL, "No user config returned"));
+
+    require_configurable(&current_config, "RESTORE");
+    Ok(())
+}
+
+fn unexpunge(
+    client: &mut RemoteClient,
+    cmd: UnexpungeCommand,
+) -> Result<(), Error> {
+    require_unexpunge_support(client)?;
+
+    let mut buffer = Vec::new();
+    let (Some(mailbox), Some(uids)) = (cmd.mailbox, cmd.uids) else {
+        let mut responses = client.command(
+            s::Command::XCryUnexpunge(s::XCryUnexpungeCommand::List(())),
+            &mut buffer,
+        )?;
+        die_if_not_success("UNEXPUNGE LIST", responses.pop().unwrap());
+
+        if responses.is_empty() {
+            println!("nothing to unexpunge");
+        }
+        for line in responses {
+            if let s::Response::XCryUnexpungeable(data) = line.response {
+                println!(
+                    "{mailbox}\t{uid}\t{expunged}\t{subject}",
+                    mailbox = data.mailbox.get_utf8(false),
+                    uid = data.uid,
+                    expunged = data.expunged.to_rfc3339(),
+                    subject = data.subject.as_deref().unwrap_or("(no subject)"),
+                );
+            }
+        }
+
+        return Ok(());
+    };
+
+    let mut responses = client.command(
+        s::Command::XCryUnexpunge(s::XCryUnexpungeCommand::Restore(
+            s::XCryUnexpungeRestoreCommand {
+                mailbox: MailboxName::of_wire(utf7::IMAP.encode(&mailbox)),
+                uids: Cow::Borrowed(&uids),
+            },
+        )),
+        &mut buffer,
+    )?;
+    die_if_not_success("UNEXPUNGE RESTORE", responses.pop().unwrap());
+
+    for line in responses {
+        if let s::Response::XCryUnexpunged(count) = line.response {
+            println!("{count} messages put back into {mailbox}");
+        }
+    }
+
+    Ok(())
+}
+
+fn require_unexpunge_support(client: &mut RemoteClient) -> Result<(), Error> {
+    let mut buffer = Vec::new();
+    let mut responses = client.command(
+        s::Command::Simple(s::SimpleCommand::XCryGetUserConfig),
+        &mut buffer,
+    )?;
+    die_if_not_success("GET-USER-CONFIG", responses.pop().unwrap());
+
+    let current_config = responses
+        .into_iter()
+        .filter_map(|r| match r.response {
+            s::Response::XCryUserConfig(c) => Some(c),
+            _ => None,
+        })
+        .next()
+        .unwrap_or_else(|| die!(EX_PROTOCOL, "No user config returned"));
+
+    require_configurable(&current_config, "UNEXPUNGE");
+    Ok(())
+}
+
+fn mailbox_retention(
+    client: &mut RemoteClient,
+    cmd: MailboxRetentionCommand,
+) -> Result<(), Error> {
+    require_mailbox_retention_support(client)?;
+
+    let mut buffer = Vec::new();
+    let Some(mailbox) = cmd.mailbox else {
+        let mut responses = client.command(
+            s::Command::Simple(s::SimpleCommand::XCryGetMailboxRetention),
+            &mut buffer,
+        )?;
+        die_if_not_success("GET-MAILBOX-RETENTION", responses.pop().unwrap());
+
+        if responses.is_empty() {
+            println!("no mailboxes have retention rules");
+        }
+        for line in responses {
+            if let s::Response::XCryMailboxRetention(data) = line.response {
+                let rules = data
+                    .rules
+                    .iter()
+                    .map(|rule| match *rule {
+                        s::XCryRetentionRule::MaxAge(days) => {
+                            format!("max-age-days={days}")
+                        },
+                        s::XCryRetentionRule::AgeBasis(
+                            s::XCryRetentionAgeBasis::SaveDate,
+                        ) => "by-save-date".to_owned(),
+                        s::XCryRetentionRule::AgeBasis(
+                            s::XCryRetentionAgeBasis::InternalDate,
+                        ) => "by-internal-date".to_owned(),
+                        s::XCryRetentionRule::MaxMessages(max) => {
+                            format!("max-messages={max}")
+                        },
+                        s::XCryRetentionRule::OnlySeen(()) => {
+                            "only-seen".to_owned()
+                        },
+                    })
+                    .collect::<Vec<_>>();
+                println!(
+                    "{mailbox}\t{rules}",
+                    mailbox = data.mailbox.get_utf8(false),
+                    rules = rules.join(" "),
+                );
+            }
+        }
+
+        return Ok(());
+    };
+
+    let mut rules = Vec::new();
+    if let Some(days) = cmd.max_age_days {
+        rules.push(s::XCryRetentionRule::MaxAge(days));
+    }
+    if cmd.by_internal_date {
+        rules.push(s::XCryRetentionRule::AgeBasis(
+            s::XCryRetentionAgeBasis::InternalDate,
+        ));
+    }
+    if let Some(max) = cmd.max_messages {
+        rules.push(s::XCryRetentionRule::MaxMessages(max));
+    }
+    if cmd.only_seen {
+        rules.push(s::XCryRetentionRule::OnlySeen(()));
+    }
+
+    let mut responses = client.command(
+        s::Command::XCrySetMailboxRetention(s::XCryMailboxRetentionData {
+            mailbox: MailboxName::of_wire(utf7::IMAP.encode(&mailbox)),
+            rules,
+        }),
+        &mut buffer,
+    )?;
+    die_if_not_success("SET-MAILBOX-RETENTION", responses.pop().unwrap());
+    println!("OK");
+
+    Ok(())
+}
+
+fn require_mailbox_retention_support(
+    client: &mut RemoteClient,
+) -> Result<(), Error> {
+    let mut buffer = Vec::new();
+    let mut responses = client.command(
+        s::Command::Simple(s::SimpleCommand::XCryGetUserConfig),
+        &mut buffer,
+    )?;
+    die_if_not_success("GET-USER-CONFIG", responses.pop().unwrap());
+
+    let current_config = responses
+        .into_iter()
+        .filter_map(|r| match r.response {
+            s::Response::XCryUserConfig(c) => Some(c),
+            _ => None,
+        })
+        .next()
+        .unwrap_or_else(|| die!(EX_PROTOCOL, "No user config returned"));
+
+    require_configurable(&current_config, "MAILBOX-RETENTION");
+    Ok(())
+}
@@ -101,12 +101,16 @@ pub async fn imaps(
 
     info!("{} SSL handshake succeeded", log_prefix);
 
-    let processor = CommandProcessor::new(
+    let mut processor = CommandProcessor::new(
         log_prefix.clone(),
         system_config,
         users_root,
         dns_resolver,
     );
+    if let Some(peer_ip) = peer_ip() {
+        processor.set_peer_ip(peer_ip);
+    }
+    processor.set_tls(io.ssl_string());
     let local_set = tokio::task::LocalSet::new();
     local_set
         .run_until(crate::imap::server::run(io, processor))
@@ -165,20 +169,7 @@ pub async fn smtpin(
     let (log_prefix, _peer_name) =
         configure_system("smtpin", &system_config, &mut users_root);
 
-    let peer_ip = if let Ok(addr) =
-        nix::sys::socket::getpeername::<nix::sys::socket::SockaddrIn>(STDIN)
-    {
-        IpAddr::V4(*std::net::SocketAddrV4::from(addr).ip())
-    } else if let Ok(addr) =
-        nix::sys::socket::getpeername::<nix::sys::socket::SockaddrIn6>(STDIN)
-    {
-        let addr = *std::net::SocketAddrV6::from(addr).ip();
-        if let Some(v4) = addr.to_ipv4_mapped() {
-            IpAddr::V4(v4)
-        } else {
-            IpAddr::V6(addr)
-        }
-    } else {
+    let Some(peer_ip) = peer_ip() else {
         fatal!(EX_OSERR, "stdin does not seem to be a TCP connection");
     };
 
@@ -296,6 +287,7 @@ pub async fn smtpsub(
             ssl_acceptor,
             users_root,
             host_name.clone(),
+            peer_ip(),
             Box::new(move |account, id| {
                 tokio::task::spawn_local({
                     let log_prefix = log_prefix2.clone();
@@ -472,3 +464,26 @@ fn configure_system(
     info!("{} Connection established", log_prefix);
     (log_prefix, peer_name)
 }
+
+/// Returns the IP address of the client connected to stdin, if it is a TCP
+/// connection.
+///
+/// IPv4-mapped IPv6 addresses are converted to plain IPv4 addresses.
+fn peer_ip() -> Option<IpAddr> {
+    if let Ok(addr) =
+        nix::sys::socket::getpeername::<nix::sys::socket::SockaddrIn>(STDIN)
+    {
+        Some(IpAddr::V4(*std::net::SocketAddrV4::from(addr).ip()))
+    } else if let Ok(addr) =
+        nix::sys::socket::getpeername::<nix::sys::socket::SockaddrIn6>(STDIN)
+    {
+        let addr = *std::net::SocketAddrV6::from(addr).ip();
+        if let Some(v4) = addr.to_ipv4_mapped() {
+            Some(IpAddr::V4(v4))
+        } else {
+            Some(IpAddr::V6(addr))
+        }
+    } else {
+        None
+    }
+}
@@ -17,18 +17,34 @@
 // Crymap. If not, see <http://www.gnu.org/licenses/>.
 
 use std::fs;
+use std::io::Read;
 use std::os::unix::fs::DirBuilderExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use log::warn;
 use rand::{rngs::OsRng, Rng};
 
-use super::main::ServerUserAddSubcommand;
-use crate::account::v2::Account;
+use super::dovecot;
+use super::main::{
+    ServerUserAddSubcommand, ServerUserRecoverSubcommand,
+    ServerUserVerifySubcommand,
+};
+use crate::account::{
+    model::SetUserConfigRequest,
+    v2::{account_config_file, Account},
+};
 use crate::crypt::master_key::MasterKey;
-use crate::support::{log_prefix::LogPrefix, safe_name::is_safe_name};
+use crate::support::{
+    log_prefix::LogPrefix, safe_name::is_safe_name,
+    system_config::SystemConfig, unix_privileges, user_config::UserConfig,
+};
 
-pub(super) fn add(cmd: ServerUserAddSubcommand, users_root: PathBuf) {
+pub(super) fn add(
+    system_config: SystemConfig,
+    cmd: ServerUserAddSubcommand,
+    users_root: PathBuf,
+) {
     if !is_safe_name(&cmd.name) {
         die!(EX_USAGE, "Invalid user name: {}", cmd.name);
     }
@@ -54,6 +70,21 @@ pub(super) fn add(cmd: ServerUserAddSubcommand, users_root: PathBuf) {
         die!(EX_CANTCREAT, "User '{}' already exists", cmd.name);
     }
 
+    // Resolve the migration source before creating anything, and before
+    // switching users, since that also changes the working directory.
+    let dovecot_maildir =
+        cmd.dovecot_maildir
+            .as_ref()
+            .map(|path| match fs::canonicalize(path) {
+                Ok(path) if path.join("cur").is_dir() => path,
+                Ok(_) => die!(
+                    EX_USAGE,
+                    "'{}' is not a Maildir directory",
+                    path.display()
+                ),
+                Err(e) => die!(EX_NOINPUT, "{}: {}", path.display(), e),
+            });
+
     if actual_path.is_dir() {
         die!(
             EX_CANTCREAT,
@@ -96,19 +127,7 @@ pub(super) fn add(cmd: ServerUserAddSubcommand, users_root: PathBuf) {
         None
     };
 
-    let password = if cmd.prompt_password {
-        match rpassword::prompt_password("Password: ").and_then(|a| {
-            rpassword::prompt_password("Confirm: ").map(|b| (a, b))
-        }) {
-            Err(e) => die!(EX_NOINPUT, "Failed to read password: {}", e),
-            Ok((a, b)) if a != b => die!(EX_DATAERR, "Passwords don't match"),
-            Ok((a, _)) if a.is_empty() => die!(EX_NOINPUT, "No password given"),
-            Ok((a, _)) => a,
-        }
-    } else {
-        let data: [u8; 8] = OsRng.gen();
-        base64::encode(data)
-    };
+    let password = obtain_password(cmd.prompt_password);
 
     if let Err(e) = fs::DirBuilder::new().mode(0o770).create(&actual_path) {
         die!(
@@ -164,14 +183,239 @@ pub(super) fn add(cmd: ServerUserAddSubcommand, users_root: PathBuf) {
 
     let log_prefix = LogPrefix::new("account-setup".to_owned());
     log_prefix.set_user(cmd.name.clone());
-    if let Err(e) =
-        Account::new(log_prefix, actual_path, Arc::new(MasterKey::new()))
-            .and_then(|mut account| account.provision(password.as_bytes()))
-    {
-        die!(EX_SOFTWARE, "Error provisioning account: {}", e);
+    let mut account =
+        match Account::new(log_prefix, actual_path, Arc::new(MasterKey::new()))
+            .and_then(|mut account| {
+                account.apply_security_config(&system_config.security);
+                account.provision(password.as_bytes())?;
+                Ok(account)
+            }) {
+            Ok(account) => account,
+            Err(e) => die!(EX_SOFTWARE, "Error provisioning account: {}", e),
+        };
+
+    if let Some(dovecot_maildir) = dovecot_maildir {
+        if let Err(e) = dovecot::migrate(&mut account, &dovecot_maildir) {
+            die!(
+                EX_SOFTWARE,
+                "Error migrating '{}': {}",
+                dovecot_maildir.display(),
+                e
+            );
+        }
+    }
+
+    if !cmd.prompt_password {
+        println!("Password: {}", password);
     }
+}
+
+pub(super) fn recover(
+    system_config: SystemConfig,
+    cmd: ServerUserRecoverSubcommand,
+    users_root: PathBuf,
+) {
+    if !is_safe_name(&cmd.name) {
+        die!(EX_USAGE, "Invalid user name: {}", cmd.name);
+    }
+
+    // Load the recovery key before dropping privileges, since it is unlikely
+    // to be readable by the user.
+    let recovery_key = match fs::read(&cmd.recovery_key) {
+        Ok(pem) => match openssl::rsa::Rsa::private_key_from_pem(&pem) {
+            Ok(key) => key,
+            Err(e) => die!(
+                EX_DATAERR,
+                "'{}' is not a PEM-format RSA private key: {}",
+                cmd.recovery_key.display(),
+                e
+            ),
+        },
+        Err(e) => die!(
+            EX_NOINPUT,
+            "Failed to read '{}': {}",
+            cmd.recovery_key.display(),
+            e
+        ),
+    };
+
+    let log_prefix = LogPrefix::new("account-recovery".to_owned());
+    log_prefix.set_user(cmd.name.clone());
+
+    let mut user_dir = users_root.join(&cmd.name);
+    if !user_dir.is_dir() {
+        die!(EX_NOUSER, "User '{}' does not exist", cmd.name);
+    }
+
+    if let Err(exit) = unix_privileges::assume_user_privileges(
+        &log_prefix.to_string(),
+        false,
+        &mut user_dir,
+        false,
+    ) {
+        exit.exit();
+    }
+
+    let user_config = read_user_config(&user_dir);
+    if !user_config.master_key.has_recovery_escrow() {
+        die!(
+            EX_UNAVAILABLE,
+            "User '{}' has no recovery escrow. The password must have been\n\
+             set after `recovery_public_key` was configured.",
+            cmd.name
+        );
+    }
+
+    let Some(master_key) =
+        MasterKey::from_recovery(&user_config.master_key, &recovery_key)
+    else {
+        die!(
+            EX_DATAERR,
+            "Failed to recover the master key of '{}'; is '{}' the right \
+             recovery key?",
+            cmd.name,
+            cmd.recovery_key.display()
+        );
+    };
+
+    let password = obtain_password(cmd.prompt_password);
+
+    let backup_file =
+        Account::new(log_prefix.clone(), user_dir, Arc::new(master_key))
+            .and_then(|mut account| {
+                account.init(&user_config.key_store)?;
+                account.apply_security_config(&system_config.security);
+                account.update_config(SetUserConfigRequest {
+                    password: Some(password.clone()),
+                    ..SetUserConfigRequest::default()
+                })
+            });
+    let backup_file = match backup_file {
+        Ok(backup_file) => backup_file,
+        Err(e) => die!(EX_SOFTWARE, "Error resetting password: {}", e),
+    };
 
+    warn!("{} Password reset via recovery key", log_prefix);
+    println!("Password reset. Old configuration backed up to {backup_file}");
     if !cmd.prompt_password {
         println!("Password: {}", password);
     }
 }
+
+pub(super) fn verify(
+    system_config: SystemConfig,
+    cmd: ServerUserVerifySubcommand,
+    users_root: PathBuf,
+) {
+    if !is_safe_name(&cmd.name) {
+        die!(EX_USAGE, "Invalid user name: {}", cmd.name);
+    }
+
+    let log_prefix = LogPrefix::new("verify".to_owned());
+    log_prefix.set_user(cmd.name.clone());
+
+    let mut user_dir = users_root.join(&cmd.name);
+    if !user_dir.is_dir() {
+        die!(EX_NOUSER, "User '{}' does not exist", cmd.name);
+    }
+
+    if let Err(exit) = unix_privileges::assume_user_privileges(
+        &log_prefix.to_string(),
+        false,
+        &mut user_dir,
+        false,
+    ) {
+        exit.exit();
+    }
+
+    let user_config = read_user_config(&user_dir);
+    let password = match rpassword::prompt_password("Password: ") {
+        Ok(password) => password,
+        Err(e) => die!(EX_NOINPUT, "Failed to read password: {}", e),
+    };
+    let Some(master_key) =
+        MasterKey::from_config(&user_config.master_key, password.as_bytes())
+    else {
+        die!(EX_NOPERM, "Incorrect password for '{}'", cmd.name);
+    };
+
+    let report = Account::new(log_prefix, user_dir, Arc::new(master_key))
+        .and_then(|mut account| {
+            account.init(&user_config.key_store)?;
+            account.apply_security_config(&system_config.security);
+            account.verify(cmd.quarantine.as_deref())
+        });
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => die!(EX_SOFTWARE, "Error verifying account: {}", e),
+    };
+
+    for problem in &report.database_problems {
+        println!("database: {problem}");
+    }
+    for message in &report.damaged_messages {
+        println!("damaged: {}: {}", message.path, message.problem);
+    }
+    for path in &report.unaccounted_files {
+        println!("unaccounted: {path}");
+    }
+    println!(
+        "{} messages checked, {} damaged, {} unaccounted files, \
+         {} database problems",
+        report.messages_checked,
+        report.damaged_messages.len(),
+        report.unaccounted_files.len(),
+        report.database_problems.len(),
+    );
+    if let Some(ref quarantine) = cmd.quarantine {
+        println!("{} messages moved into {quarantine}", report.quarantined);
+    }
+
+    if !report.database_problems.is_empty()
+        || !report.damaged_messages.is_empty()
+        || !report.unaccounted_files.is_empty()
+    {
+        crate::support::sysexits::EX_DATAERR.exit();
+    }
+}
+
+fn read_user_config(user_dir: &Path) -> UserConfig {
+    let user_config_path = account_config_file(user_dir);
+    let mut user_config_toml = Vec::new();
+    if let Err(e) = fs::File::open(&user_config_path)
+        .and_then(|mut f| f.read_to_end(&mut user_config_toml))
+    {
+        die!(
+            EX_NOINPUT,
+            "Error reading '{}': {}",
+            user_config_path.display(),
+            e
+        );
+    }
+
+    match toml::from_slice(&user_config_toml) {
+        Ok(config) => config,
+        Err(e) => die!(
+            EX_DATAERR,
+            "Error in '{}': {}",
+            user_config_path.display(),
+            e
+        ),
+    }
+}
+
+fn obtain_password(prompt: bool) -> String {
+    if prompt {
+        match rpassword::prompt_password("Password: ").and_then(|a| {
+            rpassword::prompt_password("Confirm: ").map(|b| (a, b))
+        }) {
+            Err(e) => die!(EX_NOINPUT, "Failed to read password: {}", e),
+            Ok((a, b)) if a != b => die!(EX_DATAERR, "Passwords don't match"),
+            Ok((a, _)) if a.is_empty() => die!(EX_NOINPUT, "No password given"),
+            Ok((a, _)) => a,
+        }
+    } else {
+        let data: [u8; 8] = OsRng.gen();
+        base64::encode(data)
+    }
+}
@@ -19,10 +19,10 @@
 //! Support for encrypting and decrypting data streams.
 //!
 //! A data stream is a payload encrypted with a random key itself encrypted
-//! with RSA, so that data streams can be written by non-logged-in processes
-//! but only read by those with the user's credentials. As the data is more
-//! sensitive, authenticated encryption is used to ensure tampering is
-//! impossible.
+//! with a public key (RSA or X25519), so that data streams can be written by
+//! non-logged-in processes but only read by those with the user's
+//! credentials. As the data is more sensitive, authenticated encryption is
+//! used to ensure tampering is impossible.
 //!
 //! Data streams are broken into "slabs" of up to 65536 bytes, each of which is
 //! a separate encryption. The division is not semantically important, but is
@@ -43,6 +43,13 @@
 //! - Ciphertext of exactly the given length
 //! - 16-byte authentication tag
 //!
+//! With X25519, the session key is encrypted by generating an ephemeral X25519
+//! key pair and performing ECDH with the recipient's public key. A one-time
+//! AES-128-GCM key is derived from the shared secret and both public keys with
+//! KMAC-128 and used (with an all-zero nonce, since the key is never reused)
+//! to encrypt the session key. The stored encrypted key is the ephemeral
+//! public key, the encrypted session key, and the authentication tag.
+//!
 //! When writing, slab sizes are chosen on a deterministic schedule so that
 //! information about the content is not leaked through the slab sizes. The
 //! exact sizes are encoded anyway both to facilitate future changes to the
@@ -54,12 +61,13 @@ use std::sync::Arc;
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use openssl::{
-    pkey::{HasPublic, Private},
-    rsa::Rsa,
+    derive::Deriver,
+    pkey::{HasPublic, Id, PKey, PKeyRef, Private},
 };
 use rand::{rngs::OsRng, Rng};
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use tiny_keccak::{Hasher, Kmac};
 
 use super::{master_key::MasterKey, AES_BLOCK};
 use crate::support::compression::Compression;
@@ -79,8 +87,11 @@ enum Algorithm {
 #[repr(u8)]
 enum MetaAlgorithm {
     RsaPkcs1Oaep = 0,
+    X25519Kmac128Aes128Gcm = 1,
 }
 
+const X25519_KEY_LEN: usize = 32;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Metadata {
     /// The encryption algorithm used.
@@ -98,7 +109,8 @@ pub struct Metadata {
     /// The name of the key used to encrypt the data.
     #[serde(rename = "m")]
     pub meta_key_id: String,
-    /// The RSA-encrypted form of the encryption key.
+    /// The encrypted form of the encryption key, as determined by
+    /// `meta_algorithm`.
     #[serde(rename = "k", with = "serde_bytes")]
     encrypted_key: Vec<u8>,
 }
@@ -142,11 +154,9 @@ impl<R: Read> Reader<R> {
     pub fn new(
         mut reader: R,
         cached_session_key: Option<CachedSessionKey<'_>>,
-        priv_key_lookup: impl FnOnce(&str) -> Result<Arc<Rsa<Private>>, Error>,
+        priv_key_lookup: impl FnOnce(&str) -> Result<Arc<PKey<Private>>, Error>,
     ) -> Result<Self, Error> {
-        let meta_length = reader.read_u16::<LittleEndian>()?;
-        let meta: Metadata =
-            serde_cbor::from_reader(reader.by_ref().take(meta_length.into()))?;
+        let meta = read_metadata(&mut reader)?;
 
         let key = if let Some(csk) = cached_session_key {
             let mut key = *csk.session_key;
@@ -154,29 +164,7 @@ impl<R: Read> Reader<R> {
                 .crypt_cached_session_key(&mut key, csk.message_id);
             key
         } else {
-            let priv_key = priv_key_lookup(&meta.meta_key_id)?;
-
-            match meta.meta_algorithm {
-                MetaAlgorithm::RsaPkcs1Oaep => {
-                    // private_decrypt() requires the output buffer to be at least
-                    // the size of the RSA modulus
-                    let mut buf =
-                        vec![0u8; (priv_key.size() as usize).max(AES_BLOCK)];
-                    if AES_BLOCK
-                        != priv_key.private_decrypt(
-                            &meta.encrypted_key,
-                            &mut buf,
-                            openssl::rsa::Padding::PKCS1_OAEP,
-                        )?
-                    {
-                        return Err(Error::BadEncryptedKey);
-                    }
-
-                    let mut k = [0u8; AES_BLOCK];
-                    k.copy_from_slice(&buf[..AES_BLOCK]);
-                    k
-                },
-            }
+            decrypt_session_key(&meta, priv_key_lookup)?
         };
 
         Ok(Reader {
@@ -289,32 +277,21 @@ impl<W: Write> Writer<W> {
     /// `pub_key_name` must correspond to the name of `pub_key`.
     pub fn new(
         mut writer: W,
-        pub_key: &Rsa<impl HasPublic>,
+        pub_key: &PKeyRef<impl HasPublic>,
         pub_key_name: String,
         compression: Compression,
     ) -> Result<Self, Error> {
         let key: [u8; AES_BLOCK] = OsRng.gen();
-        let mut encrypted_key = vec![0u8; pub_key.size().try_into().unwrap()];
-        let encrypted_key_length = pub_key.public_encrypt(
-            &key,
-            &mut encrypted_key,
-            openssl::rsa::Padding::PKCS1_OAEP,
-        )?;
-        encrypted_key.resize(encrypted_key_length, 0);
-
+        let (meta_algorithm, encrypted_key) =
+            encrypt_session_key(&key, pub_key)?;
         let meta = Metadata {
             algorithm: Algorithm::Aes128Gcm,
-            meta_algorithm: MetaAlgorithm::RsaPkcs1Oaep,
+            meta_algorithm,
             compression,
             meta_key_id: pub_key_name,
             encrypted_key,
         };
-        let meta_bytes = serde_cbor::to_vec(&meta)?;
-        assert!(meta_bytes.len() < 65536);
-
-        writer
-            .write_u16::<LittleEndian>(meta_bytes.len().try_into().unwrap())?;
-        writer.write_all(&meta_bytes)?;
+        write_metadata(&mut writer, &meta)?;
 
         Ok(Writer {
             writer,
@@ -380,6 +357,183 @@ impl<W: Write> Write for Writer<W> {
     }
 }
 
+/// Re-encrypts the session key of the data stream read from `reader` for
+/// `pub_key`, writing the resulting data stream to `writer`.
+///
+/// `should_rewrap` is first invoked with the name of the key the stream
+/// currently uses. If it returns `false`, nothing is written and this returns
+/// `Ok(false)`. Otherwise, `priv_key_lookup` is used to decrypt the session
+/// key as with `Reader::new()`.
+///
+/// The slabs are copied verbatim. Since the session key itself is unchanged,
+/// cached session keys remain valid for the new stream.
+pub fn rewrap(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    should_rewrap: impl FnOnce(&str) -> bool,
+    priv_key_lookup: impl FnOnce(&str) -> Result<Arc<PKey<Private>>, Error>,
+    pub_key: &PKeyRef<impl HasPublic>,
+    pub_key_name: String,
+) -> Result<bool, Error> {
+    let meta = read_metadata(&mut reader)?;
+    if !should_rewrap(&meta.meta_key_id) {
+        return Ok(false);
+    }
+
+    let key = decrypt_session_key(&meta, priv_key_lookup)?;
+    let (meta_algorithm, encrypted_key) = encrypt_session_key(&key, pub_key)?;
+    let meta = Metadata {
+        meta_algorithm,
+        meta_key_id: pub_key_name,
+        encrypted_key,
+        ..meta
+    };
+    write_metadata(&mut writer, &meta)?;
+    io::copy(&mut reader, &mut writer)?;
+    Ok(true)
+}
+
+fn read_metadata(mut reader: impl Read) -> Result<Metadata, Error> {
+    let meta_length = reader.read_u16::<LittleEndian>()?;
+    serde_cbor::from_reader(reader.take(meta_length.into())).map_err(Into::into)
+}
+
+fn write_metadata(
+    mut writer: impl Write,
+    meta: &Metadata,
+) -> Result<(), Error> {
+    let meta_bytes = serde_cbor::to_vec(meta)?;
+    assert!(meta_bytes.len() < 65536);
+
+    writer.write_u16::<LittleEndian>(meta_bytes.len().try_into().unwrap())?;
+    writer.write_all(&meta_bytes)?;
+    Ok(())
+}
+
+fn decrypt_session_key(
+    meta: &Metadata,
+    priv_key_lookup: impl FnOnce(&str) -> Result<Arc<PKey<Private>>, Error>,
+) -> Result<[u8; AES_BLOCK], Error> {
+    let priv_key = priv_key_lookup(&meta.meta_key_id)?;
+
+    match meta.meta_algorithm {
+        MetaAlgorithm::RsaPkcs1Oaep => {
+            let priv_key = priv_key.rsa()?;
+            // private_decrypt() requires the output buffer to be at least the
+            // size of the RSA modulus
+            let mut buf = vec![0u8; (priv_key.size() as usize).max(AES_BLOCK)];
+            if AES_BLOCK
+                != priv_key.private_decrypt(
+                    &meta.encrypted_key,
+                    &mut buf,
+                    openssl::rsa::Padding::PKCS1_OAEP,
+                )?
+            {
+                return Err(Error::BadEncryptedKey);
+            }
+
+            let mut k = [0u8; AES_BLOCK];
+            k.copy_from_slice(&buf[..AES_BLOCK]);
+            Ok(k)
+        },
+
+        MetaAlgorithm::X25519Kmac128Aes128Gcm => {
+            if Id::X25519 != priv_key.id()
+                || X25519_KEY_LEN + 2 * AES_BLOCK != meta.encrypted_key.len()
+            {
+                return Err(Error::BadEncryptedKey);
+            }
+
+            let (ephemeral_pub, rest) =
+                meta.encrypted_key.split_at(X25519_KEY_LEN);
+            let (ciphertext, tag) = rest.split_at(AES_BLOCK);
+            let ephemeral_pub =
+                PKey::public_key_from_raw_bytes(ephemeral_pub, Id::X25519)?;
+
+            let mut deriver = Deriver::new(&priv_key)?;
+            deriver.set_peer(&ephemeral_pub)?;
+            let wrapping_key = x25519_wrapping_key(
+                &deriver.derive_to_vec()?,
+                &ephemeral_pub.raw_public_key()?,
+                &priv_key.raw_public_key()?,
+            );
+
+            let cleartext = openssl::symm::decrypt_aead(
+                openssl::symm::Cipher::aes_128_gcm(),
+                &wrapping_key,
+                Some(&[0u8; 12]),
+                &[],
+                ciphertext,
+                tag,
+            )
+            .map_err(|_| Error::BadEncryptedKey)?;
+
+            let mut k = [0u8; AES_BLOCK];
+            k.copy_from_slice(&cleartext);
+            Ok(k)
+        },
+    }
+}
+
+fn encrypt_session_key(
+    key: &[u8; AES_BLOCK],
+    pub_key: &PKeyRef<impl HasPublic>,
+) -> Result<(MetaAlgorithm, Vec<u8>), Error> {
+    if Id::X25519 == pub_key.id() {
+        let ephemeral = PKey::generate_x25519()?;
+        let ephemeral_pub = ephemeral.raw_public_key()?;
+
+        let mut deriver = Deriver::new(&ephemeral)?;
+        deriver.set_peer(pub_key)?;
+        let wrapping_key = x25519_wrapping_key(
+            &deriver.derive_to_vec()?,
+            &ephemeral_pub,
+            &pub_key.raw_public_key()?,
+        );
+
+        let mut tag = [0u8; AES_BLOCK];
+        let ciphertext = openssl::symm::encrypt_aead(
+            openssl::symm::Cipher::aes_128_gcm(),
+            &wrapping_key,
+            Some(&[0u8; 12]),
+            &[],
+            key,
+            &mut tag,
+        )?;
+
+        let mut encrypted_key = ephemeral_pub;
+        encrypted_key.extend_from_slice(&ciphertext);
+        encrypted_key.extend_from_slice(&tag);
+        return Ok((MetaAlgorithm::X25519Kmac128Aes128Gcm, encrypted_key));
+    }
+
+    let pub_key = pub_key.rsa()?;
+    let mut encrypted_key = vec![0u8; pub_key.size().try_into().unwrap()];
+    let encrypted_key_length = pub_key.public_encrypt(
+        key,
+        &mut encrypted_key,
+        openssl::rsa::Padding::PKCS1_OAEP,
+    )?;
+    encrypted_key.resize(encrypted_key_length, 0);
+    Ok((MetaAlgorithm::RsaPkcs1Oaep, encrypted_key))
+}
+
+/// Derives the one-time key used to encrypt a session key from an X25519
+/// shared secret and the two public keys involved.
+fn x25519_wrapping_key(
+    shared_secret: &[u8],
+    ephemeral_pub: &[u8],
+    recipient_pub: &[u8],
+) -> [u8; AES_BLOCK] {
+    let mut k = Kmac::v128(shared_secret, b"data-stream-x25519");
+    k.update(ephemeral_pub);
+    k.update(recipient_pub);
+
+    let mut ret = [0u8; AES_BLOCK];
+    k.finalize(&mut ret);
+    ret
+}
+
 fn to_ioerr(e: openssl::error::ErrorStack) -> io::Error {
     io::Error::new(io::ErrorKind::InvalidData, e)
 }
@@ -452,4 +606,130 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn x25519_session_key() {
+        let mut ciphertext = Vec::<u8>::new();
+        {
+            let mut writer = Writer::new(
+                &mut ciphertext,
+                &X25519A,
+                "x".to_owned(),
+                Compression::DEFAULT_FOR_MESSAGE,
+            )
+            .unwrap();
+            writer.write_all(b"hello world").unwrap();
+            writer.flush().unwrap();
+        }
+
+        let mut reader =
+            Reader::new(&ciphertext[..], None, |_| Ok(Arc::clone(&X25519A)))
+                .unwrap();
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(b"hello world", &decrypted[..]);
+
+        let other_key = Arc::new(PKey::generate_x25519().unwrap());
+        assert!(matches!(
+            Reader::new(&ciphertext[..], None, |_| Ok(other_key)),
+            Err(Error::BadEncryptedKey),
+        ));
+        assert!(Reader::new(&ciphertext[..], None, |_| Ok(Arc::clone(
+            &RSA1024A
+        )))
+        .is_err());
+    }
+
+    #[test]
+    fn rewrap() {
+        let cleartext = b"hello world".repeat(1000);
+        let mut ciphertext = Vec::<u8>::new();
+        {
+            let mut writer = Writer::new(
+                &mut ciphertext,
+                &RSA1024A,
+                "old".to_owned(),
+                Compression::DEFAULT_FOR_MESSAGE,
+            )
+            .unwrap();
+            writer.write_all(&cleartext).unwrap();
+            writer.flush().unwrap();
+        }
+
+        let mut rewrapped = Vec::<u8>::new();
+        assert!(!super::rewrap(
+            &ciphertext[..],
+            &mut rewrapped,
+            |name| "new" == name,
+            |_| panic!("unexpected key lookup"),
+            &RSA1024B,
+            "new".to_owned(),
+        )
+        .unwrap());
+        assert!(rewrapped.is_empty());
+
+        assert!(super::rewrap(
+            &ciphertext[..],
+            &mut rewrapped,
+            |name| "old" == name,
+            |name| {
+                assert_eq!("old", name);
+                Ok(Arc::clone(&RSA1024A))
+            },
+            &RSA1024B,
+            "new".to_owned(),
+        )
+        .unwrap());
+
+        let mut reader = Reader::new(&rewrapped[..], None, |name| {
+            assert_eq!("new", name);
+            Ok(Arc::clone(&RSA1024B))
+        })
+        .unwrap();
+        assert_eq!("new", reader.metadata.meta_key_id);
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(cleartext, decrypted);
+    }
+
+    #[test]
+    fn rewrap_rsa_to_x25519() {
+        let cleartext = b"hello world".repeat(1000);
+        let mut ciphertext = Vec::<u8>::new();
+        {
+            let mut writer = Writer::new(
+                &mut ciphertext,
+                &RSA1024A,
+                "old".to_owned(),
+                Compression::DEFAULT_FOR_MESSAGE,
+            )
+            .unwrap();
+            writer.write_all(&cleartext).unwrap();
+            writer.flush().unwrap();
+        }
+
+        let mut rewrapped = Vec::<u8>::new();
+        assert!(super::rewrap(
+            &ciphertext[..],
+            &mut rewrapped,
+            |name| "old" == name,
+            |_| Ok(Arc::clone(&RSA1024A)),
+            &X25519A,
+            "new".to_owned(),
+        )
+        .unwrap());
+
+        let mut reader = Reader::new(&rewrapped[..], None, |name| {
+            assert_eq!("new", name);
+            Ok(Arc::clone(&X25519A))
+        })
+        .unwrap();
+        assert_eq!(
+            MetaAlgorithm::X25519Kmac128Aes128Gcm,
+            reader.metadata.meta_algorithm,
+        );
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(cleartext, decrypted);
+    }
 }
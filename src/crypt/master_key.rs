@@ -27,6 +27,10 @@
 //! hashed again with two different suffixes to produce the final password hash
 //! and the "derived key".
 //!
+//! New configurations always use Argon2id with the cost parameters configured
+//! by the administrator. Older configurations may use a weaker algorithm or
+//! weaker parameters, and are re-wrapped on the next successful login.
+//!
 //! The final password hash is stored in the user configuration, and makes it
 //! easy to determine whether the input password is correct.
 //!
@@ -34,6 +38,11 @@
 //! also stored in the user config, to derive the master key. This makes it
 //! possible for arbitrary passwords to derive arbitrary master keys.
 //!
+//! If the system has a recovery public key configured, the master key is also
+//! encrypted with RSA-OAEP under that key and stored alongside the password
+//! hash. This allows an administrator holding the recovery private key to
+//! regain access to the master key and reset the user's password.
+//!
 //! Several secondary key families are derived from the master key:
 //!
 //! - AES key = `KMAC128(master_key, filename, 16, "aes")`, used for all
@@ -48,8 +57,12 @@
 //! on destruction.
 
 use chrono::prelude::*;
+use openssl::{
+    pkey::{Private, Public},
+    rsa::{Padding, Rsa},
+};
 use rand::{rngs::OsRng, Rng};
-use secstr::SecBox;
+use secstr::{SecBox, SecVec};
 use serde::{Deserialize, Serialize};
 use tiny_keccak::{Hasher, Kmac};
 
@@ -68,8 +81,122 @@ pub enum Algorithm {
     ///
     /// The final password hash is `KMAC256(salt, argon2_hash, 32, "check")`.
     /// The derived key is `KMAC256(salt, argon_hash, 32, "master")`
-    #[default]
+    ///
+    /// This is only used to read configurations generated by older versions.
     Argon2i_V13_M4096_T10_L1_Kmac256,
+    /// Use the Argon2id 1.3 algorithm with the memory cost, time cost, and
+    /// lanes given by `MasterKeyConfig::argon2_params` and a hash length of
+    /// 32, with no associated data or secret.
+    ///
+    /// The final password hash and derived key are computed as with
+    /// `Argon2i_V13_M4096_T10_L1_Kmac256`.
+    #[default]
+    Argon2id_V13_Kmac256,
+}
+
+impl Algorithm {
+    /// Returns the Argon2 configuration for this algorithm, or `None` if it
+    /// requires parameters that were not given.
+    fn argon2_config(
+        self,
+        params: Option<&Argon2Params>,
+    ) -> Option<argon2::Config<'static>> {
+        match self {
+            Algorithm::Argon2i_V13_M4096_T10_L1_Kmac256 => {
+                Some(argon2::Config {
+                    hash_length: 32,
+                    lanes: 1,
+                    mem_cost: 4096,
+                    thread_mode: argon2::ThreadMode::Sequential,
+                    time_cost: 10,
+                    variant: argon2::Variant::Argon2i,
+                    version: argon2::Version::Version13,
+                    ..argon2::Config::default()
+                })
+            },
+            Algorithm::Argon2id_V13_Kmac256 => {
+                let params = params?;
+                Some(argon2::Config {
+                    hash_length: 32,
+                    lanes: params.lanes,
+                    mem_cost: params.memory_cost_kib,
+                    thread_mode: argon2::ThreadMode::Sequential,
+                    time_cost: params.time_cost,
+                    variant: argon2::Variant::Argon2id,
+                    version: argon2::Version::Version13,
+                    ..argon2::Config::default()
+                })
+            },
+        }
+    }
+}
+
+/// Cost parameters for Argon2id password hashing.
+///
+/// This is used both for the administrator's configuration in
+/// `SecurityConfig` and to record the parameters used for a particular
+/// `MasterKeyConfig`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "RawArgon2Params")]
+pub struct Argon2Params {
+    /// The memory cost, in KiB.
+    pub memory_cost_kib: u32,
+    /// The number of passes over the memory.
+    pub time_cost: u32,
+    /// The degree of parallelism. The lanes are computed sequentially, so
+    /// this does not affect how many threads are used.
+    pub lanes: u32,
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Self {
+            memory_cost_kib: 65536,
+            time_cost: 3,
+            lanes: 1,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct RawArgon2Params {
+    memory_cost_kib: u32,
+    time_cost: u32,
+    lanes: u32,
+}
+
+impl Default for RawArgon2Params {
+    fn default() -> Self {
+        let d = Argon2Params::default();
+        Self {
+            memory_cost_kib: d.memory_cost_kib,
+            time_cost: d.time_cost,
+            lanes: d.lanes,
+        }
+    }
+}
+
+impl TryFrom<RawArgon2Params> for Argon2Params {
+    type Error = String;
+
+    fn try_from(raw: RawArgon2Params) -> Result<Self, String> {
+        if !(1..=64).contains(&raw.lanes) {
+            return Err("lanes must be between 1 and 64".to_owned());
+        }
+        if raw.time_cost < 1 {
+            return Err("time_cost must be at least 1".to_owned());
+        }
+        if raw.memory_cost_kib < 8 * raw.lanes {
+            return Err("memory_cost_kib must be at least 8 * lanes".to_owned());
+        }
+
+        Ok(Self {
+            memory_cost_kib: raw.memory_cost_kib,
+            time_cost: raw.time_cost,
+            lanes: raw.lanes,
+        })
+    }
 }
 
 /// Configuration which represents the derivation of the master key.
@@ -89,11 +216,36 @@ pub struct MasterKeyConfig {
     /// Currently, this is expected to always be exactly 32 bytes long.
     #[serde(with = "b64")]
     master_key_xor: Vec<u8>,
+    /// The master key encrypted with the system recovery public key using
+    /// RSA-OAEP, if a recovery key was configured when this config was
+    /// generated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    recovery_escrow: Option<String>,
     /// The last time at which the master key was changed.
     ///
     /// The `MasterKey` code itself always generates `None` for this value.
     #[serde(default)]
     pub last_changed: Option<DateTime<FixedOffset>>,
+    /// The cost parameters for `algorithm`, if it takes any.
+    ///
+    /// This must come last since TOML requires tables to come after values.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    argon2_params: Option<Argon2Params>,
+}
+
+impl MasterKeyConfig {
+    /// Returns whether this config was generated with an algorithm or
+    /// parameters other than the current default algorithm with `params`.
+    pub fn is_outdated(&self, params: &Argon2Params) -> bool {
+        Algorithm::default() != self.algorithm
+            || Some(params) != self.argon2_params.as_ref()
+    }
+
+    /// Returns whether this config carries a copy of the master key that can
+    /// be recovered with the system recovery private key.
+    pub fn has_recovery_escrow(&self) -> bool {
+        self.recovery_escrow.is_some()
+    }
 }
 
 /// A randomly generated master key and secondary keys derived from it.
@@ -119,6 +271,14 @@ impl MasterKey {
         MasterKey { master_key: key }
     }
 
+    /// Overwrites the key with zeroes.
+    ///
+    /// This already happens when the `MasterKey` is dropped; this exists for
+    /// callers that want to be certain the key is gone at a particular point.
+    pub fn zeroise(&mut self) {
+        *self.master_key.unsecure_mut() = [0u8; MASTER_SIZE];
+    }
+
     /// Return the PEM passphrase to use for an RSA private key of the given
     /// name.
     pub fn pem_passphrase(&self, key_name: &str) -> String {
@@ -150,6 +310,17 @@ impl MasterKey {
         hash
     }
 
+    /// Generates the key used to authenticate the pages of the given
+    /// database file.
+    pub(super) fn page_mac_key(&self, filename: &str) -> [u8; 32] {
+        let mut k = Kmac::v256(self.master_key.unsecure(), b"page-mac-key");
+        k.update(filename.as_bytes());
+
+        let mut hash = [0u8; 32];
+        k.finalize(&mut hash);
+        hash
+    }
+
     /// Encrypts or decrypts a cached session key.
     ///
     /// The session key is encrypted by XORing it with a one-time-pad generated
@@ -177,25 +348,52 @@ impl MasterKey {
     ///
     /// The salt is randomly generated each call, so repeated calls will not
     /// yield identical objects.
+    ///
+    /// The password is hashed with the default algorithm using `params`.
+    ///
+    /// If `recovery_key` is given, the master key is additionally escrowed
+    /// under that key so that `from_recovery()` can be used to recover it.
     pub fn make_config(
         &self,
         password: &[u8],
+        params: &Argon2Params,
+        recovery_key: Option<&Rsa<Public>>,
     ) -> Result<MasterKeyConfig, argon2::Error> {
         let salt: [u8; 32] = OsRng.gen();
+        let argon2_config = Algorithm::default()
+            .argon2_config(Some(params))
+            .expect("default algorithm rejected explicit parameters");
         let (password_hash, derived_key) =
-            hash_password(password, &salt, Algorithm::default())?;
+            hash_password(password, &salt, &argon2_config)?;
 
         let mut master_key_xor = vec![0u8; MASTER_SIZE];
         for i in 0..MASTER_SIZE {
             master_key_xor[i] = self.master_key.unsecure()[i] ^ derived_key[i];
         }
 
+        let recovery_escrow = recovery_key.map(|recovery_key| {
+            let mut escrow = vec![0u8; recovery_key.size() as usize];
+            let len = recovery_key
+                .public_encrypt(
+                    &self.master_key.unsecure()[..],
+                    &mut escrow,
+                    Padding::PKCS1_OAEP,
+                )
+                // The key size is validated when the system config is loaded,
+                // so this can only fail due to an OpenSSL bug.
+                .expect("RSA encryption of master key failed");
+            escrow.truncate(len);
+            base64::encode(escrow)
+        });
+
         Ok(MasterKeyConfig {
             password_hash: password_hash[..].to_owned(),
             salt: salt[..].to_owned(),
             algorithm: Algorithm::default(),
             master_key_xor,
+            recovery_escrow,
             last_changed: None,
+            argon2_params: Some(*params),
         })
     }
 
@@ -207,8 +405,10 @@ impl MasterKey {
         conf: &MasterKeyConfig,
         password: &[u8],
     ) -> Option<Self> {
+        let argon2_config =
+            conf.algorithm.argon2_config(conf.argon2_params.as_ref())?;
         let (password_hash, derived_key) =
-            hash_password(password, &conf.salt, conf.algorithm).ok()?;
+            hash_password(password, &conf.salt, &argon2_config).ok()?;
 
         if password_hash.len() != conf.password_hash.len()
             || !openssl::memcmp::eq(&password_hash, &conf.password_hash)
@@ -224,29 +424,48 @@ impl MasterKey {
 
         Some(MasterKey { master_key: key })
     }
+
+    /// Given a `MasterKeyConfig` generated by `make_config()` with a recovery
+    /// key and the private half of that recovery key, recover the
+    /// `MasterKey`.
+    ///
+    /// Returns `None` if the config has no recovery escrow or if it cannot be
+    /// decrypted with `recovery_key`.
+    pub fn from_recovery(
+        conf: &MasterKeyConfig,
+        recovery_key: &Rsa<Private>,
+    ) -> Option<Self> {
+        let escrow = base64::decode(conf.recovery_escrow.as_ref()?).ok()?;
+
+        // private_decrypt() requires the output buffer to be at least as
+        // large as the key.
+        let mut decrypted =
+            SecVec::new(vec![0u8; recovery_key.size() as usize]);
+        let len = recovery_key
+            .private_decrypt(
+                &escrow,
+                decrypted.unsecure_mut(),
+                Padding::PKCS1_OAEP,
+            )
+            .ok()?;
+        if MASTER_SIZE != len {
+            return None;
+        }
+
+        let mut key = SecBox::new(Box::new([0u8; MASTER_SIZE]));
+        key.unsecure_mut()
+            .copy_from_slice(&decrypted.unsecure()[..MASTER_SIZE]);
+
+        Some(MasterKey { master_key: key })
+    }
 }
 
 fn hash_password(
     password: &[u8],
     salt: &[u8],
-    algorithm: Algorithm,
+    argon2_config: &argon2::Config<'_>,
 ) -> Result<([u8; 32], [u8; 32]), argon2::Error> {
-    let raw_hash = match algorithm {
-        Algorithm::Argon2i_V13_M4096_T10_L1_Kmac256 => argon2::hash_raw(
-            password,
-            salt,
-            &argon2::Config {
-                hash_length: 32,
-                lanes: 1,
-                mem_cost: 4096,
-                thread_mode: argon2::ThreadMode::Sequential,
-                time_cost: 10,
-                variant: argon2::Variant::Argon2i,
-                version: argon2::Version::Version13,
-                ..argon2::Config::default()
-            },
-        )?,
-    };
+    let raw_hash = argon2::hash_raw(password, salt, argon2_config)?;
 
     let mut password_hash = [0u8; 32];
     {
@@ -269,25 +488,95 @@ fn hash_password(
 mod test {
     use super::*;
 
+    const TEST_PARAMS: Argon2Params = Argon2Params {
+        memory_cost_kib: 1024,
+        time_cost: 1,
+        lanes: 1,
+    };
+
     #[test]
     fn rederive_master_key() {
         let orig = MasterKey::new();
-        let config = orig.make_config(b"hunter2").unwrap();
+        let config = orig.make_config(b"hunter2", &TEST_PARAMS, None).unwrap();
         let derived = MasterKey::from_config(&config, b"hunter2").unwrap();
         assert_eq!(orig.master_key, derived.master_key);
     }
 
     #[test]
     fn derive_fails_for_bad_password() {
-        let config = MasterKey::new().make_config(b"hunter2").unwrap();
+        let config = MasterKey::new()
+            .make_config(b"hunter2", &TEST_PARAMS, None)
+            .unwrap();
         assert!(MasterKey::from_config(&config, b"hunter3").is_none());
     }
 
     #[test]
     fn config_generation_makes_distinct_hashes() {
         let key = MasterKey::new();
-        let config1 = key.make_config(b"hunter2").unwrap();
-        let config2 = key.make_config(b"hunter2").unwrap();
+        let config1 = key.make_config(b"hunter2", &TEST_PARAMS, None).unwrap();
+        let config2 = key.make_config(b"hunter2", &TEST_PARAMS, None).unwrap();
         assert_ne!(config1.password_hash, config2.password_hash);
     }
+
+    #[test]
+    fn derive_from_legacy_config() {
+        let orig = MasterKey::new();
+        let salt = [42u8; 32];
+        let argon2_config = Algorithm::Argon2i_V13_M4096_T10_L1_Kmac256
+            .argon2_config(None)
+            .unwrap();
+        let (password_hash, derived_key) =
+            hash_password(b"hunter2", &salt, &argon2_config).unwrap();
+        let config = MasterKeyConfig {
+            password_hash: password_hash.to_vec(),
+            salt: salt.to_vec(),
+            algorithm: Algorithm::Argon2i_V13_M4096_T10_L1_Kmac256,
+            master_key_xor: orig
+                .master_key
+                .unsecure()
+                .iter()
+                .zip(&derived_key)
+                .map(|(a, b)| a ^ b)
+                .collect(),
+            recovery_escrow: None,
+            last_changed: None,
+            argon2_params: None,
+        };
+
+        assert!(config.is_outdated(&TEST_PARAMS));
+        let derived = MasterKey::from_config(&config, b"hunter2").unwrap();
+        assert_eq!(orig.master_key, derived.master_key);
+
+        let config = orig.make_config(b"hunter2", &TEST_PARAMS, None).unwrap();
+        assert!(!config.is_outdated(&TEST_PARAMS));
+        assert!(config.is_outdated(&Argon2Params::default()));
+        let derived = MasterKey::from_config(&config, b"hunter2").unwrap();
+        assert_eq!(orig.master_key, derived.master_key);
+    }
+
+    #[test]
+    fn recover_master_key() {
+        let recovery_key = Rsa::generate(2048).unwrap();
+        let recovery_pub = Rsa::from_public_components(
+            recovery_key.n().to_owned().unwrap(),
+            recovery_key.e().to_owned().unwrap(),
+        )
+        .unwrap();
+
+        let orig = MasterKey::new();
+        let config = orig
+            .make_config(b"hunter2", &TEST_PARAMS, Some(&recovery_pub))
+            .unwrap();
+        assert!(config.has_recovery_escrow());
+        let recovered =
+            MasterKey::from_recovery(&config, &recovery_key).unwrap();
+        assert_eq!(orig.master_key, recovered.master_key);
+
+        let other_key = Rsa::generate(2048).unwrap();
+        assert!(MasterKey::from_recovery(&config, &other_key).is_none());
+
+        let config = orig.make_config(b"hunter2", &TEST_PARAMS, None).unwrap();
+        assert!(!config.has_recovery_escrow());
+        assert!(MasterKey::from_recovery(&config, &recovery_key).is_none());
+    }
 }
@@ -22,6 +22,7 @@ pub const AES_BLOCK64: u64 = AES_BLOCK as u64;
 pub mod data_stream;
 pub mod master_key;
 pub mod naked;
+pub mod page_mac;
 pub mod xex;
 
 #[cfg(test)]
@@ -0,0 +1,229 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+//! Authentication of individual SQLite database pages.
+//!
+//! XEX mode conceals the content of the database, but does nothing to stop
+//! someone with access to the file from flipping bits (which garbles the
+//! affected block in a way SQLite may well not notice) or from copying in an
+//! old version of a page. To detect this, the database is given
+//! `RESERVED_BYTES` of reserved space at the end of each page (space SQLite
+//! promises never to use), which holds a trailer of the form
+//!
+//! ```text
+//!   u64 LE: version
+//!   [u8; DIGEST_LEN]: digest
+//!   [u8; MAC_LEN]: KMAC-256(key, page_number || version || digest ||
+//!                            page_content)
+//! ```
+//!
+//! where `page_content` is everything before the trailer and the key is
+//! derived from the master key and the file name. Binding the page number
+//! prevents pages from being moved around within the file, and the key
+//! prevents them from being moved between files.
+//!
+//! The version is chosen by the caller. The SQLite VFS gives every page
+//! written in a transaction the same version, which is greater than that of
+//! any earlier transaction.
+//!
+//! The digest is only used on page 1 (which SQLite rewrites on every
+//! transaction) and is zero on every other page. It is a KMAC of the versions
+//! of all other pages in the file (see `PageMac::digest`), so that the current
+//! page 1 vouches for the exact version of every other page. A page replaced
+//! by an older (or newer) copy of itself still has a valid MAC, but no longer
+//! has the version page 1 expects.
+//!
+//! The trailer is computed on the cleartext; the whole page, trailer
+//! included, is then encrypted with XEX as before.
+
+use tiny_keccak::{Hasher, Kmac};
+
+use super::master_key::MasterKey;
+
+/// The number of bytes reserved at the end of each page.
+pub const RESERVED_BYTES: usize = 48;
+const VERSION_LEN: usize = 8;
+/// The length of the version digest held in the trailer of page 1.
+pub const DIGEST_LEN: usize = 16;
+const MAC_LEN: usize = RESERVED_BYTES - VERSION_LEN - DIGEST_LEN;
+
+/// The authenticated content of a page trailer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Trailer {
+    pub version: u64,
+    pub digest: [u8; DIGEST_LEN],
+}
+
+impl Trailer {
+    /// Returns the trailer for a page other than page 1 with the given
+    /// version.
+    pub fn page(version: u64) -> Self {
+        Self {
+            version,
+            digest: [0u8; DIGEST_LEN],
+        }
+    }
+}
+
+/// Computes and checks page trailers for a single database file.
+pub struct PageMac {
+    key: [u8; 32],
+}
+
+impl PageMac {
+    /// Creates a `PageMac` for the file with the given name.
+    pub fn new(master: &MasterKey, filename: &str) -> Self {
+        Self {
+            key: master.page_mac_key(filename),
+        }
+    }
+
+    /// Fills in the trailer of `page`, which is page number `page_number`
+    /// (1-based, as SQLite counts them).
+    ///
+    /// Panics if `page` is not longer than `RESERVED_BYTES`.
+    pub fn seal(&self, page: &mut [u8], page_number: u64, trailer: &Trailer) {
+        let (content, out) = page.split_at_mut(page.len() - RESERVED_BYTES);
+        let (version, out) = out.split_at_mut(VERSION_LEN);
+        let (digest, mac) = out.split_at_mut(DIGEST_LEN);
+        version.copy_from_slice(&trailer.version.to_le_bytes());
+        digest.copy_from_slice(&trailer.digest);
+        self.mac(content, page_number, trailer, mac);
+    }
+
+    /// Checks the trailer of `page`, which is page number `page_number`.
+    ///
+    /// Returns the trailer if it is valid, and `None` otherwise.
+    pub fn open(&self, page: &[u8], page_number: u64) -> Option<Trailer> {
+        if page.len() <= RESERVED_BYTES {
+            return None;
+        }
+
+        let (content, trailer) = page.split_at(page.len() - RESERVED_BYTES);
+        let (version, trailer) = trailer.split_at(VERSION_LEN);
+        let (digest, mac) = trailer.split_at(DIGEST_LEN);
+        let trailer = Trailer {
+            version: u64::from_le_bytes(version.try_into().unwrap()),
+            digest: digest.try_into().unwrap(),
+        };
+        let mut expected = [0u8; MAC_LEN];
+        self.mac(content, page_number, &trailer, &mut expected);
+
+        // The comparison doesn't need to be constant-time: an attacker
+        // probing the MAC byte by byte would need SQLite to tell them how far
+        // it got, and every failure is reported loudly.
+        (expected[..] == *mac).then_some(trailer)
+    }
+
+    /// Computes the digest page 1 holds for a file whose pages after page 1
+    /// have the given versions, in order.
+    pub fn digest(&self, versions: &[u64]) -> [u8; DIGEST_LEN] {
+        let mut k = Kmac::v256(&self.key, b"versions");
+        k.update(&(versions.len() as u64).to_le_bytes());
+        for version in versions {
+            k.update(&version.to_le_bytes());
+        }
+
+        let mut digest = [0u8; DIGEST_LEN];
+        k.finalize(&mut digest);
+        digest
+    }
+
+    fn mac(
+        &self,
+        content: &[u8],
+        page_number: u64,
+        trailer: &Trailer,
+        out: &mut [u8],
+    ) {
+        let mut k = Kmac::v256(&self.key, b"page");
+        k.update(&page_number.to_le_bytes());
+        k.update(&trailer.version.to_le_bytes());
+        k.update(&trailer.digest);
+        k.update(content);
+        k.finalize(out);
+    }
+}
+
+/// Extracts the version from the `RESERVED_BYTES` trailer of a page without
+/// authenticating it.
+pub fn unauthenticated_version(trailer: &[u8]) -> u64 {
+    u64::from_le_bytes(trailer[..VERSION_LEN].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        let master = MasterKey::new();
+        let mac = PageMac::new(&master, "foo");
+
+        let mut page = vec![42u8; 4096];
+        let trailer = Trailer {
+            version: 7,
+            digest: mac.digest(&[1, 2, 3]),
+        };
+        mac.seal(&mut page, 3, &trailer);
+        assert_eq!(Some(trailer), mac.open(&page, 3));
+        assert_eq!(7, unauthenticated_version(&page[4096 - RESERVED_BYTES..]));
+
+        // Wrong position.
+        assert_eq!(None, mac.open(&page, 4));
+        // Wrong file.
+        assert_eq!(None, PageMac::new(&master, "bar").open(&page, 3));
+        // Wrong key.
+        assert_eq!(None, PageMac::new(&MasterKey::new(), "foo").open(&page, 3));
+
+        // Modified content.
+        let mut modified = page.clone();
+        modified[100] ^= 1;
+        assert_eq!(None, mac.open(&modified, 3));
+
+        // Modified version.
+        let mut modified = page.clone();
+        modified[4096 - RESERVED_BYTES] ^= 1;
+        assert_eq!(None, mac.open(&modified, 3));
+
+        // Modified digest.
+        let mut modified = page.clone();
+        modified[4096 - RESERVED_BYTES + VERSION_LEN] ^= 1;
+        assert_eq!(None, mac.open(&modified, 3));
+
+        // Modified MAC.
+        let mut modified = page;
+        modified[4095] ^= 1;
+        assert_eq!(None, mac.open(&modified, 3));
+    }
+
+    #[test]
+    fn digest() {
+        let master = MasterKey::new();
+        let mac = PageMac::new(&master, "foo");
+
+        assert_eq!(mac.digest(&[1, 2, 3]), mac.digest(&[1, 2, 3]));
+        assert_ne!(mac.digest(&[1, 2, 3]), mac.digest(&[1, 1, 3]));
+        assert_ne!(mac.digest(&[1, 2, 3]), mac.digest(&[1, 2]));
+        assert_ne!(mac.digest(&[]), mac.digest(&[0]));
+        assert_ne!(
+            mac.digest(&[1, 2, 3]),
+            PageMac::new(&master, "bar").digest(&[1, 2, 3]),
+        );
+    }
+}
@@ -1,10 +1,14 @@
 use std::sync::Arc;
 
 use lazy_static::lazy_static;
-use openssl::pkey::Private;
+use openssl::pkey::{PKey, Private};
 use openssl::rsa::Rsa;
 
 lazy_static! {
-    pub static ref RSA1024A: Arc<Rsa<Private>> =
-        Arc::new(Rsa::generate(1024).unwrap());
+    pub static ref RSA1024A: Arc<PKey<Private>> =
+        Arc::new(PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap());
+    pub static ref RSA1024B: Arc<PKey<Private>> =
+        Arc::new(PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap());
+    pub static ref X25519A: Arc<PKey<Private>> =
+        Arc::new(PKey::generate_x25519().unwrap());
 }
@@ -289,7 +289,55 @@ impl<R: BufRead, W: Write> Client<R, W> {
         response_buffer: &'a mut Vec<u8>,
     ) -> Result<Vec<s::ResponseLine<'a>>, Error> {
         response_buffer.clear();
+        self.write_command(command)?;
+        self.read_responses_until_tagged(response_buffer)
+    }
+
+    /// Like `command`, but passes each untagged response to `on_response` as
+    /// it arrives instead of buffering all of them.
+    ///
+    /// This is intended for commands which can produce more data than is
+    /// reasonable to hold in memory at once. Only the tagged response is
+    /// returned.
+    pub fn command_streaming<'a, E: From<Error>>(
+        &mut self,
+        command: s::Command<'_>,
+        response_buffer: &'a mut Vec<u8>,
+        mut on_response: impl FnMut(s::ResponseLine<'_>) -> Result<(), E>,
+    ) -> Result<s::ResponseLine<'a>, E> {
+        self.write_command(command)?;
 
+        loop {
+            response_buffer.clear();
+            self.read_logical_line(response_buffer)?;
+            if !response_buffer.starts_with(b"*") {
+                break;
+            }
+
+            let (remaining, r) = s::ResponseLine::parse(
+                &response_buffer[..response_buffer.len() - 2],
+            )
+            .map_err(|e| Error::Nom(e.to_string()))?;
+            if !remaining.is_empty() {
+                return Err(Error::PartialParse.into());
+            }
+
+            on_response(r)?;
+        }
+
+        let response_buffer: &'a Vec<u8> = &*response_buffer;
+        let (remaining, r) = s::ResponseLine::parse(
+            &response_buffer[..response_buffer.len() - 2],
+        )
+        .map_err(|e| Error::Nom(e.to_string()))?;
+        if !remaining.is_empty() {
+            return Err(Error::PartialParse.into());
+        }
+
+        Ok(r)
+    }
+
+    fn write_command(&mut self, command: s::Command<'_>) -> Result<(), Error> {
         let tag = self.next_tag;
         self.next_tag += 1;
 
@@ -311,7 +359,7 @@ impl<R: BufRead, W: Write> Client<R, W> {
         self.trace(false, ">>[cmd]", &command_buffer);
         self.write.write_all(&command_buffer)?;
         self.write.flush()?;
-        self.read_responses_until_tagged(response_buffer)
+        Ok(())
     }
 
     pub fn start_append(
@@ -18,8 +18,13 @@
 
 use std::borrow::Cow;
 
+use log::info;
+
 use super::defs::*;
-use crate::account::v2::{Account, LogInError};
+use crate::account::{
+    model::SeqRange,
+    v2::{Account, LogInError, LogInProtocol},
+};
 
 impl CommandProcessor {
     /// Called when a line initiating an `AUTHENTICATE` is received.
@@ -31,14 +36,16 @@ impl CommandProcessor {
     ///
     /// Note that we currently only support `AUTHENTICATE` flows that take at
     /// most one input from the client and no server challenge.
-    pub(crate) fn authenticate_start(
+    pub(crate) async fn authenticate_start(
         &mut self,
         cmd: &s::AuthenticateCommandStart<'_>,
     ) -> Option<s::ResponseLine<'static>> {
         if "plain".eq_ignore_ascii_case(&cmd.auth_type) {
-            cmd.initial_response.as_ref().map(|ir| {
+            let ir = cmd.initial_response.as_ref()?;
+            Some(
                 self.authenticate_finish(cmd.to_owned(), ir.as_bytes())
-            })
+                    .await,
+            )
         } else {
             Some(s::ResponseLine {
                 tag: Some(Cow::Owned(cmd.tag.clone().into_owned())),
@@ -51,7 +58,7 @@ impl CommandProcessor {
         }
     }
 
-    pub(crate) fn authenticate_finish(
+    pub(crate) async fn authenticate_finish(
         &mut self,
         cmd: s::AuthenticateCommandStart<'_>,
         data: &[u8],
@@ -106,10 +113,12 @@ impl CommandProcessor {
                     };
                 }
 
-                let r = self.cmd_log_in(s::LogInCommand {
-                    userid: Cow::Borrowed(authenticate),
-                    password: Cow::Borrowed(password),
-                });
+                let r = self
+                    .cmd_log_in(s::LogInCommand {
+                        userid: Cow::Borrowed(authenticate),
+                        password: Cow::Borrowed(password),
+                    })
+                    .await;
                 let r = match r {
                     Ok(r) => r,
                     Err(r) => r,
@@ -133,7 +142,10 @@ impl CommandProcessor {
         }
     }
 
-    pub(crate) fn cmd_log_in(&mut self, cmd: s::LogInCommand<'_>) -> CmdResult {
+    pub(crate) async fn cmd_log_in(
+        &mut self,
+        cmd: s::LogInCommand<'_>,
+    ) -> CmdResult {
         if self.account.is_some() {
             return Err(s::Response::Cond(s::CondResponse {
                 cond: s::RespCondType::Bad,
@@ -142,19 +154,30 @@ impl CommandProcessor {
             }));
         }
 
-        match Account::log_in(
+        let result = match Account::log_in(
             self.log_prefix.clone(),
             &self.system_config,
             &self.data_root,
             &cmd.userid,
             &cmd.password,
+            LogInProtocol::Imap,
+            &self.client,
         ) {
-            Ok((account, _)) => {
+            Ok(r) => Ok(r),
+            Err((e, delay)) => {
+                tokio::time::sleep(delay).await;
+                Err(e)
+            },
+        };
+
+        match result {
+            Ok((account, aliases)) => {
                 self.account = Some(account);
+                self.user_aliases = aliases;
                 Ok(s::Response::Cond(s::CondResponse {
                     cond: s::RespCondType::Ok,
                     code: Some(s::RespTextCode::Capability(
-                        super::commands::capability_data(),
+                        self.capability_data(),
                     )),
                     quip: Some(Cow::Borrowed("User login successful")),
                 }))
@@ -184,6 +207,14 @@ impl CommandProcessor {
                 }))
             },
 
+            Err(e @ LogInError::LockedOut) => {
+                Err(s::Response::Cond(s::CondResponse {
+                    cond: s::RespCondType::No,
+                    code: Some(s::RespTextCode::Unavailable(())),
+                    quip: Some(Cow::Owned(e.to_string())),
+                }))
+            },
+
             Err(e @ LogInError::SetupError) => {
                 Err(s::Response::Cond(s::CondResponse {
                     cond: s::RespCondType::No,
@@ -193,4 +224,43 @@ impl CommandProcessor {
             },
         }
     }
+
+    pub(super) fn cmd_unauthenticate(&mut self) -> CmdResult {
+        if !self.unauthenticate_allowed {
+            return Err(s::Response::Cond(s::CondResponse {
+                cond: s::RespCondType::Bad,
+                code: Some(s::RespTextCode::Cannot(())),
+                quip: Some(Cow::Borrowed(
+                    "UNAUTHENTICATE is not supported in this configuration",
+                )),
+            }));
+        }
+
+        let Some(account) = self.account.take() else {
+            return Err(s::Response::Cond(s::CondResponse {
+                cond: s::RespCondType::Bad,
+                code: None,
+                quip: Some(Cow::Borrowed("Not logged in")),
+            }));
+        };
+
+        // Everything tied to the user goes away. Extensions turned on with
+        // ENABLE stay on, just like TLS and compression, since the output
+        // side can't be switched back out of Unicode mode.
+        self.selected = None;
+        self.searchres = SeqRange::new();
+        self.multiappend = None;
+        self.notify = None;
+        self.user_aliases.clear();
+
+        account.log_out();
+        info!("{} Unauthenticated", self.log_prefix);
+        self.log_prefix.clear_user();
+
+        Ok(s::Response::Cond(s::CondResponse {
+            cond: s::RespCondType::Ok,
+            code: None,
+            quip: Some(Cow::Borrowed("Session unauthenticated")),
+        }))
+    }
 }
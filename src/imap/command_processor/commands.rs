@@ -19,7 +19,7 @@
 use std::borrow::Cow;
 use std::convert::TryInto;
 
-use log::{error, info};
+use log::{error, info, warn};
 
 use super::defs::*;
 use crate::{
@@ -35,7 +35,7 @@ impl CommandProcessor {
             tag: None,
             response: s::Response::Cond(s::CondResponse {
                 cond: s::RespCondType::Ok,
-                code: Some(s::RespTextCode::Capability(capability_data())),
+                code: Some(s::RespTextCode::Capability(self.capability_data())),
                 quip: Some(Cow::Borrowed(TAGLINE)),
             }),
         }
@@ -106,6 +106,9 @@ impl CommandProcessor {
             s::Command::Simple(s::SimpleCommand::StartTls) => {
                 self.cmd_start_tls()
             },
+            s::Command::Simple(s::SimpleCommand::Unauthenticate) => {
+                self.cmd_unauthenticate()
+            },
             s::Command::Simple(s::SimpleCommand::Unselect) => {
                 self.cmd_unselect()
             },
@@ -123,6 +126,9 @@ impl CommandProcessor {
             s::Command::Simple(s::SimpleCommand::XCryGetUserConfig) => {
                 self.cmd_xcry_get_user_config(sender).await
             },
+            s::Command::Simple(s::SimpleCommand::XCryGetMailboxRetention) => {
+                self.cmd_xcry_get_mailbox_retention(sender).await
+            },
             s::Command::Simple(s::SimpleCommand::XCryZstdTrain) => {
                 self.cmd_xcry_zstd_train()
             },
@@ -144,7 +150,7 @@ impl CommandProcessor {
             s::Command::Status(cmd) => self.cmd_status(cmd, sender).await,
             s::Command::Subscribe(cmd) => self.cmd_subscribe(cmd),
             s::Command::Unsubscribe(cmd) => self.cmd_unsubscribe(cmd),
-            s::Command::LogIn(cmd) => self.cmd_log_in(cmd),
+            s::Command::LogIn(cmd) => self.cmd_log_in(cmd).await,
             s::Command::Copy(cmd) => self.cmd_copy(cmd, sender).await,
             s::Command::Move(cmd) => self.cmd_move(cmd, sender).await,
             s::Command::Fetch(cmd) => self.cmd_fetch(cmd, sender).await,
@@ -178,6 +184,24 @@ impl CommandProcessor {
 
             s::Command::Enable(exts) => self.cmd_enable(exts, sender).await,
 
+            s::Command::Notify(cmd) => self.cmd_notify(cmd, sender).await,
+
+            s::Command::GetMetadata(cmd) => {
+                self.cmd_getmetadata(cmd, sender).await
+            },
+            s::Command::SetMetadata(cmd) => self.cmd_setmetadata(cmd),
+
+            s::Command::GenUrlauth(items) => {
+                self.cmd_genurlauth(items, sender).await
+            },
+            s::Command::ResetKey(cmd) => self.cmd_resetkey(cmd),
+            s::Command::UrlFetch(urls) => self.cmd_urlfetch(urls, sender).await,
+
+            s::Command::CancelUpdate(tags) => self.cmd_cancel_update(tags),
+            s::Command::Esearch(cmd) => {
+                self.cmd_esearch(cmd, &command_line.tag, sender).await
+            },
+
             s::Command::XCrySetUserConfig(configs) => {
                 self.cmd_xcry_set_user_config(configs, sender).await
             },
@@ -188,6 +212,27 @@ impl CommandProcessor {
             s::Command::XCrySmtpSpoolExecute(ids) => {
                 self.cmd_xcry_smtp_spool_execute(ids).await
             },
+            s::Command::XCryAppPassword(cmd) => {
+                self.cmd_xcry_app_password(cmd, sender).await
+            },
+            s::Command::XCryLoginHistory(limit) => {
+                self.cmd_xcry_login_history(limit, sender).await
+            },
+            s::Command::XCryExport(cmd) => {
+                self.cmd_xcry_export(cmd, sender).await
+            },
+            s::Command::XCryRestore(cmd) => {
+                self.cmd_xcry_restore(cmd, sender).await
+            },
+            s::Command::XCryUnexpunge(cmd) => {
+                self.cmd_xcry_unexpunge(cmd, sender).await
+            },
+            s::Command::XCrySetMailboxRetention(cmd) => {
+                self.cmd_xcry_set_mailbox_retention(cmd).await
+            },
+            s::Command::XCryCreateSearchMailbox(cmd) => {
+                self.cmd_xcry_create_search_mailbox(cmd)
+            },
         };
 
         if res.is_ok() {
@@ -200,6 +245,10 @@ impl CommandProcessor {
             if let Err(err) = poll_res {
                 error!("{} Poll failed: {}", self.log_prefix, err);
             }
+
+            if let Err(err) = self.notify_poll(sender).await {
+                error!("{} NOTIFY poll failed: {}", self.log_prefix, err);
+            }
         } else if let Some(selected) = self.selected.as_ref() {
             // If an error occurred and we have a selected mailbox, check that
             // the mailbox still exists. If not, disconnect the client instead
@@ -233,7 +282,10 @@ impl CommandProcessor {
             if let (&Some(ref selected), &mut s::Response::Cond(ref mut cr)) =
                 (&self.selected, &mut res)
             {
-                if s::RespCondType::Ok == cr.cond && cr.code.is_none() {
+                if s::RespCondType::Ok == cr.cond
+                    && cr.code.is_none()
+                    && !selected.is_virtual()
+                {
                     cr.code = Some(s::RespTextCode::HighestModseq(
                         selected.snapshot_modseq().raw(),
                     ));
@@ -260,10 +312,24 @@ impl CommandProcessor {
     }
 
     async fn cmd_capability(&mut self, sender: &mut SendResponse) -> CmdResult {
-        send_response(sender, s::Response::Capability(capability_data())).await;
+        send_response(sender, s::Response::Capability(self.capability_data()))
+            .await;
         success()
     }
 
+    pub(super) fn capability_data(&self) -> s::CapabilityData<'static> {
+        s::CapabilityData {
+            capabilities: CAPABILITIES
+                .iter()
+                .copied()
+                .filter(|&cap| {
+                    "UNAUTHENTICATE" != cap || self.unauthenticate_allowed
+                })
+                .map(Cow::Borrowed)
+                .collect(),
+        }
+    }
+
     async fn cmd_enable(
         &mut self,
         exts: Vec<Cow<'_, str>>,
@@ -337,6 +403,22 @@ impl CommandProcessor {
         }))
     }
 
+    /// Fails with `BAD` if the selected mailbox was reported as `NOMODSEQ`,
+    /// since RFC 7162 requires commands using modseqs to be rejected there.
+    pub(super) fn require_modseq(&self) -> PartialResult<()> {
+        if self.selected.as_ref().is_some_and(|s| s.is_virtual()) {
+            return Err(s::Response::Cond(s::CondResponse {
+                cond: s::RespCondType::Bad,
+                code: Some(s::RespTextCode::ClientBug(())),
+                quip: Some(Cow::Borrowed(
+                    "Virtual mailboxes do not have modseqs",
+                )),
+            }));
+        }
+
+        Ok(())
+    }
+
     pub(super) async fn enable_condstore(
         &mut self,
         sender: &mut SendResponse,
@@ -348,8 +430,11 @@ impl CommandProcessor {
 
         self.condstore_enabled = true;
 
-        let highest_modseq =
-            self.selected.as_ref().map(|s| s.snapshot_modseq().raw());
+        let highest_modseq = self
+            .selected
+            .as_ref()
+            .filter(|s| !s.is_virtual())
+            .map(|s| s.snapshot_modseq().raw());
 
         // Only send an untagged OK if there's something interesting to say
         if implicit || highest_modseq.is_some() {
@@ -405,11 +490,29 @@ impl CommandProcessor {
             }
 
             if !user_agent_name.is_empty() {
+                self.client.name = Some(user_agent_name.clone());
                 self.log_prefix.set_user_agent(
                     Some(user_agent_name),
                     Some(user_agent_version).filter(|v| !v.is_empty()),
                 );
             }
+            if !message.is_empty() {
+                self.client.id = Some(message.trim_start().to_owned());
+            }
+
+            // If the client only identifies itself after logging in, amend
+            // the login history entry for this session.
+            if let Some(ref mut account) = self.account {
+                if let Err(e) = account.update_login_client(
+                    self.client.name.as_deref(),
+                    self.client.id.as_deref(),
+                ) {
+                    warn!(
+                        "{} Failed to update login history: {e}",
+                        self.log_prefix,
+                    );
+                }
+            }
 
             info!(
                 "{} ID exchanged; client says it is{}",
@@ -614,6 +717,29 @@ Content-Transfer-Encoding: base64
 
         self.fetch_for_background_update(sender, poll.fetch).await;
 
+        for update in poll.search_updates {
+            send_response(
+                sender,
+                s::Response::Esearch(s::EsearchResponse {
+                    tag: Cow::Owned(update.tag),
+                    mailbox: None,
+                    uidvalidity: None,
+                    uid: update.uid,
+                    min: None,
+                    max: None,
+                    all: None,
+                    count: None,
+                    partial: None,
+                    addto: (!update.added.is_empty())
+                        .then(|| Cow::Owned(update.added.to_string())),
+                    removeto: (!update.removed.is_empty())
+                        .then(|| Cow::Owned(update.removed.to_string())),
+                    modseq: None,
+                }),
+            )
+            .await;
+        }
+
         // This must come after fetch_for_background_update so that we can
         // override the client's own calculation of HIGHESTMODSEQ
         if let Some(max_modseq) = poll.max_modseq {
@@ -692,8 +818,15 @@ Content-Transfer-Encoding: base64
             return Some(maybe_tagged_response(Cow::Owned(tag.to_owned()), e));
         }
 
-        if let Err(e) = selected!(self) {
-            return Some(maybe_tagged_response(Cow::Owned(tag.to_owned()), e));
+        // With NOTIFY in effect, there's something to wait for even without
+        // a selected mailbox.
+        if self.notify.is_none() {
+            if let Err(e) = selected!(self) {
+                return Some(maybe_tagged_response(
+                    Cow::Owned(tag.to_owned()),
+                    e,
+                ));
+            }
         }
 
         None
@@ -701,8 +834,8 @@ Content-Transfer-Encoding: base64
 
     /// The IDLE command.
     ///
-    /// This idles on the currently selected mailbox until `cancel` completes
-    /// or an error occurs. Data is sent through `sender` as it becomes
+    /// This idles on the currently selected mailbox (and any mailboxes being
+    /// watched via NOTIFY) until `cancel` completes or an error occurs. Data is sent through `sender` as it becomes
     /// available.
     ///
     /// This is not cancel-safe if the connection is expected to continue being
@@ -721,16 +854,24 @@ Content-Transfer-Encoding: base64
                 .account
                 .as_mut()
                 .expect("account was validated by cmd_idle_preflight");
-            let selected = self
-                .selected
-                .as_mut()
-                .expect("selected was validated by cmd_idle_preflight");
+            let mut selected = self.selected.as_mut();
+            let notify = self.notify.as_ref();
 
             tokio::select! {
                 _ = &mut cancel => break Ok(()),
-                r = account.idle(selected) => match r {
-                    Ok(poll) => {
+                r = account.idle_until(|account| {
+                    super::notify::check_idle(
+                        account,
+                        selected.as_deref_mut(),
+                        notify,
+                    )
+                }) => match r {
+                    Ok((poll, snapshot)) => {
                         self.send_full_poll_responses(&mut sender, poll).await;
+                        if let Some(snapshot) = snapshot {
+                            self.send_notify_responses(&mut sender, snapshot)
+                                .await;
+                        }
                         send_event(&mut sender, OutputEvent::Flush).await;
                     },
 
@@ -769,12 +910,6 @@ Content-Transfer-Encoding: base64
     }
 }
 
-pub(super) fn capability_data() -> s::CapabilityData<'static> {
-    s::CapabilityData {
-        capabilities: CAPABILITIES.iter().copied().map(Cow::Borrowed).collect(),
-    }
-}
-
 fn maybe_tagged_response(
     tag: Cow<'_, str>,
     res: s::Response<'static>,
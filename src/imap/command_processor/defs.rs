@@ -17,8 +17,10 @@
 // Crymap. If not, see <http://www.gnu.org/licenses/>.
 
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt;
+use std::net::IpAddr;
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::Arc;
@@ -28,11 +30,12 @@ use log::error;
 use crate::{
     account::{
         model::*,
-        v2::{Account, Mailbox},
+        v2::{Account, LogInClient, Mailbox},
     },
     imap::response_writer::{OutputControl, OutputEvent},
     support::{
-        dns, error::Error, log_prefix::LogPrefix, system_config::SystemConfig,
+        buffer::BufferWriter, dns, error::Error, log_prefix::LogPrefix,
+        system_config::SystemConfig,
     },
 };
 
@@ -44,9 +47,11 @@ pub(super) static CAPABILITIES: &[&str] = &[
     concat_appendlimit!("APPENDLIMIT="),
     "AUTH=PLAIN",
     "BINARY",
+    "CATENATE",
     "CHILDREN",
     "COMPRESS=DEFLATE",
     "CONDSTORE",
+    "CONTEXT=SEARCH",
     "CREATE-SPECIAL-USE",
     "ENABLE",
     "ESEARCH",
@@ -55,10 +60,16 @@ pub(super) static CAPABILITIES: &[&str] = &[
     "LIST-EXTENDED",
     "LIST-STATUS",
     "LITERAL+",
+    "METADATA",
+    "METADATA-SERVER",
     "MOVE",
     "MULTIAPPEND",
+    "MULTISEARCH",
     "NAMESPACE",
+    "NOTIFY",
     "OBJECTID",
+    "PARTIAL",
+    "PREVIEW",
     "QRESYNC",
     "SASL-IR",
     "SAVEDATE",
@@ -66,7 +77,9 @@ pub(super) static CAPABILITIES: &[&str] = &[
     "SPECIAL-USE",
     "STATUS=SIZE",
     "UIDPLUS",
+    "UNAUTHENTICATE",
     "UNSELECT",
+    "URLAUTH",
     "UTF8=ACCEPT",
     "XCRY",
     "XLIST",
@@ -98,8 +111,14 @@ pub struct CommandProcessor {
     pub(super) system_config: Arc<SystemConfig>,
     pub(super) dns_resolver: Option<Rc<dns::Resolver>>,
     pub(super) data_root: PathBuf,
+    /// Information about the client, used for login throttling and the login
+    /// history.
+    pub(super) client: LogInClient,
 
     pub(super) account: Option<Account>,
+    /// The names the logged-in user goes by, used to check who may use IMAP
+    /// URLs.
+    pub(super) user_aliases: HashSet<String>,
     pub(super) selected: Option<Mailbox>,
     pub(super) searchres: SeqRange<Uid>,
     pub(super) unicode_aware: bool,
@@ -116,7 +135,15 @@ pub struct CommandProcessor {
 
     pub(super) multiappend: Option<Multiappend>,
 
+    /// The RFC 5465 `NOTIFY` configuration, if any is in effect.
+    pub(super) notify: Option<NotifyState>,
+
     pub(super) logged_out: bool,
+    /// Whether RFC 8437 `UNAUTHENTICATE` is available.
+    ///
+    /// It's only possible when logging in does not chroot or change the UID
+    /// of the process, since that cannot be undone.
+    pub(super) unauthenticate_allowed: bool,
 
     pub(super) id_exchanged: bool,
 }
@@ -124,6 +151,40 @@ pub struct CommandProcessor {
 pub(super) struct Multiappend {
     pub(super) dst: String,
     pub(super) request: AppendRequest,
+    /// The message being built from an RFC 4469 `CATENATE` list, if one is
+    /// in progress.
+    pub(super) catenate: Option<BufferWriter>,
+}
+
+pub(super) struct NotifyState {
+    /// The event groups that apply to mailboxes other than the selected
+    /// mailbox. A mailbox matching several groups gets the union of their
+    /// events.
+    pub(super) groups: Vec<NotifyGroup>,
+    /// The account state as of the last time notifications were sent.
+    pub(super) snapshot: NotifySnapshot,
+    /// The `MESSAGES` and `UIDNEXT` values last reported for each mailbox,
+    /// keyed by `MAILBOXID`. Used to tell which kind of message event caused
+    /// a change in modseq.
+    pub(super) counts: HashMap<String, (u32, u32)>,
+}
+
+pub(super) struct NotifyGroup {
+    pub(super) filter: NotifyFilter,
+    /// The events requested for this group. Unsupported events have already
+    /// been rejected.
+    pub(super) events: Vec<s::NotifyEvent>,
+}
+
+/// An RFC 5465 mailbox filter with all names normalised.
+pub(super) enum NotifyFilter {
+    Inboxes,
+    Personal,
+    Subscribed,
+    Subtree(Vec<String>),
+    /// RFC 7377; the named mailboxes and their immediate children.
+    SubtreeOne(Vec<String>),
+    Mailboxes(Vec<String>),
 }
 
 /// Used just for the convenient `?` operator. We mostly don't distinguish `Ok`
@@ -178,13 +239,18 @@ impl CommandProcessor {
         data_root: PathBuf,
         dns_resolver: Option<Rc<dns::Resolver>>,
     ) -> Self {
+        let unauthenticate_allowed = !system_config.security.chroot_system
+            && nix::unistd::ROOT != nix::unistd::getuid();
+
         CommandProcessor {
             log_prefix,
             system_config,
             data_root,
             dns_resolver,
+            client: LogInClient::default(),
 
             account: None,
+            user_aliases: HashSet::new(),
             selected: None,
             searchres: SeqRange::new(),
             unicode_aware: false,
@@ -196,12 +262,27 @@ impl CommandProcessor {
 
             multiappend: None,
 
+            notify: None,
+
             logged_out: false,
+            unauthenticate_allowed,
 
             id_exchanged: false,
         }
     }
 
+    /// Sets the IP address of the client, used for login throttling and the
+    /// login history.
+    pub fn set_peer_ip(&mut self, peer_ip: IpAddr) {
+        self.client.peer_ip = Some(peer_ip);
+    }
+
+    /// Sets the description of the TLS session, recorded in the login
+    /// history.
+    pub fn set_tls(&mut self, tls: Option<String>) {
+        self.client.tls = tls;
+    }
+
     pub fn is_authenticated(&self) -> bool {
         self.account.is_some()
     }
@@ -25,6 +25,7 @@ use std::mem;
 use log::{error, warn};
 
 use super::defs::*;
+use super::search::partial_range_from_ast;
 use crate::account::{
     model::*,
     v2::{Account, FetchReceiver, Mailbox},
@@ -47,6 +48,7 @@ impl CommandProcessor {
             false,
             false,
             Account::seqnum_store,
+            Mailbox::seqnum_partial_fetch_ids,
             |a, mb, r| a.seqnum_prefetch(mb, r),
             Account::seqnum_fetch,
         )
@@ -66,6 +68,7 @@ impl CommandProcessor {
             true,
             true,
             Account::store,
+            Mailbox::partial_fetch_ids,
             |a, mb, r| a.prefetch(mb, r),
             |a, mb, r, f| a.fetch(mb, r, f),
         )
@@ -85,7 +88,9 @@ impl CommandProcessor {
         let mut what = Vec::with_capacity(3);
         what.push(s::FetchAtt::Uid(()));
         what.push(s::FetchAtt::Flags(()));
-        if self.condstore_enabled {
+        if self.condstore_enabled
+            && !self.selected.as_ref().is_some_and(Mailbox::is_virtual)
+        {
             what.push(s::FetchAtt::Modseq(()));
         }
 
@@ -101,12 +106,119 @@ impl CommandProcessor {
                 false,
                 false,
                 |_, _, _| panic!("Shouldn't STORE in background update"),
+                |_, _, _| panic!("Shouldn't use PARTIAL in background update"),
                 |a, mb, r| a.prefetch(mb, r),
                 |a, mb, r, f| a.fetch(mb, r, f),
             )
             .await;
     }
 
+    /// `XCRY EXPORT mailbox uid modseq`
+    ///
+    /// Dumps the given mailbox without selecting it. Every message with a UID
+    /// greater than `uid` is sent in full; if `uid` is non-zero, messages at
+    /// or below it whose flags changed after `modseq` (or all of them, if
+    /// `modseq` is 0) are sent with just their UID, flags, and modseq, so that
+    /// a client can resume an interrupted or earlier export.
+    pub(super) async fn cmd_xcry_export(
+        &mut self,
+        cmd: s::XCryExportCommand<'_>,
+        sender: &mut SendResponse,
+    ) -> CmdResult {
+        let mailbox = cmd.mailbox.get_utf8(self.unicode_aware);
+        let (mut mailbox, _) = account!(self)?
+            .select(&mailbox, false, None)
+            .map_err(map_error! {
+                self,
+                NxMailbox | MailboxUnselectable =>
+                    (No, Some(s::RespTextCode::Nonexistent(()))),
+                UnsafeName =>
+                    (No, Some(s::RespTextCode::Cannot(()))),
+            })?;
+        let select = mailbox.select_response().map_err(map_error!(self))?;
+
+        send_response(
+            sender,
+            s::Response::XCryExportMailbox(s::XCryExportMailboxData {
+                uid_validity: select.uidvalidity,
+                highest_modseq: select.max_modseq.raw(),
+            }),
+        )
+        .await;
+
+        if let Some(last_uid) = Uid::of(cmd.uid) {
+            self.export_fetch(
+                &mut mailbox,
+                FetchRequest {
+                    ids: SeqRange::range(Uid::MIN, last_uid),
+                    uid: true,
+                    flags: true,
+                    modseq: true,
+                    changed_since: Some(Modseq::of(cmd.modseq)),
+                    ..FetchRequest::default()
+                },
+                sender,
+            )
+            .await?;
+        }
+
+        let first_new_uid = Uid::of(cmd.uid.saturating_add(1))
+            .filter(|&uid| uid < mailbox.next_uid());
+        if let Some(first_new_uid) = first_new_uid {
+            self.export_fetch(
+                &mut mailbox,
+                FetchRequest {
+                    ids: SeqRange::range(first_new_uid, Uid::MAX),
+                    uid: true,
+                    flags: true,
+                    modseq: true,
+                    internal_date: true,
+                    sections: vec![BodySection::default()],
+                    ..FetchRequest::default()
+                },
+                sender,
+            )
+            .await?;
+        }
+
+        success()
+    }
+
+    async fn export_fetch(
+        &mut self,
+        mailbox: &mut Mailbox,
+        request: FetchRequest<Uid>,
+        sender: &mut SendResponse,
+    ) -> PartialResult<()> {
+        let fetch_properties = FetchProperties::default();
+        let (receiver_tx, mut receiver_rx) =
+            tokio::sync::mpsc::channel(fetch_properties.channel_buffer_size);
+
+        let do_fetch = account!(self)?.fetch(mailbox, request, receiver_tx);
+        let send_responses = async move {
+            while let Some((_, items)) = receiver_rx.recv().await {
+                send_response(
+                    sender,
+                    s::Response::XCryExport(s::MsgAtts {
+                        atts: items
+                            .into_iter()
+                            .filter_map(|att| {
+                                fetch_att_to_ast(att, fetch_properties)
+                            })
+                            .collect(),
+                    }),
+                )
+                .await;
+            }
+        };
+
+        let (response, _) = tokio::join!(do_fetch, send_responses);
+        // Messages expunged concurrently are simply left out of the export,
+        // so the response kind is irrelevant here.
+        response.map_err(map_error!(self))?;
+        Ok(())
+    }
+
     async fn fetch<
         'a,
         ID: Default,
@@ -123,6 +235,11 @@ impl CommandProcessor {
             &mut Mailbox,
             &StoreRequest<ID>,
         ) -> Result<StoreResponse<ID>, Error>,
+        f_partial: impl FnOnce(
+            &Mailbox,
+            &FetchRequest<ID>,
+            PartialRange,
+        ) -> SeqRange<ID>,
         f_prefetch: impl FnOnce(
             &mut Account,
             &mut Mailbox,
@@ -146,6 +263,7 @@ impl CommandProcessor {
 
         let mut enable_condstore = false;
         let mut has_changedsince = false;
+        let mut partial = None;
         for modifier in cmd.modifiers.unwrap_or_default() {
             match modifier {
                 s::FetchModifier::ChangedSince(modseq) => {
@@ -196,6 +314,19 @@ impl CommandProcessor {
 
                     request.collect_vanished = true;
                 },
+                s::FetchModifier::Partial(range) => {
+                    if partial.is_some() {
+                        return Err(s::Response::Cond(s::CondResponse {
+                            cond: s::RespCondType::Bad,
+                            code: Some(s::RespTextCode::ClientBug(())),
+                            quip: Some(Cow::Borrowed(
+                                "PARTIAL passed more than once",
+                            )),
+                        }));
+                    }
+
+                    partial = Some(partial_range_from_ast(&range)?);
+                },
             }
         }
 
@@ -211,6 +342,20 @@ impl CommandProcessor {
         fetch_target_from_ast(&mut request, cmd.target);
 
         request.modseq |= has_changedsince;
+        if request.modseq {
+            self.require_modseq()?;
+        }
+
+        // RFC 9394 `PARTIAL` restricts the messages which are fetched (and
+        // implicitly marked \Seen), but `VANISHED` still applies to the full
+        // set the client gave, so `request.ids` is only narrowed after the
+        // prefetch.
+        let partial_ids = match partial {
+            Some(partial) => {
+                Some(f_partial(selected!(self)?, &request, partial))
+            },
+            None => None,
+        };
 
         // Don't implicitly enable CONDSTORE if not selected since we will
         // return BAD in that case.
@@ -236,7 +381,7 @@ impl CommandProcessor {
                 account,
                 selected,
                 &StoreRequest {
-                    ids: &request.ids,
+                    ids: partial_ids.as_ref().unwrap_or(&request.ids),
                     flags: &[Flag::Seen],
                     remove_listed: false,
                     remove_unlisted: false,
@@ -267,6 +412,10 @@ impl CommandProcessor {
         }
         fetch_preresponse(sender, prefetch).await?;
 
+        if let Some(partial_ids) = partial_ids {
+            request.ids = partial_ids;
+        }
+
         let (receiver_tx, mut receiver_rx) =
             tokio::sync::mpsc::channel(fetch_properties.channel_buffer_size);
 
@@ -353,7 +502,8 @@ fn scan_fetch_properties(props: &mut FetchProperties, att: &s::FetchAtt<'_>) {
             | s::FetchAtt::Rfc822(_)
             | s::FetchAtt::Body(_)
             | s::FetchAtt::ExtendedBodyStructure(_)
-            | s::FetchAtt::ShortBodyStructure(_),
+            | s::FetchAtt::ShortBodyStructure(_)
+            | s::FetchAtt::Preview(_),
     ) {
         // Use a smaller channel size if we're fetching things that involve
         // actually reading the message.
@@ -432,6 +582,12 @@ where
         s::FetchAtt::Modseq(()) => request.modseq = true,
         s::FetchAtt::EmailId(()) => request.email_id = true,
         s::FetchAtt::ThreadId(()) => request.thread_id = true,
+        s::FetchAtt::Preview(modifiers) => {
+            request.preview = true;
+            request.preview_lazy = modifiers
+                .unwrap_or_default()
+                .contains(&s::PreviewModifier::Lazy);
+        },
         s::FetchAtt::Rfc822(Some(s::FetchAttRfc822::Header)) => {
             request.sections.push(BodySection {
                 leaf_type: LeafType::Headers,
@@ -593,6 +749,9 @@ fn fetch_att_to_ast(
         FI::SaveDate(dt) => Some(s::MsgAtt::SaveDate(dt)),
         FI::EmailId(ei) => Some(s::MsgAtt::EmailId(Cow::Owned(ei))),
         FI::ThreadIdNil => Some(s::MsgAtt::ThreadIdNil(())),
+        FI::Preview(preview) => {
+            Some(s::MsgAtt::Preview(preview.map(Cow::Owned)))
+        },
         FI::Envelope(env) => Some(s::MsgAtt::Envelope(envelope_to_ast(*env))),
         FI::BodyStructure(bs) => {
             let converted = body_structure_to_ast(
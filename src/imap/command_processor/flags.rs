@@ -60,6 +60,7 @@ impl CommandProcessor {
         SeqRange<ID>: fmt::Debug,
     {
         if cmd.unchanged_since.is_some() {
+            self.require_modseq()?;
             self.enable_condstore(sender, true).await;
         }
 
@@ -249,6 +249,7 @@ impl CommandProcessor {
                                 .collect(),
                         )
                     },
+                    old_name: None,
                 }),
             )
             .await;
@@ -293,6 +294,7 @@ impl CommandProcessor {
                         .collect(),
                     name: MailboxName::of_utf8(Cow::Owned(response.name)),
                     child_info: None,
+                    old_name: None,
                 }),
             )
             .await;
@@ -333,6 +335,7 @@ impl CommandProcessor {
                         .collect(),
                     name: MailboxName::of_utf8(Cow::Owned(response.name)),
                     child_info: None,
+                    old_name: None,
                 }),
             )
             .await;
@@ -408,7 +411,7 @@ impl CommandProcessor {
         success()
     }
 
-    async fn evaluate_status(
+    pub(super) async fn evaluate_status(
         &mut self,
         mailbox_name: Cow<'_, str>,
         atts: &[s::StatusAtt],
@@ -630,6 +633,7 @@ impl CommandProcessor {
             })?;
         let select = stateful.select_response().map_err(map_error!(self))?;
         let mailbox_id = stateful.rfc8474_mailbox_id();
+        let nomodseq = stateful.is_virtual();
 
         send_response(sender, s::Response::Flags(select.flags.clone())).await;
         send_response(
@@ -689,7 +693,19 @@ impl CommandProcessor {
             }),
         )
         .await;
-        if self.condstore_enabled {
+        if nomodseq {
+            // RFC 7162 requires this regardless of whether CONDSTORE is
+            // enabled.
+            send_response(
+                sender,
+                s::Response::Cond(s::CondResponse {
+                    cond: s::RespCondType::Ok,
+                    code: Some(s::RespTextCode::NoModseq(())),
+                    quip: Some(Cow::Borrowed("Virtual mailbox")),
+                }),
+            )
+            .await;
+        } else if self.condstore_enabled {
             send_response(
                 sender,
                 s::Response::Cond(s::CondResponse {
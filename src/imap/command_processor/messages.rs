@@ -17,14 +17,18 @@
 // Crymap. If not, see <http://www.gnu.org/licenses/>.
 
 use std::borrow::Cow;
+use std::io::{self, Write};
 
 use chrono::prelude::*;
+use tokio::io::AsyncReadExt;
 
 use super::defs::*;
 use crate::account::model::*;
 use crate::account::v2::{Account, Mailbox};
 use crate::imap::mailbox_name::MailboxName;
-use crate::support::error::Error;
+use crate::support::{
+    append_limit::APPEND_SIZE_LIMIT, buffer::BufferWriter, error::Error,
+};
 
 impl CommandProcessor {
     /// Start an append command.
@@ -59,6 +63,7 @@ impl CommandProcessor {
         self.multiappend = Some(Multiappend {
             dst: mailbox.into_owned(),
             request: AppendRequest { items: vec![] },
+            catenate: None,
         });
         Ok(())
     }
@@ -94,16 +99,7 @@ impl CommandProcessor {
             .multiappend
             .as_mut()
             .expect("cmd_append_item with no append in progress");
-
-        if append.request.items.len() >= 65536 {
-            return Err(s::Response::Cond(s::CondResponse {
-                cond: s::RespCondType::No,
-                code: Some(s::RespTextCode::Limit(())),
-                quip: Some(Cow::Borrowed(
-                    "Maximum message count for MULTIAPPEND is 65536",
-                )),
-            }));
-        }
+        check_multiappend_count(append)?;
 
         let buffered = account!(self)?
             .buffer_message_async(
@@ -120,6 +116,112 @@ impl CommandProcessor {
         Ok(())
     }
 
+    /// Starts building the next message of the append from an RFC 4469
+    /// `CATENATE` list.
+    ///
+    /// The parts are added with `cmd_append_catenate_url()` and
+    /// `cmd_append_catenate_text()`, and the result is added to the append by
+    /// `cmd_append_catenate_finish()`. Failures are handled the same way as
+    /// for `cmd_append_item()`.
+    pub fn cmd_append_catenate_start(&mut self) -> PartialResult<()> {
+        let common_paths = account!(self)?.common_paths();
+        let append = self
+            .multiappend
+            .as_mut()
+            .expect("cmd_append_catenate_start with no append in progress");
+        check_multiappend_count(append)?;
+        append.catenate = Some(BufferWriter::new(common_paths));
+        Ok(())
+    }
+
+    /// Adds the content of the given IMAP URL to the `CATENATE` message.
+    pub fn cmd_append_catenate_url(&mut self, url: &str) -> PartialResult<()> {
+        let mut fetched = self.fetch_url(url)?;
+        let writer = self
+            .multiappend
+            .as_mut()
+            .and_then(|append| append.catenate.as_mut())
+            .expect("cmd_append_catenate_url with no CATENATE in progress");
+
+        if writer.len() + fetched.buffer.len() > u64::from(APPEND_SIZE_LIMIT) {
+            return Err(catenate_too_big());
+        }
+
+        io::copy(&mut fetched.buffer, writer)
+            .map_err(|e| map_error!(self)(e.into()))?;
+        Ok(())
+    }
+
+    /// Adds a `TEXT` literal to the `CATENATE` message.
+    pub async fn cmd_append_catenate_text(
+        &mut self,
+        size: u32,
+        mut data: std::pin::Pin<&mut impl tokio::io::AsyncRead>,
+    ) -> PartialResult<()> {
+        let writer = self
+            .multiappend
+            .as_mut()
+            .and_then(|append| append.catenate.as_mut())
+            .expect("cmd_append_catenate_text with no CATENATE in progress");
+
+        if writer.len() + u64::from(size) > u64::from(APPEND_SIZE_LIMIT) {
+            return Err(catenate_too_big());
+        }
+
+        let mut buf = [0u8; 4096];
+        let result: io::Result<()> = async {
+            loop {
+                let nread = data.read(&mut buf).await?;
+                if 0 == nread {
+                    break Ok(());
+                }
+
+                writer.write_all(&buf[..nread])?;
+            }
+        }
+        .await;
+        result.map_err(|e| map_error!(self)(e.into()))
+    }
+
+    /// Completes the `CATENATE` message and adds it to the append, using the
+    /// flags and internal date from `cmd`.
+    pub fn cmd_append_catenate_finish(
+        &mut self,
+        cmd: &s::AppendFragment,
+    ) -> PartialResult<()> {
+        let writer = self
+            .multiappend
+            .as_mut()
+            .and_then(|append| append.catenate.take())
+            .expect("cmd_append_catenate_finish with no CATENATE in progress");
+        if 0 == writer.len() {
+            return Err(s::Response::Cond(s::CondResponse {
+                cond: s::RespCondType::No,
+                code: None,
+                quip: Some(Cow::Borrowed("CATENATE produced an empty message")),
+            }));
+        }
+
+        let reader = writer.flip().map_err(|e| map_error!(self)(e.into()))?;
+        let buffered = account!(self)?
+            .buffer_message(
+                cmd.internal_date.unwrap_or_else(|| Utc::now().into()),
+                reader,
+            )
+            .map_err(map_error!(self))?;
+        self.multiappend
+            .as_mut()
+            .expect("cmd_append_catenate_finish with no append in progress")
+            .request
+            .items
+            .push(AppendItem {
+                buffer_file: buffered,
+                flags: cmd.flags.clone().unwrap_or_default(),
+            });
+
+        Ok(())
+    }
+
     pub async fn cmd_append_commit(
         &mut self,
         tag: Cow<'static, str>,
@@ -140,6 +242,7 @@ impl CommandProcessor {
                 BatchTooBig => (No, Some(s::RespTextCode::Limit(()))),
                 NxMailbox => (No, Some(s::RespTextCode::TryCreate(()))),
                 MailboxUnselectable => (No, Some(s::RespTextCode::Nonexistent(()))),
+                VirtualMailbox => (No, Some(s::RespTextCode::Cannot(()))),
             }) {
                 Ok(appended) => appended,
                 Err(response) => {
@@ -314,7 +417,8 @@ impl CommandProcessor {
             BatchTooBig => (No, Some(s::RespTextCode::Limit(()))),
             NxMailbox => (No, Some(s::RespTextCode::TryCreate(()))),
             MailboxUnselectable => (No, Some(s::RespTextCode::Nonexistent(()))),
-            MoveIntoSelf => (No, Some(s::RespTextCode::Cannot(()))),
+            MoveIntoSelf | VirtualMailbox =>
+                (No, Some(s::RespTextCode::Cannot(()))),
         })?;
 
         if response.from_uids.is_empty() {
@@ -352,3 +456,25 @@ impl CommandProcessor {
         }))
     }
 }
+
+fn check_multiappend_count(append: &Multiappend) -> PartialResult<()> {
+    if append.request.items.len() >= 65536 {
+        return Err(s::Response::Cond(s::CondResponse {
+            cond: s::RespCondType::No,
+            code: Some(s::RespTextCode::Limit(())),
+            quip: Some(Cow::Borrowed(
+                "Maximum message count for MULTIAPPEND is 65536",
+            )),
+        }));
+    }
+
+    Ok(())
+}
+
+fn catenate_too_big() -> s::Response<'static> {
+    s::Response::Cond(s::CondResponse {
+        cond: s::RespCondType::No,
+        code: Some(s::RespTextCode::TooBig(())),
+        quip: Some(Cow::Borrowed("CATENATE message too big")),
+    })
+}
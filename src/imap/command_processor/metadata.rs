@@ -0,0 +1,234 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+//! Implementation of RFC 5464 `METADATA`.
+//!
+//! Most entries are simply stored by the account. A couple are virtual,
+//! backed by other parts of the system:
+//!
+//! - `/shared/admin` on the server is the `admin` value of the
+//!   `[identification]` section of the system configuration and is read-only.
+//!
+//! - `/private/vendor/crymap/retention` on a mailbox is the mailbox's
+//!   retention rules, in the same syntax as `XCRY SET-MAILBOX-RETENTION`.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use super::defs::*;
+use super::retention::{retention_to_rules, rules_to_retention};
+use crate::account::model::*;
+use crate::imap::lex::LexWriter;
+use crate::imap::mailbox_name::MailboxName;
+use crate::support::error::Error;
+
+const ADMIN_ENTRY: &str = "/shared/admin";
+const RETENTION_ENTRY: &str = "/private/vendor/crymap/retention";
+
+impl CommandProcessor {
+    pub(super) async fn cmd_getmetadata(
+        &mut self,
+        cmd: s::GetMetadataCommand<'_>,
+        sender: &mut SendResponse,
+    ) -> CmdResult {
+        let mut request = GetMetadataRequest {
+            mailbox: cmd.mailbox.get_utf8(self.unicode_aware).into_owned(),
+            entries: match cmd.entries {
+                s::MetadataEntries::Single(entry) => vec![entry.into_owned()],
+                s::MetadataEntries::Multi(entries) => {
+                    entries.into_iter().map(Cow::into_owned).collect()
+                },
+            },
+            ..GetMetadataRequest::default()
+        };
+        for option in cmd.options.unwrap_or_default() {
+            match option {
+                s::GetMetadataOption::MaxSize(max_size) => {
+                    request.max_size = Some(max_size);
+                },
+                s::GetMetadataOption::Depth(depth) => {
+                    request.depth = match depth {
+                        s::MetadataDepth::Zero => MetadataDepth::Zero,
+                        s::MetadataDepth::One => MetadataDepth::One,
+                        s::MetadataDepth::Infinity => MetadataDepth::Infinity,
+                    };
+                },
+            }
+        }
+
+        let response =
+            account!(self)?.get_metadata(&request).map_err(map_error! {
+                self,
+                BadMetadataEntry => (Bad, None),
+                NxMailbox | UnsafeName =>
+                    (No, Some(s::RespTextCode::Nonexistent(()))),
+            })?;
+        let mut entries =
+            response.entries.into_iter().collect::<BTreeMap<_, _>>();
+        let mut longest_omitted = response.longest_omitted;
+
+        let virtual_entry = if request.mailbox.is_empty() {
+            self.system_config
+                .identification
+                .get("admin")
+                .map(|admin| (ADMIN_ENTRY, admin.clone()))
+        } else {
+            account!(self)?
+                .mailbox_retention(&request.mailbox)
+                .map_err(map_error!(self))?
+                .map(|retention| {
+                    (RETENTION_ENTRY, retention_to_text(&retention))
+                })
+        };
+
+        if let Some((name, value)) = virtual_entry.filter(|&(name, _)| {
+            request.entries.iter().any(|requested| {
+                request.depth.selects(&requested.to_ascii_lowercase(), name)
+            })
+        }) {
+            let len = u32::try_from(value.len()).unwrap_or(u32::MAX);
+            if request.max_size.is_some_and(|max| len > max) {
+                longest_omitted = longest_omitted.max(Some(len));
+                entries.remove(name);
+            } else {
+                entries.insert(name.to_owned(), Some(value));
+            }
+        }
+
+        if !entries.is_empty() {
+            send_response(
+                sender,
+                s::Response::Metadata(s::MetadataResponse {
+                    mailbox: MailboxName::of_utf8(Cow::Owned(request.mailbox)),
+                    entries: entries
+                        .into_iter()
+                        .map(|(entry, value)| s::MetadataEntryValue {
+                            entry: Cow::Owned(entry),
+                            value: value.map(Cow::Owned),
+                        })
+                        .collect(),
+                }),
+            )
+            .await;
+        }
+
+        match longest_omitted {
+            None => success(),
+            Some(len) => Ok(s::Response::Cond(s::CondResponse {
+                cond: s::RespCondType::Ok,
+                code: Some(s::RespTextCode::Metadata(
+                    s::MetadataRespCode::LongEntries(len),
+                )),
+                quip: None,
+            })),
+        }
+    }
+
+    pub(super) fn cmd_setmetadata(
+        &mut self,
+        cmd: s::SetMetadataCommand<'_>,
+    ) -> CmdResult {
+        let mailbox = cmd.mailbox.get_utf8(self.unicode_aware).into_owned();
+        let mut retention = None::<Option<MailboxRetention>>;
+        let mut entries = Vec::with_capacity(cmd.entries.len());
+        for entry in cmd.entries {
+            let name = entry.entry.to_ascii_lowercase();
+            let value = entry.value.map(Cow::into_owned);
+
+            if mailbox.is_empty() && ADMIN_ENTRY == name {
+                return Err(s::Response::Cond(s::CondResponse {
+                    cond: s::RespCondType::No,
+                    code: Some(s::RespTextCode::NoPerm(())),
+                    quip: Some(Cow::Borrowed(
+                        "/shared/admin is set by the server configuration",
+                    )),
+                }));
+            }
+
+            if !mailbox.is_empty() && RETENTION_ENTRY == name {
+                let Some(parsed) = value.as_deref().map(text_to_retention)
+                else {
+                    retention = Some(None);
+                    continue;
+                };
+
+                let Some(parsed) = parsed else {
+                    return Err(s::Response::Cond(s::CondResponse {
+                        cond: s::RespCondType::Bad,
+                        code: None,
+                        quip: Some(Cow::Borrowed("Invalid retention rules")),
+                    }));
+                };
+
+                retention = Some(Some(parsed));
+                continue;
+            }
+
+            entries.push((name, value));
+        }
+
+        account!(self)?
+            .set_metadata(&mailbox, entries, retention)
+            .map_err(map_error! {
+                self,
+                BadMetadataEntry => (Bad, None),
+                NxMailbox | UnsafeName =>
+                    (No, Some(s::RespTextCode::Nonexistent(()))),
+                MailboxUnselectable | RetentionOutOfRange =>
+                    (No, Some(s::RespTextCode::Cannot(()))),
+                MetadataTooLarge => (No, Some(s::RespTextCode::Metadata(
+                    s::MetadataRespCode::MaxSize(METADATA_MAX_SIZE)))),
+                TooManyMetadataEntries => (No, Some(s::RespTextCode::Metadata(
+                    s::MetadataRespCode::TooMany(())))),
+            })?;
+
+        success()
+    }
+}
+
+fn retention_to_text(retention: &MailboxRetention) -> String {
+    let mut text = Vec::<u8>::new();
+    let mut lex = LexWriter::new(&mut text, true, false);
+    for (ix, mut rule) in retention_to_rules(retention).into_iter().enumerate()
+    {
+        if ix > 0 {
+            let _ = lex.verbatim(" ");
+        }
+        let _ = rule.write_to(&mut lex);
+    }
+
+    String::from_utf8(text).unwrap_or_default()
+}
+
+/// Parses `text` as a space-separated list of retention rules, returning
+/// `None` if it is not valid.
+fn text_to_retention(text: &str) -> Option<MailboxRetention> {
+    let mut rules = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let (tail, rule) = s::XCryRetentionRule::parse(rest.as_bytes()).ok()?;
+        if tail.first().is_some_and(|&b| b' ' != b) {
+            return None;
+        }
+
+        rules.push(rule);
+        rest = std::str::from_utf8(tail).ok()?.trim_start();
+    }
+
+    Some(rules_to_retention(&rules))
+}
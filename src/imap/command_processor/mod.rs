@@ -22,6 +22,10 @@
 //! As with `account::v2::state`, this module is split into several submodules
 //! for manageability, but is best thought of as one single module.
 
+// This warning occurs because the error type of `CmdResult` is the final
+// response to the command, the same as the success type.
+#![allow(clippy::result_large_err)]
+
 macro_rules! map_error {
     ($this:expr) => {{
         let log_prefix = &$this.log_prefix;
@@ -105,8 +109,13 @@ mod fetch;
 mod flags;
 mod mailboxes;
 mod messages;
+mod metadata;
+mod notify;
+mod restore;
+mod retention;
 mod search;
 mod smtp_out;
+mod urlauth;
 mod user_config;
 
 pub use self::defs::CommandProcessor;
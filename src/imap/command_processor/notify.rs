@@ -0,0 +1,482 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+//! Implementation of RFC 5465 `NOTIFY`.
+//!
+//! Events for the selected mailbox are reported exactly as they always are,
+//! so `SELECTED` and `SELECTED-DELAYED` are accepted but have no effect.
+//! Everything else is found by comparing `NotifySnapshot`s of the account:
+//! a change in a mailbox's modseq results in a `STATUS` response, and changes
+//! to the mailbox hierarchy or subscriptions result in `LIST` responses.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use super::defs::*;
+use crate::{
+    account::{
+        model::*,
+        v2::{Account, Mailbox},
+    },
+    imap::mailbox_name::MailboxName,
+    support::{error::Error, mailbox_paths::parse_mailbox_path},
+};
+
+/// The events we support, reported in `BADEVENT`.
+static SUPPORTED_EVENTS: &[s::NotifyEvent] = &[
+    s::NotifyEvent::MessageNew,
+    s::NotifyEvent::MessageExpunge,
+    s::NotifyEvent::FlagChange,
+    s::NotifyEvent::MailboxName,
+    s::NotifyEvent::SubscriptionChange,
+];
+
+impl CommandProcessor {
+    pub(super) async fn cmd_notify(
+        &mut self,
+        cmd: s::NotifyCommand<'_>,
+        sender: &mut SendResponse,
+    ) -> CmdResult {
+        account!(self)?;
+
+        let cmd = match cmd {
+            s::NotifyCommand::None(()) => {
+                self.notify = None;
+                return success();
+            },
+            s::NotifyCommand::Set(cmd) => cmd,
+        };
+
+        let mut groups = Vec::with_capacity(cmd.event_groups.len());
+        for group in cmd.event_groups {
+            let events = match group.events {
+                s::NotifyEvents::None(()) => vec![],
+                s::NotifyEvents::Events(events) => events,
+            };
+
+            if events.iter().any(|e| !SUPPORTED_EVENTS.contains(e)) {
+                return Err(s::Response::Cond(s::CondResponse {
+                    cond: s::RespCondType::No,
+                    code: Some(s::RespTextCode::BadEvent(
+                        SUPPORTED_EVENTS.to_vec(),
+                    )),
+                    quip: Some(Cow::Borrowed("Unsupported event")),
+                }));
+            }
+
+            // RFC 5465 requires MessageNew and MessageExpunge to always be
+            // requested together, and FlagChange requires both of them.
+            let new = events.contains(&s::NotifyEvent::MessageNew);
+            let expunge = events.contains(&s::NotifyEvent::MessageExpunge);
+            let flag = events.contains(&s::NotifyEvent::FlagChange);
+            if new != expunge || (flag && !new) {
+                return Err(s::Response::Cond(s::CondResponse {
+                    cond: s::RespCondType::Bad,
+                    code: None,
+                    quip: Some(Cow::Borrowed(
+                        "MessageNew and MessageExpunge must be used together",
+                    )),
+                }));
+            }
+
+            let filter = match group.filter {
+                s::NotifyFilter::Selected(())
+                | s::NotifyFilter::SelectedDelayed(()) => continue,
+                s::NotifyFilter::Inboxes(()) => NotifyFilter::Inboxes,
+                s::NotifyFilter::Personal(()) => NotifyFilter::Personal,
+                s::NotifyFilter::Subscribed(()) => NotifyFilter::Subscribed,
+                s::NotifyFilter::Subtree(mailboxes) => {
+                    NotifyFilter::Subtree(self.notify_names(mailboxes))
+                },
+                s::NotifyFilter::SubtreeOne(mailboxes) => {
+                    NotifyFilter::SubtreeOne(self.notify_names(mailboxes))
+                },
+                s::NotifyFilter::Mailboxes(mailboxes) => {
+                    NotifyFilter::Mailboxes(self.notify_names(mailboxes))
+                },
+            };
+
+            groups.push(NotifyGroup { filter, events });
+        }
+
+        let snapshot = account!(self)?
+            .notify_snapshot()
+            .map_err(map_error!(self))?;
+        let state = NotifyState {
+            groups,
+            snapshot,
+            counts: HashMap::new(),
+        };
+
+        // Record the current counts of every mailbox we're watching so that
+        // later changes can be classified, sending them to the client if it
+        // asked for that.
+        let selected_id =
+            self.selected.as_ref().map(Mailbox::rfc8474_mailbox_id);
+        let watched = state
+            .snapshot
+            .mailboxes
+            .iter()
+            .filter(|&(id, mb)| {
+                mb.selectable
+                    && Some(id) != selected_id.as_ref()
+                    && state
+                        .events_for(&mb.name, &state.snapshot)
+                        .contains(&s::NotifyEvent::MessageNew)
+            })
+            .map(|(id, mb)| (id.clone(), mb.name.clone()))
+            .collect::<Vec<_>>();
+        self.notify = Some(state);
+
+        for (id, name) in watched {
+            let Some((response, counts)) =
+                self.notify_status(&name, sender).await
+            else {
+                continue;
+            };
+
+            if cmd.status {
+                send_response(sender, response).await;
+            }
+
+            if let Some(ref mut notify) = self.notify {
+                notify.counts.insert(id, counts);
+            }
+        }
+
+        success()
+    }
+
+    /// Checks for changes to the account and sends any notifications the
+    /// client requested with `NOTIFY`.
+    pub(super) async fn notify_poll(
+        &mut self,
+        sender: &mut SendResponse,
+    ) -> Result<(), Error> {
+        if self.notify.is_none() {
+            return Ok(());
+        }
+
+        let Some(ref mut account) = self.account else {
+            return Ok(());
+        };
+
+        let snapshot = account.notify_snapshot()?;
+        self.send_notify_responses(sender, snapshot).await;
+        Ok(())
+    }
+
+    /// Sends notifications for everything that changed between the current
+    /// `NOTIFY` snapshot and `snapshot`, then makes `snapshot` current.
+    pub(super) async fn send_notify_responses(
+        &mut self,
+        sender: &mut SendResponse,
+        snapshot: NotifySnapshot,
+    ) {
+        let Some(state) = self.notify.as_mut() else {
+            return;
+        };
+
+        let old = std::mem::replace(&mut state.snapshot, snapshot);
+        let state = &*state;
+        let new = &state.snapshot;
+        let wants = |name: &str, event: s::NotifyEvent| {
+            state.events_for(name, &old).contains(&event)
+                || state.events_for(name, new).contains(&event)
+        };
+
+        let mut created = Vec::<(String, Option<String>)>::new();
+        let mut deleted = Vec::<String>::new();
+        let mut subscription_changes = Vec::<String>::new();
+        let mut modified = Vec::<(String, String, bool)>::new();
+
+        for (id, mb) in &new.mailboxes {
+            match old.mailboxes.get(id) {
+                None => {
+                    if wants(&mb.name, s::NotifyEvent::MailboxName) {
+                        created.push((mb.name.clone(), None));
+                    }
+                },
+
+                Some(prev) => {
+                    if prev.name != mb.name
+                        && (wants(&prev.name, s::NotifyEvent::MailboxName)
+                            || wants(&mb.name, s::NotifyEvent::MailboxName))
+                    {
+                        created
+                            .push((mb.name.clone(), Some(prev.name.clone())));
+                    }
+
+                    if prev.max_modseq != mb.max_modseq
+                        && mb.selectable
+                        && wants(&mb.name, s::NotifyEvent::MessageNew)
+                    {
+                        modified.push((
+                            id.clone(),
+                            mb.name.clone(),
+                            wants(&mb.name, s::NotifyEvent::FlagChange),
+                        ));
+                    }
+                },
+            }
+        }
+
+        for (id, mb) in &old.mailboxes {
+            if !new.mailboxes.contains_key(id)
+                && wants(&mb.name, s::NotifyEvent::MailboxName)
+            {
+                deleted.push(mb.name.clone());
+            }
+        }
+
+        for name in old.subscriptions.symmetric_difference(&new.subscriptions) {
+            if wants(name, s::NotifyEvent::SubscriptionChange) {
+                subscription_changes.push(name.clone());
+            }
+        }
+
+        let selected_id =
+            self.selected.as_ref().map(Mailbox::rfc8474_mailbox_id);
+        // The STATUS responses are not about the selected mailbox.
+        modified.retain(|&(ref id, _, _)| Some(id) != selected_id.as_ref());
+
+        created.sort();
+        deleted.sort();
+        subscription_changes.sort();
+        modified.sort();
+
+        for name in deleted {
+            send_response(
+                sender,
+                list_response(name, vec![MailboxAttribute::NonExistent], None),
+            )
+            .await;
+        }
+
+        for (name, old_name) in created {
+            let attributes = self.notify_attributes(&name);
+            send_response(sender, list_response(name, attributes, old_name))
+                .await;
+        }
+
+        for name in subscription_changes {
+            let attributes = self.notify_attributes(&name);
+            send_response(sender, list_response(name, attributes, None)).await;
+        }
+
+        for (id, name, flag_change) in modified {
+            let Some((response, counts)) =
+                self.notify_status(&name, sender).await
+            else {
+                continue;
+            };
+
+            let Some(ref mut notify) = self.notify else {
+                return;
+            };
+
+            // A change that didn't affect MESSAGES or UIDNEXT must have been
+            // a flag change.
+            let message_change = notify.counts.get(&id) != Some(&counts);
+            notify.counts.insert(id, counts);
+            if message_change || flag_change {
+                send_response(sender, response).await;
+            }
+        }
+    }
+
+    /// Returns the unsolicited `STATUS` response to send for `name`, along
+    /// with its `MESSAGES` and `UIDNEXT` values.
+    ///
+    /// Returns `None` if the mailbox could not be examined, which generally
+    /// means it was deleted concurrently.
+    async fn notify_status(
+        &mut self,
+        name: &str,
+        sender: &mut SendResponse,
+    ) -> Option<(s::Response<'static>, (u32, u32))> {
+        let mut atts = vec![
+            s::StatusAtt::Messages,
+            s::StatusAtt::UidNext,
+            s::StatusAtt::UidValidity,
+            s::StatusAtt::Unseen,
+        ];
+        if self.condstore_enabled {
+            atts.push(s::StatusAtt::HighestModseq);
+        }
+
+        let response = self
+            .evaluate_status(Cow::Borrowed(name), &atts, sender)
+            .await
+            .ok()?;
+
+        let mut counts = (0, 0);
+        if let s::Response::Status(ref status) = response {
+            for att in &status.atts {
+                match *att {
+                    s::StatusResponseAtt::Messages(n) => counts.0 = n,
+                    s::StatusResponseAtt::UidNext(n) => counts.1 = n,
+                    _ => {},
+                }
+            }
+        }
+
+        Some((response, counts))
+    }
+
+    /// Returns the attributes to report in an unsolicited `LIST` response for
+    /// `name`.
+    fn notify_attributes(&mut self, name: &str) -> Vec<MailboxAttribute> {
+        let request = ListRequest {
+            reference: String::new(),
+            patterns: vec![name.to_owned()],
+            return_subscribed: true,
+            return_special_use: true,
+            ..ListRequest::default()
+        };
+
+        let subscribed = self
+            .notify
+            .as_ref()
+            .is_some_and(|n| n.snapshot.subscriptions.contains(name));
+
+        match self
+            .account
+            .as_mut()
+            .and_then(|account| account.list(&request).ok())
+            .and_then(|responses| responses.into_iter().next())
+        {
+            Some(response) => response.attributes,
+            None if subscribed => vec![
+                MailboxAttribute::NonExistent,
+                MailboxAttribute::Subscribed,
+            ],
+            None => vec![MailboxAttribute::NonExistent],
+        }
+    }
+
+    /// Returns the normalised names of `mailboxes`.
+    pub(super) fn notify_names(
+        &self,
+        mailboxes: s::NotifyMailboxes<'_>,
+    ) -> Vec<String> {
+        let mailboxes = match mailboxes {
+            s::NotifyMailboxes::Single(mb) => vec![mb],
+            s::NotifyMailboxes::Multi(mbs) => mbs,
+        };
+
+        mailboxes
+            .iter()
+            .map(|mb| {
+                parse_mailbox_path(&mb.get_utf8(self.unicode_aware))
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .collect()
+    }
+}
+
+impl NotifyState {
+    /// Returns the events the client wants for the mailbox named `name`,
+    /// evaluating the `SUBSCRIBED` filter against `snapshot`.
+    fn events_for(
+        &self,
+        name: &str,
+        snapshot: &NotifySnapshot,
+    ) -> Vec<s::NotifyEvent> {
+        let mut events = Vec::new();
+        for group in &self.groups {
+            let matches = match group.filter {
+                NotifyFilter::Inboxes => "INBOX" == name,
+                NotifyFilter::Personal => true,
+                NotifyFilter::Subscribed => {
+                    snapshot.subscriptions.contains(name)
+                },
+                NotifyFilter::Subtree(ref roots) => roots.iter().any(|root| {
+                    name == root
+                        || name
+                            .strip_prefix(root.as_str())
+                            .is_some_and(|rest| rest.starts_with('/'))
+                }),
+                NotifyFilter::SubtreeOne(ref roots) => {
+                    roots.iter().any(|root| {
+                        name == root
+                            || name
+                                .strip_prefix(root.as_str())
+                                .and_then(|rest| rest.strip_prefix('/'))
+                                .is_some_and(|rest| !rest.contains('/'))
+                    })
+                },
+                NotifyFilter::Mailboxes(ref names) => {
+                    names.iter().any(|n| n == name)
+                },
+            };
+
+            if matches {
+                events.extend_from_slice(&group.events);
+            }
+        }
+
+        events
+    }
+}
+
+/// Checks whether anything has happened that should end an idle cycle.
+///
+/// This polls the selected mailbox, if any, and if `NOTIFY` is in effect,
+/// also takes a new account snapshot to compare against the last one.
+pub(super) fn check_idle(
+    account: &mut Account,
+    selected: Option<&mut Mailbox>,
+    notify: Option<&NotifyState>,
+) -> Result<Option<(PollResponse, Option<NotifySnapshot>)>, Error> {
+    let poll = match selected {
+        Some(selected) => account.poll(selected)?,
+        None => PollResponse::default(),
+    };
+
+    let snapshot = match notify {
+        Some(notify) => {
+            let snapshot = account.notify_snapshot()?;
+            (snapshot != notify.snapshot).then_some(snapshot)
+        },
+        None => None,
+    };
+
+    if PollResponse::default() == poll && snapshot.is_none() {
+        Ok(None)
+    } else {
+        Ok(Some((poll, snapshot)))
+    }
+}
+
+fn list_response(
+    name: String,
+    attributes: Vec<MailboxAttribute>,
+    old_name: Option<String>,
+) -> s::Response<'static> {
+    s::Response::List(s::MailboxList {
+        flags: attributes
+            .into_iter()
+            .map(|a| Cow::Borrowed(a.name()))
+            .collect(),
+        name: MailboxName::of_utf8(Cow::Owned(name)),
+        child_info: None,
+        old_name: old_name.map(|n| MailboxName::of_utf8(Cow::Owned(n))),
+    })
+}
@@ -0,0 +1,220 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use std::borrow::Cow;
+
+use chrono::prelude::*;
+
+use super::defs::*;
+use crate::account::model::*;
+use crate::imap::mailbox_name::MailboxName;
+use crate::support::error::Error;
+
+impl CommandProcessor {
+    pub(super) async fn cmd_xcry_restore(
+        &mut self,
+        cmd: s::XCryRestoreCommand<'_>,
+        sender: &mut SendResponse,
+    ) -> CmdResult {
+        match cmd {
+            s::XCryRestoreCommand::List(()) => {
+                let backups =
+                    account!(self)?.list_backups().map_err(map_error!(self))?;
+                for date in backups {
+                    send_response(
+                        sender,
+                        s::Response::XCryRestoreBackup(Cow::Owned(
+                            date.format("%Y-%m-%d").to_string(),
+                        )),
+                    )
+                    .await;
+                }
+
+                success()
+            },
+
+            s::XCryRestoreCommand::Mailboxes(date) => {
+                let date = parse_backup_date(&date)?;
+                let mailboxes = account!(self)?
+                    .list_restorable_mailboxes(date)
+                    .map_err(map_error! {
+                        self,
+                        NxBackup =>
+                            (No, Some(s::RespTextCode::Nonexistent(()))),
+                    })?;
+                for mailbox in mailboxes {
+                    send_response(
+                        sender,
+                        s::Response::XCryRestoreMailbox(
+                            s::XCryRestoreMailboxData {
+                                name: MailboxName::of_utf8(Cow::Owned(
+                                    mailbox.name,
+                                )),
+                                uid_validity: mailbox.uid_validity,
+                                status: if mailbox.deleted {
+                                    s::XCryRestoreMailboxStatus::Deleted
+                                } else {
+                                    s::XCryRestoreMailboxStatus::Exists
+                                },
+                                messages: mailbox
+                                    .messages
+                                    .try_into()
+                                    .unwrap_or(u32::MAX),
+                            },
+                        ),
+                    )
+                    .await;
+                }
+
+                success()
+            },
+
+            s::XCryRestoreCommand::Messages(cmd) => {
+                let date = parse_backup_date(&cmd.date)?;
+                let mailbox = cmd.mailbox.get_utf8(self.unicode_aware);
+                let messages = account!(self)?
+                    .list_restorable_messages(date, &mailbox)
+                    .map_err(map_error! {
+                        self,
+                        NxBackup | NxMailbox =>
+                            (No, Some(s::RespTextCode::Nonexistent(()))),
+                        UnsafeName =>
+                            (No, Some(s::RespTextCode::Cannot(()))),
+                    })?;
+                for message in messages {
+                    send_response(
+                        sender,
+                        s::Response::XCryRestoreMessage(
+                            s::XCryRestoreMessageData {
+                                uid: message.uid.0.get(),
+                                savedate: message.savedate.into(),
+                                flags: message.flags,
+                                subject: message.subject.map(Cow::Owned),
+                            },
+                        ),
+                    )
+                    .await;
+                }
+
+                success()
+            },
+
+            s::XCryRestoreCommand::Copy(cmd) => {
+                let date = parse_backup_date(&cmd.date)?;
+                let mailbox = cmd.mailbox.get_utf8(self.unicode_aware);
+                let uids = parse_uids(&cmd.uids)?;
+                let restored = account!(self)?
+                    .restore_messages(date, &mailbox, &uids)
+                    .map_err(map_error! {
+                        self,
+                        NxBackup | NxMailbox =>
+                            (No, Some(s::RespTextCode::Nonexistent(()))),
+                        UnsafeName | MailboxExists | MailboxUnselectable =>
+                            (No, Some(s::RespTextCode::Cannot(()))),
+                    })?;
+
+                send_response(
+                    sender,
+                    s::Response::XCryRestored(
+                        restored.try_into().unwrap_or(u32::MAX),
+                    ),
+                )
+                .await;
+                success()
+            },
+        }
+    }
+}
+
+impl CommandProcessor {
+    pub(super) async fn cmd_xcry_unexpunge(
+        &mut self,
+        cmd: s::XCryUnexpungeCommand<'_>,
+        sender: &mut SendResponse,
+    ) -> CmdResult {
+        match cmd {
+            s::XCryUnexpungeCommand::List(()) => {
+                let messages = account!(self)?
+                    .list_unexpungeable()
+                    .map_err(map_error!(self))?;
+                for message in messages {
+                    send_response(
+                        sender,
+                        s::Response::XCryUnexpungeable(
+                            s::XCryUnexpungeableData {
+                                mailbox: MailboxName::of_utf8(Cow::Owned(
+                                    message.mailbox,
+                                )),
+                                uid: message.uid.0.get(),
+                                expunged: message.expunged.into(),
+                                flags: message.flags,
+                                subject: message.subject.map(Cow::Owned),
+                            },
+                        ),
+                    )
+                    .await;
+                }
+
+                success()
+            },
+
+            s::XCryUnexpungeCommand::Restore(cmd) => {
+                let mailbox = cmd.mailbox.get_utf8(self.unicode_aware);
+                let uids = parse_uids(&cmd.uids)?;
+                let restored = account!(self)?
+                    .unexpunge(&mailbox, &uids)
+                    .map_err(map_error! {
+                        self,
+                        NxMailbox =>
+                            (No, Some(s::RespTextCode::Nonexistent(()))),
+                        UnsafeName | MailboxUnselectable =>
+                            (No, Some(s::RespTextCode::Cannot(()))),
+                    })?;
+
+                send_response(
+                    sender,
+                    s::Response::XCryUnexpunged(
+                        restored.try_into().unwrap_or(u32::MAX),
+                    ),
+                )
+                .await;
+                success()
+            },
+        }
+    }
+}
+
+fn parse_uids(uids: &str) -> PartialResult<SeqRange<Uid>> {
+    SeqRange::parse(uids, Uid::MAX).ok_or_else(|| {
+        s::Response::Cond(s::CondResponse {
+            cond: s::RespCondType::Bad,
+            code: Some(s::RespTextCode::Parse(())),
+            quip: Some(Cow::Borrowed("Unparsable sequence set")),
+        })
+    })
+}
+
+fn parse_backup_date(date: &str) -> PartialResult<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| {
+        s::Response::Cond(s::CondResponse {
+            cond: s::RespCondType::Bad,
+            code: Some(s::RespTextCode::Parse(())),
+            quip: Some(Cow::Borrowed("Backup dates are YYYY-MM-DD")),
+        })
+    })
+}
@@ -0,0 +1,120 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use std::borrow::Cow;
+
+use super::defs::*;
+use crate::account::model::*;
+use crate::imap::mailbox_name::MailboxName;
+use crate::support::error::Error;
+
+impl CommandProcessor {
+    pub(super) async fn cmd_xcry_get_mailbox_retention(
+        &mut self,
+        sender: &mut SendResponse,
+    ) -> CmdResult {
+        let retentions = account!(self)?
+            .list_mailbox_retentions()
+            .map_err(map_error!(self))?;
+        for (mailbox, retention) in retentions {
+            send_response(
+                sender,
+                s::Response::XCryMailboxRetention(
+                    s::XCryMailboxRetentionData {
+                        mailbox: MailboxName::of_utf8(Cow::Owned(mailbox)),
+                        rules: retention_to_rules(&retention),
+                    },
+                ),
+            )
+            .await;
+        }
+
+        success()
+    }
+
+    pub(super) async fn cmd_xcry_set_mailbox_retention(
+        &mut self,
+        cmd: s::XCryMailboxRetentionData<'_>,
+    ) -> CmdResult {
+        let mailbox = cmd.mailbox.get_utf8(self.unicode_aware);
+        let retention = rules_to_retention(&cmd.rules);
+        account!(self)?
+            .set_mailbox_retention(&mailbox, Some(retention))
+            .map_err(map_error! {
+                self,
+                NxMailbox =>
+                    (No, Some(s::RespTextCode::Nonexistent(()))),
+                UnsafeName | MailboxUnselectable | RetentionOutOfRange =>
+                    (No, Some(s::RespTextCode::Cannot(()))),
+            })?;
+
+        success()
+    }
+}
+
+pub(super) fn retention_to_rules(
+    retention: &MailboxRetention,
+) -> Vec<s::XCryRetentionRule> {
+    let mut rules = Vec::new();
+    if let Some(max_age_days) = retention.max_age_days {
+        rules.push(s::XCryRetentionRule::MaxAge(max_age_days));
+        rules.push(s::XCryRetentionRule::AgeBasis(match retention.age_basis {
+            RetentionAgeBasis::SaveDate => s::XCryRetentionAgeBasis::SaveDate,
+            RetentionAgeBasis::InternalDate => {
+                s::XCryRetentionAgeBasis::InternalDate
+            },
+        }));
+    }
+    if let Some(max_messages) = retention.max_messages {
+        rules.push(s::XCryRetentionRule::MaxMessages(max_messages));
+    }
+    if retention.only_seen {
+        rules.push(s::XCryRetentionRule::OnlySeen(()));
+    }
+    rules
+}
+
+pub(super) fn rules_to_retention(
+    rules: &[s::XCryRetentionRule],
+) -> MailboxRetention {
+    let mut retention = MailboxRetention::default();
+    for rule in rules {
+        match *rule {
+            s::XCryRetentionRule::MaxAge(days) => {
+                retention.max_age_days = Some(days);
+            },
+            s::XCryRetentionRule::AgeBasis(
+                s::XCryRetentionAgeBasis::SaveDate,
+            ) => {
+                retention.age_basis = RetentionAgeBasis::SaveDate;
+            },
+            s::XCryRetentionRule::AgeBasis(
+                s::XCryRetentionAgeBasis::InternalDate,
+            ) => {
+                retention.age_basis = RetentionAgeBasis::InternalDate;
+            },
+            s::XCryRetentionRule::MaxMessages(max) => {
+                retention.max_messages = Some(max);
+            },
+            s::XCryRetentionRule::OnlySeen(()) => {
+                retention.only_seen = true;
+            },
+        }
+    }
+    retention
+}
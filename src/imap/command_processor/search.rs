@@ -22,6 +22,7 @@ use std::convert::TryFrom;
 use super::defs::*;
 use crate::account::model::*;
 use crate::account::v2::{Account, Mailbox};
+use crate::imap::mailbox_name::MailboxName;
 use crate::support::error::Error;
 
 impl CommandProcessor {
@@ -67,37 +68,97 @@ impl CommandProcessor {
         // `SEARCH ...` and should return a vanilla SEARCH response instead of
         // ESEARCH, which is a bit weird since it's using extended search
         // syntax, but it makes our lives a bit easier.
-        let return_opts = cmd.return_opts.take().unwrap_or_default();
+        let (return_opts, partial) = split_return_opts(cmd.return_opts.take());
         // IMAP4rev2 (2020-07 draft) requires SEARCH to always return ESEARCH
-        let return_extended = !return_opts.is_empty() || self.imap4rev2_enabled;
+        let return_extended = !return_opts.is_empty()
+            || partial.is_some()
+            || self.imap4rev2_enabled;
+
+        let partial = partial
+            .map(|range| partial_range_from_ast(&range).map(|p| (range, p)))
+            .transpose()?;
 
         let mut has_modseq = false;
-        let request = self.search_command_from_ast(&mut has_modseq, cmd)?;
+        let mut request = self.search_command_from_ast(&mut has_modseq, cmd)?;
+        // If the client only wants a window of the results, let the search
+        // stop early instead of finding all the hits.
+        if return_opts
+            .iter()
+            .all(|&opt| s::SimpleSearchReturnOpt::Context == opt)
+        {
+            request.partial = partial.as_ref().map(|&(_, p)| p);
+        }
 
         if has_modseq && self.selected.is_some() {
+            self.require_modseq()?;
             self.enable_condstore(sender, true).await;
         }
 
         let response = f(account!(self)?, selected!(self)?, &request)
             .map_err(map_error!(self))?;
+
+        if return_opts.contains(&s::SimpleSearchReturnOpt::Update) {
+            let selected = selected!(self)?;
+            // RFC 5267 permits refusing any update request. We don't support
+            // updating MIN and MAX since they would need to be recomputed
+            // whenever a hit goes away.
+            if return_opts.contains(&s::SimpleSearchReturnOpt::Min)
+                || return_opts.contains(&s::SimpleSearchReturnOpt::Max)
+                || selected.search_context_count()
+                    >= self.system_config.imap.max_search_contexts
+            {
+                send_response(
+                    sender,
+                    s::Response::Cond(s::CondResponse {
+                        cond: s::RespCondType::No,
+                        code: Some(s::RespTextCode::NoUpdate(Cow::Owned(
+                            tag.to_owned(),
+                        ))),
+                        quip: Some(Cow::Borrowed(
+                            "Search results will not be updated",
+                        )),
+                    }),
+                )
+                .await;
+            } else {
+                selected.add_search_context(
+                    tag.to_owned(),
+                    is_uid,
+                    &request,
+                    &response.hit_uids,
+                );
+            }
+        }
+
         // We normally return a response. If SAVE is specified, we won't unless
         // another return option requests it.
         let mut return_response =
-            !return_opts.contains(&s::SearchReturnOpt::Save);
+            !return_opts.contains(&s::SimpleSearchReturnOpt::Save);
 
         let response = if return_extended {
             let mut r = s::EsearchResponse {
                 tag: Cow::Owned(tag.to_owned()),
+                mailbox: None,
+                uidvalidity: None,
                 uid: is_uid,
                 min: None,
                 max: None,
                 all: None,
                 count: None,
+                partial: None,
+                addto: None,
+                removeto: None,
                 modseq: None,
             };
-            let mut modseq: Option<Modseq> = None;
-
-            if return_opts.contains(&s::SearchReturnOpt::Save) {
+            // UPDATE and CONTEXT produce no result data of their own, so if
+            // they are the only return options, the client gets the initial
+            // results as if ALL had been given.
+            let implicit_all = partial.is_none()
+                && return_opts.iter().all(|&opt| {
+                    s::SimpleSearchReturnOpt::Update == opt
+                        || s::SimpleSearchReturnOpt::Context == opt
+                });
+            if return_opts.contains(&s::SimpleSearchReturnOpt::Save) {
                 self.searchres.clear();
 
                 // Per RFC 5182, SAVE interacts with MIN and MAX
@@ -105,24 +166,25 @@ impl CommandProcessor {
                 // return option that would cause all matching messages to be
                 // enumerated was specified, only the MIN and MAX which were
                 // requested are saved.
-                if return_opts.contains(&s::SearchReturnOpt::All)
-                    || return_opts.contains(&s::SearchReturnOpt::Count)
-                    || (!return_opts.contains(&s::SearchReturnOpt::Min)
-                        && !return_opts.contains(&s::SearchReturnOpt::Max))
+                if return_opts.contains(&s::SimpleSearchReturnOpt::All)
+                    || return_opts.contains(&s::SimpleSearchReturnOpt::Count)
+                    || (!return_opts.contains(&s::SimpleSearchReturnOpt::Min)
+                        && !return_opts
+                            .contains(&s::SimpleSearchReturnOpt::Max))
                 {
                     // Sane case
-                    for uid in response.hit_uids {
+                    for &uid in &response.hit_uids {
                         self.searchres.append(uid);
                     }
                 } else {
                     // Pathological case
-                    if return_opts.contains(&s::SearchReturnOpt::Min) {
+                    if return_opts.contains(&s::SimpleSearchReturnOpt::Min) {
                         if let Some(&uid) = response.hit_uids.first() {
                             self.searchres.append(uid);
                         }
                     }
 
-                    if return_opts.contains(&s::SearchReturnOpt::Max) {
+                    if return_opts.contains(&s::SimpleSearchReturnOpt::Max) {
                         if let Some(&uid) = response.hit_uids.last() {
                             // MIN could have inserted the same UID already
                             if !self.searchres.contains(uid) {
@@ -133,48 +195,15 @@ impl CommandProcessor {
                 }
             }
 
-            if return_opts.contains(&s::SearchReturnOpt::Min) {
-                r.min = response.hits.first().map(|&hit| hit.into());
-                modseq = response.first_modseq;
-                return_response = true;
-            }
-
-            if return_opts.contains(&s::SearchReturnOpt::Max) {
-                r.max = response.hits.last().map(|&hit| hit.into());
-                // If given MIN + MAX, the modseq is the maximum of the two
-                if let Some(last_modseq) = response.last_modseq {
-                    modseq = modseq
-                        .map(|m| m.max(last_modseq))
-                        .or(response.last_modseq);
-                }
-                return_response = true;
-            }
-
-            // In IMAP4rev2, `RETURN ()` is equivalent to `RETURN (ALL)`.
-            // In IMAP4rev1 with RFC 4731, we don't get here, since `RETURN ()`
-            // is equivalent to an RFC 3501 search.
-            if (return_opts.contains(&s::SearchReturnOpt::All)
-                || return_opts.is_empty())
-                && !response.hits.is_empty()
-            {
-                let mut sr = SeqRange::new();
-                for &hit in &response.hits {
-                    sr.append(hit);
-                }
-                r.all = Some(Cow::Owned(sr.to_string()));
-                modseq = response.max_modseq;
-                return_response = true;
-            }
-
-            if return_opts.contains(&s::SearchReturnOpt::Count) {
-                r.count = Some(response.hits.len() as u32);
-                modseq = response.max_modseq;
-                return_response = true;
-            }
-
-            if has_modseq {
-                r.modseq = modseq.map(|m| m.raw());
-            }
+            return_response |= esearch_result_data(
+                &mut r,
+                &return_opts,
+                implicit_all,
+                partial.as_ref(),
+                request.partial.is_some(),
+                &response,
+                has_modseq,
+            );
 
             s::Response::Esearch(r)
         } else {
@@ -197,6 +226,207 @@ impl CommandProcessor {
         success()
     }
 
+    pub(super) fn cmd_cancel_update(
+        &mut self,
+        tags: Vec<Cow<'_, str>>,
+    ) -> CmdResult {
+        let selected = selected!(self)?;
+        for tag in tags {
+            selected.cancel_search_context(&tag);
+        }
+
+        success()
+    }
+
+    /// The RFC 7377 `ESEARCH` command.
+    pub(super) async fn cmd_esearch(
+        &mut self,
+        cmd: s::EsearchCommand<'_>,
+        tag: &str,
+        sender: &mut SendResponse,
+    ) -> CmdResult {
+        account!(self)?;
+
+        let (return_opts, partial) = split_return_opts(cmd.return_opts);
+        // RFC 7377 only permits SAVE when searching just the selected
+        // mailbox, and doesn't define how UPDATE would work at all. Clients
+        // wanting either can use UID SEARCH instead.
+        if return_opts.contains(&s::SimpleSearchReturnOpt::Save)
+            || return_opts.contains(&s::SimpleSearchReturnOpt::Update)
+        {
+            return Err(s::Response::Cond(s::CondResponse {
+                cond: s::RespCondType::Bad,
+                code: Some(s::RespTextCode::ClientBug(())),
+                quip: Some(Cow::Borrowed(
+                    "SAVE and UPDATE cannot be used with ESEARCH",
+                )),
+            }));
+        }
+
+        let partial = partial
+            .map(|range| partial_range_from_ast(&range).map(|p| (range, p)))
+            .transpose()?;
+
+        let mut request = MultiSearchRequest::default();
+        let sources = cmd
+            .source
+            .unwrap_or_else(|| vec![s::NotifyFilter::Selected(())]);
+        for source in sources {
+            match source {
+                s::NotifyFilter::Selected(())
+                | s::NotifyFilter::SelectedDelayed(()) => {
+                    selected!(self)?;
+                    request.selected = true;
+                },
+                s::NotifyFilter::Inboxes(()) => {
+                    request.patterns.push("INBOX".to_owned());
+                },
+                s::NotifyFilter::Personal(()) => {
+                    request.patterns.push("*".to_owned());
+                },
+                s::NotifyFilter::Subscribed(()) => {
+                    request.subscribed_patterns.push("*".to_owned());
+                },
+                s::NotifyFilter::Subtree(mailboxes) => {
+                    for name in self.notify_names(mailboxes) {
+                        request.patterns.push(format!("{name}/*"));
+                        request.patterns.push(name);
+                    }
+                },
+                s::NotifyFilter::SubtreeOne(mailboxes) => {
+                    for name in self.notify_names(mailboxes) {
+                        request.patterns.push(format!("{name}/%"));
+                        request.patterns.push(name);
+                    }
+                },
+                s::NotifyFilter::Mailboxes(mailboxes) => {
+                    request.patterns.extend(self.notify_names(mailboxes));
+                },
+            }
+        }
+
+        // Sequence numbers, UIDs, and `$` all refer to the selected mailbox.
+        if (!request.patterns.is_empty()
+            || !request.subscribed_patterns.is_empty())
+            && cmd.keys.iter().any(uses_message_set)
+        {
+            return Err(s::Response::Cond(s::CondResponse {
+                cond: s::RespCondType::Bad,
+                code: Some(s::RespTextCode::ClientBug(())),
+                quip: Some(Cow::Borrowed(
+                    "Message sets can only be used to search \
+                     the selected mailbox",
+                )),
+            }));
+        }
+
+        let mut has_modseq = false;
+        request.search = self.search_command_from_ast(
+            &mut has_modseq,
+            s::SearchCommand {
+                return_opts: None,
+                charset: cmd.charset,
+                keys: cmd.keys,
+            },
+        )?;
+        // Unlike SEARCH, ESEARCH without any return options means ALL.
+        let implicit_all = partial.is_none()
+            && return_opts
+                .iter()
+                .all(|&opt| s::SimpleSearchReturnOpt::Context == opt);
+        if return_opts
+            .iter()
+            .all(|&opt| s::SimpleSearchReturnOpt::Context == opt)
+        {
+            request.search.partial = partial.as_ref().map(|&(_, p)| p);
+        }
+
+        if has_modseq {
+            self.enable_condstore(sender, true).await;
+        }
+
+        let responses = account!(self)?
+            .multi_search(self.selected.as_ref(), &request)
+            .map_err(map_error!(self))?;
+        for response in responses {
+            // Mailboxes without any matches are left out so that searching a
+            // large account doesn't produce a response for every mailbox.
+            if response.response.hits.is_empty() {
+                continue;
+            }
+
+            let mut r = s::EsearchResponse {
+                tag: Cow::Owned(tag.to_owned()),
+                mailbox: Some(MailboxName::of_utf8(Cow::Owned(
+                    response.mailbox,
+                ))),
+                uidvalidity: Some(response.uidvalidity),
+                uid: true,
+                min: None,
+                max: None,
+                all: None,
+                count: None,
+                partial: None,
+                addto: None,
+                removeto: None,
+                modseq: None,
+            };
+            esearch_result_data(
+                &mut r,
+                &return_opts,
+                implicit_all,
+                partial.as_ref(),
+                request.search.partial.is_some(),
+                &response.response,
+                has_modseq,
+            );
+            send_response(sender, s::Response::Esearch(r)).await;
+        }
+
+        success()
+    }
+
+    pub(super) fn cmd_xcry_create_search_mailbox(
+        &mut self,
+        cmd: s::XCryCreateSearchMailboxCommand<'_>,
+    ) -> CmdResult {
+        // Message sets only mean anything relative to the selected mailbox,
+        // so they can't be part of a saved search.
+        if cmd.keys.iter().any(uses_message_set) {
+            return Err(s::Response::Cond(s::CondResponse {
+                cond: s::RespCondType::No,
+                code: Some(s::RespTextCode::Cannot(())),
+                quip: Some(Cow::Borrowed(
+                    "Message sets cannot be used in a saved search",
+                )),
+            }));
+        }
+
+        let mut has_modseq = false;
+        let queries = cmd
+            .keys
+            .into_iter()
+            .map(|k| self.search_query_from_ast(&mut has_modseq, k))
+            .collect::<PartialResult<Vec<_>>>()?;
+
+        let mailbox = cmd.mailbox.get_utf8(self.unicode_aware);
+        let mailbox_id = account!(self)?
+            .create_search_mailbox(&mailbox, &queries)
+            .map_err(map_error! {
+                self,
+                MailboxExists =>
+                    (No, Some(s::RespTextCode::AlreadyExists(()))),
+                UnsafeName | BadOperationOnInbox | UnsavableSearch =>
+                    (No, Some(s::RespTextCode::Cannot(()))),
+            })?;
+
+        Ok(s::Response::Cond(s::CondResponse {
+            cond: s::RespCondType::Ok,
+            code: Some(s::RespTextCode::MailboxId(Cow::Owned(mailbox_id))),
+            quip: None,
+        }))
+    }
+
     fn search_command_from_ast(
         &mut self,
         has_modseq: &mut bool,
@@ -227,6 +457,7 @@ impl CommandProcessor {
                 .into_iter()
                 .map(|k| self.search_query_from_ast(has_modseq, k))
                 .collect::<PartialResult<Vec<_>>>()?,
+            partial: None,
         })
     }
 
@@ -333,3 +564,146 @@ impl CommandProcessor {
         }
     }
 }
+
+/// Converts an RFC 5267/RFC 9394 `PARTIAL` range to the model equivalent.
+///
+/// The client may give the bounds in either order, but both must be non-zero
+/// and have the same sign.
+pub(super) fn partial_range_from_ast(
+    range: &s::PartialRange,
+) -> PartialResult<PartialRange> {
+    if 0 == range.start
+        || 0 == range.end
+        || (range.start < 0) != (range.end < 0)
+    {
+        return Err(s::Response::Cond(s::CondResponse {
+            cond: s::RespCondType::Bad,
+            code: Some(s::RespTextCode::ClientBug(())),
+            quip: Some(Cow::Borrowed("Invalid PARTIAL range")),
+        }));
+    }
+
+    let (start, end) = (range.start.unsigned_abs(), range.end.unsigned_abs());
+    Ok(PartialRange {
+        low: start.min(end),
+        high: start.max(end),
+        from_end: range.start < 0,
+    })
+}
+
+/// Fills in the `ESEARCH` result data in `r` requested by `return_opts` and
+/// `partial`.
+///
+/// `windowed` indicates that `response` only contains the hits within the
+/// `PARTIAL` window. Returns whether any result data was produced.
+fn esearch_result_data<T>(
+    r: &mut s::EsearchResponse<'_>,
+    return_opts: &[s::SimpleSearchReturnOpt],
+    implicit_all: bool,
+    partial: Option<&(s::PartialRange, PartialRange)>,
+    windowed: bool,
+    response: &SearchResponse<T>,
+    has_modseq: bool,
+) -> bool
+where
+    T: Into<u32> + TryFrom<u32> + PartialOrd + Send + Sync + Copy,
+{
+    let mut has_data = false;
+    let mut modseq: Option<Modseq> = None;
+
+    if return_opts.contains(&s::SimpleSearchReturnOpt::Min) {
+        r.min = response.hits.first().map(|&hit| hit.into());
+        modseq = response.first_modseq;
+        has_data = true;
+    }
+
+    if return_opts.contains(&s::SimpleSearchReturnOpt::Max) {
+        r.max = response.hits.last().map(|&hit| hit.into());
+        // If given MIN + MAX, the modseq is the maximum of the two
+        if let Some(last_modseq) = response.last_modseq {
+            modseq =
+                modseq.map(|m| m.max(last_modseq)).or(response.last_modseq);
+        }
+        has_data = true;
+    }
+
+    // In IMAP4rev2, `RETURN ()` is equivalent to `RETURN (ALL)`.
+    // In IMAP4rev1 with RFC 4731, we don't get here, since `RETURN ()`
+    // is equivalent to an RFC 3501 search.
+    if (return_opts.contains(&s::SimpleSearchReturnOpt::All) || implicit_all)
+        && !response.hits.is_empty()
+    {
+        let mut sr = SeqRange::new();
+        for &hit in &response.hits {
+            sr.append(hit);
+        }
+        r.all = Some(Cow::Owned(sr.to_string()));
+        modseq = response.max_modseq;
+        has_data = true;
+    }
+
+    if return_opts.contains(&s::SimpleSearchReturnOpt::Count) {
+        r.count = Some(response.hits.len() as u32);
+        modseq = response.max_modseq;
+        has_data = true;
+    }
+
+    if let Some(&(ref range, window)) = partial {
+        let hits = if windowed {
+            // Already restricted by the search itself
+            &response.hits[..]
+        } else {
+            window.window(&response.hits)
+        };
+        let mut sr = SeqRange::new();
+        for &hit in hits {
+            sr.append(hit);
+        }
+        r.partial = Some(s::EsearchPartial {
+            range: range.clone(),
+            hits: (!sr.is_empty()).then(|| Cow::Owned(sr.to_string())),
+        });
+        modseq = response.max_modseq;
+        has_data = true;
+    }
+
+    if has_modseq {
+        r.modseq = modseq.map(|m| m.raw());
+    }
+
+    has_data
+}
+
+/// Separates the simple return options from the `PARTIAL` return option, if
+/// any.
+fn split_return_opts(
+    return_opts: Option<Vec<s::SearchReturnOpt>>,
+) -> (Vec<s::SimpleSearchReturnOpt>, Option<s::PartialRange>) {
+    let mut partial = None;
+    let return_opts = return_opts
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|opt| match opt {
+            s::SearchReturnOpt::Simple(opt) => Some(opt),
+            s::SearchReturnOpt::Partial(range) => {
+                partial = Some(range);
+                None
+            },
+        })
+        .collect::<Vec<_>>();
+    (return_opts, partial)
+}
+
+/// Returns whether `key` refers to messages by sequence number or UID, which
+/// is only meaningful for the selected mailbox.
+fn uses_message_set(key: &s::SearchKey<'_>) -> bool {
+    match *key {
+        s::SearchKey::Uid(_) | s::SearchKey::Seqnum(_) => true,
+        s::SearchKey::Not(ref key) => uses_message_set(key),
+        s::SearchKey::Or(ref or) => {
+            uses_message_set(&or.a) || uses_message_set(&or.b)
+        },
+        s::SearchKey::And(ref keys) => keys.iter().any(uses_message_set),
+        _ => false,
+    }
+}
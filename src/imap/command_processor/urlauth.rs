@@ -0,0 +1,171 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+//! Implementation of RFC 4467 `URLAUTH`, and resolution of IMAP URLs for it
+//! and RFC 4469 `CATENATE`.
+
+use std::borrow::Cow;
+
+use super::defs::*;
+use crate::account::{imap_url::ImapUrl, v2::URLAUTH_MECHANISM};
+use crate::imap::literal_source::LiteralSource;
+use crate::mime::fetch::section::FetchedBodySection;
+use crate::support::error::Error;
+
+impl CommandProcessor {
+    pub(super) async fn cmd_genurlauth(
+        &mut self,
+        items: Vec<s::GenUrlauthItem<'_>>,
+        sender: &mut SendResponse,
+    ) -> CmdResult {
+        let mut urls = Vec::with_capacity(items.len());
+        for item in items {
+            if !item.mechanism.eq_ignore_ascii_case(URLAUTH_MECHANISM) {
+                return Err(unsupported_mechanism(&item.mechanism));
+            }
+
+            // RFC 4467 requires the URL to be absolute and to name the
+            // current user.
+            let url = item
+                .url
+                .parse::<ImapUrl>()
+                .ok()
+                .filter(|url| {
+                    url.host.is_some()
+                        && url
+                            .user
+                            .as_ref()
+                            .is_some_and(|u| self.user_aliases.contains(u))
+                })
+                .ok_or_else(|| bad_url(&item.url))?;
+
+            let authorised = account!(self)?.generate_urlauth(&url).map_err(
+                |e| match e {
+                    Error::BadUrl => bad_url(&item.url),
+                    e => map_error!(self)(e),
+                },
+            )?;
+            urls.push(Cow::Owned(authorised));
+        }
+
+        send_response(sender, s::Response::GenUrlauth(urls)).await;
+        success()
+    }
+
+    pub(super) fn cmd_resetkey(
+        &mut self,
+        cmd: s::ResetKeyCommand<'_>,
+    ) -> CmdResult {
+        if let Some(mechanism) = cmd
+            .mechanisms
+            .iter()
+            .find(|m| !m.eq_ignore_ascii_case(URLAUTH_MECHANISM))
+        {
+            return Err(unsupported_mechanism(mechanism));
+        }
+
+        let mailbox =
+            cmd.mailbox.as_ref().map(|m| m.get_utf8(self.unicode_aware));
+        account!(self)?
+            .reset_urlauth_keys(mailbox.as_deref())
+            .map_err(map_error! {
+                self,
+                NxMailbox =>
+                    (No, Some(s::RespTextCode::Nonexistent(()))),
+                UnsafeName | MailboxUnselectable =>
+                    (No, Some(s::RespTextCode::Cannot(()))),
+            })?;
+
+        success()
+    }
+
+    pub(super) async fn cmd_urlfetch(
+        &mut self,
+        urls: Vec<Cow<'_, str>>,
+        sender: &mut SendResponse,
+    ) -> CmdResult {
+        for url in urls {
+            // URLFETCH only deals in URLAUTH URLs; anything else, like any
+            // URL we can't resolve, gets NIL data.
+            let data = if url
+                .parse::<ImapUrl>()
+                .is_ok_and(|parsed| parsed.access.is_some())
+            {
+                self.fetch_url(&url).ok().map(|fetched| {
+                    let len = fetched.buffer.len();
+                    LiteralSource::of_reader(
+                        fetched.buffer,
+                        len,
+                        fetched.contains_nul,
+                    )
+                })
+            } else {
+                None
+            };
+
+            send_response(
+                sender,
+                s::Response::UrlFetch(s::UrlFetchData {
+                    url: Cow::Owned(url.into_owned()),
+                    data,
+                }),
+            )
+            .await;
+        }
+
+        success()
+    }
+
+    /// Resolves the IMAP URL `url` on behalf of the current user.
+    ///
+    /// Any failure to parse, authorise, or fetch the URL results in a `NO
+    /// [BADURL]` response.
+    pub(super) fn fetch_url(
+        &mut self,
+        url: &str,
+    ) -> PartialResult<FetchedBodySection> {
+        let parsed = url
+            .parse::<ImapUrl>()
+            .ok()
+            .filter(|parsed| parsed.permits(&self.user_aliases, false))
+            .ok_or_else(|| bad_url(url))?;
+
+        account!(self)?.fetch_url(&parsed).map_err(|e| match e {
+            Error::BadUrl | Error::UnsupportedUrlauthMechanism => bad_url(url),
+            e => map_error!(self)(e),
+        })
+    }
+}
+
+fn bad_url(url: &str) -> s::Response<'static> {
+    s::Response::Cond(s::CondResponse {
+        cond: s::RespCondType::No,
+        code: Some(s::RespTextCode::BadUrl(Cow::Owned(url.to_owned()))),
+        quip: Some(Cow::Borrowed("Invalid or inaccessible URL")),
+    })
+}
+
+fn unsupported_mechanism(mechanism: &str) -> s::Response<'static> {
+    s::Response::Cond(s::CondResponse {
+        cond: s::RespCondType::No,
+        code: None,
+        quip: Some(Cow::Owned(format!(
+            "Unsupported URLAUTH mechanism: {mechanism}"
+        ))),
+    })
+}
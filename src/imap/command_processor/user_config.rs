@@ -19,8 +19,8 @@
 use std::borrow::Cow;
 
 use super::defs::*;
-use crate::account::model::*;
-use crate::support::error::Error;
+use crate::account::{key_store::KeyAlgorithm, model::*};
+use crate::support::{error::Error, user_config::AppPasswordRestriction};
 
 impl CommandProcessor {
     pub(super) async fn cmd_xcry_get_user_config(
@@ -38,6 +38,17 @@ impl CommandProcessor {
                     Cow::Borrowed("EXTERNAL-KEY-PATTERN"),
                     Cow::Borrowed("PASSWORD"),
                     Cow::Borrowed("SMTP-OUT"),
+                    Cow::Borrowed("APP-PASSWORD"),
+                    Cow::Borrowed("LOGIN-HISTORY"),
+                    Cow::Borrowed("NEW-DEVICE-NOTIFICATIONS"),
+                    Cow::Borrowed("EXPORT"),
+                    Cow::Borrowed("RESTORE"),
+                    Cow::Borrowed("EXPUNGE-RETENTION"),
+                    Cow::Borrowed("UNEXPUNGE"),
+                    Cow::Borrowed("MAILBOX-RETENTION"),
+                    Cow::Borrowed("KEY-RETIREMENT"),
+                    Cow::Borrowed("KEY-ALGORITHM"),
+                    Cow::Borrowed("SEARCH-MAILBOX"),
                 ],
                 internal_key_pattern: Cow::Owned(
                     user_config.key_store.internal_key_pattern,
@@ -64,6 +75,25 @@ impl CommandProcessor {
                             .clone()
                             .map(Cow::Owned),
                     ),
+                    s::XCry2UserConfigData::NewDeviceNotifications(
+                        if user_config.login.new_device_notifications {
+                            s::XCryToggle::On
+                        } else {
+                            s::XCryToggle::Off
+                        },
+                    ),
+                    s::XCry2UserConfigData::ExpungeRetention(
+                        user_config.expunge.retention_days,
+                    ),
+                    s::XCry2UserConfigData::KeyRetirement(
+                        user_config.key_store.retire_after_days.unwrap_or(0),
+                    ),
+                    s::XCry2UserConfigData::KeyAlgorithm(
+                        match user_config.key_store.algorithm {
+                            KeyAlgorithm::Rsa => s::XCryKeyAlgorithm::Rsa,
+                            KeyAlgorithm::X25519 => s::XCryKeyAlgorithm::X25519,
+                        },
+                    ),
                 ],
             }),
         )
@@ -99,13 +129,33 @@ impl CommandProcessor {
                     request.smtp_out_failure_receipts =
                         Some(s.map(Cow::into_owned));
                 },
+                s::XCryUserConfigOption::NewDeviceNotifications(toggle) => {
+                    request.new_device_notifications =
+                        Some(s::XCryToggle::On == toggle);
+                },
+                s::XCryUserConfigOption::ExpungeRetention(days) => {
+                    request.expunge_retention_days = Some(days);
+                },
+                s::XCryUserConfigOption::KeyRetirement(days) => {
+                    request.key_retirement_days =
+                        Some(Some(days).filter(|&d| d > 0));
+                },
+                s::XCryUserConfigOption::KeyAlgorithm(algorithm) => {
+                    request.key_algorithm = Some(match algorithm {
+                        s::XCryKeyAlgorithm::Rsa => KeyAlgorithm::Rsa,
+                        s::XCryKeyAlgorithm::X25519 => KeyAlgorithm::X25519,
+                    });
+                },
             }
         }
 
         let backup_file =
             account!(self)?.update_config(request).map_err(map_error! {
                 self,
-                UnsafeName => (No, Some(s::RespTextCode::Cannot(()))),
+                UnsafeName | RetentionOutOfRange =>
+                    (No, Some(s::RespTextCode::Cannot(()))),
+                NotPermittedForAppPassword =>
+                    (No, Some(s::RespTextCode::NoPerm(()))),
             })?;
 
         send_response(
@@ -115,4 +165,139 @@ impl CommandProcessor {
         .await;
         success()
     }
+
+    pub(super) async fn cmd_xcry_app_password(
+        &mut self,
+        cmd: s::XCryAppPasswordCommand<'_>,
+        sender: &mut SendResponse,
+    ) -> CmdResult {
+        match cmd {
+            s::XCryAppPasswordCommand::List(()) => {
+                let app_passwords = account!(self)?
+                    .list_app_passwords()
+                    .map_err(map_error!(self))?;
+                for ap in app_passwords {
+                    send_response(
+                        sender,
+                        s::Response::XCryAppPassword(s::XCryAppPasswordData {
+                            name: Cow::Owned(ap.name),
+                            restriction: restriction_to_wire(ap.restriction),
+                            created: ap.created,
+                            last_used: ap.last_used,
+                        }),
+                    )
+                    .await;
+                }
+
+                success()
+            },
+
+            s::XCryAppPasswordCommand::Create(cmd) => {
+                let password = account!(self)?
+                    .create_app_password(
+                        &cmd.name,
+                        restriction_from_wire(cmd.restriction),
+                    )
+                    .map_err(map_error! {
+                        self,
+                        UnsafeName => (No, Some(s::RespTextCode::Cannot(()))),
+                        AppPasswordExists =>
+                            (No, Some(s::RespTextCode::AlreadyExists(()))),
+                        TooManyAppPasswords =>
+                            (No, Some(s::RespTextCode::Limit(()))),
+                        NotPermittedForAppPassword =>
+                            (No, Some(s::RespTextCode::NoPerm(()))),
+                    })?;
+
+                send_response(
+                    sender,
+                    s::Response::XCryAppPasswordCreated(
+                        s::XCryAppPasswordCreatedData {
+                            name: Cow::Owned(cmd.name.into_owned()),
+                            password: Cow::Owned(password),
+                        },
+                    ),
+                )
+                .await;
+                success()
+            },
+
+            s::XCryAppPasswordCommand::Revoke(name) => {
+                account!(self)?.revoke_app_password(&name).map_err(
+                    map_error! {
+                        self,
+                        NxAppPassword =>
+                            (No, Some(s::RespTextCode::Nonexistent(()))),
+                        NotPermittedForAppPassword =>
+                            (No, Some(s::RespTextCode::NoPerm(()))),
+                    },
+                )?;
+                success()
+            },
+        }
+    }
+}
+
+impl CommandProcessor {
+    pub(super) async fn cmd_xcry_login_history(
+        &mut self,
+        limit: u32,
+        sender: &mut SendResponse,
+    ) -> CmdResult {
+        let history = account!(self)?
+            .login_history(limit)
+            .map_err(map_error!(self))?;
+
+        for entry in history {
+            send_response(
+                sender,
+                s::Response::XCryLoginHistory(Box::new(
+                    s::XCryLoginHistoryData {
+                        at: entry.at.into(),
+                        protocol: Cow::Owned(entry.protocol),
+                        outcome: if entry.success {
+                            s::XCryLoginOutcome::Success
+                        } else {
+                            s::XCryLoginOutcome::Failure
+                        },
+                        peer_ip: entry.peer_ip.map(Cow::Owned),
+                        tls: entry.tls.map(Cow::Owned),
+                        client_id: entry.client_id.map(Cow::Owned),
+                        app_password: entry.app_password.map(Cow::Owned),
+                    },
+                )),
+            )
+            .await;
+        }
+
+        success()
+    }
+}
+
+fn restriction_to_wire(
+    restriction: AppPasswordRestriction,
+) -> s::XCryAppPasswordRestriction {
+    match restriction {
+        AppPasswordRestriction::None => s::XCryAppPasswordRestriction::Any,
+        AppPasswordRestriction::ImapOnly => {
+            s::XCryAppPasswordRestriction::ImapOnly
+        },
+        AppPasswordRestriction::SubmissionOnly => {
+            s::XCryAppPasswordRestriction::SubmissionOnly
+        },
+    }
+}
+
+fn restriction_from_wire(
+    restriction: s::XCryAppPasswordRestriction,
+) -> AppPasswordRestriction {
+    match restriction {
+        s::XCryAppPasswordRestriction::Any => AppPasswordRestriction::None,
+        s::XCryAppPasswordRestriction::ImapOnly => {
+            AppPasswordRestriction::ImapOnly
+        },
+        s::XCryAppPasswordRestriction::SubmissionOnly => {
+            AppPasswordRestriction::SubmissionOnly
+        },
+    }
 }
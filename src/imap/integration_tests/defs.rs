@@ -118,6 +118,12 @@ async fn run_imap_server(
                 host_name: "mx.example.com".to_owned(),
                 ..Default::default()
             },
+            identification: [(
+                "admin".to_owned(),
+                "mailto:postmaster@example.com".to_owned(),
+            )]
+            .into_iter()
+            .collect(),
             ..Default::default()
         }),
         data_root,
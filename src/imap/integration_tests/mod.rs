@@ -172,20 +172,29 @@ mod rfc3502;
 mod rfc3516;
 mod rfc3691;
 mod rfc4315;
+mod rfc4467;
+mod rfc4469;
 mod rfc4731;
 mod rfc4959;
 mod rfc4978;
 mod rfc5161;
 mod rfc5182;
 mod rfc5258;
+mod rfc5267;
+mod rfc5464;
+mod rfc5465;
 mod rfc5819;
 mod rfc6154;
 mod rfc6851;
 mod rfc6855;
 mod rfc7162;
+mod rfc7377;
 mod rfc7888;
+mod rfc8437;
 mod rfc8438;
 mod rfc8474;
 mod rfc8514;
+mod rfc8970;
+mod rfc9394;
 mod xcry;
 mod xlist;
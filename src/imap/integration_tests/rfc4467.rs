@@ -0,0 +1,157 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::Read;
+
+use super::defs::*;
+
+#[test]
+fn capability_declared() {
+    test_require_capability("4467capa", "URLAUTH");
+}
+
+fn genurlauth(client: &mut PipeClient, url: &str) -> String {
+    command!(mut responses = client, cb(&format!("GENURLAUTH \"{url}\" INTERNAL")));
+    assert_eq!(2, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::GenUrlauth(ref urls) in responses => {
+            assert_eq!(1, urls.len());
+            urls[0].clone().into_owned()
+        }
+    }
+}
+
+fn urlfetch(client: &mut PipeClient, url: &str) -> Option<String> {
+    command!(mut responses = client, cb(&format!("URLFETCH \"{url}\"")));
+    assert_eq!(2, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+    match responses.pop().unwrap() {
+        s::ResponseLine {
+            tag: None,
+            response: s::Response::UrlFetch(s::UrlFetchData { url: u, data }),
+        } => {
+            assert_eq!(url, u);
+            data.map(|mut lit| {
+                let mut data = String::new();
+                lit.data.read_to_string(&mut data).unwrap();
+                data
+            })
+        },
+        r => panic!("Unexpected response: {:?}", r),
+    }
+}
+
+#[test]
+fn urlauth_lifecycle() {
+    let setup = set_up();
+    let mut client = setup.connect("4467life");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "4467life");
+    quick_append_enron(&mut client, "4467life", 1);
+
+    let rump = "imap://azure@localhost/4467life/;UID=1/;SECTION=HEADER\
+                ;URLAUTH=user+azure";
+    // Not authorised yet.
+    assert_eq!(None, urlfetch(&mut client, rump));
+
+    let url = genurlauth(&mut client, rump);
+    assert!(url.starts_with(&format!("{rump}:INTERNAL:")));
+    let header = urlfetch(&mut client, &url).unwrap();
+    assert!(
+        header.ends_with("\r\n\r\n"),
+        "Unexpected header: {header:?}"
+    );
+
+    // The URL can also be used with CATENATE.
+    client
+        .write_raw(
+            format!(
+                "A1 APPEND 4467life CATENATE (URL \"{url}\" TEXT {{4+}}\r\n\
+                 body)\r\n"
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+    let mut buffer = Vec::new();
+    let mut responses =
+        client.read_responses_until_tagged(&mut buffer).unwrap();
+    assert_tagged_ok_any(responses.pop().unwrap());
+
+    // Submission-only URLs can't be fetched over IMAP.
+    let submit_url = genurlauth(
+        &mut client,
+        "imap://azure@localhost/4467life/;UID=1;URLAUTH=submit+azure",
+    );
+    assert_eq!(None, urlfetch(&mut client, &submit_url));
+
+    ok_command!(client, c("RESETKEY 4467life INTERNAL"));
+    assert_eq!(None, urlfetch(&mut client, &url));
+    let new_url = genurlauth(&mut client, rump);
+    assert_ne!(url, new_url);
+    assert_eq!(Some(header), urlfetch(&mut client, &new_url));
+
+    ok_command!(client, c("RESETKEY"));
+    assert_eq!(None, urlfetch(&mut client, &new_url));
+}
+
+#[test]
+fn genurlauth_errors() {
+    let setup = set_up();
+    let mut client = setup.connect("4467gerr");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "4467gerr");
+    quick_append_enron(&mut client, "4467gerr", 1);
+
+    for url in [
+        // Not a URLAUTH URL
+        "imap://azure@localhost/4467gerr/;UID=1",
+        // Not absolute
+        "/4467gerr/;UID=1;URLAUTH=anonymous",
+        // Someone else's mailbox
+        "imap://someone@localhost/4467gerr/;UID=1;URLAUTH=anonymous",
+        // No such mailbox
+        "imap://azure@localhost/4467nx/;UID=1;URLAUTH=anonymous",
+    ] {
+        command!(mut responses = client, cb(&format!(
+            "GENURLAUTH \"{url}\" INTERNAL"
+        )));
+        assert_eq!(1, responses.len());
+        unpack_cond_response! {
+            (
+                Some(_),
+                s::RespCondType::No,
+                Some(s::RespTextCode::BadUrl(bad)),
+                _
+            ) = responses.pop().unwrap() => {
+                assert_eq!(url, bad);
+            }
+        };
+    }
+
+    command!(mut responses = client, c(
+        "GENURLAUTH \"imap://azure@localhost/4467gerr/;UID=1;URLAUTH=anonymous\" \
+         OTHER"
+    ));
+    assert_eq!(1, responses.len());
+    assert_tagged_no(responses.pop().unwrap());
+
+    command!(mut responses = client, c("RESETKEY 4467gerr OTHER"));
+    assert_eq!(1, responses.len());
+    assert_tagged_no(responses.pop().unwrap());
+}
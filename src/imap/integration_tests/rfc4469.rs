@@ -0,0 +1,179 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::Read;
+
+use super::defs::*;
+
+#[test]
+fn capability_declared() {
+    test_require_capability("4469capa", "CATENATE");
+}
+
+const MESSAGE: &[u8] = b"\
+From: foo@example.com\r
+Subject: Catenate test\r
+\r
+Original body\r
+";
+
+fn fetch_body(client: &mut PipeClient, uid: u32) -> String {
+    fetch_single!(client, cb(&format!("UID FETCH {uid} BODY.PEEK[]")), fr => {
+        has_msgatt_matching! {
+            move s::MsgAtt::Body(s::MsgAttBody { data: mut lit, .. }) in fr => {
+                let mut data = String::new();
+                lit.data.read_to_string(&mut data).unwrap();
+                data
+            }
+        }
+    })
+}
+
+#[test]
+fn catenate_urls_and_text() {
+    let setup = set_up();
+    let mut client = setup.connect("4469cat");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "4469cat");
+    client
+        .start_append("4469cat", s::AppendFragment::default(), MESSAGE)
+        .unwrap();
+    let mut buffer = Vec::new();
+    let mut responses = client.finish_append(&mut buffer).unwrap();
+    assert_tagged_ok_any(responses.pop().unwrap());
+    quick_select(&mut client, "4469cat");
+
+    client
+        .write_raw(
+            b"A1 APPEND 4469cat CATENATE \
+              (URL \"/4469cat/;UID=1/;SECTION=HEADER\" TEXT {8+}\r\n\
+              Replaced)\r\n",
+        )
+        .unwrap();
+    buffer.clear();
+    let mut responses =
+        client.read_responses_until_tagged(&mut buffer).unwrap();
+    assert_tagged_ok_any(responses.pop().unwrap());
+    assert_eq!(
+        "From: foo@example.com\r\nSubject: Catenate test\r\n\r\nReplaced",
+        fetch_body(&mut client, 2),
+    );
+
+    // A list of nothing but URLs has no literal at all.
+    client
+        .write_raw(
+            b"A2 APPEND 4469cat CATENATE (URL \"/4469cat/;UID=1\" \
+              URL \"imap://azure@localhost/4469cat/;UID=1/;SECTION=TEXT\")\r\n",
+        )
+        .unwrap();
+    buffer.clear();
+    let mut responses =
+        client.read_responses_until_tagged(&mut buffer).unwrap();
+    assert_tagged_ok_any(responses.pop().unwrap());
+    assert_eq!(
+        "From: foo@example.com\r\nSubject: Catenate test\r\n\r\n\
+         Original body\r\nOriginal body\r\n",
+        fetch_body(&mut client, 3),
+    );
+
+    // Synchronising literals, and CATENATE mixed with MULTIAPPEND.
+    client
+        .write_raw(b"A3 APPEND 4469cat CATENATE (TEXT {4}\r\n")
+        .unwrap();
+    buffer.clear();
+    client.read_logical_line(&mut buffer).unwrap();
+    assert!(buffer.starts_with(b"+ "));
+    client
+        .write_raw(
+            b"foo: URL \"/4469cat/;UID=1/;PARTIAL=6.3\") {3+}\r\nbar\r\n",
+        )
+        .unwrap();
+    buffer.clear();
+    let mut responses =
+        client.read_responses_until_tagged(&mut buffer).unwrap();
+    assert_tagged_ok_any(responses.pop().unwrap());
+    assert_eq!("foo:foo", fetch_body(&mut client, 4));
+    assert_eq!("bar", fetch_body(&mut client, 5));
+}
+
+#[test]
+fn catenate_errors() {
+    let setup = set_up();
+    let mut client = setup.connect("4469err");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "4469err");
+    client
+        .start_append("4469err", s::AppendFragment::default(), MESSAGE)
+        .unwrap();
+    let mut buffer = Vec::new();
+    let mut responses = client.finish_append(&mut buffer).unwrap();
+    assert_tagged_ok_any(responses.pop().unwrap());
+    quick_select(&mut client, "4469err");
+
+    for url in [
+        "/4469err/;UID=99",
+        "/4469nx/;UID=1",
+        "imap://someone@localhost/4469err/;UID=1",
+        "/4469err/;UID=1;URLAUTH=anonymous",
+    ] {
+        client
+            .write_raw(
+                format!(
+                    "A1 APPEND 4469err CATENATE (TEXT {{3+}}\r\n\
+                     foo URL \"{url}\" TEXT {{3+}}\r\nbar)\r\n",
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        buffer.clear();
+        let mut responses =
+            client.read_responses_until_tagged(&mut buffer).unwrap();
+        unpack_cond_response! {
+            (
+                Some(_),
+                s::RespCondType::No,
+                Some(s::RespTextCode::BadUrl(bad)),
+                _
+            ) = responses.pop().unwrap() => {
+                assert_eq!(url, bad);
+            }
+        };
+    }
+
+    // Bad syntax in the list is rejected and the stream recovers.
+    client
+        .write_raw(
+            b"A2 APPEND 4469err CATENATE (TEXT {3+}\r\nfoo BOGUS {3+}\r\nbar)\r\n",
+        )
+        .unwrap();
+    buffer.clear();
+    let mut responses =
+        client.read_responses_until_tagged(&mut buffer).unwrap();
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::Bad, _, _) = responses.pop().unwrap()
+    };
+
+    ok_command!(client, c("NOOP"));
+    command!(mut responses = client, c("STATUS 4469err (MESSAGES)"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Status(ref sr) in responses => {
+            assert_eq!(vec![s::StatusResponseAtt::Messages(1)], sr.atts);
+        }
+    };
+}
@@ -43,11 +43,16 @@ fn test_esearch() {
     esearch_eq(
         s::EsearchResponse {
             tag: Cow::Borrowed(""),
+            mailbox: None,
+            uidvalidity: None,
             uid: false,
             min: Some(3),
             max: None,
             all: None,
             count: None,
+            partial: None,
+            addto: None,
+            removeto: None,
             modseq: None,
         },
         &mut client,
@@ -56,11 +61,16 @@ fn test_esearch() {
     esearch_eq(
         s::EsearchResponse {
             tag: Cow::Borrowed(""),
+            mailbox: None,
+            uidvalidity: None,
             uid: false,
             min: None,
             max: Some(5),
             all: None,
             count: None,
+            partial: None,
+            addto: None,
+            removeto: None,
             modseq: None,
         },
         &mut client,
@@ -69,11 +79,16 @@ fn test_esearch() {
     esearch_eq(
         s::EsearchResponse {
             tag: Cow::Borrowed(""),
+            mailbox: None,
+            uidvalidity: None,
             uid: false,
             min: None,
             max: None,
             all: Some(Cow::Borrowed("3:5")),
             count: None,
+            partial: None,
+            addto: None,
+            removeto: None,
             modseq: None,
         },
         &mut client,
@@ -82,11 +97,16 @@ fn test_esearch() {
     esearch_eq(
         s::EsearchResponse {
             tag: Cow::Borrowed(""),
+            mailbox: None,
+            uidvalidity: None,
             uid: false,
             min: None,
             max: None,
             all: None,
             count: Some(3),
+            partial: None,
+            addto: None,
+            removeto: None,
             modseq: None,
         },
         &mut client,
@@ -95,11 +115,16 @@ fn test_esearch() {
     esearch_eq(
         s::EsearchResponse {
             tag: Cow::Borrowed(""),
+            mailbox: None,
+            uidvalidity: None,
             uid: true,
             min: Some(3),
             max: Some(7),
             all: Some(Cow::Borrowed("3:5,7")),
             count: Some(4),
+            partial: None,
+            addto: None,
+            removeto: None,
             modseq: None,
         },
         &mut client,
@@ -145,11 +170,16 @@ fn condstore_interaction() {
     esearch_eq(
         s::EsearchResponse {
             tag: Cow::Borrowed(""),
+            mailbox: None,
+            uidvalidity: None,
             uid: false,
             min: None,
             max: None,
             all: Some(Cow::Borrowed("1:21")),
             count: None,
+            partial: None,
+            addto: None,
+            removeto: None,
             modseq: Some(max_modseq),
         },
         &mut client,
@@ -158,11 +188,16 @@ fn condstore_interaction() {
     esearch_eq(
         s::EsearchResponse {
             tag: Cow::Borrowed(""),
+            mailbox: None,
+            uidvalidity: None,
             uid: false,
             min: None,
             max: None,
             all: None,
             count: Some(21),
+            partial: None,
+            addto: None,
+            removeto: None,
             modseq: Some(max_modseq),
         },
         &mut client,
@@ -171,11 +206,16 @@ fn condstore_interaction() {
     esearch_eq(
         s::EsearchResponse {
             tag: Cow::Borrowed(""),
+            mailbox: None,
+            uidvalidity: None,
             uid: false,
             min: Some(1),
             max: None,
             all: None,
             count: None,
+            partial: None,
+            addto: None,
+            removeto: None,
             modseq: Some(first_modseq),
         },
         &mut client,
@@ -184,11 +224,16 @@ fn condstore_interaction() {
     esearch_eq(
         s::EsearchResponse {
             tag: Cow::Borrowed(""),
+            mailbox: None,
+            uidvalidity: None,
             uid: false,
             min: None,
             max: Some(21),
             all: None,
             count: None,
+            partial: None,
+            addto: None,
+            removeto: None,
             modseq: Some(last_modseq),
         },
         &mut client,
@@ -197,11 +242,16 @@ fn condstore_interaction() {
     esearch_eq(
         s::EsearchResponse {
             tag: Cow::Borrowed(""),
+            mailbox: None,
+            uidvalidity: None,
             uid: false,
             min: Some(1),
             max: Some(21),
             all: None,
             count: None,
+            partial: None,
+            addto: None,
+            removeto: None,
             modseq: Some(first_modseq.max(last_modseq)),
         },
         &mut client,
@@ -210,11 +260,16 @@ fn condstore_interaction() {
     esearch_eq(
         s::EsearchResponse {
             tag: Cow::Borrowed(""),
+            mailbox: None,
+            uidvalidity: None,
             uid: false,
             min: Some(1),
             max: Some(21),
             all: None,
             count: Some(21),
+            partial: None,
+            addto: None,
+            removeto: None,
             modseq: Some(max_modseq),
         },
         &mut client,
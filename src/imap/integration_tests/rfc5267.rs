@@ -0,0 +1,218 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use super::defs::*;
+
+#[test]
+fn capability_declared() {
+    test_require_capability("5267capa", "CONTEXT=SEARCH");
+}
+
+#[test]
+fn search_updates() {
+    let setup = set_up();
+    let mut client = setup.connect("5267upda");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "5267upda");
+    quick_append_enron(&mut client, "5267upda", 4);
+    quick_select(&mut client, "5267upda");
+    ok_command!(client, c("XCRY FLAGS OFF"));
+    ok_command!(client, c("STORE 2 +FLAGS (\\Flagged)"));
+
+    let mut client2 = setup.connect("5267upd2");
+    quick_log_in(&mut client2);
+    quick_select(&mut client2, "5267upda");
+    ok_command!(client2, c("XCRY FLAGS OFF"));
+
+    command!(mut responses = client, c("UID SEARCH RETURN (UPDATE) FLAGGED"));
+    assert_eq!(2, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+    let uid_tag = has_untagged_response_matching! {
+        s::Response::Esearch(s::EsearchResponse {
+            ref tag,
+            uid: true,
+            all: Some(ref all),
+            addto: None,
+            removeto: None,
+            ..
+        }) in responses => {
+            assert_eq!("2", all);
+            tag.clone().into_owned()
+        }
+    };
+
+    command!(mut responses = client, c("SEARCH RETURN (UPDATE COUNT) UNFLAGGED"));
+    assert_eq!(2, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+    let seqnum_tag = has_untagged_response_matching! {
+        s::Response::Esearch(s::EsearchResponse {
+            ref tag,
+            uid: false,
+            count: Some(3),
+            all: None,
+            ..
+        }) in responses => tag.clone().into_owned()
+    };
+
+    ok_command!(client2, c("STORE 1 +FLAGS (\\Flagged)"));
+    ok_command!(client2, c("STORE 2 -FLAGS (\\Flagged)"));
+    ok_command!(client2, c("STORE 4 +FLAGS (\\Deleted)"));
+    ok_command!(client2, c("EXPUNGE"));
+
+    command!(mut responses = client, c("NOOP"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Esearch(s::EsearchResponse {
+            ref tag,
+            uid: true,
+            addto: Some(ref addto),
+            removeto: Some(ref removeto),
+            ..
+        }) in responses => {
+            assert_eq!(&uid_tag, tag);
+            assert_eq!("1", addto);
+            assert_eq!("2", removeto);
+        }
+    };
+    has_untagged_response_matching! {
+        s::Response::Esearch(s::EsearchResponse {
+            ref tag,
+            uid: false,
+            addto: Some(ref addto),
+            removeto: Some(ref removeto),
+            ..
+        }) in responses => {
+            assert_eq!(&seqnum_tag, tag);
+            assert_eq!("2", addto);
+            assert_eq!("1", removeto);
+        }
+    };
+
+    command!(mut responses = client, cb(&format!("CANCELUPDATE {uid_tag:?}")));
+    assert_tagged_ok(responses.pop().unwrap());
+
+    ok_command!(client2, c("STORE 1 -FLAGS (\\Flagged)"));
+
+    command!(mut responses = client, c("NOOP"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Esearch(s::EsearchResponse {
+            ref tag,
+            addto: Some(ref addto),
+            removeto: None,
+            ..
+        }) in responses => {
+            assert_eq!(&seqnum_tag, tag);
+            assert_eq!("1", addto);
+        }
+    };
+    assert_eq!(
+        1,
+        responses
+            .iter()
+            .filter(|r| matches!(r.response, s::Response::Esearch(..)))
+            .count(),
+    );
+
+    // Contexts do not survive deselecting the mailbox
+    ok_command!(client, c("UNSELECT"));
+    quick_select(&mut client, "5267upda");
+    ok_command!(client2, c("STORE 1 +FLAGS (\\Flagged)"));
+    command!(responses = client, c("NOOP"));
+    assert!(!responses
+        .iter()
+        .any(|r| matches!(r.response, s::Response::Esearch(..))));
+}
+
+#[test]
+fn update_refused() {
+    let setup = set_up();
+    let mut client = setup.connect("5267refu");
+    quick_log_in(&mut client);
+    examine_shared(&mut client);
+
+    command!(mut responses = client, c("SEARCH RETURN (UPDATE MIN) ALL"));
+    assert_eq!(3, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Cond(s::CondResponse {
+            cond: s::RespCondType::No,
+            code: Some(s::RespTextCode::NoUpdate(..)),
+            ..
+        }) in responses
+    };
+    has_untagged_response_matching! {
+        s::Response::Esearch(s::EsearchResponse {
+            min: Some(1),
+            ..
+        }) in responses
+    };
+
+    // The default limit is 8 contexts.
+    for _ in 0..8 {
+        command!(mut responses = client, c("SEARCH RETURN (UPDATE) ALL"));
+        assert_eq!(2, responses.len());
+        assert_tagged_ok(responses.pop().unwrap());
+    }
+
+    command!(mut responses = client, c("SEARCH RETURN (UPDATE) ALL"));
+    assert_eq!(3, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Cond(s::CondResponse {
+            code: Some(s::RespTextCode::NoUpdate(..)),
+            ..
+        }) in responses
+    };
+}
+
+#[test]
+fn partial() {
+    let setup = set_up();
+    let mut client = setup.connect("5267part");
+    quick_log_in(&mut client);
+    examine_shared(&mut client);
+
+    command!(mut responses = client, c("SEARCH RETURN (PARTIAL 2:4) 3:10"));
+    assert_eq!(2, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Esearch(s::EsearchResponse {
+            partial: Some(s::EsearchPartial {
+                range: s::PartialRange { start: 2, end: 4 },
+                hits: Some(ref hits),
+            }),
+            all: None,
+            ..
+        }) in responses => assert_eq!("4:6", hits)
+    };
+
+    command!(mut responses = client, c("SEARCH RETURN (PARTIAL 9:20 COUNT) 3:10"));
+    assert_eq!(2, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Esearch(s::EsearchResponse {
+            partial: Some(s::EsearchPartial {
+                hits: None,
+                ..
+            }),
+            count: Some(8),
+            ..
+        }) in responses
+    };
+}
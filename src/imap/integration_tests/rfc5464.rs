@@ -0,0 +1,311 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use super::defs::*;
+
+#[test]
+fn capability_declared() {
+    test_require_capability("5464capa", "METADATA");
+    test_require_capability("5464capa", "METADATA-SERVER");
+}
+
+/// Returns the mailbox and entries of every untagged `METADATA` response.
+fn metadata(
+    responses: &[s::ResponseLine<'_>],
+) -> Vec<(String, String, Option<String>)> {
+    let mut ret = Vec::new();
+    for r in responses {
+        if let s::Response::Metadata(ref m) = r.response {
+            for e in &m.entries {
+                ret.push((
+                    m.mailbox.raw.clone().into_owned(),
+                    e.entry.clone().into_owned(),
+                    e.value.clone().map(|v| v.into_owned()),
+                ));
+            }
+        }
+    }
+    ret
+}
+
+fn entry(
+    mailbox: &str,
+    entry: &str,
+    value: Option<&str>,
+) -> (String, String, Option<String>) {
+    (
+        mailbox.to_owned(),
+        entry.to_owned(),
+        value.map(str::to_owned),
+    )
+}
+
+#[test]
+fn mailbox_metadata() {
+    let setup = set_up();
+    let mut client = setup.connect("5464mbmd");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "5464mbmd");
+
+    ok_command!(
+        client,
+        c("SETMETADATA 5464mbmd (/private/comment \"Mine\" \
+           /shared/comment \"Everyone's\" \
+           /private/vendor/test/colour \"red\")")
+    );
+
+    command!(
+        mut responses = client,
+        c("GETMETADATA 5464mbmd (/PRIVATE/COMMENT /private/nonexistent)")
+    );
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![
+            entry("5464mbmd", "/private/comment", Some("Mine")),
+            entry("5464mbmd", "/private/nonexistent", None),
+        ],
+        metadata(&responses),
+    );
+
+    command!(
+        mut responses = client,
+        c("GETMETADATA (DEPTH infinity) 5464mbmd /private")
+    );
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![
+            entry("5464mbmd", "/private/comment", Some("Mine")),
+            entry("5464mbmd", "/private/vendor/test/colour", Some("red")),
+        ],
+        metadata(&responses),
+    );
+
+    command!(
+        mut responses = client,
+        c("GETMETADATA (DEPTH 1) 5464mbmd /private")
+    );
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![entry("5464mbmd", "/private/comment", Some("Mine"))],
+        metadata(&responses),
+    );
+
+    command!(
+        mut responses = client,
+        c("GETMETADATA (MAXSIZE 5) 5464mbmd \
+           (/private/comment /shared/comment)")
+    );
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::Ok,
+         Some(s::RespTextCode::Metadata(
+             s::MetadataRespCode::LongEntries(10))), _) =
+            responses.pop().unwrap() => ()
+    };
+    assert_eq!(
+        vec![entry("5464mbmd", "/private/comment", Some("Mine"))],
+        metadata(&responses),
+    );
+
+    ok_command!(client, c("SETMETADATA 5464mbmd (/private/comment NIL)"));
+    command!(
+        mut responses = client,
+        c("GETMETADATA (DEPTH infinity) 5464mbmd /private")
+    );
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![entry(
+            "5464mbmd",
+            "/private/vendor/test/colour",
+            Some("red")
+        )],
+        metadata(&responses),
+    );
+
+    command!(
+        [response] = client,
+        c("SETMETADATA 5464mbmd (/private/comment \"x\" /other/thing \"y\")")
+    );
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::Bad, None, _) = response => ()
+    };
+    // Nothing was changed by the failed command.
+    command!(
+        mut responses = client,
+        c("GETMETADATA 5464mbmd /private/comment")
+    );
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![entry("5464mbmd", "/private/comment", None)],
+        metadata(&responses),
+    );
+
+    command!(
+        [response] = client,
+        cb(&format!(
+            "SETMETADATA 5464mbmd (/private/comment \"{}\")",
+            "x".repeat(20000),
+        ))
+    );
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::No,
+         Some(s::RespTextCode::Metadata(s::MetadataRespCode::MaxSize(_))),
+         _) = response => ()
+    };
+
+    command!(
+        [response] = client,
+        c("SETMETADATA 5464mbmd-nx (/private/comment \"x\")")
+    );
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::No,
+         Some(s::RespTextCode::Nonexistent(())), _) = response => ()
+    };
+    command!(
+        [response] = client,
+        c("GETMETADATA 5464mbmd-nx /private/comment")
+    );
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::No,
+         Some(s::RespTextCode::Nonexistent(())), _) = response => ()
+    };
+}
+
+#[test]
+fn server_metadata() {
+    let setup = set_up();
+    let mut client = setup.connect("5464svmd");
+    quick_log_in(&mut client);
+
+    command!(mut responses = client, c("GETMETADATA \"\" /shared/admin"));
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![entry(
+            "",
+            "/shared/admin",
+            Some("mailto:postmaster@example.com"),
+        )],
+        metadata(&responses),
+    );
+
+    command!(
+        [response] = client,
+        c("SETMETADATA \"\" (/shared/admin \"mailto:me@example.com\")")
+    );
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::No,
+         Some(s::RespTextCode::NoPerm(())), _) = response => ()
+    };
+
+    ok_command!(
+        client,
+        c("SETMETADATA \"\" (/private/vendor/test/5464svmd \"hello\")")
+    );
+    command!(
+        mut responses = client,
+        c("GETMETADATA \"\" /private/vendor/test/5464svmd")
+    );
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![entry("", "/private/vendor/test/5464svmd", Some("hello"))],
+        metadata(&responses),
+    );
+}
+
+#[test]
+fn retention_via_metadata() {
+    let setup = set_up();
+    let mut client = setup.connect("5464rtmd");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "5464rtmd");
+
+    command!(
+        mut responses = client,
+        c("GETMETADATA 5464rtmd /private/vendor/crymap/retention")
+    );
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![entry("5464rtmd", "/private/vendor/crymap/retention", None)],
+        metadata(&responses),
+    );
+
+    // Nothing is applied if any entry is invalid
+    command!(
+        [response] = client,
+        c("SETMETADATA 5464rtmd (/private/vendor/crymap/retention \
+           \"max-age 30\" /other/x NIL)")
+    );
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::Bad, None, _) = response => ()
+    };
+    command!(
+        mut responses = client,
+        c("GETMETADATA 5464rtmd /private/vendor/crymap/retention")
+    );
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![entry("5464rtmd", "/private/vendor/crymap/retention", None)],
+        metadata(&responses),
+    );
+
+    ok_command!(
+        client,
+        c("SETMETADATA 5464rtmd (/private/vendor/crymap/retention \
+           \"max-age 30 max-messages 100 only-seen\")")
+    );
+    command!(
+        mut responses = client,
+        c("GETMETADATA (DEPTH infinity) 5464rtmd /private/vendor")
+    );
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![entry(
+            "5464rtmd",
+            "/private/vendor/crymap/retention",
+            Some("MAX-AGE 30 AGE-BASIS SAVEDATE MAX-MESSAGES 100 ONLY-SEEN"),
+        )],
+        metadata(&responses),
+    );
+
+    command!(mut responses = client, c("XCRY GET-MAILBOX-RETENTION"));
+    assert_tagged_ok(responses.pop().unwrap());
+    assert!(responses.iter().any(|r| matches!(
+        r.response,
+        s::Response::XCryMailboxRetention(ref r)
+            if "5464rtmd" == r.mailbox.raw
+    )));
+
+    command!(
+        [response] = client,
+        c("SETMETADATA 5464rtmd (/private/vendor/crymap/retention \
+           \"max-age forever\")")
+    );
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::Bad, None, _) = response => ()
+    };
+
+    ok_command!(
+        client,
+        c("SETMETADATA 5464rtmd (/private/vendor/crymap/retention NIL)")
+    );
+    command!(
+        mut responses = client,
+        c("GETMETADATA (DEPTH infinity) 5464rtmd /private")
+    );
+    assert_tagged_ok(responses.pop().unwrap());
+    assert!(metadata(&responses).is_empty());
+}
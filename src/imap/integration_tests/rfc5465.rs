@@ -0,0 +1,305 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use super::defs::*;
+
+#[test]
+fn capability_declared() {
+    test_require_capability("5465capa", "NOTIFY");
+}
+
+/// Returns the names and attributes of all untagged `STATUS` responses.
+fn statuses(
+    responses: &[s::ResponseLine<'_>],
+) -> Vec<(String, Vec<s::StatusResponseAtt<'static>>)> {
+    responses
+        .iter()
+        .filter_map(|r| match r.response {
+            s::Response::Status(ref sr) => Some((
+                sr.mailbox.raw.clone().into_owned(),
+                sr.atts
+                    .iter()
+                    .filter_map(|att| match *att {
+                        s::StatusResponseAtt::Messages(n) => {
+                            Some(s::StatusResponseAtt::Messages(n))
+                        },
+                        s::StatusResponseAtt::UidNext(n) => {
+                            Some(s::StatusResponseAtt::UidNext(n))
+                        },
+                        _ => None,
+                    })
+                    .collect(),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn status_notifications() {
+    let setup = set_up();
+    let mut client = setup.connect("5465stno");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "5465stno");
+    quick_create(&mut client, "5465stno/a");
+    quick_create(&mut client, "5465stno/b");
+    quick_create(&mut client, "5465stno-other");
+
+    let mut notified = setup.connect("5465stnoN");
+    quick_log_in(&mut notified);
+
+    command!(
+        mut responses = notified,
+        c("NOTIFY SET STATUS (SUBTREE 5465stno \
+           (MessageNew MessageExpunge))")
+    );
+    assert_tagged_ok(responses.pop().unwrap());
+    let mut initial = statuses(&responses);
+    initial.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        vec![
+            (
+                "5465stno".to_owned(),
+                vec![
+                    s::StatusResponseAtt::Messages(0),
+                    s::StatusResponseAtt::UidNext(1),
+                ],
+            ),
+            (
+                "5465stno/a".to_owned(),
+                vec![
+                    s::StatusResponseAtt::Messages(0),
+                    s::StatusResponseAtt::UidNext(1),
+                ],
+            ),
+            (
+                "5465stno/b".to_owned(),
+                vec![
+                    s::StatusResponseAtt::Messages(0),
+                    s::StatusResponseAtt::UidNext(1),
+                ],
+            ),
+        ],
+        initial,
+    );
+
+    quick_append_enron(&mut client, "5465stno/a", 1);
+    quick_append_enron(&mut client, "5465stno-other", 1);
+
+    command!(mut responses = notified, c("NOOP"));
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![(
+            "5465stno/a".to_owned(),
+            vec![
+                s::StatusResponseAtt::Messages(1),
+                s::StatusResponseAtt::UidNext(2),
+            ],
+        )],
+        statuses(&responses),
+    );
+
+    // Flag changes aren't reported since FlagChange wasn't requested.
+    quick_select(&mut client, "5465stno/a");
+    ok_command!(client, c("STORE 1 +FLAGS (\\Flagged)"));
+    command!(mut responses = notified, c("NOOP"));
+    assert_tagged_ok(responses.pop().unwrap());
+    assert!(statuses(&responses).is_empty());
+
+    ok_command!(client, c("XVANQUISH 1"));
+    command!(mut responses = notified, c("NOOP"));
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![(
+            "5465stno/a".to_owned(),
+            vec![
+                s::StatusResponseAtt::Messages(0),
+                s::StatusResponseAtt::UidNext(2),
+            ],
+        )],
+        statuses(&responses),
+    );
+
+    // No notifications for the selected mailbox, since it gets the normal
+    // unsolicited responses instead.
+    quick_select(&mut notified, "5465stno/b");
+    quick_append_enron(&mut client, "5465stno/b", 1);
+    command!(mut responses = notified, c("NOOP"));
+    assert_tagged_ok(responses.pop().unwrap());
+    assert!(statuses(&responses).is_empty());
+    has_untagged_response_matching! {
+        s::Response::Exists(1) in responses
+    };
+
+    ok_command!(notified, c("NOTIFY NONE"));
+    quick_append_enron(&mut client, "5465stno/a", 1);
+    command!(mut responses = notified, c("NOOP"));
+    assert_tagged_ok(responses.pop().unwrap());
+    assert!(statuses(&responses).is_empty());
+}
+
+#[test]
+fn idle_without_selected_mailbox() {
+    let setup = set_up();
+    let mut client = setup.connect("5465iwsm");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "5465iwsm");
+
+    let mut idler = setup.connect("5465iwsmI");
+    quick_log_in(&mut idler);
+    ok_command!(
+        idler,
+        c("NOTIFY SET (MAILBOXES 5465iwsm \
+           (MessageNew MessageExpunge FlagChange))")
+    );
+
+    idler.write_raw(b"I1 IDLE\r\n").unwrap();
+    let mut buffer = Vec::new();
+    idler.read_logical_line(&mut buffer).unwrap();
+    assert!(buffer.starts_with(b"+ "));
+
+    quick_append_enron(&mut client, "5465iwsm", 1);
+
+    buffer.clear();
+    let response = idler.read_one_response(&mut buffer).unwrap();
+    assert_eq!(
+        vec![(
+            "5465iwsm".to_owned(),
+            vec![
+                s::StatusResponseAtt::Messages(1),
+                s::StatusResponseAtt::UidNext(2),
+            ],
+        )],
+        statuses(&[response]),
+    );
+
+    quick_select(&mut client, "5465iwsm");
+    ok_command!(client, c("STORE 1 +FLAGS (\\Flagged)"));
+
+    buffer.clear();
+    let response = idler.read_one_response(&mut buffer).unwrap();
+    assert_eq!(
+        vec![(
+            "5465iwsm".to_owned(),
+            vec![
+                s::StatusResponseAtt::Messages(1),
+                s::StatusResponseAtt::UidNext(2),
+            ],
+        )],
+        statuses(&[response]),
+    );
+
+    idler.write_raw(b"DONE\r\n").unwrap();
+    buffer.clear();
+    let response = idler.read_one_response(&mut buffer).unwrap();
+    assert_tagged_ok(response);
+}
+
+#[test]
+fn mailbox_notifications() {
+    let setup = set_up();
+    let mut client = setup.connect("5465mbno");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "5465mbno");
+
+    let mut notified = setup.connect("5465mbnoN");
+    quick_log_in(&mut notified);
+    ok_command!(
+        notified,
+        c("NOTIFY SET (SUBTREE 5465mbno (MailboxName SubscriptionChange))")
+    );
+
+    ok_command!(client, c("CREATE 5465mbno/foo"));
+    command!(mut responses = notified, c("NOOP"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::List(ref ml) in responses => {
+            assert_eq!("5465mbno/foo", ml.name.raw);
+            assert!(ml.old_name.is_none());
+            assert!(!ml.flags.iter().any(|f| "\\NonExistent" == f));
+        }
+    };
+
+    ok_command!(client, c("RENAME 5465mbno/foo 5465mbno/bar"));
+    command!(mut responses = notified, c("NOOP"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::List(ref ml) in responses => {
+            assert_eq!("5465mbno/bar", ml.name.raw);
+            assert_eq!(
+                "5465mbno/foo",
+                ml.old_name.as_ref().unwrap().raw,
+            );
+        }
+    };
+
+    ok_command!(client, c("SUBSCRIBE 5465mbno/bar"));
+    command!(mut responses = notified, c("NOOP"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::List(ref ml) in responses => {
+            assert_eq!("5465mbno/bar", ml.name.raw);
+            assert!(ml.flags.iter().any(|f| "\\Subscribed" == f));
+        }
+    };
+
+    ok_command!(client, c("DELETE 5465mbno/bar"));
+    command!(mut responses = notified, c("NOOP"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::List(ref ml) in responses => {
+            assert_eq!("5465mbno/bar", ml.name.raw);
+            assert!(ml.flags.iter().any(|f| "\\NonExistent" == f));
+        }
+    };
+
+    // Changes outside the filter are not reported.
+    ok_command!(client, c("CREATE 5465mbno-other"));
+    command!(mut responses = notified, c("NOOP"));
+    assert_eq!(1, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+}
+
+#[test]
+fn bad_events() {
+    let setup = set_up();
+    let mut client = setup.connect("5465bdev");
+    quick_log_in(&mut client);
+
+    command!(
+        [response] = client,
+        c("NOTIFY SET (PERSONAL (AnnotationChange))")
+    );
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::No,
+         Some(s::RespTextCode::BadEvent(events)), _) = response => {
+            assert!(events.contains(&s::NotifyEvent::MessageNew));
+            assert!(!events.contains(&s::NotifyEvent::AnnotationChange));
+        }
+    };
+
+    command!([response] = client, c("NOTIFY SET (PERSONAL (MessageNew))"));
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::Bad, _, _) = response
+    };
+
+    command!([response] = client, c("NOTIFY SET (PERSONAL (FlagChange))"));
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::Bad, _, _) = response
+    };
+}
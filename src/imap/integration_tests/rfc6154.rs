@@ -120,3 +120,57 @@ fn create_special_use() {
         Error::UnsupportedSpecialUse,
     );
 }
+
+#[test]
+fn create_all_mailbox() {
+    let setup = set_up();
+    let mut client = setup.connect("6154all");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "6154all");
+    quick_append_enron(&mut client, "6154all", 1);
+
+    ok_command!(client, c("CREATE 6154all/all USE (\\All)"));
+
+    command!(mut responses = client,
+             c("LIST \"\" 6154all/% RETURN (SPECIAL-USE)"));
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!("6154all/all \\All\n", list_results_to_str(responses));
+
+    command!(mut responses = client, c("SELECT 6154all/all"));
+    assert_tagged_ok_any(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Cond(s::CondResponse {
+            code: Some(s::RespTextCode::NoModseq(())),
+            ..
+        }) in responses
+    };
+
+    command!(mut responses = client,
+             c("STATUS 6154all/all (HIGHESTMODSEQ)"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Status(ref sr) in responses => {
+            assert_eq!(
+                vec![s::StatusResponseAtt::HighestModseq(0)],
+                sr.atts,
+            );
+        }
+    };
+
+    assert_bad_command(
+        &mut client,
+        Some(s::RespTextCode::ClientBug(())),
+        "UID FETCH 1:* (FLAGS) (CHANGEDSINCE 1)",
+    );
+
+    client
+        .write_raw(b"A1 APPEND 6154all/all {3+}\r\nfoo\r\n")
+        .unwrap();
+    let mut buffer = Vec::new();
+    let response = client.read_one_response(&mut buffer).unwrap();
+    assert_error_response(
+        response,
+        Some(s::RespTextCode::Cannot(())),
+        Error::VirtualMailbox,
+    );
+}
@@ -0,0 +1,142 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use super::defs::*;
+
+#[test]
+fn capability_declared() {
+    test_require_capability("7377capa", "MULTISEARCH");
+}
+
+/// Returns the mailbox name and result data of every `ESEARCH` response in
+/// `responses`.
+fn esearch_results(
+    responses: &[s::ResponseLine<'_>],
+) -> Vec<(String, Option<String>, Option<u32>)> {
+    responses
+        .iter()
+        .filter_map(|r| match r.response {
+            s::Response::Esearch(ref er) => {
+                assert!(er.uid);
+                assert!(er.uidvalidity.is_some());
+                Some((
+                    er.mailbox
+                        .as_ref()
+                        .expect("No MAILBOX correlator")
+                        .get_utf8(false)
+                        .into_owned(),
+                    er.all.as_ref().map(|all| all.clone().into_owned()),
+                    er.count,
+                ))
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn multi_mailbox_search() {
+    let setup = set_up();
+    let mut client = setup.connect("7377mult");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "7377mult/a/deep");
+    quick_create(&mut client, "7377mult/b");
+    quick_create(&mut client, "7377mult/empty");
+    quick_append_enron(&mut client, "7377mult", 1);
+    quick_append_enron(&mut client, "7377mult/a", 2);
+    quick_append_enron(&mut client, "7377mult/a/deep", 3);
+    quick_append_enron(&mut client, "7377mult/b", 4);
+
+    command!(mut responses = client, c("ESEARCH IN (SUBTREE 7377mult) ALL"));
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![
+            ("7377mult".to_owned(), Some("1".to_owned()), None),
+            ("7377mult/a".to_owned(), Some("1:2".to_owned()), None),
+            ("7377mult/a/deep".to_owned(), Some("1:3".to_owned()), None),
+            ("7377mult/b".to_owned(), Some("1:4".to_owned()), None),
+        ],
+        esearch_results(&responses),
+    );
+
+    command!(
+        mut responses = client,
+        c("ESEARCH IN (SUBTREE-ONE 7377mult/a MAILBOXES (7377mult/b \
+           7377mult/empty)) RETURN (COUNT) ALL")
+    );
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![
+            ("7377mult/a".to_owned(), None, Some(2)),
+            ("7377mult/a/deep".to_owned(), None, Some(3)),
+            ("7377mult/b".to_owned(), None, Some(4)),
+        ],
+        esearch_results(&responses),
+    );
+
+    // Without IN, only the selected mailbox is searched
+    quick_select(&mut client, "7377mult/b");
+    command!(mut responses = client, c("ESEARCH RETURN (MIN) ALL"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Esearch(s::EsearchResponse {
+            mailbox: Some(ref mailbox),
+            uidvalidity: Some(_),
+            uid: true,
+            min: Some(1),
+            all: None,
+            ..
+        }) in responses => assert_eq!("7377mult/b", mailbox.raw)
+    };
+
+    // Message sets are fine for the selected mailbox alone
+    command!(mut responses = client, c("ESEARCH IN (SELECTED) UID 2:3"));
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![("7377mult/b".to_owned(), Some("2:3".to_owned()), None)],
+        esearch_results(&responses),
+    );
+
+    assert_bad_command(
+        &mut client,
+        Some(s::RespTextCode::ClientBug(())),
+        "ESEARCH IN (SELECTED SUBTREE 7377mult) UID 2:3",
+    );
+    assert_bad_command(
+        &mut client,
+        Some(s::RespTextCode::ClientBug(())),
+        "ESEARCH IN (SUBTREE 7377mult) NOT 1",
+    );
+    assert_bad_command(
+        &mut client,
+        Some(s::RespTextCode::ClientBug(())),
+        "ESEARCH RETURN (SAVE) ALL",
+    );
+}
+
+#[test]
+fn selected_required() {
+    let setup = set_up();
+    let mut client = setup.connect("7377slrq");
+    quick_log_in(&mut client);
+
+    command!([response] = client, c("ESEARCH ALL"));
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::Bad, _, _) = response => { }
+    }
+}
@@ -0,0 +1,68 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use super::defs::*;
+
+#[test]
+fn capability_declared() {
+    test_require_capability("8437capa", "UNAUTHENTICATE");
+}
+
+#[test]
+fn unauthenticate() {
+    let setup = set_up();
+    let mut client = setup.connect("8437unau");
+    skip_greeting(&mut client);
+
+    // Not allowed before logging in
+    command!([response] = client, c("UNAUTHENTICATE"));
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::Bad, _, _) = response
+    };
+
+    ok_command!(client, c("LOGIN azure hunter2"));
+    quick_create(&mut client, "8437unau");
+    quick_select(&mut client, "8437unau");
+
+    ok_command!(client, c("UNAUTHENTICATE"));
+
+    // Now everything requiring authentication fails, including the
+    // previously selected mailbox.
+    command!([response] = client, c("EXPUNGE"));
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::Bad, _, _) = response
+    };
+    command!([response] = client, c("LIST \"\" *"));
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::Bad, _, _) = response
+    };
+    command!([response] = client, c("EXAMINE 8437unau"));
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::Bad, _, _) = response
+    };
+    command!([response] = client, c("UNAUTHENTICATE"));
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::Bad, _, _) = response
+    };
+
+    // But the connection can be used to log in again.
+    ok_command!(client, c("LOGIN azure hunter2"));
+    command!(mut responses = client, c("EXAMINE 8437unau"));
+    assert_tagged_ok_any(responses.pop().unwrap());
+    ok_command!(client, c("UNAUTHENTICATE"));
+}
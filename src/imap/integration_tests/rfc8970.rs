@@ -0,0 +1,96 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use super::defs::*;
+use crate::account::model::Flag;
+
+#[test]
+fn capability_declared() {
+    test_require_capability("8970capa", "PREVIEW");
+}
+
+const MESSAGE: &[u8] = b"\
+From: foo@example.com\r
+Subject: Preview test\r
+Content-Type: multipart/alternative; boundary=bound\r
+\r
+--bound\r
+Content-Type: text/html; charset=utf-8\r
+Content-Transfer-Encoding: quoted-printable\r
+\r
+<html><head><style>p { margin: 0; }</style></head>\r
+<body><p>Hello &amp; caf=C3=A9,</p><p>See you <b>soon</b>.</p></body>\r
+</html>\r
+--bound--\r
+";
+
+#[test]
+fn preview() {
+    let setup = set_up();
+    let mut client = setup.connect("8970prvw");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "8970prvw");
+
+    client
+        .start_append("8970prvw", s::AppendFragment::default(), MESSAGE)
+        .unwrap();
+    let mut buffer = Vec::new();
+    let mut responses = client.finish_append(&mut buffer).unwrap();
+    assert_tagged_ok_any(responses.pop().unwrap());
+    quick_append_enron(&mut client, "8970prvw", 1);
+    quick_select(&mut client, "8970prvw");
+
+    // Nothing has been generated yet, so LAZY gives up.
+    fetch_single!(client, c("FETCH 1 (PREVIEW (LAZY))"), fr => {
+        has_msgatt_matching! {
+            s::MsgAtt::Preview(None) in fr
+        }
+    });
+
+    let preview = fetch_single!(client, c("FETCH 1 PREVIEW"), fr => {
+        has_msgatt_matching! {
+            move s::MsgAtt::Preview(Some(p)) in fr => p.into_owned()
+        }
+    });
+    assert_eq!("Hello & caf\u{e9}, See you soon.", preview);
+
+    // Now that the preview has been generated, LAZY can return it.
+    let lazy_preview = fetch_single!(client, c("FETCH 1 (UID PREVIEW (LAZY))"), fr => {
+        has_msgatt_matching! {
+            move s::MsgAtt::Preview(Some(p)) in fr => p.into_owned()
+        }
+    });
+    assert_eq!(preview, lazy_preview);
+
+    let preview = fetch_single!(client, c("FETCH 2 PREVIEW"), fr => {
+        has_msgatt_matching! {
+            move s::MsgAtt::Preview(Some(p)) in fr => p.into_owned()
+        }
+    });
+    assert!(!preview.is_empty());
+    assert!(preview.chars().count() <= 200);
+
+    // Fetching the preview does not implicitly set \Seen
+    fetch_single!(client, c("FETCH 1 FLAGS"), fr => {
+        has_msgatt_matching! {
+            s::MsgAtt::Flags(s::FlagsFetch::Recent(ref flags)) in fr => {
+                assert!(!flags.contains(&Flag::Seen));
+            }
+        }
+    });
+}
@@ -0,0 +1,166 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+use super::defs::*;
+
+#[test]
+fn capability_declared() {
+    test_require_capability("9394capa", "PARTIAL");
+}
+
+#[test]
+fn partial_search() {
+    let setup = set_up();
+    let mut client = setup.connect("9394srch");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "9394srch");
+    quick_append_enron(&mut client, "9394srch", 10);
+    quick_select(&mut client, "9394srch");
+    ok_command!(client, c("XCRY FLAGS OFF"));
+    ok_command!(client, c("UID STORE 2:4,8 +FLAGS.SILENT (\\Flagged)"));
+
+    command!(mut responses = client, c("UID SEARCH RETURN (PARTIAL -1:-2) UNFLAGGED"));
+    assert_eq!(2, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Esearch(s::EsearchResponse {
+            uid: true,
+            partial: Some(s::EsearchPartial {
+                range: s::PartialRange { start: -1, end: -2 },
+                hits: Some(ref hits),
+            }),
+            ..
+        }) in responses => assert_eq!("9:10", hits)
+    };
+
+    command!(mut responses = client, c("SEARCH RETURN (COUNT PARTIAL 3:1) UNFLAGGED"));
+    assert_eq!(2, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Esearch(s::EsearchResponse {
+            uid: false,
+            count: Some(6),
+            partial: Some(s::EsearchPartial {
+                range: s::PartialRange { start: 3, end: 1 },
+                hits: Some(ref hits),
+            }),
+            ..
+        }) in responses => assert_eq!("1,5:6", hits)
+    };
+
+    command!(mut responses = client, c("SEARCH RETURN (PARTIAL -7:-20) UNFLAGGED"));
+    assert_eq!(2, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Esearch(s::EsearchResponse {
+            partial: Some(s::EsearchPartial {
+                hits: None,
+                ..
+            }),
+            ..
+        }) in responses
+    };
+
+    assert_bad_command(
+        &mut client,
+        Some(s::RespTextCode::ClientBug(())),
+        "SEARCH RETURN (PARTIAL 1:-5) ALL",
+    );
+}
+
+#[test]
+fn partial_fetch() {
+    let setup = set_up();
+    let mut client = setup.connect("9394ftch");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "9394ftch");
+    quick_append_enron(&mut client, "9394ftch", 10);
+    quick_select(&mut client, "9394ftch");
+    ok_command!(client, c("XCRY FLAGS OFF"));
+
+    command!(mut responses = client, c("UID FETCH 1:* (FLAGS) (PARTIAL -1:-3)"));
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![8, 9, 10],
+        responses
+            .iter()
+            .filter_map(|r| match r.response {
+                s::Response::Fetch(ref fr) => Some(fr.seqnum),
+                _ => None,
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    command!(mut responses = client, c("FETCH 2:* (UID) (PARTIAL 2:3)"));
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![3, 4],
+        responses
+            .iter()
+            .filter_map(|r| match r.response {
+                s::Response::Fetch(ref fr) => Some(fr.seqnum),
+                _ => None,
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    // Only messages in the window are implicitly marked \Seen
+    command!(mut responses = client, c("FETCH 1:* BODY[] (PARTIAL -1:-1)"));
+    assert_tagged_ok(responses.pop().unwrap());
+    command!(mut responses = client, c("SEARCH SEEN"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Search(s::SearchResponse {
+            ref hits,
+            ..
+        }) in responses => assert_eq!(&vec![10], hits)
+    };
+
+    // CHANGEDSINCE is applied before the window
+    command!(mut responses = client, c("FETCH 10 MODSEQ"));
+    assert_tagged_ok(responses.pop().unwrap());
+    let modseq = has_untagged_response_matching! {
+        s::Response::Fetch(ref fr) in responses => {
+            has_msgatt_matching! {
+                s::MsgAtt::Modseq(m) in fr => m
+            }
+        }
+    };
+    ok_command!(client, c("STORE 2,4,6 +FLAGS.SILENT (\\Flagged)"));
+    command!(
+        mut responses = client,
+        cb(&format!("FETCH 1:* (UID) (CHANGEDSINCE {modseq} PARTIAL 2:5)"))
+    );
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        vec![4, 6],
+        responses
+            .iter()
+            .filter_map(|r| match r.response {
+                s::Response::Fetch(ref fr) => Some(fr.seqnum),
+                _ => None,
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    assert_bad_command(
+        &mut client,
+        Some(s::RespTextCode::ClientBug(())),
+        "FETCH 1:* (UID) (PARTIAL 0:5)",
+    );
+}
@@ -19,6 +19,7 @@
 use std::borrow::Cow;
 
 use super::defs::*;
+use crate::account::model::Flag;
 use crate::support::error::Error;
 
 #[test]
@@ -213,3 +214,585 @@ fn spool_execute() {
     // still logged in properly.
     quick_select(&mut client, "INBOX");
 }
+
+#[test]
+fn app_passwords() {
+    // Need to use a unique root since we'll be changing the password
+    let setup = set_up_new_root();
+    let mut client = setup.connect("xcryapwd");
+    quick_log_in(&mut client);
+
+    command!(mut responses = client, c("XCRY APP-PASSWORD LIST"));
+    assert_eq!(1, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+
+    command!(mut responses = client,
+             c("XCRY APP-PASSWORD CREATE phone ANY"));
+    assert_tagged_ok(responses.pop().unwrap());
+    let mut phone_password = String::new();
+    has_untagged_response_matching! {
+        s::Response::XCryAppPasswordCreated(ref data) in responses => {
+            assert_eq!("phone", data.name);
+            phone_password = data.password.clone().into_owned();
+        }
+    };
+    assert!(phone_password.starts_with("phone:"));
+
+    command!(mut responses = client,
+             c("XCRY APP-PASSWORD CREATE laptop SUBMISSION-ONLY"));
+    assert_tagged_ok(responses.pop().unwrap());
+    let mut laptop_password = String::new();
+    has_untagged_response_matching! {
+        s::Response::XCryAppPasswordCreated(ref data) in responses => {
+            laptop_password = data.password.clone().into_owned();
+        }
+    };
+
+    command!(
+        [response] = client,
+        c("XCRY APP-PASSWORD CREATE phone IMAP-ONLY")
+    );
+    assert_error_response(
+        response,
+        Some(s::RespTextCode::AlreadyExists(())),
+        Error::AppPasswordExists,
+    );
+
+    command!(
+        [response] = client,
+        c("XCRY APP-PASSWORD CREATE \"../foo\" ANY")
+    );
+    assert_error_response(
+        response,
+        Some(s::RespTextCode::Cannot(())),
+        Error::UnsafeName,
+    );
+
+    command!(mut responses = client, c("XCRY APP-PASSWORD LIST"));
+    assert_eq!(3, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::XCryAppPassword(ref data) in responses => {
+            if "laptop" == data.name {
+                assert_eq!(
+                    s::XCryAppPasswordRestriction::SubmissionOnly,
+                    data.restriction,
+                );
+            }
+            assert!(data.last_used.is_none());
+        }
+    };
+
+    // The secret of one application password is not accepted under the name
+    // of another.
+    let mut client2 = setup.connect("xcryapwd");
+    skip_greeting(&mut client2);
+    command!(
+        [response] = client2,
+        cb(&format!(
+            "LOGIN azure \"{}\"",
+            phone_password.replacen("phone:", "laptop:", 1),
+        ))
+    );
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::No,
+         Some(s::RespTextCode::AuthenticationFailed(())), _) = response => ()
+    };
+
+    // The submission-only password cannot be used for IMAP
+    let mut client2 = setup.connect("xcryapwd");
+    skip_greeting(&mut client2);
+    command!(
+        [response] = client2,
+        cb(&format!("LOGIN azure \"{}\"", laptop_password))
+    );
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::No,
+         Some(s::RespTextCode::AuthenticationFailed(())), _) = response => ()
+    };
+
+    // The unrestricted password works, but cannot be used to change the main
+    // password or manage other application passwords.
+    let mut client2 = setup.connect("xcryapwd");
+    skip_greeting(&mut client2);
+    ok_command!(client2, cb(&format!("LOGIN azure \"{}\"", phone_password)));
+
+    command!(
+        [response] = client2,
+        c("XCRY SET-USER-CONFIG PASSWORD hunter3")
+    );
+    assert_error_response(
+        response,
+        Some(s::RespTextCode::NoPerm(())),
+        Error::NotPermittedForAppPassword,
+    );
+
+    command!([response] = client2, c("XCRY APP-PASSWORD REVOKE laptop"));
+    assert_error_response(
+        response,
+        Some(s::RespTextCode::NoPerm(())),
+        Error::NotPermittedForAppPassword,
+    );
+
+    // Changing the main password does not affect application passwords
+    ok_command!(client, c("XCRY SET-USER-CONFIG PASSWORD hunter3"));
+
+    command!(mut responses = client, c("XCRY APP-PASSWORD LIST"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::XCryAppPassword(ref data) in responses => {
+            if "phone" == data.name {
+                assert!(data.last_used.is_some());
+            }
+        }
+    };
+
+    let mut client2 = setup.connect("xcryapwd");
+    skip_greeting(&mut client2);
+    ok_command!(client2, cb(&format!("LOGIN azure \"{}\"", phone_password)));
+
+    ok_command!(client, c("XCRY APP-PASSWORD REVOKE phone"));
+    command!([response] = client, c("XCRY APP-PASSWORD REVOKE phone"));
+    assert_error_response(
+        response,
+        Some(s::RespTextCode::Nonexistent(())),
+        Error::NxAppPassword,
+    );
+
+    let mut client2 = setup.connect("xcryapwd");
+    skip_greeting(&mut client2);
+    command!(
+        [response] = client2,
+        cb(&format!("LOGIN azure \"{}\"", phone_password))
+    );
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::No,
+         Some(s::RespTextCode::AuthenticationFailed(())), _) = response => ()
+    };
+}
+
+#[test]
+fn login_history() {
+    // Need to use a unique root so the history isn't affected by other tests
+    let setup = set_up_new_root();
+    let mut client = setup.connect("xcrylhst");
+    quick_log_in(&mut client);
+
+    let mut client2 = setup.connect("xcrylhst");
+    skip_greeting(&mut client2);
+    command!([response] = client2, c("LOGIN azure wrong"));
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::No,
+         Some(s::RespTextCode::AuthenticationFailed(())), _) = response => ()
+    };
+
+    let mut client3 = setup.connect("xcrylhst");
+    skip_greeting(&mut client3);
+    ok_command!(client3, c("ID (\"name\" \"Tester\")"));
+    ok_command!(client3, c("LOGIN azure hunter2"));
+
+    command!(mut responses = client3, c("XCRY LOGIN-HISTORY 10"));
+    assert_eq!(4, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+
+    let outcomes = responses
+        .into_iter()
+        .map(|r| match r.response {
+            s::Response::XCryLoginHistory(data) => {
+                assert_eq!("IMAP", data.protocol);
+                (data.outcome, data.client_id.map(Cow::into_owned))
+            },
+            r => panic!("Unexpected response: {r:?}"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            (
+                s::XCryLoginOutcome::Success,
+                Some("\"name\" = \"Tester\";".to_owned()),
+            ),
+            (s::XCryLoginOutcome::Failure, None),
+            (s::XCryLoginOutcome::Success, None),
+        ],
+        outcomes,
+    );
+
+    command!(mut responses = client3, c("XCRY LOGIN-HISTORY 1"));
+    assert_eq!(2, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+
+    ok_command!(
+        client3,
+        c("XCRY SET-USER-CONFIG NEW-DEVICE-NOTIFICATIONS ON")
+    );
+    command!(mut responses = client3, c("XCRY GET-USER-CONFIG"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::XCryUserConfig(ref data) in responses => {
+            assert!(data.extended.contains(
+                &s::XCry2UserConfigData::NewDeviceNotifications(
+                    s::XCryToggle::On,
+                ),
+            ));
+        }
+    };
+}
+
+#[test]
+fn export() {
+    let setup = set_up();
+    let mut client = setup.connect("xcryexpt");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "xcryexpt");
+    quick_append_enron(&mut client, "xcryexpt", 3);
+
+    command!(mut responses = client, c("XCRY EXPORT xcryexpt 0 0"));
+    assert_eq!(5, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+
+    let mut responses = responses.into_iter();
+    let highest_modseq = match responses.next().unwrap().response {
+        s::Response::XCryExportMailbox(data) => data.highest_modseq,
+        r => panic!("Unexpected response: {r:?}"),
+    };
+
+    let mut uids = Vec::<u32>::new();
+    for response in responses {
+        let s::Response::XCryExport(atts) = response.response else {
+            panic!("Unexpected response: {:?}", response.response);
+        };
+
+        let mut has_flags = false;
+        let mut has_internal_date = false;
+        let mut has_body = false;
+        for att in atts.atts {
+            match att {
+                s::MsgAtt::Uid(uid) => uids.push(uid),
+                s::MsgAtt::Flags(..) => has_flags = true,
+                s::MsgAtt::InternalDate(..) => has_internal_date = true,
+                s::MsgAtt::Body(body) => {
+                    assert_literal_like(b"", b"", 0, false, body.data);
+                    has_body = true;
+                },
+                _ => (),
+            }
+        }
+        assert!(has_flags);
+        assert!(has_internal_date);
+        assert!(has_body);
+    }
+    assert_eq!(vec![1, 2, 3], uids);
+
+    quick_select(&mut client, "xcryexpt");
+    ok_command!(client, c("UID STORE 1 +FLAGS (\\Flagged)"));
+
+    command!(
+        mut responses = client,
+        cb(&format!("XCRY EXPORT xcryexpt 3 {highest_modseq}"))
+    );
+    assert_eq!(3, responses.len());
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::XCryExport(ref atts) in responses => {
+            assert!(atts.atts.iter().any(
+                |att| matches!(*att, s::MsgAtt::Uid(1)),
+            ));
+            assert!(!atts.atts.iter().any(
+                |att| matches!(*att, s::MsgAtt::Body(..)),
+            ));
+        }
+    };
+
+    command!([response] = client, c("XCRY EXPORT nonexistent 0 0"));
+    assert_error_response(
+        response,
+        Some(s::RespTextCode::Nonexistent(())),
+        Error::NxMailbox,
+    );
+}
+
+#[test]
+fn restore() {
+    let setup = set_up();
+    let mut client = setup.connect("xcryrest");
+    quick_log_in(&mut client);
+
+    command!(mut responses = client, c("XCRY RESTORE LIST"));
+    assert_tagged_ok(responses.pop().unwrap());
+
+    command!([response] = client, c("XCRY RESTORE MAILBOXES 2000-01-01"));
+    assert_error_response(
+        response,
+        Some(s::RespTextCode::Nonexistent(())),
+        Error::NxBackup,
+    );
+
+    command!([response] = client, c("XCRY RESTORE MAILBOXES yesterday"));
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::Bad, _, _) = response
+    };
+
+    command!(
+        [response] = client,
+        c("XCRY RESTORE COPY 2000-01-01 INBOX 1:*")
+    );
+    assert_error_response(
+        response,
+        Some(s::RespTextCode::Nonexistent(())),
+        Error::NxBackup,
+    );
+}
+
+#[test]
+fn unexpunge() {
+    let setup = set_up();
+    let mut client = setup.connect("xcryunex");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "xcryunex");
+    quick_append_enron(&mut client, "xcryunex", 3);
+
+    ok_command!(client, c("SELECT xcryunex"));
+    ok_command!(client, c("UID STORE 2 +FLAGS (\\Seen)"));
+    ok_command!(client, c("XVANQUISH 2:3"));
+
+    command!(mut responses = client, c("XCRY UNEXPUNGE LIST"));
+    assert_tagged_ok(responses.pop().unwrap());
+    let mut uids = responses
+        .into_iter()
+        .filter_map(|r| match r.response {
+            s::Response::XCryUnexpungeable(data)
+                if "xcryunex" == data.mailbox.raw =>
+            {
+                assert!(data.subject.is_some());
+                Some((data.uid, data.flags.contains(&Flag::Seen)))
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    uids.sort();
+    assert_eq!(vec![(2, true), (3, false)], uids);
+
+    command!(
+        mut responses = client,
+        c("XCRY UNEXPUNGE RESTORE xcryunex 2,99")
+    );
+    assert_tagged_ok_any(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::XCryUnexpunged(1) in responses
+    };
+
+    // The message comes back with a new UID and its old flags. Like any other
+    // new message, it is \Recent in this session.
+    ok_command!(client, c("NOOP"));
+    fetch_single!(client, c("UID FETCH 4 FLAGS"), ref fr => {
+        has_msgatt_matching! {
+            s::MsgAtt::Flags(s::FlagsFetch::Recent(ref flags)) in fr => {
+                assert_eq!(&[Flag::Seen], &flags[..]);
+            }
+        };
+    });
+
+    ok_command!(client, c("XCRY SET-USER-CONFIG EXPUNGE-RETENTION 7"));
+    command!(
+        [response] = client,
+        c("XCRY SET-USER-CONFIG EXPUNGE-RETENTION 0")
+    );
+    assert_error_response(
+        response,
+        Some(s::RespTextCode::Cannot(())),
+        Error::RetentionOutOfRange,
+    );
+
+    command!(
+        [response] = client,
+        c("XCRY UNEXPUNGE RESTORE nonexistent 1")
+    );
+    assert_error_response(
+        response,
+        Some(s::RespTextCode::Nonexistent(())),
+        Error::NxMailbox,
+    );
+}
+
+#[test]
+fn mailbox_retention() {
+    let setup = set_up();
+    let mut client = setup.connect("xcrymbret");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "xcrymbret");
+
+    ok_command!(
+        client,
+        c("XCRY SET-MAILBOX-RETENTION xcrymbret \
+           (MAX-AGE 30 AGE-BASIS INTERNALDATE ONLY-SEEN)")
+    );
+
+    command!(mut responses = client, c("XCRY GET-MAILBOX-RETENTION"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::XCryMailboxRetention(ref data) in responses => {
+            assert_eq!("xcrymbret", data.mailbox.raw);
+            assert_eq!(
+                vec![
+                    s::XCryRetentionRule::MaxAge(30),
+                    s::XCryRetentionRule::AgeBasis(
+                        s::XCryRetentionAgeBasis::InternalDate,
+                    ),
+                    s::XCryRetentionRule::OnlySeen(()),
+                ],
+                data.rules,
+            );
+        }
+    };
+
+    // An empty rule set removes the rules.
+    ok_command!(client, c("XCRY SET-MAILBOX-RETENTION xcrymbret ()"));
+    command!(mut responses = client, c("XCRY GET-MAILBOX-RETENTION"));
+    assert_tagged_ok(responses.pop().unwrap());
+    assert!(responses.is_empty());
+
+    command!(
+        [response] = client,
+        c("XCRY SET-MAILBOX-RETENTION xcrymbret (MAX-MESSAGES 0)")
+    );
+    assert_error_response(
+        response,
+        Some(s::RespTextCode::Cannot(())),
+        Error::RetentionOutOfRange,
+    );
+
+    command!(
+        [response] = client,
+        c("XCRY SET-MAILBOX-RETENTION nonexistent (MAX-MESSAGES 10)")
+    );
+    assert_error_response(
+        response,
+        Some(s::RespTextCode::Nonexistent(())),
+        Error::NxMailbox,
+    );
+}
+
+#[test]
+fn key_retirement() {
+    let setup = set_up();
+    let mut client = setup.connect("xcrykeyret");
+    quick_log_in(&mut client);
+
+    command!(mut responses = client, c("XCRY GET-USER-CONFIG"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::XCryUserConfig(ref data) in responses => {
+            assert!(data.capabilities.contains(&Cow::Borrowed("KEY-RETIREMENT")));
+            assert!(data.extended.contains(
+                &s::XCry2UserConfigData::KeyRetirement(0),
+            ));
+        }
+    };
+
+    ok_command!(client, c("XCRY SET-USER-CONFIG KEY-RETIREMENT 90"));
+    command!(mut responses = client, c("XCRY GET-USER-CONFIG"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::XCryUserConfig(ref data) in responses => {
+            assert!(data.extended.contains(
+                &s::XCry2UserConfigData::KeyRetirement(90),
+            ));
+        }
+    };
+
+    ok_command!(client, c("XCRY SET-USER-CONFIG KEY-RETIREMENT 0"));
+    command!(mut responses = client, c("XCRY GET-USER-CONFIG"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::XCryUserConfig(ref data) in responses => {
+            assert!(data.extended.contains(
+                &s::XCry2UserConfigData::KeyRetirement(0),
+            ));
+        }
+    };
+}
+
+#[test]
+fn key_algorithm() {
+    let setup = set_up();
+    let mut client = setup.connect("xcrykeyalg");
+    quick_log_in(&mut client);
+
+    command!(mut responses = client, c("XCRY GET-USER-CONFIG"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::XCryUserConfig(ref data) in responses => {
+            assert!(data.capabilities.contains(&Cow::Borrowed("KEY-ALGORITHM")));
+            assert!(data.extended.contains(
+                &s::XCry2UserConfigData::KeyAlgorithm(s::XCryKeyAlgorithm::Rsa),
+            ));
+        }
+    };
+
+    ok_command!(client, c("XCRY SET-USER-CONFIG KEY-ALGORITHM X25519"));
+    command!(mut responses = client, c("XCRY GET-USER-CONFIG"));
+    assert_tagged_ok(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::XCryUserConfig(ref data) in responses => {
+            assert!(data.extended.contains(
+                &s::XCry2UserConfigData::KeyAlgorithm(
+                    s::XCryKeyAlgorithm::X25519,
+                ),
+            ));
+        }
+    };
+}
+
+#[test]
+fn create_search_mailbox() {
+    let setup = set_up();
+    let mut client = setup.connect("xcrysrch");
+    quick_log_in(&mut client);
+    quick_create(&mut client, "xcrysrch");
+    quick_append_enron(&mut client, "xcrysrch", 2);
+
+    ok_command!(client, c("SELECT xcrysrch"));
+    ok_command!(client, c("STORE 2 +FLAGS (xcrysrchkw)"));
+
+    command!(mut responses = client,
+             c("XCRY CREATE-SEARCH-MAILBOX xcrysrch/kw KEYWORD xcrysrchkw"));
+    assert_tagged_ok_any(responses.pop().unwrap());
+    ok_command!(
+        client,
+        c("XCRY CREATE-SEARCH-MAILBOX xcrysrch/starred FLAGGED")
+    );
+
+    command!(mut responses = client,
+             c("LIST \"\" xcrysrch/% RETURN (SPECIAL-USE)"));
+    assert_tagged_ok(responses.pop().unwrap());
+    assert_eq!(
+        "xcrysrch/kw\n\
+         xcrysrch/starred \\Flagged\n",
+        list_results_to_str(responses)
+    );
+
+    command!(mut responses = client, c("EXAMINE xcrysrch/kw"));
+    assert_tagged_ok_any(responses.pop().unwrap());
+    has_untagged_response_matching! {
+        s::Response::Exists(1) in responses
+    };
+
+    command!(
+        [response] = client,
+        c("XCRY CREATE-SEARCH-MAILBOX xcrysrch/bad RECENT")
+    );
+    assert_error_response(
+        response,
+        Some(s::RespTextCode::Cannot(())),
+        Error::UnsavableSearch,
+    );
+
+    command!(
+        [response] = client,
+        c("XCRY CREATE-SEARCH-MAILBOX xcrysrch/bad UID 1")
+    );
+    unpack_cond_response! {
+        (Some(_), s::RespCondType::No, Some(s::RespTextCode::Cannot(())), _) =
+            response => ()
+    };
+}
@@ -242,6 +242,10 @@ impl<W: LexOutput> LexWriter<W> {
         write!(self.writer, "{}", *value)
     }
 
+    pub fn num_i32(&mut self, value: &i32) -> io::Result<()> {
+        write!(self.writer, "{}", *value)
+    }
+
     fn astring(&mut self, s: &str) -> io::Result<()> {
         if self.is_conservative_atom(s) {
             write!(self.writer, "{}", s)?;
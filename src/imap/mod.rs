@@ -26,5 +26,7 @@ mod response_writer;
 pub mod server;
 pub mod syntax;
 
+pub use mailbox_name::MailboxName;
+
 #[cfg(test)]
 mod integration_tests;
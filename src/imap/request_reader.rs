@@ -60,6 +60,21 @@ pub struct RequestReader<R> {
     compressed_range: Range<usize>,
     /// Whether we've seen an EOF from the reader.
     reader_eof: bool,
+    /// If an `APPEND` is in the middle of a `CATENATE` list, the part of the
+    /// current line which has not been parsed yet.
+    catenate_line: Option<CatenateLine>,
+}
+
+/// The unparsed remainder of a command line within `APPEND ... CATENATE`.
+///
+/// Parts of a `CATENATE` list are not necessarily followed by a literal, so
+/// unlike plain `APPEND`, each line must be parsed piecemeal.
+struct CatenateLine {
+    /// The range of `text` which has not been parsed yet, excluding any
+    /// trailing literal.
+    rest: Range<usize>,
+    /// The length and LITERAL+ flag of the literal ending the line, if any.
+    literal: Option<(u32, bool)>,
 }
 
 /// Possible outcomes of trying to read the start of a command line.
@@ -84,6 +99,11 @@ pub enum CommandStart<'a> {
     /// accepted by sending the continuation line, consuming the literal, then
     /// calling `read_append_continue`, or must be entirely rejected by calling
     /// `abort_append`.
+    ///
+    /// If the first fragment is `CATENATE`, there is no literal yet; `size`
+    /// is 0 and `literal_plus` is false. The parts of the message must
+    /// instead be read with `read_catenate_part`, and the append is rejected
+    /// with `abort_append_after_literal`.
     AppendStart {
         append: s::AppendCommandStart<'a>,
         size: u32,
@@ -100,6 +120,9 @@ pub enum CommandStart<'a> {
 
 pub enum AppendContinuation {
     /// There is another part in the `APPEND` sequence.
+    ///
+    /// As with `CommandStart::AppendStart`, `size` and `literal_plus` are
+    /// meaningless if the fragment is `CATENATE`.
     NextPart {
         fragment: s::AppendFragment,
         size: u32,
@@ -114,6 +137,26 @@ pub enum AppendContinuation {
     TooLong,
 }
 
+/// Possible outcomes of reading the next part of an `APPEND ... CATENATE`
+/// list.
+pub enum CatenatePart {
+    /// The next part is the message at the given URL.
+    Url(String),
+    /// The next part is the literal which follows.
+    ///
+    /// As with the `APPEND` literal, this must be accepted by sending the
+    /// continuation line and then consuming the literal, or rejected by
+    /// calling `abort_append`.
+    Text { size: u32, literal_plus: bool },
+    /// The list is done. `continue_append` must be called next.
+    End,
+    /// The syntax of the part is invalid. The parser has recovered and
+    /// aborted the append.
+    SyntaxError,
+    /// The line continuation was too long.
+    TooLong,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CompressionStatus {
     Started,
@@ -132,6 +175,7 @@ impl<R: AsyncRead + Unpin> RequestReader<R> {
             compressed: Vec::new(),
             compressed_range: 0..0,
             reader_eof: false,
+            catenate_line: None,
         }
     }
 
@@ -150,6 +194,7 @@ impl<R: AsyncRead + Unpin> RequestReader<R> {
         recover_overlong: bool,
     ) -> io::Result<CommandStart<'_>> {
         self.drop_consumed();
+        self.catenate_line = None;
 
         loop {
             if self.consume_line().await?.is_none() {
@@ -166,28 +211,50 @@ impl<R: AsyncRead + Unpin> RequestReader<R> {
             else {
                 break;
             };
+            // Borrow only `text` from here on so that `catenate_line` can be
+            // set below.
+            let before_literal = &self.text[..before_literal.len()];
 
             // APPEND needs to be handled specially since it can be much
             // larger than MAX_CMDLINE.
-            if let Ok((b"", append)) =
+            if let Ok((rest, append)) =
                 s::AppendCommandStart::parse(before_literal)
             {
-                return Ok(CommandStart::AppendStart {
-                    // Work around https://github.com/rust-lang/rust/issues/54663
-                    //
-                    // SAFETY: We're transmuting to AppendCommandStart<'b>
-                    // (where 'b is the local borrow from self.check_literal())
-                    // to AppendCommandStart<'a>. 'a and 'b are both borrows
-                    // into `self`.
-                    append: unsafe {
-                        std::mem::transmute::<
-                            s::AppendCommandStart<'_>,
-                            s::AppendCommandStart<'a>,
-                        >(append)
-                    },
-                    size: literal_length,
-                    literal_plus,
-                });
+                let catenate = append.first_fragment.catenate;
+                if !catenate && !rest.is_empty() {
+                    // Not actually an APPEND; parse it as a normal command
+                    // so that the usual error handling happens.
+                } else if catenate && append.first_fragment.utf8 {
+                    // Syntactically possible with our grammar, but not valid.
+                } else {
+                    let (size, literal_plus) = if catenate {
+                        let end = before_literal.len();
+                        self.catenate_line = Some(CatenateLine {
+                            rest: end - rest.len()..end,
+                            literal: Some((literal_length, literal_plus)),
+                        });
+                        (0, false)
+                    } else {
+                        (literal_length, literal_plus)
+                    };
+
+                    return Ok(CommandStart::AppendStart {
+                        // Work around https://github.com/rust-lang/rust/issues/54663
+                        //
+                        // SAFETY: We're transmuting to AppendCommandStart<'b>
+                        // (where 'b is the local borrow from
+                        // self.check_literal()) to AppendCommandStart<'a>. 'a
+                        // and 'b are both borrows into `self`.
+                        append: unsafe {
+                            std::mem::transmute::<
+                                s::AppendCommandStart<'_>,
+                                s::AppendCommandStart<'a>,
+                            >(append)
+                        },
+                        size,
+                        literal_plus,
+                    });
+                }
             }
 
             // Otherwise, just add the literal to the command line if
@@ -232,6 +299,24 @@ impl<R: AsyncRead + Unpin> RequestReader<R> {
         }
         if let Ok((b"", command)) = s::CommandLine::parse(command_line) {
             Ok(CommandStart::StandAlone(command))
+        } else if let Some((rest, append)) = s::AppendCommandStart::parse(
+            command_line,
+        )
+        .ok()
+        .filter(|&(_, ref append)| {
+            append.first_fragment.catenate && !append.first_fragment.utf8
+        }) {
+            // An APPEND ... CATENATE consisting entirely of URLs
+            let end = command_line.len();
+            self.catenate_line = Some(CatenateLine {
+                rest: end - rest.len()..end,
+                literal: None,
+            });
+            Ok(CommandStart::AppendStart {
+                append,
+                size: 0,
+                literal_plus: false,
+            })
         } else if let Ok((b"", command)) =
             s::AuthenticateCommandStart::parse(command_line)
         {
@@ -281,72 +366,189 @@ impl<R: AsyncRead + Unpin> RequestReader<R> {
 
     /// Abort the in-progress `APPEND` command after having read the entire
     /// literal.
+    ///
+    /// This is also used to abort an `APPEND` in the middle of a `CATENATE`
+    /// list, provided any `TEXT` literal has been consumed in its entirety.
     pub async fn abort_append_after_literal(&mut self) -> io::Result<()> {
-        match self.continue_append(false).await? {
-            AppendContinuation::Done
-            | AppendContinuation::SyntaxError
-            | AppendContinuation::TooLong => Ok(()),
-            AppendContinuation::NextPart {
-                size, literal_plus, ..
-            } => self.abort_append(size, literal_plus).await,
+        let literal = match self.catenate_line.take() {
+            Some(line) => line.literal,
+            None => {
+                self.drop_consumed();
+                if self.consume_line().await?.is_none() {
+                    return self.skip_command(OverflowState::Line).await;
+                }
+
+                self.check_literal()
+                    .map(|(_, size, literal_plus)| (size, literal_plus))
+            },
+        };
+
+        match literal {
+            // The rest of the command can only be further lines if it is
+            // LITERAL+; otherwise, the client is waiting for us.
+            Some((size, literal_plus)) => {
+                self.abort_append(size, literal_plus).await
+            },
+            // If the current line has no literal, it is the end of the
+            // command.
+            None => Ok(()),
         }
     }
 
     /// Continues parsing an `APPEND` command.
     ///
     /// This must be called after the previous `APPEND` literal was completely
-    /// consumed. `prev_utf8` is the value of `utf8` from the previous
-    /// fragment.
+    /// consumed, or after `read_catenate_part` returned
+    /// `CatenatePart::End`. `prev_utf8` is the value of `utf8` from the
+    /// previous fragment.
     pub async fn continue_append(
         &mut self,
         prev_utf8: bool,
     ) -> io::Result<AppendContinuation> {
-        self.drop_consumed();
-        let Some(line) = self.consume_line().await? else {
-            self.skip_command(OverflowState::Line).await?;
-            return Ok(AppendContinuation::TooLong);
+        let (mut start, end, literal) = match self.catenate_line.take() {
+            Some(line) => (line.rest.start, line.rest.end, line.literal),
+            None => {
+                self.drop_consumed();
+                let Some(line) = self.consume_line().await? else {
+                    self.skip_command(OverflowState::Line).await?;
+                    return Ok(AppendContinuation::TooLong);
+                };
+
+                // A completed APPEND is either the empty string or just ")"
+                // (for UTF8).
+                match (line, prev_utf8) {
+                    (b"", false) | (b")", true) => {
+                        return Ok(AppendContinuation::Done)
+                    },
+                    (b"", true) | (b")", false) => {
+                        return Ok(AppendContinuation::SyntaxError)
+                    },
+                    _ => {},
+                }
+
+                let (end, literal) = self.split_line();
+                (0, end, literal)
+            },
         };
 
-        // A completed APPEND is either the empty string or just ")" (for
-        // UTF8).
-        match (line, prev_utf8) {
-            (b"", false) | (b")", true) => return Ok(AppendContinuation::Done),
-            (b"", true) | (b")", false) => {
-                return Ok(AppendContinuation::SyntaxError)
+        if start == end && literal.is_none() && !prev_utf8 {
+            // The end of the line following a CATENATE list.
+            return Ok(AppendContinuation::Done);
+        }
+
+        let prefix = if prev_utf8 { b")".as_slice() } else { b"" };
+        let mut parsed = None;
+        if self.text[start..end].starts_with(prefix) {
+            start += prefix.len();
+            parsed = s::AppendFragment::parse(&self.text[start..end]).ok();
+        }
+
+        match parsed {
+            Some((rest, fragment)) if fragment.catenate && !fragment.utf8 => {
+                self.catenate_line = Some(CatenateLine {
+                    rest: end - rest.len()..end,
+                    literal,
+                });
+                return Ok(AppendContinuation::NextPart {
+                    fragment,
+                    size: 0,
+                    literal_plus: false,
+                });
             },
+
+            Some((b"", fragment)) if !fragment.catenate => {
+                if let Some((size, literal_plus)) = literal {
+                    return Ok(AppendContinuation::NextPart {
+                        fragment,
+                        size,
+                        literal_plus,
+                    });
+                }
+
+                // There's no literal so this is the end of the command line,
+                // but we also disqualified the possibility of valid syntax
+                // above.
+            },
+
             _ => {},
         }
 
-        let Some((before_literal, size, literal_plus)) = self.check_literal()
-        else {
-            // There's no literal so this is the end of the command line, but
-            // we also disqualified the possibility of valid syntax above.
-            return Ok(AppendContinuation::SyntaxError);
-        };
+        if let Some((size, true)) = literal {
+            self.skip_command(OverflowState::LiteralPlus(size)).await?;
+        }
+        Ok(AppendContinuation::SyntaxError)
+    }
 
-        let prefix = if prev_utf8 { b")".as_slice() } else { b"" };
-        let frag = if !before_literal.starts_with(prefix) {
-            None
-        } else if let Ok((b"", frag)) =
-            s::AppendFragment::parse(&before_literal[prefix.len()..])
-        {
-            Some(frag)
-        } else {
-            None
+    /// Reads the next part of an `APPEND ... CATENATE` list.
+    ///
+    /// This must be called after the `APPEND` or fragment introducing the
+    /// list was returned, after the previous `URL` part was processed, or
+    /// after the previous `TEXT` literal was completely consumed. `first` is
+    /// true for the first part in the list.
+    ///
+    /// `URL` parts given as literals are not supported.
+    pub async fn read_catenate_part(
+        &mut self,
+        first: bool,
+    ) -> io::Result<CatenatePart> {
+        let (mut start, end, literal) = match self.catenate_line.take() {
+            Some(line) => (line.rest.start, line.rest.end, line.literal),
+            None => {
+                self.drop_consumed();
+                if self.consume_line().await?.is_none() {
+                    self.skip_command(OverflowState::Line).await?;
+                    return Ok(CatenatePart::TooLong);
+                }
+
+                let (end, literal) = self.split_line();
+                (0, end, literal)
+            },
         };
 
-        let Some(frag) = frag else {
-            if literal_plus {
-                self.skip_command(OverflowState::LiteralPlus(size)).await?;
+        if !first {
+            if self.text[start..end].starts_with(b")") {
+                self.catenate_line = Some(CatenateLine {
+                    rest: start + 1..end,
+                    literal,
+                });
+                return Ok(CatenatePart::End);
+            }
+
+            if !self.text[start..end].starts_with(b" ") {
+                return self.catenate_syntax_error(literal).await;
             }
-            return Ok(AppendContinuation::SyntaxError);
-        };
+            start += 1;
+        }
+
+        match (s::CatenatePart::parse(&self.text[start..end]), literal) {
+            (Ok((rest, s::CatenatePart::Url(url))), _) => {
+                let url = url.into_owned();
+                self.catenate_line = Some(CatenateLine {
+                    rest: end - rest.len()..end,
+                    literal,
+                });
+                Ok(CatenatePart::Url(url))
+            },
+
+            (
+                Ok((b"", s::CatenatePart::Text(()))),
+                Some((size, literal_plus)),
+            ) => Ok(CatenatePart::Text { size, literal_plus }),
 
-        Ok(AppendContinuation::NextPart {
-            fragment: frag,
-            size,
-            literal_plus,
-        })
+            _ => self.catenate_syntax_error(literal).await,
+        }
+    }
+
+    /// Recovers from a syntax error in a `CATENATE` part on a line ending
+    /// with `literal`.
+    async fn catenate_syntax_error(
+        &mut self,
+        literal: Option<(u32, bool)>,
+    ) -> io::Result<CatenatePart> {
+        if let Some((size, true)) = literal {
+            self.skip_command(OverflowState::LiteralPlus(size)).await?;
+        }
+        Ok(CatenatePart::SyntaxError)
     }
 
     /// Enables compression on the input.
@@ -385,6 +587,22 @@ impl<R: AsyncRead + Unpin> RequestReader<R> {
             })
     }
 
+    /// Splits the line just read by `consume_line()`, which must start at the
+    /// beginning of `text`, into the end of its text and the length and
+    /// LITERAL+ flag of the literal it ends with, if any.
+    fn split_line(&self) -> (usize, Option<(u32, bool)>) {
+        if let Some((before_literal, size, literal_plus)) = self.check_literal()
+        {
+            return (before_literal.len(), Some((size, literal_plus)));
+        }
+
+        let mut end = self.text_consumed - 1; // exclude \n
+        if end > 0 && b'\r' == self.text[end - 1] {
+            end -= 1;
+        }
+        (end, None)
+    }
+
     /// Called when the command line has grown too long.
     ///
     /// This attempts to skip the rest of the command and resynchronise the
@@ -28,6 +28,9 @@ use super::{
 use crate::support::async_io::ServerIo;
 
 /// An event to be sent to the client.
+// Nearly every event is a response line, so boxing it would only add an
+// allocation to each one.
+#[allow(clippy::large_enum_variant)]
 pub enum OutputEvent {
     /// A full response line.
     ResponseLine {
@@ -27,7 +27,8 @@ use tokio::io::AsyncWriteExt;
 use super::{
     command_processor::CommandProcessor,
     request_reader::{
-        AppendContinuation, CommandStart, CompressionStatus, RequestReader,
+        AppendContinuation, CatenatePart, CommandStart, CompressionStatus,
+        RequestReader,
     },
     response_writer::{self, OutputControl, OutputDisconnect, OutputEvent},
 };
@@ -298,7 +299,7 @@ async fn process_input(
             },
 
             CommandStart::AuthenticateStart(auth) => {
-                if let Some(line) = processor.authenticate_start(&auth) {
+                if let Some(line) = processor.authenticate_start(&auth).await {
                     output_tx
                         .send(OutputEvent::ResponseLine {
                             ctl: command_end_ctl(&line.response),
@@ -338,7 +339,7 @@ async fn process_input(
                     );
                 };
 
-                let line = processor.authenticate_finish(auth, auth_data);
+                let line = processor.authenticate_finish(auth, auth_data).await;
                 output_tx
                     .send(OutputEvent::ResponseLine {
                         ctl: command_end_ctl(&line.response),
@@ -412,10 +413,12 @@ async fn handle_append(
     let tag = append.tag.clone().into_owned();
 
     if let Err(e) = processor.cmd_append_start(&append) {
-        request_reader
-            .abort_append(size, literal_plus)
-            .await
-            .map_err(ProcessError::InputIo)?;
+        if append.first_fragment.catenate {
+            request_reader.abort_append_after_literal().await
+        } else {
+            request_reader.abort_append(size, literal_plus).await
+        }
+        .map_err(ProcessError::InputIo)?;
         output_tx
             .send(OutputEvent::ResponseLine {
                 ctl: command_end_ctl(&e),
@@ -430,79 +433,32 @@ async fn handle_append(
     }
 
     let mut fragment = append.first_fragment;
-    // For each iteration of the loop, we're about to read a
-    // literal described by (size, literal_plus, fragment)
+    // For each iteration of the loop, we're about to read either a literal
+    // described by (size, literal_plus, fragment) or a CATENATE list.
     loop {
-        // Verify allowable sizes. (0 explicitly cancels but is
-        // still a NO.)
-        if 0 == size {
-            send_cond!(
+        let in_progress = if fragment.catenate {
+            handle_append_catenate(
+                request_reader,
                 output_tx,
-                Cow::Owned(tag),
-                No,
-                None,
-                "Zero-size APPEND",
-            )?;
-            request_reader
-                .abort_append(size, literal_plus)
-                .await
-                .map_err(ProcessError::InputIo)?;
-            return Ok(());
-        }
-
-        if size > APPEND_SIZE_LIMIT {
-            send_cond!(
+                processor,
+                &tag,
+                &fragment,
+            )
+            .await?
+        } else {
+            handle_append_literal(
+                request_reader,
                 output_tx,
-                Cow::Owned(tag),
-                No,
-                Some(s::RespTextCode::TooBig(())),
-                "APPEND message too big",
-            )?;
-            request_reader
-                .abort_append(size, literal_plus)
-                .await
-                .map_err(ProcessError::InputIo)?;
-            return Ok(());
-        }
-
-        // Ready to read the literal.
-        if !literal_plus {
-            output_tx
-                .send(OutputEvent::ContinuationLine { prompt: "go" })
-                .await
-                .map_err(|_| ProcessError::OutputClosed)?;
-        }
-
-        // Process this item.
-        let result = {
-            let mut reader = request_reader.read_append_literal(size);
-            let result = processor
-                .cmd_append_item(&fragment, size, Pin::new(&mut reader))
-                .await;
-            // Ensure we consume the whole thing
-            tokio::io::copy(&mut reader, &mut tokio::io::sink())
-                .await
-                .map_err(ProcessError::InputIo)?;
-            result
+                processor,
+                &tag,
+                &fragment,
+                size,
+                literal_plus,
+            )
+            .await?
         };
 
-        // Back out if this item specifically failed.
-        if let Err(response) = result {
-            output_tx
-                .send(OutputEvent::ResponseLine {
-                    ctl: command_end_ctl(&response),
-                    line: s::ResponseLine {
-                        tag: Some(Cow::Owned(tag)),
-                        response,
-                    },
-                })
-                .await
-                .map_err(|_| ProcessError::OutputClosed)?;
-            processor.cmd_append_abort();
-            request_reader
-                .abort_append_after_literal()
-                .await
-                .map_err(ProcessError::InputIo)?;
+        if !in_progress {
             return Ok(());
         }
 
@@ -566,6 +522,239 @@ async fn handle_append(
     Ok(())
 }
 
+/// Reads one `APPEND` literal of the given size and adds it to the append.
+///
+/// Returns whether the append is still in progress. If not, the append has
+/// been aborted and the tagged response sent.
+async fn handle_append_literal(
+    request_reader: &mut RequestReader<ServerIo>,
+    output_tx: &mut tokio::sync::mpsc::Sender<OutputEvent>,
+    processor: &mut CommandProcessor,
+    tag: &str,
+    fragment: &s::AppendFragment,
+    size: u32,
+    literal_plus: bool,
+) -> Result<bool, ProcessError> {
+    // Verify allowable sizes. (0 explicitly cancels but is still a NO.)
+    if 0 == size {
+        processor.cmd_append_abort();
+        send_cond!(
+            output_tx,
+            Cow::Owned(tag.to_owned()),
+            No,
+            None,
+            "Zero-size APPEND",
+        )?;
+        request_reader
+            .abort_append(size, literal_plus)
+            .await
+            .map_err(ProcessError::InputIo)?;
+        return Ok(false);
+    }
+
+    if size > APPEND_SIZE_LIMIT {
+        processor.cmd_append_abort();
+        send_cond!(
+            output_tx,
+            Cow::Owned(tag.to_owned()),
+            No,
+            Some(s::RespTextCode::TooBig(())),
+            "APPEND message too big",
+        )?;
+        request_reader
+            .abort_append(size, literal_plus)
+            .await
+            .map_err(ProcessError::InputIo)?;
+        return Ok(false);
+    }
+
+    // Ready to read the literal.
+    if !literal_plus {
+        output_tx
+            .send(OutputEvent::ContinuationLine { prompt: "go" })
+            .await
+            .map_err(|_| ProcessError::OutputClosed)?;
+    }
+
+    // Process this item.
+    let result = {
+        let mut reader = request_reader.read_append_literal(size);
+        let result = processor
+            .cmd_append_item(fragment, size, Pin::new(&mut reader))
+            .await;
+        // Ensure we consume the whole thing
+        tokio::io::copy(&mut reader, &mut tokio::io::sink())
+            .await
+            .map_err(ProcessError::InputIo)?;
+        result
+    };
+
+    // Back out if this item specifically failed.
+    if let Err(response) = result {
+        abort_append_after_literal(
+            request_reader,
+            output_tx,
+            processor,
+            tag,
+            response,
+        )
+        .await?;
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Reads the parts of an RFC 4469 `CATENATE` list and adds the resulting
+/// message to the append.
+///
+/// Returns whether the append is still in progress. If not, the append has
+/// been aborted and the tagged response sent.
+async fn handle_append_catenate(
+    request_reader: &mut RequestReader<ServerIo>,
+    output_tx: &mut tokio::sync::mpsc::Sender<OutputEvent>,
+    processor: &mut CommandProcessor,
+    tag: &str,
+    fragment: &s::AppendFragment,
+) -> Result<bool, ProcessError> {
+    if let Err(response) = processor.cmd_append_catenate_start() {
+        abort_append_after_literal(
+            request_reader,
+            output_tx,
+            processor,
+            tag,
+            response,
+        )
+        .await?;
+        return Ok(false);
+    }
+
+    let mut first = true;
+    loop {
+        let part = request_reader
+            .read_catenate_part(first)
+            .await
+            .map_err(ProcessError::InputIo)?;
+        first = false;
+
+        let result = match part {
+            CatenatePart::Url(url) => processor.cmd_append_catenate_url(&url),
+
+            CatenatePart::Text { size, literal_plus } => {
+                if size > APPEND_SIZE_LIMIT {
+                    processor.cmd_append_abort();
+                    send_cond!(
+                        output_tx,
+                        Cow::Owned(tag.to_owned()),
+                        No,
+                        Some(s::RespTextCode::TooBig(())),
+                        "CATENATE part too big",
+                    )?;
+                    request_reader
+                        .abort_append(size, literal_plus)
+                        .await
+                        .map_err(ProcessError::InputIo)?;
+                    return Ok(false);
+                }
+
+                if !literal_plus {
+                    output_tx
+                        .send(OutputEvent::ContinuationLine { prompt: "go" })
+                        .await
+                        .map_err(|_| ProcessError::OutputClosed)?;
+                }
+
+                let mut reader = request_reader.read_append_literal(size);
+                let result = processor
+                    .cmd_append_catenate_text(size, Pin::new(&mut reader))
+                    .await;
+                tokio::io::copy(&mut reader, &mut tokio::io::sink())
+                    .await
+                    .map_err(ProcessError::InputIo)?;
+                result
+            },
+
+            CatenatePart::End => break,
+
+            CatenatePart::SyntaxError => {
+                processor.cmd_append_abort();
+                send_cond!(
+                    output_tx,
+                    Cow::Owned(tag.to_owned()),
+                    Bad,
+                    Some(s::RespTextCode::Parse(())),
+                    "Bad CATENATE part",
+                )?;
+                return Ok(false);
+            },
+
+            CatenatePart::TooLong => {
+                processor.cmd_append_abort();
+                send_cond!(
+                    output_tx,
+                    Cow::Owned(tag.to_owned()),
+                    Bad,
+                    None,
+                    "CATENATE line too long",
+                )?;
+                return Ok(false);
+            },
+        };
+
+        if let Err(response) = result {
+            abort_append_after_literal(
+                request_reader,
+                output_tx,
+                processor,
+                tag,
+                response,
+            )
+            .await?;
+            return Ok(false);
+        }
+    }
+
+    if let Err(response) = processor.cmd_append_catenate_finish(fragment) {
+        abort_append_after_literal(
+            request_reader,
+            output_tx,
+            processor,
+            tag,
+            response,
+        )
+        .await?;
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Aborts the in-progress `APPEND` at a point where no literal is pending,
+/// responding with `response`.
+async fn abort_append_after_literal(
+    request_reader: &mut RequestReader<ServerIo>,
+    output_tx: &mut tokio::sync::mpsc::Sender<OutputEvent>,
+    processor: &mut CommandProcessor,
+    tag: &str,
+    response: s::Response<'static>,
+) -> Result<(), ProcessError> {
+    output_tx
+        .send(OutputEvent::ResponseLine {
+            ctl: command_end_ctl(&response),
+            line: s::ResponseLine {
+                tag: Some(Cow::Owned(tag.to_owned())),
+                response,
+            },
+        })
+        .await
+        .map_err(|_| ProcessError::OutputClosed)?;
+    processor.cmd_append_abort();
+    request_reader
+        .abort_append_after_literal()
+        .await
+        .map_err(ProcessError::InputIo)
+}
+
 async fn handle_compress(
     request_reader: &mut RequestReader<ServerIo>,
     output_tx: &mut tokio::sync::mpsc::Sender<OutputEvent>,
@@ -224,6 +224,10 @@ syntax_rule! {
         #[prefix("ESEARCH ")]
         #[delegate]
         Esearch(EsearchResponse<'a>),
+        // RFC 5464
+        #[prefix("METADATA ")]
+        #[delegate]
+        Metadata(MetadataResponse<'a>),
         // Crymap extensions
         #[prefix("XCRY USER-CONFIG")]
         #[delegate]
@@ -234,6 +238,49 @@ syntax_rule! {
         #[prefix("XCRY SMTP-OUT FOREIGN-TLS ")]
         #[delegate]
         XCryForeignSmtpTls(XCryForeignSmtpTlsData<'a>),
+        #[prefix("XCRY APP-PASSWORD ")]
+        #[delegate]
+        XCryAppPassword(XCryAppPasswordData<'a>),
+        #[prefix("XCRY APP-PASSWORD-CREATED ")]
+        #[delegate]
+        XCryAppPasswordCreated(XCryAppPasswordCreatedData<'a>),
+        #[prefix("XCRY LOGIN-HISTORY ") box]
+        #[delegate(XCryLoginHistoryData)]
+        XCryLoginHistory(Box<XCryLoginHistoryData<'a>>),
+        #[prefix("XCRY EXPORT-MAILBOX ")]
+        #[delegate]
+        XCryExportMailbox(XCryExportMailboxData),
+        #[prefix("XCRY EXPORT ")]
+        #[delegate]
+        XCryExport(MsgAtts<'a>),
+        #[prefix("XCRY RESTORE-BACKUP ")]
+        #[primitive(unicode_astring, astring)]
+        XCryRestoreBackup(Cow<'a, str>),
+        #[prefix("XCRY RESTORE-MAILBOX ")]
+        #[delegate]
+        XCryRestoreMailbox(XCryRestoreMailboxData<'a>),
+        #[prefix("XCRY RESTORE-MESSAGE ")]
+        #[delegate]
+        XCryRestoreMessage(XCryRestoreMessageData<'a>),
+        #[prefix("XCRY RESTORED ")]
+        #[primitive(num_u32, number)]
+        XCryRestored(u32),
+        #[prefix("XCRY UNEXPUNGEABLE ")]
+        #[delegate]
+        XCryUnexpungeable(XCryUnexpungeableData<'a>),
+        #[prefix("XCRY UNEXPUNGED ")]
+        #[primitive(num_u32, number)]
+        XCryUnexpunged(u32),
+        #[prefix("XCRY MAILBOX-RETENTION ")]
+        #[delegate]
+        XCryMailboxRetention(XCryMailboxRetentionData<'a>),
+        // RFC 4467
+        #[prefix("GENURLAUTH ") 1*(" ")]
+        #[primitive(censored_astring, astring)]
+        GenUrlauth(Vec<Cow<'a, str>>),
+        #[prefix("URLFETCH ")]
+        #[delegate]
+        UrlFetch(UrlFetchData<'a>),
     }
 }
 
@@ -379,6 +426,10 @@ syntax_rule! {
         #[]
         #[tag("TOOBIG")]
         TooBig(()),
+        // RFC 4469
+        #[prefix("BADURL ")]
+        #[primitive(verbatim, url_resp_text)]
+        BadUrl(Cow<'a, str>),
         // RFC 6154
         #[]
         #[tag("USEATTR")]
@@ -412,11 +463,41 @@ syntax_rule! {
         #[surrounded("MAILBOXID (", ")")]
         #[primitive(verbatim, normal_atom)]
         MailboxId(Cow<'a, str>),
+        // RFC 5465
+        #[surrounded("BADEVENT (", ")") 1*(" ")]
+        #[delegate(NotifyEvent)]
+        BadEvent(Vec<NotifyEvent>),
+        // RFC 5464
+        #[prefix("METADATA ")]
+        #[delegate]
+        Metadata(MetadataRespCode),
+        // RFC 5267
+        #[prefix("NOUPDATE ")]
+        #[primitive(censored_string, string)]
+        NoUpdate(Cow<'a, str>),
         // We don't handle unknown response codes, since the server never needs
         // to parse this. Unknown response codes just become part of the text.
     }
 }
 
+syntax_rule! {
+    #[]
+    enum MetadataRespCode {
+        #[prefix("LONGENTRIES ")]
+        #[primitive(num_u32, number)]
+        LongEntries(u32),
+        #[prefix("MAXSIZE ")]
+        #[primitive(num_u32, number)]
+        MaxSize(u32),
+        #[]
+        #[tag("TOOMANY")]
+        TooMany(()),
+        #[]
+        #[tag("NOPRIVATE")]
+        NoPrivate(()),
+    }
+}
+
 syntax_rule! {
     #[]
     struct AppendUidData<'a> {
@@ -507,9 +588,16 @@ syntax_rule! {
         // RFC 4466 makes this optional, but we always include it
         // Yes, it needs to be a string, even though `tag` is a subset of atom.
         // At least it uses the standard string syntax.
-        #[surrounded("(TAG ", ")")]
+        #[prefix("(TAG ")]
         #[primitive(censored_string, string)]
         tag: Cow<'a, str>,
+        // RFC 7377
+        #[opt prefix(" MAILBOX ")]
+        #[primitive(mailbox, mailbox)]
+        mailbox: Option<MailboxName<'a>>,
+        #[suffix(")") opt prefix(" UIDVALIDITY ")]
+        #[primitive(num_u32, number)]
+        uidvalidity: Option<u32>,
         #[]
         #[cond(" UID")]
         uid: bool,
@@ -529,12 +617,35 @@ syntax_rule! {
         #[opt prefix(" COUNT ")]
         #[primitive(num_u32, number)]
         count: Option<u32>,
+        // RFC 5267
+        #[opt surrounded(" PARTIAL (", ")")]
+        #[delegate(EsearchPartial)]
+        partial: Option<EsearchPartial<'a>>,
+        // We don't support sorted contexts, so the position is always 0.
+        #[opt surrounded(" ADDTO (0 ", ")")]
+        #[primitive(verbatim, sequence_set)]
+        addto: Option<Cow<'a, str>>,
+        #[opt surrounded(" REMOVETO (0 ", ")")]
+        #[primitive(verbatim, sequence_set)]
+        removeto: Option<Cow<'a, str>>,
         #[opt prefix(" MODSEQ ")]
         #[primitive(num_u64, number64)]
         modseq: Option<u64>,
     }
 }
 
+syntax_rule! {
+    #[]
+    struct EsearchPartial<'a> {
+        #[suffix(" ")]
+        #[delegate]
+        range: PartialRange,
+        #[nil]
+        #[primitive(verbatim, sequence_set)]
+        hits: Option<Cow<'a, str>>,
+    }
+}
+
 syntax_rule! {
     #[]
     struct FetchResponse<'a> {
@@ -974,6 +1085,204 @@ syntax_rule! {
         #[opt surrounded(r#" ("CHILDINFO" ("#, "))") 1*(" ")]
         #[primitive(censored_string, string)]
         child_info: Option<Vec<Cow<'a, str>>>,
+        // RFC 5465 requires the OLDNAME extended data item when notifying of
+        // a rename. This is never combined with CHILDINFO.
+        #[opt surrounded(r#" ("OLDNAME" ("#, "))")]
+        #[primitive(mailbox, mailbox)]
+        old_name: Option<MailboxName<'a>>,
+    }
+}
+
+syntax_rule! {
+    #[prefix("GETMETADATA ")]
+    struct GetMetadataCommand<'a> {
+        #[opt surrounded("(", ") ") 1*(" ")]
+        #[delegate(GetMetadataOption)]
+        options: Option<Vec<GetMetadataOption>>,
+        #[suffix(" ")]
+        #[primitive(mailbox, mailbox)]
+        mailbox: MailboxName<'a>,
+        #[]
+        #[delegate]
+        entries: MetadataEntries<'a>,
+    }
+}
+
+syntax_rule! {
+    #[]
+    enum GetMetadataOption {
+        #[prefix("MAXSIZE ")]
+        #[primitive(num_u32, number)]
+        MaxSize(u32),
+        #[prefix("DEPTH ")]
+        #[delegate]
+        Depth(MetadataDepth),
+    }
+}
+
+simple_enum! {
+    enum MetadataDepth {
+        Zero("0"),
+        One("1"),
+        Infinity("infinity"),
+    }
+}
+
+syntax_rule! {
+    #[]
+    enum MetadataEntries<'a> {
+        #[surrounded("(", ")") 1*(" ")]
+        #[primitive(unicode_astring, astring)]
+        Multi(Vec<Cow<'a, str>>),
+        #[]
+        #[primitive(unicode_astring, astring)]
+        Single(Cow<'a, str>),
+    }
+}
+
+syntax_rule! {
+    #[prefix("SETMETADATA ")]
+    struct SetMetadataCommand<'a> {
+        #[suffix(" ")]
+        #[primitive(mailbox, mailbox)]
+        mailbox: MailboxName<'a>,
+        #[surrounded("(", ")") 1*(" ")]
+        #[delegate(MetadataEntryValue)]
+        entries: Vec<MetadataEntryValue<'a>>,
+    }
+}
+
+// Values may formally be binary, but we only handle text.
+syntax_rule! {
+    #[]
+    struct MetadataEntryValue<'a> {
+        #[suffix(" ")]
+        #[primitive(unicode_astring, astring)]
+        entry: Cow<'a, str>,
+        #[]
+        #[primitive(unicode_nstring, nstring)]
+        value: Option<Cow<'a, str>>,
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct MetadataResponse<'a> {
+        #[suffix(" ")]
+        #[primitive(mailbox, mailbox)]
+        mailbox: MailboxName<'a>,
+        #[surrounded("(", ")") 1*(" ")]
+        #[delegate(MetadataEntryValue)]
+        entries: Vec<MetadataEntryValue<'a>>,
+    }
+}
+
+syntax_rule! {
+    #[prefix("NOTIFY ")]
+    enum NotifyCommand<'a> {
+        #[]
+        #[tag("NONE")]
+        None(()),
+        #[prefix("SET")]
+        #[delegate]
+        Set(NotifySetCommand<'a>),
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct NotifySetCommand<'a> {
+        #[]
+        #[cond(" STATUS")]
+        status: bool,
+        #[1* prefix(" ")]
+        #[delegate(NotifyEventGroup)]
+        event_groups: Vec<NotifyEventGroup<'a>>,
+    }
+}
+
+syntax_rule! {
+    #[surrounded("(", ")")]
+    struct NotifyEventGroup<'a> {
+        #[suffix(" ")]
+        #[delegate]
+        filter: NotifyFilter<'a>,
+        #[]
+        #[delegate]
+        events: NotifyEvents,
+    }
+}
+
+syntax_rule! {
+    #[]
+    enum NotifyFilter<'a> {
+        // Must come before SELECTED since the latter is a prefix.
+        #[]
+        #[tag("SELECTED-DELAYED")]
+        SelectedDelayed(()),
+        #[]
+        #[tag("SELECTED")]
+        Selected(()),
+        #[]
+        #[tag("INBOXES")]
+        Inboxes(()),
+        #[]
+        #[tag("PERSONAL")]
+        Personal(()),
+        #[]
+        #[tag("SUBSCRIBED")]
+        Subscribed(()),
+        // RFC 7377
+        #[prefix("SUBTREE-ONE ")]
+        #[delegate]
+        SubtreeOne(NotifyMailboxes<'a>),
+        #[prefix("SUBTREE ")]
+        #[delegate]
+        Subtree(NotifyMailboxes<'a>),
+        #[prefix("MAILBOXES ")]
+        #[delegate]
+        Mailboxes(NotifyMailboxes<'a>),
+    }
+}
+
+syntax_rule! {
+    #[]
+    enum NotifyMailboxes<'a> {
+        #[surrounded("(", ")") 1*(" ")]
+        #[primitive(mailbox, mailbox)]
+        Multi(Vec<MailboxName<'a>>),
+        #[]
+        #[primitive(mailbox, mailbox)]
+        Single(MailboxName<'a>),
+    }
+}
+
+// We don't support the optional fetch attributes on MessageNew, so a client
+// requesting them gets a BAD.
+syntax_rule! {
+    #[]
+    enum NotifyEvents {
+        #[]
+        #[tag("NONE")]
+        None(()),
+        #[surrounded("(", ")") 1*(" ")]
+        #[delegate(NotifyEvent)]
+        Events(Vec<NotifyEvent>),
+    }
+}
+
+// This includes events we don't support so that we can reject them with
+// BADEVENT as RFC 5465 requires instead of failing to parse.
+simple_enum! {
+    enum NotifyEvent {
+        MessageNew("MessageNew"),
+        MessageExpunge("MessageExpunge"),
+        FlagChange("FlagChange"),
+        AnnotationChange("AnnotationChange"),
+        MailboxName("MailboxName"),
+        SubscriptionChange("SubscriptionChange"),
+        MailboxMetadataChange("MailboxMetadataChange"),
+        ServerMetadataChange("ServerMetadataChange"),
     }
 }
 
@@ -1056,6 +1365,16 @@ syntax_rule! {
         #[]
         #[tag("THREADID")]
         ThreadId(()),
+        // RFC 8970
+        #[prefix("PREVIEW") opt surrounded(" (", ")") 1*(" ")]
+        #[delegate(PreviewModifier)]
+        Preview(Option<Vec<PreviewModifier>>),
+    }
+}
+
+simple_enum! {
+    enum PreviewModifier {
+        Lazy("LAZY"),
     }
 }
 
@@ -1174,6 +1493,10 @@ syntax_rule! {
         #[]
         #[tag("VANISHED")]
         Vanished(()),
+        // RFC 9394
+        #[prefix("PARTIAL ")]
+        #[delegate]
+        Partial(PartialRange),
     }
 }
 
@@ -1252,6 +1575,10 @@ syntax_rule! {
         #[]
         #[tag("THREADID NIL")]
         ThreadIdNil(()),
+        // RFC 8970
+        #[prefix("PREVIEW ")]
+        #[primitive(unicode_nstring, nstring)]
+        Preview(Option<Cow<'a, str>>),
     }
 }
 
@@ -1383,8 +1710,9 @@ simple_enum! {
 syntax_rule! {
     #[prefix("SEARCH ")]
     struct SearchCommand<'a> {
-        // RFC 4466 allows a more complex syntax, but RFC 4731 doesn't use it
-        // and we can just treat this as a list of atoms.
+        // RFC 4466 allows a more complex syntax, but the only extension we
+        // support with an argument is RFC 5267 PARTIAL, so we can just treat
+        // this as a list of atoms and PARTIAL.
         #[opt surrounded("RETURN (", ") ") 0*(" ")]
         #[delegate(SearchReturnOpt)]
         return_opts: Option<Vec<SearchReturnOpt>>,
@@ -1397,13 +1725,65 @@ syntax_rule! {
     }
 }
 
-simple_enum! {
+// RFC 7377. The source options are the RFC 5465 mailbox filters; we don't
+// support any scope options.
+syntax_rule! {
+    #[prefix("ESEARCH ")]
+    struct EsearchCommand<'a> {
+        #[opt surrounded("IN (", ") ") 1*(" ")]
+        #[delegate(NotifyFilter)]
+        source: Option<Vec<NotifyFilter<'a>>>,
+        #[opt surrounded("RETURN (", ") ") 0*(" ")]
+        #[delegate(SearchReturnOpt)]
+        return_opts: Option<Vec<SearchReturnOpt>>,
+        #[opt surrounded("CHARSET ", " ")]
+        #[primitive(censored_astring, astring)]
+        charset: Option<Cow<'a, str>>,
+        #[1*(" ")]
+        #[delegate(SearchKey)]
+        keys: Vec<SearchKey<'a>>,
+    }
+}
+
+syntax_rule! {
+    #[]
     enum SearchReturnOpt {
+        #[]
+        #[delegate]
+        Simple(SimpleSearchReturnOpt),
+        // RFC 5267
+        #[prefix("PARTIAL ")]
+        #[delegate]
+        Partial(PartialRange),
+    }
+}
+
+simple_enum! {
+    enum SimpleSearchReturnOpt {
         Min("MIN"),
         Max("MAX"),
         All("ALL"),
         Count("COUNT"),
         Save("SAVE"),
+        // RFC 5267
+        Update("UPDATE"),
+        Context("CONTEXT"),
+    }
+}
+
+// RFC 5267 range of 1-based positions within the search results, extended by
+// RFC 9394 to allow negative positions which count from the end. The grammar
+// requires both ends to have the same sign, but we leave that check to the
+// command processor so it can give a better error.
+syntax_rule! {
+    #[]
+    struct PartialRange {
+        #[suffix(":")]
+        #[primitive(num_i32, signed_number)]
+        start: i32,
+        #[]
+        #[primitive(num_i32, signed_number)]
+        end: i32,
     }
 }
 
@@ -1782,6 +2162,7 @@ simple_enum! {
         XCryFlagsOff("XCRY FLAGS OFF"),
         XCryFlagsOn("XCRY FLAGS ON"),
         XCryGetUserConfig("XCRY GET-USER-CONFIG"),
+        XCryGetMailboxRetention("XCRY GET-MAILBOX-RETENTION"),
         XCryPurge("XCRY PURGE"),
         XCryZstdTrain("XCRY ZSTD TRAIN"),
         Xyzzy("XYZZY"),
@@ -1789,6 +2170,8 @@ simple_enum! {
         Namespace("NAMESPACE"),
         // RFC 3691
         Unselect("UNSELECT"),
+        // RFC 8437
+        Unauthenticate("UNAUTHENTICATE"),
         // RFC 4978
         Compress("COMPRESS DEFLATE"),
         // RFC 2177
@@ -1881,6 +2264,25 @@ syntax_rule! {
         #[prefix("ENABLE ") 1*(" ")]
         #[primitive(verbatim, normal_atom)]
         Enable(Vec<Cow<'a, str>>),
+        // RFC 5465
+        #[]
+        #[delegate]
+        Notify(NotifyCommand<'a>),
+        // RFC 5464
+        #[]
+        #[delegate]
+        GetMetadata(GetMetadataCommand<'a>),
+        #[]
+        #[delegate]
+        SetMetadata(SetMetadataCommand<'a>),
+        // RFC 7377
+        #[]
+        #[delegate]
+        Esearch(EsearchCommand<'a>),
+        // RFC 5267
+        #[prefix("CANCELUPDATE ") 1*(" ")]
+        #[primitive(censored_string, string)]
+        CancelUpdate(Vec<Cow<'a, str>>),
         // Crymap extensions
         #[prefix("XCRY SET-USER-CONFIG") 1* prefix(" ")]
         #[delegate(XCryUserConfigOption)]
@@ -1891,6 +2293,37 @@ syntax_rule! {
         #[prefix("XCRY SMTP-OUT SPOOL EXECUTE ")]
         #[primitive(unicode_astring, astring)]
         XCrySmtpSpoolExecute(Cow<'a, str>),
+        #[prefix("XCRY APP-PASSWORD ")]
+        #[delegate]
+        XCryAppPassword(XCryAppPasswordCommand<'a>),
+        #[prefix("XCRY LOGIN-HISTORY ")]
+        #[primitive(num_u32, number)]
+        XCryLoginHistory(u32),
+        #[prefix("XCRY EXPORT ")]
+        #[delegate]
+        XCryExport(XCryExportCommand<'a>),
+        #[prefix("XCRY RESTORE ")]
+        #[delegate]
+        XCryRestore(XCryRestoreCommand<'a>),
+        #[prefix("XCRY UNEXPUNGE ")]
+        #[delegate]
+        XCryUnexpunge(XCryUnexpungeCommand<'a>),
+        #[prefix("XCRY SET-MAILBOX-RETENTION ")]
+        #[delegate]
+        XCrySetMailboxRetention(XCryMailboxRetentionData<'a>),
+        #[prefix("XCRY CREATE-SEARCH-MAILBOX ")]
+        #[delegate]
+        XCryCreateSearchMailbox(XCryCreateSearchMailboxCommand<'a>),
+        // RFC 4467
+        #[prefix("GENURLAUTH ") 1*(" ")]
+        #[delegate(GenUrlauthItem)]
+        GenUrlauth(Vec<GenUrlauthItem<'a>>),
+        #[prefix("RESETKEY")]
+        #[delegate]
+        ResetKey(ResetKeyCommand<'a>),
+        #[prefix("URLFETCH ") 1*(" ")]
+        #[primitive(censored_astring, astring)]
+        UrlFetch(Vec<Cow<'a, str>>),
     }
 }
 
@@ -1933,6 +2366,10 @@ syntax_rule! {
         #[]
         #[cond("UTF8 (")]
         utf8: bool,
+        // RFC 4469
+        #[]
+        #[cond("CATENATE (")]
+        catenate: bool,
     }
 }
 
@@ -1942,10 +2379,64 @@ impl Default for AppendFragment {
             flags: None,
             internal_date: None,
             utf8: false,
+            catenate: false,
         }
     }
 }
 
+// A single part of an `APPEND ... CATENATE` list, excluding the leading space
+// or parenthesis.
+//
+// `TEXT` is followed by a literal, so like `AppendFragment`, this must be
+// called with the part of the line before the literal itself.
+syntax_rule! {
+    #[]
+    enum CatenatePart<'a> {
+        #[prefix("URL ")]
+        #[primitive(censored_astring, astring)]
+        Url(Cow<'a, str>),
+        #[]
+        #[tag("TEXT ")]
+        Text(()),
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct GenUrlauthItem<'a> {
+        #[suffix(" ")]
+        #[primitive(censored_astring, astring)]
+        url: Cow<'a, str>,
+        #[]
+        #[primitive(verbatim, normal_atom)]
+        mechanism: Cow<'a, str>,
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct ResetKeyCommand<'a> {
+        #[opt prefix(" ")]
+        #[primitive(mailbox, mailbox)]
+        mailbox: Option<MailboxName<'a>>,
+        #[0* prefix(" ")]
+        #[primitive(verbatim, normal_atom)]
+        mechanisms: Vec<Cow<'a, str>>,
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct UrlFetchData<'a> {
+        #[suffix(" ")]
+        #[primitive(censored_astring, astring)]
+        url: Cow<'a, str>,
+        #[nil]
+        #[primitive(literal_source, literal_source)]
+        data: Option<LiteralSource>,
+    }
+}
+
 syntax_rule! {
     #[]
     struct UnknownCommandFragment<'a> {
@@ -1992,6 +2483,18 @@ syntax_rule! {
         #[prefix("SMTP-OUT-FAILURE-RECEIPTS ")]
         #[primitive(unicode_nstring, nstring)]
         SmtpOutFailureReceipts(Option<Cow<'a, str>>),
+        #[prefix("NEW-DEVICE-NOTIFICATIONS ")]
+        #[delegate]
+        NewDeviceNotifications(XCryToggle),
+        #[prefix("EXPUNGE-RETENTION ")]
+        #[primitive(num_u32, number)]
+        ExpungeRetention(u32),
+        #[prefix("KEY-RETIREMENT ")]
+        #[primitive(num_u32, number)]
+        KeyRetirement(u32),
+        #[prefix("KEY-ALGORITHM ")]
+        #[delegate]
+        KeyAlgorithm(XCryKeyAlgorithm),
         #[]
         #[delegate]
         Unknown(XCryUnknownUserConfigData<'a>),
@@ -2031,6 +2534,32 @@ syntax_rule! {
         #[prefix("SMTP-OUT-FAILURE-RECEIPTS ")]
         #[primitive(unicode_nstring, nstring)]
         SmtpOutFailureReceipts(Option<Cow<'a, str>>),
+        #[prefix("NEW-DEVICE-NOTIFICATIONS ")]
+        #[delegate]
+        NewDeviceNotifications(XCryToggle),
+        #[prefix("EXPUNGE-RETENTION ")]
+        #[primitive(num_u32, number)]
+        ExpungeRetention(u32),
+        #[prefix("KEY-RETIREMENT ")]
+        #[primitive(num_u32, number)]
+        KeyRetirement(u32),
+        #[prefix("KEY-ALGORITHM ")]
+        #[delegate]
+        KeyAlgorithm(XCryKeyAlgorithm),
+    }
+}
+
+simple_enum! {
+    enum XCryToggle {
+        On("ON"),
+        Off("OFF"),
+    }
+}
+
+simple_enum! {
+    enum XCryKeyAlgorithm {
+        Rsa("RSA"),
+        X25519("X25519"),
     }
 }
 
@@ -2059,11 +2588,319 @@ syntax_rule! {
         #[cond(" STARTTLS")]
         starttls: bool,
         #[]
-        #[cond(" VALID-CERTIFICATE")]
-        valid_certificate: bool,
-        #[prefix(" ")]
+        #[cond(" VALID-CERTIFICATE")]
+        valid_certificate: bool,
+        #[prefix(" ")]
+        #[primitive(unicode_nstring, nstring)]
+        tls_version: Option<Cow<'a, str>>,
+    }
+}
+
+syntax_rule! {
+    #[]
+    enum XCryAppPasswordCommand<'a> {
+        #[]
+        #[tag("LIST")]
+        List(()),
+        #[prefix("CREATE ")]
+        #[delegate]
+        Create(XCryAppPasswordCreateCommand<'a>),
+        #[prefix("REVOKE ")]
+        #[primitive(unicode_astring, astring)]
+        Revoke(Cow<'a, str>),
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct XCryAppPasswordCreateCommand<'a> {
+        #[suffix(" ")]
+        #[primitive(unicode_astring, astring)]
+        name: Cow<'a, str>,
+        #[]
+        #[delegate]
+        restriction: XCryAppPasswordRestriction,
+    }
+}
+
+simple_enum! {
+    enum XCryAppPasswordRestriction {
+        Any("ANY"),
+        ImapOnly("IMAP-ONLY"),
+        SubmissionOnly("SUBMISSION-ONLY"),
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct XCryAppPasswordData<'a> {
+        #[suffix(" ")]
+        #[primitive(unicode_astring, astring)]
+        name: Cow<'a, str>,
+        #[suffix(" ")]
+        #[delegate]
+        restriction: XCryAppPasswordRestriction,
+        #[suffix(" ")]
+        #[primitive(datetime, datetime)]
+        created: DateTime<FixedOffset>,
+        #[nil]
+        #[primitive(datetime, datetime)]
+        last_used: Option<DateTime<FixedOffset>>,
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct XCryAppPasswordCreatedData<'a> {
+        #[suffix(" ")]
+        #[primitive(unicode_astring, astring)]
+        name: Cow<'a, str>,
+        #[]
+        #[primitive(unicode_astring, astring)]
+        password: Cow<'a, str>,
+    }
+}
+
+simple_enum! {
+    enum XCryLoginOutcome {
+        Success("SUCCESS"),
+        Failure("FAILURE"),
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct XCryLoginHistoryData<'a> {
+        #[suffix(" ")]
+        #[primitive(datetime, datetime)]
+        at: DateTime<FixedOffset>,
+        #[suffix(" ")]
+        #[primitive(unicode_astring, astring)]
+        protocol: Cow<'a, str>,
+        #[suffix(" ")]
+        #[delegate]
+        outcome: XCryLoginOutcome,
+        #[suffix(" ")]
+        #[primitive(unicode_nstring, nstring)]
+        peer_ip: Option<Cow<'a, str>>,
+        #[suffix(" ")]
+        #[primitive(unicode_nstring, nstring)]
+        tls: Option<Cow<'a, str>>,
+        #[suffix(" ")]
+        #[primitive(unicode_nstring, nstring)]
+        client_id: Option<Cow<'a, str>>,
+        #[]
+        #[primitive(unicode_nstring, nstring)]
+        app_password: Option<Cow<'a, str>>,
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct XCryExportCommand<'a> {
+        #[suffix(" ")]
+        #[primitive(mailbox, mailbox)]
+        mailbox: MailboxName<'a>,
+        #[suffix(" ")]
+        #[primitive(num_u32, number)]
+        uid: u32,
+        #[]
+        #[primitive(num_u64, number64)]
+        modseq: u64,
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct XCryExportMailboxData {
+        #[suffix(" ")]
+        #[primitive(num_u32, number)]
+        uid_validity: u32,
+        #[]
+        #[primitive(num_u64, number64)]
+        highest_modseq: u64,
+    }
+}
+
+syntax_rule! {
+    #[]
+    enum XCryRestoreCommand<'a> {
+        #[]
+        #[tag("LIST")]
+        List(()),
+        #[prefix("MAILBOXES ")]
+        #[primitive(unicode_astring, astring)]
+        Mailboxes(Cow<'a, str>),
+        #[prefix("MESSAGES ")]
+        #[delegate]
+        Messages(XCryRestoreMessagesCommand<'a>),
+        #[prefix("COPY ")]
+        #[delegate]
+        Copy(XCryRestoreCopyCommand<'a>),
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct XCryRestoreMessagesCommand<'a> {
+        #[suffix(" ")]
+        #[primitive(unicode_astring, astring)]
+        date: Cow<'a, str>,
+        #[]
+        #[primitive(mailbox, mailbox)]
+        mailbox: MailboxName<'a>,
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct XCryRestoreCopyCommand<'a> {
+        #[suffix(" ")]
+        #[primitive(unicode_astring, astring)]
+        date: Cow<'a, str>,
+        #[suffix(" ")]
+        #[primitive(mailbox, mailbox)]
+        mailbox: MailboxName<'a>,
+        #[]
+        #[primitive(verbatim, sequence_set)]
+        uids: Cow<'a, str>,
+    }
+}
+
+simple_enum! {
+    enum XCryRestoreMailboxStatus {
+        Deleted("DELETED"),
+        Exists("EXISTS"),
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct XCryRestoreMailboxData<'a> {
+        #[suffix(" ")]
+        #[primitive(mailbox, mailbox)]
+        name: MailboxName<'a>,
+        #[suffix(" ")]
+        #[primitive(num_u32, number)]
+        uid_validity: u32,
+        #[suffix(" ")]
+        #[delegate]
+        status: XCryRestoreMailboxStatus,
+        #[]
+        #[primitive(num_u32, number)]
+        messages: u32,
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct XCryRestoreMessageData<'a> {
+        #[suffix(" ")]
+        #[primitive(num_u32, number)]
+        uid: u32,
+        #[suffix(" ")]
+        #[primitive(datetime, datetime)]
+        savedate: DateTime<FixedOffset>,
+        #[surrounded("(", ") ") 0*(" ")]
+        #[primitive(flag, flag)]
+        flags: Vec<Flag>,
+        #[]
+        #[primitive(unicode_nstring, nstring)]
+        subject: Option<Cow<'a, str>>,
+    }
+}
+
+syntax_rule! {
+    #[]
+    enum XCryUnexpungeCommand<'a> {
+        #[]
+        #[tag("LIST")]
+        List(()),
+        #[prefix("RESTORE ")]
+        #[delegate]
+        Restore(XCryUnexpungeRestoreCommand<'a>),
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct XCryUnexpungeRestoreCommand<'a> {
+        #[suffix(" ")]
+        #[primitive(mailbox, mailbox)]
+        mailbox: MailboxName<'a>,
+        #[]
+        #[primitive(verbatim, sequence_set)]
+        uids: Cow<'a, str>,
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct XCryMailboxRetentionData<'a> {
+        #[suffix(" ")]
+        #[primitive(mailbox, mailbox)]
+        mailbox: MailboxName<'a>,
+        #[surrounded("(", ")") 0*(" ")]
+        #[delegate(XCryRetentionRule)]
+        rules: Vec<XCryRetentionRule>,
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct XCryCreateSearchMailboxCommand<'a> {
+        #[suffix(" ")]
+        #[primitive(mailbox, mailbox)]
+        mailbox: MailboxName<'a>,
+        #[1*(" ")]
+        #[delegate(SearchKey)]
+        keys: Vec<SearchKey<'a>>,
+    }
+}
+
+syntax_rule! {
+    #[]
+    enum XCryRetentionRule {
+        #[prefix("MAX-AGE ")]
+        #[primitive(num_u32, number)]
+        MaxAge(u32),
+        #[prefix("AGE-BASIS ")]
+        #[delegate]
+        AgeBasis(XCryRetentionAgeBasis),
+        #[prefix("MAX-MESSAGES ")]
+        #[primitive(num_u32, number)]
+        MaxMessages(u32),
+        #[]
+        #[tag("ONLY-SEEN")]
+        OnlySeen(()),
+    }
+}
+
+simple_enum! {
+    enum XCryRetentionAgeBasis {
+        SaveDate("SAVEDATE"),
+        InternalDate("INTERNALDATE"),
+    }
+}
+
+syntax_rule! {
+    #[]
+    struct XCryUnexpungeableData<'a> {
+        #[suffix(" ")]
+        #[primitive(mailbox, mailbox)]
+        mailbox: MailboxName<'a>,
+        #[suffix(" ")]
+        #[primitive(num_u32, number)]
+        uid: u32,
+        #[suffix(" ")]
+        #[primitive(datetime, datetime)]
+        expunged: DateTime<FixedOffset>,
+        #[surrounded("(", ") ") 0*(" ")]
+        #[primitive(flag, flag)]
+        flags: Vec<Flag>,
+        #[]
         #[primitive(unicode_nstring, nstring)]
-        tls_version: Option<Cow<'a, str>>,
+        subject: Option<Cow<'a, str>>,
     }
 }
 
@@ -2143,6 +2980,16 @@ fn number(i: &[u8]) -> IResult<&[u8], u32> {
     })(i)
 }
 
+fn signed_number(i: &[u8]) -> IResult<&[u8], i32> {
+    map_opt(
+        combinator::recognize(sequence::pair(
+            opt(tag("-")),
+            character::complete::digit1,
+        )),
+        |s| str::from_utf8(s).ok().and_then(|s| s.parse::<i32>().ok()),
+    )(i)
+}
+
 fn number64(i: &[u8]) -> IResult<&[u8], u64> {
     map_opt(character::complete::digit1, |s| {
         str::from_utf8(s).ok().and_then(|s| s.parse::<u64>().ok())
@@ -2251,6 +3098,10 @@ fn text(i: &[u8]) -> IResult<&[u8], Cow<str>> {
     map(is_not("\r\n"), String::from_utf8_lossy)(i)
 }
 
+fn url_resp_text(i: &[u8]) -> IResult<&[u8], Cow<str>> {
+    map(is_not("]\r\n"), String::from_utf8_lossy)(i)
+}
+
 fn keyword(i: &[u8]) -> IResult<&[u8], Flag> {
     map_opt(normal_atom, |a| a.parse::<Flag>().ok())(i)
 }
@@ -2908,6 +3759,7 @@ mod test {
                 flags: vec![],
                 name: mn("~peter/mail/台北/日本語"),
                 child_info: None,
+                old_name: None,
             }
         );
         assert_reversible!(
@@ -2918,6 +3770,7 @@ mod test {
                 flags: vec![s("\\Noinferiors")],
                 name: mn("~peter/mail/台北/日本語"),
                 child_info: None,
+                old_name: None,
             }
         );
         assert_reversible!(
@@ -2928,6 +3781,7 @@ mod test {
                 flags: vec![s("\\Noinferiors"), s("\\Marked")],
                 name: mn("~peter/mail/台北/日本語"),
                 child_info: None,
+                old_name: None,
             }
         );
     }
@@ -3089,6 +3943,36 @@ mod test {
                 modifiers: None,
             }
         );
+        assert_reversible!(
+            FetchCommand,
+            "FETCH 1 PREVIEW",
+            FetchCommand {
+                messages: s("1"),
+                target: FetchCommandTarget::Single(FetchAtt::Preview(None)),
+                modifiers: None,
+            }
+        );
+        assert_reversible!(
+            FetchCommand,
+            "FETCH 1 (UID PREVIEW (LAZY))",
+            FetchCommand {
+                messages: s("1"),
+                target: FetchCommandTarget::Multi(vec![
+                    FetchAtt::Uid(()),
+                    FetchAtt::Preview(Some(vec![PreviewModifier::Lazy])),
+                ]),
+                modifiers: None,
+            }
+        );
+        assert_reversible!(
+            FetchCommand,
+            "FETCH 1 PREVIEW (CHANGEDSINCE 42)",
+            FetchCommand {
+                messages: s("1"),
+                target: FetchCommandTarget::Single(FetchAtt::Preview(None)),
+                modifiers: Some(vec![FetchModifier::ChangedSince(42)]),
+            }
+        );
 
         assert_reversible!(
             FetchCommand,
@@ -3495,6 +4379,18 @@ mod test {
                 ]),
             }
         );
+        assert_reversible!(
+            FetchCommand,
+            "FETCH 1:* (UID) (PARTIAL -1:-50)",
+            FetchCommand {
+                messages: s("1:*"),
+                target: FetchCommandTarget::Multi(vec![FetchAtt::Uid(())]),
+                modifiers: Some(vec![FetchModifier::Partial(PartialRange {
+                    start: -1,
+                    end: -50,
+                })]),
+            }
+        );
     }
 
     #[test]
@@ -3552,6 +4448,12 @@ mod test {
             MsgAtt::EmailId(s("Ethemessageid"))
         );
         assert_reversible!(MsgAtt, "THREADID NIL", MsgAtt::ThreadIdNil(()));
+        assert_reversible!(
+            MsgAtt,
+            "PREVIEW \"Hello world\"",
+            MsgAtt::Preview(Some(s("Hello world")))
+        );
+        assert_reversible!(MsgAtt, "PREVIEW NIL", MsgAtt::Preview(None));
 
         assert_reversible!(
             MsgAtt,
@@ -4074,8 +4976,8 @@ mod test {
             "SEARCH RETURN (MIN MAX) ALL",
             SearchCommand {
                 return_opts: Some(vec![
-                    SearchReturnOpt::Min,
-                    SearchReturnOpt::Max,
+                    SearchReturnOpt::Simple(SimpleSearchReturnOpt::Min),
+                    SearchReturnOpt::Simple(SimpleSearchReturnOpt::Max),
                 ]),
                 charset: None,
                 keys: vec![SearchKey::Simple(SimpleSearchKey::All)],
@@ -4090,6 +4992,72 @@ mod test {
                 keys: vec![SearchKey::Simple(SimpleSearchKey::All)],
             }
         );
+        assert_reversible!(
+            SearchCommand,
+            "SEARCH RETURN (UPDATE CONTEXT COUNT PARTIAL 1:10) ALL",
+            SearchCommand {
+                return_opts: Some(vec![
+                    SearchReturnOpt::Simple(SimpleSearchReturnOpt::Update),
+                    SearchReturnOpt::Simple(SimpleSearchReturnOpt::Context),
+                    SearchReturnOpt::Simple(SimpleSearchReturnOpt::Count),
+                    SearchReturnOpt::Partial(PartialRange {
+                        start: 1,
+                        end: 10
+                    }),
+                ]),
+                charset: None,
+                keys: vec![SearchKey::Simple(SimpleSearchKey::All)],
+            }
+        );
+        assert_reversible!(
+            SearchCommand,
+            "SEARCH RETURN (PARTIAL -100:-51) ALL",
+            SearchCommand {
+                return_opts: Some(vec![SearchReturnOpt::Partial(
+                    PartialRange {
+                        start: -100,
+                        end: -51,
+                    }
+                )]),
+                charset: None,
+                keys: vec![SearchKey::Simple(SimpleSearchKey::All)],
+            }
+        );
+        assert_reversible!(
+            Command,
+            r#"CANCELUPDATE "A1" "B2""#,
+            Command::CancelUpdate(vec![s("A1"), s("B2")])
+        );
+        assert_reversible!(
+            Command,
+            "ESEARCH ALL",
+            Command::Esearch(EsearchCommand {
+                source: None,
+                return_opts: None,
+                charset: None,
+                keys: vec![SearchKey::Simple(SimpleSearchKey::All)],
+            })
+        );
+        assert_reversible!(
+            Command,
+            "ESEARCH IN (PERSONAL SUBTREE-ONE (foo bar) MAILBOXES baz) \
+             RETURN (COUNT) UNSEEN",
+            Command::Esearch(EsearchCommand {
+                source: Some(vec![
+                    NotifyFilter::Personal(()),
+                    NotifyFilter::SubtreeOne(NotifyMailboxes::Multi(vec![
+                        mn("foo"),
+                        mn("bar"),
+                    ])),
+                    NotifyFilter::Mailboxes(NotifyMailboxes::Single(mn("baz"))),
+                ]),
+                return_opts: Some(vec![SearchReturnOpt::Simple(
+                    SimpleSearchReturnOpt::Count
+                )]),
+                charset: None,
+                keys: vec![SearchKey::Simple(SimpleSearchKey::Unseen)],
+            })
+        );
     }
 
     #[test]
@@ -4633,6 +5601,17 @@ mod test {
             "XVANQUISH 1:*",
             Command::XVanquish(s("1:*"))
         );
+        assert_reversible!(
+            Command,
+            "XCRY CREATE-SEARCH-MAILBOX Starred FLAGGED UNDELETED",
+            Command::XCryCreateSearchMailbox(XCryCreateSearchMailboxCommand {
+                mailbox: mn("Starred"),
+                keys: vec![
+                    SearchKey::Simple(SimpleSearchKey::Flagged),
+                    SearchKey::Simple(SimpleSearchKey::Undeleted),
+                ],
+            })
+        );
 
         assert_reversible!(
             Command,
@@ -4678,6 +5657,199 @@ mod test {
         );
     }
 
+    #[test]
+    fn metadata_syntax() {
+        assert_reversible!(
+            Command,
+            "GETMETADATA \"\" /shared/admin",
+            Command::GetMetadata(GetMetadataCommand {
+                options: None,
+                mailbox: mn(""),
+                entries: MetadataEntries::Single(s("/shared/admin")),
+            })
+        );
+        assert_reversible!(
+            Command,
+            "GETMETADATA (MAXSIZE 1024 DEPTH infinity) INBOX \
+             (/private/comment /shared/comment)",
+            Command::GetMetadata(GetMetadataCommand {
+                options: Some(vec![
+                    GetMetadataOption::MaxSize(1024),
+                    GetMetadataOption::Depth(MetadataDepth::Infinity),
+                ]),
+                mailbox: mn("INBOX"),
+                entries: MetadataEntries::Multi(vec![
+                    s("/private/comment"),
+                    s("/shared/comment"),
+                ]),
+            })
+        );
+        assert_reversible!(
+            Command,
+            "SETMETADATA INBOX (/private/comment \"My comment\" \
+             /shared/comment NIL)",
+            Command::SetMetadata(SetMetadataCommand {
+                mailbox: mn("INBOX"),
+                entries: vec![
+                    MetadataEntryValue {
+                        entry: s("/private/comment"),
+                        value: Some(s("My comment")),
+                    },
+                    MetadataEntryValue {
+                        entry: s("/shared/comment"),
+                        value: None,
+                    },
+                ],
+            })
+        );
+        assert_reversible!(
+            ResponseLine,
+            "* METADATA INBOX (/private/comment \"My comment\")",
+            ResponseLine {
+                tag: None,
+                response: Response::Metadata(MetadataResponse {
+                    mailbox: mn("INBOX"),
+                    entries: vec![MetadataEntryValue {
+                        entry: s("/private/comment"),
+                        value: Some(s("My comment")),
+                    }],
+                }),
+            }
+        );
+        assert_reversible!(
+            ResponseLine,
+            "* OK [METADATA LONGENTRIES 2199] K",
+            ResponseLine {
+                tag: None,
+                response: Response::Cond(CondResponse {
+                    cond: RespCondType::Ok,
+                    code: Some(RespTextCode::Metadata(
+                        MetadataRespCode::LongEntries(2199)
+                    )),
+                    quip: None,
+                }),
+            }
+        );
+        assert_reversible!(
+            ResponseLine,
+            "* NO [METADATA TOOMANY] K",
+            ResponseLine {
+                tag: None,
+                response: Response::Cond(CondResponse {
+                    cond: RespCondType::No,
+                    code: Some(RespTextCode::Metadata(
+                        MetadataRespCode::TooMany(())
+                    )),
+                    quip: None,
+                }),
+            }
+        );
+        assert_reversible!(
+            ResponseLine,
+            r#"* NO [NOUPDATE "A1"] K"#,
+            ResponseLine {
+                tag: None,
+                response: Response::Cond(CondResponse {
+                    cond: RespCondType::No,
+                    code: Some(RespTextCode::NoUpdate(s("A1"))),
+                    quip: None,
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn notify_command_syntax() {
+        assert_reversible!(
+            Command,
+            "NOTIFY NONE",
+            Command::Notify(NotifyCommand::None(()))
+        );
+        assert_reversible!(
+            Command,
+            "NOTIFY SET (SELECTED (MessageNew MessageExpunge FlagChange)) \
+             (SUBTREE (foo bar) NONE)",
+            Command::Notify(NotifyCommand::Set(NotifySetCommand {
+                status: false,
+                event_groups: vec![
+                    NotifyEventGroup {
+                        filter: NotifyFilter::Selected(()),
+                        events: NotifyEvents::Events(vec![
+                            NotifyEvent::MessageNew,
+                            NotifyEvent::MessageExpunge,
+                            NotifyEvent::FlagChange,
+                        ]),
+                    },
+                    NotifyEventGroup {
+                        filter: NotifyFilter::Subtree(NotifyMailboxes::Multi(
+                            vec![mn("foo"), mn("bar")]
+                        )),
+                        events: NotifyEvents::None(()),
+                    },
+                ],
+            }))
+        );
+        assert_reversible!(
+            Command,
+            "NOTIFY SET STATUS (SELECTED-DELAYED (MessageNew MessageExpunge)) \
+             (MAILBOXES foo (MailboxName)) (PERSONAL (SubscriptionChange))",
+            Command::Notify(NotifyCommand::Set(NotifySetCommand {
+                status: true,
+                event_groups: vec![
+                    NotifyEventGroup {
+                        filter: NotifyFilter::SelectedDelayed(()),
+                        events: NotifyEvents::Events(vec![
+                            NotifyEvent::MessageNew,
+                            NotifyEvent::MessageExpunge,
+                        ]),
+                    },
+                    NotifyEventGroup {
+                        filter: NotifyFilter::Mailboxes(
+                            NotifyMailboxes::Single(mn("foo"))
+                        ),
+                        events: NotifyEvents::Events(vec![
+                            NotifyEvent::MailboxName
+                        ]),
+                    },
+                    NotifyEventGroup {
+                        filter: NotifyFilter::Personal(()),
+                        events: NotifyEvents::Events(vec![
+                            NotifyEvent::SubscriptionChange
+                        ]),
+                    },
+                ],
+            }))
+        );
+        assert_reversible!(
+            ResponseLine,
+            "* NO [BADEVENT (MessageNew MessageExpunge)] K",
+            ResponseLine {
+                tag: None,
+                response: Response::Cond(CondResponse {
+                    cond: RespCondType::No,
+                    code: Some(RespTextCode::BadEvent(vec![
+                        NotifyEvent::MessageNew,
+                        NotifyEvent::MessageExpunge,
+                    ])),
+                    quip: None,
+                }),
+            }
+        );
+        assert_reversible!(
+            ResponseLine,
+            "* LIST () \"/\" bar (\"OLDNAME\" (foo))",
+            ResponseLine {
+                tag: None,
+                response: Response::List(MailboxList {
+                    flags: vec![],
+                    name: mn("bar"),
+                    child_info: None,
+                    old_name: Some(mn("foo")),
+                }),
+            }
+        );
+    }
+
     #[test]
     fn command_line_syntax() {
         assert_reversible!(
@@ -4925,6 +6097,7 @@ mod test {
                     flags: vec![],
                     name: mn("INBOX"),
                     child_info: None,
+                    old_name: None,
                 }),
             }
         );
@@ -4937,6 +6110,7 @@ mod test {
                     flags: vec![s("\\Marked"), s("\\Subscribed")],
                     name: mn("INBOX"),
                     child_info: None,
+                    old_name: None,
                 }),
             }
         );
@@ -4949,6 +6123,7 @@ mod test {
                     flags: vec![s("\\Noselect")],
                     name: mn("foo bar"),
                     child_info: None,
+                    old_name: None,
                 }),
             }
         );
@@ -5003,11 +6178,16 @@ mod test {
                 tag: None,
                 response: Response::Esearch(EsearchResponse {
                     tag: s("42"),
+                    mailbox: None,
+                    uidvalidity: None,
                     uid: false,
                     min: Some(1),
                     max: Some(42),
                     all: None,
                     count: None,
+                    partial: None,
+                    addto: None,
+                    removeto: None,
                     modseq: Some(12345678901234567890),
                 }),
             }
@@ -5019,11 +6199,85 @@ mod test {
                 tag: None,
                 response: Response::Esearch(EsearchResponse {
                     tag: s("42"),
+                    mailbox: None,
+                    uidvalidity: None,
                     uid: true,
                     min: None,
                     max: None,
                     all: ns("2:4"),
                     count: Some(42),
+                    partial: None,
+                    addto: None,
+                    removeto: None,
+                    modseq: None,
+                }),
+            }
+        );
+        assert_reversible!(
+            ResponseLine,
+            r#"* ESEARCH (TAG "42") UID PARTIAL (1:10 5,7:9) ADDTO (0 3) REMOVETO (0 4:5)"#,
+            ResponseLine {
+                tag: None,
+                response: Response::Esearch(EsearchResponse {
+                    tag: s("42"),
+                    mailbox: None,
+                    uidvalidity: None,
+                    uid: true,
+                    min: None,
+                    max: None,
+                    all: None,
+                    count: None,
+                    partial: Some(EsearchPartial {
+                        range: PartialRange { start: 1, end: 10 },
+                        hits: ns("5,7:9"),
+                    }),
+                    addto: ns("3"),
+                    removeto: ns("4:5"),
+                    modseq: None,
+                }),
+            }
+        );
+        assert_reversible!(
+            ResponseLine,
+            r#"* ESEARCH (TAG "42" MAILBOX foo UIDVALIDITY 3) UID COUNT 0"#,
+            ResponseLine {
+                tag: None,
+                response: Response::Esearch(EsearchResponse {
+                    tag: s("42"),
+                    mailbox: Some(mn("foo")),
+                    uidvalidity: Some(3),
+                    uid: true,
+                    min: None,
+                    max: None,
+                    all: None,
+                    count: Some(0),
+                    partial: None,
+                    addto: None,
+                    removeto: None,
+                    modseq: None,
+                }),
+            }
+        );
+        assert_reversible!(
+            ResponseLine,
+            r#"* ESEARCH (TAG "42") PARTIAL (5:6 NIL)"#,
+            ResponseLine {
+                tag: None,
+                response: Response::Esearch(EsearchResponse {
+                    tag: s("42"),
+                    mailbox: None,
+                    uidvalidity: None,
+                    uid: false,
+                    min: None,
+                    max: None,
+                    all: None,
+                    count: None,
+                    partial: Some(EsearchPartial {
+                        range: PartialRange { start: 5, end: 6 },
+                        hits: None,
+                    }),
+                    addto: None,
+                    removeto: None,
                     modseq: None,
                 }),
             }
@@ -5144,6 +6398,7 @@ mod test {
                     flags: None,
                     internal_date: None,
                     utf8: false,
+                    catenate: false,
                 },
             }
         );
@@ -5157,6 +6412,7 @@ mod test {
                     flags: None,
                     internal_date: None,
                     utf8: true,
+                    catenate: false,
                 },
             }
         );
@@ -5170,6 +6426,7 @@ mod test {
                     flags: Some(vec![]),
                     internal_date: None,
                     utf8: false,
+                    catenate: false,
                 },
             }
         );
@@ -5183,6 +6440,7 @@ mod test {
                     flags: Some(vec![Flag::Deleted]),
                     internal_date: None,
                     utf8: false,
+                    catenate: false,
                 },
             }
         );
@@ -5199,6 +6457,7 @@ mod test {
                     ]),
                     internal_date: None,
                     utf8: false,
+                    catenate: false,
                 },
             }
         );
@@ -5215,6 +6474,7 @@ mod test {
                             .ymd_hmsx(2020, 7, 4, 16, 31, 0),
                     ),
                     utf8: false,
+                    catenate: false,
                 }
             }
         );
@@ -5231,6 +6491,21 @@ mod test {
                             .ymd_hmsx(2020, 7, 4, 16, 31, 0)
                     ),
                     utf8: false,
+                    catenate: false,
+                }
+            }
+        );
+        assert_reversible!(
+            AppendCommandStart,
+            "1 APPEND dst CATENATE (",
+            AppendCommandStart {
+                tag: s("1"),
+                mailbox: mn("dst"),
+                first_fragment: AppendFragment {
+                    flags: None,
+                    internal_date: None,
+                    utf8: false,
+                    catenate: true,
                 }
             }
         );
@@ -22,6 +22,7 @@ use chrono::prelude::*;
 
 use super::bodystructure;
 use super::envelope;
+use super::preview;
 use super::section;
 use super::simple;
 use crate::account::model::*;
@@ -52,6 +53,9 @@ pub enum FetchedItem {
             Result<section::FetchedBodySection, Error>,
         ),
     ),
+    /// The preview text, or `None` if it is not available without more
+    /// effort.
+    Preview(Option<String>),
 }
 
 impl FetchedItem {
@@ -81,6 +85,13 @@ impl FetchedItem {
         }
     }
 
+    pub fn into_preview(self) -> Option<String> {
+        match self {
+            FetchedItem::Preview(p) => p,
+            _ => None,
+        }
+    }
+
     fn into_none<T>(self) -> Option<T> {
         None
     }
@@ -205,6 +216,15 @@ impl MultiFetcher {
         )));
     }
 
+    /// Add a preview fetcher as a sub-fetcher.
+    pub fn add_preview(&mut self) {
+        self.add_fetcher(Box::new(VisitorMap::new(
+            preview::fetcher(),
+            |p| FetchedItem::Preview(Some(p)),
+            FetchedItem::into_preview,
+        )));
+    }
+
     fn add_fetcher(&mut self, fetcher: Fetcher) {
         self.fetchers.push(Some(fetcher));
         self.results.push(FetchedItem::Nil);
@@ -0,0 +1,458 @@
+//-
+// Copyright (c) 2024, Jason Lingle
+//
+// This file is part of Crymap.
+//
+// Crymap is free software: you can  redistribute it and/or modify it under the
+// terms of  the GNU General Public  License as published by  the Free Software
+// Foundation, either version  3 of the License, or (at  your option) any later
+// version.
+//
+// Crymap is distributed  in the hope that  it will be useful,  but WITHOUT ANY
+// WARRANTY; without  even the implied  warranty of MERCHANTABILITY  or FITNESS
+// FOR  A PARTICULAR  PURPOSE.  See the  GNU General  Public  License for  more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// Crymap. If not, see <http://www.gnu.org/licenses/>.
+
+//! Generation of the RFC 8970 `PREVIEW` of a message.
+//!
+//! The preview is taken from the first `text/plain` or `text/html` part which
+//! is not an attachment and has some visible text. HTML has its markup
+//! stripped in a fairly naïve way; the result only needs to be good enough to
+//! give the user an idea of what the message is about.
+
+use std::str;
+
+use crate::mime::content_encoding::ContentDecoder;
+use crate::mime::grovel::Visitor;
+use crate::mime::header;
+
+/// The maximum length of a preview, in characters.
+///
+/// RFC 8970 requires at most 256 and recommends at most 200.
+const MAX_PREVIEW_CHARS: usize = 200;
+/// Stop reading a `text/plain` part once this many bytes have been collected.
+const PLAIN_READ_LIMIT: usize = 4096;
+/// Stop reading a `text/html` part once this many bytes have been collected.
+///
+/// This is much larger than `PLAIN_READ_LIMIT` since HTML messages frequently
+/// start with a large amount of styling.
+const HTML_READ_LIMIT: usize = 65536;
+
+/// A type which can be passed to `grovel` to produce the preview text of a
+/// message.
+///
+/// The output is the empty string if the message has no suitable text.
+pub type Fetcher = Box<dyn Visitor<Output = String>>;
+
+/// Create a new preview fetcher.
+pub fn fetcher() -> Fetcher {
+    Box::new(ContentDecoder::new(Box::<PreviewFetcher>::default(), true))
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum PartKind {
+    /// A part which is neither text nor a container.
+    #[default]
+    Other,
+    Plain,
+    Html,
+    /// A multipart or embedded message.
+    Container,
+}
+
+#[derive(Debug, Default)]
+struct PreviewFetcher {
+    kind: PartKind,
+    attachment: bool,
+    text: Vec<u8>,
+}
+
+impl Visitor for PreviewFetcher {
+    type Output = String;
+
+    fn header(
+        &mut self,
+        _raw: &[u8],
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), String> {
+        if "Content-Disposition".eq_ignore_ascii_case(name) {
+            self.attachment = str::from_utf8(value).ok().is_some_and(|v| {
+                v.trim_start()
+                    .get(..10)
+                    .is_some_and(|v| v.eq_ignore_ascii_case("attachment"))
+            });
+        }
+
+        Ok(())
+    }
+
+    fn content_type(
+        &mut self,
+        ct: &header::ContentType<'_>,
+    ) -> Result<(), String> {
+        self.kind = if ct.is_type("text") && ct.is_subtype("plain") {
+            PartKind::Plain
+        } else if ct.is_type("text") && ct.is_subtype("html") {
+            PartKind::Html
+        } else if ct.is_type("multipart") || ct.is_type("message") {
+            PartKind::Container
+        } else {
+            return Err(String::new());
+        };
+
+        Ok(())
+    }
+
+    fn start_content(&mut self) -> Result<(), String> {
+        if self.attachment {
+            Err(String::new())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn content(&mut self, data: &[u8]) -> Result<(), String> {
+        let limit = match self.kind {
+            PartKind::Plain => PLAIN_READ_LIMIT,
+            PartKind::Html => HTML_READ_LIMIT,
+            PartKind::Other | PartKind::Container => return Ok(()),
+        };
+
+        self.text.extend_from_slice(data);
+        if self.text.len() >= limit {
+            Err(self.end())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn start_part(&mut self) -> Option<Fetcher> {
+        if PartKind::Container == self.kind {
+            Some(fetcher())
+        } else {
+            None
+        }
+    }
+
+    fn child_result(&mut self, child_result: String) -> Result<(), String> {
+        if child_result.is_empty() {
+            Ok(())
+        } else {
+            Err(child_result)
+        }
+    }
+
+    fn end(&mut self) -> String {
+        let text = String::from_utf8_lossy(&self.text);
+        match self.kind {
+            PartKind::Plain => summarise(&text),
+            PartKind::Html => summarise(&strip_html(&text)),
+            PartKind::Other | PartKind::Container => String::new(),
+        }
+    }
+
+    fn visit_default(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Collapses all whitespace in `text` and truncates it to at most
+/// `MAX_PREVIEW_CHARS` characters.
+fn summarise(text: &str) -> String {
+    let mut preview = String::new();
+    let mut len = 0usize;
+    'outer: for word in text.split_whitespace() {
+        if len > 0 {
+            preview.push(' ');
+            len += 1;
+        }
+
+        for ch in word.chars().filter(|ch| !ch.is_control()) {
+            if len >= MAX_PREVIEW_CHARS {
+                break 'outer;
+            }
+
+            preview.push(ch);
+            len += 1;
+        }
+    }
+
+    preview.truncate(preview.trim_end().len());
+    preview
+}
+
+/// Elements whose content is not visible text.
+const INVISIBLE_ELEMENTS: &[&str] = &["head", "script", "style", "title"];
+
+/// Converts `html` to plain text by discarding all markup.
+///
+/// Every tag is replaced with a space except for a few common inline ones,
+/// since the preview collapses whitespace anyway.
+fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len() / 2);
+    let mut rest = html;
+
+    while let Some(ix) = rest.find(['<', '&']) {
+        text.push_str(&rest[..ix]);
+        rest = &rest[ix..];
+
+        if rest.starts_with('&') {
+            let (decoded, tail) = decode_entity(rest);
+            text.push_str(&decoded);
+            rest = tail;
+            continue;
+        }
+
+        if let Some(tail) = rest.strip_prefix("<!--") {
+            rest = tail.find("-->").map_or("", |end| &tail[end + 3..]);
+            continue;
+        }
+
+        let Some(end) = rest.find('>') else {
+            rest = "";
+            break;
+        };
+
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        let name = tag
+            .split(|ch: char| ch.is_whitespace() || '/' == ch)
+            .find(|s| !s.is_empty())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        if !tag.starts_with('/')
+            && !tag.ends_with('/')
+            && INVISIBLE_ELEMENTS.contains(&name.as_str())
+        {
+            // Skip everything up to the matching close tag. This is
+            // case-insensitive, so search in a lowercase copy; the
+            // lowercasing here is ASCII-only so the byte offsets agree.
+            let needle = format!("</{name}");
+            rest = rest
+                .to_ascii_lowercase()
+                .find(&needle)
+                .map_or("", |close| &rest[close..]);
+            continue;
+        }
+
+        if !matches!(
+            name.as_str(),
+            "a" | "b" | "i" | "u" | "em" | "strong" | "span" | "font" | "small"
+        ) {
+            text.push(' ');
+        }
+    }
+
+    text.push_str(rest);
+    text
+}
+
+/// Decodes the character reference at the start of `s`, returning the text it
+/// represents and the remainder of `s`.
+///
+/// Unrecognised references are returned verbatim.
+fn decode_entity(s: &str) -> (String, &str) {
+    let end = s
+        .char_indices()
+        .take(12)
+        .find(|&(_, ch)| ';' == ch)
+        .map(|(ix, _)| ix);
+    let Some(end) = end else {
+        return ("&".to_owned(), &s[1..]);
+    };
+
+    let name = &s[1..end];
+    let decoded = match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => name
+            .strip_prefix("#x")
+            .or_else(|| name.strip_prefix("#X"))
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .or_else(|| name.strip_prefix('#').and_then(|d| d.parse().ok()))
+            .and_then(char::from_u32),
+    };
+
+    match decoded {
+        Some(ch) => (ch.to_string(), &s[end + 1..]),
+        None => ("&".to_owned(), &s[1..]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mime::grovel;
+
+    fn preview(message: &str) -> String {
+        let message = message.replace('\n', "\r\n");
+        grovel::grovel(
+            &mut grovel::SimpleAccessor {
+                data: message.into(),
+                ..grovel::SimpleAccessor::default()
+            },
+            fetcher(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn simple_text() {
+        assert_eq!(
+            "Hello world. This is the content.",
+            preview(
+                "\
+Subject: foo
+
+Hello world.
+
+  This is the
+content.
+",
+            ),
+        );
+    }
+
+    #[test]
+    fn encoded_text() {
+        assert_eq!(
+            "Caf\u{e9} \u{2014} d\u{e9}j\u{e0} vu",
+            preview(
+                "\
+Content-Type: text/plain; charset=utf-8
+Content-Transfer-Encoding: base64
+
+Q2Fmw6kg4oCUIGTDqWrDoCB2dQ==
+",
+            ),
+        );
+
+        assert_eq!(
+            "na\u{ef}ve",
+            preview(
+                "\
+Content-Type: text/plain; charset=iso-8859-1
+Content-Transfer-Encoding: quoted-printable
+
+na=EFve
+",
+            ),
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let result = preview(&format!(
+            "Content-Type: text/plain\n\n{}\n",
+            "word ".repeat(100),
+        ));
+        assert_eq!(MAX_PREVIEW_CHARS - 1, result.chars().count());
+        assert!(result.starts_with("word word"));
+        assert!(result.ends_with("word"));
+    }
+
+    #[test]
+    fn html_only() {
+        assert_eq!(
+            "Title here Some bold & important text. Next line",
+            preview(
+                "\
+Content-Type: text/html
+
+<html><head><title>Ignored</title>
+<STYLE>p { color: red; }</STYLE></head>
+<body><!-- a comment -->
+<h1>Title here</h1><p>Some <b>bold</b> &amp; important&#x20;text.<br>Next&nbsp;line
+</body></html>
+",
+            ),
+        );
+    }
+
+    #[test]
+    fn multipart_prefers_first_text_part() {
+        assert_eq!(
+            "The plain version",
+            preview(
+                "\
+Content-Type: multipart/mixed; boundary=outer
+
+--outer
+Content-Type: multipart/alternative; boundary=inner
+
+--inner
+Content-Type: text/plain
+
+The plain version
+--inner
+Content-Type: text/html
+
+<p>The HTML version</p>
+--inner--
+--outer
+Content-Type: text/plain
+Content-Disposition: attachment; filename=foo.txt
+
+Attached
+--outer--
+",
+            ),
+        );
+    }
+
+    #[test]
+    fn multipart_skips_attachments_and_empty_parts() {
+        assert_eq!(
+            "Finally some text",
+            preview(
+                "\
+Content-Type: multipart/mixed; boundary=bound
+
+--bound
+Content-Type: image/png
+Content-Transfer-Encoding: base64
+
+iVBORw0KGgo=
+--bound
+Content-Type: text/plain
+Content-Disposition: ATTACHMENT
+
+Attached
+--bound
+Content-Type: text/plain
+
+\x20\x20
+--bound
+Content-Type: text/html
+
+<p>Finally <i>some</i> text</p>
+--bound--
+",
+            ),
+        );
+    }
+
+    #[test]
+    fn no_text() {
+        assert_eq!(
+            "",
+            preview(
+                "\
+Content-Type: application/octet-stream
+
+xyzzy
+",
+            ),
+        );
+    }
+}
@@ -135,6 +135,8 @@ pub enum SubjectCode {
     AuthenticationCredentialsInvalid = 78,
     AuthenticationMechanismWeak = 79,
     EncryptionRequiredForRequestedAuthenticationMechanism = 711,
+    // RFC 4468
+    MessageContentNotAvailable = 66,
 }
 
 pub mod sc {
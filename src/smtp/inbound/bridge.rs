@@ -31,6 +31,7 @@ use std::borrow::Cow;
 use tokio::sync::{mpsc, oneshot};
 
 use super::super::codes::*;
+use crate::support::buffer::BufferReader;
 
 /// An SMTP response, excluding the continuation/final distinction.
 #[derive(Clone, Debug)]
@@ -113,8 +114,22 @@ pub struct RecipientRequest {
 /// response required after delivery. For SMTP, this will be only one response.
 /// For LMTP, it will be one response for each successful `RecipientRequest`
 /// since the last reset.
+///
+/// While `data` is open, the server may also send `UrlRequest`s on `urls` to
+/// have `BURL` URLs resolved. Services which don't offer `BURL` can simply
+/// drop `urls`.
 pub struct DataRequest {
     pub data: tokio::io::DuplexStream,
+    pub urls: mpsc::Receiver<UrlRequest>,
     pub recipient_responses:
         oneshot::Receiver<mpsc::Sender<Result<(), SmtpResponse<'static>>>>,
 }
+
+/// A `BURL` command within a data transfer.
+///
+/// The service responds with the content the URL refers to, which the server
+/// then feeds into the data stream as if it had been sent by `BDAT`.
+pub struct UrlRequest {
+    pub url: String,
+    pub respond: oneshot::Sender<Result<BufferReader, SmtpResponse<'static>>>,
+}
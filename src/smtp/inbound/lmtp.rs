@@ -54,6 +54,7 @@ pub async fn serve_lmtp(
     let server_service = super::server::Service {
         lmtp: true,
         offer_binarymime: true,
+        offer_burl: false,
         auth: false,
         send_request: request_tx,
     };
@@ -193,6 +194,7 @@ impl LmtpService {
         let DataRequest {
             data,
             recipient_responses,
+            ..
         } = data;
         let copy_result = {
             // Move `data` into here so it gets dropped at the end of this
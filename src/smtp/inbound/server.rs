@@ -49,6 +49,11 @@ pub(super) struct Service {
     /// remain are systems that fail to declare their functional support for
     /// these extensions.)
     pub(super) offer_binarymime: bool,
+    /// Whether the RFC 4468 `BURL` extension is offered.
+    ///
+    /// If true, the service must handle the `urls` channel of every
+    /// `DataRequest`.
+    pub(super) offer_burl: bool,
     /// Whether authentication is in use.
     ///
     /// If true, no mail commands can be issued without authentication. If
@@ -117,6 +122,7 @@ pub(super) async fn run(
 
 struct SendData {
     stream: DuplexStream,
+    urls: mpsc::Sender<UrlRequest>,
     recipient_responses:
         oneshot::Sender<mpsc::Sender<Result<(), SmtpResponse<'static>>>>,
 }
@@ -169,6 +175,7 @@ static EXTENSIONS: &[&str] = &[
     "8BITMIME", // RFC 6152
     "AUTH PLAIN",
     "BINARYMIME",          // RFC 3030
+    "BURL imap",           // RFC 4468
     "CHUNKING",            // RFC 3030
     "ENHANCEDSTATUSCODES", // RFC 5248
     "PIPELINING",
@@ -337,6 +344,7 @@ impl Server {
             Command::BinaryData(len, last) => {
                 self.cmd_binary_data(len, last).await
             },
+            Command::Burl(url, last) => self.cmd_burl(url, last).await,
             Command::Reset => self.cmd_reset().await,
             Command::Verify => self.cmd_verify().await,
             Command::Expand => self.cmd_expand().await,
@@ -396,6 +404,16 @@ impl Server {
                     continue;
                 }
 
+                // BURL is only usable after authentication, so only offer it
+                // when AUTH is on the table.
+                if ext.starts_with("BURL ")
+                    && (!self.service.offer_burl
+                        || !self.service.auth
+                        || !self.io.get_ref().is_ssl())
+                {
+                    continue;
+                }
+
                 if ext.starts_with("AUTH ")
                     && (!self.service.auth || !self.io.get_ref().is_ssl())
                 {
@@ -693,10 +711,12 @@ impl Server {
     /// is rejected, `false` is returned.
     async fn start_data_transfer(&mut self) -> Result<bool, Error> {
         let (data_in, data_out) = tokio::io::duplex(4096);
+        let (urls_tx, urls_rx) = mpsc::channel(1);
         let (recipients_tx, recipients_rx) = oneshot::channel();
         if !self
             .service_request(RequestPayload::Data(DataRequest {
                 data: data_in,
+                urls: urls_rx,
                 recipient_responses: recipients_rx,
             }))
             .await?
@@ -706,6 +726,7 @@ impl Server {
 
         self.sending_data = Some(SendData {
             stream: data_out,
+            urls: urls_tx,
             recipient_responses: recipients_tx,
         });
         Ok(true)
@@ -713,12 +734,13 @@ impl Server {
 
     /// Completes a data transfer.
     ///
-    /// The data stream to the service is severed, then the responses for each
-    /// recipient (LMTP) or singular response (SMTP) are retrieved and sent.
-    /// The mail delivery state is reset.
+    /// The data stream and URL channel to the service are severed, then the
+    /// responses for each recipient (LMTP) or singular response (SMTP) are
+    /// retrieved and sent. The mail delivery state is reset.
     async fn complete_data_transfer(&mut self) -> Result<(), Error> {
         let sending_data = self.sending_data.take().unwrap();
         drop(sending_data.stream);
+        drop(sending_data.urls);
 
         let (recipients_tx, mut recipients_rx) = mpsc::channel(1);
         // If this fails, `recipients_rx` will be a broken channel, and we'll
@@ -901,6 +923,160 @@ impl Server {
         }
     }
 
+    async fn cmd_burl(&mut self, url: String, last: bool) -> Result<(), Error> {
+        if !self.service.offer_burl {
+            return self
+                .send_response(
+                    Final,
+                    pc::CommandSyntaxError,
+                    Some((cc::PermFail, sc::InvalidCommand)),
+                    Cow::Borrowed("Unrecognised command"),
+                )
+                .await;
+        }
+
+        require!(
+            self,
+            need_helo = true,
+            need_mail_from = true,
+            need_recipients = true
+        );
+
+        self.ineffective_commands = 0;
+        if self.sending_data.is_none() {
+            if !self.start_data_transfer().await? {
+                return Ok(());
+            }
+
+            info!("{} Begin binary data transfer", self.log_prefix);
+        }
+
+        let (response_tx, response_rx) = oneshot::channel();
+        let sent = self
+            .sending_data
+            .as_mut()
+            .unwrap()
+            .urls
+            .send(UrlRequest {
+                url,
+                respond: response_tx,
+            })
+            .await
+            .is_ok();
+        let response = if sent { response_rx.await.ok() } else { None };
+        let mut content = match response {
+            Some(Ok(content)) => content,
+            Some(Err(response)) => {
+                // Like a failed BDAT, a failed BURL fails the whole
+                // transaction.
+                self.abort_data_transfer();
+                return self
+                    .send_response(Final, response.0, response.1, response.2)
+                    .await;
+            },
+            None => {
+                error!(
+                    "{} [BUG] Service worker disappeared during BURL",
+                    self.log_prefix,
+                );
+                self.abort_data_transfer();
+                return self
+                    .send_response(
+                        Final,
+                        pc::TransactionFailed,
+                        Some((cc::TempFail, sc::OtherMailSystem)),
+                        Cow::Borrowed("Internal server error"),
+                    )
+                    .await;
+            },
+        };
+
+        // Extend the deadline to account for the service being slow to
+        // consume the content.
+        let _ = self
+            .deadline_tx
+            .send(Instant::now() + Duration::from_secs(300))
+            .await;
+
+        // Reading the content involves file I/O and decryption once it has
+        // spilled to disk, so it is done on a blocking thread which hands the
+        // data over in chunks. The thread stops when `chunk_rx` is dropped.
+        let (chunk_tx, mut chunk_rx) = mpsc::channel::<io::Result<Vec<u8>>>(4);
+        tokio::task::spawn_blocking(move || loop {
+            let mut chunk = vec![0u8; 65536];
+            let result = match io::Read::read(&mut content, &mut chunk) {
+                Ok(0) => break,
+                Ok(nread) => {
+                    chunk.truncate(nread);
+                    Ok(chunk)
+                },
+                Err(e) => Err(e),
+            };
+
+            let failed = result.is_err();
+            if chunk_tx.blocking_send(result).is_err() || failed {
+                break;
+            }
+        });
+
+        let copied = {
+            let sending_data = self.sending_data.as_mut().unwrap();
+            async {
+                while let Some(chunk) = chunk_rx.recv().await {
+                    match sending_data.stream.write_all(&chunk?).await {
+                        Ok(()) => {},
+                        // BrokenPipe => sending_data.stream is broken
+                        Err(e) if io::ErrorKind::BrokenPipe == e.kind() => {
+                            return Ok(true);
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+
+                Ok(false)
+            }
+            .await
+        };
+
+        let abort = match copied {
+            Ok(abort) => abort,
+            Err(e) => {
+                error!("{} Failed to copy BURL content: {e}", self.log_prefix);
+                self.abort_data_transfer();
+                return self
+                    .send_response(
+                        Final,
+                        pc::TransactionFailed,
+                        Some((cc::TempFail, sc::OtherMailSystem)),
+                        Cow::Borrowed("Internal server error"),
+                    )
+                    .await;
+            },
+        };
+
+        if last || abort {
+            self.complete_data_transfer().await
+        } else {
+            self.send_response(
+                Final,
+                pc::Ok,
+                Some((cc::Success, sc::Undefined)),
+                Cow::Borrowed("OK"),
+            )
+            .await
+        }
+    }
+
+    /// Abandons the current data transfer without delivering anything.
+    ///
+    /// The service sees the transfer as aborted since `recipient_responses`
+    /// is dropped.
+    fn abort_data_transfer(&mut self) {
+        self.sending_data = None;
+        self.recipients = 0;
+        self.has_mail_from = false;
+    }
+
     async fn cmd_reset(&mut self) -> Result<(), Error> {
         self.has_mail_from = false;
         self.recipients = 0;
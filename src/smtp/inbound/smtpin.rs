@@ -73,6 +73,7 @@ pub async fn serve_smtpin(
     let server_service = super::server::Service {
         lmtp: false,
         offer_binarymime: true,
+        offer_burl: false,
         auth: false,
         send_request: request_tx,
     };
@@ -21,6 +21,7 @@ use std::cell::RefCell;
 use std::collections::HashSet;
 use std::fmt::Write as _;
 use std::io::{self, Write};
+use std::net::IpAddr;
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::Arc;
@@ -33,7 +34,13 @@ use tokio::{io::AsyncReadExt, sync::mpsc};
 use super::super::codes::*;
 use super::{bridge::*, delivery::*};
 use crate::{
-    account::v2::{Account, LogInError, SmtpTransfer, SpooledMessageId},
+    account::{
+        imap_url::ImapUrl,
+        v2::{
+            Account, LogInClient, LogInError, LogInProtocol, SmtpTransfer,
+            SpooledMessageId,
+        },
+    },
     mime::{dkim, header},
     support::{
         append_limit::APPEND_SIZE_LIMIT,
@@ -54,6 +61,7 @@ pub async fn serve_smtpsub(
     ssl_acceptor: Option<openssl::ssl::SslAcceptor>,
     data_root: PathBuf,
     local_host_name: String,
+    peer_ip: Option<IpAddr>,
     spool_out: Box<dyn FnMut(Rc<RefCell<Account>>, SpooledMessageId)>,
 ) -> Result<(), crate::support::error::Error> {
     let tls = io.ssl_string();
@@ -61,6 +69,7 @@ pub async fn serve_smtpsub(
     let server_service = super::server::Service {
         lmtp: false,
         offer_binarymime: false,
+        offer_burl: true,
         auth: true,
         send_request: request_tx,
     };
@@ -69,6 +78,7 @@ pub async fn serve_smtpsub(
         log_prefix: log_prefix.clone(),
         config,
         data_root,
+        peer_ip,
         request_in: request_rx,
         spool_out,
 
@@ -97,6 +107,7 @@ struct SmtpsubService {
     log_prefix: LogPrefix,
     config: Arc<SystemConfig>,
     data_root: PathBuf,
+    peer_ip: Option<IpAddr>,
     request_in: mpsc::Receiver<Request>,
     spool_out: Box<dyn FnMut(Rc<RefCell<Account>>, SpooledMessageId)>,
 
@@ -127,7 +138,7 @@ impl SmtpsubService {
                 },
 
                 RequestPayload::Auth(req) => {
-                    let response = self.req_auth(req);
+                    let response = self.req_auth(req).await;
                     let _ = request.respond.send(response);
                 },
 
@@ -167,18 +178,31 @@ impl SmtpsubService {
         Ok(())
     }
 
-    fn req_auth(
+    async fn req_auth(
         &mut self,
         req: AuthRequest,
     ) -> Result<(), SmtpResponse<'static>> {
-        let (account, aliases) = Account::log_in(
+        let result = match Account::log_in(
             self.log_prefix.clone(),
             &self.config,
             &self.data_root,
             &req.userid,
             &req.password,
-        )
-        .map_err(|e| match e {
+            LogInProtocol::Submission,
+            &LogInClient {
+                peer_ip: self.peer_ip,
+                tls: self.tls.clone(),
+                ..LogInClient::default()
+            },
+        ) {
+            Ok(r) => Ok(r),
+            Err((e, delay)) => {
+                tokio::time::sleep(delay).await;
+                Err(e)
+            },
+        };
+
+        let (account, aliases) = result.map_err(|e| match e {
             LogInError::IllegalUserId | LogInError::InvalidCredentials => {
                 SmtpResponse(
                     pc::AuthenticationCredentialsInvalid,
@@ -193,6 +217,12 @@ impl SmtpsubService {
                 Cow::Owned(e.to_string()),
             ),
 
+            LogInError::LockedOut => SmtpResponse(
+                pc::TemporaryAuthenticationFailure,
+                Some((cc::TempFail, sc::OtherSecurity)),
+                Cow::Owned(e.to_string()),
+            ),
+
             LogInError::SetupError => SmtpResponse(
                 pc::TemporaryAuthenticationFailure,
                 Some((cc::TempFail, sc::SystemIncorrectlyConfigured)),
@@ -298,8 +328,22 @@ impl SmtpsubService {
             }
         };
 
-        let data_result = self.consume_data(data.data).await;
-        let Ok(recipient_responses) = data.recipient_responses.await else {
+        let DataRequest {
+            data,
+            urls,
+            recipient_responses,
+        } = data;
+        // URLs need to be resolved while the data stream is being consumed,
+        // since BURL and BDAT chunks can be freely interleaved.
+        let serve_urls = serve_urls(
+            self.log_prefix.clone(),
+            Rc::clone(self.account.as_ref().unwrap()),
+            self.authed_user_names.clone(),
+            urls,
+        );
+        let (data_result, ()) =
+            tokio::join!(self.consume_data(data), serve_urls);
+        let Ok(recipient_responses) = recipient_responses.await else {
             return;
         };
 
@@ -600,6 +644,69 @@ impl SmtpsubService {
     }
 }
 
+/// Resolves the `BURL` URLs received on `urls` until the server closes the
+/// channel.
+///
+/// Only URLAUTH URLs into the user's own mailboxes which the user is allowed
+/// to submit are accepted.
+async fn serve_urls(
+    log_prefix: LogPrefix,
+    account: Rc<RefCell<Account>>,
+    aliases: HashSet<String>,
+    mut urls: mpsc::Receiver<UrlRequest>,
+) {
+    while let Some(request) = urls.recv().await {
+        let result = resolve_url(
+            &log_prefix,
+            &mut account.borrow_mut(),
+            &aliases,
+            &request.url,
+        );
+        let _ = request.respond.send(result);
+    }
+}
+
+fn resolve_url(
+    log_prefix: &LogPrefix,
+    account: &mut Account,
+    aliases: &HashSet<String>,
+    url: &str,
+) -> Result<BufferReader, SmtpResponse<'static>> {
+    let url = url
+        .parse::<ImapUrl>()
+        .ok()
+        .filter(|url| {
+            url.user.is_some()
+                && url.host.is_some()
+                && url.access.is_some()
+                && url.permits(aliases, true)
+        })
+        .ok_or(SmtpResponse(
+            pc::TransactionFailed,
+            Some((cc::PermFail, sc::DeliveryNotAuthorised)),
+            Cow::Borrowed("URL is not a valid URLAUTH URL for this user"),
+        ))?;
+
+    match account.fetch_url(&url) {
+        Ok(fetched) => Ok(fetched.buffer),
+        Err(Error::BadUrl | Error::UnsupportedUrlauthMechanism) => {
+            Err(SmtpResponse(
+                pc::TransactionFailed,
+                Some((cc::PermFail, sc::MessageContentNotAvailable)),
+                Cow::Borrowed("URL is invalid or inaccessible"),
+            ))
+        },
+        Err(e) => {
+            error!("{log_prefix} Failed to resolve BURL URL: {e}");
+            Err(SmtpResponse(
+                pc::TransactionFailed,
+                Some((cc::TempFail, sc::MessageContentNotAvailable)),
+                Cow::Borrowed("Internal error resolving URL"),
+            ))
+        },
+    }
+}
+
 struct DeliverableMessage {
     data_buffer: BufferReader,
     trace_headers: String,
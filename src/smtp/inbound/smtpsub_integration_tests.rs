@@ -29,7 +29,10 @@ use tempfile::TempDir;
 
 use super::integration_test_common::*;
 use crate::{
-    account::v2::{Account, SmtpTransfer, SpooledMessageId},
+    account::{
+        imap_url::ImapUrl,
+        v2::{Account, SmtpTransfer, SpooledMessageId},
+    },
     crypt::master_key::MasterKey,
     mime::dkim,
     support::{
@@ -177,6 +180,7 @@ async fn run_server(
             Some(ssl_acceptor()),
             data_root,
             "mx.earth.com".to_owned(),
+            None,
             Box::new(move |_, id| spool_tx.lock().unwrap().push(id)),
         ))
         .await;
@@ -252,6 +256,7 @@ fn ehlo() {
     assert!(responses.iter().any(|r| r.contains("PIPELINING")));
     assert!(!responses.iter().any(|r| r.contains("BINARYMIME")));
     assert!(!responses.iter().any(|r| r.contains("AUTH")));
+    assert!(!responses.iter().any(|r| r.contains("BURL")));
 
     cxn.simple_command("STARTTLS", "220 2.0.0");
     cxn.start_tls();
@@ -271,6 +276,7 @@ fn ehlo() {
     assert!(responses.iter().any(|r| r.contains("PIPELINING")));
     assert!(!responses.iter().any(|r| r.contains("BINARYMIME")));
     assert!(responses.iter().any(|r| r.contains("AUTH PLAIN")));
+    assert!(responses.iter().any(|r| r.contains("BURL imap")));
 
     cxn.write_line("QUIT\r\n");
     responses = cxn.read_responses();
@@ -995,3 +1001,110 @@ fn too_many_recipients() {
 
     panic!("never got 'too many recipients' response");
 }
+
+#[test]
+fn burl_submission() {
+    let draft = "\
+From: Zim <zim@earth.com>\r
+To: tallest@irk.com\r
+Subject: Invasion status\r
+\r
+burl_submission\r
+";
+
+    let setup = set_up();
+    let mut account = Account::new(
+        LogPrefix::new("burl_submission".to_owned()),
+        setup.system_dir.path().join("zim"),
+        Arc::clone(&setup.master_key),
+    )
+    .unwrap();
+    let uid = account
+        .append(
+            "Drafts",
+            FixedOffset::east_opt(0)
+                .unwrap()
+                .timestamp_opt(0, 0)
+                .unwrap(),
+            std::iter::empty(),
+            draft.as_bytes(),
+        )
+        .unwrap();
+    let mut authorise = |url: String| {
+        account
+            .generate_urlauth(&url.parse::<ImapUrl>().unwrap())
+            .unwrap()
+    };
+    let whole_url = authorise(format!(
+        "imap://zim@localhost/Drafts/;UID={};URLAUTH=submit+zim",
+        uid.0,
+    ));
+    let header_url = authorise(format!(
+        "imap://zim@localhost/Drafts/;UID={}/;SECTION=HEADER\
+         ;URLAUTH=submit+zim",
+        uid.0,
+    ));
+    let other_url = authorise(format!(
+        "imap://zim@localhost/Drafts/;UID={};URLAUTH=submit+dib",
+        uid.0,
+    ));
+
+    let (mut cxn, spool_rx) = setup.connect2("burl_submission");
+    cxn.quick_log_in("EHLO localhost", "zim", "hunter2");
+    cxn.simple_command(&format!("BURL {whole_url} LAST"), "503 5.5.1");
+
+    // The whole message by reference.
+    cxn.simple_command("MAIL FROM:<zim@earth.com>", "250 2.0.0");
+    cxn.simple_command("RCPT TO:<tallest@irk.com>", "250 2.1.5");
+    cxn.simple_command(&format!("BURL {whole_url} LAST"), "250 2.0.0");
+
+    // The header by reference, with a new body sent inline.
+    // The line ending appended by `simple_command` is part of the chunk.
+    let body = "burl_submission with new body";
+    cxn.simple_command("MAIL FROM:<zim@earth.com>", "250 2.0.0");
+    cxn.simple_command("RCPT TO:<tallest@irk.com>", "250 2.1.5");
+    cxn.simple_command(&format!("BURL {header_url}"), "250 2.0.0");
+    cxn.simple_command(
+        &format!("BDAT {} LAST\r\n{body}", body.len() + 2),
+        "250 2.0.0",
+    );
+
+    // URLs the submission server may not use fail the whole transaction.
+    cxn.simple_command("MAIL FROM:<zim@earth.com>", "250 2.0.0");
+    cxn.simple_command("RCPT TO:<tallest@irk.com>", "250 2.1.5");
+    cxn.simple_command(&format!("BURL {other_url} LAST"), "554 5.7.1");
+    cxn.simple_command("RCPT TO:<tallest@irk.com>", "503 5.5.1");
+
+    cxn.simple_command("MAIL FROM:<zim@earth.com>", "250 2.0.0");
+    cxn.simple_command("RCPT TO:<tallest@irk.com>", "250 2.1.5");
+    cxn.simple_command(
+        &format!("BURL imap://zim@localhost/Drafts/;UID={} LAST", uid.0),
+        "554 5.7.1",
+    );
+
+    let tampered = whole_url.replace("UID=", "UID=9");
+    cxn.simple_command("MAIL FROM:<zim@earth.com>", "250 2.0.0");
+    cxn.simple_command("RCPT TO:<tallest@irk.com>", "250 2.1.5");
+    cxn.simple_command(&format!("BURL {tampered} LAST"), "554 5.6.6");
+
+    let spooled = spool_rx.lock().unwrap().clone();
+    assert_eq!(2, spooled.len());
+    check_message(
+        &setup,
+        "zim",
+        spooled[0],
+        SmtpTransfer::SevenBit,
+        "zim@earth.com",
+        &["tallest@irk.com"],
+        "\r\n\r\nburl_submission\r\n",
+    );
+    check_message(
+        &setup,
+        "zim",
+        spooled[1],
+        SmtpTransfer::SevenBit,
+        "zim@earth.com",
+        &["tallest@irk.com"],
+        "Subject: Invasion status\r\n\r\nburl_submission with new body",
+    );
+}
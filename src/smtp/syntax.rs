@@ -35,6 +35,8 @@ pub enum Command {
     Data,
     /// BDAT length [LAST]
     BinaryData(u64, bool),
+    /// BURL url [LAST]
+    Burl(String, bool),
     /// RSET
     Reset,
     /// VRFY ignored...
@@ -75,11 +77,13 @@ lazy_static! {
         Regex::new("^(?i)RCPT TO:<(?:@[^:]+:)?([^>]+)>$").unwrap();
     static ref RX_BDAT: Regex =
         Regex::new("^(?i)BDAT ([0-9]+)( LAST)?$").unwrap();
+    static ref RX_BURL: Regex =
+        Regex::new("^(?i)BURL ([^ ]+)( LAST)?$").unwrap();
     static ref RX_AUTH: Regex =
         Regex::new("^(?i)AUTH ([A-Z0-9-]+)(?: ([0-9A-Za-z+/=]+))?$").unwrap();
     static ref RX_KNOWN_COMMANDS: Regex = Regex::new(
         "^(?i)(DATA|RSET|VRFY|EXPN|HELP|NOOP|QUIT|\
-         STARTTLS|LHLO|MAIL|RCPT|BDAT|HELO|EHLO|AUTH)( .*)?$"
+         STARTTLS|LHLO|MAIL|RCPT|BDAT|BURL|HELO|EHLO|AUTH)( .*)?$"
     )
     .unwrap();
 }
@@ -127,6 +131,11 @@ impl FromStr for Command {
                 .parse::<u64>()
                 .map_err(|_| ())
                 .map(|len| Command::BinaryData(len, cap.get(2).is_some()))
+        } else if let Some(cap) = RX_BURL.captures(s) {
+            Ok(Command::Burl(
+                cap.get(1).unwrap().as_str().to_owned(),
+                cap.get(2).is_some(),
+            ))
         } else if let Some(cap) = RX_AUTH.captures(s) {
             let mechanism = cap.get(1).unwrap().as_str().to_owned();
             let data = cap.get(2).map(|data| data.as_str().to_owned());
@@ -246,6 +255,20 @@ mod test {
         );
         assert_eq!(Ok(Command::BinaryData(1, true)), "bdat 1 last".parse());
 
+        assert_eq!(
+            Ok(Command::Burl(
+                "imap://foo@bar/INBOX/;UID=1".to_owned(),
+                false
+            )),
+            "BURL imap://foo@bar/INBOX/;UID=1".parse(),
+        );
+        assert_eq!(
+            Ok(Command::Burl("imap://x/y/;UID=1".to_owned(), true)),
+            "burl imap://x/y/;UID=1 last".parse(),
+        );
+        assert_eq!(Err(()), "BURL".parse::<Command>());
+        assert_eq!(Err(()), "BURL a b".parse::<Command>());
+
         assert_eq!(Ok(Command::Reset), "RSET".parse());
         assert_eq!(Err(()), "RSET FOO".parse::<Command>());
 
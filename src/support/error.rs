@@ -72,6 +72,34 @@ pub enum Error {
     BatchTooBig,
     #[error("Unknown Content-Transfer-Encoding")]
     UnknownCte,
+    #[error("No such application password")]
+    NxAppPassword,
+    #[error("Application password already exists")]
+    AppPasswordExists,
+    #[error("Too many application passwords")]
+    TooManyAppPasswords,
+    #[error("Not permitted when logged in with an application password")]
+    NotPermittedForAppPassword,
+    #[error("No such backup")]
+    NxBackup,
+    #[error("Retention period out of range")]
+    RetentionOutOfRange,
+    #[error("Invalid METADATA entry name")]
+    BadMetadataEntry,
+    #[error("METADATA value too large")]
+    MetadataTooLarge,
+    #[error("Too many METADATA entries")]
+    TooManyMetadataEntries,
+    #[error("Not possible for a virtual mailbox")]
+    VirtualMailbox,
+    #[error("Search query cannot be saved")]
+    UnsavableSearch,
+    #[error("Invalid or inaccessible URL")]
+    BadUrl,
+    #[error("Unsupported URLAUTH mechanism")]
+    UnsupportedUrlauthMechanism,
+    #[error("Database failed authentication; it may have been tampered with")]
+    DatabaseTampered,
     #[error(transparent)]
     Io(#[from] io::Error),
     #[error(transparent)]
@@ -83,7 +111,21 @@ pub enum Error {
     #[error(transparent)]
     Toml(#[from] toml::de::Error),
     #[error(transparent)]
-    Rusqlite(#[from] rusqlite::Error),
+    Rusqlite(rusqlite::Error),
     #[error("unexpected SQLite error: {0}")]
     Sqlite(std::os::raw::c_int),
 }
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        // The XEX VFS reports pages which fail authentication this way.
+        match e {
+            rusqlite::Error::SqliteFailure(ref f, _)
+                if libsqlite3_sys::SQLITE_IOERR_DATA == f.extended_code =>
+            {
+                Error::DatabaseTampered
+            },
+            e => Error::Rusqlite(e),
+        }
+    }
+}
@@ -61,6 +61,10 @@ impl LogPrefix {
         self.inner.lock().unwrap().user = Some(sanitise(user));
     }
 
+    pub fn clear_user(&self) {
+        self.inner.lock().unwrap().user = None;
+    }
+
     pub fn set_helo(&self, helo: String) {
         self.inner.lock().unwrap().helo = Some(sanitise(helo));
     }
@@ -21,6 +21,8 @@ use std::path::PathBuf;
 
 use serde::Deserialize;
 
+use crate::crypt::master_key::Argon2Params;
+
 /// The system-wide configuration for Crymap.
 ///
 /// This is stored in a file named `crymap.toml` under the Crymap system root,
@@ -39,6 +41,10 @@ pub struct SystemConfig {
     #[serde(default)]
     pub identification: BTreeMap<String, String>,
 
+    /// Configuration for the IMAP server.
+    #[serde(default)]
+    pub imap: ImapConfig,
+
     /// Configuration for the SMTP/LMTP servers.
     ///
     /// For LMTP, the defaults are reasonable for most installations. SMTP
@@ -90,6 +96,123 @@ pub struct SecurityConfig {
     /// still running as root at that point, it will refuse further operation.
     #[serde(default)]
     pub system_user: String,
+    /// If set, an RSA public key, in PEM format, under which every user's
+    /// master key is escrowed whenever their password is set.
+    ///
+    /// The corresponding private key should be kept offline. Given that
+    /// private key, `crymap server user recover` can reset the password of a
+    /// user who has forgotten theirs without losing any data.
+    ///
+    /// Users whose password has not been set since this was configured do not
+    /// have an escrowed key and cannot be recovered.
+    #[serde(default)]
+    pub recovery_public_key: Option<RecoveryPublicKey>,
+    /// The Argon2id parameters used when hashing passwords.
+    ///
+    /// Passwords hashed with other parameters or an older algorithm are
+    /// transparently re-hashed with these parameters the next time they are
+    /// used to log in.
+    #[serde(default)]
+    pub password_hash: Argon2Params,
+    /// Throttling of repeated failed login attempts.
+    #[serde(default)]
+    pub login_throttle: LoginThrottleConfig,
+}
+
+/// Configuration for throttling of failed login attempts.
+///
+/// Failed attempts are tracked both per user name and per client IP prefix.
+/// Each failure delays the response by an exponentially increasing amount of
+/// time, and too many failures within `failure_window_secs` cause a temporary
+/// lockout during which no login attempt for that user or from that network is
+/// considered at all.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LoginThrottleConfig {
+    /// Whether login throttling is enabled at all.
+    pub enabled: bool,
+    /// The number of failures for a single user name after which that user is
+    /// locked out.
+    pub max_user_failures: u32,
+    /// The number of failures from a single IP prefix after which that prefix
+    /// is locked out.
+    pub max_ip_failures: u32,
+    /// Failures older than this many seconds are forgotten.
+    pub failure_window_secs: u64,
+    /// How long, in seconds, a lockout lasts.
+    pub lockout_secs: u64,
+    /// The delay, in milliseconds, after the first failure. Each subsequent
+    /// failure doubles the delay.
+    pub base_delay_ms: u64,
+    /// The maximum delay, in milliseconds, after a failure.
+    pub max_delay_ms: u64,
+    /// The prefix length used to group IPv4 clients.
+    pub ipv4_prefix_len: u8,
+    /// The prefix length used to group IPv6 clients.
+    pub ipv6_prefix_len: u8,
+}
+
+impl Default for LoginThrottleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_user_failures: 10,
+            max_ip_failures: 50,
+            failure_window_secs: 3600,
+            lockout_secs: 900,
+            base_delay_ms: 250,
+            max_delay_ms: 8000,
+            ipv4_prefix_len: 32,
+            ipv6_prefix_len: 64,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ImapConfig {
+    /// The maximum number of RFC 5267 search contexts (i.e., `SEARCH RETURN
+    /// (UPDATE)`) a single session may have active at once.
+    ///
+    /// Every active context is re-evaluated against each new or changed
+    /// message, so this bounds the work a client can cause on each poll.
+    pub max_search_contexts: usize,
+}
+
+impl Default for ImapConfig {
+    fn default() -> Self {
+        Self {
+            max_search_contexts: 8,
+        }
+    }
+}
+
+/// The minimum size of the recovery key, in bits.
+const MIN_RECOVERY_KEY_BITS: u32 = 2048;
+
+#[derive(Debug, Clone)]
+pub struct RecoveryPublicKey(pub openssl::rsa::Rsa<openssl::pkey::Public>);
+
+impl<'de> serde::Deserialize<'de> for RecoveryPublicKey {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        de: D,
+    ) -> Result<Self, D::Error> {
+        let s = <String as serde::Deserialize<'de>>::deserialize(de)?;
+        let key = openssl::rsa::Rsa::public_key_from_pem(s.as_bytes())
+            .map_err(|e| {
+                serde::de::Error::custom(format!(
+                    "invalid PEM-format RSA public key: {e}",
+                ))
+            })?;
+
+        if key.size() * 8 < MIN_RECOVERY_KEY_BITS {
+            return Err(serde::de::Error::custom(format!(
+                "recovery key must be at least {MIN_RECOVERY_KEY_BITS} bits",
+            )));
+        }
+
+        Ok(Self(key))
+    }
 }
 
 // The Default implementation of TlsConfig is not useful in the real world, but
@@ -16,6 +16,9 @@
 // You should have received a copy of the GNU General Public License along with
 // Crymap. If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::BTreeMap;
+
+use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::account::key_store::KeyStoreConfig;
@@ -54,6 +57,22 @@ pub struct UserConfig {
     pub key_store: KeyStoreConfig,
     #[serde(default)]
     pub smtp_out: SmtpOutConfig,
+    #[serde(default)]
+    pub login: LoginConfig,
+    #[serde(default)]
+    pub expunge: ExpungeConfig,
+    /// RFC 4467 URLAUTH access keys, keyed by the RFC 8474 mailbox ID of the
+    /// mailbox they are for.
+    ///
+    /// Each value is a base64-encoded 32-byte key.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub urlauth_keys: BTreeMap<String, String>,
+    /// Application-specific passwords, keyed by name.
+    ///
+    /// Each of these independently derives the same master key as
+    /// `master_key`, so they are unaffected by changes to the main password.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub app_passwords: BTreeMap<String, AppPasswordConfig>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -67,3 +86,61 @@ pub struct SmtpOutConfig {
     /// this mailbox instead of `INBOX`.
     pub failure_receipts: Option<String>,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LoginConfig {
+    /// If true, deliver a message to the INBOX whenever a client/IP
+    /// combination logs in for the first time.
+    #[serde(default)]
+    pub new_device_notifications: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExpungeConfig {
+    /// The number of days for which a message expunged from every mailbox is
+    /// kept so that it can still be put back with `XCRY UNEXPUNGE`.
+    #[serde(default = "default_expunge_retention_days")]
+    pub retention_days: u32,
+}
+
+impl Default for ExpungeConfig {
+    fn default() -> Self {
+        Self {
+            retention_days: default_expunge_retention_days(),
+        }
+    }
+}
+
+fn default_expunge_retention_days() -> u32 {
+    1
+}
+
+/// An application-specific password.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AppPasswordConfig {
+    /// When this password was created.
+    pub created: DateTime<FixedOffset>,
+    /// The last time this password was used to log in.
+    #[serde(default)]
+    pub last_used: Option<DateTime<FixedOffset>>,
+    /// Which protocols this password may be used for.
+    #[serde(default)]
+    pub restriction: AppPasswordRestriction,
+    // This must come last since TOML requires tables to come after values.
+    pub master_key: MasterKeyConfig,
+}
+
+/// Restrictions on what an application-specific password can be used for.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum AppPasswordRestriction {
+    /// The password can be used for any protocol.
+    #[default]
+    None,
+    /// The password can only be used to log in to IMAP.
+    ImapOnly,
+    /// The password can only be used for SMTP submission.
+    SubmissionOnly,
+}